@@ -95,22 +95,35 @@ export const AppProvider: React.FC<{
     setIsCompiling(true);
     try {
       const response = await fetch(
-        `${process.env.NEXT_PUBLIC_SERVER}/compile_solidity`,
+        `${process.env.NEXT_PUBLIC_SERVER}/v1/compile_solidity`,
         {
           method: "POST",
           headers: {
             "Content-Type": "application/json",
           },
-          body: JSON.stringify({ files }),
+          // The server refuses fields it doesn't know, like the editor's id and address
+          body: JSON.stringify({
+            files: files.map(({ name, content }) => ({ name, content })),
+          }),
         },
       );
 
+      const body = await response.json();
+      // Sources with errors come back as a 422 whose details are the
+      // compiler output, errors included, so the editor can still mark them
+      if (
+        response.status === 422 &&
+        body.code === "COMPILE_FAILED" &&
+        body.details
+      ) {
+        setCompilationResult(body.details);
+        return;
+      }
       if (!response.ok) {
-        throw new Error("Compilation failed");
+        throw new Error(body.message ?? "Compilation failed");
       }
 
-      const result = await response.json();
-      setCompilationResult(result);
+      setCompilationResult(body);
     } catch (error) {
       console.error("Compilation error:", error);
       setCompilationResult(undefined);
@@ -167,7 +180,7 @@ export const AppProvider: React.FC<{
 
     try {
       const response = await axios.post<ExecutionResponse[]>(
-        `${process.env.NEXT_PUBLIC_SERVER}/execute_calldatas_fork`,
+        `${process.env.NEXT_PUBLIC_SERVER}/v1/execute_calldatas_fork`,
         {
           bytecode,
           calls: encodedCalls,
@@ -178,6 +191,8 @@ export const AppProvider: React.FC<{
             blockNumber: forkConfig.blockNumber,
           },
           traceMode: "debug",
+          // The debugger still reads forge's arena
+          includeRawTraces: true,
         },
       );
 
@@ -238,7 +253,7 @@ export const AppProvider: React.FC<{
           response: returned,
           logs,
           rawLogs: result.logs,
-          traces: result.traces,
+          traces: result.rawTraces,
         };
 
         // Enhance with source mapping if compilation result exists
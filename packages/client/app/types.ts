@@ -119,11 +119,34 @@ export interface SolidityFile {
 
 export type ExecutionResponse = {
   exitReason: string;
+  success: boolean;
   reverted: boolean;
   result: Hex;
   gasUsed: string;
   logs: Log[];
-  traces: FunctionCallResult["traces"];
+  traces: TraceNode[];
+  // Only returned with `includeRawTraces`
+  rawTraces: FunctionCallResult["traces"];
+};
+
+export type TraceNode = {
+  kind:
+    | "call"
+    | "staticCall"
+    | "callCode"
+    | "delegateCall"
+    | "authCall"
+    | "create"
+    | "create2";
+  from: Address;
+  to: Address;
+  value: string;
+  gasUsed: number;
+  input: Hex;
+  output: Hex;
+  status: "success" | "revert" | "halt";
+  children: TraceNode[];
+  logs: { address: Address; topics: Hex[]; data: Hex }[];
 };
 
 export interface ForkConfig {
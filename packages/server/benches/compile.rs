@@ -0,0 +1,25 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use gas_exp::compile::solidity::{compile, SolidityFile};
+use std::time::Duration;
+
+// Calls `compile` directly, so the compile cache routes go through never answers. Needs solc,
+// which foundry-compilers fetches on the first run if it isn't installed.
+fn compilation(c: &mut Criterion) {
+    let files = [SolidityFile {
+        name: "Vault.sol".to_string(),
+        content: include_str!("fixtures/Vault.sol").to_string(),
+    }];
+    let result = compile(&files, Duration::from_secs(120)).unwrap();
+    assert!(!result.has_errors(), "fixture doesn't compile");
+
+    let mut group = c.benchmark_group("compile");
+    // Each run starts solc, so a handful of samples is plenty
+    group.sample_size(10);
+    group.bench_function("vault", |b| {
+        b.iter(|| compile(&files, Duration::from_secs(120)).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, compilation);
+criterion_main!(benches);
@@ -0,0 +1,113 @@
+use alloy_primitives::{address, Address, Bytes, B256, U256};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use gas_exp::format::Format;
+use gas_exp::gas::{
+    EngineConfig, ForkCall, StateDump, TraceKind, TraceLog, TraceNode, TraceStatus,
+};
+
+// SimpleStorage's runtime: `set(uint256)` stores its argument in slot 0, anything shorter
+// returns slot 0 like `get()`
+const SIMPLE_STORAGE: &str = "0x60243610600e57600435600055005b60005460005260206000f3";
+const ADDRESS: Address = address!("b2f9974c62815d3177079e150377915d9bc49c82");
+const CALLER: Address = address!("1000000000000000000000000000000000000000");
+
+fn call(calldata: Vec<u8>) -> ForkCall {
+    ForkCall {
+        calldata: calldata.into(),
+        value: U256::ZERO,
+        caller: Some(CALLER.into()),
+        independent: false,
+        signature: None,
+        args: None,
+        meta: None,
+    }
+}
+
+// set(i) then get(), ten times over
+fn simple_storage_calls() -> Vec<ForkCall> {
+    (0..10u64)
+        .flat_map(|i| {
+            let mut set = vec![0x60, 0xfe, 0x47, 0xb1];
+            set.extend_from_slice(&U256::from(i).to_be_bytes::<32>());
+            [call(set), call(vec![0x6d, 0x4c, 0xe6, 0x3c])]
+        })
+        .collect()
+}
+
+fn execution(c: &mut Criterion) {
+    let code: Bytes = SIMPLE_STORAGE.parse().unwrap();
+    let calls = simple_storage_calls();
+    c.bench_function("execute_simple_storage", |b| {
+        b.iter_batched(
+            || {
+                let mut engine = EngineConfig::memory(StateDump::new(), None)
+                    .build()
+                    .unwrap();
+                engine.insert_contract(ADDRESS, code.clone());
+                engine
+            },
+            |mut engine| engine.execute_calls(ADDRESS, calls.clone(), 1),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn engine_construction(c: &mut Criterion) {
+    c.bench_function("build_memory_engine", |b| {
+        b.iter(|| {
+            EngineConfig::memory(black_box(StateDump::new()), None)
+                .build()
+                .unwrap()
+        })
+    });
+}
+
+// A call `depth` frames deep, each frame also making a few shallow calls that emit an event
+fn deep_trace(depth: usize) -> TraceNode {
+    let leaf = |i: u8| TraceNode {
+        kind: TraceKind::StaticCall,
+        from: ADDRESS,
+        to: Address::repeat_byte(i),
+        value: U256::ZERO,
+        gas_used: 2_600,
+        input: Bytes::from(vec![i; 68]),
+        output: Bytes::from(vec![i; 32]),
+        status: TraceStatus::Success,
+        children: vec![],
+        logs: vec![TraceLog {
+            address: Address::repeat_byte(i),
+            topics: vec![B256::repeat_byte(i); 3],
+            data: Bytes::from(vec![i; 64]),
+        }],
+    };
+    (0..depth).fold(leaf(0), |inner, level| {
+        let mut children: Vec<TraceNode> = (1..=4).map(leaf).collect();
+        children.push(inner);
+        TraceNode {
+            kind: TraceKind::Call,
+            from: CALLER,
+            to: ADDRESS,
+            value: U256::from(level),
+            gas_used: 50_000 + level as u64,
+            input: Bytes::from(vec![0xab; 132]),
+            output: Bytes::from(vec![0xcd; 64]),
+            status: TraceStatus::Success,
+            children,
+            logs: vec![],
+        }
+    })
+}
+
+fn trace_serialization(c: &mut Criterion) {
+    let trace = vec![deep_trace(256)];
+    let mut group = c.benchmark_group("serialize_deep_trace");
+    for format in [Format::Json, Format::MessagePack, Format::Cbor] {
+        group.bench_function(format!("{:?}", format), |b| {
+            b.iter(|| format.encode(black_box(&trace)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, execution, engine_construction, trace_serialization);
+criterion_main!(benches);
@@ -0,0 +1,177 @@
+// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.0;
+
+// A fixed, medium-size source for the compile bench: an ERC-20 share token over a vault with
+// roles, fees and a withdrawal queue. It isn't deployed anywhere.
+
+interface IERC20 {
+    event Transfer(address indexed from, address indexed to, uint256 value);
+    event Approval(address indexed owner, address indexed spender, uint256 value);
+
+    function totalSupply() external view returns (uint256);
+    function balanceOf(address account) external view returns (uint256);
+    function transfer(address to, uint256 amount) external returns (bool);
+    function allowance(address owner, address spender) external view returns (uint256);
+    function approve(address spender, uint256 amount) external returns (bool);
+    function transferFrom(address from, address to, uint256 amount) external returns (bool);
+}
+
+library FeeMath {
+    uint256 internal constant BPS = 10_000;
+
+    function fee(uint256 amount, uint256 bps) internal pure returns (uint256) {
+        return (amount * bps) / BPS;
+    }
+
+    function toShares(uint256 assets, uint256 supply, uint256 total) internal pure returns (uint256) {
+        return supply == 0 || total == 0 ? assets : (assets * supply) / total;
+    }
+
+    function toAssets(uint256 shares, uint256 supply, uint256 total) internal pure returns (uint256) {
+        return supply == 0 ? shares : (shares * total) / supply;
+    }
+}
+
+abstract contract Roles {
+    error Unauthorized(address account, bytes32 role);
+
+    bytes32 public constant ADMIN = keccak256("ADMIN");
+    bytes32 public constant KEEPER = keccak256("KEEPER");
+
+    mapping(bytes32 => mapping(address => bool)) public hasRole;
+
+    event RoleSet(bytes32 indexed role, address indexed account, bool granted);
+
+    modifier only(bytes32 role) {
+        if (!hasRole[role][msg.sender]) revert Unauthorized(msg.sender, role);
+        _;
+    }
+
+    function setRole(bytes32 role, address account, bool granted) external only(ADMIN) {
+        hasRole[role][account] = granted;
+        emit RoleSet(role, account, granted);
+    }
+}
+
+contract Shares is IERC20 {
+    string public name;
+    string public symbol;
+    uint8 public constant decimals = 18;
+    uint256 public totalSupply;
+
+    mapping(address => uint256) public balanceOf;
+    mapping(address => mapping(address => uint256)) public allowance;
+
+    constructor(string memory name_, string memory symbol_) {
+        name = name_;
+        symbol = symbol_;
+    }
+
+    function transfer(address to, uint256 amount) external returns (bool) {
+        _move(msg.sender, to, amount);
+        return true;
+    }
+
+    function approve(address spender, uint256 amount) external returns (bool) {
+        allowance[msg.sender][spender] = amount;
+        emit Approval(msg.sender, spender, amount);
+        return true;
+    }
+
+    function transferFrom(address from, address to, uint256 amount) external returns (bool) {
+        uint256 allowed = allowance[from][msg.sender];
+        if (allowed != type(uint256).max) {
+            require(allowed >= amount, "allowance");
+            allowance[from][msg.sender] = allowed - amount;
+        }
+        _move(from, to, amount);
+        return true;
+    }
+
+    function _move(address from, address to, uint256 amount) internal {
+        require(balanceOf[from] >= amount, "balance");
+        balanceOf[from] -= amount;
+        balanceOf[to] += amount;
+        emit Transfer(from, to, amount);
+    }
+
+    function _mint(address to, uint256 amount) internal {
+        totalSupply += amount;
+        balanceOf[to] += amount;
+        emit Transfer(address(0), to, amount);
+    }
+
+    function _burn(address from, uint256 amount) internal {
+        require(balanceOf[from] >= amount, "balance");
+        balanceOf[from] -= amount;
+        totalSupply -= amount;
+        emit Transfer(from, address(0), amount);
+    }
+}
+
+contract Vault is Shares, Roles {
+    using FeeMath for uint256;
+
+    struct Withdrawal {
+        address owner;
+        uint256 shares;
+        uint64 requestedAt;
+    }
+
+    IERC20 public immutable asset;
+    uint256 public feeBps;
+    uint256 public totalAssets;
+    Withdrawal[] public queue;
+    uint256 public nextInQueue;
+
+    event Deposit(address indexed owner, uint256 assets, uint256 shares);
+    event WithdrawalQueued(uint256 indexed id, address indexed owner, uint256 shares);
+    event WithdrawalPaid(uint256 indexed id, uint256 assets);
+
+    constructor(IERC20 asset_, uint256 feeBps_) Shares("Vault Shares", "vSHR") {
+        asset = asset_;
+        feeBps = feeBps_;
+        hasRole[ADMIN][msg.sender] = true;
+    }
+
+    function deposit(uint256 assets) external returns (uint256 shares) {
+        uint256 fee = assets.fee(feeBps);
+        shares = (assets - fee).toShares(totalSupply, totalAssets);
+        require(shares > 0, "zero shares");
+        require(asset.transferFrom(msg.sender, address(this), assets), "transfer");
+        totalAssets += assets;
+        _mint(msg.sender, shares);
+        emit Deposit(msg.sender, assets, shares);
+    }
+
+    function requestWithdrawal(uint256 shares) external returns (uint256 id) {
+        _move(msg.sender, address(this), shares);
+        id = queue.length;
+        queue.push(Withdrawal(msg.sender, shares, uint64(block.timestamp)));
+        emit WithdrawalQueued(id, msg.sender, shares);
+    }
+
+    function processQueue(uint256 max) external only(KEEPER) returns (uint256 paid) {
+        uint256 end = nextInQueue + max;
+        if (end > queue.length) end = queue.length;
+        for (uint256 id = nextInQueue; id < end; id++) {
+            Withdrawal memory w = queue[id];
+            uint256 assets = w.shares.toAssets(totalSupply, totalAssets);
+            _burn(address(this), w.shares);
+            totalAssets -= assets;
+            require(asset.transfer(w.owner, assets), "transfer");
+            emit WithdrawalPaid(id, assets);
+            paid++;
+        }
+        nextInQueue = end;
+    }
+
+    function setFee(uint256 feeBps_) external only(ADMIN) {
+        require(feeBps_ <= 1_000, "fee too high");
+        feeBps = feeBps_;
+    }
+
+    function pending() external view returns (uint256) {
+        return queue.length - nextInQueue;
+    }
+}
@@ -0,0 +1,218 @@
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::State;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::config::Limits;
+use crate::error::{reject, ApiError};
+
+/// Bounds how many requests run an expensive section at once. Past the limit a request waits up
+/// to `max_wait` in a queue of at most `max_queued`, and is turned away with a 429 when the queue
+/// is full or the wait runs out.
+#[derive(Clone)]
+pub struct Gate {
+    name: &'static str,
+    limit: usize,
+    permits: Arc<Semaphore>,
+    queued: Arc<AtomicUsize>,
+    max_queued: usize,
+    max_wait: Duration,
+}
+
+// Keeps the queue count right however the wait ends, cancellation included
+struct Queued(Arc<AtomicUsize>);
+
+impl Drop for Queued {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Gate {
+    pub fn new(name: &'static str, limit: usize, max_queued: usize, max_wait: Duration) -> Self {
+        let limit = limit.max(1);
+        Gate {
+            name,
+            limit,
+            permits: Arc::new(Semaphore::new(limit)),
+            queued: Arc::default(),
+            max_queued,
+            max_wait,
+        }
+    }
+
+    /// Waits for a slot. The slot is held until the permit is dropped.
+    pub async fn enter(&self) -> Result<OwnedSemaphorePermit, ApiError> {
+        if let Ok(permit) = self.permits.clone().try_acquire_owned() {
+            return Ok(permit);
+        }
+        if self.queued.fetch_add(1, Ordering::SeqCst) >= self.max_queued {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            return Err(self.busy());
+        }
+        let _queued = Queued(self.queued.clone());
+        match tokio::time::timeout(self.max_wait, self.permits.clone().acquire_owned()).await {
+            Ok(Ok(permit)) => Ok(permit),
+            // The semaphore is never closed, so only the timeout gets here
+            _ => Err(self.busy()),
+        }
+    }
+
+    /// Waits for a slot however long it takes, outside the queue, for work that was already
+    /// accepted, like a job. The semaphore is never closed, so this is always Some.
+    pub async fn wait(&self) -> Option<OwnedSemaphorePermit> {
+        self.permits.clone().acquire_owned().await.ok()
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.limit - self.permits.available_permits()
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    fn busy(&self) -> ApiError {
+        let mut err = ApiError::new(
+            Status::TooManyRequests,
+            "SERVER_BUSY",
+            format!(
+                "{} {} requests are already running, try again shortly",
+                self.limit, self.name
+            ),
+        );
+        err.retry_after = Some(self.max_wait.as_secs().max(1));
+        err
+    }
+}
+
+/// One gate around forking and executing, and a larger one around compiling.
+#[derive(Clone)]
+pub struct Gates {
+    pub fork: Gate,
+    pub compile: Gate,
+}
+
+impl Gates {
+    pub fn new(limits: &Limits) -> Self {
+        Gates {
+            fork: Gate::new(
+                "fork",
+                limits.max_concurrent_forks,
+                limits.max_queued_requests,
+                limits.max_queue_wait,
+            ),
+            compile: Gate::new(
+                "compile",
+                limits.max_concurrent_compiles,
+                limits.max_queued_requests,
+                limits.max_queue_wait,
+            ),
+        }
+    }
+}
+
+/// A slot in the fork gate, held for the duration of a handler.
+pub struct ForkSlot {
+    _permit: OwnedSemaphorePermit,
+}
+
+/// A slot in the compile gate, held for the duration of a handler.
+pub struct CompileSlot {
+    _permit: OwnedSemaphorePermit,
+}
+
+async fn enter<'r>(
+    req: &'r Request<'_>,
+    gate: fn(&Gates) -> &Gate,
+) -> Outcome<OwnedSemaphorePermit, ApiError> {
+    let gates = match req.guard::<&State<Gates>>().await {
+        Outcome::Success(gates) => gates,
+        _ => return Outcome::Forward(Status::InternalServerError),
+    };
+    match gate(gates).enter().await {
+        Ok(permit) => Outcome::Success(permit),
+        Err(err) => {
+            reject(req, err.clone());
+            Outcome::Error((err.status, err))
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ForkSlot {
+    type Error = ApiError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        enter(req, |gates| &gates.fork)
+            .await
+            .map(|permit| ForkSlot { _permit: permit })
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for CompileSlot {
+    type Error = ApiError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        enter(req, |gates| &gates.compile)
+            .await
+            .map(|permit| CompileSlot { _permit: permit })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::task::JoinSet;
+
+    #[tokio::test]
+    async fn test_rejects_past_limit_and_queue() {
+        let gate = Gate::new("fork", 2, 1, Duration::from_millis(20));
+        let mut requests = JoinSet::new();
+        for _ in 0..5 {
+            let gate = gate.clone();
+            requests.spawn(async move {
+                let _permit = gate.enter().await?;
+                // A slow fork
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                Ok::<_, ApiError>(())
+            });
+        }
+
+        let (mut admitted, mut rejected) = (0, 0);
+        while let Some(outcome) = requests.join_next().await {
+            match outcome.unwrap() {
+                Ok(()) => admitted += 1,
+                Err(err) => {
+                    assert_eq!(err.status, Status::TooManyRequests);
+                    assert_eq!(err.code, "SERVER_BUSY");
+                    assert_eq!(err.retry_after, Some(1));
+                    rejected += 1;
+                }
+            }
+        }
+        assert_eq!((admitted, rejected), (2, 3));
+        assert_eq!((gate.in_flight(), gate.queued()), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_queued_request_gets_freed_slot() {
+        let gate = Gate::new("compile", 1, 1, Duration::from_secs(5));
+        let permit = gate.enter().await.unwrap();
+
+        let waiting = tokio::spawn({
+            let gate = gate.clone();
+            async move { gate.enter().await.map(drop) }
+        });
+        tokio::task::yield_now().await;
+        assert_eq!((gate.in_flight(), gate.queued()), (1, 1));
+
+        drop(permit);
+        waiting.await.unwrap().unwrap();
+        assert_eq!(gate.queued(), 0);
+    }
+}
@@ -0,0 +1,498 @@
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::State;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Instant;
+use utoipa::ToSchema;
+
+use crate::config::{AppConfig, DemoConfig, RateLimit};
+use crate::demo;
+use crate::error::{reject, ApiError};
+
+/// Demo requests' usage is counted together, under this name.
+pub const DEMO_USAGE: &str = "(demo)";
+
+// Past this many client IP buckets, those that have filled back up are dropped
+const MAX_IP_BUCKETS: usize = 10_000;
+
+/// Routes are rate limited in two classes, since compiling and forking cost very different amounts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RouteClass {
+    Compile,
+    Execute,
+}
+
+#[derive(Clone, Debug, Default, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Usage {
+    pub compile: u64,
+    pub execute: u64,
+    pub rate_limited: u64,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(rate: RateLimit) -> Self {
+        Bucket {
+            tokens: rate.burst as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    // The tokens it has at `now`, refilled since it was last used
+    fn tokens_at(&self, rate: RateLimit, now: Instant) -> f64 {
+        let per_second = rate.per_minute as f64 / 60.0;
+        (self.tokens + now.duration_since(self.updated).as_secs_f64() * per_second)
+            .min(rate.burst as f64)
+    }
+
+    // Takes a token, or returns how many seconds until one is available
+    fn take(&mut self, rate: RateLimit) -> Result<(), u64> {
+        let per_second = rate.per_minute as f64 / 60.0;
+        let now = Instant::now();
+        self.tokens = self.tokens_at(rate, now);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+        if per_second == 0.0 {
+            return Err(60);
+        }
+        Err(((1.0 - self.tokens) / per_second).ceil() as u64)
+    }
+}
+
+/// API keys and their rate limiter state. With no keys configured every request is let through
+/// and nothing is counted, unless demo mode is on.
+pub struct Auth {
+    keys: HashSet<String>,
+    admin_key: Option<String>,
+    compile_rate: RateLimit,
+    execute_rate: RateLimit,
+    buckets: Mutex<HashMap<(String, RouteClass), Bucket>>,
+    demo: Option<DemoConfig>,
+    ip_buckets: Mutex<HashMap<(IpAddr, RouteClass), Bucket>>,
+    usage: Mutex<HashMap<String, Usage>>,
+}
+
+impl Auth {
+    pub fn new(config: &AppConfig) -> Self {
+        Auth {
+            keys: config.api_keys.clone(),
+            admin_key: config.admin_key.clone(),
+            compile_rate: config.limits.compile_rate,
+            execute_rate: config.limits.execute_rate,
+            buckets: Mutex::default(),
+            demo: config.demo.clone(),
+            ip_buckets: Mutex::default(),
+            usage: Mutex::default(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    /// The demo mode config, when a request sending `key` is let in as a demo request: one with
+    /// no key, or any request when no keys are configured.
+    pub fn demo(&self, key: Option<&str>) -> Option<&DemoConfig> {
+        self.demo
+            .as_ref()
+            .filter(|_| key.is_none() || !self.enabled())
+    }
+
+    fn check_key(&self, key: Option<&str>) -> Result<(), ApiError> {
+        match key {
+            None => Err(ApiError::new(
+                Status::Unauthorized,
+                "MISSING_API_KEY",
+                "X-API-Key header is required",
+            )),
+            Some(key) if !self.keys.contains(key) => Err(ApiError::new(
+                Status::Unauthorized,
+                "INVALID_API_KEY",
+                "X-API-Key is not a valid key",
+            )),
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Checks the key and takes a token from its bucket for `class`.
+    pub fn authorize(&self, key: Option<&str>, class: RouteClass) -> Result<(), ApiError> {
+        if !self.enabled() {
+            return Ok(());
+        }
+        self.check_key(key)?;
+        let key = key.unwrap_or_default();
+
+        let rate = match class {
+            RouteClass::Compile => self.compile_rate,
+            RouteClass::Execute => self.execute_rate,
+        };
+        let taken = self
+            .buckets
+            .lock()
+            .unwrap()
+            .entry((key.to_string(), class))
+            .or_insert_with(|| Bucket::full(rate))
+            .take(rate);
+        self.count(key, class, taken)
+    }
+
+    /// Takes a token from the bucket for `class` of the client at `ip`, for a demo request.
+    pub fn authorize_demo(&self, ip: IpAddr, class: RouteClass) -> Result<(), ApiError> {
+        let Some(demo) = &self.demo else {
+            return Ok(());
+        };
+        let rate = |class: RouteClass| match class {
+            RouteClass::Compile => demo.compile_rate,
+            RouteClass::Execute => demo.execute_rate,
+        };
+        let mut buckets = self.ip_buckets.lock().unwrap();
+        if buckets.len() >= MAX_IP_BUCKETS {
+            let now = Instant::now();
+            buckets.retain(|(_, class), bucket| {
+                bucket.tokens_at(rate(*class), now) < rate(*class).burst as f64
+            });
+        }
+        let taken = buckets
+            .entry((ip, class))
+            .or_insert_with(|| Bucket::full(rate(class)))
+            .take(rate(class));
+        drop(buckets);
+        self.count(DEMO_USAGE, class, taken)
+    }
+
+    // Counts a request against `name`'s usage, a bucket that was empty as a 429
+    fn count(&self, name: &str, class: RouteClass, taken: Result<(), u64>) -> Result<(), ApiError> {
+        let mut usage = self.usage.lock().unwrap();
+        let usage = usage.entry(name.to_string()).or_default();
+        match taken {
+            Ok(()) => {
+                match class {
+                    RouteClass::Compile => usage.compile += 1,
+                    RouteClass::Execute => usage.execute += 1,
+                }
+                Ok(())
+            }
+            Err(retry_after) => {
+                usage.rate_limited += 1;
+                let mut err = ApiError::new(
+                    Status::TooManyRequests,
+                    "RATE_LIMITED",
+                    format!("rate limit exceeded, retry in {}s", retry_after),
+                );
+                err.retry_after = Some(retry_after);
+                Err(err)
+            }
+        }
+    }
+
+    pub fn usage(&self) -> BTreeMap<String, Usage> {
+        self.usage
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(key, usage)| (key.clone(), usage.clone()))
+            .collect()
+    }
+}
+
+// Who the request is from, once it's let in: the key it sent, or for a demo request, which has
+// none of its own, the client's IP
+async fn guard<'r>(
+    req: &'r Request<'_>,
+    class: Option<RouteClass>,
+) -> Outcome<Option<String>, ApiError> {
+    let auth = match req.guard::<&State<Auth>>().await {
+        Outcome::Success(auth) => auth,
+        _ => {
+            let err = ApiError::new(
+                Status::InternalServerError,
+                "AUTH_NOT_CONFIGURED",
+                "authentication state is not managed",
+            );
+            reject(req, err.clone());
+            return Outcome::Error((err.status, err));
+        }
+    };
+    let key = req.headers().get_one("X-API-Key");
+    let checked = match (auth.demo(key), class) {
+        (Some(demo), class) => {
+            demo::admit(req, demo);
+            class.map_or(Ok(()), |class| {
+                auth.authorize_demo(demo::client_ip(req, demo), class)
+            })
+        }
+        (None, Some(class)) => auth.authorize(key, class),
+        (None, None) if auth.enabled() => auth.check_key(key),
+        (None, None) => Ok(()),
+    };
+    let owner = match demo::gated(req) {
+        Some(demo) => Some(format!("demo:{}", demo::client_ip(req, demo))),
+        None => key.map(str::to_string),
+    };
+    match checked {
+        Ok(()) => Outcome::Success(owner),
+        Err(err) => {
+            reject(req, err.clone());
+            Outcome::Error((err.status, err))
+        }
+    }
+}
+
+/// A valid API key, charged against the compile bucket.
+pub struct CompileKey;
+
+/// A valid API key, charged against the execute bucket. Holds the key sent, if any, or for a
+/// demo request `demo:<client IP>`.
+pub struct ExecuteKey(pub Option<String>);
+
+/// A valid API key, not rate limited. For cheap routes like polling a job. Holds the key sent, if
+/// any, or for a demo request `demo:<client IP>`.
+pub struct ApiKey(pub Option<String>);
+
+/// The admin key. Admin routes are open only when neither API keys nor an admin key is
+/// configured, i.e. in local development.
+pub struct AdminKey;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for CompileKey {
+    type Error = ApiError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        guard(req, Some(RouteClass::Compile))
+            .await
+            .map(|_| CompileKey)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ExecuteKey {
+    type Error = ApiError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        guard(req, Some(RouteClass::Execute)).await.map(ExecuteKey)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ApiKey {
+    type Error = ApiError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        guard(req, None).await.map(ApiKey)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminKey {
+    type Error = ApiError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Outcome::Success(auth) = req.guard::<&State<Auth>>().await else {
+            return Outcome::Forward(Status::NotFound);
+        };
+        let key = req.headers().get_one("X-Admin-Key");
+        let allowed = match &auth.admin_key {
+            Some(admin_key) => key == Some(admin_key.as_str()),
+            None => !auth.enabled(),
+        };
+        if allowed {
+            return Outcome::Success(AdminKey);
+        }
+        let err = ApiError::new(
+            Status::Forbidden,
+            "ADMIN_KEY_REQUIRED",
+            "X-Admin-Key is missing or wrong",
+        );
+        reject(req, err.clone());
+        Outcome::Error((err.status, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Limits;
+    use crate::error::catchers;
+    use rocket::http::Header;
+    use rocket::local::blocking::Client;
+    use rocket::{get, post, routes};
+
+    #[post("/probe")]
+    fn probe(_key: ExecuteKey) -> &'static str {
+        "ok"
+    }
+
+    #[get("/owner")]
+    fn owner(key: ApiKey) -> String {
+        key.0.unwrap_or_default()
+    }
+
+    fn client(keys: &[&str]) -> Client {
+        let config = AppConfig {
+            api_keys: keys.iter().map(|key| key.to_string()).collect(),
+            limits: Limits {
+                execute_rate: RateLimit {
+                    burst: 2,
+                    per_minute: 1,
+                },
+                ..Limits::default()
+            },
+            ..AppConfig::default()
+        };
+        let rocket = rocket::build()
+            .manage(Auth::new(&config))
+            .mount("/", routes![probe])
+            .register("/", catchers());
+        Client::tracked(rocket).unwrap()
+    }
+
+    fn demo_client() -> Client {
+        let config = AppConfig {
+            api_keys: ["secret".to_string()].into(),
+            demo: Some(DemoConfig {
+                ip_header: Some("X-Forwarded-For".to_string()),
+                execute_rate: RateLimit {
+                    burst: 2,
+                    per_minute: 1,
+                },
+                ..DemoConfig::default()
+            }),
+            ..AppConfig::default()
+        };
+        let rocket = rocket::build()
+            .manage(Auth::new(&config))
+            .mount("/", routes![probe, owner])
+            .register("/", catchers());
+        Client::tracked(rocket).unwrap()
+    }
+
+    fn code(response: rocket::local::blocking::LocalResponse) -> String {
+        let body: serde_json::Value = response.into_json().unwrap();
+        body["code"].as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_no_keys_configured() {
+        let client = client(&[]);
+        assert_eq!(client.post("/probe").dispatch().status(), Status::Ok);
+    }
+
+    #[test]
+    fn test_missing_and_bad_key() {
+        let client = client(&["secret"]);
+
+        let response = client.post("/probe").dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert_eq!(code(response), "MISSING_API_KEY");
+
+        let response = client
+            .post("/probe")
+            .header(Header::new("X-API-Key", "wrong"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert_eq!(code(response), "INVALID_API_KEY");
+    }
+
+    #[test]
+    fn test_bucket_exhaustion() {
+        let client = client(&["secret"]);
+        let send = || {
+            client
+                .post("/probe")
+                .header(Header::new("X-API-Key", "secret"))
+                .dispatch()
+        };
+
+        assert_eq!(send().status(), Status::Ok);
+        assert_eq!(send().status(), Status::Ok);
+
+        let response = send();
+        assert_eq!(response.status(), Status::TooManyRequests);
+        let retry_after: u64 = response
+            .headers()
+            .get_one("Retry-After")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(retry_after > 0 && retry_after <= 60);
+        assert_eq!(code(response), "RATE_LIMITED");
+
+        let usage = client.rocket().state::<Auth>().unwrap().usage();
+        assert_eq!(usage["secret"].execute, 2);
+        assert_eq!(usage["secret"].rate_limited, 1);
+    }
+
+    #[test]
+    fn test_demo_buckets_per_ip() {
+        let client = demo_client();
+        let from = |forwarded: &'static str| {
+            client
+                .post("/probe")
+                .header(Header::new("X-Forwarded-For", forwarded))
+                .dispatch()
+        };
+
+        assert_eq!(from("203.0.113.7").status(), Status::Ok);
+        // The proxy's address is the last, whatever the client sent before it
+        assert_eq!(from("198.51.100.1, 203.0.113.7").status(), Status::Ok);
+        let response = from("203.0.113.7");
+        assert_eq!(response.status(), Status::TooManyRequests);
+        assert!(response.headers().get_one("Retry-After").is_some());
+        assert_eq!(code(response), "RATE_LIMITED");
+
+        // Another IP has its own bucket, and a key isn't a demo request at all
+        assert_eq!(from("203.0.113.8").status(), Status::Ok);
+        let response = client
+            .post("/probe")
+            .header(Header::new("X-Forwarded-For", "203.0.113.7"))
+            .header(Header::new("X-API-Key", "secret"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let response = client
+            .post("/probe")
+            .header(Header::new("X-API-Key", "wrong"))
+            .dispatch();
+        assert_eq!(code(response), "INVALID_API_KEY");
+
+        let usage = client.rocket().state::<Auth>().unwrap().usage();
+        assert_eq!(usage[DEMO_USAGE].execute, 3);
+        assert_eq!(usage[DEMO_USAGE].rate_limited, 1);
+        assert_eq!(usage["secret"].execute, 1);
+    }
+
+    #[test]
+    fn test_demo_requests_owned_by_ip() {
+        let client = demo_client();
+        let owner = |headers: &[(&'static str, &'static str)]| {
+            let mut request = client.get("/owner");
+            for (name, value) in headers {
+                request = request.header(Header::new(*name, *value));
+            }
+            request.dispatch().into_string().unwrap()
+        };
+
+        assert_eq!(
+            owner(&[("X-Forwarded-For", "203.0.113.7")]),
+            "demo:203.0.113.7"
+        );
+        assert_eq!(
+            owner(&[("X-Forwarded-For", "203.0.113.8")]),
+            "demo:203.0.113.8"
+        );
+        assert_eq!(
+            owner(&[("X-Forwarded-For", "203.0.113.7"), ("X-API-Key", "secret")]),
+            "secret"
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use alloy_primitives::{Address, Bytes};
+use clap::{Parser, Subcommand};
+use foundry_compilers::compilers::CompilationError;
+use gas_exp::compile::solidity::{compile, SolidityFile};
+use gas_exp::config::{AppConfig, Limits};
+use gas_exp::error::ApiError;
+use gas_exp::gas::{execute_calldatas_fork, ExecutionOptions, ForkCall, ForkConfig};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::str::FromStr;
+use tracing_subscriber::EnvFilter;
+
+/// The code didn't compile, or a call reverted under `--strict`
+const EXIT_FAILED: u8 = 1;
+/// Bad input, or the work couldn't be done at all. Usage errors exit with 2, from clap.
+const EXIT_ERROR: u8 = 3;
+
+/// Compiles and executes the way the server does, printing the JSON its routes return.
+#[derive(Parser)]
+#[command(name = "evm-repl", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compiles the .sol files in a directory, like `/compile_solidity`. Subdirectories aren't
+    /// read, since the server only takes flat file names.
+    Compile {
+        dir: PathBuf,
+        /// Print the compiler output as JSON instead of just its diagnostics
+        #[arg(long)]
+        json: bool,
+    },
+    /// Runs calls against runtime bytecode on a fork, like `/execute_calldatas_fork`.
+    Exec {
+        /// Runtime bytecode as hex, 0x-prefixed
+        #[arg(long)]
+        bytecode_file: PathBuf,
+        /// A JSON array of calls, as in the `calls` of an `/execute_calldatas_fork` request
+        #[arg(long)]
+        calls: PathBuf,
+        /// Where the bytecode is placed
+        #[arg(long, default_value = "0x5FbDB2315678afecb367f032d93F642f64180aa3")]
+        address: Address,
+        /// Chain to fork, its RPC URL taken from the environment as for the server
+        #[arg(long)]
+        chain: Option<u64>,
+        #[arg(long)]
+        rpc_url: Option<String>,
+        #[arg(long)]
+        block: Option<u64>,
+        /// `call`, `jump`, `jumpSimple`, `debug` or `none`
+        #[arg(long)]
+        trace_mode: Option<String>,
+        /// Exit nonzero if any call reverts
+        #[arg(long)]
+        strict: bool,
+    },
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    // Logs go to stderr so stdout stays parseable
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn"));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .init();
+
+    let outcome = match Cli::parse().command {
+        Command::Compile { dir, json } => compile_dir(&dir, json),
+        Command::Exec {
+            bytecode_file,
+            calls,
+            address,
+            chain,
+            rpc_url,
+            block,
+            trace_mode,
+            strict,
+        } => {
+            let fork_config = ForkConfig {
+                rpc_url,
+                chain_id: chain,
+                block_number: block,
+                ..Default::default()
+            };
+            exec(
+                &bytecode_file,
+                &calls,
+                address,
+                fork_config,
+                trace_mode,
+                strict,
+            )
+            .await
+        }
+    };
+    match outcome {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("{}", serde_json::to_string(&err).unwrap_or(err.message));
+            ExitCode::from(EXIT_ERROR)
+        }
+    }
+}
+
+fn compile_dir(dir: &Path, json: bool) -> Result<ExitCode, ApiError> {
+    let files = read_sources(dir)?;
+    if files.is_empty() {
+        return Err(ApiError::invalid_request(format!(
+            "no .sol files in {}",
+            dir.display()
+        )));
+    }
+    let result = compile(&files, &Limits::default()).map_err(ApiError::compile_failed)?;
+
+    if json {
+        print_json(&result)?;
+    } else {
+        for err in &result.errors {
+            eprintln!("{}", err);
+        }
+    }
+    Ok(if result.has_errors() {
+        ExitCode::from(EXIT_FAILED)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+fn read_sources(dir: &Path) -> Result<Vec<SolidityFile>, ApiError> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .and_then(|entries| entries.map(|entry| Ok(entry?.path())).collect())
+        .map_err(|err| io_error(dir, err))?;
+    paths.retain(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "sol"));
+    paths.sort();
+    paths
+        .into_iter()
+        .map(|path| {
+            let content = fs::read_to_string(&path).map_err(|err| io_error(&path, err))?;
+            let name = path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into();
+            Ok(SolidityFile { name, content })
+        })
+        .collect()
+}
+
+async fn exec(
+    bytecode_file: &Path,
+    calls_file: &Path,
+    address: Address,
+    fork_config: ForkConfig,
+    trace_mode: Option<String>,
+    strict: bool,
+) -> Result<ExitCode, ApiError> {
+    let bytecode = fs::read_to_string(bytecode_file).map_err(|err| io_error(bytecode_file, err))?;
+    let bytecode = Bytes::from_str(bytecode.trim()).map_err(|err| {
+        ApiError::invalid_request(format!("{}: {}", bytecode_file.display(), err))
+    })?;
+    let calls = fs::read(calls_file).map_err(|err| io_error(calls_file, err))?;
+    let calls: Vec<ForkCall> = serde_json::from_slice(&calls)
+        .map_err(|err| ApiError::invalid_request(format!("{}: {}", calls_file.display(), err)))?;
+
+    let config = AppConfig::from_env()
+        .map_err(|err| ApiError::invalid_request(format!("invalid configuration: {}", err)))?;
+    let fork = fork_config
+        .resolve(&config)
+        .map_err(|err| ApiError::from_execution(err.into()))?;
+    let results = execute_calldatas_fork(
+        &config,
+        bytecode,
+        address.into(),
+        calls,
+        fork,
+        ExecutionOptions::new(trace_mode, false, None, None, false),
+    )
+    .await
+    .map_err(ApiError::from_execution)?;
+
+    print_json(&results)?;
+    Ok(if strict && results.iter().any(|result| result.reverted) {
+        ExitCode::from(EXIT_FAILED)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+fn print_json(value: &impl Serialize) -> Result<(), ApiError> {
+    let json = serde_json::to_string_pretty(value)
+        .map_err(|err| ApiError::invalid_request(err.to_string()))?;
+    println!("{}", json);
+    Ok(())
+}
+
+fn io_error(path: &Path, err: std::io::Error) -> ApiError {
+    ApiError::invalid_request(format!("{}: {}", path.display(), err))
+}
@@ -1,25 +1,107 @@
-use gas_exp::routes::{
-    compile_solidity_route, execute_calldatas_fork_route, execute_calldatas_route,
-};
-use rocket_cors::{AllowedHeaders, AllowedOrigins, CorsOptions};
+use clap::Parser;
+use gas_exp::admission::Gates;
+use gas_exp::auth::Auth;
+use gas_exp::caches::Caches;
+use gas_exp::compile::cache::CompileCache;
+use gas_exp::compile::workdir;
+use gas_exp::compression::Compression;
+use gas_exp::config::{AppConfig, ConfigArgs};
+use gas_exp::cors;
+use gas_exp::error;
+use gas_exp::gas;
+use gas_exp::jobs::JobQueue;
+use gas_exp::legacy::Legacy;
+use gas_exp::results::{FsStore, Results};
+use gas_exp::routes;
+use gas_exp::sessions::Sessions;
+use gas_exp::shutdown::{self, InFlight};
+use gas_exp::snapshots::Snapshots;
+use gas_exp::telemetry::{self, RequestLogger};
+use gas_exp::webhooks::Webhooks;
+use rocket::data::{Limits, ToByteUnit};
+use rocket::fairing::AdHoc;
 
 #[macro_use]
 extern crate rocket;
 
+/// Serves the API. Settings come from `evm-repl.toml`, the environment and these flags, each over
+/// the one before.
+#[derive(Parser)]
+#[command(name = "server", version)]
+struct Args {
+    #[command(flatten)]
+    config: ConfigArgs,
+}
+
 #[launch]
 fn rocket() -> _ {
-    // Configure CORS options
-    let cors = CorsOptions::default()
-        .allowed_origins(AllowedOrigins::all())
-        .allowed_headers(AllowedHeaders::all())
-        .allow_credentials(true);
-
-    rocket::build().attach(cors.to_cors().unwrap()).mount(
-        "/",
-        routes![
-            execute_calldatas_route,
-            compile_solidity_route,
-            execute_calldatas_fork_route,
-        ],
-    )
+    let args = Args::parse();
+    let config = AppConfig::from_args(&args.config).expect("invalid server configuration");
+    telemetry::init(config.log_json);
+    workdir::sweep(&std::env::temp_dir(), config.limits.stale_compile_dir_age);
+
+    let cors = cors::cors(&config.cors).expect("invalid CORS configuration");
+
+    // Requests over the JSON or MessagePack limit are refused before they're read into memory
+    let figment = rocket::Config::figment().merge((
+        "limits",
+        Limits::default()
+            .limit("json", config.limits.max_request_bytes.bytes())
+            .limit("msgpack", config.limits.max_request_bytes.bytes()),
+    ));
+
+    let in_flight = InFlight::default();
+    let compression = Compression {
+        threshold: config.compression_threshold,
+    };
+    let snapshots = Snapshots::new(&config.limits);
+    let mut caches = Caches::default();
+    caches.register(snapshots.clone());
+    let compile_cache = CompileCache::new(&config.limits);
+    caches.register(compile_cache.clone());
+    caches.register(gas::code::cache());
+    caches.register(gas::rpc_diagnostics::cache());
+    let gates = Gates::new(&config.limits);
+    let mut jobs = JobQueue::new(&config.limits, in_flight.clone(), &gates);
+    if let Some(webhooks) = Webhooks::new(&config) {
+        jobs = jobs.with_webhooks(webhooks);
+    }
+    let results = Results::new(
+        FsStore::new(&config.results_dir).expect("can't open the results directory"),
+        &config.limits,
+    );
+
+    let logger = RequestLogger::default();
+
+    let mut rocket = rocket::custom(figment)
+        .manage(jobs)
+        .manage(snapshots)
+        .manage(compile_cache)
+        .manage(caches)
+        .manage(gates)
+        .manage(Sessions::new(&config.limits))
+        .manage(results.clone())
+        .manage(in_flight)
+        .manage(logger.metrics())
+        .manage(Auth::new(&config))
+        .manage(config)
+        .attach(cors)
+        .attach(logger)
+        // Before compression, so adapters see plain JSON
+        .attach(Legacy::with_adapters())
+        .attach(compression)
+        .attach(AdHoc::on_liftoff("Expire stored results", |_| {
+            Box::pin(async move {
+                tokio::spawn(results.collect_garbage_forever());
+            })
+        }))
+        .attach(AdHoc::on_shutdown("Drain and clean up", |rocket| {
+            Box::pin(shutdown::shutdown(rocket))
+        }))
+        .register("/", error::catchers())
+        .mount("/", routes::swagger_ui());
+    for (base, routes) in routes::versions() {
+        rocket = rocket.mount(base, routes);
+    }
+    rocket
 }
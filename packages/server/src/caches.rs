@@ -0,0 +1,107 @@
+use rocket::http::Status;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+use crate::error::ApiError;
+
+/// Where a cache stands, as reported by `/admin/caches`.
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheStats {
+    pub entries: usize,
+    /// A rough estimate of the memory held
+    pub bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// Seconds since the oldest entry was stored, if there is one
+    pub oldest_entry_age_secs: Option<u64>,
+}
+
+/// A cache an operator can inspect and flush.
+pub trait Cache: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn stats(&self) -> CacheStats;
+    /// Drops every entry, returning how many there were.
+    fn clear(&self) -> usize;
+}
+
+/// Every cache registered at startup.
+#[derive(Clone, Default)]
+pub struct Caches {
+    caches: Vec<Arc<dyn Cache>>,
+}
+
+impl Caches {
+    pub fn register(&mut self, cache: impl Cache + 'static) {
+        self.caches.push(Arc::new(cache));
+    }
+
+    pub fn stats(&self) -> BTreeMap<&'static str, CacheStats> {
+        self.caches
+            .iter()
+            .map(|cache| (cache.name(), cache.stats()))
+            .collect()
+    }
+
+    /// Clears the named cache, or all of them, returning the entries dropped from each.
+    pub fn flush(&self, name: Option<&str>) -> Result<BTreeMap<&'static str, usize>, ApiError> {
+        let flushed: BTreeMap<_, _> = self
+            .caches
+            .iter()
+            .filter(|cache| name.map_or(true, |name| cache.name() == name))
+            .map(|cache| (cache.name(), cache.clear()))
+            .collect();
+        if let Some(name) = name.filter(|_| flushed.is_empty()) {
+            return Err(ApiError::new(
+                Status::NotFound,
+                "CACHE_NOT_FOUND",
+                format!("no cache named {}", name),
+            ));
+        }
+        Ok(flushed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct Counter(AtomicUsize);
+
+    impl Cache for Arc<Counter> {
+        fn name(&self) -> &'static str {
+            "counter"
+        }
+
+        fn stats(&self) -> CacheStats {
+            CacheStats {
+                entries: self.0.load(Ordering::SeqCst),
+                ..Default::default()
+            }
+        }
+
+        fn clear(&self) -> usize {
+            self.0.swap(0, Ordering::SeqCst)
+        }
+    }
+
+    #[test]
+    fn test_flush() {
+        let counter = Arc::new(Counter(AtomicUsize::new(3)));
+        let mut caches = Caches::default();
+        caches.register(counter.clone());
+        assert_eq!(caches.stats()["counter"].entries, 3);
+
+        assert_eq!(
+            caches.flush(Some("other")).err().unwrap().code,
+            "CACHE_NOT_FOUND"
+        );
+        assert_eq!(caches.flush(Some("counter")).unwrap()["counter"], 3);
+        assert_eq!(caches.stats()["counter"].entries, 0);
+        assert_eq!(caches.flush(None).unwrap()["counter"], 0);
+    }
+}
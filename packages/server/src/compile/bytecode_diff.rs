@@ -0,0 +1,317 @@
+//! Two deployed bytecodes side by side, instruction by instruction, for seeing what a source change
+//! did to the code: the runs of instructions added, removed or changed, and the source each run
+//! was compiled from.
+
+use alloy_primitives::Bytes;
+use foundry_compilers::artifacts::sourcemap::SourceElement;
+use revm::interpreter::{opcode, OpCode};
+use serde::Serialize;
+use std::collections::HashSet;
+use utoipa::ToSchema;
+
+use super::cache::Sources;
+
+// Past this many cells of the alignment table, whatever lies between the common prefix and suffix
+// is reported as one change rather than aligned
+const MAX_CELLS: usize = 1 << 22;
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Instruction {
+    pub pc: usize,
+    /// `PUSH2`, `SSTORE`, or `UNKNOWN(0x0c)` for a byte that isn't an opcode
+    pub opcode: String,
+    /// A push's data
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub immediate: Option<Bytes>,
+    /// Where it was compiled from, going by the source map. None without one, and for code solc
+    /// generated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<SourceRange>,
+}
+
+/// A span of a source file, as a source map gives it.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceRange {
+    /// solc's number for the file
+    pub source_index: u32,
+    /// Only when the sources are known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    /// Of the span's start, from 1. Only when the sources are known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    /// Bytes into the file
+    pub offset: usize,
+    pub length: usize,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum HunkKind {
+    /// Only in `b`
+    Added,
+    /// Only in `a`
+    Removed,
+    Changed,
+}
+
+/// A run of instructions where the two codes differ.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Hunk {
+    pub kind: HunkKind,
+    pub a: HunkSide,
+    pub b: HunkSide,
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HunkSide {
+    /// Empty on the side an addition or removal isn't in
+    pub instructions: Vec<Instruction>,
+    /// The distinct source ranges of `instructions`, in order
+    pub sources: Vec<SourceRange>,
+}
+
+/// `code` as instructions, each with the source `source_map` maps it to. Files are named from
+/// `sources`, which solc numbers in name order.
+pub fn disassemble(
+    code: &[u8],
+    source_map: Option<&[SourceElement]>,
+    sources: &Sources,
+) -> Vec<Instruction> {
+    let files: Vec<_> = sources.iter().collect();
+    let mut instructions = Vec::new();
+    let mut at = 0;
+    while let Some(&op) = code.get(at) {
+        let size = match op {
+            opcode::PUSH1..=opcode::PUSH32 => (op - opcode::PUSH0) as usize,
+            _ => 0,
+        };
+        // A push cut short by the end of the code gets what's there
+        let immediate = (size > 0)
+            .then(|| Bytes::copy_from_slice(&code[at + 1..(at + 1 + size).min(code.len())]));
+        let source = source_map
+            .and_then(|map| map.get(instructions.len()))
+            .and_then(|element| source_range(element, &files));
+        instructions.push(Instruction {
+            pc: at,
+            opcode: name(op),
+            immediate,
+            source,
+        });
+        at += 1 + size;
+    }
+    instructions
+}
+
+/// The hunks where `b` differs from `a`. Instructions match when their opcodes and push data do,
+/// or when both push a JUMPDEST of their own code, as adding code anywhere moves every jump
+/// target after it.
+pub fn diff(a: &[Instruction], b: &[Instruction]) -> Vec<Hunk> {
+    let (targets_a, targets_b) = (jump_targets(a), jump_targets(b));
+    let same = |x: &Instruction, y: &Instruction| {
+        x.opcode == y.opcode
+            && (x.immediate == y.immediate
+                || (pushes_target(x, &targets_a) && pushes_target(y, &targets_b)))
+    };
+    let prefix = a.iter().zip(b).take_while(|&(x, y)| same(x, y)).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|&(x, y)| same(x, y))
+        .count();
+    let (a, b) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    let pairs = if a.len().saturating_mul(b.len()) > MAX_CELLS {
+        Vec::new()
+    } else {
+        matching(a, b, &same)
+    };
+
+    // Whatever's unmatched between one matched pair and the next is a hunk
+    let mut hunks = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for (next_i, next_j) in pairs.into_iter().chain([(a.len(), b.len())]) {
+        if next_i > i || next_j > j {
+            hunks.push(hunk(&a[i..next_i], &b[j..next_j]));
+        }
+        (i, j) = (next_i + 1, next_j + 1);
+    }
+    hunks
+}
+
+// The index pairs a longest common subsequence of `a` and `b` is made of, in order
+fn matching(
+    a: &[Instruction],
+    b: &[Instruction],
+    same: impl Fn(&Instruction, &Instruction) -> bool,
+) -> Vec<(usize, usize)> {
+    let width = b.len() + 1;
+    // At `i * width + j`, the length of the longest for `a[i..]` and `b[j..]`
+    let mut lengths = vec![0u32; (a.len() + 1) * width];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i * width + j] = if same(&a[i], &b[j]) {
+                lengths[(i + 1) * width + j + 1] + 1
+            } else {
+                lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+            };
+        }
+    }
+    let mut pairs = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if same(&a[i], &b[j]) {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+fn hunk(a: &[Instruction], b: &[Instruction]) -> Hunk {
+    let kind = match (a.is_empty(), b.is_empty()) {
+        (true, _) => HunkKind::Added,
+        (_, true) => HunkKind::Removed,
+        _ => HunkKind::Changed,
+    };
+    Hunk {
+        kind,
+        a: side(a),
+        b: side(b),
+    }
+}
+
+fn side(instructions: &[Instruction]) -> HunkSide {
+    let mut sources: Vec<SourceRange> = Vec::new();
+    for source in instructions.iter().filter_map(|i| i.source.as_ref()) {
+        if !sources.contains(source) {
+            sources.push(source.clone());
+        }
+    }
+    HunkSide {
+        instructions: instructions.to_vec(),
+        sources,
+    }
+}
+
+fn jump_targets(code: &[Instruction]) -> HashSet<usize> {
+    code.iter()
+        .filter(|instruction| instruction.opcode == "JUMPDEST")
+        .map(|instruction| instruction.pc)
+        .collect()
+}
+
+fn pushes_target(instruction: &Instruction, targets: &HashSet<usize>) -> bool {
+    instruction
+        .immediate
+        .as_ref()
+        .filter(|data| data.len() <= std::mem::size_of::<usize>())
+        .map(|data| data.iter().fold(0, |value, b| value << 8 | *b as usize))
+        .is_some_and(|pc| targets.contains(&pc))
+}
+
+fn source_range(element: &SourceElement, files: &[(&String, &String)]) -> Option<SourceRange> {
+    let source_index = u32::try_from(element.index_i32()).ok()?;
+    let offset = element.offset() as usize;
+    let file = files.get(source_index as usize);
+    Some(SourceRange {
+        source_index,
+        file: file.map(|(name, _)| name.to_string()),
+        line: file.and_then(|(_, text)| Some(text.get(..offset)?.matches('\n').count() + 1)),
+        offset,
+        length: element.length() as usize,
+    })
+}
+
+fn name(op: u8) -> String {
+    OpCode::new(op).map_or_else(
+        || format!("UNKNOWN(0x{:02x})", op),
+        |op| op.as_str().to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::solidity::{compile, SolidityFile};
+    use crate::config::Limits;
+    use crate::gas::hardfork::without_metadata;
+    use alloy_primitives::hex;
+    use foundry_compilers::Artifact;
+
+    const SIMPLE_STORAGE: &str = include_str!("fixtures/SimpleStorage.sol");
+
+    fn instructions(code: &str) -> Vec<Instruction> {
+        disassemble(&hex::decode(code).unwrap(), None, &Sources::new())
+    }
+
+    fn deployed(content: &str) -> Vec<Instruction> {
+        let files = [SolidityFile {
+            name: "SimpleStorage.sol".to_string(),
+            content: content.to_string(),
+        }];
+        let compiled = compile(&files, &Limits::default()).unwrap();
+        let (_, _, contract, _) = compiled
+            .contracts
+            .contracts_with_files_and_version()
+            .find(|(_, name, _, _)| name.as_str() == "SimpleStorage")
+            .unwrap();
+        let code = contract.get_deployed_bytecode_bytes().unwrap();
+        let source_map = contract.get_source_map_deployed().unwrap().unwrap();
+        let sources = files
+            .into_iter()
+            .map(|file| (file.name, file.content))
+            .collect();
+        disassemble(without_metadata(&code), Some(&source_map), &sources)
+    }
+
+    #[test]
+    fn test_moved_jump_targets_match() {
+        // PUSH1 4, JUMP, INVALID, JUMPDEST, STOP; then the same after CALLER, POP, which moves
+        // the JUMPDEST to 6
+        let hunks = diff(
+            &instructions("600456fe5b00"),
+            &instructions("3350600656fe5b00"),
+        );
+        assert_eq!(hunks.len(), 1);
+        assert_eq!(hunks[0].kind, HunkKind::Added);
+        let added: Vec<_> = hunks[0].b.instructions.iter().map(|i| &i.opcode).collect();
+        assert_eq!(added, ["CALLER", "POP"]);
+
+        // Pushing anything else is a change
+        let hunks = diff(&instructions("600100"), &instructions("600200"));
+        assert_eq!(hunks.len(), 1);
+        assert_eq!(hunks[0].kind, HunkKind::Changed);
+        assert_eq!(hunks[0].a.instructions[0].pc, 0);
+    }
+
+    #[test]
+    fn test_extra_statement_is_one_hunk() {
+        let before = deployed(SIMPLE_STORAGE);
+        let after = deployed(&SIMPLE_STORAGE.replace(
+            "storedData = x;",
+            "storedData = x;\n        storedData = x;",
+        ));
+        assert!(diff(&before, &before).is_empty());
+
+        let hunks = diff(&before, &after);
+        assert_eq!(hunks.len(), 1, "{:#?}", hunks);
+        assert_eq!(hunks[0].kind, HunkKind::Added);
+        assert!(hunks[0]
+            .b
+            .sources
+            .iter()
+            .any(|source| source.line == Some(9)
+                && source.file.as_deref() == Some("SimpleStorage.sol")));
+    }
+}
@@ -0,0 +1,187 @@
+use alloy_primitives::hex;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use super::solidity::SolidityFile;
+use crate::caches::{Cache, CacheStats};
+use crate::config::Limits;
+
+/// Source files by name, as they were sent to be compiled.
+pub type Sources = BTreeMap<String, String>;
+
+struct Entry {
+    body: Arc<Vec<u8>>,
+    sources: Arc<Sources>,
+    stored_at: Instant,
+    used_at: Instant,
+}
+
+/// Serialized `/compile_solidity` responses, and the sources they came from, by request hash,
+/// which doubles as their `ETag`. Clones share the same entries.
+#[derive(Clone)]
+pub struct CompileCache {
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+    max: usize,
+    ttl: Duration,
+}
+
+/// Identifies a set of sources regardless of the order they were sent in.
+pub fn request_hash(files: &[SolidityFile]) -> String {
+    let mut files: Vec<_> = files.iter().collect();
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut hasher = Sha256::new();
+    for file in files {
+        // Length-prefixed so no two sets of files hash the same input
+        for part in [&file.name, &file.content] {
+            hasher.update((part.len() as u64).to_be_bytes());
+            hasher.update(part.as_bytes());
+        }
+    }
+    hex::encode(hasher.finalize())
+}
+
+impl CompileCache {
+    pub fn new(limits: &Limits) -> Self {
+        CompileCache {
+            entries: Arc::default(),
+            hits: Arc::default(),
+            misses: Arc::default(),
+            max: limits.max_compile_cache_entries.max(1),
+            ttl: limits.compile_cache_ttl,
+        }
+    }
+
+    pub fn get(&self, hash: &str) -> Option<Arc<Vec<u8>>> {
+        let mut entries = self.lock();
+        let Some(entry) = entries.get_mut(hash) else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        entry.used_at = Instant::now();
+        Some(entry.body.clone())
+    }
+
+    /// A response and its sources, for routes that build on an earlier compile. Not counted as a
+    /// hit or a miss, which are about answering compiles.
+    pub fn compiled(&self, hash: &str) -> Option<(Arc<Vec<u8>>, Arc<Sources>)> {
+        let mut entries = self.lock();
+        let entry = entries.get_mut(hash)?;
+        entry.used_at = Instant::now();
+        Some((entry.body.clone(), entry.sources.clone()))
+    }
+
+    /// Stores a response, evicting the least recently used past the limit.
+    pub fn insert(&self, hash: String, body: Vec<u8>, sources: Sources) -> Arc<Vec<u8>> {
+        let body = Arc::new(body);
+        let mut entries = self.lock();
+        while entries.len() >= self.max && !entries.contains_key(&hash) {
+            let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.used_at)
+                .map(|(hash, _)| hash.clone())
+            else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+        entries.insert(
+            hash,
+            Entry {
+                body: body.clone(),
+                sources: Arc::new(sources),
+                stored_at: Instant::now(),
+                used_at: Instant::now(),
+            },
+        );
+        body
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Entry>> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| entry.used_at.elapsed() < self.ttl);
+        entries
+    }
+}
+
+impl Cache for CompileCache {
+    fn name(&self) -> &'static str {
+        "compile"
+    }
+
+    fn stats(&self) -> CacheStats {
+        let entries = self.lock();
+        CacheStats {
+            entries: entries.len(),
+            bytes: entries
+                .values()
+                .map(|entry| {
+                    let sources: usize = entry
+                        .sources
+                        .iter()
+                        .map(|(name, content)| name.len() + content.len())
+                        .sum();
+                    entry.body.len() + sources
+                })
+                .sum(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            oldest_entry_age_secs: entries
+                .values()
+                .map(|entry| entry.stored_at.elapsed().as_secs())
+                .max(),
+        }
+    }
+
+    fn clear(&self) -> usize {
+        let mut entries = self.lock();
+        let cleared = entries.len();
+        entries.clear();
+        cleared
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &str, content: &str) -> SolidityFile {
+        SolidityFile {
+            name: name.to_string(),
+            content: content.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_request_hash() {
+        let a = [file("A.sol", "a"), file("B.sol", "b")];
+        let b = [file("B.sol", "b"), file("A.sol", "a")];
+        assert_eq!(request_hash(&a), request_hash(&b));
+        assert_ne!(
+            request_hash(&[file("A.sol", "ab")]),
+            request_hash(&[file("A.so", "lab")])
+        );
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let cache = CompileCache::new(&Limits {
+            max_compile_cache_entries: 2,
+            ..Limits::default()
+        });
+        cache.insert("a".to_string(), b"1".to_vec(), Sources::new());
+        cache.insert("b".to_string(), b"2".to_vec(), Sources::new());
+        assert!(cache.get("a").is_some());
+        cache.insert("c".to_string(), b"3".to_vec(), Sources::new());
+
+        assert!(cache.get("b").is_none());
+        assert_eq!(cache.get("c").unwrap().as_slice(), b"3");
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses), (2, 2, 1));
+    }
+}
@@ -0,0 +1,178 @@
+//! Deployed code as a listing to read: an instruction a line, split into basic blocks, with the
+//! source each instruction was compiled from when there's a source map.
+
+use alloy_primitives::Bytes;
+use foundry_compilers::artifacts::sourcemap::SourceElement;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+use super::bytecode_diff::{disassemble, Instruction};
+use super::cache::Sources;
+
+// How much of a source range a snippet shows
+const MAX_SNIPPET: usize = 80;
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ListingEntry {
+    pub pc: usize,
+    /// `PUSH2`, `SSTORE`, or `UNKNOWN(0x0c)` for a byte that isn't an opcode
+    pub opcode: String,
+    /// A push's data. Shorter than the push says when the code ends first.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub push_data: Option<Bytes>,
+    /// The basic block it's in, counting from 0. A block starts at the start of the code, at
+    /// each JUMPDEST and after each instruction that jumps or stops.
+    pub block: usize,
+    /// A JUMPDEST's name, `L<n>` in code order
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// The label of the JUMPDEST a push pushes, for what's likely a jump target
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// Only with a source map, and not for code solc generated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    /// The first line of the source it was compiled from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snippet: Option<String>,
+}
+
+/// `code` as a listing, annotated from `source_map` and `sources` as `disassemble` does.
+pub fn listing(
+    code: &[u8],
+    source_map: Option<&[SourceElement]>,
+    sources: &Sources,
+) -> Vec<ListingEntry> {
+    let instructions = disassemble(code, source_map, sources);
+    let labels: BTreeMap<usize, String> = instructions
+        .iter()
+        .filter(|instruction| instruction.opcode == "JUMPDEST")
+        .enumerate()
+        .map(|(n, instruction)| (instruction.pc, format!("L{}", n)))
+        .collect();
+
+    let mut block = 0;
+    let mut ended = false;
+    instructions
+        .into_iter()
+        .enumerate()
+        .map(|(index, instruction)| {
+            if index > 0 && (ended || instruction.opcode == "JUMPDEST") {
+                block += 1;
+            }
+            ended = ends_block(&instruction.opcode);
+            entry(instruction, block, &labels, sources)
+        })
+        .collect()
+}
+
+fn entry(
+    instruction: Instruction,
+    block: usize,
+    labels: &BTreeMap<usize, String>,
+    sources: &Sources,
+) -> ListingEntry {
+    let target = instruction
+        .immediate
+        .as_ref()
+        .filter(|data| data.len() <= std::mem::size_of::<usize>())
+        .map(|data| data.iter().fold(0, |value, b| value << 8 | *b as usize))
+        .and_then(|pc| labels.get(&pc).cloned());
+    let source = instruction.source.as_ref();
+    let snippet = source.and_then(|source| {
+        let text = sources.get(source.file.as_ref()?)?;
+        let range = text.get(source.offset..source.offset + source.length)?;
+        let first = range.lines().next()?.trim_end();
+        Some(match first.char_indices().nth(MAX_SNIPPET) {
+            Some((end, _)) => format!("{}...", &first[..end]),
+            None => first.to_string(),
+        })
+    });
+    ListingEntry {
+        label: labels.get(&instruction.pc).cloned(),
+        pc: instruction.pc,
+        push_data: instruction.immediate,
+        block,
+        target,
+        source_file: source.and_then(|source| source.file.clone()),
+        line: source.and_then(|source| source.line),
+        snippet,
+        opcode: instruction.opcode,
+    }
+}
+
+// Whether nothing after it in the code runs straight after it
+fn ends_block(opcode: &str) -> bool {
+    matches!(
+        opcode,
+        "JUMP" | "JUMPI" | "STOP" | "RETURN" | "REVERT" | "INVALID" | "SELFDESTRUCT"
+    ) || opcode.starts_with("UNKNOWN")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::solidity::{compile, SolidityFile};
+    use crate::config::Limits;
+    use alloy_primitives::{bytes, hex};
+    use foundry_compilers::Artifact;
+
+    #[test]
+    fn test_simple_storage_selectors() {
+        let files = [SolidityFile {
+            name: "SimpleStorage.sol".to_string(),
+            content: include_str!("fixtures/SimpleStorage.sol").to_string(),
+        }];
+        let compiled = compile(&files, &Limits::default()).unwrap();
+        let (_, _, contract, _) = compiled
+            .contracts
+            .contracts_with_files_and_version()
+            .find(|(_, name, _, _)| name.as_str() == "SimpleStorage")
+            .unwrap();
+        let code = contract.get_deployed_bytecode_bytes().unwrap();
+        let source_map = contract.get_source_map_deployed().unwrap().unwrap();
+        let sources = files
+            .into_iter()
+            .map(|file| (file.name, file.content))
+            .collect();
+
+        let listing = listing(&code, Some(&source_map), &sources);
+        // storedData(), set(uint256) and get()
+        for selector in ["2a1afcd9", "60fe47b1", "6d4ce63c"] {
+            assert!(
+                listing.iter().any(|entry| entry.opcode == "PUSH4"
+                    && entry.push_data.as_ref().map(hex::encode).as_deref() == Some(selector)),
+                "no PUSH4 {}",
+                selector
+            );
+        }
+        assert!(listing
+            .iter()
+            .any(|entry| entry.snippet.as_deref() == Some("storedData = x")));
+        assert!(listing
+            .iter()
+            .filter(|entry| entry.opcode == "JUMPDEST")
+            .all(|entry| entry.label.is_some()));
+    }
+
+    #[test]
+    fn test_invalid_and_truncated_code() {
+        // PUSH1 4, JUMPI, an unassigned opcode, JUMPDEST, then a PUSH2 with one byte left
+        let listing = listing(&bytes!("6004570c5b61ff"), None, &Sources::new());
+        let opcodes: Vec<_> = listing.iter().map(|entry| entry.opcode.as_str()).collect();
+        assert_eq!(
+            opcodes,
+            ["PUSH1", "JUMPI", "UNKNOWN(0x0c)", "JUMPDEST", "PUSH2"]
+        );
+        let blocks: Vec<_> = listing.iter().map(|entry| entry.block).collect();
+        assert_eq!(blocks, [0, 0, 1, 2, 2]);
+        assert_eq!(listing[0].target.as_deref(), Some("L0"));
+        assert_eq!(listing[3].label.as_deref(), Some("L0"));
+        assert_eq!(listing[4].push_data, Some(bytes!("ff")));
+    }
+}
@@ -0,0 +1,144 @@
+//! Compiled contracts as the artifact files Hardhat writes (`hh-sol-artifact-1`), so tools that
+//! read those can take ours unchanged.
+
+use alloy_json_abi::JsonAbi;
+use foundry_compilers::artifacts::{Bytecode, BytecodeObject, Contract, Offsets};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+pub const FORMAT: &str = "hh-sol-artifact-1";
+
+/// Where library addresses go: `{ <source name>: { <library>: [{ start, length }] } }`
+pub type LinkReferences = BTreeMap<String, BTreeMap<String, Vec<Offsets>>>;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HardhatArtifact {
+    /// Always `hh-sol-artifact-1`
+    #[serde(rename = "_format")]
+    pub format: String,
+    pub contract_name: String,
+    /// The file the contract is in, as it was sent
+    pub source_name: String,
+    #[schema(value_type = Vec<Object>)]
+    pub abi: JsonAbi,
+    /// `0x`-prefixed hex, with `__$...$__` placeholders where libraries are yet to be linked.
+    /// Just `0x` for interfaces and abstract contracts.
+    pub bytecode: String,
+    pub deployed_bytecode: String,
+    #[schema(value_type = Object)]
+    pub link_references: LinkReferences,
+    #[schema(value_type = Object)]
+    pub deployed_link_references: LinkReferences,
+}
+
+impl HardhatArtifact {
+    /// `contract_name` from `source_name`, as compiled. `strip` names link references' sources
+    /// the way the request did, as `source_name` does.
+    pub fn new(
+        source_name: &str,
+        contract_name: &str,
+        contract: &Contract,
+        strip: impl Fn(&str) -> String,
+    ) -> Self {
+        let evm = contract.evm.as_ref();
+        let bytecode = evm.and_then(|evm| evm.bytecode.as_ref());
+        let deployed = evm
+            .and_then(|evm| evm.deployed_bytecode.as_ref())
+            .and_then(|deployed| deployed.bytecode.as_ref());
+        HardhatArtifact {
+            format: FORMAT.to_string(),
+            contract_name: contract_name.to_string(),
+            source_name: source_name.to_string(),
+            abi: contract.abi.clone().unwrap_or_default(),
+            bytecode: hex_object(bytecode),
+            deployed_bytecode: hex_object(deployed),
+            link_references: link_references(bytecode, &strip),
+            deployed_link_references: link_references(deployed, &strip),
+        }
+    }
+}
+
+fn hex_object(bytecode: Option<&Bytecode>) -> String {
+    match bytecode.map(|bytecode| &bytecode.object) {
+        Some(BytecodeObject::Bytecode(bytes)) => bytes.to_string(),
+        // solc leaves the prefix off unlinked code
+        Some(BytecodeObject::Unlinked(code)) => {
+            format!("0x{}", code.strip_prefix("0x").unwrap_or(code))
+        }
+        None => "0x".to_string(),
+    }
+}
+
+fn link_references(bytecode: Option<&Bytecode>, strip: impl Fn(&str) -> String) -> LinkReferences {
+    bytecode
+        .map(|bytecode| {
+            bytecode
+                .link_references
+                .iter()
+                .map(|(source, libraries)| (strip(source), libraries.clone()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn solc_output(json: &str) -> Contract {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_simple_storage_matches_hardhat() {
+        // solc 0.8.26, optimizer off, as Hardhat compiled contracts/SimpleStorage.sol
+        let contract = solc_output(include_str!("fixtures/simple_storage.solc.json"));
+        let artifact = HardhatArtifact::new(
+            "contracts/SimpleStorage.sol",
+            "SimpleStorage",
+            &contract,
+            str::to_string,
+        );
+        let golden: Value =
+            serde_json::from_str(include_str!("fixtures/SimpleStorage.json")).unwrap();
+        assert_eq!(serde_json::to_value(&artifact).unwrap(), golden);
+    }
+
+    #[test]
+    fn test_unlinked_library() {
+        let contract = solc_output(
+            r#"{
+                "abi": [],
+                "evm": {
+                    "bytecode": {
+                        "object": "73__$2a5e7a2b0e9a1c3d4f5b6c7d8e9f0a1b2c$__6000",
+                        "linkReferences": {
+                            "/tmp/evm-repl-x/src/Math.sol": {
+                                "Math": [{ "start": 1, "length": 20 }]
+                            }
+                        }
+                    }
+                }
+            }"#,
+        );
+        let artifact = HardhatArtifact::new("Uses.sol", "Uses", &contract, |source| {
+            source
+                .trim_start_matches("/tmp/evm-repl-x/src/")
+                .to_string()
+        });
+
+        assert_eq!(
+            artifact.bytecode,
+            "0x73__$2a5e7a2b0e9a1c3d4f5b6c7d8e9f0a1b2c$__6000"
+        );
+        assert_eq!(artifact.deployed_bytecode, "0x");
+        assert_eq!(
+            serde_json::to_value(&artifact.link_references).unwrap(),
+            serde_json::json!({ "Math.sol": { "Math": [{ "start": 1, "length": 20 }] } })
+        );
+        assert!(artifact.deployed_link_references.is_empty());
+    }
+}
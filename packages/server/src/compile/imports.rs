@@ -0,0 +1,404 @@
+//! Which submitted files import which, read off their import directives without compiling: what
+//! the frontend shows before a paste is compiled, and what a partial compile groups files by.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use utoipa::ToSchema;
+
+use super::solidity::SolidityFile;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportGraph {
+    /// Every import directive, in the order the files were sent and, within a file, written
+    pub edges: Vec<ImportEdge>,
+    /// The packages external imports are from, sorted
+    pub external_packages: Vec<String>,
+    /// Each set of files that import one another round in a circle, in the order they were
+    /// sent. solc accepts them, but they're usually a mistake.
+    pub cycles: Vec<Vec<String>>,
+    /// Files that import none of the other files and that none of them import
+    pub orphans: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportEdge {
+    /// The importing file
+    pub from: String,
+    /// As written
+    pub path: String,
+    /// Of the `import` keyword, from 1
+    pub line: usize,
+    pub kind: ImportKind,
+    /// For an internal import, the file it resolves to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+    /// For an external import, `@scope/name`, or the path's first directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub package: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum ImportKind {
+    /// Of a submitted file
+    Internal,
+    /// Of a package's file, e.g. `@openzeppelin/contracts/...`. Nothing is installed to compile
+    /// against, so these don't resolve either.
+    External,
+    /// Of a file that wasn't sent, by a path relative to the importer or to the sources
+    Unresolved,
+}
+
+/// An import directive's path as written, and where its `import` keyword is in the source.
+pub(crate) struct Directive {
+    pub offset: usize,
+    pub path: String,
+}
+
+/// `files`' import graph.
+pub fn import_graph(files: &[SolidityFile]) -> ImportGraph {
+    let index: BTreeMap<&str, usize> = files
+        .iter()
+        .enumerate()
+        .map(|(i, file)| (file.name.as_str(), i))
+        .collect();
+    // The first directories of the files sent, which a path starting with one is into
+    let directories: BTreeSet<&str> = files
+        .iter()
+        .filter_map(|file| Some(file.name.split_once('/')?.0))
+        .collect();
+
+    let mut edges = Vec::new();
+    // Each file's internal imports, by index
+    let mut imports = vec![Vec::new(); files.len()];
+    for (i, file) in files.iter().enumerate() {
+        for directive in directives(&file.content) {
+            let resolved = resolve(&file.name, &directive.path);
+            let (kind, to, package) = match index.get(resolved.as_str()) {
+                Some(&j) => {
+                    imports[i].push(j);
+                    (ImportKind::Internal, Some(resolved), None)
+                }
+                None => match package(&directive.path, &directories) {
+                    Some(package) => (ImportKind::External, None, Some(package)),
+                    None => (ImportKind::Unresolved, None, None),
+                },
+            };
+            edges.push(ImportEdge {
+                from: file.name.clone(),
+                line: file.content[..directive.offset].matches('\n').count() + 1,
+                path: directive.path,
+                kind,
+                to,
+                package,
+            });
+        }
+    }
+
+    let external_packages: BTreeSet<_> = edges
+        .iter()
+        .filter_map(|edge| edge.package.clone())
+        .collect();
+    let name = |i: usize| files[i].name.clone();
+    let cycles = components(&imports)
+        .into_iter()
+        .filter(|component| match component[..] {
+            [i] => imports[i].contains(&i),
+            _ => true,
+        })
+        .map(|component| component.into_iter().map(name).collect())
+        .collect();
+    let imported: BTreeSet<usize> = imports
+        .iter()
+        .enumerate()
+        .flat_map(|(i, targets)| targets.iter().filter(move |&&j| j != i))
+        .copied()
+        .collect();
+    let orphans = (0..files.len())
+        .filter(|i| !imported.contains(i) && imports[*i].iter().all(|j| j == i))
+        .map(name)
+        .collect();
+    ImportGraph {
+        edges,
+        external_packages: external_packages.into_iter().collect(),
+        cycles,
+        orphans,
+    }
+}
+
+// The package an import of `path` that isn't a submitted file is from, if it looks like one's:
+// scoped, or into a directory none of the files sent are in
+fn package(path: &str, directories: &BTreeSet<&str>) -> Option<String> {
+    if path.starts_with('.') {
+        return None;
+    }
+    let mut parts = path.split('/');
+    let first = parts.next()?;
+    if first.starts_with('@') {
+        return Some(format!("{}/{}", first, parts.next()?));
+    }
+    parts.next()?;
+    (!directories.contains(first)).then(|| first.to_string())
+}
+
+// The strongly connected components of the graph `edges` gives each node's successors in, each
+// in node order and the components in the order of their first nodes
+fn components(edges: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    struct Tarjan<'a> {
+        edges: &'a [Vec<usize>],
+        index: Vec<Option<usize>>,
+        low: Vec<usize>,
+        stack: Vec<usize>,
+        on_stack: Vec<bool>,
+        next: usize,
+        components: Vec<Vec<usize>>,
+    }
+
+    impl Tarjan<'_> {
+        fn visit(&mut self, node: usize) {
+            self.index[node] = Some(self.next);
+            self.low[node] = self.next;
+            self.next += 1;
+            self.stack.push(node);
+            self.on_stack[node] = true;
+            for &successor in &self.edges[node] {
+                match self.index[successor] {
+                    None => {
+                        self.visit(successor);
+                        self.low[node] = self.low[node].min(self.low[successor]);
+                    }
+                    Some(index) if self.on_stack[successor] => {
+                        self.low[node] = self.low[node].min(index);
+                    }
+                    Some(_) => {}
+                }
+            }
+            if Some(self.low[node]) == self.index[node] {
+                let mut component = Vec::new();
+                while let Some(member) = self.stack.pop() {
+                    self.on_stack[member] = false;
+                    component.push(member);
+                    if member == node {
+                        break;
+                    }
+                }
+                component.sort_unstable();
+                self.components.push(component);
+            }
+        }
+    }
+
+    let mut tarjan = Tarjan {
+        edges,
+        index: vec![None; edges.len()],
+        low: vec![0; edges.len()],
+        stack: Vec::new(),
+        on_stack: vec![false; edges.len()],
+        next: 0,
+        components: Vec::new(),
+    };
+    for node in 0..edges.len() {
+        if tarjan.index[node].is_none() {
+            tarjan.visit(node);
+        }
+    }
+    let mut components = tarjan.components;
+    components.sort_unstable_by_key(|component| component[0]);
+    components
+}
+
+/// `source`'s import directives, in the order written. Comments and strings are skipped, and a
+/// directive may run over several lines.
+pub(crate) fn directives(source: &str) -> Vec<Directive> {
+    let code = without_comments(source);
+    let bytes = code.as_bytes();
+    let mut directives = Vec::new();
+    let mut at = 0;
+    while at < bytes.len() {
+        match bytes[at] {
+            b'"' | b'\'' => at = string_end(bytes, at),
+            b if identifier(b) => {
+                let end = at + bytes[at..].iter().take_while(|&&b| identifier(b)).count();
+                if &bytes[at..end] != b"import" {
+                    at = end;
+                    continue;
+                }
+                // The directive's first string is its path, whatever it names from it
+                let mut path = None;
+                at = end;
+                while at < bytes.len() && bytes[at] != b';' {
+                    if matches!(bytes[at], b'"' | b'\'') {
+                        let start = at;
+                        at = string_end(bytes, at);
+                        // Nothing for a string left open
+                        if let Some(text) = code
+                            .get(start + 1..at - 1)
+                            .filter(|_| at - 1 > start && bytes[at - 1] == bytes[start])
+                        {
+                            path.get_or_insert_with(|| text.to_string());
+                        }
+                    } else {
+                        at += 1;
+                    }
+                }
+                directives.extend(path.map(|path| Directive {
+                    offset: end - "import".len(),
+                    path,
+                }));
+            }
+            _ => at += 1,
+        }
+    }
+    directives
+}
+
+fn identifier(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || b == b'$'
+}
+
+// Just past the end of the string starting at `start`, or the end of `code` when it isn't closed
+fn string_end(code: &[u8], start: usize) -> usize {
+    let quote = code[start];
+    let mut at = start + 1;
+    while at < code.len() {
+        match code[at] {
+            b'\\' => at += 2,
+            b if b == quote => return at + 1,
+            _ => at += 1,
+        }
+    }
+    code.len()
+}
+
+// `source` with each of its comments' characters but newlines blanked, so what's left is where
+// it was in the source. Strings are left as they are.
+fn without_comments(source: &str) -> String {
+    let blank = |code: &mut String, c: char| match c {
+        '\n' => code.push('\n'),
+        _ => code.extend(std::iter::repeat(' ').take(c.len_utf8())),
+    };
+    let mut code = String::with_capacity(source.len());
+    let mut chars = source.chars().peekable();
+    let mut quote = None;
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), _) => {
+                code.push(c);
+                if c == '\\' {
+                    code.extend(chars.next());
+                } else if c == q {
+                    quote = None;
+                }
+            }
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                code.push(c);
+            }
+            (None, '/') if chars.peek() == Some(&'/') => {
+                blank(&mut code, c);
+                while let Some(c) = chars.next_if(|&c| c != '\n') {
+                    blank(&mut code, c);
+                }
+            }
+            (None, '/') if chars.peek() == Some(&'*') => {
+                blank(&mut code, c);
+                let mut last = ' ';
+                for c in chars.by_ref() {
+                    blank(&mut code, c);
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+            }
+            _ => code.push(c),
+        }
+    }
+    code
+}
+
+/// The file `from` means by importing `path`: relative to `from`'s directory when it starts with
+/// `./` or `../`, else as it is, the sources directory being solc's base path.
+pub(crate) fn resolve(from: &str, path: &str) -> String {
+    if !(path.starts_with("./") || path.starts_with("../")) {
+        return path.to_string();
+    }
+    let mut parts: Vec<&str> = from.split('/').collect();
+    parts.pop();
+    for part in path.split('/') {
+        match part {
+            "." | "" => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &str, content: &str) -> SolidityFile {
+        SolidityFile {
+            name: name.to_string(),
+            content: content.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_directives() {
+        let source = "// import \"./Commented.sol\";\n\
+            /* import \"./Blocked.sol\";\n */ import {\n    A,\n    B\n} from \"./lib/AB.sol\";\n\
+            contract C { string s = \"import 'x';\"; } import * as D from '../D.sol';";
+        let directives = directives(source);
+        let paths: Vec<_> = directives.iter().map(|d| d.path.as_str()).collect();
+        assert_eq!(paths, ["./lib/AB.sol", "../D.sol"]);
+        assert_eq!(source[..directives[0].offset].matches('\n').count(), 2);
+        assert_eq!(resolve("a/A.sol", "../B.sol"), "B.sol");
+        assert_eq!(resolve("a/A.sol", "./lib/C.sol"), "a/lib/C.sol");
+        assert_eq!(resolve("a/A.sol", "b/C.sol"), "b/C.sol");
+    }
+
+    #[test]
+    fn test_import_graph() {
+        let files = [
+            file("A.sol", "import \"./B.sol\";\ncontract A {}"),
+            file("B.sol", "import {A} from \"./A.sol\";\ncontract B {}"),
+            file(
+                "C.sol",
+                "import \"@openzeppelin/contracts/token/ERC20/ERC20.sol\";\n\
+                 import \"./Missing.sol\";\ncontract C {}",
+            ),
+            file("D.sol", "contract D {}"),
+        ];
+        let graph = import_graph(&files);
+
+        assert_eq!(graph.cycles, [vec!["A.sol", "B.sol"]]);
+        let kinds: Vec<_> = graph
+            .edges
+            .iter()
+            .map(|edge| (edge.from.as_str(), edge.line, edge.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                ("A.sol", 1, ImportKind::Internal),
+                ("B.sol", 1, ImportKind::Internal),
+                ("C.sol", 1, ImportKind::External),
+                ("C.sol", 2, ImportKind::Unresolved),
+            ]
+        );
+        assert_eq!(graph.edges[0].to.as_deref(), Some("B.sol"));
+        assert_eq!(
+            graph.edges[2].package.as_deref(),
+            Some("@openzeppelin/contracts")
+        );
+        assert_eq!(graph.external_packages, ["@openzeppelin/contracts"]);
+        assert_eq!(graph.orphans, ["C.sol", "D.sol"]);
+    }
+}
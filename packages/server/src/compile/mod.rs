@@ -1 +1,8 @@
+pub mod bytecode_diff;
+pub mod cache;
+pub mod disassembly;
+pub mod hardhat;
+pub mod imports;
 pub mod solidity;
+pub mod sourcify;
+pub mod workdir;
@@ -1,13 +1,33 @@
 use foundry_compilers::{
-    artifacts::sourcemap::SourceElement, contracts::VersionedContracts, multi::MultiCompilerError,
+    artifacts::{
+        output_selection::ContractOutputSelection,
+        sourcemap::{Jump, SourceElement},
+        Contract, Settings,
+    },
+    contracts::VersionedContracts,
+    multi::{MultiCompilerError, MultiCompilerSettings},
     Artifact, Project, ProjectPathsConfig,
 };
 use serde::{Deserialize, Serialize};
 use serde_json;
-use std::{collections::BTreeMap, fs, path::Path};
-use tempfile::{self, TempDir};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    thread,
+};
+use tracing::{debug, warn};
+use utoipa::ToSchema;
+
+use super::hardhat::HardhatArtifact;
+use super::imports::{directives, resolve, ImportGraph};
+use super::workdir;
+use crate::config::Limits;
+use crate::gas::{CodeMap, FunctionJump, SourceLocation};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize, Clone, ToSchema)]
 pub struct SolidityFile {
     pub name: String,
     pub content: String,
@@ -24,11 +44,243 @@ pub struct SerializableSourceElement {
     pub modifier_depth: u32,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct CompileResult {
+    #[schema(value_type = Vec<CompilerError>)]
     pub errors: Vec<MultiCompilerError>,
+    #[schema(value_type = CompiledContracts)]
     pub contracts: VersionedContracts,
     pub source_maps: BTreeMap<String, String>,
+    /// Only with `artifactFormat: "hardhat"`: each contract as the artifact file Hardhat writes,
+    /// keyed `<file>:<contract>`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artifacts: Option<BTreeMap<String, HardhatArtifact>>,
+    /// Only from a partial compile: some groups of files didn't compile, and are in
+    /// `failedClusters` rather than here
+    #[serde(rename = "partialFailure", skip_serializing_if = "std::ops::Not::not")]
+    pub partial_failure: bool,
+    #[serde(rename = "failedClusters", skip_serializing_if = "Vec::is_empty")]
+    pub failed_clusters: Vec<FailedCluster>,
+    /// Only with `importGraph: true`
+    #[serde(rename = "importGraph", skip_serializing_if = "Option::is_none")]
+    pub import_graph: Option<ImportGraph>,
+    // Where the sources were compiled, to give file names back as they were sent. One
+    // directory a compiled group of files for a partial compile.
+    #[serde(skip)]
+    sources_dirs: Vec<PathBuf>,
+    // The sources by name, to tell what each contract was declared as
+    #[serde(skip)]
+    sources: BTreeMap<String, String>,
+    // The sources' names by the index solc gave them in source maps
+    #[serde(skip)]
+    source_ids: BTreeMap<u32, String>,
+}
+
+/// Files that import one another, directly or not, and didn't compile, in a partial compile.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FailedCluster {
+    /// As they were sent
+    pub files: Vec<String>,
+    /// solc's, all about these files
+    #[schema(value_type = Vec<CompilerError>)]
+    pub errors: Vec<MultiCompilerError>,
+}
+
+/// What a contract was declared as.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContractKind {
+    Contract,
+    Abstract,
+    Interface,
+    Library,
+}
+
+impl CompileResult {
+    /// Whether solc reported anything worse than a warning, in which case `contracts` is at best
+    /// partial.
+    pub fn has_errors(&self) -> bool {
+        self.errors.iter().any(|err| err.is_error())
+    }
+
+    /// A file in the output by the name it was sent under.
+    pub fn source_name(&self, path: &Path) -> String {
+        self.sources_dirs
+            .iter()
+            .find_map(|dir| path.strip_prefix(dir).ok())
+            .unwrap_or(path)
+            .display()
+            .to_string()
+    }
+
+    /// What `name` was declared as in `source`, going by the source's text. `Contract` when
+    /// the declaration can't be found.
+    pub fn kind(&self, source: &str, name: &str) -> ContractKind {
+        self.sources
+            .get(source)
+            .map_or(ContractKind::Contract, |text| declared_kind(text, name))
+    }
+
+    /// Where the opcode at `pc` in the deployed code of `contract`, as `File.sol:Name`, was
+    /// compiled from, going by its source map. None for code solc generated.
+    pub fn source_location(&self, contract: &str, pc: usize) -> Option<SourceLocation> {
+        let compiled = self.contract(contract)?;
+        let code = compiled.get_deployed_bytecode_bytes()?;
+        let source_map = compiled.get_source_map_deployed()?.ok()?;
+        self.locate(source_map.get(instruction_index(&code, pc)?)?)
+    }
+
+    /// `source_location` for every opcode of the deployed code of `contract` it has one for, by
+    /// pc.
+    pub fn source_locations(&self, contract: &str) -> Option<BTreeMap<usize, SourceLocation>> {
+        let compiled = self.contract(contract)?;
+        let code = compiled.get_deployed_bytecode_bytes()?;
+        let source_map = compiled.get_source_map_deployed()?.ok()?;
+        Some(
+            instruction_pcs(&code)
+                .zip(&source_map)
+                .filter_map(|(pc, element)| Some((pc, self.locate(element)?)))
+                .collect(),
+        )
+    }
+
+    // Where a source map element points, with the file named as it was sent
+    fn locate(&self, element: &SourceElement) -> Option<SourceLocation> {
+        let file = self
+            .source_ids
+            .get(&u32::try_from(element.index_i32()).ok()?)?;
+        let offset = element.offset() as usize;
+        let text = self.sources.get(file)?;
+        Some(SourceLocation {
+            file: file.clone(),
+            line: text.get(..offset)?.matches('\n').count() + 1,
+            offset,
+            length: element.length() as usize,
+        })
+    }
+
+    /// The deployed code of `contract`, as `File.sol:Name`, as far as telling its internal
+    /// function calls apart goes: the JUMPs its source map marks as into or out of a function,
+    /// and where `functionDebugData` says each function starts.
+    pub fn code_map(&self, contract: &str) -> Option<CodeMap> {
+        let compiled = self.contract(contract)?;
+        let code = compiled.get_deployed_bytecode_bytes()?;
+        let source_map = compiled.get_source_map_deployed()?.ok()?;
+        let jumps = instruction_pcs(&code)
+            .zip(&source_map)
+            .filter_map(|(pc, element)| match element.jump() {
+                Jump::In => Some((pc, FunctionJump::In)),
+                Jump::Out => Some((pc, FunctionJump::Out)),
+                Jump::Regular => None,
+            })
+            .collect();
+        let debug_data = compiled
+            .evm
+            .as_ref()
+            .and_then(|evm| evm.deployed_bytecode.as_ref())
+            .and_then(|deployed| deployed.bytecode.as_ref())
+            .map(|bytecode| &bytecode.function_debug_data);
+        let entry_points: Vec<_> = debug_data
+            .into_iter()
+            .flatten()
+            .filter_map(|(name, data)| Some((data.entry_point? as usize, function_name(name))))
+            .collect();
+        Some(CodeMap {
+            jumps,
+            sources: entry_points
+                .iter()
+                .filter_map(|(pc, _)| Some((*pc, self.source_location(contract, *pc)?)))
+                .collect(),
+            functions: entry_points.into_iter().collect(),
+        })
+    }
+
+    // `contract` as `File.sol:Name`
+    fn contract(&self, contract: &str) -> Option<&Contract> {
+        self.contracts
+            .contracts_with_files_and_version()
+            .find(|(file, name, _, _)| format!("{}:{}", self.source_name(file), name) == contract)
+            .map(|(_, _, compiled, _)| compiled)
+    }
+
+    /// Every contract as a Hardhat artifact, keyed like Hardhat's fully qualified names.
+    pub fn hardhat_artifacts(&self) -> BTreeMap<String, HardhatArtifact> {
+        let source_name = |path: &str| self.source_name(Path::new(path));
+        self.contracts
+            .contracts_with_files_and_version()
+            .map(|(file, name, contract, _)| {
+                let source = self.source_name(file);
+                let artifact = HardhatArtifact::new(&source, name, contract, source_name);
+                (format!("{}:{}", source, name), artifact)
+            })
+            .collect()
+    }
+
+    // `other`'s output along with this one's, the two compiled apart
+    fn merge(&mut self, other: CompileResult) {
+        self.errors.extend(other.errors);
+        self.contracts.0.extend(other.contracts.0);
+        self.source_maps.extend(other.source_maps);
+        self.sources_dirs.extend(other.sources_dirs);
+        self.sources.extend(other.sources);
+        // Each compile numbers its sources from 0, so an index no longer names one file
+        self.source_ids.clear();
+    }
+}
+
+// Which instruction of `code` starts at `pc`, counting push data as part of its push, as source
+// maps do
+fn instruction_index(code: &[u8], pc: usize) -> Option<usize> {
+    instruction_pcs(code).position(|at| at == pc)
+}
+
+// Where each instruction of `code` starts
+fn instruction_pcs(code: &[u8]) -> impl Iterator<Item = usize> + '_ {
+    let mut at = 0;
+    std::iter::from_fn(move || {
+        let op = *code.get(at)?;
+        let pc = at;
+        // PUSH1 to PUSH32
+        at += match op {
+            0x60..=0x7f => (op - 0x5f) as usize + 1,
+            _ => 1,
+        };
+        Some(pc)
+    })
+}
+
+// A function as `functionDebugData` keys it, `@name_<AST id>` for the ones declared in the
+// sources, as declared
+fn function_name(key: &str) -> String {
+    let Some(declared) = key.strip_prefix('@') else {
+        return key.to_string();
+    };
+    match declared.rsplit_once('_') {
+        Some((name, id)) if !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()) => {
+            name.to_string()
+        }
+        _ => declared.to_string(),
+    }
+}
+
+// The keywords before `name`'s declaration, e.g. `abstract contract Name`
+fn declared_kind(source: &str, name: &str) -> ContractKind {
+    let words: Vec<_> = source
+        .split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$'))
+        .filter(|word| !word.is_empty())
+        .collect();
+    for i in 1..words.len() {
+        if words[i] != name {
+            continue;
+        }
+        match words[i - 1] {
+            "interface" => return ContractKind::Interface,
+            "library" => return ContractKind::Library,
+            "contract" if i >= 2 && words[i - 2] == "abstract" => return ContractKind::Abstract,
+            "contract" => return ContractKind::Contract,
+            _ => {}
+        }
+    }
+    ContractKind::Contract
 }
 
 // Helper function to process source map data and convert to JSON string
@@ -69,9 +321,154 @@ fn process_source_map_data(
     (key, source_map_string)
 }
 
-pub fn compile(files: &[SolidityFile]) -> Result<CompileResult, eyre::Error> {
-    // Create a temporary directory
-    let temp_dir = TempDir::new()?;
+/// solc ran past the compile timeout and was killed.
+#[derive(Debug)]
+pub struct CompileTimeout(pub Duration);
+
+impl fmt::Display for CompileTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Compilation took longer than {:?}", self.0)
+    }
+}
+
+impl std::error::Error for CompileTimeout {}
+
+/// solc's processes held more memory between them than a compile may, and were killed, or ran out
+/// of memory against the cap on it.
+#[derive(Debug)]
+pub struct CompileMemoryLimit(pub u64);
+
+impl fmt::Display for CompileMemoryLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Compiler resource limit exceeded: solc used more than {} MiB of memory",
+            self.0 >> 20
+        )
+    }
+}
+
+impl std::error::Error for CompileMemoryLimit {}
+
+/// How often a running compile's solc is looked for, to cap its memory, and its memory checked
+const MEMORY_POLL: Duration = Duration::from_millis(50);
+
+// What one compile may take before its solc is killed
+#[derive(Clone, Copy)]
+struct Budget {
+    timeout: Duration,
+    max_memory: u64,
+}
+
+#[cfg(test)]
+thread_local! {
+    // Called with the compile directory once solc is done, so tests can fail a compile there
+    static AFTER_SOLC: std::cell::Cell<Option<fn(&Path)>> = const { std::cell::Cell::new(None) };
+}
+
+pub fn compile(files: &[SolidityFile], limits: &Limits) -> Result<CompileResult, eyre::Error> {
+    compile_with(files, limits, false)
+}
+
+/// Same as `compile`, with every path in the output the file's name as it was sent rather than
+/// where it was compiled, so compiling the same files again gives the same output.
+pub fn compile_relative(
+    files: &[SolidityFile],
+    limits: &Limits,
+) -> Result<CompileResult, eyre::Error> {
+    compile_with(files, limits, true)
+}
+
+/// `compile`, or `compile_relative` when `relative`, run apart on each group of `files` that
+/// import one another, so that a group that doesn't compile, e.g. for an import of a file that
+/// wasn't sent, doesn't take the other groups' contracts with it. Those that did compile are
+/// merged, and those that didn't are in `failed_clusters` with their errors. When none did, it's
+/// every group's output merged, errors and all, as a whole compile would have failed.
+///
+/// Source map indices name a file only within its group, so `source_location` and the like find
+/// nothing in a result merged from more than one.
+pub fn compile_partial(
+    files: &[SolidityFile],
+    limits: &Limits,
+    relative: bool,
+) -> Result<CompileResult, eyre::Error> {
+    let mut compiled = Vec::new();
+    let mut failed = Vec::new();
+    for cluster in import_clusters(files) {
+        let cluster: Vec<_> = cluster.into_iter().map(|i| files[i].clone()).collect();
+        let result = compile_with(&cluster, limits, relative)?;
+        match result.has_errors() {
+            false => compiled.push(result),
+            true => failed.push((cluster, result)),
+        }
+    }
+    if compiled.is_empty() {
+        let mut results = failed.into_iter().map(|(_, result)| result);
+        let mut merged = results
+            .next()
+            .ok_or_else(|| eyre::eyre!("No files to compile"))?;
+        results.for_each(|result| merged.merge(result));
+        return Ok(merged);
+    }
+
+    let mut results = compiled.into_iter();
+    let mut merged = results.next().expect("one compiled");
+    results.for_each(|result| merged.merge(result));
+    merged.partial_failure = !failed.is_empty();
+    merged.failed_clusters = failed
+        .into_iter()
+        .map(|(cluster, result)| FailedCluster {
+            files: cluster.into_iter().map(|file| file.name).collect(),
+            errors: result.errors,
+        })
+        .collect();
+    Ok(merged)
+}
+
+/// The indices of `files` in groups that import one another, going by their import directives,
+/// each group and the groups themselves in the order the files were sent. Imports of files that
+/// weren't sent join nothing.
+pub fn import_clusters(files: &[SolidityFile]) -> Vec<Vec<usize>> {
+    let index: BTreeMap<&str, usize> = files
+        .iter()
+        .enumerate()
+        .map(|(i, file)| (file.name.as_str(), i))
+        .collect();
+    // Each file's representative, files sharing one being in the same group
+    let mut parent: Vec<usize> = (0..files.len()).collect();
+    for (i, file) in files.iter().enumerate() {
+        for directive in directives(&file.content) {
+            let Some(&j) = index.get(resolve(&file.name, &directive.path).as_str()) else {
+                continue;
+            };
+            let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+            // The earlier file stands for the group, keeping groups in the order sent
+            parent[a.max(b)] = a.min(b);
+        }
+    }
+    let mut clusters: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for i in 0..files.len() {
+        clusters.entry(root(&mut parent, i)).or_default().push(i);
+    }
+    clusters.into_values().collect()
+}
+
+// The representative of `i`'s group
+fn root(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+fn compile_with(
+    files: &[SolidityFile],
+    limits: &Limits,
+    relative: bool,
+) -> Result<CompileResult, eyre::Error> {
+    // Removed on every way out, unwinding from a panic included
+    let temp_dir = workdir::create()?;
 
     // Create a subdirectory for sources
     let sources_dir = temp_dir.path().join("src");
@@ -85,18 +482,37 @@ pub fn compile(files: &[SolidityFile]) -> Result<CompileResult, eyre::Error> {
 
     let paths = ProjectPathsConfig::builder()
         .root(sources_dir.clone())
-        .sources(sources_dir)
+        .sources(sources_dir.clone())
         .build()?;
 
+    // Each contract's metadata too, which verifying it on Sourcify takes
+    let settings = MultiCompilerSettings {
+        solc: Settings::default().with_extra_output([ContractOutputSelection::Metadata]),
+        ..Default::default()
+    };
     let project = Project::builder()
         .paths(paths)
+        .settings(settings)
         .ephemeral()
         .no_artifacts()
         .build(Default::default())?;
 
-    let output = project.compile()?;
+    let budget = Budget {
+        timeout: limits.compile_timeout,
+        max_memory: limits.max_compile_memory_bytes,
+    };
+    let output = supervise(temp_dir.path(), budget, move || project.compile())?;
+    let output = if relative {
+        output.with_stripped_file_prefixes(&sources_dir)
+    } else {
+        output
+    };
+    #[cfg(test)]
+    if let Some(hook) = AFTER_SOLC.get() {
+        hook(temp_dir.path());
+    }
 
-    println!("Output: {:?}", output);
+    debug!("compile output: {:?}", output);
 
     let mut source_maps = BTreeMap::new();
     // let mut generated_sources = BTreeMap::new();
@@ -125,14 +541,109 @@ pub fn compile(files: &[SolidityFile]) -> Result<CompileResult, eyre::Error> {
         }
     }
 
+    let source_ids = output
+        .output()
+        .sources
+        .0
+        .iter()
+        .flat_map(|(path, files)| {
+            let name = path
+                .strip_prefix(&sources_dir)
+                .unwrap_or(path)
+                .display()
+                .to_string();
+            files
+                .iter()
+                .map(move |file| (file.source_file.id, name.clone()))
+        })
+        .collect();
+
     Ok(CompileResult {
         errors: output.output().errors.clone(),
         contracts: output.output().contracts.clone(),
         source_maps,
+        artifacts: None,
+        partial_failure: false,
+        failed_clusters: Vec::new(),
+        import_graph: None,
+        sources_dirs: vec![sources_dir],
+        sources: files
+            .iter()
+            .map(|file| (file.name.clone(), file.content.clone()))
+            .collect(),
+        source_ids,
         // generated_sources,
     })
 }
 
+// Runs `compile` on a thread of its own, so a wedged solc can be killed rather than holding the
+// request and its directory forever. A solc's memory is capped by rlimits as soon as it's seen,
+// and its resident set checked as well, for platforms without them and whatever it took first.
+// A capped solc that fails is only put down to the cap when it says it ran out of memory
+fn supervise<T: Send + 'static, E: Into<eyre::Error> + Send + 'static>(
+    dir: &Path,
+    budget: Budget,
+    compile: impl FnOnce() -> Result<T, E> + Send + 'static,
+) -> Result<T, eyre::Error> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        // Nobody's listening after a timeout
+        let _ = tx.send(compile());
+    });
+    let deadline = Instant::now() + budget.timeout;
+    let mut capped = false;
+    loop {
+        let wait = deadline.saturating_duration_since(Instant::now());
+        match rx.recv_timeout(wait.min(MEMORY_POLL)) {
+            Ok(Ok(output)) => return Ok(output),
+            Ok(Err(err)) => {
+                let err: eyre::Error = err.into();
+                if capped && out_of_memory(&err) {
+                    warn!(%err, "compile ran out of memory");
+                    return Err(CompileMemoryLimit(budget.max_memory).into());
+                }
+                return Err(err);
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(eyre::eyre!("The compiler panicked"))
+            }
+        }
+        capped |= workdir::limit_compilers(dir, budget.max_memory) > 0;
+        let memory = workdir::compiler_memory(dir);
+        if memory > budget.max_memory {
+            let killed = workdir::kill_compilers(dir);
+            warn!(killed, memory, "compile ran out of memory");
+            return Err(CompileMemoryLimit(budget.max_memory).into());
+        }
+        if Instant::now() >= deadline {
+            let killed = workdir::kill_compilers(dir);
+            warn!(
+                killed,
+                timeout_secs = budget.timeout.as_secs(),
+                "compile timed out"
+            );
+            return Err(CompileTimeout(budget.timeout).into());
+        }
+    }
+}
+
+// What solc, and the C and C++ runtimes under it, say when an allocation fails
+const OUT_OF_MEMORY: [&str; 4] = [
+    "bad_alloc",
+    "memory exhausted",
+    "out of memory",
+    "Cannot allocate memory",
+];
+
+// Whether a failed compile failed for want of memory
+fn out_of_memory(err: &eyre::Error) -> bool {
+    err.chain().any(|cause| {
+        let message = cause.to_string();
+        OUT_OF_MEMORY.iter().any(|marker| message.contains(marker))
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -178,7 +689,7 @@ mod tests {
             },
         ];
 
-        let result = compile(&files);
+        let result = compile(&files, &Limits::default());
 
         // assert!(result.is_ok(), "Compilation failed: {:?}", result.err());
 
@@ -200,6 +711,173 @@ mod tests {
         println!("Compilation successful: {:?}", compile_result);
     }
 
+    #[test]
+    fn test_import_clusters() {
+        let file = |name: &str, content: &str| SolidityFile {
+            name: name.to_string(),
+            content: content.to_string(),
+        };
+        let files = [
+            file("a/A.sol", "import {B} from \"../B.sol\";\ncontract A {}"),
+            file("B.sol", "// import \"./C.sol\";\ncontract B {}"),
+            file("C.sol", "contract C {} import * as D from './a/D.sol';"),
+            file(
+                "a/D.sol",
+                "/* import \"../B.sol\"; */ import \"./Missing.sol\";",
+            ),
+        ];
+        assert_eq!(import_clusters(&files), [vec![0, 1], vec![2, 3]]);
+    }
+
+    fn panic_with_dir(dir: &Path) {
+        panic!("{}", dir.display());
+    }
+
+    #[test]
+    fn test_temp_dir_removed_on_panic() {
+        let files = vec![SolidityFile {
+            name: "A.sol".to_string(),
+            content: "pragma solidity ^0.8.0;\ncontract A {}".to_string(),
+        }];
+        AFTER_SOLC.set(Some(panic_with_dir as fn(&Path)));
+        let panic = std::panic::catch_unwind(|| compile(&files, &Limits::default()));
+        AFTER_SOLC.set(None);
+
+        let dir = panic.unwrap_err().downcast::<String>().unwrap();
+        assert!(dir.contains(workdir::PREFIX), "{}", dir);
+        assert!(!Path::new(dir.as_str()).exists());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_timeout_kills_solc() {
+        let dir = workdir::create().unwrap();
+        let cwd = dir.path().to_path_buf();
+        let (done_tx, done_rx) = mpsc::channel();
+        let budget = Budget {
+            timeout: Duration::from_millis(200),
+            max_memory: u64::MAX,
+        };
+        let err = supervise(dir.path(), budget, move || {
+            let status = std::process::Command::new("sleep")
+                .arg("30")
+                .current_dir(cwd)
+                .status();
+            done_tx.send(status).unwrap();
+            Ok::<_, eyre::Error>(())
+        })
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<CompileTimeout>().unwrap().to_string(),
+            "Compilation took longer than 200ms"
+        );
+
+        // Killed rather than left to finish
+        let status = done_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(!status.unwrap().success());
+    }
+
+    #[test]
+    fn test_declared_kind() {
+        let source = "
+            interface IStore { function get() external view returns (uint256); }
+            library Math {}
+            abstract contract Base is IStore {}
+            contract Store is Base { function get() external pure returns (uint256) { return 1; } }
+        ";
+        assert_eq!(declared_kind(source, "IStore"), ContractKind::Interface);
+        assert_eq!(declared_kind(source, "Math"), ContractKind::Library);
+        assert_eq!(declared_kind(source, "Base"), ContractKind::Abstract);
+        assert_eq!(declared_kind(source, "Store"), ContractKind::Contract);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_memory_limit_kills_compiler() {
+        let dir = workdir::create().unwrap();
+        let cwd = dir.path().to_path_buf();
+        let (done_tx, done_rx) = mpsc::channel();
+        let budget = Budget {
+            timeout: Duration::from_secs(30),
+            max_memory: 64 << 20,
+        };
+        // /dev/zero has no line ends, so tail holds all of it, until it's killed or its
+        // allocations fail against the cap and it says its memory is exhausted
+        let err = supervise(dir.path(), budget, move || {
+            let output = std::process::Command::new("tail")
+                .arg("/dev/zero")
+                .current_dir(cwd)
+                .output()
+                .unwrap();
+            done_tx.send(output.status).unwrap();
+            match output.status.success() {
+                true => Ok(()),
+                false => Err(eyre::eyre!(
+                    "tail failed: {}",
+                    String::from_utf8_lossy(&output.stderr)
+                )),
+            }
+        })
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<CompileMemoryLimit>()
+                .unwrap()
+                .to_string(),
+            "Compiler resource limit exceeded: solc used more than 64 MiB of memory"
+        );
+        let status = done_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(!status.success());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_capped_failure_keeps_its_error() {
+        let dir = workdir::create().unwrap();
+        let cwd = dir.path().to_path_buf();
+        let budget = Budget {
+            timeout: Duration::from_secs(30),
+            max_memory: 64 << 20,
+        };
+        // Running long enough to be capped, then failing for a reason of its own
+        let err = supervise(dir.path(), budget, move || {
+            std::process::Command::new("sleep")
+                .arg("0.3")
+                .current_dir(cwd)
+                .status()
+                .unwrap();
+            Err::<(), _>(eyre::eyre!("ParserError: Expected ';'"))
+        })
+        .unwrap_err();
+        assert!(err.downcast_ref::<CompileMemoryLimit>().is_none());
+        assert_eq!(err.to_string(), "ParserError: Expected ';'");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_memory_limit_on_solc() {
+        // A constant array literal of thousands of elements, which solc takes a while over
+        let elements = vec!["uint256(1)"; 20_000].join(", ");
+        let files = vec![SolidityFile {
+            name: "Huge.sol".to_string(),
+            content: format!(
+                "pragma solidity ^0.8.0;\ncontract Huge {{\n    function f() public pure \
+                 returns (uint256) {{\n        uint256[20000] memory a = [{}];\n        \
+                 return a[0];\n    }}\n}}",
+                elements
+            ),
+        }];
+        let limits = Limits {
+            max_compile_memory_bytes: 1 << 20,
+            ..Limits::default()
+        };
+        let err = compile(&files, &limits).unwrap_err();
+        assert!(
+            err.downcast_ref::<CompileMemoryLimit>().is_some(),
+            "{}",
+            err
+        );
+    }
+
     // #[test]
     // fn test_compile_invalid_contract() {
     //     let invalid_solidity_code = r#"
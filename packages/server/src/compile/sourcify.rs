@@ -0,0 +1,170 @@
+//! Verifying deployed contracts on Sourcify from the metadata solc produced for them.
+
+use alloy_primitives::{hex, keccak256, Address};
+use foundry_compilers::contracts::VersionedContracts;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+const SUBMIT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The body of Sourcify's `POST /verify`: the metadata as `metadata.json` and every source it
+/// names, under the names it gives them.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct SourcifyPayload {
+    #[schema(value_type = String)]
+    pub address: Address,
+    /// The chain ID, in decimal
+    pub chain: String,
+    pub files: BTreeMap<String, String>,
+}
+
+/// How Sourcify matched the deployed code.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct SourcifyMatch {
+    /// `perfect` when the metadata hash matched too, `partial` when only the code did
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct VerifyResponse {
+    #[serde(default)]
+    result: Vec<SourcifyMatch>,
+    error: Option<String>,
+}
+
+/// The metadata solc wrote for `contract_name`, exactly as it wrote it, since Sourcify checks
+/// its hash against the one in the bytecode.
+pub fn find_metadata<'a>(
+    contracts: &'a VersionedContracts,
+    contract_name: &str,
+) -> Option<&'a str> {
+    contracts
+        .contracts_with_files_and_version()
+        .find(|(_, name, _, _)| name.as_str() == contract_name)
+        .and_then(|(_, _, contract, _)| contract.metadata.as_ref())
+        .map(|metadata| metadata.raw_metadata.as_str())
+}
+
+/// Puts `metadata` together with the sources it names. Each comes from the metadata itself when
+/// it was compiled with literal content, otherwise from `sources`, and has to hash to what the
+/// metadata says.
+pub fn payload(
+    metadata: &str,
+    sources: &BTreeMap<String, String>,
+    chain_id: u64,
+    address: Address,
+) -> Result<SourcifyPayload, eyre::Error> {
+    let parsed: Value = serde_json::from_str(metadata)?;
+    let named = parsed["sources"]
+        .as_object()
+        .ok_or_else(|| eyre::eyre!("The metadata lists no sources"))?;
+
+    let mut files = BTreeMap::from([("metadata.json".to_string(), metadata.to_string())]);
+    for (name, source) in named {
+        let content = match source["content"].as_str() {
+            Some(content) => content,
+            None => sources
+                .get(name)
+                .ok_or_else(|| eyre::eyre!("The metadata names {}, which wasn't compiled", name))?,
+        };
+        let expected = source["keccak256"].as_str().unwrap_or_default();
+        if hex::encode_prefixed(keccak256(content)) != expected {
+            return Err(eyre::eyre!(
+                "{} doesn't hash to the metadata's keccak256",
+                name
+            ));
+        }
+        files.insert(name.clone(), content.to_string());
+    }
+
+    Ok(SourcifyPayload {
+        address,
+        chain: chain_id.to_string(),
+        files,
+    })
+}
+
+/// Sends `payload` to the Sourcify server at `url`.
+pub async fn submit(url: &str, payload: &SourcifyPayload) -> Result<SourcifyMatch, eyre::Error> {
+    let response = reqwest::Client::builder()
+        .timeout(SUBMIT_TIMEOUT)
+        .build()?
+        .post(format!("{}/verify", url.trim_end_matches('/')))
+        .json(payload)
+        .send()
+        .await?;
+    let status = response.status();
+    let body: VerifyResponse = response.json().await?;
+    if let Some(error) = body.error {
+        return Err(eyre::eyre!("Sourcify refused it ({}): {}", status, error));
+    }
+    body.result
+        .into_iter()
+        .next()
+        .ok_or_else(|| eyre::eyre!("Sourcify answered {} without a result", status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::address;
+
+    const METADATA: &str = include_str!("fixtures/simple_storage.metadata.json");
+    const SOURCE: &str = include_str!("fixtures/SimpleStorage.sol");
+    const DEPLOYED: Address = address!("b2f9974c62815d3177079e150377915d9bc49c82");
+
+    fn sources(content: &str) -> BTreeMap<String, String> {
+        BTreeMap::from([("SimpleStorage.sol".to_string(), content.to_string())])
+    }
+
+    #[test]
+    fn test_payload() {
+        let payload = payload(METADATA, &sources(SOURCE), 8453, DEPLOYED).unwrap();
+        assert_eq!(payload.chain, "8453");
+        assert_eq!(
+            payload.files,
+            BTreeMap::from([
+                ("metadata.json".to_string(), METADATA.to_string()),
+                ("SimpleStorage.sol".to_string(), SOURCE.to_string()),
+            ])
+        );
+
+        let body = serde_json::to_value(&payload).unwrap();
+        assert_eq!(
+            body["address"],
+            "0xb2f9974c62815d3177079e150377915d9bc49c82"
+        );
+        assert_eq!(body["files"]["metadata.json"], METADATA);
+    }
+
+    #[test]
+    fn test_sources_must_match_metadata() {
+        let err = payload(METADATA, &sources("contract Changed {}"), 1, DEPLOYED).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "SimpleStorage.sol doesn't hash to the metadata's keccak256"
+        );
+
+        let err = payload(METADATA, &BTreeMap::new(), 1, DEPLOYED).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The metadata names SimpleStorage.sol, which wasn't compiled"
+        );
+    }
+
+    #[test]
+    fn test_literal_content() {
+        let mut metadata: Value = serde_json::from_str(METADATA).unwrap();
+        metadata["sources"]["SimpleStorage.sol"]["content"] = SOURCE.into();
+        let metadata = metadata.to_string();
+
+        let payload = payload(&metadata, &BTreeMap::new(), 1, DEPLOYED).unwrap();
+        assert_eq!(payload.files["SimpleStorage.sol"], SOURCE);
+        assert_eq!(payload.files["metadata.json"], metadata);
+    }
+}
@@ -0,0 +1,237 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use tempfile::TempDir;
+use tracing::{info, warn};
+
+/// Marks the temporary directories compiles write their sources to, so stale ones can be told
+/// apart from anything else in the temp dir.
+pub const PREFIX: &str = "evm-repl-compile-";
+
+/// A directory for one compile, removed when dropped.
+pub fn create() -> io::Result<TempDir> {
+    tempfile::Builder::new().prefix(PREFIX).tempdir()
+}
+
+/// Removes compile directories in `root` last touched more than `max_age` ago. Those are left by
+/// a process that died mid-compile, since every other way out removes them. Returns how many
+/// were removed.
+pub fn sweep(root: &Path, max_age: Duration) -> usize {
+    let Ok(entries) = fs::read_dir(root) else {
+        return 0;
+    };
+    let now = SystemTime::now();
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        if !entry.file_name().to_string_lossy().starts_with(PREFIX) {
+            continue;
+        }
+        let age = entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .map(|modified| now.duration_since(modified).unwrap_or_default());
+        if !matches!(age, Ok(age) if age >= max_age) {
+            continue;
+        }
+        match fs::remove_dir_all(entry.path()) {
+            Ok(()) => removed += 1,
+            Err(err) => {
+                warn!(path = %entry.path().display(), %err, "can't remove stale compile directory")
+            }
+        }
+    }
+    if removed > 0 {
+        info!(removed, "removed stale compile directories");
+    }
+    removed
+}
+
+/// Kills the processes this one started that work in `dir`, i.e. a solc still running for a
+/// compile that's been given up on. Returns how many were killed.
+#[cfg(target_os = "linux")]
+pub fn kill_compilers(dir: &Path) -> usize {
+    compilers(dir)
+        .into_iter()
+        // SAFETY: `kill` has no memory safety requirements
+        .filter(|pid| unsafe { libc::kill(*pid, libc::SIGKILL) } == 0)
+        .count()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn kill_compilers(_dir: &Path) -> usize {
+    0
+}
+
+/// Caps the data segment of the processes this one started that work in `dir` at `max_bytes`, so
+/// the kernel fails a solc's allocations past it rather than letting it take the machine's
+/// memory. The data segment counts the heap and private mappings, close to what the resident set
+/// budget is about; the address space would count code and reservations never touched too. solc is spawned inside `foundry_compilers`, out of reach of a
+/// `pre_exec`, so its limits are set from here once it's running. Returns how many were capped.
+#[cfg(target_os = "linux")]
+pub fn limit_compilers(dir: &Path, max_bytes: u64) -> usize {
+    let limit = libc::rlimit {
+        rlim_cur: max_bytes,
+        rlim_max: max_bytes,
+    };
+    compilers(dir)
+        .into_iter()
+        // SAFETY: `limit` outlives the call, and no old limit is asked for
+        .filter(|pid| unsafe {
+            libc::prlimit(*pid, libc::RLIMIT_DATA, &limit, std::ptr::null_mut()) == 0
+        })
+        .count()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn limit_compilers(_dir: &Path, _max_bytes: u64) -> usize {
+    0
+}
+
+/// Bytes of memory the processes this one started in `dir` hold between them, their resident
+/// set sizes added up.
+#[cfg(target_os = "linux")]
+pub fn compiler_memory(dir: &Path) -> u64 {
+    compilers(dir)
+        .into_iter()
+        .filter_map(|pid| {
+            let status = fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+            // e.g. `VmRSS:     5120 kB`
+            let kb = status
+                .lines()
+                .find_map(|line| line.strip_prefix("VmRSS:"))?
+                .split_whitespace()
+                .next()?
+                .parse::<u64>()
+                .ok()?;
+            Some(kb * 1024)
+        })
+        .sum()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn compiler_memory(_dir: &Path) -> u64 {
+    0
+}
+
+// The processes this one started that work in `dir`
+#[cfg(target_os = "linux")]
+fn compilers(dir: &Path) -> Vec<i32> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let parent = std::process::id();
+    let dir = dir.to_string_lossy();
+    let mut pids = Vec::new();
+    for entry in entries.flatten() {
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|pid| pid.parse::<i32>().ok())
+        else {
+            continue;
+        };
+        let proc = entry.path();
+        // The parent follows the command name, which may itself hold spaces and parentheses
+        let ppid = fs::read_to_string(proc.join("stat")).ok().and_then(|stat| {
+            let (_, fields) = stat.rsplit_once(')')?;
+            fields.split_whitespace().nth(1)?.parse::<u32>().ok()
+        });
+        if ppid != Some(parent) {
+            continue;
+        }
+        let in_dir = fs::read_link(proc.join("cwd"))
+            .is_ok_and(|cwd| cwd.to_string_lossy().starts_with(dir.as_ref()))
+            || fs::read(proc.join("cmdline"))
+                .is_ok_and(|cmdline| String::from_utf8_lossy(&cmdline).contains(dir.as_ref()));
+        if in_dir {
+            pids.push(pid);
+        }
+    }
+    pids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sweep() {
+        let root = TempDir::new().unwrap();
+        let stale = tempfile::Builder::new()
+            .prefix(PREFIX)
+            .tempdir_in(root.path())
+            .unwrap()
+            .into_path();
+        fs::write(stale.join("A.sol"), "contract A {}").unwrap();
+        let other = root.path().join("other");
+        fs::create_dir(&other).unwrap();
+
+        // Nothing is that old yet
+        assert_eq!(sweep(root.path(), Duration::from_secs(3600)), 0);
+        assert!(stale.exists());
+
+        assert_eq!(sweep(root.path(), Duration::ZERO), 1);
+        assert!(!stale.exists());
+        assert!(other.exists());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_kill_compilers() {
+        let dir = create().unwrap();
+        let mut elsewhere = std::process::Command::new("sleep")
+            .arg("30")
+            .spawn()
+            .unwrap();
+        let mut child = std::process::Command::new("sleep")
+            .arg("30")
+            .current_dir(dir.path())
+            .spawn()
+            .unwrap();
+
+        assert_eq!(kill_compilers(dir.path()), 1);
+        assert!(!child.wait().unwrap().success());
+        assert!(elsewhere.try_wait().unwrap().is_none());
+        elsewhere.kill().unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_compiler_memory() {
+        let dir = create().unwrap();
+        assert_eq!(compiler_memory(dir.path()), 0);
+        let mut child = std::process::Command::new("sleep")
+            .arg("30")
+            .current_dir(dir.path())
+            .spawn()
+            .unwrap();
+        assert!(compiler_memory(dir.path()) > 0);
+        child.kill().unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_limit_compilers() {
+        let dir = create().unwrap();
+        let mut child = std::process::Command::new("sleep")
+            .arg("30")
+            .current_dir(dir.path())
+            .spawn()
+            .unwrap();
+        assert_eq!(limit_compilers(dir.path(), 1 << 30), 1);
+        let limits = fs::read_to_string(format!("/proc/{}/limits", child.id())).unwrap();
+        let limit = |name: &str| {
+            let line = limits.lines().find(|line| line.starts_with(name)).unwrap();
+            line[name.len()..]
+                .split_whitespace()
+                .next()
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(limit("Max data size"), (1u64 << 30).to_string());
+        // Left alone, as code and untouched reservations count towards it
+        assert_ne!(limit("Max address space"), (1u64 << 30).to_string());
+        child.kill().unwrap();
+    }
+}
@@ -0,0 +1,290 @@
+use flate2::write::{DeflateEncoder, GzEncoder};
+use flate2::Compression as Level;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::futures::StreamExt;
+use rocket::http::ContentType;
+use rocket::response::stream::{stream, ReaderStream};
+use rocket::tokio::io::AsyncReadExt;
+use rocket::{Request, Response};
+use std::io::{self, Cursor, Write};
+use tracing::warn;
+
+// How much of a streamed body is read and compressed at a time
+const STREAM_CHUNK_BYTES: usize = 64 << 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    fn encoder(self) -> Encoder {
+        match self {
+            Encoding::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), Level::fast())),
+            Encoding::Deflate => Encoder::Deflate(DeflateEncoder::new(Vec::new(), Level::fast())),
+        }
+    }
+
+    fn encode(self, body: &[u8]) -> io::Result<Vec<u8>> {
+        let mut encoder = self.encoder();
+        encoder.write_all(body)?;
+        encoder.finish()
+    }
+}
+
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(DeflateEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.write_all(buf),
+            Encoder::Deflate(encoder) => encoder.write_all(buf),
+        }
+    }
+
+    // Takes what's been compressed so far, leaving the encoder to carry on
+    fn take(&mut self) -> Vec<u8> {
+        match self {
+            Encoder::Gzip(encoder) => std::mem::take(encoder.get_mut()),
+            Encoder::Deflate(encoder) => std::mem::take(encoder.get_mut()),
+        }
+    }
+
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Deflate(encoder) => encoder.finish(),
+        }
+    }
+}
+
+// Picks gzip over deflate when both are accepted. Anything else, br included, is ignored.
+fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let accepted: Vec<&str> = accept_encoding
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';').map(str::trim);
+            let name = parts.next()?;
+            let refused = parts.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .map_or(false, |q| q == 0.0)
+            });
+            (!refused).then_some(name)
+        })
+        .collect();
+    [Encoding::Gzip, Encoding::Deflate]
+        .into_iter()
+        .find(|encoding| accepted.contains(&encoding.name()))
+}
+
+/// Compresses JSON responses of at least `threshold` bytes when the client accepts gzip or
+/// deflate. JSON bodies streamed without a size, as big execution results are, are compressed
+/// chunk by chunk as they go out. Other streams, NDJSON and SSE, are left alone.
+pub struct Compression {
+    pub threshold: usize,
+}
+
+#[rocket::async_trait]
+impl Fairing for Compression {
+    fn info(&self) -> Info {
+        Info {
+            name: "JSON response compression",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let Some(encoding) = req.headers().get("Accept-Encoding").find_map(negotiate) else {
+            return;
+        };
+        if res.content_type() != Some(ContentType::JSON)
+            || res.headers().contains("Content-Encoding")
+        {
+            return;
+        }
+        // Unknown sizes are streams, and buffering them would defeat the point, so they're
+        // compressed as they're read
+        let Some(size) = res.body().preset_size() else {
+            let mut body = res.body_mut().take();
+            let compressed = stream! {
+                let mut encoder = encoding.encoder();
+                let mut chunk = vec![0; STREAM_CHUNK_BYTES];
+                loop {
+                    let read = match body.read(&mut chunk).await {
+                        Ok(0) => break,
+                        Ok(read) => read,
+                        Err(err) => {
+                            warn!(%err, "couldn't read streamed response body");
+                            return;
+                        }
+                    };
+                    if let Err(err) = encoder.write_all(&chunk[..read]) {
+                        warn!(%err, "couldn't compress streamed response body");
+                        return;
+                    }
+                    let compressed = encoder.take();
+                    if !compressed.is_empty() {
+                        yield compressed;
+                    }
+                }
+                match encoder.finish() {
+                    Ok(rest) => yield rest,
+                    Err(err) => warn!(%err, "couldn't compress streamed response body"),
+                }
+            };
+            res.set_raw_header("Content-Encoding", encoding.name());
+            res.adjoin_raw_header("Vary", "Accept-Encoding");
+            res.set_streamed_body(ReaderStream::from(compressed.map(Cursor::new)));
+            return;
+        };
+        if size < self.threshold {
+            return;
+        }
+
+        let body = match res.body_mut().to_bytes().await {
+            Ok(body) => body,
+            Err(err) => {
+                warn!(%err, "couldn't read response body for compression");
+                return;
+            }
+        };
+        match encoding.encode(&body) {
+            Ok(compressed) => {
+                res.set_raw_header("Content-Encoding", encoding.name());
+                res.adjoin_raw_header("Vary", "Accept-Encoding");
+                res.set_sized_body(compressed.len(), Cursor::new(compressed));
+            }
+            Err(err) => {
+                warn!(%err, "couldn't compress response body");
+                res.set_sized_body(body.len(), Cursor::new(body));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use rocket::http::Header;
+    use rocket::local::blocking::Client;
+    use rocket::response::stream::ByteStream;
+    use rocket::serde::json::{json, Json, Value};
+    use rocket::{get, routes};
+    use std::io::Read;
+
+    #[get("/big")]
+    fn big() -> Json<Value> {
+        Json(json!({ "steps": vec!["PUSH1 0x60"; 1000] }))
+    }
+
+    #[get("/streamed")]
+    fn streamed() -> (ContentType, ByteStream![Vec<u8>]) {
+        let steps = ByteStream! {
+            yield b"[".to_vec();
+            for i in 0..1000 {
+                let comma = if i == 0 { "" } else { "," };
+                yield format!("{}\"PUSH1 0x60\"", comma).into_bytes();
+            }
+            yield b"]".to_vec();
+        };
+        (ContentType::JSON, steps)
+    }
+
+    #[get("/small")]
+    fn small() -> Json<Value> {
+        Json(json!({ "ok": true }))
+    }
+
+    fn client() -> Client {
+        let rocket = rocket::build()
+            .attach(Compression { threshold: 1024 })
+            .mount("/", routes![big, streamed, small]);
+        Client::tracked(rocket).unwrap()
+    }
+
+    fn get(
+        client: &Client,
+        uri: &'static str,
+        accept: Option<&'static str>,
+    ) -> (Option<String>, Vec<u8>) {
+        let mut req = client.get(uri);
+        if let Some(accept) = accept {
+            req = req.header(Header::new("Accept-Encoding", accept));
+        }
+        let response = req.dispatch();
+        let encoding = response
+            .headers()
+            .get_one("Content-Encoding")
+            .map(String::from);
+        (encoding, response.into_bytes().unwrap())
+    }
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate("gzip, deflate, br"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("br;q=1.0, deflate"), Some(Encoding::Deflate));
+        assert_eq!(
+            negotiate("gzip;q=0, deflate;q=0.5"),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(negotiate("br"), None);
+    }
+
+    #[test]
+    fn test_large_json_is_gzipped() {
+        let client = client();
+        let (encoding, plain) = get(&client, "/big", None);
+        assert_eq!(encoding, None);
+
+        let (encoding, compressed) = get(&client, "/big", Some("gzip"));
+        assert_eq!(encoding.as_deref(), Some("gzip"));
+        assert!(compressed.len() < plain.len());
+
+        let mut decompressed = Vec::new();
+        GzDecoder::new(&compressed[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, plain);
+    }
+
+    #[test]
+    fn test_streamed_json_is_gzipped() {
+        let client = client();
+        let (encoding, plain) = get(&client, "/streamed", None);
+        assert_eq!(encoding, None);
+
+        let (encoding, compressed) = get(&client, "/streamed", Some("gzip"));
+        assert_eq!(encoding.as_deref(), Some("gzip"));
+        assert!(compressed.len() < plain.len());
+
+        let mut decompressed = Vec::new();
+        GzDecoder::new(&compressed[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, plain);
+        let steps: Value = serde_json::from_slice(&decompressed).unwrap();
+        assert_eq!(steps.as_array().map(Vec::len), Some(1000));
+    }
+
+    #[test]
+    fn test_small_json_is_not_compressed() {
+        let client = client();
+        let (encoding, body) = get(&client, "/small", Some("gzip"));
+        assert_eq!(encoding, None);
+        assert_eq!(body, br#"{"ok":true}"#);
+    }
+}
@@ -0,0 +1,1106 @@
+use dotenv::dotenv;
+use rocket::figment::providers::{Format, Toml};
+use rocket::figment::Figment;
+use serde::{Deserialize, Deserializer};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Read from the working directory when neither `--config` nor `EVM_REPL_CONFIG` names a file.
+pub const CONFIG_FILE: &str = "evm-repl.toml";
+
+// Chain IDs and the environment variables holding their RPC URLs. Each may list several, comma
+// separated, each optionally followed by a space and its weight: `https://a/KEY1 3, https://b/KEY2`
+const CHAIN_RPC_ENV_VARS: [(u64, &str); 7] = [
+    (8453, "BASE_RPC"),
+    (1, "ETH_RPC"),
+    (42161, "ARBITRUM_RPC"),
+    (10, "OPTIMISM_RPC"),
+    (137, "POLYGON_RPC"),
+    (56, "BNB_RPC"),
+    (43114, "AVALANCHE_RPC"),
+];
+
+const BASE_CHAIN_ID: u64 = 8453;
+
+// Blocks behind the head a fork takes when a request names no block, for chains that reorg
+// deeper than most; other chains get `DEFAULT_CONFIRMATION_LAG`
+const CONFIRMATION_LAGS: [(u64, u64); 1] = [(137, 5)];
+const DEFAULT_CONFIRMATION_LAG: u64 = 1;
+
+/// A token bucket: `burst` requests at once, refilled at `per_minute`.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+/// One of a chain's RPC URLs, usually one per API key.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RpcEndpoint {
+    pub url: String,
+    /// Share of the chain's forks sent to this key, relative to the others. 1 when the config
+    /// file leaves it out.
+    #[serde(default = "one")]
+    pub weight: u32,
+}
+
+/// How a chain's RPC keys take turns.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PoolStrategy {
+    /// In proportion to their weights
+    #[default]
+    RoundRobin,
+    /// The key limited longest ago (or never), by weight among equals
+    LeastRecentlyLimited,
+}
+
+#[derive(Clone, Debug)]
+pub struct RpcPoolConfig {
+    pub strategy: PoolStrategy,
+    /// How long a key sits out after a 429
+    pub cooldown: Duration,
+}
+
+impl Default for RpcPoolConfig {
+    fn default() -> Self {
+        RpcPoolConfig {
+            strategy: PoolStrategy::default(),
+            cooldown: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Bounds on request size and work done per request. In the config file's `[limits]`, durations
+/// are named with their unit, `job_ttl_secs` or `request_deadline_ms`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// Rocket's limit on a JSON or MessagePack body, checked before anything is parsed
+    pub max_request_bytes: usize,
+    pub max_bytecode_bytes: usize,
+    pub max_calls: usize,
+    /// Summed over every call in the request
+    pub max_calldata_bytes: usize,
+    /// Of each call's `meta`, as JSON
+    pub max_call_meta_bytes: usize,
+    pub max_files: usize,
+    /// Summed over every file in the request
+    pub max_source_bytes: usize,
+    pub max_batch_scenarios: usize,
+    pub max_batch_concurrency: usize,
+    /// Background jobs executing at once; the rest wait in the queue
+    pub max_job_concurrency: usize,
+    pub max_queued_jobs: usize,
+    /// How long a finished job's result is kept
+    #[serde(rename = "job_ttl_secs", deserialize_with = "secs")]
+    pub job_ttl: Duration,
+    /// In-memory states kept for `/transact` to continue from; the least recently used go first
+    pub max_snapshots: usize,
+    #[serde(rename = "snapshot_ttl_secs", deserialize_with = "secs")]
+    pub snapshot_ttl: Duration,
+    /// `/ws` sessions open at once, counting those in their grace period
+    pub max_sessions: usize,
+    /// How long a session survives its connection dropping
+    #[serde(rename = "session_grace_secs", deserialize_with = "secs")]
+    pub session_grace: Duration,
+    /// Snapshots one `/ws` session can take, each a copy of its chain
+    pub max_session_snapshots: usize,
+    /// Largest execution result that can be persisted, in bytes
+    pub max_result_bytes: usize,
+    /// How long persisted results are kept
+    #[serde(rename = "result_ttl_secs", deserialize_with = "secs")]
+    pub result_ttl: Duration,
+    /// Fork executions running at once across the server
+    pub max_concurrent_forks: usize,
+    pub max_concurrent_compiles: usize,
+    /// The most gas a call on a fork is given, however large the block's gas limit
+    pub max_call_gas: u64,
+    /// Independent calls run at once within a request
+    pub max_parallel_calls: usize,
+    /// The most times a request can have a call run again after a transient RPC failure
+    pub max_call_retries: u32,
+    /// How long an execute request gets before the calls it hasn't started are skipped, so it
+    /// answers with what finished before a proxy gives up on it. Requests can ask for less.
+    #[serde(rename = "request_deadline_ms", deserialize_with = "millis")]
+    pub request_deadline: Duration,
+    /// Requests waiting for a fork or compile slot, per kind; past this they're turned away
+    pub max_queued_requests: usize,
+    /// How long a request waits for a slot before it's turned away
+    #[serde(rename = "max_queue_wait_secs", deserialize_with = "secs")]
+    pub max_queue_wait: Duration,
+    /// Larger job callbacks send a summary and a link to the full result instead
+    pub max_callback_bytes: usize,
+    /// Compile responses kept for identical requests; the least recently used go first
+    pub max_compile_cache_entries: usize,
+    #[serde(rename = "compile_cache_ttl_secs", deserialize_with = "secs")]
+    pub compile_cache_ttl: Duration,
+    /// How long solc gets before it's killed
+    #[serde(rename = "compile_timeout_secs", deserialize_with = "secs")]
+    pub compile_timeout: Duration,
+    /// Memory one compile's solc processes may hold between them before they're killed
+    pub max_compile_memory_bytes: u64,
+    /// Compile directories older than this are removed at startup, left behind by a crash
+    #[serde(rename = "stale_compile_dir_age_secs", deserialize_with = "secs")]
+    pub stale_compile_dir_age: Duration,
+    /// Per API key, and only enforced when keys are configured
+    pub compile_rate: RateLimit,
+    pub execute_rate: RateLimit,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_request_bytes: 8 << 20,
+            max_bytecode_bytes: 128 << 10,
+            max_calls: 256,
+            max_calldata_bytes: 1 << 20,
+            max_call_meta_bytes: 4 << 10,
+            max_files: 64,
+            max_source_bytes: 2 << 20,
+            max_batch_scenarios: 32,
+            max_batch_concurrency: 4,
+            max_job_concurrency: 2,
+            max_queued_jobs: 64,
+            job_ttl: Duration::from_secs(600),
+            max_snapshots: 256,
+            snapshot_ttl: Duration::from_secs(1800),
+            max_sessions: 64,
+            session_grace: Duration::from_secs(60),
+            max_session_snapshots: 64,
+            max_result_bytes: 4 << 20,
+            result_ttl: Duration::from_secs(7 * 24 * 3600),
+            max_concurrent_forks: 8,
+            max_concurrent_compiles: 32,
+            max_call_gas: 50_000_000,
+            max_parallel_calls: 16,
+            max_call_retries: 3,
+            request_deadline: Duration::from_secs(60),
+            max_queued_requests: 32,
+            max_queue_wait: Duration::from_secs(10),
+            max_callback_bytes: 256 << 10,
+            max_compile_cache_entries: 256,
+            compile_cache_ttl: Duration::from_secs(3600),
+            compile_timeout: Duration::from_secs(60),
+            max_compile_memory_bytes: 2 << 30,
+            stale_compile_dir_age: Duration::from_secs(6 * 3600),
+            compile_rate: RateLimit {
+                burst: 30,
+                per_minute: 60,
+            },
+            execute_rate: RateLimit {
+                burst: 10,
+                per_minute: 20,
+            },
+        }
+    }
+}
+
+/// A public instance: requests without an API key are let in, rate limited by client IP instead of
+/// by key, and turned away from what costs the most to run. Requests with a key are unaffected.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DemoConfig {
+    /// Header the proxy in front of the server puts the client's IP in, e.g. `X-Forwarded-For`.
+    /// Its last address is used, the one the proxy added. Without one, the connection's address.
+    pub ip_header: Option<String>,
+    /// Per client IP
+    pub compile_rate: RateLimit,
+    pub execute_rate: RateLimit,
+    /// Calls one request can make, in place of `Limits::max_calls`
+    pub max_calls: usize,
+}
+
+impl Default for DemoConfig {
+    fn default() -> Self {
+        DemoConfig {
+            ip_header: None,
+            compile_rate: RateLimit {
+                burst: 5,
+                per_minute: 10,
+            },
+            execute_rate: RateLimit {
+                burst: 3,
+                per_minute: 6,
+            },
+            max_calls: 16,
+        }
+    }
+}
+
+/// Origins allowed to call the API from a browser. With none configured any origin is allowed,
+/// but without credentials.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    pub origins: Vec<String>,
+    pub origin_regexes: Vec<String>,
+    /// Seconds browsers may cache a preflight response
+    pub max_age: Option<usize>,
+}
+
+/// Server configuration. Read once at startup and shared with handlers through Rocket managed
+/// state, so request handling never touches the process environment.
+#[derive(Clone, Debug)]
+pub struct AppConfig {
+    /// Never empty for a chain that's present. A request's own `rpcUrl` bypasses these.
+    pub chain_rpc_urls: HashMap<u64, Vec<RpcEndpoint>>,
+    pub rpc_pool: RpcPoolConfig,
+    /// Chain forked when a request doesn't name one
+    pub default_chain_id: u64,
+    /// Blocks behind the head a fork takes when the request names no block, by chain ID. The
+    /// head itself can shift under a fork on chains that reorg, so it isn't forked by default.
+    pub confirmation_lags: HashMap<u64, u64>,
+    /// The lag for chains missing from `confirmation_lags`
+    pub default_confirmation_lag: u64,
+    /// anvil binary used when no RPC is available
+    pub anvil_bin: Option<PathBuf>,
+    pub limits: Limits,
+    /// Keys accepted in `X-API-Key`. Empty disables authentication.
+    pub api_keys: HashSet<String>,
+    /// Key for the admin routes, sent in `X-Admin-Key`
+    pub admin_key: Option<String>,
+    /// Public demo mode. Off without one.
+    pub demo: Option<DemoConfig>,
+    /// Log as JSON lines instead of human readable text
+    pub log_json: bool,
+    pub cors: CorsConfig,
+    /// How long shutdown waits for in-flight work before aborting it
+    pub drain_timeout: Duration,
+    /// JSON responses smaller than this many bytes are sent uncompressed
+    pub compression_threshold: usize,
+    /// Where persisted execution results are written
+    pub results_dir: PathBuf,
+    /// Signs job callbacks. Jobs can't ask for a callback without one.
+    pub webhook_secret: Option<String>,
+    /// Callback hosts let through even though they resolve to private or loopback addresses
+    pub webhook_allowed_hosts: Vec<String>,
+    /// Sourcify server contracts are submitted to for verification, e.g.
+    /// `https://sourcify.dev/server`. Only dry runs are allowed without one.
+    pub sourcify_url: Option<String>,
+    /// Percent a scenario's gas can go up by between gas snapshots before a diff calls it a
+    /// regression, when the diff doesn't give its own threshold
+    pub gas_regression_threshold: f64,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        AppConfig {
+            chain_rpc_urls: HashMap::new(),
+            rpc_pool: RpcPoolConfig::default(),
+            default_chain_id: BASE_CHAIN_ID,
+            confirmation_lags: HashMap::from(CONFIRMATION_LAGS),
+            default_confirmation_lag: DEFAULT_CONFIRMATION_LAG,
+            anvil_bin: None,
+            limits: Limits::default(),
+            api_keys: HashSet::new(),
+            admin_key: None,
+            demo: None,
+            log_json: false,
+            cors: CorsConfig::default(),
+            drain_timeout: Duration::from_secs(30),
+            compression_threshold: 1024,
+            results_dir: env::temp_dir().join("evm-repl-results"),
+            webhook_secret: None,
+            webhook_allowed_hosts: Vec::new(),
+            sourcify_url: None,
+            gas_regression_threshold: 1.0,
+        }
+    }
+}
+
+impl AppConfig {
+    /// The config file at `path`, with the environment over it.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, eyre::Error> {
+        dotenv().ok();
+        Self::layered(
+            Some(path.as_ref()),
+            &|name| env::var(name).ok(),
+            &ConfigArgs::default(),
+        )
+    }
+
+    /// The defaults with the environment over them, for when there's no config file.
+    pub fn from_env() -> Result<Self, eyre::Error> {
+        dotenv().ok();
+        Self::layered(None, &|name| env::var(name).ok(), &ConfigArgs::default())
+    }
+
+    /// Every layer, each over the one before: the defaults, the config file, the environment and
+    /// the command line. The file is `args.config`, else `EVM_REPL_CONFIG`, else `evm-repl.toml`
+    /// if the working directory has one.
+    pub fn from_args(args: &ConfigArgs) -> Result<Self, eyre::Error> {
+        dotenv().ok();
+        let path = args
+            .config
+            .clone()
+            .or_else(|| env::var_os("EVM_REPL_CONFIG").map(PathBuf::from))
+            .or_else(|| Some(PathBuf::from(CONFIG_FILE)).filter(|path| path.is_file()));
+        Self::layered(path.as_deref(), &|name| env::var(name).ok(), args)
+    }
+
+    fn layered(path: Option<&Path>, env: Env, args: &ConfigArgs) -> Result<Self, eyre::Error> {
+        let config = match path {
+            Some(path) => ConfigFile::read(path)?.into_config()?,
+            None => AppConfig::default(),
+        };
+        let config = config.with_env(env)?.with_args(args)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // What the environment sets, over `self`
+    fn with_env(self, env: Env) -> Result<Self, eyre::Error> {
+        let mut chain_rpc_urls = self.chain_rpc_urls;
+        for (chain_id, var) in CHAIN_RPC_ENV_VARS {
+            if let Some(value) = env(var) {
+                let endpoints = parse_rpc_endpoints(var, &value)?;
+                if !endpoints.is_empty() {
+                    chain_rpc_urls.insert(chain_id, endpoints);
+                }
+            }
+        }
+        let rpc_pool = RpcPoolConfig {
+            strategy: match env("RPC_POOL_STRATEGY") {
+                Some(value) => pool_strategy("RPC_POOL_STRATEGY", &value)?,
+                None => self.rpc_pool.strategy,
+            },
+            cooldown: env_number(env, "RPC_COOLDOWN_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(self.rpc_pool.cooldown),
+        };
+
+        let base = self.limits;
+        let limits = Limits {
+            max_job_concurrency: env_number(env, "JOB_CONCURRENCY")?
+                .unwrap_or(base.max_job_concurrency),
+            max_queued_jobs: env_number(env, "MAX_QUEUED_JOBS")?.unwrap_or(base.max_queued_jobs),
+            job_ttl: env_number(env, "JOB_TTL_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(base.job_ttl),
+            max_snapshots: env_number(env, "MAX_SNAPSHOTS")?.unwrap_or(base.max_snapshots),
+            snapshot_ttl: env_number(env, "SNAPSHOT_TTL_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(base.snapshot_ttl),
+            max_sessions: env_number(env, "MAX_SESSIONS")?.unwrap_or(base.max_sessions),
+            session_grace: env_number(env, "SESSION_GRACE_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(base.session_grace),
+            max_session_snapshots: env_number(env, "MAX_SESSION_SNAPSHOTS")?
+                .unwrap_or(base.max_session_snapshots),
+            max_result_bytes: env_number(env, "MAX_RESULT_BYTES")?.unwrap_or(base.max_result_bytes),
+            result_ttl: env_number(env, "RESULT_TTL_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(base.result_ttl),
+            max_concurrent_forks: env_number(env, "MAX_CONCURRENT_FORKS")?
+                .unwrap_or(base.max_concurrent_forks),
+            max_concurrent_compiles: env_number(env, "MAX_CONCURRENT_COMPILES")?
+                .unwrap_or(base.max_concurrent_compiles),
+            max_call_gas: env_number(env, "MAX_CALL_GAS")?.unwrap_or(base.max_call_gas),
+            max_parallel_calls: env_number(env, "MAX_PARALLEL_CALLS")?
+                .unwrap_or(base.max_parallel_calls),
+            max_call_retries: env_number(env, "MAX_CALL_RETRIES")?.unwrap_or(base.max_call_retries),
+            request_deadline: env_number(env, "REQUEST_DEADLINE_MS")?
+                .map(Duration::from_millis)
+                .unwrap_or(base.request_deadline),
+            max_queued_requests: env_number(env, "MAX_QUEUED_REQUESTS")?
+                .unwrap_or(base.max_queued_requests),
+            max_queue_wait: env_number(env, "QUEUE_WAIT_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(base.max_queue_wait),
+            max_callback_bytes: env_number(env, "MAX_CALLBACK_BYTES")?
+                .unwrap_or(base.max_callback_bytes),
+            max_compile_cache_entries: env_number(env, "MAX_COMPILE_CACHE_ENTRIES")?
+                .unwrap_or(base.max_compile_cache_entries),
+            compile_cache_ttl: env_number(env, "COMPILE_CACHE_TTL_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(base.compile_cache_ttl),
+            compile_timeout: env_number(env, "COMPILE_TIMEOUT_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(base.compile_timeout),
+            max_compile_memory_bytes: env_number(env, "MAX_COMPILE_MEMORY_BYTES")?
+                .unwrap_or(base.max_compile_memory_bytes),
+            stale_compile_dir_age: env_number(env, "STALE_COMPILE_DIR_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(base.stale_compile_dir_age),
+            compile_rate: RateLimit {
+                per_minute: env_number(env, "COMPILE_RATE_PER_MINUTE")?
+                    .unwrap_or(base.compile_rate.per_minute),
+                ..base.compile_rate
+            },
+            execute_rate: RateLimit {
+                per_minute: env_number(env, "EXECUTE_RATE_PER_MINUTE")?
+                    .unwrap_or(base.execute_rate.per_minute),
+                ..base.execute_rate
+            },
+            ..base
+        };
+
+        let api_keys = read_api_keys(env)?;
+        Ok(AppConfig {
+            chain_rpc_urls,
+            rpc_pool,
+            default_chain_id: env_number(env, "DEFAULT_CHAIN_ID")?.unwrap_or(self.default_chain_id),
+            confirmation_lags: match env("CONFIRMATION_LAGS") {
+                Some(value) => parse_confirmation_lags(&value)?,
+                None => self.confirmation_lags,
+            },
+            default_confirmation_lag: env_number(env, "DEFAULT_CONFIRMATION_LAG")?
+                .unwrap_or(self.default_confirmation_lag),
+            anvil_bin: find_anvil(env, self.anvil_bin),
+            limits,
+            api_keys: match api_keys.is_empty() {
+                true => self.api_keys,
+                false => api_keys,
+            },
+            admin_key: env("ADMIN_API_KEY").or(self.admin_key),
+            demo: read_demo_config(env, self.demo)?,
+            log_json: match env("LOG_FORMAT") {
+                Some(value) => log_json("LOG_FORMAT", &value)?,
+                None => self.log_json,
+            },
+            cors: CorsConfig {
+                origins: env_list(env, "CORS_ORIGINS").unwrap_or(self.cors.origins),
+                origin_regexes: env_list(env, "CORS_ORIGIN_REGEXES")
+                    .unwrap_or(self.cors.origin_regexes),
+                max_age: env_number(env, "CORS_MAX_AGE")?.or(self.cors.max_age),
+            },
+            drain_timeout: env_number(env, "DRAIN_TIMEOUT_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(self.drain_timeout),
+            compression_threshold: env_number(env, "COMPRESSION_THRESHOLD")?
+                .unwrap_or(self.compression_threshold),
+            results_dir: env("RESULTS_DIR")
+                .map(PathBuf::from)
+                .unwrap_or(self.results_dir),
+            webhook_secret: env("WEBHOOK_SECRET").or(self.webhook_secret),
+            webhook_allowed_hosts: env_list(env, "WEBHOOK_ALLOWED_HOSTS")
+                .unwrap_or(self.webhook_allowed_hosts),
+            sourcify_url: env("SOURCIFY_URL").or(self.sourcify_url),
+            gas_regression_threshold: env_number(env, "GAS_REGRESSION_THRESHOLD")?
+                .unwrap_or(self.gas_regression_threshold),
+        })
+    }
+
+    // What the command line sets, over `self`
+    fn with_args(mut self, args: &ConfigArgs) -> Result<Self, eyre::Error> {
+        if let Some(chain_id) = args.default_chain_id {
+            self.default_chain_id = chain_id;
+        }
+        if let Some(format) = &args.log_format {
+            self.log_json = log_json("--log-format", format)?;
+        }
+        if let Some(dir) = &args.results_dir {
+            self.results_dir = dir.clone();
+        }
+        if let Some(bin) = &args.anvil_bin {
+            self.anvil_bin = Some(check_file("--anvil-bin", bin)?);
+        }
+        if args.demo {
+            self.demo.get_or_insert_with(DemoConfig::default);
+        }
+        Ok(self)
+    }
+
+    // What no one layer can check alone, named as the config file names it
+    fn validate(&self) -> Result<(), eyre::Error> {
+        let limits = &self.limits;
+        for (field, value) in [
+            ("limits.max_batch_concurrency", limits.max_batch_concurrency),
+            ("limits.max_job_concurrency", limits.max_job_concurrency),
+            ("limits.max_concurrent_forks", limits.max_concurrent_forks),
+            (
+                "limits.max_concurrent_compiles",
+                limits.max_concurrent_compiles,
+            ),
+            ("limits.max_parallel_calls", limits.max_parallel_calls),
+        ] {
+            if value == 0 {
+                return Err(eyre::eyre!(
+                    "{} is 0, so nothing it limits would ever run",
+                    field
+                ));
+            }
+        }
+        if let Some(url) = &self.sourcify_url {
+            check_url("sourcify_url", url)?;
+        }
+        let threshold = self.gas_regression_threshold;
+        if threshold.is_nan() || threshold < 0.0 {
+            return Err(eyre::eyre!(
+                "gas_regression_threshold is a percent, 0 or more, got {}",
+                threshold
+            ));
+        }
+        if let Some(demo) = self
+            .demo
+            .as_ref()
+            .filter(|demo| demo.max_calls > limits.max_calls)
+        {
+            return Err(eyre::eyre!(
+                "demo.max_calls is {}, over limits.max_calls of {}",
+                demo.max_calls,
+                limits.max_calls
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn rpc_endpoints(&self, chain_id: u64) -> Option<&[RpcEndpoint]> {
+        self.chain_rpc_urls
+            .get(&chain_id)
+            .map(Vec::as_slice)
+            .filter(|endpoints| !endpoints.is_empty())
+    }
+
+    /// Blocks behind the head `chain_id` is forked at when a request names no block.
+    pub fn confirmation_lag(&self, chain_id: u64) -> u64 {
+        self.confirmation_lags
+            .get(&chain_id)
+            .copied()
+            .unwrap_or(self.default_confirmation_lag)
+    }
+}
+
+// `137:5, 56:2`: chain IDs and their lags, comma separated
+fn parse_confirmation_lags(value: &str) -> Result<HashMap<u64, u64>, eyre::Error> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            entry
+                .split_once(':')
+                .and_then(|(chain_id, lag)| {
+                    Some((chain_id.trim().parse().ok()?, lag.trim().parse().ok()?))
+                })
+                .ok_or_else(|| {
+                    eyre::eyre!(
+                        "CONFIRMATION_LAGS entries are a chain ID and a lag, like 137:5, got {}",
+                        entry
+                    )
+                })
+        })
+        .collect()
+}
+
+fn parse_rpc_endpoints(var: &str, value: &str) -> Result<Vec<RpcEndpoint>, eyre::Error> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let mut parts = entry.split_whitespace();
+            let url = parts.next().unwrap_or_default().to_string();
+            let weight = match (parts.next(), parts.next()) {
+                (None, _) => 1,
+                (Some(weight), None) => weight
+                    .parse()
+                    .ok()
+                    .filter(|weight| *weight > 0)
+                    .ok_or_else(|| {
+                        eyre::eyre!("{} weights must be positive numbers, got {}", var, weight)
+                    })?,
+                (Some(_), Some(_)) => {
+                    return Err(eyre::eyre!(
+                        "{} entries are a URL and an optional weight, got {}",
+                        var,
+                        entry
+                    ))
+                }
+            };
+            check_url(var, &url)?;
+            Ok(RpcEndpoint { url, weight })
+        })
+        .collect()
+}
+
+// Reads an environment variable: the process's, or a map's in tests
+type Env<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+fn env_number<T: FromStr>(env: Env, var: &str) -> Result<Option<T>, eyre::Error> {
+    match env(var) {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| eyre::eyre!("{} must be a number, got {}", var, value)),
+        None => Ok(None),
+    }
+}
+
+// None when the variable isn't set, so an empty list can still override
+fn env_list(env: Env, var: &str) -> Option<Vec<String>> {
+    let value = env(var)?;
+    Some(
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(String::from)
+            .collect(),
+    )
+}
+
+fn log_json(name: &str, value: &str) -> Result<bool, eyre::Error> {
+    match value {
+        "json" => Ok(true),
+        "text" => Ok(false),
+        other => Err(eyre::eyre!(
+            "{} must be \"json\" or \"text\", got {}",
+            name,
+            other
+        )),
+    }
+}
+
+fn pool_strategy(name: &str, value: &str) -> Result<PoolStrategy, eyre::Error> {
+    match value {
+        "round-robin" => Ok(PoolStrategy::RoundRobin),
+        "least-recently-limited" => Ok(PoolStrategy::LeastRecentlyLimited),
+        other => Err(eyre::eyre!(
+            "{} must be \"round-robin\" or \"least-recently-limited\", got {}",
+            name,
+            other
+        )),
+    }
+}
+
+// An RPC or service URL. The error names the setting but not the URL, which may hold a key.
+fn check_url(name: &str, url: &str) -> Result<(), eyre::Error> {
+    let parsed =
+        url::Url::parse(url).map_err(|err| eyre::eyre!("{} is not a valid URL: {}", name, err))?;
+    match parsed.scheme() {
+        "http" | "https" | "ws" | "wss" => Ok(()),
+        scheme => Err(eyre::eyre!(
+            "{} must be an http(s) or ws(s) URL, not {}",
+            name,
+            scheme
+        )),
+    }
+}
+
+fn check_file(name: &str, path: &Path) -> Result<PathBuf, eyre::Error> {
+    match path.is_file() {
+        true => Ok(path.to_path_buf()),
+        false => Err(eyre::eyre!(
+            "{} is {}, which isn't a file",
+            name,
+            path.display()
+        )),
+    }
+}
+
+// Demo mode is on with DEMO_MODE=true, or a `[demo]` in the config file that DEMO_MODE=false
+// doesn't turn off; the rest only tune it
+fn read_demo_config(env: Env, base: Option<DemoConfig>) -> Result<Option<DemoConfig>, eyre::Error> {
+    let base = match (env("DEMO_MODE").as_deref(), base) {
+        (Some("true"), base) => base.unwrap_or_default(),
+        (None, Some(base)) => base,
+        (Some("false") | None, _) => return Ok(None),
+        (Some(other), _) => {
+            return Err(eyre::eyre!(
+                "DEMO_MODE must be \"true\" or \"false\", got {}",
+                other
+            ))
+        }
+    };
+    Ok(Some(DemoConfig {
+        ip_header: env("DEMO_IP_HEADER").or(base.ip_header),
+        compile_rate: RateLimit {
+            per_minute: env_number(env, "DEMO_COMPILE_RATE_PER_MINUTE")?
+                .unwrap_or(base.compile_rate.per_minute),
+            ..base.compile_rate
+        },
+        execute_rate: RateLimit {
+            per_minute: env_number(env, "DEMO_EXECUTE_RATE_PER_MINUTE")?
+                .unwrap_or(base.execute_rate.per_minute),
+            ..base.execute_rate
+        },
+        max_calls: env_number(env, "DEMO_MAX_CALLS")?.unwrap_or(base.max_calls),
+    }))
+}
+
+// Keys come from API_KEYS (comma separated) and API_KEYS_FILE (one per line), combined
+fn read_api_keys(env: Env) -> Result<HashSet<String>, eyre::Error> {
+    let mut keys = env("API_KEYS").unwrap_or_default();
+    if let Some(path) = env("API_KEYS_FILE") {
+        let file = std::fs::read_to_string(&path)
+            .map_err(|err| eyre::eyre!("can't read API_KEYS_FILE {}: {}", path, err))?;
+        keys.push('\n');
+        keys.push_str(&file);
+    }
+    Ok(keys
+        .split(|c| c == ',' || c == '\n')
+        .map(str::trim)
+        .filter(|key| !key.is_empty() && !key.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+// Look for anvil in ANVIL_BIN, then where the config file says, then PATH, then foundryup's
+// install directory
+fn find_anvil(env: Env, configured: Option<PathBuf>) -> Option<PathBuf> {
+    if let Some(bin) = env("ANVIL_BIN") {
+        let bin = PathBuf::from(bin);
+        return bin.is_file().then_some(bin);
+    }
+    if configured.is_some() {
+        return configured;
+    }
+
+    let mut candidates: Vec<PathBuf> = env("PATH")
+        .map(|paths| {
+            std::env::split_paths(&paths)
+                .map(|dir| dir.join("anvil"))
+                .collect()
+        })
+        .unwrap_or_default();
+    if let Some(home) = env("HOME") {
+        candidates.push(Path::new(&home).join(".foundry/bin/anvil"));
+    }
+    candidates.into_iter().find(|path| path.is_file())
+}
+
+fn one() -> u32 {
+    1
+}
+
+fn secs<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    u64::deserialize(deserializer).map(Duration::from_secs)
+}
+
+fn millis<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    u64::deserialize(deserializer).map(Duration::from_millis)
+}
+
+/// `evm-repl.toml`, the first layer over the defaults. What it leaves out keeps its default;
+/// keys it doesn't know are refused rather than ignored, so a misspelt setting can't go unnoticed.
+/// See `evm-repl.example.toml` for every setting.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    /// By chain ID, `[chains.8453]`
+    chains: BTreeMap<String, ChainFile>,
+    rpc_pool: RpcPoolFile,
+    default_chain_id: Option<u64>,
+    default_confirmation_lag: Option<u64>,
+    anvil_bin: Option<PathBuf>,
+    limits: Limits,
+    api_keys: HashSet<String>,
+    admin_key: Option<String>,
+    /// `json` or `text`
+    log_format: Option<String>,
+    cors: CorsConfig,
+    drain_timeout_secs: Option<u64>,
+    compression_threshold: Option<usize>,
+    results_dir: Option<PathBuf>,
+    webhook_secret: Option<String>,
+    webhook_allowed_hosts: Vec<String>,
+    sourcify_url: Option<String>,
+    gas_regression_threshold: Option<f64>,
+    /// Demo mode is on when the file has a `[demo]`
+    demo: Option<DemoConfig>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ChainFile {
+    /// The chain's one RPC URL. With several, `rpc_urls` gives each a weight.
+    rpc_url: Option<String>,
+    rpc_urls: Vec<RpcEndpoint>,
+    confirmation_lag: Option<u64>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RpcPoolFile {
+    /// `round-robin` or `least-recently-limited`
+    strategy: Option<String>,
+    cooldown_secs: Option<u64>,
+}
+
+impl ConfigFile {
+    fn read(path: &Path) -> Result<Self, eyre::Error> {
+        if !path.is_file() {
+            return Err(eyre::eyre!("config file {} doesn't exist", path.display()));
+        }
+        Figment::from(Toml::file(path))
+            .extract()
+            .map_err(|err| eyre::eyre!("{}: {}", path.display(), err))
+    }
+
+    fn into_config(self) -> Result<AppConfig, eyre::Error> {
+        let defaults = AppConfig::default();
+        let mut chain_rpc_urls = HashMap::new();
+        let mut confirmation_lags = defaults.confirmation_lags;
+        for (key, chain) in self.chains {
+            let chain_id = key.parse().map_err(|_| {
+                eyre::eyre!(
+                    "chains.{} isn't a chain ID: chains are keyed by number, like [chains.8453]",
+                    key
+                )
+            })?;
+            let endpoints = chain.endpoints(&key)?;
+            if !endpoints.is_empty() {
+                chain_rpc_urls.insert(chain_id, endpoints);
+            }
+            if let Some(lag) = chain.confirmation_lag {
+                confirmation_lags.insert(chain_id, lag);
+            }
+        }
+        Ok(AppConfig {
+            chain_rpc_urls,
+            rpc_pool: RpcPoolConfig {
+                strategy: match &self.rpc_pool.strategy {
+                    Some(strategy) => pool_strategy("rpc_pool.strategy", strategy)?,
+                    None => defaults.rpc_pool.strategy,
+                },
+                cooldown: self
+                    .rpc_pool
+                    .cooldown_secs
+                    .map(Duration::from_secs)
+                    .unwrap_or(defaults.rpc_pool.cooldown),
+            },
+            default_chain_id: self.default_chain_id.unwrap_or(defaults.default_chain_id),
+            confirmation_lags,
+            default_confirmation_lag: self
+                .default_confirmation_lag
+                .unwrap_or(defaults.default_confirmation_lag),
+            anvil_bin: match &self.anvil_bin {
+                Some(bin) => Some(check_file("anvil_bin", bin)?),
+                None => None,
+            },
+            limits: self.limits,
+            api_keys: self.api_keys,
+            admin_key: self.admin_key,
+            demo: self.demo,
+            log_json: match &self.log_format {
+                Some(format) => log_json("log_format", format)?,
+                None => defaults.log_json,
+            },
+            cors: self.cors,
+            drain_timeout: self
+                .drain_timeout_secs
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.drain_timeout),
+            compression_threshold: self
+                .compression_threshold
+                .unwrap_or(defaults.compression_threshold),
+            results_dir: self.results_dir.unwrap_or(defaults.results_dir),
+            webhook_secret: self.webhook_secret,
+            webhook_allowed_hosts: self.webhook_allowed_hosts,
+            sourcify_url: self.sourcify_url,
+            gas_regression_threshold: self
+                .gas_regression_threshold
+                .unwrap_or(defaults.gas_regression_threshold),
+        })
+    }
+}
+
+impl ChainFile {
+    // `key` is the chain's as the file has it, to name the setting that's wrong
+    fn endpoints(&self, key: &str) -> Result<Vec<RpcEndpoint>, eyre::Error> {
+        let mut endpoints = Vec::new();
+        if let Some(url) = &self.rpc_url {
+            check_url(&format!("chains.{}.rpc_url", key), url)?;
+            endpoints.push(RpcEndpoint {
+                url: url.clone(),
+                weight: 1,
+            });
+        }
+        for (i, endpoint) in self.rpc_urls.iter().enumerate() {
+            check_url(
+                &format!("chains.{}.rpc_urls[{}].url", key, i),
+                &endpoint.url,
+            )?;
+            if endpoint.weight == 0 {
+                return Err(eyre::eyre!(
+                    "chains.{}.rpc_urls[{}].weight must be positive",
+                    key,
+                    i
+                ));
+            }
+            endpoints.push(endpoint.clone());
+        }
+        Ok(endpoints)
+    }
+}
+
+/// Settings the command line can give, the last layer, over the config file and the
+/// environment.
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct ConfigArgs {
+    /// TOML config file. Without one, `EVM_REPL_CONFIG`, or `evm-repl.toml` if the working
+    /// directory has one.
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Chain forked when a request doesn't name one
+    #[arg(long)]
+    pub default_chain_id: Option<u64>,
+    /// `json` or `text`
+    #[arg(long)]
+    pub log_format: Option<String>,
+    /// Where persisted execution results are written
+    #[arg(long)]
+    pub results_dir: Option<PathBuf>,
+    #[arg(long)]
+    pub anvil_bin: Option<PathBuf>,
+    /// Turns public demo mode on, with the config's `[demo]` settings or the defaults
+    #[arg(long)]
+    pub demo: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rpc_endpoints() {
+        let endpoint = |url: &str, weight| RpcEndpoint {
+            url: url.to_string(),
+            weight,
+        };
+        assert_eq!(
+            parse_rpc_endpoints("ETH_RPC", "https://a/KEY1").unwrap(),
+            [endpoint("https://a/KEY1", 1)]
+        );
+        assert_eq!(
+            parse_rpc_endpoints("ETH_RPC", "https://a/KEY1 3, https://b/KEY2,").unwrap(),
+            [endpoint("https://a/KEY1", 3), endpoint("https://b/KEY2", 1)]
+        );
+        assert!(parse_rpc_endpoints("ETH_RPC", "https://a/KEY1 0").is_err());
+        assert!(parse_rpc_endpoints("ETH_RPC", "https://a/KEY1 heavy").is_err());
+        assert!(parse_rpc_endpoints("ETH_RPC", "https://a/KEY1 1 2").is_err());
+    }
+
+    #[test]
+    fn test_confirmation_lags() {
+        let config = AppConfig::default();
+        assert_eq!(config.confirmation_lag(137), 5);
+        assert_eq!(config.confirmation_lag(8453), 1);
+
+        let lags = parse_confirmation_lags("137:8, 8453:0,").unwrap();
+        assert_eq!(lags, HashMap::from([(137, 8), (8453, 0)]));
+        assert!(parse_confirmation_lags("137").is_err());
+        assert!(parse_confirmation_lags("polygon:5").is_err());
+    }
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src/fixtures/config")
+            .join(name)
+    }
+
+    // `name`'s fixture, with `vars` for the environment instead of the process's
+    fn layered(
+        name: &str,
+        vars: &[(&str, &str)],
+        args: &ConfigArgs,
+    ) -> Result<AppConfig, eyre::Error> {
+        let vars: HashMap<&str, &str> = vars.iter().copied().collect();
+        AppConfig::layered(
+            Some(&fixture(name)),
+            &|name| vars.get(name).map(|value| value.to_string()),
+            args,
+        )
+    }
+
+    #[test]
+    fn test_layering() {
+        let config = layered("layered.toml", &[], &ConfigArgs::default()).unwrap();
+        assert_eq!(config.default_chain_id, 1);
+        assert!(config.log_json);
+        assert_eq!(config.limits.max_calls, 64);
+        assert_eq!(config.limits.job_ttl, Duration::from_secs(60));
+        assert_eq!(config.limits.request_deadline, Duration::from_millis(5000));
+        assert_eq!(config.limits.execute_rate.burst, 4);
+        // Left out of the file
+        assert_eq!(config.limits.max_files, Limits::default().max_files);
+        assert_eq!(config.confirmation_lag(1), 2);
+        assert_eq!(config.confirmation_lag(137), 5);
+        assert_eq!(
+            config.rpc_endpoints(1).unwrap(),
+            [
+                RpcEndpoint {
+                    url: "https://eth.example/KEY1".to_string(),
+                    weight: 3
+                },
+                RpcEndpoint {
+                    url: "wss://eth.example/KEY2".to_string(),
+                    weight: 1
+                },
+            ]
+        );
+        assert_eq!(config.demo.unwrap().max_calls, 8);
+
+        let env = [
+            ("DEFAULT_CHAIN_ID", "10"),
+            ("BASE_RPC", "https://base.example/ENV"),
+            ("JOB_TTL_SECS", "120"),
+            ("WEBHOOK_ALLOWED_HOSTS", ""),
+            ("DEMO_MODE", "false"),
+        ];
+        let config = layered("layered.toml", &env, &ConfigArgs::default()).unwrap();
+        assert_eq!(config.default_chain_id, 10);
+        assert_eq!(
+            config.rpc_endpoints(8453).unwrap()[0].url,
+            "https://base.example/ENV"
+        );
+        assert_eq!(config.limits.job_ttl, Duration::from_secs(120));
+        assert_eq!(config.limits.max_calls, 64);
+        assert!(config.webhook_allowed_hosts.is_empty());
+        assert!(config.demo.is_none());
+
+        let args = ConfigArgs {
+            default_chain_id: Some(137),
+            log_format: Some("text".to_string()),
+            demo: true,
+            ..ConfigArgs::default()
+        };
+        let config = layered("layered.toml", &env, &args).unwrap();
+        assert_eq!(config.default_chain_id, 137);
+        assert!(!config.log_json);
+        assert_eq!(config.limits.job_ttl, Duration::from_secs(120));
+        assert_eq!(
+            config.demo.unwrap().max_calls,
+            DemoConfig::default().max_calls
+        );
+    }
+
+    #[test]
+    fn test_example_config() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("evm-repl.example.toml");
+        let config = AppConfig::layered(Some(&path), &|_| None, &ConfigArgs::default()).unwrap();
+        assert_eq!(config.rpc_endpoints(1).unwrap().len(), 2);
+        assert_eq!(config.rpc_pool.strategy, PoolStrategy::RoundRobin);
+        assert!(config.demo.is_none());
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let error = |name: &str, vars: &[(&str, &str)]| {
+            layered(name, vars, &ConfigArgs::default())
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(error("bad_url.toml", &[]).starts_with("chains.8453.rpc_url is not a valid URL"));
+        assert!(error("chain_name.toml", &[]).starts_with("chains.base isn't a chain ID"));
+        assert!(error("unknown_field.toml", &[]).contains("max_cals"));
+        assert_eq!(
+            error("no_forks.toml", &[]),
+            "limits.max_concurrent_forks is 0, so nothing it limits would ever run"
+        );
+        assert!(error("missing.toml", &[]).ends_with("missing.toml doesn't exist"));
+        // The environment's settings are checked as the file's are
+        assert!(
+            error("layered.toml", &[("ETH_RPC", "ftp://eth.example/KEY")])
+                .starts_with("ETH_RPC must be an http(s) or ws(s) URL, not ftp")
+        );
+        assert!(error("layered.toml", &[("DEMO_MAX_CALLS", "100")])
+            .starts_with("demo.max_calls is 100, over limits.max_calls of 64"));
+    }
+}
@@ -0,0 +1,118 @@
+use rocket::http::Method;
+use rocket_cors::{AllowedHeaders, AllowedOrigins, Cors, CorsOptions};
+
+use crate::config::CorsConfig;
+
+// Only what the routes use
+const METHODS: [Method; 3] = [Method::Get, Method::Post, Method::Delete];
+const REQUEST_HEADERS: [&str; 4] = ["Accept", "Content-Type", "X-API-Key", "X-Admin-Key"];
+const EXPOSED_HEADERS: [&str; 6] = [
+    "X-Request-Id",
+    "X-Result-Id",
+    "Retry-After",
+    "Deprecation",
+    "Link",
+    "ETag",
+];
+
+/// Builds the CORS fairing. Credentials are only allowed when origins are listed explicitly, as
+/// browsers refuse them with a wildcard anyway.
+pub fn cors(config: &CorsConfig) -> Result<Cors, eyre::Error> {
+    let explicit = !config.origins.is_empty() || !config.origin_regexes.is_empty();
+    let allowed_origins = if explicit {
+        AllowedOrigins::some(&config.origins, &config.origin_regexes)
+    } else {
+        AllowedOrigins::all()
+    };
+
+    CorsOptions::default()
+        .allowed_origins(allowed_origins)
+        .allowed_methods(METHODS.into_iter().map(From::from).collect())
+        .allowed_headers(AllowedHeaders::some(&REQUEST_HEADERS))
+        .expose_headers(EXPOSED_HEADERS.iter().map(|h| h.to_string()).collect())
+        .max_age(config.max_age)
+        .allow_credentials(explicit)
+        .to_cors()
+        .map_err(|err| eyre::eyre!("invalid CORS configuration: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::{Header, Status};
+    use rocket::local::blocking::{Client, LocalResponse};
+    use rocket::{get, routes};
+
+    #[get("/ping")]
+    fn ping() -> &'static str {
+        "pong"
+    }
+
+    fn client(config: CorsConfig) -> Client {
+        let rocket = rocket::build()
+            .attach(cors(&config).unwrap())
+            .mount("/", routes![ping]);
+        Client::tracked(rocket).unwrap()
+    }
+
+    fn preflight<'c>(client: &'c Client, origin: &'static str) -> LocalResponse<'c> {
+        client
+            .options("/ping")
+            .header(Header::new("Origin", origin))
+            .header(Header::new("Access-Control-Request-Method", "GET"))
+            .dispatch()
+    }
+
+    #[test]
+    fn test_explicit_origins() {
+        let client = client(CorsConfig {
+            origins: vec!["https://app.example.com".to_string()],
+            origin_regexes: vec![r"^https://.*\.preview\.example\.com$".to_string()],
+            max_age: Some(600),
+        });
+
+        let response = preflight(&client, "https://app.example.com");
+        assert_eq!(
+            response.headers().get_one("Access-Control-Allow-Origin"),
+            Some("https://app.example.com")
+        );
+        assert_eq!(
+            response
+                .headers()
+                .get_one("Access-Control-Allow-Credentials"),
+            Some("true")
+        );
+        assert_eq!(
+            response.headers().get_one("Access-Control-Max-Age"),
+            Some("600")
+        );
+
+        let response = preflight(&client, "https://pr-1.preview.example.com");
+        assert_eq!(
+            response.headers().get_one("Access-Control-Allow-Origin"),
+            Some("https://pr-1.preview.example.com")
+        );
+
+        let response = preflight(&client, "https://evil.example.org");
+        assert_eq!(response.status(), Status::Forbidden);
+        assert!(response
+            .headers()
+            .get_one("Access-Control-Allow-Origin")
+            .is_none());
+    }
+
+    #[test]
+    fn test_dev_default_has_no_credentials() {
+        let client = client(CorsConfig::default());
+
+        let response = preflight(&client, "http://localhost:3000");
+        assert!(response
+            .headers()
+            .get_one("Access-Control-Allow-Origin")
+            .is_some());
+        assert!(response
+            .headers()
+            .get_one("Access-Control-Allow-Credentials")
+            .is_none());
+    }
+}
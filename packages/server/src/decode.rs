@@ -0,0 +1,292 @@
+use alloy_dyn_abi::{DynSolType, DynSolValue, JsonAbiExt};
+use alloy_json_abi::Error;
+use alloy_primitives::{hex, Bytes, U256};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+// Numbers are returned as decimal strings, since they don't fit in a JSON number
+pub fn to_json(value: &DynSolValue) -> Value {
+    match value {
+        DynSolValue::Bool(b) => Value::Bool(*b),
+        DynSolValue::Int(i, _) => Value::String(i.to_string()),
+        DynSolValue::Uint(u, _) => Value::String(u.to_string()),
+        DynSolValue::FixedBytes(word, size) => Value::String(hex::encode_prefixed(&word[..*size])),
+        DynSolValue::Address(address) => Value::String(address.to_checksum(None)),
+        DynSolValue::Bytes(bytes) => Value::String(hex::encode_prefixed(bytes)),
+        DynSolValue::String(s) => Value::String(s.clone()),
+        DynSolValue::Array(items) | DynSolValue::FixedArray(items) | DynSolValue::Tuple(items) => {
+            Value::Array(items.iter().map(to_json).collect())
+        }
+        other => match other.as_tuple() {
+            Some(items) => Value::Array(items.iter().map(to_json).collect()),
+            None => Value::String(hex::encode_prefixed(other.abi_encode())),
+        },
+    }
+}
+
+/// Where an argument doesn't fit its type: the offending value within the arguments, e.g.
+/// `args[1][0]`, and the type expected there.
+pub struct ArgError {
+    pub path: String,
+    pub ty: String,
+    pub message: String,
+}
+
+/// `value` as a `ty`, the way `to_json` writes one: arrays and tuples as JSON arrays, anything else
+/// as a string, number or boolean that `ty` parses. `path` is where `value` is, for errors.
+pub fn from_json(ty: &DynSolType, value: &Value, path: &str) -> Result<DynSolValue, ArgError> {
+    let fail = |message: String| ArgError {
+        path: path.to_string(),
+        ty: ty.to_string(),
+        message,
+    };
+    let items = |expected_len: Option<usize>| -> Result<&Vec<Value>, ArgError> {
+        let items = value
+            .as_array()
+            .ok_or_else(|| fail(format!("expected a JSON array, got {}", value)))?;
+        match expected_len {
+            Some(len) if items.len() != len => {
+                Err(fail(format!("expected {} items, got {}", len, items.len())))
+            }
+            _ => Ok(items),
+        }
+    };
+
+    match ty {
+        DynSolType::Array(inner) => items(None)?
+            .iter()
+            .enumerate()
+            .map(|(i, item)| from_json(inner, item, &format!("{}[{}]", path, i)))
+            .collect::<Result<_, _>>()
+            .map(DynSolValue::Array),
+        DynSolType::FixedArray(inner, len) => items(Some(*len))?
+            .iter()
+            .enumerate()
+            .map(|(i, item)| from_json(inner, item, &format!("{}[{}]", path, i)))
+            .collect::<Result<_, _>>()
+            .map(DynSolValue::FixedArray),
+        DynSolType::Tuple(types) => items(Some(types.len()))?
+            .iter()
+            .zip(types)
+            .enumerate()
+            .map(|(i, (item, ty))| from_json(ty, item, &format!("{}[{}]", path, i)))
+            .collect::<Result<_, _>>()
+            .map(DynSolValue::Tuple),
+        leaf => {
+            let s = match value {
+                Value::String(s) => s.clone(),
+                Value::Number(n) => n.to_string(),
+                Value::Bool(b) => b.to_string(),
+                other => return Err(fail(format!("unexpected JSON value {}", other))),
+            };
+            leaf.coerce_str(&s).map_err(|err| fail(err.to_string()))
+        }
+    }
+}
+
+/// What a call's revert data says, as best it can be read.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum RevertReason {
+    /// `Error(string)`, from `require(condition, "message")` and `revert("message")`
+    Error { message: String },
+    /// `Panic(uint256)`, from a failed `assert`, an overflow, a division by zero, ...
+    Panic {
+        /// Hex, e.g. `0x11`
+        code: String,
+        description: String,
+    },
+    /// A custom error found among the ABIs given
+    Custom {
+        name: String,
+        signature: String,
+        #[schema(value_type = Vec<Object>)]
+        args: Vec<Value>,
+    },
+    /// Nothing matched. `selector` is missing when there are fewer than 4 bytes.
+    Unknown {
+        selector: Option<String>,
+        #[schema(value_type = String)]
+        data: Bytes,
+    },
+}
+
+// Always known, ahead of any ABI passed in
+static BUILTIN_ERRORS: Lazy<[Error; 2]> = Lazy::new(|| {
+    [
+        Error::parse("Error(string)").expect("valid signature"),
+        Error::parse("Panic(uint256)").expect("valid signature"),
+    ]
+});
+
+/// The standard meaning of a `Panic(uint256)` code.
+pub fn panic_description(code: U256) -> &'static str {
+    if code > U256::from(u8::MAX) {
+        return "unknown panic code";
+    }
+    match code.to::<u8>() {
+        0x00 => "generic compiler inserted panic",
+        0x01 => "assertion failed",
+        0x11 => "arithmetic underflow or overflow",
+        0x12 => "division or modulo by zero",
+        0x21 => "conversion to an enum out of range",
+        0x22 => "access to an incorrectly encoded storage byte array",
+        0x31 => "pop() on an empty array",
+        0x32 => "array index out of bounds",
+        0x41 => "out of memory, or an array too large to allocate",
+        0x51 => "call to an uninitialized internal function",
+        _ => "unknown panic code",
+    }
+}
+
+impl RevertReason {
+    /// One line saying why, as an error message would
+    pub fn message(&self) -> String {
+        match self {
+            RevertReason::Error { message } => message.clone(),
+            RevertReason::Panic { description, .. } => format!("panic: {}", description),
+            RevertReason::Custom { signature, .. } => signature.clone(),
+            RevertReason::Unknown { .. } => "execution reverted".to_string(),
+        }
+    }
+}
+
+/// Decodes revert data, trying `Error(string)` and `Panic(uint256)` first and then `errors`.
+pub fn decode_revert(data: &[u8], errors: &[Error]) -> RevertReason {
+    let unknown = || RevertReason::Unknown {
+        selector: (data.len() >= 4).then(|| hex::encode_prefixed(&data[..4])),
+        data: Bytes::copy_from_slice(data),
+    };
+    if data.len() < 4 {
+        return unknown();
+    }
+    let (selector, payload) = data.split_at(4);
+    let Some((error, args)) = BUILTIN_ERRORS
+        .iter()
+        .chain(errors)
+        .filter(|error| error.selector().as_slice() == selector)
+        .find_map(|error| Some((error, error.abi_decode_input(payload, true).ok()?)))
+    else {
+        return unknown();
+    };
+
+    match (error.signature().as_str(), args.as_slice()) {
+        ("Error(string)", [DynSolValue::String(message)]) => RevertReason::Error {
+            message: message.clone(),
+        },
+        ("Panic(uint256)", [DynSolValue::Uint(code, _)]) => RevertReason::Panic {
+            code: format!("{:#x}", code),
+            description: panic_description(*code).to_string(),
+        },
+        _ => RevertReason::Custom {
+            name: error.name.clone(),
+            signature: error.signature(),
+            args: args.iter().map(to_json).collect(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::bytes;
+
+    fn panic_data(code: u64) -> Vec<u8> {
+        BUILTIN_ERRORS[1]
+            .abi_encode_input(&[DynSolValue::Uint(U256::from(code), 256)])
+            .unwrap()
+    }
+
+    #[test]
+    fn test_error_string() {
+        let data = BUILTIN_ERRORS[0]
+            .abi_encode_input(&[DynSolValue::String("not owner".to_string())])
+            .unwrap();
+        assert_eq!(
+            decode_revert(&data, &[]),
+            RevertReason::Error {
+                message: "not owner".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_every_panic_code() {
+        let codes = [
+            (0x00, "generic compiler inserted panic"),
+            (0x01, "assertion failed"),
+            (0x11, "arithmetic underflow or overflow"),
+            (0x12, "division or modulo by zero"),
+            (0x21, "conversion to an enum out of range"),
+            (0x22, "access to an incorrectly encoded storage byte array"),
+            (0x31, "pop() on an empty array"),
+            (0x32, "array index out of bounds"),
+            (0x41, "out of memory, or an array too large to allocate"),
+            (0x51, "call to an uninitialized internal function"),
+            (0x99, "unknown panic code"),
+            (0x1_0000, "unknown panic code"),
+        ];
+        for (code, description) in codes {
+            assert_eq!(
+                decode_revert(&panic_data(code), &[]),
+                RevertReason::Panic {
+                    code: format!("{:#x}", code),
+                    description: description.to_string(),
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_custom_error() {
+        let error = Error::parse("InsufficientBalance(address owner, uint256 needed)").unwrap();
+        let data = error
+            .abi_encode_input(&[
+                DynSolValue::Address(Default::default()),
+                DynSolValue::Uint(U256::from(5), 256),
+            ])
+            .unwrap();
+        assert_eq!(
+            decode_revert(&data, &[error]),
+            RevertReason::Custom {
+                name: "InsufficientBalance".to_string(),
+                signature: "InsufficientBalance(address,uint256)".to_string(),
+                args: vec![
+                    Value::String("0x0000000000000000000000000000000000000000".to_string()),
+                    Value::String("5".to_string()),
+                ],
+            }
+        );
+
+        // Without the ABI it's just a selector
+        assert!(matches!(
+            decode_revert(&data, &[]),
+            RevertReason::Unknown { selector: Some(selector), .. } if selector == "0xf6deaa04"
+        ));
+    }
+
+    #[test]
+    fn test_unknown() {
+        assert_eq!(
+            decode_revert(&[], &[]),
+            RevertReason::Unknown {
+                selector: None,
+                data: Bytes::new(),
+            }
+        );
+        assert_eq!(
+            decode_revert(&bytes!("deadbeef01"), &[]),
+            RevertReason::Unknown {
+                selector: Some("0xdeadbeef".to_string()),
+                data: bytes!("deadbeef01"),
+            }
+        );
+        // The Error(string) selector with a payload that isn't a string
+        assert!(matches!(
+            decode_revert(&bytes!("08c379a0ff"), &[]),
+            RevertReason::Unknown { .. }
+        ));
+    }
+}
@@ -0,0 +1,175 @@
+//! Public demo mode. Requests without an API key are let in by `auth` against buckets per client
+//! IP, then turned away by `Checked` from what costs the most to run: debug traces, RPC URLs of
+//! their own and more calls than `DemoConfig::max_calls`. It's read off the raw JSON, so a
+//! feature is refused wherever in the body it's asked for, a batch's scenarios or a job's request.
+
+use rocket::http::Status;
+use rocket::request::Request;
+use serde_json::{json, Value};
+use std::net::{IpAddr, Ipv4Addr};
+
+use crate::config::DemoConfig;
+use crate::error::ApiError;
+
+// Keys whose values are the client's own, anything at all, so never read as options
+const FREE_FORM: [&str; 2] = ["meta", "args"];
+
+// Set on a request let in as a demo request
+struct Demo(Option<DemoConfig>);
+
+/// Marks `req` as let in as a demo request, so its body is gated by `config`.
+pub(crate) fn admit(req: &Request<'_>, config: &DemoConfig) {
+    req.local_cache(|| Demo(Some(config.clone())));
+}
+
+/// What gates `req`'s body, if it was let in as a demo request.
+pub(crate) fn gated<'r>(req: &'r Request<'_>) -> Option<&'r DemoConfig> {
+    req.local_cache(|| Demo(None)).0.as_ref()
+}
+
+/// The IP `req` came from: the last address in `config`'s header, the one the proxy in front of
+/// the server added, or without the header the connection's. Requests with neither share the
+/// unspecified address.
+pub fn client_ip(req: &Request<'_>, config: &DemoConfig) -> IpAddr {
+    config
+        .ip_header
+        .as_deref()
+        .and_then(|header| req.headers().get(header).last())
+        .and_then(|value| value.rsplit(',').next())
+        .and_then(|ip| ip.trim().parse().ok())
+        .or_else(|| req.remote().map(|addr| addr.ip()))
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+}
+
+/// Refuses the first feature `body` asks for that demo requests can't have.
+pub fn gate(body: &Value, config: &DemoConfig) -> Result<(), ApiError> {
+    walk(body, "", config)
+}
+
+/// The error a demo request gets for asking for `feature` at `field`.
+pub fn upgrade(feature: &str, field: &str, what: &str) -> ApiError {
+    ApiError::new(
+        Status::Forbidden,
+        "UPGRADE_REQUIRED",
+        format!(
+            "{} isn't available in the public demo. Send an X-API-Key to use it.",
+            what
+        ),
+    )
+    .with_details(json!({ "feature": feature, "field": field }))
+}
+
+fn walk(value: &Value, path: &str, config: &DemoConfig) -> Result<(), ApiError> {
+    match value {
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                if FREE_FORM.contains(&key.as_str()) {
+                    continue;
+                }
+                let field = match path {
+                    "" => key.clone(),
+                    _ => format!("{}.{}", path, key),
+                };
+                check(key, value, &field, config)?;
+                walk(value, &field, config)?;
+            }
+        }
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                walk(item, &format!("{}[{}]", path, i), config)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn check(key: &str, value: &Value, field: &str, config: &DemoConfig) -> Result<(), ApiError> {
+    match key {
+        "rpcUrl" if !value.is_null() => Err(upgrade(
+            "rpcUrl",
+            field,
+            "Forking your own RPC URL (fork a configured chain by chainId instead)",
+        )),
+        "traceMode" if *value == "debug" => {
+            Err(upgrade("debugTraces", field, "The debug trace mode"))
+        }
+        // Each records opcodes as the debug trace mode does
+        "journal" | "transientStorage" if *value == true => Err(upgrade(
+            "debugTraces",
+            field,
+            &format!("{}, which records a debug trace,", key),
+        )),
+        "breakpoints" if value.as_array().is_some_and(|b| !b.is_empty()) => Err(upgrade(
+            "debugTraces",
+            field,
+            "Breakpoints, which record a debug trace,",
+        )),
+        "calls" | "rawTransactions" => match value.as_array() {
+            Some(calls) if calls.len() > config.max_calls => Err(upgrade(
+                "calls",
+                field,
+                &format!("More than {} calls a request", config.max_calls),
+            )
+            .with_details(json!({
+                "feature": "calls",
+                "field": field,
+                "max": config.max_calls,
+                "actual": calls.len(),
+            }))),
+            _ => Ok(()),
+        },
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feature(body: Value) -> Option<(String, String)> {
+        let err = gate(&body, &DemoConfig::default()).err()?;
+        assert_eq!(err.status, Status::Forbidden);
+        assert_eq!(err.code, "UPGRADE_REQUIRED");
+        let details = err.details.unwrap();
+        Some((
+            details["feature"].as_str().unwrap().to_string(),
+            details["field"].as_str().unwrap().to_string(),
+        ))
+    }
+
+    #[test]
+    fn test_gate() {
+        assert_eq!(
+            feature(json!({
+                "calls": [{ "calldata": "0x", "meta": { "rpcUrl": "mine" } }],
+                "options": { "traceMode": "call", "journal": false, "breakpoints": [] },
+                "forkConfig": { "chainId": 1, "rpcUrl": null },
+            })),
+            None
+        );
+        assert_eq!(
+            feature(json!({ "forkConfig": { "rpcUrl": "http://localhost:8545" } })),
+            Some(("rpcUrl".to_string(), "forkConfig.rpcUrl".to_string()))
+        );
+        assert_eq!(
+            feature(json!({ "scenarios": [{}, { "options": { "traceMode": "debug" } }] })),
+            Some((
+                "debugTraces".to_string(),
+                "scenarios[1].options.traceMode".to_string()
+            ))
+        );
+        assert_eq!(
+            feature(json!({ "options": { "transientStorage": true } })),
+            Some((
+                "debugTraces".to_string(),
+                "options.transientStorage".to_string()
+            ))
+        );
+        let calls = vec![json!({ "calldata": "0x" }); DemoConfig::default().max_calls + 1];
+        assert_eq!(
+            feature(json!({ "calls": calls })),
+            Some(("calls".to_string(), "calls".to_string()))
+        );
+    }
+}
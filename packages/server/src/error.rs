@@ -0,0 +1,400 @@
+use alloy_transport::TransportError;
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::Json;
+use rocket::{catch, catchers, Catcher};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fmt;
+use std::sync::Mutex;
+use utoipa::ToSchema;
+
+use crate::compile::solidity::{CompileMemoryLimit, CompileResult, CompileTimeout};
+use crate::gas::{
+    anvil::AnvilError, ens::EnsError, raw_transaction::InvalidTransaction,
+    templates::TemplateError, ForkError, NotIndependent, ReplayError,
+};
+
+/// The JSON body returned by every route on failure. `code` is stable and meant for clients to
+/// branch on; `message` is for humans.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct ApiError {
+    #[serde(skip)]
+    pub status: Status,
+    #[schema(value_type = String)]
+    pub code: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+    /// Seconds, sent as `Retry-After`
+    #[serde(skip)]
+    pub retry_after: Option<u64>,
+}
+
+impl ApiError {
+    pub fn new(status: Status, code: &'static str, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            code,
+            message: message.into(),
+            details: None,
+            retry_after: None,
+        }
+    }
+
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    pub fn invalid_request(message: impl Into<String>) -> Self {
+        ApiError::new(Status::BadRequest, "INVALID_REQUEST", message)
+    }
+
+    pub fn compile_failed(err: eyre::Error) -> Self {
+        if err.downcast_ref::<CompileTimeout>().is_some() {
+            return ApiError::new(Status::GatewayTimeout, "COMPILE_TIMEOUT", err.to_string());
+        }
+        if err.downcast_ref::<CompileMemoryLimit>().is_some() {
+            return ApiError::new(
+                Status::UnprocessableEntity,
+                "COMPILE_RESOURCE_LIMIT",
+                err.to_string(),
+            );
+        }
+        ApiError::new(
+            Status::UnprocessableEntity,
+            "COMPILE_FAILED",
+            err.to_string(),
+        )
+    }
+
+    /// Sources solc rejected. `details` holds the compiler output, errors and any contracts it
+    /// still produced.
+    pub fn compile_errors(result: &CompileResult) -> Self {
+        let errors = result.errors.iter().filter(|err| err.is_error()).count();
+        ApiError::new(
+            Status::UnprocessableEntity,
+            "COMPILE_FAILED",
+            format!("Compilation failed with {} error(s)", errors),
+        )
+        .with_details(json!(result))
+    }
+
+    /// Classifies an error coming out of the gas module.
+    pub fn from_execution(err: eyre::Error) -> Self {
+        if let Some(err) = err.downcast_ref::<AnvilError>() {
+            return ApiError::new(
+                Status::ServiceUnavailable,
+                "ANVIL_UNAVAILABLE",
+                err.to_string(),
+            );
+        }
+        if let Some(err) = err.downcast_ref::<EnsError>() {
+            return ApiError::new(
+                Status::UnprocessableEntity,
+                "ENS_RESOLUTION_FAILED",
+                err.to_string(),
+            )
+            .with_details(json!({ "field": err.field }));
+        }
+        if let Some(err) = err.downcast_ref::<InvalidTransaction>() {
+            return ApiError::new(
+                Status::UnprocessableEntity,
+                "INVALID_TRANSACTION",
+                err.to_string(),
+            )
+            .with_details(json!({ "field": err.field() }));
+        }
+        if let Some(replay_err) = err.downcast_ref::<ReplayError>() {
+            let (status, code) = match replay_err {
+                ReplayError::NotFound(_) => (Status::NotFound, "TRANSACTION_NOT_FOUND"),
+                ReplayError::Pending(_) => (Status::UnprocessableEntity, "TRANSACTION_PENDING"),
+                ReplayError::NotInBlock { .. } => (Status::BadGateway, "FORK_BLOCK_INVALID"),
+                ReplayError::NoHistory => (Status::UnprocessableEntity, "INVALID_FORK_CONFIG"),
+            };
+            return ApiError::new(status, code, replay_err.to_string());
+        }
+        if let Some(err) = err.downcast_ref::<TemplateError>() {
+            return ApiError::new(
+                Status::UnprocessableEntity,
+                "INVALID_ABI_ARGUMENT",
+                err.to_string(),
+            )
+            .with_details(json!({ "field": err.field() }));
+        }
+        if let Some(err) = err.downcast_ref::<NotIndependent>() {
+            return ApiError::new(
+                Status::UnprocessableEntity,
+                "CALL_NOT_INDEPENDENT",
+                err.to_string(),
+            )
+            .with_details(json!({ "field": err.field() }));
+        }
+        if let Some(fork_err) = err.downcast_ref::<ForkError>() {
+            let (status, code) = match fork_err {
+                ForkError::UnknownMode(_)
+                | ForkError::UnknownNetwork(_)
+                | ForkError::BlockInFuture { .. }
+                | ForkError::Conflict { .. } => {
+                    (Status::UnprocessableEntity, "INVALID_FORK_CONFIG")
+                }
+                ForkError::UnsupportedChain(_) => {
+                    (Status::UnprocessableEntity, "UNSUPPORTED_CHAIN")
+                }
+                ForkError::EnsUnsupported(_) => {
+                    (Status::UnprocessableEntity, "ENS_RESOLUTION_FAILED")
+                }
+                ForkError::BlockNotFound => (Status::BadGateway, "FORK_BLOCK_NOT_FOUND"),
+                ForkError::InvalidBlock(_) => (Status::BadGateway, "FORK_BLOCK_INVALID"),
+            };
+            let err = ApiError::new(status, code, fork_err.to_string());
+            return match fork_err.field() {
+                Some(field) => err.with_details(json!({ "field": field })),
+                None => err,
+            };
+        }
+        if err
+            .chain()
+            .any(|cause| cause.downcast_ref::<TransportError>().is_some())
+        {
+            return ApiError::new(Status::BadGateway, "FORK_RPC_UNREACHABLE", err.to_string());
+        }
+        ApiError::new(
+            Status::UnprocessableEntity,
+            "EXECUTION_FAILED",
+            err.to_string(),
+        )
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let status = self.status;
+        let retry_after = self.retry_after;
+        let mut response = Response::build_from(Json(self).respond_to(req)?);
+        response.status(status);
+        if let Some(secs) = retry_after {
+            response.raw_header("Retry-After", secs.to_string());
+        }
+        response.ok()
+    }
+}
+
+// Anything that doesn't reach a handler (bad JSON, unknown routes) or panics inside one
+#[catch(default)]
+fn default_catcher(status: Status, req: &Request) -> ApiError {
+    // Guards that reject a request leave the full error behind for us
+    if let Some(err) = rejection(req) {
+        return err;
+    }
+    let code = match status.code {
+        400 | 422 => "INVALID_REQUEST",
+        404 => "NOT_FOUND",
+        413 => "PAYLOAD_TOO_LARGE",
+        500 => "EXECUTION_PANIC",
+        _ => "HTTP_ERROR",
+    };
+    ApiError::new(status, code, status.reason_lossy())
+}
+
+/// Stashes the error a request guard failed with, so the catcher can send it as is.
+pub fn reject(req: &Request<'_>, err: ApiError) {
+    req.local_cache(|| Rejection(Mutex::new(None)))
+        .0
+        .lock()
+        .unwrap()
+        .replace(err);
+}
+
+fn rejection(req: &Request<'_>) -> Option<ApiError> {
+    req.local_cache(|| Rejection(Mutex::new(None)))
+        .0
+        .lock()
+        .unwrap()
+        .take()
+}
+
+struct Rejection(Mutex<Option<ApiError>>);
+
+pub fn catchers() -> Vec<Catcher> {
+    catchers![default_catcher]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admission::Gates;
+    use crate::auth::Auth;
+    use crate::config::AppConfig;
+    use crate::results::{MemoryStore, Results};
+    use crate::routes::{execute_calldatas_fork_route, execute_calldatas_route};
+    use crate::shutdown::InFlight;
+    use rocket::http::ContentType;
+    use rocket::local::blocking::Client;
+    use rocket::{post, routes};
+
+    // Panics on the blocking pool, as a bug in the EVM would under any execution route
+    #[post("/panic")]
+    async fn panicking() -> Result<(), ApiError> {
+        crate::gas::run_blocking(|| -> Result<(), eyre::Error> { panic!("boom") })
+            .await
+            .map_err(ApiError::from_execution)
+    }
+
+    fn client() -> Client {
+        let config = AppConfig::default();
+        let rocket = rocket::build()
+            .manage(Auth::new(&config))
+            .manage(InFlight::default())
+            .manage(Gates::new(&config.limits))
+            .manage(Results::new(MemoryStore::default(), &config.limits))
+            .manage(config)
+            .mount(
+                "/",
+                routes![
+                    execute_calldatas_route,
+                    execute_calldatas_fork_route,
+                    panicking
+                ],
+            )
+            .register("/", catchers());
+        Client::tracked(rocket).unwrap()
+    }
+
+    fn post(client: &Client, uri: &'static str, body: &str) -> (Status, Value) {
+        let response = client
+            .post(uri)
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch();
+        (response.status(), response.into_json().unwrap())
+    }
+
+    #[test]
+    fn test_unsupported_chain() {
+        let client = client();
+        let (status, body) = post(
+            &client,
+            "/execute_calldatas_fork",
+            r#"{"bytecode":"0x00","address":"0x0000000000000000000000000000000000000001",
+                "calls":[],"forkConfig":{"chainId":999}}"#,
+        );
+        assert_eq!(status, Status::UnprocessableEntity);
+        assert_eq!(body["code"], "UNSUPPORTED_CHAIN");
+        assert_eq!(body["message"], "No RPC URL configured for chain ID 999");
+    }
+
+    #[test]
+    fn test_invalid_field_path() {
+        let client = client();
+        let call =
+            |caller: &str| json!({ "calldata": "0x6d4ce63c", "value": "0", "caller": caller });
+        let mut body = json!({
+            "bytecode": "0x00",
+            "address": "0x0000000000000000000000000000000000000001",
+            "calls": [
+                call("0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed"),
+                call("0x0000000000000000000000000000000000000002"),
+                call("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD"),
+            ],
+            "forkConfig": { "chainId": 999 },
+        });
+        let (status, response) = post(&client, "/execute_calldatas_fork", &body.to_string());
+        assert_eq!(status, Status::UnprocessableEntity);
+        assert_eq!(response["code"], "INVALID_FIELD");
+        assert_eq!(response["details"]["field"], "calls[2].caller");
+
+        // Gets as far as the fork
+        body["skipChecksum"] = json!(true);
+        let (_, response) = post(&client, "/execute_calldatas_fork", &body.to_string());
+        assert_eq!(response["code"], "UNSUPPORTED_CHAIN");
+
+        body["fields"] = json!(["gasUsed", "trace"]);
+        let (status, response) = post(&client, "/execute_calldatas_fork", &body.to_string());
+        assert_eq!(status, Status::UnprocessableEntity);
+        assert_eq!(response["details"]["field"], "fields[1]");
+        assert!(response["details"]["valid"]
+            .as_array()
+            .unwrap()
+            .contains(&json!("traces")));
+    }
+
+    #[test]
+    fn test_unknown_fields() {
+        let client = client();
+        let body = json!({
+            "bytecode": "0x00",
+            "address": "0x0000000000000000000000000000000000000001",
+            "calls": [{ "calldata": "0x6d4ce63c", "value": "0", "calller": "0x0000000000000000000000000000000000000002" }],
+            "forkConfig": { "chainId": 999 },
+        })
+        .to_string();
+        let (status, response) = post(&client, "/execute_calldatas_fork", &body);
+        assert_eq!(status, Status::UnprocessableEntity);
+        assert_eq!(response["code"], "UNKNOWN_FIELD");
+        assert_eq!(response["details"]["field"], "calls[0].calller");
+        assert_eq!(response["details"]["suggestion"], "caller");
+
+        // Let through, the call has no caller
+        let (_, response) = post(&client, "/execute_calldatas_fork?lenient=true", &body);
+        assert_eq!(response["code"], "INVALID_FIELD");
+        assert_eq!(response["details"]["field"], "calls[0].caller");
+    }
+
+    #[test]
+    fn test_conflicting_fork_config() {
+        let client = client();
+        let (status, body) = post(
+            &client,
+            "/execute_calldatas_fork",
+            r#"{"bytecode":"0x00","address":"0x0000000000000000000000000000000000000001",
+                "calls":[],"forkConfig":{"mode":"anvil","rpcUrl":"http://localhost:8545"}}"#,
+        );
+        assert_eq!(status, Status::UnprocessableEntity);
+        assert_eq!(body["code"], "INVALID_FORK_CONFIG");
+        assert_eq!(body["details"]["field"], "forkConfig.rpcUrl");
+    }
+
+    #[test]
+    fn test_invalid_hex() {
+        let client = client();
+        let (status, body) = post(
+            &client,
+            "/execute_calldatas",
+            r#"{"bytecode":"zz","calls":[]}"#,
+        );
+        assert_eq!(status, Status::BadRequest);
+        assert_eq!(body["code"], "INVALID_REQUEST");
+    }
+
+    #[test]
+    fn test_malformed_json_and_unknown_route() {
+        let client = client();
+        let (status, body) = post(&client, "/execute_calldatas", "{");
+        assert_eq!(status, Status::BadRequest);
+        assert_eq!(body["code"], "INVALID_REQUEST");
+
+        let (status, body) = post(&client, "/nope", "{}");
+        assert_eq!(status, Status::NotFound);
+        assert_eq!(body["code"], "NOT_FOUND");
+        assert!(body.get("details").is_none());
+    }
+
+    #[test]
+    fn test_panic_in_blocking_task() {
+        let client = client();
+        let (status, body) = post(&client, "/panic", "{}");
+        assert_eq!(status, Status::InternalServerError);
+        assert_eq!(body["code"], "EXECUTION_PANIC");
+    }
+}
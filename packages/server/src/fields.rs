@@ -0,0 +1,371 @@
+use crate::gas::ExecutionResult;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, SerializeSeq, SerializeStruct, Serializer};
+
+/// Top-level `ExecutionResult` fields, as named in JSON.
+pub const RESULT_FIELDS: [&str; 30] = [
+    "callIndex",
+    "meta",
+    "exitReason",
+    "success",
+    "reverted",
+    "result",
+    "revertReason",
+    "gasUsed",
+    "gasLimit",
+    "targetBalanceBefore",
+    "targetBalanceAfter",
+    "logs",
+    "traces",
+    "rawTraces",
+    "labels",
+    "traceExport",
+    "flamegraph",
+    "stateDiff",
+    "accessList",
+    "consistencyCheck",
+    "env",
+    "txValidity",
+    "attempts",
+    "skipped",
+    "hint",
+    "journal",
+    "transientStorageAccesses",
+    "created",
+    "pausedAt",
+    "internalFrames",
+];
+
+/// A subset of `RESULT_FIELDS` a client asked for, so it isn't sent traces it won't read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Fields(u32);
+
+impl Fields {
+    /// Fails with the first name that isn't a field.
+    pub fn parse<'a>(names: impl IntoIterator<Item = &'a str>) -> Result<Fields, &'a str> {
+        names.into_iter().try_fold(Fields(0), |fields, name| {
+            let index = RESULT_FIELDS.iter().position(|field| *field == name);
+            index.map(|i| Fields(fields.0 | 1 << i)).ok_or(name)
+        })
+    }
+
+    pub fn contains(self, name: &str) -> bool {
+        RESULT_FIELDS
+            .iter()
+            .position(|field| *field == name)
+            .is_some_and(|i| self.0 & 1 << i != 0)
+    }
+
+    pub fn names(self) -> impl Iterator<Item = &'static str> {
+        RESULT_FIELDS
+            .into_iter()
+            .filter(move |name| self.contains(name))
+    }
+}
+
+impl Serialize for Fields {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.names())
+    }
+}
+
+impl<'de> Deserialize<'de> for Fields {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let names = Vec::<String>::deserialize(deserializer)?;
+        Fields::parse(names.iter().map(String::as_str))
+            .map_err(|name| de::Error::unknown_variant(name, &RESULT_FIELDS))
+    }
+}
+
+/// Something that can be serialized with only some of its top-level fields.
+pub trait Prune {
+    fn serialize_pruned<S: Serializer>(
+        &self,
+        fields: Fields,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>;
+}
+
+/// `value` serialized with only `fields`, or whole without them. Pruning happens as it's written
+/// out, so everything before then works with the full value.
+pub struct Pruned<T> {
+    pub value: T,
+    pub fields: Option<Fields>,
+}
+
+impl<T: Prune + Serialize> Serialize for Pruned<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.fields {
+            Some(fields) => self.value.serialize_pruned(fields, serializer),
+            None => self.value.serialize(serializer),
+        }
+    }
+}
+
+impl<T: Prune + ?Sized> Prune for &T {
+    fn serialize_pruned<S: Serializer>(
+        &self,
+        fields: Fields,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        (**self).serialize_pruned(fields, serializer)
+    }
+}
+
+impl<T: Prune + Serialize> Prune for Vec<T> {
+    fn serialize_pruned<S: Serializer>(
+        &self,
+        fields: Fields,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.len()))?;
+        for value in self {
+            seq.serialize_element(&Pruned {
+                value,
+                fields: Some(fields),
+            })?;
+        }
+        seq.end()
+    }
+}
+
+impl Prune for ExecutionResult {
+    fn serialize_pruned<S: Serializer>(
+        &self,
+        fields: Fields,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        // The same fields, and the same ones left out when empty, as the derived impl
+        let present: Vec<_> = fields
+            .names()
+            .filter(|name| match *name {
+                "callIndex" => self.call_index.is_some(),
+                "meta" => self.meta.is_some(),
+                "revertReason" => self.revert_reason.is_some(),
+                "gasLimit" => self.gas_limit.is_some(),
+                "targetBalanceBefore" => self.target_balance_before.is_some(),
+                "targetBalanceAfter" => self.target_balance_after.is_some(),
+                "traces" => self.traces.is_some(),
+                "rawTraces" => self.raw_traces.is_some(),
+                "labels" => !self.labels.is_empty(),
+                "traceExport" => self.trace_export.is_some(),
+                "flamegraph" => self.flamegraph.is_some(),
+                "stateDiff" => self.state_diff.is_some(),
+                "accessList" => self.access_list.is_some(),
+                "consistencyCheck" => self.consistency_check.is_some(),
+                "env" => self.env.is_some(),
+                "txValidity" => self.tx_validity.is_some(),
+                "attempts" => self.attempts.is_some(),
+                "skipped" => self.skipped.is_some(),
+                "hint" => self.hint.is_some(),
+                "journal" => self.journal.is_some(),
+                "transientStorageAccesses" => self.transient_storage_accesses.is_some(),
+                "created" => self.created.is_some(),
+                "pausedAt" => self.paused_at.is_some(),
+                "internalFrames" => self.internal_frames.is_some(),
+                _ => true,
+            })
+            .collect();
+        let mut state = serializer.serialize_struct("ExecutionResult", present.len())?;
+        for name in present {
+            match name {
+                "callIndex" => state.serialize_field(name, &self.call_index)?,
+                "meta" => state.serialize_field(name, &self.meta)?,
+                "exitReason" => state.serialize_field(name, &self.exit_reason)?,
+                "success" => state.serialize_field(name, &self.success)?,
+                "reverted" => state.serialize_field(name, &self.reverted)?,
+                "result" => state.serialize_field(name, &self.result)?,
+                "revertReason" => state.serialize_field(name, &self.revert_reason)?,
+                "gasUsed" => state.serialize_field(name, &self.gas_used)?,
+                "gasLimit" => state.serialize_field(name, &self.gas_limit)?,
+                "targetBalanceBefore" => {
+                    state.serialize_field(name, &self.target_balance_before)?
+                }
+                "targetBalanceAfter" => state.serialize_field(name, &self.target_balance_after)?,
+                "logs" => state.serialize_field(name, &self.logs)?,
+                "traces" => state.serialize_field(name, &self.traces)?,
+                "rawTraces" => state.serialize_field(name, &self.raw_traces)?,
+                "labels" => state.serialize_field(name, &self.labels)?,
+                "traceExport" => state.serialize_field(name, &self.trace_export)?,
+                "flamegraph" => state.serialize_field(name, &self.flamegraph)?,
+                "stateDiff" => state.serialize_field(name, &self.state_diff)?,
+                "accessList" => state.serialize_field(name, &self.access_list)?,
+                "consistencyCheck" => state.serialize_field(name, &self.consistency_check)?,
+                "env" => state.serialize_field(name, &self.env)?,
+                "txValidity" => state.serialize_field(name, &self.tx_validity)?,
+                "attempts" => state.serialize_field(name, &self.attempts)?,
+                "skipped" => state.serialize_field(name, &self.skipped)?,
+                "hint" => state.serialize_field(name, &self.hint)?,
+                "journal" => state.serialize_field(name, &self.journal)?,
+                "transientStorageAccesses" => {
+                    state.serialize_field(name, &self.transient_storage_accesses)?
+                }
+                "created" => state.serialize_field(name, &self.created)?,
+                "pausedAt" => state.serialize_field(name, &self.paused_at)?,
+                "internalFrames" => state.serialize_field(name, &self.internal_frames)?,
+                _ => unreachable!("every field is listed in RESULT_FIELDS"),
+            }
+        }
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::RevertReason;
+    use crate::gas::{EventLog, ExitReason, TraceKind, TraceNode, TraceStatus};
+    use alloy_primitives::{address, bytes, Bytes, Log, LogData, U256};
+    use serde::de::IgnoredAny;
+    use serde_json::{json, Value};
+    use std::collections::BTreeMap;
+
+    fn result() -> ExecutionResult {
+        let contract = address!("b2f9974c62815d3177079e150377915d9bc49c82");
+        let caller = address!("1000000000000000000000000000000000000000");
+        ExecutionResult {
+            exit_reason: ExitReason::Revert,
+            success: false,
+            reverted: true,
+            revert_reason: Some(RevertReason::Unknown {
+                selector: None,
+                data: Bytes::new(),
+            }),
+            result: Bytes::new(),
+            gas_used: 43_512,
+            gas_limit: None,
+            logs: EventLog::from_logs(vec![Log {
+                address: contract,
+                data: LogData::new_unchecked(vec![], bytes!("deadbeef")),
+            }]),
+            traces: Some(vec![TraceNode {
+                kind: TraceKind::Call,
+                from: caller,
+                to: contract,
+                value: U256::from(1),
+                gas_used: 22_512,
+                input: bytes!("6d4ce63c"),
+                output: Bytes::new(),
+                status: TraceStatus::Revert,
+                output_length: None,
+                revert_reason: None,
+                children: vec![],
+                logs: vec![],
+            }]),
+            raw_traces: None,
+            labels: BTreeMap::from([(contract, "SimpleStorage".to_string())]),
+            trace_export: Some(r#"{"traceEvents":[]}"#.to_string()),
+            flamegraph: Some(
+                "Root;0xb2f9974C62815D3177079e150377915D9bC49C82.0x6d4ce63c 100\n".to_string(),
+            ),
+            state_diff: None,
+            access_list: None,
+            consistency_check: None,
+            env: None,
+            hint: None,
+            journal: None,
+            transient_storage_accesses: None,
+            created: None,
+            paused_at: None,
+            internal_frames: None,
+            target_balance_before: None,
+            target_balance_after: None,
+            tx_validity: None,
+            attempts: None,
+            skipped: None,
+            call_index: None,
+            meta: None,
+        }
+    }
+
+    fn pruned(fields: &[&str]) -> Vec<u8> {
+        let fields = Fields::parse(fields.iter().copied()).unwrap();
+        serde_json::to_vec(&Pruned {
+            value: vec![result()],
+            fields: Some(fields),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let fields = Fields::parse(["gasUsed", "reverted", "gasUsed"]).unwrap();
+        assert_eq!(fields.names().collect::<Vec<_>>(), ["reverted", "gasUsed"]);
+        assert_eq!(Fields::parse(["gasUsed", "gas"]), Err("gas"));
+
+        let err = serde_json::from_value::<Fields>(json!(["traces", "trace"])).unwrap_err();
+        assert!(err.to_string().contains("`trace`"));
+        assert!(err.to_string().contains("`revertReason`"));
+    }
+
+    #[test]
+    fn test_prunes_top_level_fields() {
+        let body = pruned(&["gasUsed", "reverted", "revertReason"]);
+        let results: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            results,
+            json!([{
+                "gasUsed": 43_512,
+                "reverted": true,
+                "revertReason": { "kind": "unknown", "selector": null, "data": "0x" },
+            }])
+        );
+
+        let full = serde_json::to_vec(&vec![result()]).unwrap();
+        assert!(
+            body.len() * 3 < full.len(),
+            "{} of {}",
+            body.len(),
+            full.len()
+        );
+    }
+
+    #[test]
+    fn test_every_field_matches_the_derived_impl() {
+        let full = serde_json::to_value(vec![result()]).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Value>(&pruned(&RESULT_FIELDS)).unwrap(),
+            full
+        );
+        // And every field that's serialized can be asked for
+        for name in full[0].as_object().unwrap().keys() {
+            assert!(RESULT_FIELDS.contains(&name.as_str()), "{}", name);
+        }
+
+        // Fields left out when empty stay out when asked for
+        let mut result = result();
+        result.revert_reason = None;
+        let value = serde_json::to_value(Pruned {
+            value: &result,
+            fields: Some(Fields::parse(["revertReason", "success"]).unwrap()),
+        })
+        .unwrap();
+        assert_eq!(value, json!({ "success": false }));
+    }
+
+    #[test]
+    fn test_whole_without_fields() {
+        let value = serde_json::to_value(Pruned {
+            value: vec![result()],
+            fields: None,
+        })
+        .unwrap();
+        assert_eq!(value, serde_json::to_value(vec![result()]).unwrap());
+    }
+
+    #[test]
+    fn test_msgpack_map_length() {
+        // The struct length is written up front in MessagePack, so it has to be exact
+        let fields = Fields::parse(["gasUsed", "revertReason", "rawTraces"]).unwrap();
+        let body = rmp_serde::to_vec_named(&Pruned {
+            value: &result(),
+            fields: Some(fields),
+        })
+        .unwrap();
+        let value: BTreeMap<String, IgnoredAny> = rmp_serde::from_slice(&body).unwrap();
+        assert_eq!(
+            value.keys().collect::<Vec<_>>(),
+            ["gasUsed", "revertReason"]
+        );
+    }
+}
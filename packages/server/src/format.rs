@@ -0,0 +1,334 @@
+use rocket::futures::{Stream, StreamExt};
+use rocket::http::{Accept, ContentType, Status};
+use rocket::request::Request;
+use rocket::response::stream::{stream, ReaderStream};
+use rocket::response::{self, Responder, Response};
+use serde::Serialize;
+use std::io::{self, BufWriter, Cursor, Write};
+use tokio::sync::mpsc;
+use tracing::{error, warn};
+
+// Bodies bigger than this are streamed as they're encoded rather than built in memory first
+const STREAM_ABOVE: usize = 1 << 20;
+// The size of each streamed write, and how many can wait for a slow client
+const CHUNK_BYTES: usize = 64 << 10;
+const BUFFERED_CHUNKS: usize = 4;
+
+/// Encodings an execution response can be sent in, picked from the `Accept` header.
+///
+/// Byte fields (`Bytes`, `Address`, `B256`, `U256`) are hex strings in JSON. MessagePack and CBOR
+/// tell serde they aren't human readable, so the same fields go out as native binary there.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+impl Format {
+    /// The client's preferred format, JSON when it has none or prefers something else.
+    pub fn negotiate(accept: Option<&Accept>) -> Format {
+        let Some(accept) = accept else {
+            return Format::Json;
+        };
+        let preferred = accept.preferred().media_type();
+        match (preferred.top().as_str(), preferred.sub().as_str()) {
+            ("application", "msgpack" | "x-msgpack" | "vnd.msgpack") => Format::MessagePack,
+            ("application", "cbor") => Format::Cbor,
+            _ => Format::Json,
+        }
+    }
+
+    pub fn content_type(self) -> ContentType {
+        match self {
+            Format::Json => ContentType::JSON,
+            Format::MessagePack => ContentType::MsgPack,
+            Format::Cbor => ContentType::new("application", "cbor"),
+        }
+    }
+
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>, eyre::Error> {
+        let mut body = Vec::new();
+        self.encode_to(value, &mut body)?;
+        Ok(body)
+    }
+
+    /// Encodes `value` into `writer` as it goes, without holding the whole encoding.
+    pub fn encode_to<T: Serialize>(
+        self,
+        value: &T,
+        mut writer: impl Write,
+    ) -> Result<(), eyre::Error> {
+        match self {
+            Format::Json => serde_json::to_writer(&mut writer, value)?,
+            // Named fields: skipped and flattened fields rule out positional arrays
+            Format::MessagePack => rmp_serde::encode::write_named(&mut writer, value)?,
+            Format::Cbor => ciborium::into_writer(value, &mut writer)?,
+        }
+        Ok(())
+    }
+}
+
+/// A response body encoded as the request's `Accept` header asks.
+///
+/// Small bodies are sent whole with a `Content-Length`. Bigger ones, like results carrying
+/// multi-megabyte traces, are encoded on the blocking pool and streamed a chunk at a time, so the
+/// memory they take stays about the same whatever their size.
+pub struct Negotiated<T>(pub T);
+
+impl<'r, T: Serialize + Send + 'static> Responder<'r, 'static> for Negotiated<T> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let format = Format::negotiate(req.accept());
+        let mut body = Capped(Vec::new());
+        let response = match format.encode_to(&self.0, &mut body) {
+            Ok(()) => {
+                let body = body.0;
+                Response::build()
+                    .sized_body(body.len(), Cursor::new(body))
+                    .finalize()
+            }
+            Err(_) if body.0.len() > STREAM_ABOVE => {
+                // Too big to build up front; what was encoded so far is thrown away
+                drop(body);
+                Response::build()
+                    .streamed_body(stream_encoded(format, self.0))
+                    .finalize()
+            }
+            Err(err) => {
+                error!(%err, ?format, "couldn't encode response");
+                return Err(Status::InternalServerError);
+            }
+        };
+        Response::build_from(response)
+            .header(format.content_type())
+            .raw_header("Vary", "Accept")
+            .ok()
+    }
+}
+
+// A buffer that fails writes once it holds more than `STREAM_ABOVE` bytes
+struct Capped(Vec<u8>);
+
+impl Write for Capped {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.0.len() > STREAM_ABOVE {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "body too big to buffer",
+            ));
+        }
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Sends writes on as chunks of the response body, none bigger than `CHUNK_BYTES`
+struct Chunks(mpsc::Sender<Vec<u8>>);
+
+impl Write for Chunks {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let chunk = &buf[..buf.len().min(CHUNK_BYTES)];
+        self.0
+            .blocking_send(chunk.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client went away"))?;
+        Ok(chunk.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn stream_encoded<T: Serialize + Send + 'static>(
+    format: Format,
+    value: T,
+) -> ReaderStream<impl Stream<Item = Cursor<Vec<u8>>> + Send> {
+    let (chunks, mut received) = mpsc::channel(BUFFERED_CHUNKS);
+    tokio::task::spawn_blocking(move || {
+        let mut writer = BufWriter::with_capacity(CHUNK_BYTES, Chunks(chunks));
+        let encoded = format
+            .encode_to(&value, &mut writer)
+            .and_then(|()| Ok(writer.flush()?));
+        // The status went out with the first chunk, so all that's left is to cut the body short
+        if let Err(err) = encoded {
+            warn!(%err, ?format, "couldn't finish streaming response");
+        }
+    });
+    ReaderStream::from(
+        stream! {
+            while let Some(chunk) = received.recv().await {
+                yield chunk;
+            }
+        }
+        .map(Cursor::new),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gas::{EventLog, ExecutionResult, ExitReason, TraceKind, TraceNode, TraceStatus};
+    use alloy_primitives::{address, bytes, Log, LogData, U256};
+    use rocket::http::Header;
+    use rocket::local::blocking::Client;
+    use rocket::{get, routes};
+    use std::collections::BTreeMap;
+
+    fn result() -> ExecutionResult {
+        let contract = address!("b2f9974c62815d3177079e150377915d9bc49c82");
+        let caller = address!("1000000000000000000000000000000000000000");
+        ExecutionResult {
+            exit_reason: ExitReason::Success,
+            success: true,
+            reverted: false,
+            revert_reason: None,
+            result: bytes!("00000000000000000000000000000000000000000000000000000000deadbeef"),
+            gas_used: 43_512,
+            gas_limit: None,
+            logs: EventLog::from_logs(vec![Log {
+                address: contract,
+                data: LogData::new_unchecked(vec![], bytes!("deadbeef")),
+            }]),
+            traces: Some(vec![TraceNode {
+                kind: TraceKind::Call,
+                from: caller,
+                to: contract,
+                value: U256::from(1),
+                gas_used: 22_512,
+                input: bytes!("6d4ce63c"),
+                output: bytes!("deadbeef"),
+                status: TraceStatus::Success,
+                output_length: None,
+                revert_reason: None,
+                children: vec![],
+                logs: vec![],
+            }]),
+            raw_traces: None,
+            labels: BTreeMap::from([(contract, "SimpleStorage".to_string())]),
+            trace_export: None,
+            flamegraph: None,
+            state_diff: None,
+            access_list: None,
+            consistency_check: None,
+            env: None,
+            hint: None,
+            journal: None,
+            transient_storage_accesses: None,
+            created: None,
+            paused_at: None,
+            internal_frames: None,
+            target_balance_before: None,
+            target_balance_after: None,
+            tx_validity: None,
+            attempts: None,
+            skipped: None,
+            call_index: None,
+            meta: None,
+        }
+    }
+
+    #[get("/result")]
+    fn result_route() -> Negotiated<ExecutionResult> {
+        Negotiated(result())
+    }
+
+    // Results with a 1MB output in their trace
+    #[get("/fat")]
+    fn fat_route() -> Negotiated<Vec<ExecutionResult>> {
+        Negotiated(fat_results())
+    }
+
+    fn fat_results() -> Vec<ExecutionResult> {
+        let mut result = result();
+        result.traces.as_mut().unwrap()[0].output = vec![0xab; 1 << 20].into();
+        vec![result; 3]
+    }
+
+    fn fetch(client: &Client, accept: Option<&'static str>) -> (Option<ContentType>, Vec<u8>) {
+        let mut req = client.get("/result");
+        if let Some(accept) = accept {
+            req = req.header(Header::new("Accept", accept));
+        }
+        let response = req.dispatch();
+        (response.content_type(), response.into_bytes().unwrap())
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let negotiate = |accept: &str| Format::negotiate(Some(&accept.parse().unwrap()));
+        assert_eq!(Format::negotiate(None), Format::Json);
+        assert_eq!(negotiate("application/msgpack"), Format::MessagePack);
+        assert_eq!(negotiate("application/x-msgpack"), Format::MessagePack);
+        assert_eq!(negotiate("application/cbor"), Format::Cbor);
+        assert_eq!(
+            negotiate("application/json;q=0.5, application/cbor"),
+            Format::Cbor
+        );
+        assert_eq!(negotiate("text/html, */*"), Format::Json);
+    }
+
+    #[test]
+    fn test_msgpack_round_trip() {
+        let client = Client::tracked(rocket::build().mount("/", routes![result_route])).unwrap();
+        let (content_type, body) = fetch(&client, Some("application/msgpack"));
+        assert_eq!(content_type, Some(ContentType::MsgPack));
+
+        let decoded: ExecutionResult = rmp_serde::from_slice(&body).unwrap();
+        assert_eq!(decoded, result());
+        // Bytes travel as binary, not hex
+        assert!(!body.windows(8).any(|w| w == b"deadbeef"));
+    }
+
+    #[test]
+    fn test_cbor_round_trip() {
+        let client = Client::tracked(rocket::build().mount("/", routes![result_route])).unwrap();
+        let (content_type, body) = fetch(&client, Some("application/cbor"));
+        assert_eq!(content_type, Some(Format::Cbor.content_type()));
+
+        let decoded: ExecutionResult = ciborium::from_reader(&body[..]).unwrap();
+        assert_eq!(decoded, result());
+        assert!(!body.windows(8).any(|w| w == b"deadbeef"));
+    }
+
+    #[test]
+    fn test_large_body_is_streamed() {
+        let client = Client::tracked(rocket::build().mount("/", routes![fat_route])).unwrap();
+        for accept in [
+            "application/json",
+            "application/msgpack",
+            "application/cbor",
+        ] {
+            let response = client
+                .get("/fat")
+                .header(Header::new("Accept", accept))
+                .dispatch();
+            assert_eq!(response.body().preset_size(), None, "{}", accept);
+            let body = response.into_bytes().unwrap();
+            let decoded: Vec<ExecutionResult> = match accept {
+                "application/json" => serde_json::from_slice(&body).unwrap(),
+                "application/msgpack" => rmp_serde::from_slice(&body).unwrap(),
+                _ => ciborium::from_reader(&body[..]).unwrap(),
+            };
+            assert_eq!(decoded, fat_results());
+        }
+
+        // Small bodies still go out whole
+        let client = Client::tracked(rocket::build().mount("/", routes![result_route])).unwrap();
+        let response = client.get("/result").dispatch();
+        assert!(response.body().preset_size().is_some());
+    }
+
+    #[test]
+    fn test_json_by_default() {
+        let client = Client::tracked(rocket::build().mount("/", routes![result_route])).unwrap();
+        let (content_type, body) = fetch(&client, None);
+        assert_eq!(content_type, Some(ContentType::JSON));
+
+        let decoded: ExecutionResult = serde_json::from_slice(&body).unwrap();
+        assert_eq!(decoded, result());
+    }
+}
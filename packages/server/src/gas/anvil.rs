@@ -0,0 +1,228 @@
+use alloy::providers::{Provider, ProviderBuilder};
+use alloy_primitives::U256;
+use once_cell::sync::Lazy;
+use std::fmt;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+use tokio::sync::{oneshot, Mutex};
+use tracing::info;
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What each of a request's `fundedAccounts` holds on its fork: 10,000 ETH.
+pub fn funded_balance() -> U256 {
+    U256::from(10).pow(U256::from(22))
+}
+
+#[derive(Debug)]
+pub enum AnvilError {
+    NotInstalled,
+    SpawnFailed(String),
+    NotReady(String),
+}
+
+impl fmt::Display for AnvilError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnvilError::NotInstalled => write!(
+                f,
+                "anvil is not installed: no RPC is configured, so a local anvil is required. \
+                 Install foundry (https://getfoundry.sh) or set ANVIL_BIN"
+            ),
+            AnvilError::SpawnFailed(err) => write!(f, "failed to spawn anvil: {}", err),
+            AnvilError::NotReady(err) => write!(f, "anvil did not become ready: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for AnvilError {}
+
+/// An anvil child process. The process is killed when the instance is dropped.
+pub struct AnvilInstance {
+    child: Child,
+    port: u16,
+}
+
+impl AnvilInstance {
+    /// Starts anvil on a port it picks itself, read back from the address it says it's
+    /// listening on, so nothing can take the port in between.
+    pub async fn spawn(bin: &Path) -> Result<Self, AnvilError> {
+        let mut child = Command::new(bin)
+            .args(["--port", "0", "--host", "127.0.0.1"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|err| AnvilError::SpawnFailed(err.to_string()))?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+        // Killed on drop from here on, port or not
+        let mut instance = AnvilInstance { child, port: 0 };
+
+        // After the port, anvil's output is its request log, drained so it never blocks on a
+        // full pipe
+        let (port_tx, port_rx) = oneshot::channel();
+        std::thread::spawn(move || {
+            let mut lines = BufReader::new(stdout).lines().map_while(Result::ok);
+            let port = lines.by_ref().find_map(|line| listening_port(&line));
+            let _ = port_tx.send(port);
+            lines.for_each(drop);
+        });
+        instance.port = match tokio::time::timeout(STARTUP_TIMEOUT, port_rx).await {
+            Ok(Ok(Some(port))) => port,
+            Ok(_) => {
+                return Err(AnvilError::NotReady(
+                    "exited before it was listening".to_string(),
+                ))
+            }
+            Err(_) => {
+                return Err(AnvilError::NotReady(
+                    "never said which port it was listening on".to_string(),
+                ))
+            }
+        };
+        Ok(instance)
+    }
+
+    pub fn endpoint(&self) -> String {
+        format!("http://127.0.0.1:{}", self.port)
+    }
+
+    pub fn is_running(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+
+    /// Polls `eth_chainId` until anvil answers or the timeout elapses.
+    pub async fn wait_until_ready(&mut self, timeout: Duration) -> Result<(), AnvilError> {
+        let url = url::Url::parse(&self.endpoint())
+            .map_err(|err| AnvilError::NotReady(err.to_string()))?;
+        let provider = ProviderBuilder::new().on_http(url);
+        let started = Instant::now();
+        loop {
+            if !self.is_running() {
+                return Err(AnvilError::NotReady("process exited".to_string()));
+            }
+            match provider.get_chain_id().await {
+                Ok(_) => return Ok(()),
+                Err(err) if started.elapsed() > timeout => {
+                    return Err(AnvilError::NotReady(err.to_string()))
+                }
+                Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+            }
+        }
+    }
+}
+
+// The port in anvil's `Listening on 127.0.0.1:8545`
+fn listening_port(line: &str) -> Option<u16> {
+    let (_, port) = line
+        .trim()
+        .strip_prefix("Listening on ")?
+        .rsplit_once(':')?;
+    port.parse().ok()
+}
+
+impl Drop for AnvilInstance {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// The server-wide anvil, started lazily on the first request that needs it
+static MANAGED: Lazy<Mutex<Option<AnvilInstance>>> = Lazy::new(|| Mutex::new(None));
+
+/// Returns the URL of the managed anvil, spawning (or respawning) it from `bin` if needed.
+pub async fn managed_endpoint(bin: Option<&Path>) -> Result<String, AnvilError> {
+    let mut managed = MANAGED.lock().await;
+    if let Some(instance) = managed.as_mut() {
+        if instance.is_running() {
+            return Ok(instance.endpoint());
+        }
+    }
+
+    let bin = bin.ok_or(AnvilError::NotInstalled)?;
+    let mut instance = AnvilInstance::spawn(bin).await?;
+    instance.wait_until_ready(STARTUP_TIMEOUT).await?;
+    info!(endpoint = %instance.endpoint(), "started managed anvil");
+
+    let endpoint = instance.endpoint();
+    *managed = Some(instance);
+    Ok(endpoint)
+}
+
+/// Stops the managed anvil, if one was started.
+pub async fn shutdown() {
+    MANAGED.lock().await.take();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use crate::gas::execute_calldatas_fork::{prepare_fork, ForkConfig};
+    use alloy_primitives::Address;
+    use revm::DatabaseRef;
+
+    // Held by the tests that use the managed anvil, since the lifecycle test stops it
+    static MANAGED_TESTS: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+    #[test]
+    fn test_listening_port() {
+        assert_eq!(listening_port("Listening on 127.0.0.1:8545"), Some(8545));
+        assert_eq!(
+            listening_port("Listening on 127.0.0.1:41234\n"),
+            Some(41234)
+        );
+        assert_eq!(listening_port("Private Keys"), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_managed_anvil_lifecycle() {
+        let _managed = MANAGED_TESTS.lock().await;
+        let Some(bin) = AppConfig::from_env().unwrap().anvil_bin else {
+            println!("anvil not found in PATH, skipping");
+            return;
+        };
+
+        let endpoint = managed_endpoint(Some(&bin)).await.unwrap();
+        // A second request reuses the running instance
+        assert_eq!(managed_endpoint(Some(&bin)).await.unwrap(), endpoint);
+
+        let provider = ProviderBuilder::new().on_http(endpoint.parse().unwrap());
+        assert_eq!(provider.get_chain_id().await.unwrap(), 31337);
+
+        shutdown().await;
+        assert!(provider.get_chain_id().await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_funded_accounts_stay_on_the_fork() {
+        let _managed = MANAGED_TESTS.lock().await;
+        let config = AppConfig::from_env().unwrap();
+        let Some(bin) = config.anvil_bin.clone() else {
+            println!("anvil not found in PATH, skipping");
+            return;
+        };
+        let account = Address::repeat_byte(0xfa);
+        let fork = ForkConfig {
+            mode: Some("anvil".to_string()),
+            funded_accounts: Some(vec![account]),
+            ..Default::default()
+        }
+        .resolve(&config)
+        .unwrap();
+        let engine = prepare_fork(&config, fork, None, false)
+            .await
+            .unwrap()
+            .build()
+            .unwrap();
+        let funded = engine.executor().backend().basic_ref(account).unwrap();
+        assert_eq!(funded.unwrap().balance, funded_balance());
+
+        // The next request's fork of the same anvil starts without it
+        let endpoint = managed_endpoint(Some(&bin)).await.unwrap();
+        let provider = ProviderBuilder::new().on_http(endpoint.parse().unwrap());
+        assert_eq!(provider.get_balance(account).await.unwrap(), U256::ZERO);
+    }
+}
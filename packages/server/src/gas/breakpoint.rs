@@ -0,0 +1,174 @@
+//! Source-level breakpoints: a call stopped at the first opcode compiled from one of the given
+//! lines, with the stack there and the storage it had written so far.
+//!
+//! Forge's executor only takes its own inspectors, so the call can't be halted as it runs. It
+//! runs to the end recording opcodes as the `debug` trace mode does, the stop is read off them as
+//! the journal is, and the call's state changes are thrown away instead of committed.
+
+use alloy_primitives::{Address, Bytes, U256};
+use forge::traces::{CallTraceArena, CallTraceNode};
+use revm::interpreter::opcode;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+use super::execute_calldatas_fork::ExecutionResult;
+use super::exit::ExitReason;
+use super::journal::{stack, SourceLocation};
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Breakpoint {
+    /// As the file was sent
+    pub file: String,
+    /// From 1
+    pub line: usize,
+}
+
+impl Breakpoint {
+    /// Whether code compiled from `source` stops here: code whose span starts on the line.
+    pub fn matches(&self, source: &SourceLocation) -> bool {
+        source.file == self.file && source.line == self.line
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PausedAt {
+    /// The breakpoint's
+    pub file: String,
+    pub line: usize,
+    /// The contract the call stopped in
+    #[schema(value_type = String)]
+    pub address: Address,
+    /// Of the opcode it stopped before
+    pub pc: usize,
+    /// The frame it stopped in, the call itself being 0
+    pub depth: usize,
+    /// How many opcodes the call had run, in every frame, counted as the journal counts them
+    pub step: usize,
+    /// Top first
+    #[schema(value_type = Vec<String>)]
+    pub stack: Vec<U256>,
+    /// The SSTOREs the call had run, in order. None of them were kept.
+    pub storage_writes_so_far: Vec<StorageWrite>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageWrite {
+    #[schema(value_type = String)]
+    pub address: Address,
+    #[schema(value_type = String)]
+    pub slot: U256,
+    #[schema(value_type = String)]
+    pub value: U256,
+}
+
+/// Of `sources`, a contract's opcodes by pc, those one of `breakpoints` stops at.
+pub(super) fn breakpoint_pcs(
+    breakpoints: &[Breakpoint],
+    sources: &BTreeMap<usize, SourceLocation>,
+) -> BTreeMap<usize, SourceLocation> {
+    sources
+        .iter()
+        .filter(|(_, source)| {
+            breakpoints
+                .iter()
+                .any(|breakpoint| breakpoint.matches(source))
+        })
+        .map(|(pc, source)| (*pc, source.clone()))
+        .collect()
+}
+
+/// Where the call in `arena` first reached one of `pcs` in the code at `address`, if it did.
+pub(super) fn paused_at(
+    arena: &CallTraceArena,
+    address: Address,
+    pcs: &BTreeMap<usize, SourceLocation>,
+) -> Option<PausedAt> {
+    let nodes = arena.nodes();
+    let mut walker = Walker {
+        nodes,
+        address,
+        pcs,
+        step: 0,
+        writes: Vec::new(),
+    };
+    nodes
+        .iter()
+        .filter(|node| node.parent.is_none())
+        .find_map(|root| walker.enter(root))
+}
+
+/// `result` as the partial result of a call stopped `at` a breakpoint: neither a success nor a
+/// revert, with nothing returned.
+pub(super) fn pause(result: &mut ExecutionResult, at: PausedAt) {
+    result.exit_reason = ExitReason::Paused;
+    result.success = false;
+    result.reverted = false;
+    result.result = Bytes::new();
+    result.revert_reason = None;
+    result.paused_at = Some(at);
+}
+
+struct Walker<'a> {
+    nodes: &'a [CallTraceNode],
+    address: Address,
+    pcs: &'a BTreeMap<usize, SourceLocation>,
+    // Opcodes run so far
+    step: usize,
+    writes: Vec<StorageWrite>,
+}
+
+impl Walker<'_> {
+    fn enter(&mut self, node: &CallTraceNode) -> Option<PausedAt> {
+        let trace = &node.trace;
+        let nodes = self.nodes;
+        let mut children = node.children.iter().map(|&child| &nodes[child]);
+        for step in &trace.steps {
+            let index = self.step;
+            self.step += 1;
+            let source = self
+                .pcs
+                .get(&step.pc)
+                .filter(|_| trace.address == self.address);
+            if let Some(source) = source {
+                return Some(PausedAt {
+                    file: source.file.clone(),
+                    line: source.line,
+                    address: trace.address,
+                    pc: step.pc,
+                    depth: trace.depth,
+                    step: index,
+                    stack: step.stack.iter().flatten().rev().copied().collect(),
+                    storage_writes_so_far: std::mem::take(&mut self.writes),
+                });
+            }
+            match step.op.get() {
+                opcode::SSTORE => {
+                    if let (Some(slot), Some(value)) = (stack(step, 0), stack(step, 1)) {
+                        self.writes.push(StorageWrite {
+                            address: trace.address,
+                            slot,
+                            value,
+                        });
+                    }
+                }
+                opcode::CALL
+                | opcode::CALLCODE
+                | opcode::DELEGATECALL
+                | opcode::STATICCALL
+                | opcode::CREATE
+                | opcode::CREATE2 => {
+                    // None when the call failed before it started, e.g. too deep
+                    if let Some(paused) = children.next().and_then(|child| self.enter(child)) {
+                        return Some(paused);
+                    }
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+}
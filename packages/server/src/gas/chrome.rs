@@ -0,0 +1,366 @@
+//! Call traces as Chrome's Trace Event Format, for chrome://tracing and ui.perfetto.dev. Gas is
+//! the time axis: an event's `ts` and `dur` are gas, which the viewers show as microseconds.
+
+use alloy_primitives::{Address, Bytes, U256};
+use forge::traces::{CallTraceArena, CallTraceNode};
+use revm::interpreter::opcode;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use super::trace::{TraceKind, TraceStatus};
+
+// Every call of a request is on the one process, a track (thread) each
+const PID: u64 = 1;
+
+/// A call frame, with the opcodes it ran when the trace mode recorded them.
+#[derive(Clone, Debug)]
+pub(super) struct Frame {
+    pub kind: TraceKind,
+    pub from: Address,
+    pub to: Address,
+    pub value: U256,
+    pub input: Bytes,
+    pub gas_used: u64,
+    pub gas_limit: u64,
+    pub status: TraceStatus,
+    pub steps: Vec<Step>,
+    pub children: Vec<Frame>,
+}
+
+#[derive(Clone, Debug)]
+pub(super) struct Step {
+    pub pc: usize,
+    pub op: &'static str,
+    /// Before the step ran
+    pub gas_remaining: u64,
+    pub gas_cost: u64,
+    /// A call or create, which the frame it starts stands in for
+    pub enters_frame: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TraceEvent {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cat: Option<String>,
+    /// `X` for a complete event, `M` for metadata naming the process or a track
+    pub ph: char,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ts: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dur: Option<u64>,
+    pub pid: u64,
+    pub tid: u64,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub args: Map<String, Value>,
+}
+
+/// A whole Trace Event Format document, in its JSON object form.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ChromeTrace {
+    pub trace_events: Vec<TraceEvent>,
+    #[serde(default)]
+    pub other_data: Map<String, Value>,
+}
+
+impl Frame {
+    /// The call trees in `arena`, one per top-level call.
+    pub fn from_arena(arena: &CallTraceArena) -> Vec<Frame> {
+        let nodes = arena.nodes();
+        nodes
+            .iter()
+            .filter(|node| node.parent.is_none())
+            .map(|node| Frame::build(nodes, node))
+            .collect()
+    }
+
+    fn build(nodes: &[CallTraceNode], node: &CallTraceNode) -> Frame {
+        let trace = &node.trace;
+        Frame {
+            kind: trace.kind.into(),
+            from: trace.caller,
+            to: trace.address,
+            value: trace.value,
+            input: trace.data.clone(),
+            gas_used: trace.gas_used,
+            gas_limit: trace.gas_limit,
+            status: trace.status.into(),
+            steps: trace
+                .steps
+                .iter()
+                .map(|step| Step {
+                    pc: step.pc,
+                    op: step.op.as_str(),
+                    gas_remaining: step.gas_remaining,
+                    gas_cost: step.gas_cost,
+                    enters_frame: matches!(
+                        step.op.get(),
+                        opcode::CALL
+                            | opcode::CALLCODE
+                            | opcode::DELEGATECALL
+                            | opcode::STATICCALL
+                            | opcode::CREATE
+                            | opcode::CREATE2
+                    ),
+                })
+                .collect(),
+            children: node
+                .children
+                .iter()
+                .map(|&child| Frame::build(nodes, &nodes[child]))
+                .collect(),
+        }
+    }
+
+    fn name(&self) -> String {
+        let kind = match self.kind {
+            TraceKind::Call => "CALL",
+            TraceKind::StaticCall => "STATICCALL",
+            TraceKind::CallCode => "CALLCODE",
+            TraceKind::DelegateCall => "DELEGATECALL",
+            TraceKind::AuthCall => "AUTHCALL",
+            TraceKind::Create => return "CREATE".to_string(),
+            TraceKind::Create2 => return "CREATE2".to_string(),
+        };
+        match self.selector() {
+            Some(selector) => format!("{} {}", kind, selector),
+            None => kind.to_string(),
+        }
+    }
+
+    fn selector(&self) -> Option<Bytes> {
+        let creates = matches!(self.kind, TraceKind::Create | TraceKind::Create2);
+        (!creates && self.input.len() >= 4).then(|| self.input.slice(..4))
+    }
+
+    // Adds this frame starting at `ts`, then its steps and the frames it called
+    fn push_events(&self, ts: u64, tid: u64, events: &mut Vec<TraceEvent>) {
+        let mut args = Map::new();
+        args.insert("from".into(), json!(self.from));
+        args.insert("to".into(), json!(self.to));
+        if let Some(selector) = self.selector() {
+            args.insert("selector".into(), json!(selector));
+        }
+        args.insert("value".into(), json!(self.value));
+        args.insert("gasUsed".into(), json!(self.gas_used));
+        args.insert("gasLimit".into(), json!(self.gas_limit));
+        args.insert("status".into(), json!(self.status));
+        events.push(TraceEvent {
+            name: self.name(),
+            cat: Some("call".into()),
+            ph: 'X',
+            ts: Some(ts),
+            dur: Some(self.gas_used),
+            pid: PID,
+            tid,
+            args,
+        });
+
+        // A step's place is the gas the frame had spent when it ran
+        let at = |step: &Step| ts + self.gas_limit.saturating_sub(step.gas_remaining);
+        for step in self.steps.iter().filter(|step| !step.enters_frame) {
+            events.push(TraceEvent {
+                name: step.op.to_string(),
+                cat: Some("step".into()),
+                ph: 'X',
+                ts: Some(at(step)),
+                dur: Some(step.gas_cost),
+                pid: PID,
+                tid,
+                args: Map::from_iter([("pc".to_string(), json!(step.pc))]),
+            });
+        }
+
+        // Frames start where the step that made them ran. Without steps to go by, or when they
+        // don't line up (a call that failed before it started), they're packed one after another.
+        let calls: Vec<_> = self
+            .steps
+            .iter()
+            .filter(|step| step.enters_frame)
+            .map(at)
+            .collect();
+        let mut next = ts;
+        for (i, child) in self.children.iter().enumerate() {
+            let start = if calls.len() == self.children.len() {
+                calls[i]
+            } else {
+                next
+            };
+            child.push_events(start, tid, events);
+            next = start + child.gas_used;
+        }
+    }
+}
+
+/// The frames of call `index` of a request on a track of their own, starting `offset` gas in so
+/// the calls before it fit ahead. The events of every call's document together make up the whole
+/// request.
+pub(super) fn document(frames: &[Frame], index: usize, offset: u64) -> ChromeTrace {
+    let tid = index as u64;
+    let metadata = |name: &str, label: String| TraceEvent {
+        name: name.to_string(),
+        cat: None,
+        ph: 'M',
+        ts: None,
+        dur: None,
+        pid: PID,
+        tid,
+        args: Map::from_iter([("name".to_string(), json!(label))]),
+    };
+    let mut trace_events = vec![
+        metadata("process_name", "evm-repl".to_string()),
+        metadata("thread_name", format!("call {}", index)),
+    ];
+    let mut ts = offset;
+    for frame in frames {
+        frame.push_events(ts, tid, &mut trace_events);
+        ts += frame.gas_used;
+    }
+    ChromeTrace {
+        trace_events,
+        other_data: Map::from_iter([("timeAxis".to_string(), json!("gas"))]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::{address, bytes};
+    use std::collections::HashSet;
+
+    // A call that runs two opcodes around a static call to another contract, which reverts
+    fn nested() -> Frame {
+        Frame {
+            kind: TraceKind::Call,
+            from: address!("1000000000000000000000000000000000000001"),
+            to: address!("2000000000000000000000000000000000000002"),
+            value: U256::ZERO,
+            input: bytes!(
+                "12345678000000000000000000000000000000000000000000000000000000000000002a"
+            ),
+            gas_used: 30_000,
+            gas_limit: 100_000,
+            status: TraceStatus::Success,
+            steps: vec![
+                Step {
+                    pc: 0,
+                    op: "PUSH1",
+                    gas_remaining: 100_000,
+                    gas_cost: 3,
+                    enters_frame: false,
+                },
+                Step {
+                    pc: 2,
+                    op: "STATICCALL",
+                    gas_remaining: 99_997,
+                    gas_cost: 2_600,
+                    enters_frame: true,
+                },
+                Step {
+                    pc: 3,
+                    op: "SSTORE",
+                    gas_remaining: 90_000,
+                    gas_cost: 20_000,
+                    enters_frame: false,
+                },
+            ],
+            children: vec![Frame {
+                kind: TraceKind::StaticCall,
+                from: address!("2000000000000000000000000000000000000002"),
+                to: address!("3000000000000000000000000000000000000003"),
+                value: U256::ZERO,
+                input: bytes!("87654321"),
+                gas_used: 5_000,
+                gas_limit: 60_000,
+                status: TraceStatus::Revert,
+                steps: vec![],
+                children: vec![],
+            }],
+        }
+    }
+
+    // What the Trace Event Format asks of the events used here, and that complete events on a
+    // track nest rather than overlap
+    fn check_schema(document: &Value) {
+        let events = document["traceEvents"]
+            .as_array()
+            .expect("traceEvents array");
+        let mut named = HashSet::new();
+        let mut spans: Vec<(u64, u64, u64)> = Vec::new();
+        for event in events {
+            assert!(event["name"].is_string(), "{}", event);
+            assert!(event["pid"].is_u64() && event["tid"].is_u64(), "{}", event);
+            match event["ph"].as_str() {
+                Some("M") => {
+                    assert!(event["args"]["name"].is_string(), "{}", event);
+                    named.insert(event["tid"].as_u64());
+                }
+                Some("X") => {
+                    assert!(event["cat"].is_string(), "{}", event);
+                    let (ts, dur) = (event["ts"].as_u64(), event["dur"].as_u64());
+                    let (Some(ts), Some(dur)) = (ts, dur) else {
+                        panic!("complete event without ts and dur: {}", event);
+                    };
+                    spans.push((event["tid"].as_u64().unwrap(), ts, ts + dur));
+                }
+                _ => panic!("unexpected phase: {}", event),
+            }
+        }
+
+        spans.sort_by_key(|&(tid, start, end)| (tid, start, std::cmp::Reverse(end)));
+        let mut open: Vec<(u64, u64)> = Vec::new();
+        for (tid, start, end) in spans {
+            assert!(named.contains(&Some(tid)), "track {} isn't named", tid);
+            while open
+                .last()
+                .is_some_and(|&(open_tid, open_end)| open_tid != tid || open_end <= start)
+            {
+                open.pop();
+            }
+            if let Some(&(_, parent_end)) = open.last() {
+                assert!(
+                    end <= parent_end,
+                    "[{}, {}) overlaps its parent",
+                    start,
+                    end
+                );
+            }
+            open.push((tid, end));
+        }
+    }
+
+    #[test]
+    fn test_nested_call_matches_golden() {
+        // The third call of a request, after calls that used 50,000 gas
+        let document = serde_json::to_value(document(&[nested()], 2, 50_000)).unwrap();
+        check_schema(&document);
+        let golden: Value =
+            serde_json::from_str(include_str!("fixtures/chrome_trace.json")).unwrap();
+        assert_eq!(document, golden);
+    }
+
+    #[test]
+    fn test_frames_without_steps_are_packed() {
+        let mut frame = nested();
+        frame.steps.clear();
+        frame.children.push(frame.children[0].clone());
+        let document = document(&[frame], 0, 0);
+        check_schema(&serde_json::to_value(&document).unwrap());
+
+        let calls: Vec<_> = document
+            .trace_events
+            .iter()
+            .filter(|event| event.cat.as_deref() == Some("call"))
+            .map(|event| (event.ts, event.dur))
+            .collect();
+        assert_eq!(
+            calls,
+            [
+                (Some(0), Some(30_000)),
+                (Some(0), Some(5_000)),
+                (Some(5_000), Some(5_000)),
+            ]
+        );
+    }
+}
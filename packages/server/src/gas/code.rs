@@ -0,0 +1,277 @@
+use alloy_primitives::{Bytes, B256};
+use once_cell::sync::Lazy;
+use revm::interpreter::opcode;
+use revm_primitives::{AccountInfo, Bytecode};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+use crate::caches::{Cache, CacheStats};
+
+// Room for over a thousand full-size contracts
+const MAX_BYTES: usize = 32 << 20;
+
+static CODE: Lazy<CodeCache> = Lazy::new(|| CodeCache::new(MAX_BYTES));
+
+struct Entry {
+    code: Bytecode,
+    hash: B256,
+    stored_at: Instant,
+    used_at: Instant,
+}
+
+/// Bytecode put into executors, by its bytes, so code inserted again (by every scenario of a
+/// batch, or every request trying out the same contract) isn't hashed again. Clones share the
+/// same entries.
+#[derive(Clone)]
+pub struct CodeCache {
+    entries: Arc<Mutex<HashMap<Bytes, Entry>>>,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+    max_bytes: usize,
+}
+
+/// The cache the gas module inserts code through, for registering with `Caches`.
+pub fn cache() -> CodeCache {
+    CODE.clone()
+}
+
+/// An empty account holding `code`.
+pub(super) fn account(code: Bytes) -> AccountInfo {
+    CODE.account(code)
+}
+
+impl CodeCache {
+    pub fn new(max_bytes: usize) -> Self {
+        CodeCache {
+            entries: Arc::default(),
+            hits: Arc::default(),
+            misses: Arc::default(),
+            max_bytes,
+        }
+    }
+
+    /// An empty account holding `code`, hashed only the first time it's seen.
+    pub fn account(&self, code: Bytes) -> AccountInfo {
+        let (code, code_hash) = self.get(code);
+        AccountInfo {
+            code_hash,
+            code: Some(code),
+            ..Default::default()
+        }
+    }
+
+    fn get(&self, bytes: Bytes) -> (Bytecode, B256) {
+        if let Some(entry) = self.lock().get_mut(&bytes) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            entry.used_at = Instant::now();
+            return (entry.code.clone(), entry.hash);
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        // Outside the lock, so other code can be looked up meanwhile
+        let code = Bytecode::new_raw(bytes.clone());
+        let hash = code.hash_slow();
+        if bytes.len() > self.max_bytes {
+            return (code, hash);
+        }
+
+        let mut entries = self.lock();
+        let mut held: usize = entries.keys().map(Bytes::len).sum();
+        // Evict the least recently used to make room
+        while held + bytes.len() > self.max_bytes {
+            let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.used_at)
+                .map(|(bytes, _)| bytes.clone())
+            else {
+                break;
+            };
+            entries.remove(&oldest);
+            held -= oldest.len();
+        }
+        entries.insert(
+            bytes,
+            Entry {
+                code: code.clone(),
+                hash,
+                stored_at: Instant::now(),
+                used_at: Instant::now(),
+            },
+        );
+        (code, hash)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<Bytes, Entry>> {
+        self.entries.lock().unwrap()
+    }
+}
+
+impl Cache for CodeCache {
+    fn name(&self) -> &'static str {
+        "code"
+    }
+
+    fn stats(&self) -> CacheStats {
+        let entries = self.lock();
+        CacheStats {
+            entries: entries.len(),
+            bytes: entries.keys().map(Bytes::len).sum(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            oldest_entry_age_secs: entries
+                .values()
+                .map(|entry| entry.stored_at.elapsed().as_secs())
+                .max(),
+        }
+    }
+
+    fn clear(&self) -> usize {
+        let mut entries = self.lock();
+        let cleared = entries.len();
+        entries.clear();
+        cleared
+    }
+}
+
+/// Whether `code` looks like creation code rather than the runtime code it deploys: it copies a
+/// part of itself from past the copying into memory and returns it straight away, as
+/// constructors do, and either starts by setting up solc's free memory pointer or does the
+/// copying in its first few bytes, as minimal and Vyper constructors do. Runtime code that
+/// serves data appended to it can look the same.
+pub fn looks_like_creation_code(code: &[u8]) -> bool {
+    let instructions: Vec<_> = instructions(code).collect();
+    instructions
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, op, _))| *op == opcode::CODECOPY)
+        .any(|(i, &(pc, _, _))| {
+            // Its offset and size, among the values pushed just before
+            let pushed: Vec<usize> = instructions[i.saturating_sub(6)..i]
+                .iter()
+                .filter_map(|(_, _, value)| *value)
+                .collect();
+            let returned = instructions[i + 1..]
+                .iter()
+                .take(64)
+                .take_while(|(_, op, _)| {
+                    !matches!(
+                        *op,
+                        opcode::STOP
+                            | opcode::JUMP
+                            | opcode::JUMPI
+                            | opcode::REVERT
+                            | opcode::INVALID
+                            | opcode::SELFDESTRUCT
+                    )
+                })
+                .find(|(_, op, _)| *op == opcode::RETURN);
+            let Some(&(returned_at, _, _)) = returned else {
+                return false;
+            };
+            let copies_suffix = pushed.iter().enumerate().any(|(j, &offset)| {
+                offset > returned_at
+                    && offset < code.len()
+                    && pushed.iter().enumerate().any(|(k, &size)| {
+                        j != k && size > 0 && offset.saturating_add(size) <= code.len()
+                    })
+            });
+            copies_suffix && (sets_free_memory_pointer(code) || pc < 64)
+        })
+}
+
+// Each instruction of `code` as its pc, its opcode and, for pushes that fit a `usize`, the value
+// it pushes
+fn instructions(code: &[u8]) -> impl Iterator<Item = (usize, u8, Option<usize>)> + '_ {
+    let mut at = 0;
+    std::iter::from_fn(move || {
+        let pc = at;
+        let op = *code.get(pc)?;
+        let size = match op {
+            opcode::PUSH1..=opcode::PUSH32 => (op - opcode::PUSH0) as usize,
+            _ => 0,
+        };
+        at += 1 + size;
+        let value = match op {
+            opcode::PUSH0 => Some(0),
+            opcode::PUSH1..=opcode::PUSH32 => code
+                .get(pc + 1..pc + 1 + size)
+                .filter(|bytes| bytes.len() <= std::mem::size_of::<usize>())
+                .map(|bytes| bytes.iter().fold(0, |value, b| value << 8 | *b as usize)),
+            _ => None,
+        };
+        Some((pc, op, value))
+    })
+}
+
+// `mstore(0x40, 0x80)`, or higher with immutables, which solc's code starts with
+fn sets_free_memory_pointer(code: &[u8]) -> bool {
+    match code {
+        [opcode::PUSH1, _, opcode::PUSH1, 0x40, opcode::MSTORE, ..] => true,
+        [opcode::PUSH2, _, _, opcode::PUSH1, 0x40, opcode::MSTORE, ..] => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::keccak256;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_identical_code_hashed_once() {
+        let cache = CodeCache::new(1 << 20);
+        let blob = Bytes::from(vec![0x5b; 24 << 10]);
+        let first = cache.account(blob.clone());
+        // The same code from another request, in its own allocation
+        let second = cache.account(Bytes::copy_from_slice(&blob));
+
+        assert_eq!(first.code_hash, keccak256(&blob));
+        assert_eq!(second.code_hash, first.code_hash);
+        assert_eq!(second.code, first.code);
+        let stats = cache.stats();
+        // Only the first was hashed
+        assert_eq!((stats.misses, stats.hits), (1, 1));
+        assert_eq!((stats.entries, stats.bytes), (1, 24 << 10));
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let cache = CodeCache::new(64);
+        let code = |byte: u8| Bytes::from(vec![byte; 32]);
+        cache.account(code(1));
+        cache.account(code(2));
+        cache.account(code(1));
+        cache.account(code(3));
+        assert_eq!(cache.stats().entries, 2);
+
+        // 1 was used more recently than 2
+        cache.account(code(1));
+        assert_eq!(cache.stats().misses, 3);
+        cache.account(code(2));
+        assert_eq!(cache.stats().misses, 4);
+
+        // Too big to keep, but still hashed
+        let big = Bytes::from(vec![0; 65]);
+        assert_eq!(cache.account(big.clone()).code_hash, keccak256(&big));
+        assert!(cache.stats().bytes <= 64);
+    }
+
+    #[test]
+    fn test_creation_code_corpus() {
+        // Runtime code from the demo chain, solc and well-known deployments, and creation code
+        // from solc and those deployments, with the demo chain's contracts wrapped the way solc
+        // and Vyper wrap them
+        let corpus: BTreeMap<String, BTreeMap<String, Bytes>> =
+            serde_json::from_str(include_str!("fixtures/bytecode_corpus.json")).unwrap();
+        assert!(corpus["runtime"].len() >= 12);
+        for (name, code) in &corpus["runtime"] {
+            assert!(!looks_like_creation_code(code), "{} is runtime code", name);
+        }
+        for (name, code) in &corpus["creation"] {
+            assert!(looks_like_creation_code(code), "{} is creation code", name);
+        }
+        assert!(!looks_like_creation_code(&[]));
+    }
+}
@@ -0,0 +1,547 @@
+//! Cross-checking calls against the node a fork comes from: the same call at the same block,
+//! through `debug_traceCall`, or `eth_call` where the node doesn't serve the debug namespace.
+
+use alloy_primitives::{Address, Bytes, U256};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::time::Duration;
+use utoipa::ToSchema;
+
+use super::ens::NameOrAddress;
+use super::execute_calldatas_fork::{Call, ExecutionResult, ForkContext};
+
+/// How long the node gets to answer each request
+const CHECK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How far apart, in percent, the node's gas and ours can be and still agree. The two differ by
+/// a little where clients price the same thing differently, e.g. warm and cold accesses around
+/// precompiles.
+const GAS_TOLERANCE_PERCENT: u64 = 1;
+
+/// What the node made of a call, next to what we did.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsistencyCheck {
+    pub status: CheckStatus,
+    /// `debug_traceCall` or `eth_call`. Missing when the node wasn't asked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    /// Why the call was skipped or the node couldn't answer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Where the node's outcome differs from ours
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub divergences: Vec<Divergence>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum CheckStatus {
+    Agree,
+    Diverged,
+    /// The call can't be compared, e.g. it runs on injected code
+    Skipped,
+    /// The node couldn't be asked, or didn't answer
+    Unavailable,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct Divergence {
+    /// `success`, `result` or `gasUsed`
+    pub field: String,
+    #[schema(value_type = Object)]
+    pub simulated: Value,
+    #[schema(value_type = Object)]
+    pub node: Value,
+}
+
+impl ConsistencyCheck {
+    pub(super) fn skipped(reason: impl Into<String>) -> Self {
+        ConsistencyCheck {
+            status: CheckStatus::Skipped,
+            method: None,
+            reason: Some(reason.into()),
+            divergences: Vec::new(),
+        }
+    }
+
+    fn unavailable(method: Option<&str>, reason: impl Into<String>) -> Self {
+        ConsistencyCheck {
+            status: CheckStatus::Unavailable,
+            method: method.map(str::to_string),
+            reason: Some(reason.into()),
+            divergences: Vec::new(),
+        }
+    }
+}
+
+/// Checks each of `results` against `rpc`, the node `context`'s fork was taken from. Only calls
+/// to the code actually at `address`, on the fork's state as the node has it, can be compared:
+/// when `bytecode` was injected, or earlier calls committed changes, the node would run
+/// something else, so those calls are skipped.
+pub(super) async fn check_calls(
+    rpc: &str,
+    context: &ForkContext,
+    address: Address,
+    bytecode: &Bytes,
+    calls: &[Call],
+    results: &mut [ExecutionResult],
+) {
+    let node = Node::new(rpc, context.block_number);
+    let on_chain = match node
+        .request("eth_getCode", json!([address, node.block]))
+        .await
+    {
+        Ok(Ok(code)) => Ok(serde_json::from_value::<Bytes>(code).ok()),
+        Ok(Err(error)) => Err(error.message),
+        Err(err) => Err(err.to_string()),
+    };
+    let not_checked = match on_chain {
+        Err(reason) => Some(ConsistencyCheck::unavailable(None, reason)),
+        Ok(code) if code.as_ref() != Some(bytecode) => Some(ConsistencyCheck::skipped(format!(
+            "bytecode isn't the code at {} on chain, so the node would run something else",
+            address
+        ))),
+        Ok(_) => None,
+    };
+    if let Some(check) = not_checked {
+        for result in results.iter_mut() {
+            result.consistency_check = Some(check.clone());
+        }
+        return;
+    }
+
+    let mut committed = false;
+    for (call, result) in calls.iter().zip(results.iter_mut()) {
+        let from = result
+            .traces
+            .as_ref()
+            .and_then(|traces| traces.first())
+            .map(|node| node.from)
+            .or(match call.caller {
+                Some(NameOrAddress::Address(address)) => Some(address),
+                _ => None,
+            });
+        let check = match from {
+            _ if result.skipped.is_some() => ConsistencyCheck::skipped("never ran"),
+            _ if committed => {
+                ConsistencyCheck::skipped("runs on state earlier calls in the request changed")
+            }
+            None => ConsistencyCheck::skipped(
+                "the caller is an ENS name and traces are off, so it isn't known",
+            ),
+            Some(from) => {
+                let tx = NodeCall {
+                    from,
+                    to: address,
+                    input: call.calldata.clone(),
+                    value: call.value,
+                    gas: result.gas_limit.unwrap_or(context.gas_limit),
+                };
+                node.check(&tx, result).await
+            }
+        };
+        result.consistency_check = Some(check);
+        committed |= !call.independent;
+    }
+}
+
+// A call as a node is asked to run it
+pub(super) struct NodeCall {
+    pub from: Address,
+    pub to: Address,
+    pub input: Bytes,
+    pub value: U256,
+    pub gas: u64,
+}
+
+impl NodeCall {
+    fn to_json(&self) -> Value {
+        json!({
+            "from": self.from,
+            "to": self.to,
+            "data": self.input,
+            "value": self.value,
+            "gas": format!("{:#x}", self.gas),
+        })
+    }
+}
+
+// What a node made of a call
+struct Outcome {
+    success: bool,
+    output: Bytes,
+    /// Not from `eth_call`
+    gas_used: Option<u64>,
+}
+
+// A JSON-RPC error, as opposed to not getting an answer at all
+pub(super) struct RpcError {
+    code: i64,
+    message: String,
+    data: Option<Value>,
+}
+
+pub(super) struct Node {
+    client: reqwest::Client,
+    url: String,
+    block: String,
+}
+
+impl Node {
+    pub(super) fn new(url: &str, block_number: u64) -> Self {
+        Node {
+            client: reqwest::Client::builder()
+                .timeout(CHECK_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            url: url.to_string(),
+            block: format!("{:#x}", block_number),
+        }
+    }
+
+    /// `result` compared with what the node makes of `tx`.
+    pub(super) async fn check(&self, tx: &NodeCall, result: &ExecutionResult) -> ConsistencyCheck {
+        let (method, outcome) = match self.trace_call(tx).await {
+            Ok(Ok(outcome)) => ("debug_traceCall", outcome),
+            // Most likely no debug namespace; whatever it was, eth_call can still be compared
+            Ok(Err(_)) => match self.call(tx).await {
+                Ok(outcome) => ("eth_call", outcome),
+                Err(err) => {
+                    return ConsistencyCheck::unavailable(Some("eth_call"), err.to_string())
+                }
+            },
+            Err(err) => {
+                return ConsistencyCheck::unavailable(Some("debug_traceCall"), err.to_string())
+            }
+        };
+
+        let mut divergences = Vec::new();
+        if outcome.success != result.success {
+            divergences.push(Divergence {
+                field: "success".to_string(),
+                simulated: json!(result.success),
+                node: json!(outcome.success),
+            });
+        }
+        if outcome.output != result.result {
+            divergences.push(Divergence {
+                field: "result".to_string(),
+                simulated: json!(result.result),
+                node: json!(outcome.output),
+            });
+        }
+        if let Some(gas_used) = outcome.gas_used {
+            if !gas_agrees(result.gas_used, gas_used) {
+                divergences.push(Divergence {
+                    field: "gasUsed".to_string(),
+                    simulated: json!(result.gas_used),
+                    node: json!(gas_used),
+                });
+            }
+        }
+        ConsistencyCheck {
+            status: if divergences.is_empty() {
+                CheckStatus::Agree
+            } else {
+                CheckStatus::Diverged
+            },
+            method: Some(method.to_string()),
+            reason: None,
+            divergences,
+        }
+    }
+
+    // The top-level frame of geth's call tracer, which every client with the debug namespace
+    // implements
+    async fn trace_call(&self, tx: &NodeCall) -> Result<Result<Outcome, RpcError>, eyre::Error> {
+        let params = json!([tx.to_json(), self.block, { "tracer": "callTracer" }]);
+        let frame = match self.request("debug_traceCall", params).await? {
+            Ok(frame) => frame,
+            Err(error) => return Ok(Err(error)),
+        };
+        let output = match frame.get("output") {
+            Some(output) => serde_json::from_value(output.clone())?,
+            None => Bytes::new(),
+        };
+        let gas_used = frame
+            .get("gasUsed")
+            .and_then(Value::as_str)
+            .and_then(|gas| u64::from_str_radix(gas.trim_start_matches("0x"), 16).ok())
+            .ok_or_else(|| eyre::eyre!("debug_traceCall returned a frame without gasUsed"))?;
+        Ok(Ok(Outcome {
+            success: frame.get("error").is_none(),
+            output,
+            gas_used: Some(gas_used),
+        }))
+    }
+
+    // A failed call comes back as an error, with the revert data when there is any
+    async fn call(&self, tx: &NodeCall) -> Result<Outcome, eyre::Error> {
+        let params = json!([tx.to_json(), self.block]);
+        match self.request("eth_call", params).await? {
+            Ok(output) => Ok(Outcome {
+                success: true,
+                output: serde_json::from_value(output)?,
+                gas_used: None,
+            }),
+            Err(error) if error.code == 3 || error.message.contains("revert") => Ok(Outcome {
+                success: false,
+                output: error
+                    .data
+                    .and_then(|data| serde_json::from_value(data).ok())
+                    .unwrap_or_default(),
+                gas_used: None,
+            }),
+            Err(error) => Err(eyre::eyre!(
+                "eth_call failed ({}): {}",
+                error.code,
+                error.message
+            )),
+        }
+    }
+
+    pub(super) async fn request(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> Result<Result<Value, RpcError>, eyre::Error> {
+        let body = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        let response: Value = self
+            .client
+            .post(&self.url)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if let Some(error) = response.get("error") {
+            return Ok(Err(RpcError {
+                code: error["code"].as_i64().unwrap_or_default(),
+                message: error["message"].as_str().unwrap_or_default().to_string(),
+                data: error.get("data").cloned(),
+            }));
+        }
+        Ok(Ok(response["result"].clone()))
+    }
+}
+
+fn gas_agrees(simulated: u64, node: u64) -> bool {
+    simulated.abs_diff(node) * 100 <= simulated.max(node) * GAS_TOLERANCE_PERCENT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gas::recorded_rpc::serve;
+    use crate::gas::{EventLog, ExitReason};
+    use alloy_primitives::{address, bytes};
+    use std::collections::BTreeMap;
+    use std::net::TcpListener;
+
+    const CALLER: Address = address!("1000000000000000000000000000000000000000");
+    const CONTRACT: Address = address!("b2f9974c62815d3177079e150377915d9bc49c82");
+    const CODE: Bytes = bytes!("60005460005260206000f3");
+    const FORTY_TWO: Bytes =
+        bytes!("000000000000000000000000000000000000000000000000000000000000002a");
+
+    // Answers each method with what `answers` has for it: `{"result": ...}` or `{"error": ...}`
+    fn mock_rpc(answers: Value) -> String {
+        serve(move |request| {
+            let method = request["method"].as_str().unwrap_or_default();
+            answers.get(method).cloned().unwrap_or_else(
+                || json!({ "error": { "code": -32601, "message": "method not found" } }),
+            )
+        })
+    }
+
+    fn context() -> ForkContext {
+        ForkContext {
+            chain_id: 8453,
+            block_number: 16,
+            gas_limit: 30_000_000,
+            hardfork: "latest".to_string(),
+            confirmation_lag: 0,
+            rpc_diagnostics: None,
+        }
+    }
+
+    fn call(independent: bool) -> Call {
+        Call {
+            calldata: bytes!("6d4ce63c"),
+            value: U256::ZERO,
+            caller: Some(CALLER.into()),
+            independent,
+            signature: None,
+            args: None,
+            meta: None,
+        }
+    }
+
+    // get() returning 42
+    fn result() -> ExecutionResult {
+        ExecutionResult {
+            exit_reason: ExitReason::Success,
+            success: true,
+            reverted: false,
+            result: FORTY_TWO,
+            revert_reason: None,
+            gas_used: 23_408,
+            gas_limit: Some(30_000_000),
+            logs: EventLog::from_logs(vec![]),
+            traces: None,
+            raw_traces: None,
+            labels: BTreeMap::new(),
+            trace_export: None,
+            flamegraph: None,
+            state_diff: None,
+            access_list: None,
+            consistency_check: None,
+            env: None,
+            hint: None,
+            journal: None,
+            transient_storage_accesses: None,
+            created: None,
+            paused_at: None,
+            internal_frames: None,
+            target_balance_before: None,
+            target_balance_after: None,
+            tx_validity: None,
+            attempts: None,
+            skipped: None,
+            call_index: None,
+            meta: None,
+        }
+    }
+
+    fn tx() -> NodeCall {
+        NodeCall {
+            from: CALLER,
+            to: CONTRACT,
+            input: bytes!("6d4ce63c"),
+            value: U256::ZERO,
+            gas: 30_000_000,
+        }
+    }
+
+    fn frame(output: &Bytes, gas_used: u64, error: Option<&str>) -> Value {
+        let mut frame = json!({
+            "type": "CALL",
+            "from": CALLER,
+            "to": CONTRACT,
+            "input": "0x6d4ce63c",
+            "output": output,
+            "gas": "0x1c9c380",
+            "gasUsed": format!("{:#x}", gas_used),
+        });
+        if let Some(error) = error {
+            frame["error"] = json!(error);
+        }
+        json!({ "result": frame })
+    }
+
+    #[tokio::test]
+    async fn test_node_agrees() {
+        // A little off on gas is still the same
+        let rpc = mock_rpc(json!({ "debug_traceCall": frame(&FORTY_TWO, 23_500, None) }));
+        let check = Node::new(&rpc, 16).check(&tx(), &result()).await;
+        assert_eq!(check.status, CheckStatus::Agree);
+        assert_eq!(check.method.as_deref(), Some("debug_traceCall"));
+        assert!(check.divergences.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_node_disagrees() {
+        let revert = bytes!("08c379a0");
+        let rpc = mock_rpc(json!({
+            "debug_traceCall": frame(&revert, 30_000, Some("execution reverted")),
+        }));
+        let check = Node::new(&rpc, 16).check(&tx(), &result()).await;
+        assert_eq!(check.status, CheckStatus::Diverged);
+        let fields: Vec<_> = check
+            .divergences
+            .iter()
+            .map(|divergence| divergence.field.as_str())
+            .collect();
+        assert_eq!(fields, ["success", "result", "gasUsed"]);
+        assert_eq!(check.divergences[2].simulated, json!(23_408));
+        assert_eq!(check.divergences[2].node, json!(30_000));
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_eth_call() {
+        // No debug namespace: the mock answers debug_traceCall with method not found
+        let rpc = mock_rpc(json!({ "eth_call": { "result": FORTY_TWO } }));
+        let check = Node::new(&rpc, 16).check(&tx(), &result()).await;
+        assert_eq!(check.status, CheckStatus::Agree);
+        assert_eq!(check.method.as_deref(), Some("eth_call"));
+
+        // A revert comes back as an error carrying the revert data
+        let rpc = mock_rpc(json!({
+            "eth_call": {
+                "error": { "code": 3, "message": "execution reverted", "data": "0x08c379a0" },
+            },
+        }));
+        let check = Node::new(&rpc, 16).check(&tx(), &result()).await;
+        assert_eq!(check.status, CheckStatus::Diverged);
+        assert_eq!(check.divergences[0].field, "success");
+        assert_eq!(check.divergences[1].node, json!("0x08c379a0"));
+        // eth_call says nothing about gas
+        assert_eq!(check.divergences.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_node_unreachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let rpc = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let check = Node::new(&rpc, 16).check(&tx(), &result()).await;
+        assert_eq!(check.status, CheckStatus::Unavailable);
+        assert!(check.reason.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_only_unchanged_on_chain_code_is_checked() {
+        let rpc = mock_rpc(json!({
+            "eth_getCode": { "result": CODE },
+            "debug_traceCall": frame(&FORTY_TWO, 23_408, None),
+        }));
+        let calls = [call(true), call(false), call(false)];
+        let mut results = vec![result(); 3];
+        check_calls(&rpc, &context(), CONTRACT, &CODE, &calls, &mut results).await;
+        let statuses: Vec<_> = results
+            .iter()
+            .map(|result| result.consistency_check.as_ref().unwrap().status)
+            .collect();
+        // The second call commits, so the third runs on state the node doesn't have
+        assert_eq!(
+            statuses,
+            [CheckStatus::Agree, CheckStatus::Agree, CheckStatus::Skipped]
+        );
+
+        // Injected code isn't what the node would run
+        let mut results = vec![result()];
+        let injected = bytes!("600160005260206000f3");
+        check_calls(
+            &rpc,
+            &context(),
+            CONTRACT,
+            &injected,
+            &calls[..1],
+            &mut results,
+        )
+        .await;
+        let check = results[0].consistency_check.as_ref().unwrap();
+        assert_eq!(check.status, CheckStatus::Skipped);
+        assert!(check.method.is_none());
+    }
+
+    #[test]
+    fn test_gas_tolerance() {
+        assert!(gas_agrees(100_000, 100_000));
+        assert!(gas_agrees(100_000, 101_000));
+        assert!(gas_agrees(101_000, 100_000));
+        assert!(!gas_agrees(100_000, 102_000));
+    }
+}
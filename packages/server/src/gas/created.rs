@@ -0,0 +1,63 @@
+//! What the addresses of the contracts a call created were derived from, so they can be
+//! predicted: a CREATE's is its deployer's address and nonce hashed together.
+
+use alloy_primitives::Address;
+use forge::executors::RawCallResult;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::trace::TraceKind;
+
+// How far below a deployer's nonce after the call to look for the one a CREATE used
+const MAX_NONCE_SEARCH: u64 = 4_096;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedContract {
+    #[schema(value_type = String)]
+    pub address: Address,
+    /// `create` or `create2`
+    pub kind: TraceKind,
+    /// The transaction's sender for a deployment, or the contract that ran CREATE or CREATE2
+    #[schema(value_type = String)]
+    pub deployer: Address,
+    /// For `create`, the deployer's nonce the address comes from. A CREATE2 address comes from
+    /// a salt and the creation code instead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<u64>,
+}
+
+/// Every contract `r` created, in the order it created them.
+pub(super) fn created(r: &RawCallResult) -> Vec<CreatedContract> {
+    let Some(arena) = &r.traces else {
+        return Vec::new();
+    };
+    arena
+        .nodes()
+        .iter()
+        .filter(|node| node.trace.success)
+        .filter_map(|node| {
+            let trace = &node.trace;
+            let kind = TraceKind::from(trace.kind);
+            let nonce = match kind {
+                TraceKind::Create => nonce_of(r, trace.caller, trace.address),
+                TraceKind::Create2 => None,
+                _ => return None,
+            };
+            Some(CreatedContract {
+                address: trace.address,
+                kind,
+                deployer: trace.caller,
+                nonce,
+            })
+        })
+        .collect()
+}
+
+// The nonce `deployer` had when it created `address`, looked for below the one it has after
+fn nonce_of(r: &RawCallResult, deployer: Address, address: Address) -> Option<u64> {
+    let after = r.state_changeset.get(&deployer)?.info.nonce;
+    (after.saturating_sub(MAX_NONCE_SEARCH)..after)
+        .rev()
+        .find(|&nonce| deployer.create(nonce) == address)
+}
@@ -0,0 +1,273 @@
+//! A small chain compiled into the binary, for trying forks out without an RPC. It's the same on
+//! every run:
+//!
+//! - `0x1000000000000000000000000000000000000001` through `...0004` each hold 100 ETH, 10 WETH and
+//!   10,000 dUSD
+//! - WETH at `0x4200000000000000000000000000000000000006`, with `deposit`, `withdraw` and the
+//!   ERC-20 functions
+//! - dUSD at `0xd05d000000000000000000000000000000000001`, the same token
+//! - A WETH/dUSD pool at `0xd05d000000000000000000000000000000000002` holding 100 WETH and
+//!   300,000 dUSD, with `swap(address tokenIn, uint256 amountIn, uint256 minAmountOut)`. It pulls
+//!   `tokenIn` with `transferFrom` and takes a 0.3% fee.
+
+use alloy_primitives::{address, Address, U256};
+use once_cell::sync::Lazy;
+use revm::primitives::TxEnv;
+use revm_primitives::{BlockEnv, CfgEnv, Env};
+use std::sync::Arc;
+
+use super::engine::EngineConfig;
+use super::execute_calldatas_fork::{ExecutionOptions, ForkContext};
+use super::hardfork;
+use super::local::StateDump;
+
+/// The `forkConfig.network` that selects this chain.
+pub const NETWORK: &str = "demo";
+/// Reserved for local development chains, so it's never mistaken for a real one.
+pub const DEMO_CHAIN_ID: u64 = 1337;
+pub const DEMO_BLOCK_NUMBER: u64 = 1;
+
+pub const WETH: Address = address!("4200000000000000000000000000000000000006");
+pub const DUSD: Address = address!("d05d000000000000000000000000000000000001");
+pub const POOL: Address = address!("d05d000000000000000000000000000000000002");
+pub const ACCOUNTS: [Address; 4] = [
+    address!("1000000000000000000000000000000000000001"),
+    address!("1000000000000000000000000000000000000002"),
+    address!("1000000000000000000000000000000000000003"),
+    address!("1000000000000000000000000000000000000004"),
+];
+
+// 2023-11-14, so contracts reading the time see the same value on every run
+const DEMO_TIMESTAMP: u64 = 1_700_000_000;
+const DEMO_GAS_LIMIT: u64 = 30_000_000;
+
+static STATE: Lazy<Arc<StateDump>> = Lazy::new(|| {
+    Arc::new(
+        serde_json::from_str(include_str!("fixtures/demo.json"))
+            .expect("demo fixture is a state dump"),
+    )
+});
+
+/// What calls on the demo chain see, echoed back like a fork's.
+pub(super) fn context() -> ForkContext {
+    ForkContext {
+        chain_id: DEMO_CHAIN_ID,
+        block_number: DEMO_BLOCK_NUMBER,
+        gas_limit: DEMO_GAS_LIMIT,
+        hardfork: hardfork::LATEST.to_string(),
+        confirmation_lag: 0,
+        rpc_diagnostics: None,
+    }
+}
+
+/// A fresh copy of the demo chain.
+pub(super) fn engine(options: Option<ExecutionOptions>) -> EngineConfig {
+    EngineConfig::memory(STATE.clone(), options)
+}
+
+// The chain ID, block and gas limits calls on the demo chain see
+pub(super) fn env() -> Env {
+    Env {
+        cfg: CfgEnv::default().with_chain_id(DEMO_CHAIN_ID),
+        block: BlockEnv {
+            number: U256::from(DEMO_BLOCK_NUMBER),
+            timestamp: U256::from(DEMO_TIMESTAMP),
+            gas_limit: U256::from(DEMO_GAS_LIMIT),
+            ..Default::default()
+        },
+        tx: TxEnv {
+            chain_id: Some(DEMO_CHAIN_ID),
+            gas_limit: DEMO_GAS_LIMIT,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use crate::decode::RevertReason;
+    use crate::gas::{
+        execute_calldatas_fork, execute_calldatas_fork_with, ForkCall, ForkConfig, ForkProgress,
+        ResolvedFork,
+    };
+    use alloy_primitives::{bytes, keccak256, Bytes};
+    use alloy_sol_types::{sol, SolCall};
+
+    sol! {
+        function deposit();
+        function approve(address spender, uint256 amount) returns (bool);
+        function balanceOf(address owner) returns (uint256);
+        function swap(address tokenIn, uint256 amountIn, uint256 minAmountOut) returns (uint256);
+    }
+
+    // Forwards its calldata, after a 20-byte target, to that target with the call's value, so
+    // one contract can act on the demo chain's tokens
+    const FORWARDER: Bytes = bytes!(
+        "60143603806014600037600060008260003460003560601c5af13d600060003e610028573d6000fd5b3d6000f3"
+    );
+    const FORWARDER_ADDRESS: Address = address!("b2f9974c62815d3177079e150377915d9bc49c82");
+
+    fn forward(target: Address, calldata: Vec<u8>, value: U256) -> ForkCall {
+        ForkCall {
+            calldata: [target.as_slice(), &calldata].concat().into(),
+            value,
+            caller: Some(ACCOUNTS[0].into()),
+            independent: false,
+            signature: None,
+            args: None,
+            meta: None,
+        }
+    }
+
+    fn demo() -> ResolvedFork {
+        ForkConfig {
+            network: Some(NETWORK.to_string()),
+            ..Default::default()
+        }
+        .resolve(&AppConfig::default())
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_swap_offline() {
+        let one = U256::from(10).pow(U256::from(18));
+        // Enough for two swaps, the second asking for more than it can get
+        let calls = vec![
+            forward(WETH, depositCall {}.abi_encode(), one * U256::from(2)),
+            forward(
+                WETH,
+                approveCall {
+                    spender: POOL,
+                    amount: U256::MAX,
+                }
+                .abi_encode(),
+                U256::ZERO,
+            ),
+            forward(
+                POOL,
+                swapCall {
+                    tokenIn: WETH,
+                    amountIn: one,
+                    minAmountOut: U256::ZERO,
+                }
+                .abi_encode(),
+                U256::ZERO,
+            ),
+            forward(
+                DUSD,
+                balanceOfCall {
+                    owner: FORWARDER_ADDRESS,
+                }
+                .abi_encode(),
+                U256::ZERO,
+            ),
+            forward(
+                POOL,
+                swapCall {
+                    tokenIn: WETH,
+                    amountIn: one,
+                    minAmountOut: U256::MAX,
+                }
+                .abi_encode(),
+                U256::ZERO,
+            ),
+        ];
+
+        // No RPC is configured, and none is needed
+        let mut context = None;
+        let (_, _, results) = execute_calldatas_fork_with(
+            &AppConfig::default(),
+            FORWARDER,
+            FORWARDER_ADDRESS.into(),
+            calls,
+            demo(),
+            None,
+            |progress| {
+                if let ForkProgress::Forked(forked) = progress {
+                    context = Some(forked.clone());
+                }
+                Ok(())
+            },
+        )
+        .await
+        .unwrap();
+        let context = context.unwrap();
+        assert_eq!(
+            (context.chain_id, context.block_number),
+            (DEMO_CHAIN_ID, DEMO_BLOCK_NUMBER)
+        );
+
+        assert!(results[..4].iter().all(|result| result.success));
+        assert_eq!(results[0].gas_limit, Some(DEMO_GAS_LIMIT));
+        // 1 WETH into 100 WETH / 300,000 dUSD, less the fee
+        let out = U256::from(2_961_474_103_191_183_896_551u128);
+        assert_eq!(results[2].result, Bytes::from(out.to_be_bytes_vec()));
+        assert_eq!(results[3].result, Bytes::from(out.to_be_bytes_vec()));
+        let swapped = keccak256("Swap(address,address,uint256,uint256)");
+        assert!(results[2]
+            .logs
+            .iter()
+            .any(|log| log.address == POOL && log.topics[0] == swapped));
+
+        assert!(results[4].reverted);
+        assert!(matches!(
+            &results[4].revert_reason,
+            Some(RevertReason::Error { message }) if message == "insufficient output"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_every_run_starts_fresh() {
+        let call = || {
+            forward(
+                WETH,
+                balanceOfCall {
+                    owner: FORWARDER_ADDRESS,
+                }
+                .abi_encode(),
+                U256::ZERO,
+            )
+        };
+        let deposit = forward(WETH, depositCall {}.abi_encode(), U256::from(5));
+        let results = execute_calldatas_fork(
+            &AppConfig::default(),
+            FORWARDER,
+            FORWARDER_ADDRESS.into(),
+            vec![deposit, call()],
+            demo(),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            results[1].result,
+            Bytes::from(U256::from(5).to_be_bytes_vec())
+        );
+
+        let results = execute_calldatas_fork(
+            &AppConfig::default(),
+            FORWARDER,
+            FORWARDER_ADDRESS.into(),
+            vec![call()],
+            demo(),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(results[0].result, Bytes::from(U256::ZERO.to_be_bytes_vec()));
+    }
+
+    #[test]
+    fn test_unknown_network() {
+        let err = ForkConfig {
+            network: Some("mainnet".to_string()),
+            ..Default::default()
+        }
+        .resolve(&AppConfig::default())
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Unknown network mainnet, expected demo");
+    }
+}
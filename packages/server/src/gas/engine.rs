@@ -0,0 +1,1732 @@
+//! The executor every execution path runs on, whatever its state comes from. Fork setup, the
+//! demo chain and local chains each produce an `EngineConfig`; `Engine` then places contracts,
+//! runs calls and keeps snapshots the same way for all of them.
+
+use alloy_eips::eip2930::AccessList;
+use alloy_json_abi::Error;
+use alloy_primitives::{Address, Bytes, B256, U256};
+use alloy_transport::TransportError;
+use forge::{
+    backend::{Backend, DatabaseError},
+    executors::{Executor, ExecutorBuilder, RawCallResult},
+    opts::EvmOpts,
+};
+use foundry_config::Config;
+use revm::DatabaseRef;
+use revm_primitives::{AccountInfo, Env, SpecId, TransactTo};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::iter;
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use super::anvil;
+use super::breakpoint::{pause, paused_at};
+use super::chrome::{self, Frame};
+use super::code;
+use super::demo;
+use super::ens::{supports_ens, EnsCache, EnsResolver, NameOrAddress};
+use super::execute_calldatas_fork::{
+    access_list, address_labels, breakpoints, collect_logs, deadline, deterministic_addresses,
+    flamegraph, gas_cap, include_raw_traces, journal, retries, spec, state_diff, subcall_outputs,
+    trace_export, trace_mode, transient_storage, Call, ExecutionOptions, ExecutionResult,
+    ForkContext, NamedStateOverride, NotIndependent, SkipReason, TraceExport,
+};
+use super::flamegraph::folded;
+use super::hardfork;
+use super::interop;
+use super::journal::{SourceLocation, MAX_JOURNAL_EVENTS};
+use super::labels;
+use super::local::StateDump;
+use super::mermaid;
+use super::pretty;
+use super::raw_transaction::SignedTransaction;
+use super::state_diff::diff;
+use super::trace::MAX_FRAME_OUTPUT;
+use super::transient::{transient_accesses, MAX_TRANSIENT_ACCESSES};
+use super::tx_validity;
+
+/// A contract `Engine::deploy` placed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Deployment {
+    pub address: Address,
+    /// The deployment transaction's, intrinsic cost included
+    pub gas_used: u64,
+    /// Bytes of runtime code
+    pub code_size: usize,
+}
+
+/// How to build an `Engine`. Building spawns the backend and blocks, so a fork is configured
+/// (everything fetched from the RPC that needs awaiting) first and built wherever its calls run.
+pub struct EngineConfig {
+    /// What calls report about the chain they ran on
+    pub context: ForkContext,
+    options: Option<ExecutionOptions>,
+    env: Env,
+    /// The hardfork calls run under. The latest without one.
+    spec: Option<SpecId>,
+    source: Source,
+    /// How many more times the fork block had to be asked for
+    pub(super) block_retries: u32,
+    /// Accounts given `anvil::funded_balance` on the fork, for a managed anvil's `fundedAccounts`
+    pub(super) funded_accounts: Vec<Address>,
+}
+
+enum Source {
+    Memory(Arc<StateDump>),
+    Fork { opts: EvmOpts, fork_env: Env },
+}
+
+impl EngineConfig {
+    /// A fresh in-memory chain holding `state`, with the demo chain's ID, block and gas limit and
+    /// no RPC behind it.
+    pub fn memory(state: impl Into<Arc<StateDump>>, options: Option<ExecutionOptions>) -> Self {
+        EngineConfig {
+            context: demo::context(),
+            options,
+            env: demo::env(),
+            spec: None,
+            source: Source::Memory(state.into()),
+            block_retries: 0,
+            funded_accounts: Vec::new(),
+        }
+        .with_options_spec()
+    }
+
+    /// A fork of `opts.fork_url`, with calls run in `env`.
+    pub(super) fn fork(
+        env: Env,
+        context: ForkContext,
+        opts: EvmOpts,
+        fork_env: Env,
+        options: Option<ExecutionOptions>,
+    ) -> Self {
+        EngineConfig {
+            context,
+            options,
+            env,
+            spec: None,
+            source: Source::Fork { opts, fork_env },
+            block_retries: 0,
+            funded_accounts: Vec::new(),
+        }
+        .with_options_spec()
+    }
+
+    /// Runs calls in `env` instead, reporting its chain ID, block number and transaction gas
+    /// limit.
+    pub fn with_env(mut self, env: Env) -> Self {
+        self.context = ForkContext {
+            chain_id: env.cfg.chain_id,
+            block_number: env.block.number.saturating_to(),
+            gas_limit: env.tx.gas_limit,
+            hardfork: std::mem::take(&mut self.context.hardfork),
+            confirmation_lag: 0,
+            rpc_diagnostics: self.context.rpc_diagnostics.take(),
+        };
+        self.env = env;
+        self
+    }
+
+    /// Runs calls under `spec`'s rules instead of the latest hardfork's.
+    pub fn with_spec(mut self, spec: SpecId) -> Self {
+        self.spec = Some(spec);
+        self.context.hardfork = hardfork::name(spec).to_string();
+        self
+    }
+
+    // The options' `hardfork`, when they name one
+    fn with_options_spec(self) -> Self {
+        match spec(self.options.as_ref()) {
+            Some(spec) => self.with_spec(spec),
+            None => self,
+        }
+    }
+
+    /// The RPC a fork is taken from. None for in-memory chains.
+    pub(super) fn rpc_url(&self) -> Option<&str> {
+        match &self.source {
+            Source::Memory(_) => None,
+            Source::Fork { opts, .. } => opts.fork_url.as_deref(),
+        }
+    }
+
+    pub fn build(mut self) -> Result<Engine, eyre::Error> {
+        let gas_cap = gas_cap(self.options.as_ref());
+        if let Some(cap) = gas_cap {
+            self.context.gas_limit = self.context.gas_limit.min(cap);
+            self.env.tx.gas_limit = self.env.tx.gas_limit.min(cap);
+        }
+        let options = self.options.as_ref();
+        let mut builder = ExecutorBuilder::new().inspectors(|stack| {
+            stack
+                .trace_mode(trace_mode(options))
+                .logs(collect_logs(options))
+        });
+        if let Some(spec) = self.spec {
+            builder = builder.spec(spec);
+        }
+        // A fork's executor is always given the context's
+        if gas_cap.is_some() {
+            builder = builder.gas_limit(U256::from(self.context.gas_limit));
+        }
+        let executor = match self.source {
+            Source::Memory(state) => {
+                let mut executor = builder.build(self.env, Backend::spawn(None));
+                for (address, account) in state.iter() {
+                    executor.backend_mut().insert_account_info(
+                        *address,
+                        AccountInfo {
+                            balance: account.balance,
+                            nonce: account.nonce,
+                            ..code::account(account.code.clone())
+                        },
+                    );
+                    for (slot, value) in &account.storage {
+                        executor
+                            .backend_mut()
+                            .insert_account_storage(*address, *slot, *value)?;
+                    }
+                }
+                executor
+            }
+            Source::Fork { opts, fork_env } => {
+                let backend = Backend::spawn(opts.get_fork(&Config::default(), fork_env));
+                let executor = builder
+                    .gas_limit(U256::from(self.context.gas_limit))
+                    .build(self.env, backend);
+                info!(
+                    chain_id = self.context.chain_id,
+                    block_number = self.context.block_number,
+                    gas_limit = self.context.gas_limit,
+                    "fork ready"
+                );
+                executor
+            }
+        };
+        let mut engine = Engine {
+            executor,
+            context: self.context,
+            include_raw_traces: include_raw_traces(self.options.as_ref()),
+            trace_export: trace_export(self.options.as_ref()),
+            flamegraph: flamegraph(self.options.as_ref()),
+            state_diff: state_diff(self.options.as_ref()),
+            access_list: access_list(self.options.as_ref()),
+            journal: journal(self.options.as_ref()),
+            transient_storage: transient_storage(self.options.as_ref()),
+            deterministic_addresses: deterministic_addresses(self.options.as_ref()),
+            breakpoints: breakpoints(self.options.as_ref()),
+            subcall_outputs: subcall_outputs(self.options.as_ref()),
+            retries: retries(self.options.as_ref()),
+            deadline: deadline(self.options.as_ref()),
+            gas_cap,
+            labels: labels::merged(self.context.chain_id, address_labels(self.options.as_ref())),
+            snapshots: Vec::new(),
+        };
+        // On the fork alone: the anvil behind it is every request's
+        for account in self.funded_accounts {
+            engine.set_balance(account, anvil::funded_balance())?;
+        }
+        Ok(engine)
+    }
+}
+
+/// Block and transaction fields one call sees differently from the engine's environment.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EnvOverrides {
+    pub block_number: Option<u64>,
+    pub timestamp: Option<u64>,
+    pub coinbase: Option<Address>,
+    pub basefee: Option<u64>,
+    pub gas_limit: Option<u64>,
+}
+
+/// The environment a call actually ran in, overrides and all, so a result says what it was
+/// computed against.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CallEnv {
+    pub chain_id: u64,
+    pub block_number: u64,
+    pub timestamp: u64,
+    #[schema(value_type = String)]
+    pub basefee: U256,
+    #[schema(value_type = String)]
+    pub coinbase: Address,
+    /// Missing before the merge
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub prevrandao: Option<B256>,
+    /// The gas the call was given
+    pub gas_limit: u64,
+    /// `msg.sender`: the call's `caller`, or the request's `defaultCaller`
+    #[schema(value_type = String)]
+    pub caller: Address,
+    /// The caller's, before the call
+    pub caller_nonce: u64,
+}
+
+impl CallEnv {
+    fn new(env: &Env, caller_nonce: u64) -> Self {
+        CallEnv {
+            chain_id: env.cfg.chain_id,
+            block_number: env.block.number.saturating_to(),
+            timestamp: env.block.timestamp.saturating_to(),
+            basefee: env.block.basefee,
+            coinbase: env.block.coinbase,
+            prevrandao: env.block.prevrandao,
+            gas_limit: env.tx.gas_limit,
+            caller: env.tx.caller,
+            caller_nonce,
+        }
+    }
+}
+
+impl EnvOverrides {
+    fn apply(&self, env: &mut Env) {
+        if let Some(number) = self.block_number {
+            env.block.number = U256::from(number);
+        }
+        if let Some(timestamp) = self.timestamp {
+            env.block.timestamp = U256::from(timestamp);
+        }
+        if let Some(coinbase) = self.coinbase {
+            env.block.coinbase = coinbase;
+        }
+        if let Some(basefee) = self.basefee {
+            env.block.basefee = U256::from(basefee);
+        }
+        if let Some(gas_limit) = self.gas_limit {
+            env.tx.gas_limit = gas_limit;
+        }
+    }
+}
+
+/// An executor and what it's running on. Clones are independent copies of the state; a fork's
+/// clones still share its RPC cache.
+#[derive(Clone)]
+pub struct Engine {
+    executor: Executor,
+    context: ForkContext,
+    include_raw_traces: bool,
+    trace_export: Option<TraceExport>,
+    flamegraph: bool,
+    state_diff: bool,
+    access_list: bool,
+    journal: bool,
+    transient_storage: bool,
+    deterministic_addresses: bool,
+    /// The pcs of the called contract's code calls stop at, with where each is in the sources
+    breakpoints: BTreeMap<usize, SourceLocation>,
+    /// With `captureSubcallOutputs`, the custom errors frames' revert data is read as
+    subcall_outputs: Option<Vec<Error>>,
+    /// How many more times a call that failed on a transient RPC error is run
+    retries: u32,
+    /// When calls stop being started
+    deadline: Option<Instant>,
+    /// What a call's own gas limit is cut to, when calls record every opcode
+    gas_cap: Option<u64>,
+    /// Well-known contracts on the chain and the request's own labels
+    labels: BTreeMap<Address, String>,
+    snapshots: Vec<Backend>,
+}
+
+impl Engine {
+    pub fn context(&self) -> &ForkContext {
+        &self.context
+    }
+
+    pub fn executor(&self) -> &Executor {
+        &self.executor
+    }
+
+    /// Places runtime `code` at `address`, replacing whatever account was there.
+    pub fn insert_contract(&mut self, address: Address, code: Bytes) {
+        self.executor
+            .backend_mut()
+            .insert_account_info(address, code::account(code));
+    }
+
+    /// The address `value` names, resolved through the fork's ENS registry when it's a name.
+    /// `field` is the request field it came from, for the error when it doesn't resolve.
+    pub fn resolve_name(&self, value: &NameOrAddress, field: &str) -> Result<Address, eyre::Error> {
+        EnsResolver::new(&self.executor, &mut EnsCache::default()).resolve_field(value, field)
+    }
+
+    /// Applies an `eth_call` state override set. Per account, `balance`, `nonce` and `code`
+    /// replace what's there; `state` replaces the whole storage, so slots it leaves out read as
+    /// zero, while `stateDiff` only sets the slots it names. Accounts given as ENS names are
+    /// resolved first, so a bad name changes nothing.
+    pub fn override_state(&mut self, overrides: &NamedStateOverride) -> Result<(), eyre::Error> {
+        let mut cache = EnsCache::default();
+        let mut ens = EnsResolver::new(&self.executor, &mut cache);
+        let overrides = overrides
+            .iter()
+            .map(|(account, changes)| {
+                let field = format!("stateOverrides.{}", account);
+                Ok((ens.resolve_field(account, &field)?, changes))
+            })
+            .collect::<Result<Vec<_>, eyre::Error>>()?;
+        for (address, account) in &overrides {
+            if account.state.is_some() && account.state_diff.is_some() {
+                eyre::bail!("the override for {} sets both state and stateDiff", address);
+            }
+            let backend = self.executor.backend_mut();
+            let mut info = backend.basic_ref(*address)?.unwrap_or_default();
+            if let Some(code) = &account.code {
+                info = AccountInfo {
+                    balance: info.balance,
+                    nonce: info.nonce,
+                    ..code::account(code.clone())
+                };
+            }
+            if let Some(balance) = account.balance {
+                info.balance = balance;
+            }
+            if let Some(nonce) = account.nonce {
+                info.nonce = nonce.to();
+            }
+            backend.insert_account_info(*address, info);
+
+            let slot = |(slot, value): (&B256, &B256)| {
+                (U256::from_be_bytes(slot.0), U256::from_be_bytes(value.0))
+            };
+            if let Some(state) = &account.state {
+                backend.replace_account_storage(*address, state.iter().map(slot).collect())?;
+            }
+            for (slot, value) in account.state_diff.iter().flatten().map(slot) {
+                backend.insert_account_storage(*address, slot, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set_balance(&mut self, address: Address, balance: U256) -> Result<(), eyre::Error> {
+        self.executor.set_balance(address, balance)?;
+        Ok(())
+    }
+
+    /// Runs `creation_code` (constructor args appended) from `deployer` and returns where it was
+    /// deployed and what that cost. A constructor that reverts is an error.
+    pub fn deploy(
+        &mut self,
+        deployer: Address,
+        creation_code: Bytes,
+    ) -> Result<Deployment, eyre::Error> {
+        let deployed = self
+            .executor
+            .deploy(deployer, creation_code, U256::ZERO, None)?;
+        let code_size = self
+            .executor
+            .backend()
+            .basic_ref(deployed.address)?
+            .and_then(|info| info.code)
+            .map_or(0, |code| code.original_bytes().len());
+        Ok(Deployment {
+            address: deployed.address,
+            gas_used: deployed.gas_used,
+            code_size,
+        })
+    }
+
+    /// Runs creation code from `caller` like `deploy`, but a revert comes back as the result,
+    /// with no address.
+    pub fn create(
+        &mut self,
+        caller: Address,
+        creation_code: Bytes,
+        value: U256,
+    ) -> Result<(Option<Address>, ExecutionResult), eyre::Error> {
+        let sender = account_of(&self.executor, caller)?;
+        let env = self
+            .executor
+            .build_test_env(caller, TransactTo::Create, creation_code, value);
+        let r = self.executor.transact_with_env(env)?;
+        let result = self.result_of(r, &sender, None, None);
+        let address = (!result.reverted).then(|| caller.create(sender.nonce));
+        Ok((address, result))
+    }
+
+    /// Sends one call, committing its state changes.
+    pub fn execute_call(
+        &mut self,
+        caller: Address,
+        to: Address,
+        calldata: Bytes,
+        value: U256,
+    ) -> Result<ExecutionResult, eyre::Error> {
+        let sender = account_of(&self.executor, caller)?;
+        let balance = balance_of(&self.executor, to)?;
+        let r = self.executor.transact_raw(caller, to, calldata, value)?;
+        Ok(self.result_of(r, &sender, Some((to, balance)), None))
+    }
+
+    /// `execute_call` with some of the environment changed for this call alone.
+    pub fn execute_call_with(
+        &mut self,
+        caller: Address,
+        to: Address,
+        calldata: Bytes,
+        value: U256,
+        overrides: &EnvOverrides,
+    ) -> Result<ExecutionResult, eyre::Error> {
+        let mut env = self
+            .executor
+            .build_test_env(caller, TransactTo::Call(to), calldata, value);
+        overrides.apply(&mut env);
+        self.cap_gas(&mut env);
+        let sender = account_of(&self.executor, caller)?;
+        let balance = balance_of(&self.executor, to)?;
+        let r = self.executor.transact_with_env(env)?;
+        Ok(self.result_of(r, &sender, Some((to, balance)), None))
+    }
+
+    /// Runs a signed transaction from its signer, with the gas limit, fees and access list it was
+    /// signed with, committing its state changes.
+    pub fn execute_transaction(
+        &mut self,
+        tx: &SignedTransaction,
+    ) -> Result<ExecutionResult, eyre::Error> {
+        let sender = account_of(&self.executor, tx.from)?;
+        let target = match tx.to {
+            TransactTo::Call(to) => Some((to, balance_of(&self.executor, to)?)),
+            TransactTo::Create => None,
+        };
+        // It commits, so what it changed is compared against a copy taken first
+        let before = self.state_diff.then(|| self.executor.backend().clone());
+        let r = self.transact(tx)?;
+        let changes = before
+            .map(|before| diff(&before, &r.state_changeset))
+            .transpose()?;
+        let touched = self.access_list.then(|| accessed(tx.from, &r));
+        let mut result = self.result_of(r, &sender, target, Some(tx));
+        result.gas_limit = Some(tx.gas_limit);
+        result.state_diff = changes;
+        result.access_list = touched;
+        result.labels = labels::touched(&result, &self.labels);
+        if let Some(traces) = result.traces.as_ref().filter(|_| self.flamegraph) {
+            result.flamegraph = Some(folded(traces, &result.labels));
+        }
+        Ok(result)
+    }
+
+    /// Runs a transaction only for what it changes, as `execute_transaction` would, without
+    /// making a result of it. Whether it reverted doesn't matter.
+    pub fn apply_transaction(&mut self, tx: &SignedTransaction) -> Result<(), eyre::Error> {
+        self.transact(tx)?;
+        Ok(())
+    }
+
+    fn transact(&mut self, tx: &SignedTransaction) -> Result<RawCallResult, eyre::Error> {
+        let mut env = self
+            .executor
+            .build_test_env(tx.from, tx.to, tx.input.clone(), tx.value);
+        tx.apply(&mut env);
+        self.cap_gas(&mut env);
+        Ok(self.executor.transact_with_env(env)?)
+    }
+
+    fn cap_gas(&self, env: &mut Env) {
+        if let Some(cap) = self.gas_cap {
+            env.tx.gas_limit = env.tx.gas_limit.min(cap);
+        }
+    }
+
+    // `r` as a result, with the environment it ran in, whether `sender` could have sent it as
+    // a transaction (`signed`, when it was one) and, for calls, the balance of the account
+    // called before and after
+    fn result_of(
+        &self,
+        r: RawCallResult,
+        sender: &AccountInfo,
+        target: Option<(Address, U256)>,
+        signed: Option<&SignedTransaction>,
+    ) -> ExecutionResult {
+        let env = CallEnv::new(&r.env, sender.nonce);
+        let validity = tx_validity::check(sender, &r, signed);
+        let balances = target.map(|(to, before)| (before, balance_after(&r, to, before)));
+        let (mut result, _) = convert(
+            r,
+            self.include_raw_traces,
+            false,
+            self.journal,
+            self.transient_storage,
+            self.deterministic_addresses,
+            None,
+        );
+        capture_outputs(&mut result, self.subcall_outputs.as_deref());
+        result.labels = labels::touched(&result, &self.labels);
+        result.env = Some(env);
+        result.tx_validity = Some(validity);
+        if let Some((before, after)) = balances {
+            result.target_balance_before = Some(before);
+            result.target_balance_after = Some(after);
+        }
+        result
+    }
+
+    /// Sends `calls` to `address` in order, as a request's calls are run, and collects the
+    /// results.
+    pub fn execute_calls(
+        &mut self,
+        address: Address,
+        calls: Vec<Call>,
+        parallelism: usize,
+    ) -> Result<Vec<ExecutionResult>, eyre::Error> {
+        let mut results = Vec::with_capacity(calls.len());
+        self.execute_calls_with(address, calls, parallelism, |_, result| {
+            results.push(result);
+            Ok(())
+        })?;
+        Ok(results)
+    }
+
+    /// `execute_calls`, handing each result to `on_result` as it completes. Callers named by ENS
+    /// are resolved first, each result gets its call's index and `meta`, the call gas limit,
+    /// where ENS exists labels for its traces and, when asked for, its `traceExport`,
+    /// `flamegraph`, `stateDiff`, `journal`, `transientStorageAccesses` and `created`.
+    /// Consecutive independent calls run `parallelism` at a time. Once the deadline passes, the
+    /// calls after the one running come back skipped.
+    pub fn execute_calls_with<F>(
+        &mut self,
+        address: Address,
+        mut calls: Vec<Call>,
+        parallelism: usize,
+        mut on_result: F,
+    ) -> Result<(), eyre::Error>
+    where
+        F: FnMut(usize, ExecutionResult) -> Result<(), eyre::Error>,
+    {
+        let mut ens_cache = EnsCache::default();
+        let context = &self.context;
+        let include_raw_traces = self.include_raw_traces;
+        let export = self.trace_export;
+        let chrome_export = export == Some(TraceExport::Chrome);
+        let flamegraph = self.flamegraph;
+        let state_diff = self.state_diff;
+        let access_list = self.access_list;
+        let journal = self.journal;
+        let transient_storage = self.transient_storage;
+        let deterministic_addresses = self.deterministic_addresses;
+        let breakpoints = (!self.breakpoints.is_empty()).then_some((address, &self.breakpoints));
+        let retries = self.retries;
+        let deadline = self.deadline;
+        let labels = &self.labels;
+        let subcall_outputs = self.subcall_outputs.as_deref();
+        let executor = &mut self.executor;
+        // Where the next call's export starts, after the gas of those before it
+        let mut exported_gas = 0;
+
+        // Resolve all names up front against the pinned block so a bad name fails the whole request
+        let callers = {
+            let mut ens = EnsResolver::new(executor, &mut ens_cache);
+            calls
+                .iter()
+                .enumerate()
+                .map(|(i, call)| {
+                    let field = format!("calls[{}].caller", i);
+                    // The routes fill in `defaultCaller` before getting here
+                    let caller = call
+                        .caller
+                        .as_ref()
+                        .ok_or_else(|| eyre::eyre!("{} is missing", field))?;
+                    ens.resolve_field(caller, &field)
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        };
+
+        // Only the results carry these; the calls run without them
+        let mut metas: Vec<_> = calls.iter_mut().map(|call| call.meta.take()).collect();
+
+        let mut finish = |executor: &mut Executor,
+                          index: usize,
+                          (mut result, frames): Converted| {
+            result.call_index = Some(index);
+            result.meta = metas[index].take();
+            result.gas_limit = result.skipped.is_none().then_some(context.gas_limit);
+            if let Some(frames) = frames {
+                let document = chrome::document(&frames, index, exported_gas);
+                // Only ever fails for maps with keys that aren't strings
+                result.trace_export = serde_json::to_string(&document).ok();
+            }
+            exported_gas += result.gas_used;
+            capture_outputs(&mut result, subcall_outputs);
+            result.labels = labels::touched(&result, labels);
+            // ENS names only for what nothing else named
+            if let Some(traces) = result
+                .traces
+                .as_ref()
+                .filter(|_| supports_ens(context.chain_id))
+            {
+                let names = EnsResolver::new(executor, &mut ens_cache).label_traces(traces);
+                for (address, name) in names {
+                    result.labels.entry(address).or_insert(name);
+                }
+            }
+            if let Some(traces) = &result.traces {
+                match export {
+                    Some(TraceExport::Pretty) => {
+                        result.trace_export = Some(pretty::render(traces, &result.labels));
+                    }
+                    Some(TraceExport::Mermaid(depth)) => {
+                        result.trace_export = Some(mermaid::render(traces, &result.labels, depth));
+                    }
+                    _ => {}
+                }
+            }
+            if let Some(traces) = result.traces.as_ref().filter(|_| flamegraph) {
+                result.flamegraph = Some(folded(traces, &result.labels));
+            }
+            on_result(index, result)
+        };
+
+        let mut calls = calls.into_iter().zip(callers).enumerate().peekable();
+        while let Some((index, (call, caller))) = calls.next() {
+            // Checked between calls only, as one that has started can't be stopped
+            if index > 0 && deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                warn!(
+                    skipped = calls.len() + 1,
+                    "request deadline passed, skipping the remaining calls"
+                );
+                for index in iter::once(index).chain(calls.by_ref().map(|(index, _)| index)) {
+                    let result = ExecutionResult::skipped(SkipReason::DeadlineExceeded);
+                    finish(executor, index, (result, None))?;
+                }
+                break;
+            }
+            if !call.independent {
+                // The call commits, so what it changed is compared against a copy taken first.
+                // A retry starts from it too, so nothing a failed run wrote is left behind, and a
+                // call that stopped at a breakpoint goes back to it.
+                let before = (state_diff || retries > 0 || breakpoints.is_some())
+                    .then(|| executor.backend().clone());
+                let ((sender, balance, r), attempts) = retrying(retries, |attempt| {
+                    if let Some(before) = before.as_ref().filter(|_| attempt > 1) {
+                        *executor.backend_mut() = before.clone();
+                    }
+                    let sender = account_of(executor, caller)?;
+                    let balance = balance_of(executor, address)?;
+                    let r = executor.transact_raw(
+                        caller,
+                        address,
+                        call.calldata.clone(),
+                        call.value,
+                    )?;
+                    Ok((sender, balance, r))
+                })?;
+                let changes = before
+                    .as_ref()
+                    .filter(|_| state_diff)
+                    .map(|before| diff(before, &r.state_changeset))
+                    .transpose()?;
+                let touched = access_list.then(|| accessed(caller, &r));
+                let env = CallEnv::new(&r.env, sender.nonce);
+                let validity = tx_validity::check(&sender, &r, None);
+                let balance_after = balance_after(&r, address, balance);
+                let (mut result, frames) = convert(
+                    r,
+                    include_raw_traces,
+                    chrome_export,
+                    journal,
+                    transient_storage,
+                    deterministic_addresses,
+                    breakpoints,
+                );
+                if let Some(before) = before.filter(|_| result.paused_at.is_some()) {
+                    *executor.backend_mut() = before;
+                }
+                result.state_diff = changes.filter(|_| result.paused_at.is_none());
+                result.access_list = touched;
+                result.env = Some(env);
+                result.tx_validity = Some(validity);
+                result.target_balance_before = Some(balance);
+                result.target_balance_after = Some(balance_after);
+                result.attempts = (retries > 0).then_some(attempts);
+                finish(executor, index, (result, frames))?;
+                continue;
+            }
+            let mut run = vec![(index, call, caller)];
+            while let Some((index, (call, caller))) =
+                calls.next_if(|(_, (call, _))| call.independent)
+            {
+                run.push((index, call, caller));
+            }
+            let results = call_independent(
+                executor,
+                address,
+                run,
+                include_raw_traces,
+                chrome_export,
+                state_diff,
+                access_list,
+                journal,
+                transient_storage,
+                deterministic_addresses,
+                breakpoints,
+                retries,
+                parallelism,
+            )?;
+            for (index, result) in results {
+                finish(executor, index, result)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remembers the current state and returns an id to `revert` to. None when `max` snapshots
+    /// are already kept, each a copy of the whole backend.
+    pub fn snapshot(&mut self, max: usize) -> Option<usize> {
+        if self.snapshots.len() >= max {
+            return None;
+        }
+        self.snapshots.push(self.executor.backend().clone());
+        Some(self.snapshots.len() - 1)
+    }
+
+    /// Goes back to the state snapshot `id` was taken of. Snapshots taken after it are dropped;
+    /// `id` itself can be reverted to again. False if there's no such snapshot.
+    pub fn revert(&mut self, id: usize) -> bool {
+        let Some(state) = self.snapshots.get(id) else {
+            return false;
+        };
+        *self.executor.backend_mut() = state.clone();
+        self.snapshots.truncate(id + 1);
+        true
+    }
+}
+
+// A call's result, with its frames when they're to be exported
+type Converted = (ExecutionResult, Option<Vec<Frame>>);
+
+// The contract whose breakpoints calls stop at, and their pcs
+type Breakpoints<'a> = Option<(Address, &'a BTreeMap<usize, SourceLocation>)>;
+
+fn convert(
+    r: RawCallResult,
+    include_raw_traces: bool,
+    chrome_export: bool,
+    journal: bool,
+    transient_storage: bool,
+    deterministic_addresses: bool,
+    breakpoints: Breakpoints,
+) -> Converted {
+    let frames = r
+        .traces
+        .as_ref()
+        .filter(|_| chrome_export)
+        .map(Frame::from_arena);
+    let events = r
+        .traces
+        .as_ref()
+        .filter(|_| journal)
+        .map(|arena| super::journal::journal(arena, MAX_JOURNAL_EVENTS));
+    // Before Cancun the opcodes are invalid, and the hint says so
+    let accesses = r
+        .traces
+        .as_ref()
+        .filter(|_| transient_storage && SpecId::enabled(r.env.handler_cfg.spec_id, SpecId::CANCUN))
+        .map(|arena| transient_accesses(arena, MAX_TRANSIENT_ACCESSES));
+    let created = deterministic_addresses.then(|| super::created::created(&r));
+    let paused = breakpoints.and_then(|(address, pcs)| {
+        r.traces
+            .as_ref()
+            .and_then(|arena| paused_at(arena, address, pcs))
+    });
+    let mut result = ExecutionResult::from_raw(r, include_raw_traces);
+    result.journal = events;
+    result.transient_storage_accesses = accesses;
+    result.created = created;
+    if let Some(at) = paused {
+        pause(&mut result, at);
+    }
+    (result, frames)
+}
+
+// With `captureSubcallOutputs`, each reverted frame's revert data read as `errors` or the
+// built-in ones, and each frame's output cut short
+fn capture_outputs(result: &mut ExecutionResult, errors: Option<&[Error]>) {
+    let Some(errors) = errors else {
+        return;
+    };
+    for node in result.traces.iter_mut().flatten() {
+        node.capture_outputs(MAX_FRAME_OUTPUT, errors);
+    }
+}
+
+// What `address` is before a call, or an empty account where there's none
+fn account_of(executor: &Executor, address: Address) -> Result<AccountInfo, eyre::Error> {
+    Ok(executor.backend().basic_ref(address)?.unwrap_or_default())
+}
+
+fn balance_of(executor: &Executor, address: Address) -> Result<U256, eyre::Error> {
+    Ok(executor
+        .backend()
+        .basic_ref(address)?
+        .map_or(U256::ZERO, |account| account.balance))
+}
+
+// `address`'s balance once `r` ran, from `before`. A reverted call's changeset still has the
+// accounts it loaded, as they were.
+fn balance_after(r: &RawCallResult, address: Address, before: U256) -> U256 {
+    r.state_changeset
+        .get(&address)
+        .map_or(before, |account| account.info.balance)
+}
+
+// What `r` touched, less the caller and coinbase, which every call loads
+fn accessed(caller: Address, r: &RawCallResult) -> AccessList {
+    interop::access_list(&r.state_changeset, &[caller, r.env.block.coinbase])
+}
+
+// Runs calls that don't depend on each other at the same time, `parallelism` at once, each on
+// its own clone of `executor` so none sees another's changes. Nothing is committed, so a call
+// that changed state is refused rather than have its changes dropped. Results come back in call
+// order.
+#[allow(clippy::too_many_arguments)]
+fn call_independent(
+    executor: &Executor,
+    address: Address,
+    calls: Vec<(usize, Call, Address)>,
+    include_raw_traces: bool,
+    chrome_export: bool,
+    state_diff: bool,
+    access_list: bool,
+    journal: bool,
+    transient_storage: bool,
+    deterministic_addresses: bool,
+    breakpoints: Breakpoints,
+    retries: u32,
+    parallelism: usize,
+) -> Result<Vec<(usize, Converted)>, eyre::Error> {
+    let mut results = Vec::with_capacity(calls.len());
+    for chunk in calls.chunks(parallelism.max(1)) {
+        let outcomes: Vec<_> = std::thread::scope(|scope| {
+            let handles: Vec<_> = chunk
+                .iter()
+                .map(|(_, call, caller)| {
+                    let executor = executor.clone();
+                    scope.spawn(move || {
+                        // Nothing is committed, so a retry has nothing to roll back
+                        let ((sender, balance, r), attempts) = retrying(retries, |_| {
+                            let sender = account_of(&executor, *caller)?;
+                            let balance = balance_of(&executor, address)?;
+                            let r = executor.call_raw(
+                                *caller,
+                                address,
+                                call.calldata.clone(),
+                                call.value,
+                            )?;
+                            Ok((sender, balance, r))
+                        })?;
+                        let changed = changed_account(&executor, *caller, &r);
+                        let changes = state_diff
+                            .then(|| diff(executor.backend(), &r.state_changeset))
+                            .transpose()?;
+                        let touched = access_list.then(|| accessed(*caller, &r));
+                        let env = CallEnv::new(&r.env, sender.nonce);
+                        let validity = tx_validity::check(&sender, &r, None);
+                        let balance_after = balance_after(&r, address, balance);
+                        let (mut result, frames) = convert(
+                            r,
+                            include_raw_traces,
+                            chrome_export,
+                            journal,
+                            transient_storage,
+                            deterministic_addresses,
+                            breakpoints,
+                        );
+                        result.state_diff = changes;
+                        result.access_list = touched;
+                        result.env = Some(env);
+                        result.tx_validity = Some(validity);
+                        result.target_balance_before = Some(balance);
+                        result.target_balance_after = Some(balance_after);
+                        result.attempts = (retries > 0).then_some(attempts);
+                        Ok::<_, eyre::Error>((changed, (result, frames)))
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+                })
+                .collect()
+        });
+        for ((index, _, _), outcome) in chunk.iter().zip(outcomes) {
+            let (changed, result) = outcome?;
+            if let Some(account) = changed {
+                return Err(NotIndependent {
+                    index: *index,
+                    account,
+                }
+                .into());
+            }
+            results.push((*index, result));
+        }
+    }
+    Ok(results)
+}
+
+/// Whether running a call again could get past `err`: the fork's node failing to answer for
+/// state the call loaded, rather than anything the EVM did. Any backend database error counts,
+/// as a fork's only come from its node.
+pub(super) fn is_transient(err: &eyre::Error) -> bool {
+    err.chain().any(|cause| {
+        cause.downcast_ref::<DatabaseError>().is_some()
+            || cause.downcast_ref::<TransportError>().is_some()
+    })
+}
+
+// Runs `attempt` until it works, fails for a reason that isn't transient, or has been retried
+// `retries` times, telling it which try it is from 1. Comes back with how many tries it took.
+fn retrying<T>(
+    retries: u32,
+    mut attempt: impl FnMut(u32) -> Result<T, eyre::Error>,
+) -> Result<(T, u32), eyre::Error> {
+    let mut tries = 1;
+    loop {
+        match attempt(tries) {
+            Ok(value) => return Ok((value, tries)),
+            Err(err) if tries <= retries && is_transient(&err) => {
+                warn!(
+                    attempt = tries,
+                    "call failed on a transient RPC error, retrying"
+                );
+                tries += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+// The first account a call changed, leaving out the caller's nonce and what it paid for gas.
+// `executor` is the one the call ran on, which it didn't commit to.
+fn changed_account(executor: &Executor, caller: Address, r: &RawCallResult) -> Option<Address> {
+    if r.reverted {
+        return None;
+    }
+    let coinbase = r.env.block.coinbase;
+    r.state_changeset.iter().find_map(|(address, account)| {
+        let paid_gas = *address == caller || *address == coinbase;
+        let balance_changed = !paid_gas
+            && executor
+                .backend()
+                .basic_ref(*address)
+                .ok()
+                .flatten()
+                .unwrap_or_default()
+                .balance
+                != account.info.balance;
+        let changed = account.is_created()
+            || account.is_selfdestructed()
+            || account.storage.values().any(|slot| slot.is_changed())
+            || balance_changed;
+        changed.then_some(*address)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gas::execute_calldatas_fork::{DEFAULT_DEPLOYER, MAX_DEBUG_TRACE_GAS};
+    use crate::gas::Change;
+    use alloy_eips::eip2930::AccessListItem;
+    use alloy_primitives::{address, hex, B256};
+
+    // Stores calldata[4..36] in slot 0 when given an argument and otherwise returns slot 0
+    const SIMPLE_STORAGE: &str = "0x60243610600e57600435600055005b60005460005260206000f3";
+    const ADDRESS: Address = Address::repeat_byte(0x20);
+
+    fn engine() -> Engine {
+        EngineConfig::memory(StateDump::new(), None)
+            .build()
+            .unwrap()
+    }
+
+    fn set(value: u64) -> Bytes {
+        let mut calldata = hex::decode("60fe47b1").unwrap();
+        calldata.extend_from_slice(&U256::from(value).to_be_bytes::<32>());
+        calldata.into()
+    }
+
+    fn get(engine: &mut Engine) -> Bytes {
+        let calldata = hex::decode("6d4ce63c").unwrap().into();
+        engine
+            .execute_call(DEFAULT_DEPLOYER, ADDRESS, calldata, U256::ZERO)
+            .unwrap()
+            .result
+    }
+
+    fn word(value: u64) -> Bytes {
+        U256::from(value).to_be_bytes_vec().into()
+    }
+
+    #[test]
+    fn test_execute_calls() {
+        let call = |calldata: Bytes| Call {
+            calldata,
+            value: U256::ZERO,
+            caller: Some(DEFAULT_DEPLOYER.into()),
+            independent: false,
+            signature: None,
+            args: None,
+            meta: None,
+        };
+        let mut engine = engine();
+        engine.insert_contract(ADDRESS, SIMPLE_STORAGE.parse().unwrap());
+        let results = engine
+            .execute_calls(
+                ADDRESS,
+                vec![call(set(42)), call(hex::decode("6d4ce63c").unwrap().into())],
+                1,
+            )
+            .unwrap();
+        assert!(results.iter().all(|result| result.success));
+        assert_eq!(results[1].result, word(42));
+        assert_eq!(results[1].gas_limit, Some(demo::context().gas_limit));
+    }
+
+    #[test]
+    fn test_target_balance() {
+        let ether = U256::from(10).pow(U256::from(18));
+        // deposit(), which SIMPLE_STORAGE takes for a read, as it checks no callvalue
+        let deposit = Call {
+            calldata: hex::decode("d0e30db0").unwrap().into(),
+            value: ether,
+            caller: Some(DEFAULT_DEPLOYER.into()),
+            independent: false,
+            signature: None,
+            args: None,
+            meta: None,
+        };
+        let mut engine = engine();
+        engine.insert_contract(ADDRESS, SIMPLE_STORAGE.parse().unwrap());
+        engine
+            .set_balance(DEFAULT_DEPLOYER, ether * U256::from(10))
+            .unwrap();
+        let results = engine
+            .execute_calls(ADDRESS, vec![deposit.clone(), deposit], 1)
+            .unwrap();
+        assert!(results.iter().all(|result| result.success));
+        assert_eq!(results[0].target_balance_before, Some(U256::ZERO));
+        assert_eq!(results[0].target_balance_after, Some(ether));
+        // The first deposit stayed
+        assert_eq!(results[1].target_balance_before, Some(ether));
+        assert_eq!(results[1].target_balance_after, Some(ether * U256::from(2)));
+    }
+
+    #[test]
+    fn test_tx_validity() {
+        use crate::gas::TxCheck;
+
+        let mut engine = engine();
+        engine.insert_contract(ADDRESS, SIMPLE_STORAGE.parse().unwrap());
+        let gwei = EnvOverrides {
+            basefee: Some(1_000_000_000),
+            ..Default::default()
+        };
+        let broke = Address::repeat_byte(0x30);
+        let checks = |result: &ExecutionResult| -> Vec<TxCheck> {
+            let validity = result.tx_validity.as_ref().unwrap();
+            assert_eq!(validity.valid, validity.failures.is_empty());
+            validity
+                .failures
+                .iter()
+                .map(|failure| failure.check)
+                .collect()
+        };
+
+        // It runs, but a node would want gasUsed gwei from a sender that has none
+        let result = engine
+            .execute_call_with(broke, ADDRESS, set(42), U256::ZERO, &gwei)
+            .unwrap();
+        assert!(result.success);
+        assert_eq!(checks(&result), [TxCheck::InsufficientFunds]);
+        assert_eq!(get(&mut engine), word(42));
+
+        engine
+            .set_balance(broke, U256::from(10).pow(U256::from(18)))
+            .unwrap();
+        let result = engine
+            .execute_call_with(broke, ADDRESS, set(43), U256::ZERO, &gwei)
+            .unwrap();
+        assert!(checks(&result).is_empty());
+
+        // Without a basefee there's nothing to pay, but a contract still can't send it
+        let result = engine
+            .execute_call(ADDRESS, ADDRESS, set(44), U256::ZERO)
+            .unwrap();
+        assert!(result.success);
+        assert_eq!(checks(&result), [TxCheck::SenderIsContract]);
+    }
+
+    #[test]
+    fn test_chrome_export_puts_each_call_on_its_track() {
+        let call = |calldata: Bytes| Call {
+            calldata,
+            value: U256::ZERO,
+            caller: Some(DEFAULT_DEPLOYER.into()),
+            independent: false,
+            signature: None,
+            args: None,
+            meta: None,
+        };
+        let options = ExecutionOptions::new(None, false, None, Some("chrome".to_string()), false);
+        let mut engine = EngineConfig::memory(StateDump::new(), options)
+            .build()
+            .unwrap();
+        engine.insert_contract(ADDRESS, SIMPLE_STORAGE.parse().unwrap());
+        let results = engine
+            .execute_calls(
+                ADDRESS,
+                vec![call(set(42)), call(hex::decode("6d4ce63c").unwrap().into())],
+                1,
+            )
+            .unwrap();
+
+        let document: chrome::ChromeTrace =
+            serde_json::from_str(results[1].trace_export.as_deref().unwrap()).unwrap();
+        let frame = document
+            .trace_events
+            .iter()
+            .find(|event| event.ph == 'X')
+            .unwrap();
+        assert_eq!(frame.name, "CALL 0x6d4ce63c");
+        assert_eq!(frame.tid, 1);
+        // After the first call
+        assert_eq!(frame.ts, Some(results[0].gas_used));
+
+        // Not without asking
+        let results = engine()
+            .execute_calls(ADDRESS, vec![call(set(1))], 1)
+            .unwrap();
+        assert!(results[0].trace_export.is_none());
+    }
+
+    #[test]
+    fn test_pretty_export() {
+        let options = ExecutionOptions::new(None, false, None, Some("pretty".to_string()), false);
+        let mut engine = EngineConfig::memory(StateDump::new(), options)
+            .build()
+            .unwrap();
+        engine.insert_contract(ADDRESS, SIMPLE_STORAGE.parse().unwrap());
+        let call = Call {
+            calldata: set(42),
+            value: U256::ZERO,
+            caller: Some(DEFAULT_DEPLOYER.into()),
+            independent: false,
+            signature: None,
+            args: None,
+            meta: None,
+        };
+        let result = engine
+            .execute_calls(ADDRESS, vec![call], 1)
+            .unwrap()
+            .remove(0);
+
+        let text = result.trace_export.unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(
+            lines[0],
+            format!(
+                "[{}] {}::60fe47b1({})",
+                result.traces.as_ref().unwrap()[0].gas_used,
+                ADDRESS,
+                hex::encode(U256::from(42).to_be_bytes::<32>())
+            )
+        );
+        assert_eq!(lines[1], "  └─ ← [Stop]");
+    }
+
+    #[test]
+    fn test_flamegraph_weighs_execution_gas() {
+        // STATICCALLs the contract at 0x3030...30 and returns what it did
+        let caller = "0x60206000600060007330303030303030303030303030303030303030305afa60206000f3";
+        // NUMBER, returned
+        let callee = "0x4360005260206000f3";
+        let options = ExecutionOptions::new(None, false, None, None, true);
+        let mut engine = EngineConfig::memory(StateDump::new(), options)
+            .build()
+            .unwrap();
+        engine.insert_contract(ADDRESS, caller.parse().unwrap());
+        engine.insert_contract(Address::repeat_byte(0x30), callee.parse().unwrap());
+        let call = Call {
+            calldata: Bytes::new(),
+            value: U256::ZERO,
+            caller: Some(DEFAULT_DEPLOYER.into()),
+            independent: false,
+            signature: None,
+            args: None,
+            meta: None,
+        };
+        let result = engine
+            .execute_calls(ADDRESS, vec![call], 1)
+            .unwrap()
+            .remove(0);
+
+        let folded = result.flamegraph.unwrap();
+        let stacks: Vec<_> = folded
+            .lines()
+            .map(|line| line.rsplit_once(' ').unwrap())
+            .collect();
+        let outer = format!("Root;{}.fallback", ADDRESS);
+        let inner = format!("{};{}.fallback", outer, Address::repeat_byte(0x30));
+        assert_eq!(
+            stacks.iter().map(|(stack, _)| *stack).collect::<Vec<_>>(),
+            [outer.as_str(), inner.as_str()]
+        );
+        // NUMBER, PUSH1, MSTORE and the word of memory it takes, PUSH1, PUSH1, RETURN
+        assert_eq!(stacks[1].1, "17");
+        // No calldata and nothing refunded, so only the base 21,000 is left out
+        let total: u64 = stacks
+            .iter()
+            .map(|(_, gas)| gas.parse::<u64>().unwrap())
+            .sum();
+        assert_eq!(total, result.gas_used - 21_000);
+    }
+
+    #[test]
+    fn test_state_diff() {
+        let options = ExecutionOptions {
+            state_diff: true,
+            ..Default::default()
+        };
+        let mut engine = EngineConfig::memory(StateDump::new(), Some(options))
+            .build()
+            .unwrap();
+        engine.insert_contract(ADDRESS, SIMPLE_STORAGE.parse().unwrap());
+        let call = |calldata: Bytes| Call {
+            calldata,
+            value: U256::ZERO,
+            caller: Some(DEFAULT_DEPLOYER.into()),
+            independent: false,
+            signature: None,
+            args: None,
+            meta: None,
+        };
+        let results = engine
+            .execute_calls(ADDRESS, vec![call(set(42)), call(set(7))], 1)
+            .unwrap();
+
+        let slot = |result: &ExecutionResult| {
+            let diff = result.state_diff.as_ref().unwrap();
+            // Nothing is paid for gas on the demo chain, so only the contract changed
+            assert_eq!(diff.keys().collect::<Vec<_>>(), [&ADDRESS]);
+            assert!(diff[&ADDRESS].balance.is_none());
+            diff[&ADDRESS].storage[&B256::ZERO]
+        };
+        assert_eq!(
+            slot(&results[0]),
+            Change {
+                from: U256::ZERO,
+                to: U256::from(42)
+            }
+        );
+        assert_eq!(
+            slot(&results[1]),
+            Change {
+                from: U256::from(42),
+                to: U256::from(7)
+            }
+        );
+
+        // Not without asking
+        let mut engine = engine();
+        engine.insert_contract(ADDRESS, SIMPLE_STORAGE.parse().unwrap());
+        let results = engine
+            .execute_calls(ADDRESS, vec![call(set(1))], 1)
+            .unwrap();
+        assert!(results[0].state_diff.is_none());
+    }
+
+    #[test]
+    fn test_access_list() {
+        let options = ExecutionOptions {
+            access_list: true,
+            ..Default::default()
+        };
+        let mut engine = EngineConfig::memory(StateDump::new(), Some(options))
+            .build()
+            .unwrap();
+        engine.insert_contract(ADDRESS, SIMPLE_STORAGE.parse().unwrap());
+        let call = |calldata: Bytes, independent| Call {
+            calldata,
+            value: U256::ZERO,
+            caller: Some(DEFAULT_DEPLOYER.into()),
+            independent,
+            signature: None,
+            args: None,
+            meta: None,
+        };
+        let results = engine
+            .execute_calls(
+                ADDRESS,
+                vec![
+                    call(set(42), false),
+                    call("0x6d4ce63c".parse().unwrap(), true),
+                ],
+                1,
+            )
+            .unwrap();
+
+        // Slot 0 written, then read; the caller and coinbase aren't listed
+        for result in &results {
+            assert_eq!(
+                result.access_list.as_ref().unwrap().0,
+                [AccessListItem {
+                    address: ADDRESS,
+                    storage_keys: vec![B256::ZERO],
+                }]
+            );
+        }
+    }
+
+    #[test]
+    fn test_snapshot_and_revert() {
+        let mut engine = engine();
+        engine.insert_contract(ADDRESS, SIMPLE_STORAGE.parse().unwrap());
+        let store = |engine: &mut Engine, value| {
+            engine
+                .execute_call(DEFAULT_DEPLOYER, ADDRESS, set(value), U256::ZERO)
+                .unwrap();
+        };
+
+        store(&mut engine, 1);
+        let first = engine.snapshot(2).unwrap();
+        store(&mut engine, 2);
+        let second = engine.snapshot(2).unwrap();
+        store(&mut engine, 3);
+        assert_eq!(get(&mut engine), word(3));
+        assert_eq!(engine.snapshot(2), None);
+
+        assert!(engine.revert(second));
+        assert_eq!(get(&mut engine), word(2));
+        assert!(engine.revert(first));
+        assert_eq!(get(&mut engine), word(1));
+        // Reverting again is fine, but what came after is gone
+        store(&mut engine, 4);
+        assert!(engine.revert(first));
+        assert_eq!(get(&mut engine), word(1));
+        assert!(!engine.revert(second));
+    }
+
+    #[test]
+    fn test_insert_contract_replaces_code() {
+        let mut engine = engine();
+        engine.insert_contract(ADDRESS, SIMPLE_STORAGE.parse().unwrap());
+        assert_eq!(get(&mut engine), word(0));
+
+        // SELFBALANCE, returned
+        engine.insert_contract(ADDRESS, "0x4760005260206000f3".parse().unwrap());
+        engine.set_balance(ADDRESS, U256::from(7)).unwrap();
+        assert_eq!(get(&mut engine), word(7));
+    }
+
+    #[test]
+    fn test_env_overrides_apply_to_one_call() {
+        let mut engine = engine();
+        // NUMBER, TIMESTAMP and COINBASE, returned side by side
+        engine.insert_contract(
+            ADDRESS,
+            "0x43600052426020524160405260606000f3".parse().unwrap(),
+        );
+        let coinbase = Address::repeat_byte(0xcb);
+        let overridden = engine
+            .execute_call_with(
+                DEFAULT_DEPLOYER,
+                ADDRESS,
+                Bytes::new(),
+                U256::ZERO,
+                &EnvOverrides {
+                    block_number: Some(1234),
+                    timestamp: Some(1_800_000_000),
+                    coinbase: Some(coinbase),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            overridden.result,
+            Bytes::from(
+                [
+                    word(1234).to_vec(),
+                    word(1_800_000_000).to_vec(),
+                    coinbase.into_word().to_vec(),
+                ]
+                .concat()
+            )
+        );
+
+        let plain = engine
+            .execute_call(DEFAULT_DEPLOYER, ADDRESS, Bytes::new(), U256::ZERO)
+            .unwrap();
+        assert_eq!(&plain.result[..32], &word(demo::DEMO_BLOCK_NUMBER)[..]);
+    }
+
+    #[test]
+    fn test_results_echo_their_env() {
+        let mut engine = engine();
+        // TIMESTAMP, returned
+        engine.insert_contract(ADDRESS, "0x4260005260206000f3".parse().unwrap());
+        let overridden = engine
+            .execute_call_with(
+                DEFAULT_DEPLOYER,
+                ADDRESS,
+                Bytes::new(),
+                U256::ZERO,
+                &EnvOverrides {
+                    timestamp: Some(1_800_000_000),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let env = overridden.env.unwrap();
+        assert_eq!(env.timestamp, 1_800_000_000);
+        assert_eq!(overridden.result, word(1_800_000_000));
+        assert_eq!(env.chain_id, demo::DEMO_CHAIN_ID);
+        assert_eq!(env.block_number, demo::DEMO_BLOCK_NUMBER);
+        assert_eq!(env.gas_limit, engine.context().gas_limit);
+
+        // The override was for that call alone
+        let plain = engine
+            .execute_call(DEFAULT_DEPLOYER, ADDRESS, Bytes::new(), U256::ZERO)
+            .unwrap();
+        let env = plain.env.unwrap();
+        assert_ne!(env.timestamp, 1_800_000_000);
+        assert_eq!(plain.result, word(env.timestamp));
+
+        // Calls run as a request's are echoed too
+        let call = Call {
+            calldata: Bytes::new(),
+            value: U256::ZERO,
+            caller: Some(DEFAULT_DEPLOYER.into()),
+            independent: true,
+            signature: None,
+            args: None,
+            meta: None,
+        };
+        let results = engine.execute_calls(ADDRESS, vec![call], 1).unwrap();
+        assert_eq!(results[0].env.as_ref().unwrap().timestamp, env.timestamp);
+    }
+
+    #[test]
+    fn test_gas_limit_override() {
+        let mut engine = engine();
+        engine.insert_contract(ADDRESS, SIMPLE_STORAGE.parse().unwrap());
+        // Not enough for the SSTORE
+        let starved = engine
+            .execute_call_with(
+                DEFAULT_DEPLOYER,
+                ADDRESS,
+                set(1),
+                U256::ZERO,
+                &EnvOverrides {
+                    gas_limit: Some(30_000),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert!(!starved.success);
+        assert_eq!(get(&mut engine), word(0));
+    }
+
+    #[test]
+    fn test_debug_traces_cap_gas() {
+        let options = ExecutionOptions {
+            journal: true,
+            ..Default::default()
+        };
+        let mut engine = EngineConfig::memory(StateDump::new(), Some(options))
+            .build()
+            .unwrap();
+        assert_eq!(engine.context().gas_limit, MAX_DEBUG_TRACE_GAS);
+        engine.insert_contract(ADDRESS, SIMPLE_STORAGE.parse().unwrap());
+
+        let result = engine
+            .execute_call(DEFAULT_DEPLOYER, ADDRESS, set(1), U256::ZERO)
+            .unwrap();
+        assert!(result.success);
+        assert_eq!(result.env.unwrap().gas_limit, MAX_DEBUG_TRACE_GAS);
+        // An override can lower the gas but not raise it past the cap
+        let result = engine
+            .execute_call_with(
+                DEFAULT_DEPLOYER,
+                ADDRESS,
+                set(2),
+                U256::ZERO,
+                &EnvOverrides {
+                    gas_limit: Some(MAX_DEBUG_TRACE_GAS * 2),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(result.env.unwrap().gas_limit, MAX_DEBUG_TRACE_GAS);
+
+        // Without opcodes recorded, the chain's gas limit
+        assert_eq!(engine().context().gas_limit, demo::context().gas_limit);
+    }
+
+    #[test]
+    fn test_hint_for_later_opcodes() {
+        // PUSH1 0x20 PUSH0 PUSH0 MCOPY STOP: Cancun's, run on Shanghai
+        let code: Bytes = "0x60205f5f5e00".parse().unwrap();
+        let mut shanghai = EngineConfig::memory(StateDump::new(), None)
+            .with_spec(SpecId::SHANGHAI)
+            .build()
+            .unwrap();
+        shanghai.insert_contract(ADDRESS, code.clone());
+        let result = shanghai
+            .execute_call(DEFAULT_DEPLOYER, ADDRESS, Bytes::new(), U256::ZERO)
+            .unwrap();
+        assert!(!result.success);
+        assert_eq!(result.exit_reason, crate::gas::ExitReason::InvalidOpcode);
+        let hint = result.hint.unwrap();
+        assert!(hint.contains("MCOPY"), "{}", hint);
+        assert!(hint.contains("evmVersion shanghai"), "{}", hint);
+
+        // Where the chain has it, it runs
+        let mut engine = engine();
+        engine.insert_contract(ADDRESS, code);
+        let result = engine
+            .execute_call(DEFAULT_DEPLOYER, ADDRESS, Bytes::new(), U256::ZERO)
+            .unwrap();
+        assert!(result.success);
+        assert_eq!(result.hint, None);
+    }
+
+    #[test]
+    fn test_labels() {
+        let weth = address!("4200000000000000000000000000000000000006");
+        let run = |labels: Option<BTreeMap<Address, String>>| {
+            let options = ExecutionOptions {
+                trace_export: Some("pretty".to_string()),
+                labels,
+                ..Default::default()
+            };
+            let mut env = demo::env();
+            env.cfg.chain_id = 8453;
+            let mut engine = EngineConfig::memory(StateDump::new(), Some(options))
+                .with_env(env)
+                .build()
+                .unwrap();
+            engine.insert_contract(weth, SIMPLE_STORAGE.parse().unwrap());
+            let call = Call {
+                calldata: hex::decode("6d4ce63c").unwrap().into(),
+                value: U256::ZERO,
+                caller: Some(DEFAULT_DEPLOYER.into()),
+                independent: false,
+                signature: None,
+                args: None,
+                meta: None,
+            };
+            engine.execute_calls(weth, vec![call], 1).unwrap().remove(0)
+        };
+
+        // Base's WETH predeploy, known without being told
+        let result = run(None);
+        assert_eq!(result.labels[&weth], "WETH");
+        assert!(result.trace_export.unwrap().contains("WETH::6d4ce63c"));
+        // Only what the call touched is echoed
+        assert!(result.labels.values().all(|label| label != "Multicall3"));
+
+        let result = run(Some(BTreeMap::from([(weth, "Wrapped Ether".to_string())])));
+        assert_eq!(result.labels[&weth], "Wrapped Ether");
+        assert!(result
+            .trace_export
+            .unwrap()
+            .contains("Wrapped Ether::6d4ce63c"));
+    }
+
+    #[test]
+    fn test_state_overrides() {
+        use alloy_rpc_types_eth::state::AccountOverride;
+        use std::collections::HashMap;
+
+        let storage = |slots: &[(u64, u64)]| -> Option<HashMap<B256, B256>> {
+            Some(
+                slots
+                    .iter()
+                    .map(|(slot, value)| (U256::from(*slot).into(), U256::from(*value).into()))
+                    .collect(),
+            )
+        };
+        let read = |engine: &mut Engine, slot: u64| {
+            engine
+                .execute_call(DEFAULT_DEPLOYER, ADDRESS, word(slot), U256::ZERO)
+                .unwrap()
+                .result
+        };
+        let mut engine = engine();
+        // Returns the slot named by its calldata
+        engine.insert_contract(ADDRESS, "0x6000355460005260206000f3".parse().unwrap());
+        engine
+            .override_state(&NamedStateOverride::from([(
+                ADDRESS.into(),
+                AccountOverride {
+                    state_diff: storage(&[(0, 1), (1, 2), (2, 3)]),
+                    ..Default::default()
+                },
+            )]))
+            .unwrap();
+
+        // A diff leaves the slots it doesn't name alone
+        let mut diffed = engine.clone();
+        diffed
+            .override_state(&NamedStateOverride::from([(
+                ADDRESS.into(),
+                AccountOverride {
+                    state_diff: storage(&[(1, 20)]),
+                    ..Default::default()
+                },
+            )]))
+            .unwrap();
+        assert_eq!(read(&mut diffed, 0), word(1));
+        assert_eq!(read(&mut diffed, 1), word(20));
+        assert_eq!(read(&mut diffed, 2), word(3));
+
+        // A full state wipes them
+        engine
+            .override_state(&NamedStateOverride::from([(
+                ADDRESS.into(),
+                AccountOverride {
+                    state: storage(&[(1, 20)]),
+                    ..Default::default()
+                },
+            )]))
+            .unwrap();
+        assert_eq!(read(&mut engine, 0), word(0));
+        assert_eq!(read(&mut engine, 1), word(20));
+        assert_eq!(read(&mut engine, 2), word(0));
+
+        // Code and balance, on an account that had neither; SELFBALANCE, returned
+        let other = Address::repeat_byte(0x21);
+        engine
+            .override_state(&NamedStateOverride::from([(
+                other.into(),
+                AccountOverride {
+                    code: Some("0x4760005260206000f3".parse().unwrap()),
+                    balance: Some(U256::from(7)),
+                    ..Default::default()
+                },
+            )]))
+            .unwrap();
+        let result = engine
+            .execute_call(DEFAULT_DEPLOYER, other, Bytes::new(), U256::ZERO)
+            .unwrap();
+        assert_eq!(result.result, word(7));
+
+        let both = AccountOverride {
+            state: storage(&[]),
+            state_diff: storage(&[]),
+            ..Default::default()
+        };
+        assert!(engine
+            .override_state(&NamedStateOverride::from([(ADDRESS.into(), both)]))
+            .is_err());
+    }
+}
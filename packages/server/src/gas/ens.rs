@@ -0,0 +1,408 @@
+use alloy_primitives::{address, keccak256, Address, B256, U256};
+use alloy_sol_types::{sol, SolCall};
+use forge::executors::Executor;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+use super::trace::TraceNode;
+
+// The ENS registry lives at the same address on mainnet and its testnets
+pub const ENS_REGISTRY: Address = address!("00000000000C2E074eC69A0dFb2997BA6C7d2e1e");
+
+// Chains where the canonical ENS registry is deployed
+const ENS_CHAIN_IDS: [u64; 3] = [1, 11155111, 17000];
+
+sol! {
+    function resolver(bytes32 node) external view returns (address);
+    function addr(bytes32 node) external view returns (address);
+    function name(bytes32 node) external view returns (string);
+}
+
+/// An address as accepted in requests: either a hex address or an ENS name such as `vitalik.eth`.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum NameOrAddress {
+    Address(Address),
+    Name(String),
+}
+
+impl From<Address> for NameOrAddress {
+    fn from(address: Address) -> Self {
+        NameOrAddress::Address(address)
+    }
+}
+
+impl FromStr for NameOrAddress {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("0x") || s.starts_with("0X") {
+            return Address::from_str(s)
+                .map(NameOrAddress::Address)
+                .map_err(|err| eyre::eyre!("invalid address {}: {}", s, err));
+        }
+        if s.contains('.') && !s.starts_with('.') && !s.ends_with('.') {
+            return Ok(NameOrAddress::Name(s.to_lowercase()));
+        }
+        Err(eyre::eyre!("expected an address or ENS name, got {}", s))
+    }
+}
+
+impl<'de> Deserialize<'de> for NameOrAddress {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct NameOrAddressVisitor;
+
+        impl<'de> Visitor<'de> for NameOrAddressVisitor {
+            type Value = NameOrAddress;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("an address or ENS name")
+            }
+
+            fn visit_str<E: de::Error>(self, s: &str) -> Result<NameOrAddress, E> {
+                s.parse().map_err(de::Error::custom)
+            }
+
+            // Binary formats send an address as its 20 bytes, as `Serialize` does
+            fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<NameOrAddress, E> {
+                if bytes.len() != 20 {
+                    return Err(de::Error::invalid_length(bytes.len(), &"20 bytes"));
+                }
+                Ok(NameOrAddress::Address(Address::from_slice(bytes)))
+            }
+        }
+
+        deserializer.deserialize_any(NameOrAddressVisitor)
+    }
+}
+
+impl Serialize for NameOrAddress {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            NameOrAddress::Address(address) => address.serialize(serializer),
+            NameOrAddress::Name(name) => serializer.serialize_str(name),
+        }
+    }
+}
+
+impl NameOrAddress {
+    pub fn as_name(&self) -> Option<&str> {
+        match self {
+            NameOrAddress::Name(name) => Some(name),
+            NameOrAddress::Address(_) => None,
+        }
+    }
+
+    pub fn as_address(&self) -> Option<Address> {
+        match self {
+            NameOrAddress::Address(address) => Some(*address),
+            NameOrAddress::Name(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for NameOrAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NameOrAddress::Address(address) => write!(f, "{}", address),
+            NameOrAddress::Name(name) => f.write_str(name),
+        }
+    }
+}
+
+/// A name in the request that couldn't be resolved, tagged with the request field it came from.
+#[derive(Debug)]
+pub struct EnsError {
+    pub field: String,
+    pub message: String,
+}
+
+impl fmt::Display for EnsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+impl std::error::Error for EnsError {}
+
+pub fn supports_ens(chain_id: u64) -> bool {
+    ENS_CHAIN_IDS.contains(&chain_id)
+}
+
+/// EIP-137 namehash.
+pub fn namehash(name: &str) -> B256 {
+    let mut node = B256::ZERO;
+    if name.is_empty() {
+        return node;
+    }
+    for label in name.rsplit('.') {
+        let mut buf = [0u8; 64];
+        buf[..32].copy_from_slice(node.as_slice());
+        buf[32..].copy_from_slice(keccak256(label.as_bytes()).as_slice());
+        node = keccak256(buf);
+    }
+    node
+}
+
+/// Lookups made during one request. Kept apart from `EnsResolver` so it can outlive the
+/// resolver's borrow of the executor between calls.
+#[derive(Default)]
+pub struct EnsCache {
+    forward: HashMap<String, Address>,
+    reverse: HashMap<Address, Option<String>>,
+}
+
+/// Resolves names and reverse-resolves addresses against the ENS registry visible to an executor,
+/// i.e. at the pinned fork block.
+pub struct EnsResolver<'a> {
+    executor: &'a Executor,
+    registry: Address,
+    cache: &'a mut EnsCache,
+}
+
+impl<'a> EnsResolver<'a> {
+    pub fn new(executor: &'a Executor, cache: &'a mut EnsCache) -> Self {
+        Self::with_registry(executor, cache, ENS_REGISTRY)
+    }
+
+    pub fn with_registry(
+        executor: &'a Executor,
+        cache: &'a mut EnsCache,
+        registry: Address,
+    ) -> Self {
+        Self {
+            executor,
+            registry,
+            cache,
+        }
+    }
+
+    fn static_call(&self, to: Address, calldata: Vec<u8>) -> Result<Vec<u8>, eyre::Error> {
+        let r = self
+            .executor
+            .call_raw(Address::ZERO, to, calldata.into(), U256::ZERO)?;
+        if r.reverted {
+            return Err(eyre::eyre!("call to {} reverted", to));
+        }
+        Ok(r.result.to_vec())
+    }
+
+    fn resolver_for(&self, node: B256) -> Result<Option<Address>, eyre::Error> {
+        let out = self.static_call(self.registry, resolverCall { node }.abi_encode())?;
+        let resolver = resolverCall::abi_decode_returns(&out, true)?._0;
+        Ok((resolver != Address::ZERO).then_some(resolver))
+    }
+
+    pub fn resolve(&mut self, name: &str) -> Result<Address, eyre::Error> {
+        if let Some(address) = self.cache.forward.get(name) {
+            return Ok(*address);
+        }
+        let node = namehash(name);
+        let resolver = self
+            .resolver_for(node)?
+            .ok_or_else(|| eyre::eyre!("ENS name {} has no resolver", name))?;
+        let out = self.static_call(resolver, addrCall { node }.abi_encode())?;
+        let address = addrCall::abi_decode_returns(&out, true)?._0;
+        if address == Address::ZERO {
+            return Err(eyre::eyre!(
+                "ENS name {} does not resolve to an address",
+                name
+            ));
+        }
+        self.cache.forward.insert(name.to_string(), address);
+        Ok(address)
+    }
+
+    pub fn resolve_field(
+        &mut self,
+        value: &NameOrAddress,
+        field: &str,
+    ) -> Result<Address, eyre::Error> {
+        match value {
+            NameOrAddress::Address(address) => Ok(*address),
+            NameOrAddress::Name(name) => self.resolve(name).map_err(|err| {
+                EnsError {
+                    field: field.to_string(),
+                    message: err.to_string(),
+                }
+                .into()
+            }),
+        }
+    }
+
+    /// Looks up the primary name of an address. Names that don't forward-resolve back to the
+    /// address are ignored, as the reverse record alone is not authoritative.
+    pub fn lookup(&mut self, address: Address) -> Option<String> {
+        if let Some(name) = self.cache.reverse.get(&address) {
+            return name.clone();
+        }
+        let name = self.lookup_uncached(address).ok().flatten();
+        self.cache.reverse.insert(address, name.clone());
+        name
+    }
+
+    fn lookup_uncached(&mut self, address: Address) -> Result<Option<String>, eyre::Error> {
+        let reverse_name = format!("{}.addr.reverse", alloy_primitives::hex::encode(address));
+        let node = namehash(&reverse_name);
+        let Some(resolver) = self.resolver_for(node)? else {
+            return Ok(None);
+        };
+        let out = self.static_call(resolver, nameCall { node }.abi_encode())?;
+        let name = nameCall::abi_decode_returns(&out, true)?._0;
+        if name.is_empty() || self.resolve(&name).ok() != Some(address) {
+            return Ok(None);
+        }
+        Ok(Some(name))
+    }
+
+    /// Reverse-resolves every caller and callee in the call trees.
+    pub fn label_traces(&mut self, traces: &[TraceNode]) -> BTreeMap<Address, String> {
+        let mut labels = BTreeMap::new();
+        for node in traces.iter().flat_map(TraceNode::iter) {
+            for address in [node.from, node.to] {
+                if labels.contains_key(&address) {
+                    continue;
+                }
+                if let Some(name) = self.lookup(address) {
+                    labels.insert(address, name);
+                }
+            }
+        }
+        labels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::solidity::{compile, SolidityFile};
+    use crate::config::Limits;
+    use crate::gas::{AccountDump, EngineConfig, NamedStateOverride, StateDump};
+    use alloy_primitives::{b256, Bytes};
+    use alloy_rpc_types_eth::state::AccountOverride;
+    use revm::DatabaseRef;
+
+    const MOCK_ENS: &str = r#"
+        pragma solidity ^0.8.0;
+
+        contract MockRegistry {
+            function resolver(bytes32) external pure returns (address) {
+                return 0x0000000000000000000000000000000000001234;
+            }
+        }
+
+        contract MockResolver {
+            function vitalik() internal pure returns (bytes32) {
+                bytes32 eth = keccak256(abi.encodePacked(bytes32(0), keccak256("eth")));
+                return keccak256(abi.encodePacked(eth, keccak256("vitalik")));
+            }
+
+            function addr(bytes32 node) external pure returns (address) {
+                if (node == vitalik()) {
+                    return 0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045;
+                }
+                return address(0);
+            }
+
+            function name(bytes32) external pure returns (string memory) {
+                return "vitalik.eth";
+            }
+        }
+    "#;
+
+    fn runtime_code(contracts: &crate::compile::solidity::CompileResult, name: &str) -> Bytes {
+        contracts
+            .contracts
+            .find_first(name)
+            .and_then(|c| c.bin_runtime.and_then(|b| b.as_bytes().cloned()))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_namehash() {
+        assert_eq!(namehash(""), B256::ZERO);
+        assert_eq!(
+            namehash("eth"),
+            b256!("93cdeb708b7545dc668eb9280176169d1c33cfd8ed6f04690a0bcc88a93fc4ae")
+        );
+    }
+
+    #[test]
+    fn test_parse_name_or_address() {
+        assert_eq!(
+            "vitalik.eth".parse::<NameOrAddress>().unwrap(),
+            NameOrAddress::Name("vitalik.eth".to_string())
+        );
+        assert!(matches!(
+            "0x1000000000000000000000000000000000000000".parse::<NameOrAddress>(),
+            Ok(NameOrAddress::Address(_))
+        ));
+        assert!("vitalik".parse::<NameOrAddress>().is_err());
+    }
+
+    #[test]
+    fn test_resolve_against_local_registry() {
+        let compiled = compile(
+            &[SolidityFile {
+                name: "MockENS.sol".to_string(),
+                content: MOCK_ENS.to_string(),
+            }],
+            &Limits::default(),
+        )
+        .unwrap();
+
+        let code = |name| AccountDump {
+            code: runtime_code(&compiled, name),
+            ..Default::default()
+        };
+        let mut engine = EngineConfig::memory(
+            StateDump::from([
+                (ENS_REGISTRY, code("MockRegistry")),
+                (
+                    address!("0000000000000000000000000000000000001234"),
+                    code("MockResolver"),
+                ),
+            ]),
+            None,
+        )
+        .build()
+        .unwrap();
+
+        let vitalik = address!("d8dA6BF26964aF9D7eEd9e03E53415D37aA96045");
+        let mut cache = EnsCache::default();
+        let mut resolver = EnsResolver::new(engine.executor(), &mut cache);
+        assert_eq!(resolver.resolve("vitalik.eth").unwrap(), vitalik);
+        assert_eq!(resolver.lookup(vitalik), Some("vitalik.eth".to_string()));
+
+        let err = resolver
+            .resolve_field(
+                &NameOrAddress::Name("nobody.eth".to_string()),
+                "calls[0].caller",
+            )
+            .unwrap_err();
+        assert!(err.to_string().starts_with("calls[0].caller:"));
+
+        // Names stand for the contract's address and state overrides' accounts too
+        let name: NameOrAddress = "vitalik.eth".parse().unwrap();
+        assert_eq!(engine.resolve_name(&name, "address").unwrap(), vitalik);
+        let overrides = NamedStateOverride::from([(
+            name,
+            AccountOverride {
+                balance: Some(U256::from(7)),
+                ..Default::default()
+            },
+        )]);
+        engine.override_state(&overrides).unwrap();
+        let account = engine.executor().backend().basic_ref(vitalik).unwrap();
+        assert_eq!(account.unwrap().balance, U256::from(7));
+
+        let overrides = NamedStateOverride::from([(
+            NameOrAddress::Name("nobody.eth".to_string()),
+            AccountOverride::default(),
+        )]);
+        let err = engine.override_state(&overrides).unwrap_err();
+        assert!(err.to_string().starts_with("stateOverrides.nobody.eth:"));
+    }
+}
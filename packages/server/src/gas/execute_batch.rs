@@ -0,0 +1,87 @@
+use alloy_primitives::{Address, Bytes, B256};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tracing::{Instrument, Span};
+
+use super::ens::NameOrAddress;
+use super::execute_calldatas_fork::{
+    prepare_fork, run_blocking, uses_names, Call, ExecutionOptions, ExecutionResult,
+    NamedStateOverride,
+};
+use super::resolve::ResolvedFork;
+use super::templates;
+use crate::config::AppConfig;
+
+/// One independent set of calls against its own copy of the forked state.
+#[derive(Clone, Debug)]
+pub struct Scenario {
+    pub bytecode: Bytes,
+    pub address: NameOrAddress,
+    pub calls: Vec<Call>,
+    /// The keys its calls' `$sign712` templates sign with
+    pub signers: BTreeMap<Address, B256>,
+    /// Applied to its copy of the fork only
+    pub state_overrides: Option<NamedStateOverride>,
+}
+
+/// Forks once and runs every scenario against a clone of the same engine, so they share the
+/// fork's RPC cache but not each other's state changes. At most `concurrency` scenarios run at a
+/// time. The outer error is a fork setup failure; per-scenario failures are returned in place.
+pub async fn execute_batch_fork(
+    config: &AppConfig,
+    scenarios: Vec<Scenario>,
+    fork: ResolvedFork,
+    options: Option<ExecutionOptions>,
+    concurrency: usize,
+) -> Result<Vec<Result<Vec<ExecutionResult>, eyre::Error>>, eyre::Error> {
+    let uses_names = scenarios.iter().any(|scenario| {
+        scenario.address.as_name().is_some()
+            || uses_names(&scenario.calls)
+            || scenario
+                .state_overrides
+                .iter()
+                .flatten()
+                .any(|(account, _)| account.as_name().is_some())
+    });
+    let fork = prepare_fork(config, fork, options, uses_names).await?;
+    let engine = run_blocking(move || fork.build()).await?;
+    let parallelism = config.limits.max_parallel_calls;
+
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    let handles: Vec<_> = scenarios
+        .into_iter()
+        .map(|scenario| {
+            let semaphore = semaphore.clone();
+            let mut engine = engine.clone();
+            tokio::spawn(
+                async move {
+                    let _permit = semaphore.acquire_owned().await?;
+                    let span = Span::current();
+                    tokio::task::spawn_blocking(move || {
+                        let _span = span.entered();
+                        let calls = templates::encode_calls(
+                            scenario.calls,
+                            &scenario.signers,
+                            engine.context().chain_id,
+                        )?;
+                        let address = engine.resolve_name(&scenario.address, "address")?;
+                        engine.insert_contract(address, scenario.bytecode);
+                        if let Some(overrides) = &scenario.state_overrides {
+                            engine.override_state(overrides)?;
+                        }
+                        engine.execute_calls(address, calls, parallelism)
+                    })
+                    .await?
+                }
+                .in_current_span(),
+            )
+        })
+        .collect();
+
+    let mut results = Vec::with_capacity(handles.len());
+    for handle in handles {
+        results.push(handle.await.map_err(eyre::Error::from).and_then(|r| r));
+    }
+    Ok(results)
+}
@@ -4,27 +4,54 @@ use revm::{
     primitives::{Bytecode, ExecutionResult},
     InMemoryDB,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
 
 use super::{deploy, transact};
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, ToSchema)]
 pub struct Call {
+    #[schema(value_type = Option<String>)]
     pub calldata: Option<Bytes>,
+    #[schema(value_type = Option<String>)]
     pub value: Option<U256>,
+    #[schema(value_type = Option<String>)]
     pub caller: Option<Address>,
+    /// Anything, echoed back on the call's result
+    #[schema(value_type = Option<Object>)]
+    pub meta: Option<Value>,
+}
+
+/// revm's result for a call, with where it was in the request and the `meta` it was sent with.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CallResult {
+    #[serde(flatten)]
+    pub result: ExecutionResult,
+    pub call_index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<Value>,
 }
 
 pub fn execute_calldatas(
     bytecode: Bytecode,
     calls: Vec<Call>,
-) -> Result<Vec<ExecutionResult>, eyre::Error> {
+) -> Result<Vec<CallResult>, eyre::Error> {
     let mut db = CacheDB::new(InMemoryDB::default());
 
     let address = deploy(bytecode.bytes(), &mut db)?;
 
     calls
         .into_iter()
-        .map(|call| transact(address, call.calldata, call.value, call.caller, &mut db))
+        .enumerate()
+        .map(|(call_index, call)| {
+            let result = transact(address, call.calldata, call.value, call.caller, &mut db)?;
+            Ok(CallResult {
+                result,
+                call_index,
+                meta: call.meta,
+            })
+        })
         .collect()
 }
@@ -1,288 +1,1320 @@
-use dotenv::dotenv;
-use once_cell::sync::Lazy;
-use std::collections::HashMap;
-use std::env;
-
 use alloy::providers::{Provider, ProviderBuilder};
+use alloy_eips::eip2930::AccessList;
 use alloy_eips::BlockId;
-use alloy_primitives::{Address, Bytes, Log, U256};
-use alloy_rpc_types_eth::BlockTransactionsKind;
+use alloy_json_abi::Error;
+use alloy_primitives::{address, Address, Bytes, B256, U256};
+use alloy_rpc_types_eth::state::AccountOverride;
+use alloy_rpc_types_eth::{Block, BlockTransactionsKind};
 use forge::{
-    backend::{self},
-    executors::ExecutorBuilder,
+    executors::RawCallResult,
     opts::EvmOpts,
     traces::{CallTraceArena, TraceMode},
 };
-use foundry_config::Config;
-use revm::{interpreter::InstructionResult, primitives::TxEnv};
-use revm_primitives::{AccountInfo, BlockEnv, Bytecode, CfgEnv, Env};
+use revm::primitives::TxEnv;
+use revm_primitives::{BlockEnv, CfgEnv, Env, SpecId};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::{debug, Span};
+use utoipa::ToSchema;
+
+use super::anvil;
+use super::breakpoint::{breakpoint_pcs, Breakpoint, PausedAt};
+use super::consistency::{self, ConsistencyCheck};
+use super::created::CreatedContract;
+use super::demo;
+use super::engine::{CallEnv, Deployment, EngineConfig};
+use super::ens::{supports_ens, NameOrAddress};
+use super::exit::ExitReason;
+use super::hardfork;
+use super::internal_frames::InternalFrame;
+use super::journal::{JournalEvent, SourceLocation};
+use super::log::EventLog;
+use super::raw_transaction::{self, RawTransactionOutcome};
+use super::resolve::{ForkSource, ResolvedFork};
+use super::rpc_diagnostics::{self, RpcDiagnostics};
+use super::rpc_pool;
+use super::state_diff::AccountDiff;
+use super::templates;
+use super::trace::TraceNode;
+use super::transient::TransientAccess;
+use super::tx_validity::TxValidity;
+use crate::config::{AppConfig, RpcEndpoint};
+use crate::decode::{decode_revert, RevertReason};
+use crate::telemetry::redact_url;
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug, ToSchema)]
+#[schema(as = ForkCall)]
 pub struct Call {
+    /// Left out when the call gives `signature` and `args` instead
+    #[serde(default)]
+    #[schema(value_type = String)]
     pub calldata: Bytes,
+    #[schema(value_type = String)]
     pub value: U256,
-    pub caller: Address,
+    /// An address, or an ENS name on chains that support it. Can be left out when the request
+    /// has a `defaultCaller`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub caller: Option<NameOrAddress>,
+    /// Neither reads what the calls before it write nor writes anything itself. A run of these
+    /// goes concurrently, each call on its own copy of the state, and one that changes state
+    /// fails the request.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub independent: bool,
+    /// The function to call in place of `calldata`, e.g. `transfer(address,uint256)`, encoded
+    /// with `args` once the fork is up
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// `signature`'s arguments, as `/abi/encode` takes them. One can be a `$sign712` template,
+    /// which stands for the `v`, `r` and `s` of an EIP-712 signature by one of `signers`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Vec<Object>>)]
+    pub args: Option<Vec<Value>>,
+    /// Anything, echoed back as the result's `meta`, so results can be matched to calls however
+    /// the client reorders them. Capped at `maxCallMetaBytes` of JSON.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub meta: Option<Value>,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ForkConfig {
     pub rpc_url: Option<String>,
     pub chain_id: Option<u64>,
     pub block_number: Option<u64>,
+    pub mode: Option<String>, // "rpc" (default) or "anvil"
+    #[schema(value_type = Option<Vec<String>>)]
+    pub funded_accounts: Option<Vec<Address>>, // only used in anvil mode
+    /// `demo` runs on a chain bundled with the server instead of a fork, with no RPC involved.
+    /// It can't be combined with `rpcUrl` or `mode`.
+    pub network: Option<String>,
+    /// How many blocks behind the head to fork when `blockNumber` isn't given. The chain's
+    /// default without one (5 on Polygon, 1 elsewhere); 0 forks the head itself.
+    pub confirmation_lag: Option<u64>,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+/// An `eth_call` state override set, with ENS names allowed for the accounts on chains that have
+/// ENS.
+pub type NamedStateOverride = BTreeMap<NameOrAddress, AccountOverride>;
+
+#[derive(Deserialize, Clone, Debug, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct ExecutionOptions {
-    pub trace_mode: Option<String>, // "call", "jump", "jumpSimple", "debug", "none"
+    /// `call`, `jump`, `jumpSimple`, `debug` or `none`. `debug` records every opcode, and
+    /// `journal`, `transient_storage` and `breakpoints` record them whatever this says. Calls
+    /// recording opcodes get at most `MAX_DEBUG_TRACE_GAS`, which bounds how many are kept.
+    pub trace_mode: Option<String>,
+    /// Also return forge's trace arena as `rawTraces`. Deprecated, kept while clients move to
+    /// `traces`.
+    #[serde(default)]
+    pub include_raw_traces: bool,
+    /// Record the events calls emit. Defaults to true.
+    pub collect_logs: Option<bool>,
+    /// Also return each call's trace in `traceExport`: `chrome` for Trace Event Format JSON,
+    /// `pretty` for forge's text tree, `mermaid` for a sequence diagram
+    pub trace_export: Option<String>,
+    /// How many calls deep `mermaid` draws. All of them when missing.
+    pub trace_export_depth: Option<usize>,
+    /// Also return each call's trace as gas-weighted folded stacks in `flamegraph`
+    #[serde(default)]
+    pub flamegraph: bool,
+    /// Also return the balances and storage each call changed in `stateDiff`
+    #[serde(default)]
+    pub state_diff: bool,
+    /// Also return the accounts and slots each call touched in `accessList`
+    #[serde(default)]
+    pub access_list: bool,
+    /// Also run each call through the fork's RPC and report how it compares in
+    /// `consistencyCheck`
+    #[serde(default)]
+    pub consistency_check: bool,
+    /// Run calls under this hardfork's rules, e.g. `istanbul`, whatever the chain and block.
+    /// The latest without one.
+    pub hardfork: Option<String>,
+    /// Accounts to change once forked, before any call runs, as `eth_call` takes them
+    pub state_overrides: Option<NamedStateOverride>,
+    /// Also return what each call did, event by event, in `journal`
+    #[serde(default)]
+    pub journal: bool,
+    /// Also return each call's TLOADs and TSTOREs in `transientStorageAccesses`
+    #[serde(default)]
+    pub transient_storage: bool,
+    /// Also return what the address of each contract a call created was derived from, in
+    /// `created`
+    #[serde(default)]
+    pub deterministic_addresses: bool,
+    /// Also read the revert data of every frame in `traces` that reverted into its
+    /// `revertReason`, a revert its caller caught included, and cut each frame's `output` to
+    /// 24 KiB, with `outputLength` on those that were
+    #[serde(default)]
+    pub capture_subcall_outputs: bool,
+    /// The custom errors `capture_subcall_outputs` reads revert data as. Set by the routes, from
+    /// the ABIs they have.
+    #[serde(skip)]
+    pub errors: Option<Vec<Error>>,
+    /// Stop each call at the first opcode of the called contract compiled from one of these
+    /// lines, returning where as `pausedAt` and keeping none of what the call changed. Needs
+    /// `source_locations`.
+    pub breakpoints: Option<Vec<Breakpoint>>,
+    /// Where each opcode of the called contract's deployed code was compiled from, by pc. Set by
+    /// the routes that compiled it.
+    #[serde(skip)]
+    pub source_locations: Option<BTreeMap<usize, SourceLocation>>,
+    /// Names for addresses, over the well-known ones for the chain
+    pub labels: Option<BTreeMap<Address, String>>,
+    /// Private keys `$sign712` templates in calls' `args` sign with, by their address
+    pub signers: Option<BTreeMap<Address, B256>>,
+    /// Run a call again, up to this many more times, when it fails on a transient RPC error
+    /// loading state
+    #[serde(default)]
+    pub retries: u32,
+    /// Once this passes, calls not yet started are skipped rather than run. The first always
+    /// runs. Set by the routes, from when the request came in.
+    #[serde(skip)]
+    pub deadline: Option<Instant>,
+}
+
+impl ExecutionOptions {
+    /// `None` when all are left at their defaults.
+    pub fn new(
+        trace_mode: Option<String>,
+        include_raw_traces: bool,
+        collect_logs: Option<bool>,
+        trace_export: Option<String>,
+        flamegraph: bool,
+    ) -> Option<Self> {
+        (trace_mode.is_some()
+            || include_raw_traces
+            || collect_logs.is_some()
+            || trace_export.is_some()
+            || flamegraph)
+            .then_some(ExecutionOptions {
+                trace_mode,
+                include_raw_traces,
+                collect_logs,
+                trace_export,
+                trace_export_depth: None,
+                flamegraph,
+                state_diff: false,
+                access_list: false,
+                consistency_check: false,
+                hardfork: None,
+                state_overrides: None,
+                journal: false,
+                transient_storage: false,
+                deterministic_addresses: false,
+                capture_subcall_outputs: false,
+                errors: None,
+                breakpoints: None,
+                source_locations: None,
+                labels: None,
+                signers: None,
+                retries: 0,
+                deadline: None,
+            })
+    }
+
+    /// Whether any state override is for an ENS name.
+    pub fn uses_names(&self) -> bool {
+        self.state_overrides
+            .iter()
+            .flatten()
+            .any(|(account, _)| account.as_name().is_some())
+    }
+
+    /// `options`, with calls run under `hardfork` when one is given.
+    pub fn with_hardfork(options: Option<Self>, hardfork: Option<String>) -> Option<Self> {
+        match hardfork {
+            None => options,
+            hardfork => Some(ExecutionOptions {
+                hardfork,
+                ..options.unwrap_or_default()
+            }),
+        }
+    }
+
+    /// `options`, with `created` asked for when `deterministic_addresses` is.
+    pub fn with_deterministic_addresses(
+        options: Option<Self>,
+        deterministic_addresses: bool,
+    ) -> Option<Self> {
+        match deterministic_addresses {
+            false => options,
+            true => Some(ExecutionOptions {
+                deterministic_addresses,
+                ..options.unwrap_or_default()
+            }),
+        }
+    }
+
+    /// `options`, with calls skipped once `deadline` passes when one is given.
+    pub fn with_deadline(options: Option<Self>, deadline: Option<Instant>) -> Option<Self> {
+        match deadline {
+            None => options,
+            deadline => Some(ExecutionOptions {
+                deadline,
+                ..options.unwrap_or_default()
+            }),
+        }
+    }
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+/// Byte fields are hex strings in JSON and raw binary in MessagePack and CBOR.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ExecutionResult {
-    pub exit_reason: InstructionResult,
+    /// Which of the request's calls this is. Missing for results that aren't of a call.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub call_index: Option<usize>,
+    /// The call's `meta`, as it was sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub meta: Option<Value>,
+    /// `success`, `revert`, `outOfGas`, `invalidOpcode`, ... or revm's name for anything else
+    #[schema(value_type = String)]
+    pub exit_reason: ExitReason,
+    pub success: bool,
     pub reverted: bool,
+    #[schema(value_type = String)]
     pub result: Bytes,
+    /// `result` decoded, when the call reverted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revert_reason: Option<RevertReason>,
     pub gas_used: u64,
-    pub logs: Vec<Log>,
-    pub traces: CallTraceArena,
+    /// The gas the call was given: the fork block's gas limit, capped at `MAX_CALL_GAS`. Missing
+    /// outside forks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gas_limit: Option<u64>,
+    /// The balance of the account called, before the call. Missing for contract creations and
+    /// results that didn't come from running the call here.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub target_balance_before: Option<U256>,
+    /// And after it, with any value it was sent. A call that reverted leaves it as it was.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub target_balance_after: Option<U256>,
+    /// Empty with `collectLogs: false`
+    pub logs: Vec<EventLog>,
+    /// The call tree. Missing with `traceMode: "none"`, which skips tracing altogether.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub traces: Option<Vec<TraceNode>>,
+    /// Only with `includeRawTraces`. Deprecated, its shape follows forge.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<TraceArena>)]
+    pub raw_traces: Option<CallTraceArena>,
+    /// Names for the addresses the call touched: the request's `labels`, well-known contracts
+    /// on the chain, and ENS names where ENS is deployed, in that order of preference
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[schema(value_type = BTreeMap<String, String>)]
+    pub labels: BTreeMap<Address, String>,
+    /// Only with `traceExport`. For `chrome`, the call tree, and with `traceMode: "debug"` the
+    /// opcodes, as a Trace Event Format document for chrome://tracing or ui.perfetto.dev. Gas is
+    /// the time axis. Each call is on its own track and starts after the gas of the calls before
+    /// it, so the `traceEvents` of every result together make up the whole request. For
+    /// `pretty`, the call tree as `forge test -vvvv` prints it. For `mermaid`, a
+    /// `sequenceDiagram` of the calls and what they returned or why they reverted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_export: Option<String>,
+    /// Only with `flamegraph`: the call tree as folded stacks for `inferno` or speedscope, a
+    /// `Root;<contract>.<selector>;... <gas>` line per stack weighted by the gas spent in its
+    /// innermost frame. Contracts are named by their labels where they have one. The weights add
+    /// up to `gasUsed` less the intrinsic cost and refunds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flamegraph: Option<String>,
+    /// Only with `stateDiff`: the accounts whose balance or storage the call changed, with
+    /// what changed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<BTreeMap<String, AccountDiff>>)]
+    pub state_diff: Option<BTreeMap<Address, AccountDiff>>,
+    /// Only with `accessList`: the accounts and storage slots the call touched, as
+    /// `eth_createAccessList` lists them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Vec<Object>>)]
+    pub access_list: Option<AccessList>,
+    /// Only with `consistencyCheck`: how the fork's node saw the same call
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consistency_check: Option<ConsistencyCheck>,
+    /// The block and transaction environment the call ran in, after any overrides
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env: Option<CallEnv>,
+    /// What a node would refuse about the call sent as a transaction: the sender not having the
+    /// ETH for its gas and value, the sender being a contract, a signed transaction's nonce, or
+    /// deployment code over the size limit. The call runs either way. Missing for results that
+    /// didn't come from running the call here.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx_validity: Option<TxValidity>,
+    /// Only with `retries`: how many times the call ran, counting the run a transient RPC
+    /// failure cut short
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attempts: Option<u32>,
+    /// Set when the call never ran, with why: `deadlineExceeded` when the request ran out of
+    /// time first. Its `exitReason` is then `skipped` and everything it would have done is
+    /// empty.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<SkipReason>,
+    /// Why an `invalidOpcode` exit may have happened: an opcode the call ran into that's from a
+    /// later hardfork than the chain's, with what to do about it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+    /// Only with `journal`: what the call did that changes state, in order, each event with the
+    /// opcode it happened at. Cut off after 10,000 events, with a `truncated` event saying how
+    /// many more there were.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Vec<Object>>)]
+    pub journal: Option<Vec<JournalEvent>>,
+    /// Only with `transientStorage`, from Cancun on: every TLOAD and TSTORE the call ran, in
+    /// order, up to 10,000. Before Cancun the opcodes don't exist, which `hint` explains.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transient_storage_accesses: Option<Vec<TransientAccess>>,
+    /// Only with `deterministicAddresses`: the contracts the call created, in order, each with
+    /// the deployer and nonce its address was derived from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<Vec<CreatedContract>>,
+    /// Only with `breakpoints`, for a call that reached one: where it stopped, the stack there
+    /// and what it had written to storage. Its `exitReason` is then `paused` and `result` is
+    /// empty. Its traces and gas are still those of the whole call, which ran on to the end to
+    /// find where it stopped, but none of what it changed was kept.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paused_at: Option<PausedAt>,
+    /// Only from `/run` with `internalFrames`: the functions the deployed contract jumped into
+    /// within each of its calls, as a tree under each call
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Vec<Object>>)]
+    pub internal_frames: Option<Vec<InternalFrame>>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum SkipReason {
+    /// The request's deadline passed before the call's turn came
+    DeadlineExceeded,
 }
 
-// Define a static mapping of chain IDs to RPC URLs loaded from environment variables
-static CHAIN_RPC_URLS: Lazy<HashMap<u64, String>> = Lazy::new(|| {
-    let mut map = HashMap::new();
+/// Where the fork points at, echoed back to clients.
+#[derive(Serialize, Clone, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ForkContext {
+    pub chain_id: u64,
+    pub block_number: u64,
+    /// The gas each call is given
+    pub gas_limit: u64,
+    /// The hardfork calls run under: `hardfork` when it was given, `latest` otherwise
+    pub hardfork: String,
+    /// How many blocks behind the head the fork was taken, when no block was named. 0 when one
+    /// was.
+    pub confirmation_lag: u64,
+    /// What the fork's node says about itself, for telling a stale fork from a lagging or
+    /// pruned node. Missing off forks.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rpc_diagnostics: Option<RpcDiagnostics>,
+}
 
-    // Add Base (8453)
-    if let Ok(rpc) = env::var("BASE_RPC") {
-        map.insert(8453, rpc);
-    }
+#[derive(Serialize, Clone, Debug, Default, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Timings {
+    pub fork_setup_ms: u64,
+    pub execution_ms: u64,
+    /// How many more times the fork block was asked for because the RPC didn't have it yet, as
+    /// when a load balancer sends the request to a node behind the others
+    pub block_retries: u32,
+}
 
-    // Add Ethereum (1)
-    if let Ok(rpc) = env::var("ETH_RPC") {
-        map.insert(1, rpc);
-    }
+/// How many more times a fork block the RPC says doesn't exist is asked for, the wait doubling
+/// from `BLOCK_RETRY_DELAY` each time.
+const BLOCK_RETRIES: u32 = 3;
+const BLOCK_RETRY_DELAY: Duration = Duration::from_millis(100);
 
-    // Add Arbitrum (42161)
-    if let Ok(rpc) = env::var("ARBITRUM_RPC") {
-        map.insert(42161, rpc);
-    }
+/// Fork setup failures that callers may want to tell apart from execution errors.
+#[derive(Debug)]
+pub enum ForkError {
+    UnknownMode(String),
+    UnknownNetwork(String),
+    UnsupportedChain(u64),
+    EnsUnsupported(u64),
+    BlockNotFound,
+    InvalidBlock(String),
+    BlockInFuture {
+        requested: u64,
+        head: u64,
+    },
+    /// Settings that contradict each other
+    Conflict {
+        field: &'static str,
+        message: String,
+    },
+}
 
-    // Add Optimism (10)
-    if let Ok(rpc) = env::var("OPTIMISM_RPC") {
-        map.insert(10, rpc);
+impl ForkError {
+    /// The request field at fault, as a path from the request body.
+    pub fn field(&self) -> Option<&'static str> {
+        match self {
+            ForkError::UnknownMode(_) => Some("forkConfig.mode"),
+            ForkError::UnknownNetwork(_) => Some("forkConfig.network"),
+            ForkError::UnsupportedChain(_) => Some("forkConfig.chainId"),
+            ForkError::BlockInFuture { .. } => Some("forkConfig.blockNumber"),
+            ForkError::Conflict { field, .. } => Some(field),
+            ForkError::EnsUnsupported(_)
+            | ForkError::BlockNotFound
+            | ForkError::InvalidBlock(_) => None,
+        }
     }
+}
 
-    if let Ok(rpc) = env::var("POLYGON_RPC") {
-        map.insert(137, rpc);
+impl fmt::Display for ForkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ForkError::UnknownMode(mode) => write!(f, "Unknown fork mode {}", mode),
+            ForkError::UnknownNetwork(network) => {
+                write!(f, "Unknown network {}, expected {}", network, demo::NETWORK)
+            }
+            ForkError::UnsupportedChain(chain_id) => {
+                write!(f, "No RPC URL configured for chain ID {}", chain_id)
+            }
+            ForkError::EnsUnsupported(chain_id) => {
+                write!(f, "ENS names are not supported on chain ID {}", chain_id)
+            }
+            ForkError::BlockNotFound => write!(f, "block not found"),
+            ForkError::InvalidBlock(reason) => write!(f, "Invalid fork block: {}", reason),
+            ForkError::BlockInFuture { requested, head } => write!(
+                f,
+                "Block {} is past the chain's head, block {}",
+                requested, head
+            ),
+            ForkError::Conflict { message, .. } => write!(f, "{}", message),
+        }
     }
+}
+
+impl std::error::Error for ForkError {}
+
+/// A call marked `independent` that changed state anyway. Independent calls each run on a copy of
+/// the state, so what it changed would have been lost.
+#[derive(Debug)]
+pub struct NotIndependent {
+    pub index: usize,
+    /// The first account it changed
+    pub account: Address,
+}
 
-    if let Ok(rpc) = env::var("BNB_RPC") {
-        map.insert(56, rpc);
+impl NotIndependent {
+    pub fn field(&self) -> String {
+        format!("calls[{}].independent", self.index)
     }
+}
 
-    if let Ok(rpc) = env::var("AVALANCHE_RPC") {
-        map.insert(43114, rpc);
+impl fmt::Display for NotIndependent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "calls[{}] is marked independent but changed the state of {}",
+            self.index, self.account
+        )
     }
+}
 
-    map
-});
+impl std::error::Error for NotIndependent {}
+
+/// Runs signed transactions, as `eth_sendRawTransaction` takes them, one after the other on a fork
+/// with `deployed_bytes` placed at `address`. A transaction that can't be decoded, recovered or
+/// run fails alone, and the ones after it still run.
+pub async fn execute_raw_transactions_fork(
+    config: &AppConfig,
+    deployed_bytes: Bytes,
+    address: NameOrAddress,
+    transactions: Vec<Bytes>,
+    fork: ResolvedFork,
+    options: Option<ExecutionOptions>,
+) -> Result<(ForkContext, Timings, Vec<RawTransactionOutcome>), eyre::Error> {
+    let started = Instant::now();
+    let overrides = options
+        .as_ref()
+        .and_then(|opts| opts.state_overrides.clone());
+    let deadline = deadline(options.as_ref());
+    let uses_names =
+        address.as_name().is_some() || options.as_ref().is_some_and(|opts| opts.uses_names());
+    let fork = prepare_fork(config, fork, options, uses_names).await?;
+    let context = fork.context.clone();
+    let block_retries = fork.block_retries;
+    let (timings, outcomes) = run_blocking(move || {
+        let mut engine = fork.build()?;
+        let fork_setup_ms = started.elapsed().as_millis() as u64;
+        let address = engine.resolve_name(&address, "address")?;
+        engine.insert_contract(address, deployed_bytes);
+        if let Some(overrides) = &overrides {
+            engine.override_state(overrides)?;
+        }
+
+        let started = Instant::now();
+        let outcomes: Vec<_> = transactions
+            .iter()
+            .enumerate()
+            .map(|(index, raw)| {
+                // As with calls, the first always runs
+                if index > 0 && deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    raw_transaction::skipped(index, raw, SkipReason::DeadlineExceeded)
+                } else {
+                    raw_transaction::run(&mut engine, index, raw)
+                }
+            })
+            .collect();
+        let timings = Timings {
+            fork_setup_ms,
+            execution_ms: started.elapsed().as_millis() as u64,
+            block_retries,
+        };
+        Ok((timings, outcomes))
+    })
+    .await?;
+    Ok((context, timings, outcomes))
+}
+
+// Sender used when deploying creation code into the fork
+pub const DEFAULT_DEPLOYER: Address = address!("1804c8AB1F12E6bbf3894d4083f33e07309d1f38");
 
 pub async fn execute_calldatas_fork(
+    config: &AppConfig,
     deployed_bytes: Bytes,
-    address: Address,
+    address: NameOrAddress,
     calls: Vec<Call>,
-    fork_config: Option<ForkConfig>,
+    fork: ResolvedFork,
     options: Option<ExecutionOptions>,
 ) -> Result<Vec<ExecutionResult>, eyre::Error> {
-    dotenv().ok();
-
-    // Debug log the fork config
-    println!("Fork config: {:?}", fork_config);
-    println!("Execution options: {:?}", options);
-
-    // Get RPC URL from fork config or environment variable
-    let rpc = match &fork_config {
-        // If custom RPC URL is provided, use it
-        Some(config) if config.rpc_url.is_some() => {
-            let url = config.rpc_url.clone().unwrap();
-            println!("Using custom RPC URL: {}", url);
-            url
+    let (_, _, results) = execute_calldatas_fork_with(
+        config,
+        deployed_bytes,
+        address,
+        calls,
+        fork,
+        options,
+        |_| Ok(()),
+    )
+    .await?;
+    Ok(results)
+}
+
+/// What `execute_calldatas_fork_with` reports as it goes.
+#[derive(Debug)]
+pub enum ForkProgress<'a> {
+    /// The fork is up, before any call runs
+    Forked(&'a ForkContext),
+    /// A call completed
+    Result(usize, &'a ExecutionResult),
+}
+
+// What the blocking half of `execute_calldatas_fork_with` hands back as it goes
+enum Step {
+    Forked,
+    Result(usize, ExecutionResult),
+}
+
+/// `execute_calldatas_fork`, calling `on_progress` once the fork is up and as each call completes
+/// so results can be streamed. An error from `on_progress` stops execution.
+pub async fn execute_calldatas_fork_with<F>(
+    config: &AppConfig,
+    deployed_bytes: Bytes,
+    address: NameOrAddress,
+    calls: Vec<Call>,
+    fork: ResolvedFork,
+    options: Option<ExecutionOptions>,
+    mut on_progress: F,
+) -> Result<(ForkContext, Timings, Vec<ExecutionResult>), eyre::Error>
+where
+    F: FnMut(ForkProgress<'_>) -> Result<(), eyre::Error>,
+{
+    let started = Instant::now();
+    let parallelism = config.limits.max_parallel_calls;
+    let count = calls.len();
+    let check = consistency_check(options.as_ref());
+    let deadline = deadline(options.as_ref());
+    let overrides = options
+        .as_ref()
+        .and_then(|opts| opts.state_overrides.clone());
+    let signers = options
+        .as_ref()
+        .and_then(|opts| opts.signers.clone())
+        .unwrap_or_default();
+    let uses_names = uses_names(&calls)
+        || address.as_name().is_some()
+        || options.as_ref().is_some_and(|opts| opts.uses_names());
+    let fork = prepare_fork(config, fork, options, uses_names).await?;
+    let context = fork.context.clone();
+    let block_retries = fork.block_retries;
+    // Signatures are for the chain the calls run on, which is only settled now
+    let calls = templates::encode_calls(calls, &signers, context.chain_id)?;
+    // What the node is asked about afterwards, when it's to be
+    let to_check = check.then(|| {
+        let rpc = fork.rpc_url().map(str::to_string);
+        (rpc, deployed_bytes.clone(), calls.clone())
+    });
+
+    // Steps come back over a channel so `on_progress` runs here. Once nothing is listening,
+    // because `on_progress` failed or this future was dropped, the calls stop at the next send.
+    let (steps, mut received) = mpsc::unbounded_channel();
+    let send = move |step| {
+        steps
+            .send(step)
+            .map_err(|_| eyre::eyre!("execution abandoned, nothing is waiting for the results"))
+    };
+    let worker = run_blocking(move || {
+        let mut engine = fork.build()?;
+        let fork_setup_ms = started.elapsed().as_millis() as u64;
+        send(Step::Forked)?;
+
+        let address = engine.resolve_name(&address, "address")?;
+        engine.insert_contract(address, deployed_bytes);
+        if let Some(overrides) = &overrides {
+            engine.override_state(overrides)?;
         }
-        // If chain ID is provided, look up the RPC URL from our mapping
-        Some(config) if config.chain_id.is_some() => {
-            let chain_id = config.chain_id.unwrap();
-            println!("Looking up RPC URL for chain ID: {}", chain_id);
-            let url = CHAIN_RPC_URLS
-                .get(&chain_id)
-                .cloned()
-                .ok_or_else(|| eyre::eyre!("No RPC URL configured for chain ID {}", chain_id))?;
-            println!("Found RPC URL: {}", url);
-            url
+
+        let started = Instant::now();
+        engine.execute_calls_with(address, calls, parallelism, |index, result| {
+            send(Step::Result(index, result))
+        })?;
+        let timings = Timings {
+            fork_setup_ms,
+            execution_ms: started.elapsed().as_millis() as u64,
+            block_retries,
+        };
+        Ok((timings, address))
+    });
+
+    let mut results = Vec::with_capacity(count);
+    while let Some(step) = received.recv().await {
+        match step {
+            Step::Forked => on_progress(ForkProgress::Forked(&context))?,
+            Step::Result(index, result) => {
+                on_progress(ForkProgress::Result(index, &result))?;
+                results.push(result);
+            }
         }
-        // Default to BASE_RPC
-        _ => {
-            let url = env::var("BASE_RPC")
-                .map_err(|_| eyre::eyre!("BASE_RPC environment variable not set"))?;
-            println!("Using default RPC URL: {}", url);
-            url
+    }
+    let (timings, address) = worker.await?;
+
+    if let Some((rpc, bytecode, calls)) = to_check {
+        match rpc {
+            // Asking the node would only run the request further over
+            _ if deadline.is_some_and(|deadline| Instant::now() >= deadline) => {
+                for result in &mut results {
+                    result.consistency_check = Some(ConsistencyCheck::skipped(
+                        "the request's deadline passed before the node could be asked",
+                    ));
+                }
+            }
+            Some(rpc) => {
+                consistency::check_calls(&rpc, &context, address, &bytecode, &calls, &mut results)
+                    .await
+            }
+            None => {
+                for result in &mut results {
+                    result.consistency_check = Some(ConsistencyCheck::skipped(
+                        "runs on the bundled demo chain, which has no node to compare with",
+                    ));
+                }
+            }
         }
-    };
+    }
+    Ok((context, timings, results))
+}
 
-    let rpc_url = rpc.parse()?;
-    let provider = ProviderBuilder::new().on_http(rpc_url);
+/// Like `execute_calldatas_fork`, but runs `creation_code` (constructor args included) to deploy
+/// the contract instead of injecting runtime code. Returns the deployment with the results.
+pub async fn deploy_and_execute_calldatas_fork(
+    config: &AppConfig,
+    creation_code: Bytes,
+    calls: Vec<Call>,
+    fork: ResolvedFork,
+    options: Option<ExecutionOptions>,
+) -> Result<(Deployment, Vec<ExecutionResult>), eyre::Error> {
+    let parallelism = config.limits.max_parallel_calls;
+    let fork = prepare_fork(config, fork, options, uses_names(&calls)).await?;
+
+    run_blocking(move || {
+        let mut engine = fork.build()?;
+        let deployment = engine.deploy(DEFAULT_DEPLOYER, creation_code)?;
 
-    // Determine block ID based on fork config
-    let block_id = match &fork_config {
-        Some(config) if config.block_number.is_some() => {
-            BlockId::Number(config.block_number.unwrap().into())
+        let results = engine.execute_calls(deployment.address, calls, parallelism)?;
+        Ok((deployment, results))
+    })
+    .await
+}
+
+pub(super) fn uses_names(calls: &[Call]) -> bool {
+    calls.iter().any(|call| {
+        call.caller
+            .as_ref()
+            .is_some_and(|caller| caller.as_name().is_some())
+    })
+}
+
+/// Runs `work` on tokio's blocking pool, in the current span. Spawning a fork's backend and
+/// running calls on it block on the EVM and on RPC round trips, which would otherwise hold up the
+/// async workers every other request is served from. A panic in `work` carries on here.
+pub(crate) fn run_blocking<T, F>(work: F) -> impl Future<Output = Result<T, eyre::Error>>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, eyre::Error> + Send + 'static,
+{
+    let span = Span::current();
+    let handle = tokio::task::spawn_blocking(move || span.in_scope(work));
+    async move {
+        match handle.await {
+            Ok(result) => result,
+            Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+            Err(err) => Err(err.into()),
         }
-        _ => BlockId::latest(),
-    };
+    }
+}
 
-    // Debug log the block ID
-    println!("Using block ID: {:?}", block_id);
+// Fetches what's needed to fork as `fork` settled
+pub(super) async fn prepare_fork(
+    config: &AppConfig,
+    fork: ResolvedFork,
+    options: Option<ExecutionOptions>,
+    uses_names: bool,
+) -> Result<EngineConfig, eyre::Error> {
+    debug!(
+        chain_id = ?fork.chain_id,
+        block_number = ?fork.block_number,
+        trace_mode = ?options.as_ref().and_then(|o| o.trace_mode.as_deref()),
+        "setting up fork"
+    );
 
-    let (_fork_gas_price, mut rpc_chain_id, block) = tokio::try_join!(
-        provider.get_gas_price(),
-        provider.get_chain_id(),
-        provider.get_block(block_id, BlockTransactionsKind::Hashes)
-    )?;
+    let mut funded_accounts = Vec::new();
+    let rpc = match &fork.source {
+        // The bundled chain needs nothing from outside, so skip everything below
+        ForkSource::Demo => {
+            if uses_names {
+                return Err(ForkError::EnsUnsupported(demo::DEMO_CHAIN_ID).into());
+            }
+            debug!("using the demo network");
+            return Ok(demo::engine(options));
+        }
+        ForkSource::Anvil {
+            funded_accounts: funded,
+        } => {
+            let url = anvil::managed_endpoint(config.anvil_bin.as_deref()).await?;
+            debug!(rpc = %redact_url(&url), "using managed anvil");
+            // Funded on the fork when it's built, so anvil itself is left as it was
+            funded_accounts = funded.clone();
+            Rpc::Url(url)
+        }
+        ForkSource::Url(url) => {
+            debug!(rpc = %redact_url(url), "using custom RPC URL");
+            Rpc::Url(url.clone())
+        }
+        ForkSource::Pool(chain_id) => {
+            let endpoints = config
+                .rpc_endpoints(*chain_id)
+                .ok_or(ForkError::UnsupportedChain(*chain_id))?;
+            debug!(
+                chain_id,
+                keys = endpoints.len(),
+                "using configured RPC pool"
+            );
+            Rpc::Pool(endpoints)
+        }
+    };
 
-    // Override chain ID if specified in fork config
-    if let Some(config) = &fork_config {
-        if let Some(chain_id) = config.chain_id {
-            rpc_chain_id = chain_id;
+    debug!(block_number = ?fork.block_number, "fetching fork block");
+
+    // A rate-limited key hands the fork to the next one. The fork then keeps the key that worked.
+    let (rpc, fetched) = match rpc {
+        Rpc::Pool(endpoints) => {
+            rpc_pool::with_endpoint(endpoints, &config.rpc_pool, |rpc| {
+                fetch_fork_block(rpc, &fork, config)
+            })
+            .await?
+        }
+        Rpc::Url(rpc) => {
+            let fetched = fetch_fork_block(rpc.clone(), &fork, config).await?;
+            (rpc, fetched)
         }
+    };
+
+    let FetchedBlock {
+        chain_id: mut rpc_chain_id,
+        confirmation_lag,
+        block,
+        retries,
+    } = fetched;
+    if let Some(chain_id) = fork.chain_id {
+        rpc_chain_id = chain_id;
     }
 
-    let cfg = CfgEnv::default().with_chain_id(rpc_chain_id);
+    // Names can only be resolved where the ENS registry exists, so fail before forking
+    if uses_names && !supports_ens(rpc_chain_id) {
+        return Err(ForkError::EnsUnsupported(rpc_chain_id).into());
+    }
 
-    let block = if let Some(block) = block {
-        block
-    } else {
-        Err(eyre::eyre!("block not found"))?
+    let block = block.ok_or(ForkError::BlockNotFound)?;
+    let (env, mut context) = fork_env(rpc_chain_id, &block, config.limits.max_call_gas)?;
+    context.confirmation_lag = confirmation_lag;
+    // The block fetched above, not whatever the head has moved on to since
+    let opts = EvmOpts {
+        fork_url: Some(rpc),
+        fork_block_number: Some(context.block_number),
+        ..Default::default()
     };
+    // Asked alongside forge's own setup, so they only add time when they're the slower
+    let (fork_env, diagnostics) = tokio::join!(
+        opts.evm_env(),
+        rpc_diagnostics::diagnose(
+            opts.fork_url.as_deref().unwrap_or_default(),
+            fork.block_number
+        )
+    );
+    let fork_env = fork_env?;
+    context.rpc_diagnostics = Some(diagnostics);
+
+    let mut engine = EngineConfig::fork(env, context, opts, fork_env, options);
+    engine.block_retries = retries;
+    engine.funded_accounts = funded_accounts;
+    Ok(engine)
+}
 
-    // After getting the block
-    println!("Block number: {:?}", block.header.number);
+/// The environment calls on a fork of `block` run in, and what's echoed back about it.
+pub(super) fn fork_env(
+    chain_id: u64,
+    block: &Block,
+    max_call_gas: u64,
+) -> Result<(Env, ForkContext), ForkError> {
+    let header = &block.header;
+    let block_number = header
+        .number
+        .ok_or_else(|| ForkError::InvalidBlock("it has no number, so it's still pending".into()))?;
+    let gas_limit = call_gas_limit(header.gas_limit, max_call_gas);
 
     let block_env = BlockEnv {
-        number: U256::from(block.header.number.expect("block number not found")),
-        timestamp: U256::from(block.header.timestamp),
-        coinbase: block.header.miner,
-        difficulty: block.header.difficulty,
-        prevrandao: Some(block.header.mix_hash.unwrap_or_default()),
-        basefee: U256::from(block.header.base_fee_per_gas.unwrap_or_default()),
-        gas_limit: U256::from(block.header.gas_limit),
+        number: U256::from(block_number),
+        timestamp: U256::from(header.timestamp),
+        coinbase: header.miner,
+        difficulty: header.difficulty,
+        prevrandao: Some(header.mix_hash.unwrap_or_default()),
+        basefee: U256::from(header.base_fee_per_gas.unwrap_or_default()),
+        // revm refuses calls given more gas than the block has
+        gas_limit: U256::from(header.gas_limit.max(u128::from(gas_limit))),
         ..Default::default()
     };
     let env = Env {
-        cfg,
+        cfg: CfgEnv::default().with_chain_id(chain_id),
         block: block_env,
         tx: TxEnv {
-            chain_id: Some(rpc_chain_id),
-            gas_limit: block.header.gas_limit as u64,
+            chain_id: Some(chain_id),
+            gas_limit,
             ..Default::default()
         },
         ..Default::default()
     };
-    let opts = EvmOpts {
-        fork_url: Some(rpc),
-        fork_block_number: fork_config.as_ref().and_then(|c| c.block_number),
-        ..Default::default()
+    let context = ForkContext {
+        chain_id,
+        block_number,
+        gas_limit,
+        hardfork: hardfork::LATEST.to_string(),
+        confirmation_lag: 0,
+        rpc_diagnostics: None,
     };
-    println!(
-        "EVM options - fork URL: {:?}, fork block number: {:?}",
-        opts.fork_url, opts.fork_block_number
-    );
-    let backend = backend::Backend::spawn(opts.get_fork(&Config::default(), opts.evm_env().await?));
-    let mut executor = ExecutorBuilder::new()
-        .inspectors(|stack| {
-            // Default to Jump trace mode if not specified in options
-            let trace_mode = match &options {
-                Some(opts) => match opts.trace_mode.as_deref() {
-                    Some("debug") => TraceMode::Debug,
-                    Some("jump") => TraceMode::Jump,
-                    Some("jumpSimple") => TraceMode::JumpSimple,
-                    Some("call") => TraceMode::Call,
-                    Some("none") => TraceMode::None,
-                    _ => TraceMode::Jump, // Default to Jump mode for best balance
-                },
-                None => TraceMode::Call, // Default to Jump mode for best balance
-            };
-            println!("Trace mode: {:?}", trace_mode);
-            stack.trace_mode(trace_mode).logs(true)
-        })
-        .build(env, backend);
+    Ok((env, context))
+}
 
-    let deployed_bytecode = Bytecode::new_raw(deployed_bytes);
-    executor.backend_mut().insert_account_info(
-        address,
-        AccountInfo {
-            code_hash: deployed_bytecode.hash_slow(),
-            code: Some(deployed_bytecode),
-            ..Default::default()
-        },
-    );
+/// The gas each call on a fork is given: the block's gas limit, but no more than `max`. Some
+/// chains and test setups report limits too large to fit in a `u64`, or of zero, and one call
+/// shouldn't be able to use up a whole block anyway.
+pub(super) fn call_gas_limit(block_gas_limit: u128, max: u64) -> u64 {
+    match u64::try_from(block_gas_limit) {
+        Ok(0) | Err(_) => max,
+        Ok(limit) => limit.min(max),
+    }
+}
 
-    // After setting rpc_chain_id
-    println!("Using chain ID: {}", rpc_chain_id);
-
-    calls
-        .into_iter()
-        .map(|call| {
-            let r = executor.transact_raw(call.caller, address, call.calldata, call.value)?;
-            Ok(ExecutionResult {
-                exit_reason: r.exit_reason,
-                reverted: r.reverted,
-                result: r.result,
-                gas_used: r.gas_used,
-                logs: r.logs,
-                traces: r.traces.unwrap_or(CallTraceArena::default()),
-            })
+// Why the fork block couldn't be fetched. A block past the head is a mistake in the request
+// rather than the RPC's, so it's worth the extra round trip to tell them apart.
+
+// Where a fork's state comes from
+enum Rpc<'a> {
+    Url(String),
+    Pool(&'a [RpcEndpoint]),
+}
+
+// What `fetch_fork_block` found
+struct FetchedBlock {
+    chain_id: u64,
+    /// How many blocks behind the head the block is
+    confirmation_lag: u64,
+    block: Option<Block>,
+    retries: u32,
+}
+
+// The chain ID and the block to fork from. Without a block number that takes a round trip for
+// the head first.
+async fn fetch_fork_block(
+    rpc: String,
+    fork: &ResolvedFork,
+    config: &AppConfig,
+) -> Result<FetchedBlock, eyre::Error> {
+    let provider = ProviderBuilder::new().on_http(rpc.parse()?);
+    let block = |number: u64| {
+        provider.get_block(
+            BlockId::Number(number.into()),
+            BlockTransactionsKind::Hashes,
+        )
+    };
+    let Some(number) = fork.block_number else {
+        let (_fork_gas_price, chain_id, head) = tokio::try_join!(
+            provider.get_gas_price(),
+            provider.get_chain_id(),
+            provider.get_block_number()
+        )?;
+        let (number, lag) = fork.lagged_block(config, fork.chain_id.unwrap_or(chain_id), head);
+        let (block, retries) = fetch_block(block, number, true, BLOCK_RETRY_DELAY).await?;
+        // Further behind when it fell back to the parent
+        let confirmation_lag = block
+            .as_ref()
+            .and_then(|block| block.header.number)
+            .map_or(lag, |number| head.saturating_sub(number));
+        return Ok(FetchedBlock {
+            chain_id,
+            confirmation_lag,
+            block,
+            retries,
+        });
+    };
+    // Refused before the retries for a block the node hasn't caught up to
+    let (_fork_gas_price, chain_id, head) = tokio::try_join!(
+        provider.get_gas_price(),
+        provider.get_chain_id(),
+        provider.get_block_number()
+    )?;
+    if number > head {
+        return Err(ForkError::BlockInFuture {
+            requested: number,
+            head,
+        }
+        .into());
+    }
+    let (block, retries) = fetch_block(block, number, false, BLOCK_RETRY_DELAY).await?;
+    Ok(FetchedBlock {
+        chain_id,
+        confirmation_lag: 0,
+        block,
+        retries,
+    })
+}
+
+// Block `number` from `get_block`, asked for again with backoff while the RPC says it has no such
+// block: behind a load balancer, the node asked may not have caught up with the one that gave the
+// head. Once the retries run out, `fallback` takes the block's parent instead, for forks of the
+// latest block, where any recent block will do. The block, if there was one, and the retries it
+// took, the fallback counting as one.
+async fn fetch_block<F, Fut, E>(
+    get_block: F,
+    number: u64,
+    fallback: bool,
+    mut delay: Duration,
+) -> Result<(Option<Block>, u32), E>
+where
+    F: Fn(u64) -> Fut,
+    Fut: Future<Output = Result<Option<Block>, E>>,
+{
+    for retries in 0..=BLOCK_RETRIES {
+        if let Some(block) = get_block(number).await? {
+            return Ok((Some(block), retries));
+        }
+        if retries < BLOCK_RETRIES {
+            debug!(number, ?delay, "fork block not found, retrying");
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+    if fallback && number > 0 {
+        debug!(number, "fork block still not found, taking its parent");
+        return Ok((get_block(number - 1).await?, BLOCK_RETRIES + 1));
+    }
+    Ok((None, BLOCK_RETRIES))
+}
+
+/// The most gas a call recording every opcode is given. Every opcode costs some, so it bounds
+/// the steps a debug trace keeps.
+pub const MAX_DEBUG_TRACE_GAS: u64 = 5_000_000;
+
+// Whether calls record every opcode. The journal, transient storage accesses and breakpoints
+// are read off them.
+fn records_opcodes(options: Option<&ExecutionOptions>) -> bool {
+    options.is_some_and(|opts| opts.trace_mode.as_deref() == Some("debug"))
+        || journal(options)
+        || transient_storage(options)
+        || !breakpoints(options).is_empty()
+}
+
+/// The most gas a call is given under `options`, when that's less than the chain's.
+pub(super) fn gas_cap(options: Option<&ExecutionOptions>) -> Option<u64> {
+    records_opcodes(options).then_some(MAX_DEBUG_TRACE_GAS)
+}
+
+pub(super) fn trace_mode(options: Option<&ExecutionOptions>) -> TraceMode {
+    let trace_mode = match options.and_then(|opts| opts.trace_mode.as_deref()) {
+        _ if records_opcodes(options) => TraceMode::Debug,
+        Some("jump") => TraceMode::Jump,
+        Some("jumpSimple") => TraceMode::JumpSimple,
+        Some("call") => TraceMode::Call,
+        Some("none") => TraceMode::None,
+        Some(_) => TraceMode::Jump, // Unknown modes get Jump for best balance
+        None => TraceMode::Call,
+    };
+    debug!(?trace_mode, "building executor");
+    trace_mode
+}
+
+pub(super) fn include_raw_traces(options: Option<&ExecutionOptions>) -> bool {
+    options.is_some_and(|opts| opts.include_raw_traces)
+}
+
+pub(super) fn collect_logs(options: Option<&ExecutionOptions>) -> bool {
+    options.and_then(|opts| opts.collect_logs).unwrap_or(true)
+}
+
+/// The forms `traceExport` can ask for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum TraceExport {
+    Chrome,
+    Pretty,
+    /// Down to this many calls deep
+    Mermaid(Option<usize>),
+}
+
+pub(super) fn trace_export(options: Option<&ExecutionOptions>) -> Option<TraceExport> {
+    match options.and_then(|opts| opts.trace_export.as_deref()) {
+        Some("chrome") => Some(TraceExport::Chrome),
+        Some("pretty") => Some(TraceExport::Pretty),
+        Some("mermaid") => Some(TraceExport::Mermaid(
+            options.and_then(|opts| opts.trace_export_depth),
+        )),
+        _ => None,
+    }
+}
+
+pub(super) fn flamegraph(options: Option<&ExecutionOptions>) -> bool {
+    options.is_some_and(|opts| opts.flamegraph)
+}
+
+pub(super) fn state_diff(options: Option<&ExecutionOptions>) -> bool {
+    options.is_some_and(|opts| opts.state_diff)
+}
+
+pub(super) fn access_list(options: Option<&ExecutionOptions>) -> bool {
+    options.is_some_and(|opts| opts.access_list)
+}
+
+pub(super) fn journal(options: Option<&ExecutionOptions>) -> bool {
+    options.is_some_and(|opts| opts.journal)
+}
+
+pub(super) fn transient_storage(options: Option<&ExecutionOptions>) -> bool {
+    options.is_some_and(|opts| opts.transient_storage)
+}
+
+pub(super) fn deterministic_addresses(options: Option<&ExecutionOptions>) -> bool {
+    options.is_some_and(|opts| opts.deterministic_addresses)
+}
+
+/// The custom errors to read frames' revert data as, when `capture_subcall_outputs` asks for it.
+pub(super) fn subcall_outputs(options: Option<&ExecutionOptions>) -> Option<Vec<Error>> {
+    options
+        .filter(|opts| opts.capture_subcall_outputs)
+        .map(|opts| opts.errors.clone().unwrap_or_default())
+}
+
+/// The pcs of the called contract's code `breakpoints` stop at, with where each is in the sources.
+pub(super) fn breakpoints(options: Option<&ExecutionOptions>) -> BTreeMap<usize, SourceLocation> {
+    options
+        .and_then(|opts| {
+            opts.breakpoints
+                .as_ref()
+                .zip(opts.source_locations.as_ref())
+        })
+        .map_or_else(BTreeMap::new, |(breakpoints, sources)| {
+            breakpoint_pcs(breakpoints, sources)
         })
-        .collect()
+}
+
+pub(super) fn retries(options: Option<&ExecutionOptions>) -> u32 {
+    options.map_or(0, |opts| opts.retries)
+}
+
+pub(super) fn deadline(options: Option<&ExecutionOptions>) -> Option<Instant> {
+    options.and_then(|opts| opts.deadline)
+}
+
+pub(super) fn address_labels(
+    options: Option<&ExecutionOptions>,
+) -> Option<&BTreeMap<Address, String>> {
+    options.and_then(|opts| opts.labels.as_ref())
+}
+
+pub(super) fn consistency_check(options: Option<&ExecutionOptions>) -> bool {
+    options.is_some_and(|opts| opts.consistency_check)
+}
+
+// Unknown names are rejected before they get here
+pub(super) fn spec(options: Option<&ExecutionOptions>) -> Option<SpecId> {
+    options
+        .and_then(|opts| opts.hardfork.as_deref())
+        .and_then(hardfork::spec)
+}
+
+impl ExecutionResult {
+    pub(super) fn from_raw(r: RawCallResult, include_raw_traces: bool) -> Self {
+        let exit_reason = ExitReason::from(r.exit_reason);
+        let hint = (exit_reason == ExitReason::InvalidOpcode)
+            .then(|| hardfork::hint(&r))
+            .flatten();
+        ExecutionResult {
+            exit_reason,
+            success: !r.reverted,
+            reverted: r.reverted,
+            revert_reason: r.reverted.then(|| decode_revert(&r.result, &[])),
+            result: r.result,
+            gas_used: r.gas_used,
+            gas_limit: None,
+            logs: EventLog::from_logs(r.logs),
+            traces: r.traces.as_ref().map(TraceNode::from_arena),
+            raw_traces: include_raw_traces.then(|| r.traces.unwrap_or_default()),
+            labels: BTreeMap::new(),
+            trace_export: None,
+            flamegraph: None,
+            state_diff: None,
+            access_list: None,
+            consistency_check: None,
+            env: None,
+            hint,
+            journal: None,
+            transient_storage_accesses: None,
+            created: None,
+            paused_at: None,
+            internal_frames: None,
+            target_balance_before: None,
+            target_balance_after: None,
+            tx_validity: None,
+            attempts: None,
+            skipped: None,
+            call_index: None,
+            meta: None,
+        }
+    }
+
+    /// The result of a call that never ran, for `reason`.
+    pub(super) fn skipped(reason: SkipReason) -> Self {
+        ExecutionResult {
+            exit_reason: ExitReason::Skipped,
+            success: false,
+            reverted: false,
+            revert_reason: None,
+            result: Bytes::new(),
+            gas_used: 0,
+            gas_limit: None,
+            logs: Vec::new(),
+            traces: None,
+            raw_traces: None,
+            labels: BTreeMap::new(),
+            trace_export: None,
+            flamegraph: None,
+            state_diff: None,
+            access_list: None,
+            consistency_check: None,
+            env: None,
+            hint: None,
+            journal: None,
+            transient_storage_accesses: None,
+            created: None,
+            paused_at: None,
+            internal_frames: None,
+            target_balance_before: None,
+            target_balance_after: None,
+            tx_validity: None,
+            attempts: None,
+            skipped: Some(reason),
+            call_index: None,
+            meta: None,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::gas::engine;
+    use crate::gas::recorded_rpc::{serve, RecordedRpc};
     use alloy::hex;
-    use alloy_primitives::{Address, Bytes, U256};
+    use alloy_primitives::{keccak256, Address, Bytes, B256, U256};
+    use alloy_rpc_types_eth::BlockTransactions;
+    use std::collections::HashMap;
     use std::str::FromStr;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    fn resolved(fork: ForkConfig) -> ResolvedFork {
+        fork.resolve(&AppConfig::default()).unwrap()
+    }
 
     // TODO test for contract that exists
+    // Against the local mock chain, so the RPC fork path runs without a network
     #[tokio::test(flavor = "multi_thread")]
     async fn test_simple_storage_contract() {
+        let rpc = slow_rpc(Duration::ZERO);
         // Simple storage contract bytecode
         let bytecode = Bytes::from_str("0x608060405234801561000f575f80fd5b506004361061004a575f3560e01c80632a1afcd91461004e57806342cbb15c1461006c57806360fe47b11461008a5780636d4ce63c146100a6575b5f80fd5b6100566100c4565b6040516100639190610130565b60405180910390f35b6100746100c9565b6040516100819190610130565b60405180910390f35b6100a4600480360381019061009f9190610177565b6100d0565b005b6100ae610110565b6040516100bb9190610130565b60405180910390f35b5f5481565b5f43905090565b805f819055507fe0dca1a932506e28dc1cd7f50b0604489287b36ba09c37f13b25ee518d813528816040516101059190610130565b60405180910390a150565b5f8054905090565b5f819050919050565b61012a81610118565b82525050565b5f6020820190506101435f830184610121565b92915050565b5f80fd5b61015681610118565b8114610160575f80fd5b50565b5f813590506101718161014d565b92915050565b5f6020828403121561018c5761018b610149565b5b5f61019984828501610163565b9150509291505056fea2646970667358221220f7399e877793618afbf93c1ab591511f69fa1330a3fd5526ff45418127a04af964736f6c634300081a0033").unwrap();
         let address = Address::from_str("0xb2f9974c62815d3177079e150377915d9bc49c82").unwrap();
 
         // Call to store a value
         let store_call = Call {
-            caller: Address::from_str("0x1000000000000000000000000000000000000000").unwrap(),
+            caller: Some(
+                Address::from_str("0x1000000000000000000000000000000000000000")
+                    .unwrap()
+                    .into(),
+            ),
             calldata: Bytes::from_str(
                 "0x60fe47b10000000000000000000000000000000000000000000000000000000000000001", // set 1
             )
             .unwrap(), // store(66)
             value: U256::from(0),
+            independent: false,
+            signature: None,
+            args: None,
+            meta: None,
         };
 
         // Call to retrieve the value
         let retrieve_call = Call {
-            caller: Address::from_str("0x1000000000000000000000000000000000000000").unwrap(),
+            caller: Some(
+                Address::from_str("0x1000000000000000000000000000000000000000")
+                    .unwrap()
+                    .into(),
+            ),
             calldata: Bytes::from_str("0x6d4ce63c").unwrap(), // get()
             value: U256::from(0),
+            independent: false,
+            signature: None,
+            args: None,
+            meta: None,
         };
 
         // Execute the calls
         let results = execute_calldatas_fork(
+            &AppConfig::default(),
             bytecode,
-            address,
+            address.into(),
             vec![store_call, retrieve_call],
-            None,
+            resolved(ForkConfig {
+                rpc_url: Some(rpc),
+                block_number: Some(16),
+                ..Default::default()
+            }),
             None,
         )
         .await
@@ -297,10 +1329,550 @@ mod tests {
             println!("---");
         }
 
+        // set() emits StoredDataUpdated(1)
+        let log = &results[0].logs[0];
+        assert_eq!(log.address, address);
+        assert_eq!(log.topics[0], keccak256("StoredDataUpdated(uint256)"));
+        assert_eq!(log.data, Bytes::from(U256::from(1).to_be_bytes_vec()));
+        assert_eq!(log.log_index, 0);
+
         // Check the retrieve call result
         assert_eq!(
             hex::encode(&results[1].result),
             "0000000000000000000000000000000000000000000000000000000000000001"
         );
     }
+
+    fn block(number: Option<u64>, gas_limit: u128) -> Block {
+        Block {
+            header: alloy_rpc_types_eth::Header {
+                number,
+                gas_limit,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_call_gas_limit() {
+        let max = 50_000_000;
+        assert_eq!(call_gas_limit(30_000_000, max), 30_000_000);
+        assert_eq!(call_gas_limit(u128::from(u64::MAX), max), max);
+        assert_eq!(call_gas_limit(u128::from(u64::MAX) + 1, max), max);
+        assert_eq!(call_gas_limit(u128::MAX, max), max);
+        assert_eq!(call_gas_limit(0, max), max);
+    }
+
+    #[test]
+    fn test_fork_env_with_huge_gas_limits() {
+        for gas_limit in [u128::from(u64::MAX) - 1, u128::MAX] {
+            let (env, context) = fork_env(8453, &block(Some(7), gas_limit), 50_000_000).unwrap();
+            assert_eq!(env.tx.gas_limit, 50_000_000);
+            assert_eq!(env.block.gas_limit, U256::from(gas_limit));
+            assert_eq!(
+                (context.chain_id, context.block_number, context.gas_limit),
+                (8453, 7, 50_000_000)
+            );
+        }
+
+        // Never less block gas than a call is given
+        let (env, _) = fork_env(8453, &block(Some(7), 0), 50_000_000).unwrap();
+        assert_eq!(env.block.gas_limit, U256::from(50_000_000));
+
+        let err = fork_env(8453, &block(None, 30_000_000), 50_000_000).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid fork block: it has no number, so it's still pending"
+        );
+    }
+
+    #[test]
+    fn test_unconfigured_chain_id() {
+        let config = AppConfig {
+            chain_rpc_urls: HashMap::from([(
+                8453,
+                vec![RpcEndpoint {
+                    url: "http://localhost:1".to_string(),
+                    weight: 1,
+                }],
+            )]),
+            ..Default::default()
+        };
+        let fork_config = ForkConfig {
+            rpc_url: None,
+            chain_id: Some(1),
+            block_number: None,
+            mode: None,
+            funded_accounts: None,
+            network: None,
+            confirmation_lag: None,
+        };
+
+        let err = fork_config.resolve(&config).unwrap_err();
+        assert_eq!(err.to_string(), "No RPC URL configured for chain ID 1");
+    }
+
+    // Calls an empty account 50,000 times, so a traced run records as many call nodes
+    const CALL_LOOP: &str = "0x61c3505b60006000600060006112345afa50600190038060035700";
+    // Emits one anonymous, empty event
+    const LOG_ONCE: &str = "0x60006000a000";
+
+    async fn run_on_demo(bytecode: &str, options: Option<ExecutionOptions>) -> ExecutionResult {
+        let mut results = execute_calldatas_fork(
+            &AppConfig::default(),
+            Bytes::from_str(bytecode).unwrap(),
+            Address::from_str("0x2000000000000000000000000000000000000000")
+                .unwrap()
+                .into(),
+            vec![Call {
+                calldata: Bytes::new(),
+                value: U256::ZERO,
+                caller: Some(demo::ACCOUNTS[0].into()),
+                independent: false,
+                signature: None,
+                args: None,
+                meta: None,
+            }],
+            resolved(ForkConfig {
+                network: Some(demo::NETWORK.to_string()),
+                ..Default::default()
+            }),
+            options,
+        )
+        .await
+        .unwrap();
+        results.remove(0)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_trace_mode_none_skips_tracing() {
+        let untraced = || ExecutionOptions::new(Some("none".to_string()), false, None, None, false);
+        // Loads the demo state, so neither timed run pays for it
+        run_on_demo(CALL_LOOP, untraced()).await;
+
+        let started = Instant::now();
+        let traced = run_on_demo(CALL_LOOP, None).await;
+        let traced_time = started.elapsed();
+        let started = Instant::now();
+        let result = run_on_demo(CALL_LOOP, untraced()).await;
+        let untraced_time = started.elapsed();
+
+        assert!(traced.success && result.success);
+        assert_eq!(traced.gas_used, result.gas_used);
+        assert_eq!(traced.traces.unwrap()[0].children.len(), 50_000);
+        assert!(result.traces.is_none());
+        let json = serde_json::to_value(&result).unwrap();
+        assert!(json.get("traces").is_none());
+        assert!(
+            untraced_time < traced_time,
+            "{:?} untraced, {:?} traced",
+            untraced_time,
+            traced_time
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_collect_logs() {
+        let result = run_on_demo(LOG_ONCE, None).await;
+        assert_eq!(result.logs.len(), 1);
+
+        let result = run_on_demo(
+            LOG_ONCE,
+            ExecutionOptions::new(None, false, Some(false), None, false),
+        )
+        .await;
+        assert!(result.success);
+        assert!(result.logs.is_empty());
+    }
+
+    // A chain with nothing on it but balances, each account's being its address's last byte. It
+    // answers every request after `delay`, like a distant RPC.
+    fn slow_rpc(delay: Duration) -> String {
+        let block = serde_json::to_value(Block {
+            header: alloy_rpc_types_eth::Header {
+                hash: Some(B256::repeat_byte(1)),
+                number: Some(16),
+                gas_limit: 30_000_000,
+                timestamp: 1_700_000_000,
+                base_fee_per_gas: Some(0),
+                mix_hash: Some(B256::ZERO),
+                ..Default::default()
+            },
+            transactions: BlockTransactions::Hashes(vec![]),
+            ..Default::default()
+        })
+        .unwrap();
+        serve(move |request: &serde_json::Value| {
+            std::thread::sleep(delay);
+            let result = match request["method"].as_str().unwrap_or_default() {
+                "eth_chainId" => serde_json::json!("0x7a69"),
+                "eth_blockNumber" => serde_json::json!("0x10"),
+                "eth_getBlockByNumber" | "eth_getBlockByHash" => block.clone(),
+                "eth_getCode" => serde_json::json!("0x"),
+                "eth_getStorageAt" => serde_json::json!(B256::ZERO),
+                "eth_getBalance" => {
+                    let address = request["params"][0].as_str().unwrap();
+                    let last = u8::from_str_radix(&address[address.len() - 2..], 16);
+                    serde_json::json!(format!("{:#x}", last.unwrap()))
+                }
+                _ => serde_json::json!("0x0"),
+            };
+            serde_json::json!({ "result": result })
+        })
+    }
+
+    // An RPC behind a load balancer that routes to a lagging node: it has no block `missing`
+    // times before it has it, and never has any of `never`
+    async fn lagging_node(
+        missing: usize,
+        never: &[u64],
+        number: u64,
+        fallback: bool,
+    ) -> (Option<Block>, u32, Vec<u64>) {
+        let asked = std::sync::Mutex::new(Vec::new());
+        let get_block = |number: u64| {
+            let mut asked = asked.lock().unwrap();
+            asked.push(number);
+            let found = asked.len() > missing && !never.contains(&number);
+            let block = found.then(|| Block {
+                header: alloy_rpc_types_eth::Header {
+                    number: Some(number),
+                    ..Default::default()
+                },
+                ..Default::default()
+            });
+            async move { Ok::<_, eyre::Error>(block) }
+        };
+        let (block, retries) = fetch_block(get_block, number, fallback, Duration::ZERO)
+            .await
+            .unwrap();
+        (block, retries, asked.into_inner().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_fetch_block_retries() {
+        let (block, retries, asked) = lagging_node(2, &[], 16, false).await;
+        assert_eq!(block.unwrap().header.number, Some(16));
+        assert_eq!(retries, 2);
+        assert_eq!(asked, [16, 16, 16]);
+
+        // A named block is never swapped for another
+        let (block, retries, asked) = lagging_node(0, &[16], 16, false).await;
+        assert!(block.is_none());
+        assert_eq!(retries, BLOCK_RETRIES);
+        assert_eq!(asked, [16; BLOCK_RETRIES as usize + 1]);
+
+        // The latest one falls back to its parent
+        let (block, retries, asked) = lagging_node(0, &[16], 16, true).await;
+        assert_eq!(block.unwrap().header.number, Some(15));
+        assert_eq!(retries, BLOCK_RETRIES + 1);
+        assert_eq!(asked.last(), Some(&15));
+    }
+
+    // Returns the balance of the address in its calldata
+    const BALANCE_OF: &str = "0x6000353160005260206000f3";
+
+    // Reads the balances of 20 accounts no other run has touched, so each one is fetched
+    async fn read_balances(rpc: &str, run: u8, independent: bool) -> (Timings, Vec<U256>) {
+        let calls = (1..=20u8)
+            .map(|i| {
+                let mut account = [0u8; 20];
+                account[0] = run;
+                account[19] = i;
+                Call {
+                    calldata: Bytes::copy_from_slice(Address::from(account).into_word().as_slice()),
+                    value: U256::ZERO,
+                    caller: Some(
+                        Address::from_str("0x1000000000000000000000000000000000000000")
+                            .unwrap()
+                            .into(),
+                    ),
+                    independent,
+                    signature: None,
+                    args: None,
+                    meta: None,
+                }
+            })
+            .collect();
+        let (_, timings, results) = execute_calldatas_fork_with(
+            &AppConfig::default(),
+            Bytes::from_str(BALANCE_OF).unwrap(),
+            Address::from_str("0x2000000000000000000000000000000000000000")
+                .unwrap()
+                .into(),
+            calls,
+            resolved(ForkConfig {
+                rpc_url: Some(rpc.to_string()),
+                block_number: Some(16),
+                ..Default::default()
+            }),
+            None,
+            |_| Ok(()),
+        )
+        .await
+        .unwrap();
+        let balances = results
+            .iter()
+            .map(|result| U256::from_be_slice(&result.result))
+            .collect();
+        (timings, balances)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_independent_calls_run_concurrently() {
+        let rpc = slow_rpc(Duration::from_millis(100));
+        let expected: Vec<_> = (1..=20u8).map(U256::from).collect();
+
+        let (serial, balances) = read_balances(&rpc, 0xaa, false).await;
+        assert_eq!(balances, expected);
+        let (parallel, balances) = read_balances(&rpc, 0xbb, true).await;
+        // Still in call order
+        assert_eq!(balances, expected);
+
+        assert!(
+            parallel.execution_ms * 2 < serial.execution_ms,
+            "{}ms in parallel, {}ms serially",
+            parallel.execution_ms,
+            serial.execution_ms
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_recorded_rpc_replays_offline() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("balances.json");
+        let live = read_balances(
+            RecordedRpc::record(path.clone(), slow_rpc(Duration::ZERO)).url(),
+            0xcc,
+            false,
+        )
+        .await;
+
+        // Written when the recorder went, and enough for the same run with the node out of reach
+        let replayed = read_balances(RecordedRpc::replay(&path).url(), 0xcc, false).await;
+        assert_eq!(replayed.1, live.1);
+
+        // Anything else is an error, not a guess
+        let rpc = RecordedRpc::replay(&path);
+        let err = execute_calldatas_fork(
+            &AppConfig::default(),
+            Bytes::from_str(BALANCE_OF).unwrap(),
+            Address::from_str("0x2000000000000000000000000000000000000000")
+                .unwrap()
+                .into(),
+            vec![],
+            resolved(ForkConfig {
+                rpc_url: Some(rpc.url().to_string()),
+                block_number: Some(17),
+                ..Default::default()
+            }),
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert!(format!("{:?}", err).contains("not recorded"), "{:?}", err);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_independent_call_that_writes() {
+        let contract = Address::from_str("0x2000000000000000000000000000000000000000").unwrap();
+        let call = |calldata: &str| Call {
+            calldata: Bytes::from_str(calldata).unwrap(),
+            value: U256::ZERO,
+            caller: Some(demo::ACCOUNTS[0].into()),
+            independent: true,
+            signature: None,
+            args: None,
+            meta: None,
+        };
+        // Writes slot 0 when given any calldata
+        let bytecode = Bytes::from_str("0x3615600a5760016000555b00").unwrap();
+        let err = execute_calldatas_fork(
+            &AppConfig::default(),
+            bytecode,
+            contract.into(),
+            vec![call("0x"), call("0x01020304"), call("0x")],
+            resolved(ForkConfig {
+                network: Some(demo::NETWORK.to_string()),
+                ..Default::default()
+            }),
+            None,
+        )
+        .await
+        .unwrap_err();
+        let err = err.downcast::<NotIndependent>().unwrap();
+        assert_eq!((err.index, err.account), (1, contract));
+        assert_eq!(err.field(), "calls[1].independent");
+    }
+
+    // A node at block 16 whose first `eth_getStorageAt` fails, as a provider's does now and then
+    fn flaky_node() -> String {
+        let block = serde_json::to_value(Block {
+            header: alloy_rpc_types_eth::Header {
+                hash: Some(B256::repeat_byte(1)),
+                number: Some(16),
+                gas_limit: 30_000_000,
+                timestamp: 1_700_000_000,
+                base_fee_per_gas: Some(0),
+                mix_hash: Some(B256::ZERO),
+                ..Default::default()
+            },
+            transactions: BlockTransactions::Hashes(vec![]),
+            ..Default::default()
+        })
+        .unwrap();
+        let failed = AtomicBool::new(false);
+        serve(move |request: &serde_json::Value| {
+            let result = match request["method"].as_str().unwrap_or_default() {
+                "eth_chainId" => serde_json::json!("0x7a69"),
+                "eth_blockNumber" => serde_json::json!("0x10"),
+                "eth_getBlockByNumber" | "eth_getBlockByHash" => block.clone(),
+                "eth_getCode" => serde_json::json!("0x"),
+                "eth_getStorageAt" if !failed.swap(true, Ordering::SeqCst) => {
+                    return serde_json::json!({
+                        "error": { "code": -32603, "message": "upstream request failed" }
+                    });
+                }
+                "eth_getStorageAt" => serde_json::json!(B256::with_last_byte(42)),
+                _ => serde_json::json!("0x0"),
+            };
+            serde_json::json!({ "result": result })
+        })
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_retries_transient_failures() {
+        let contract = Address::from_str("0x2000000000000000000000000000000000000000").unwrap();
+        // Returns slot 0, which is read from the node
+        let bytecode = Bytes::from_str("0x60005460005260206000f3").unwrap();
+        let call = |independent: bool| Call {
+            calldata: Bytes::new(),
+            value: U256::ZERO,
+            caller: Some(demo::ACCOUNTS[0].into()),
+            independent,
+            signature: None,
+            args: None,
+            meta: None,
+        };
+        let fork = |rpc: String| {
+            resolved(ForkConfig {
+                rpc_url: Some(rpc),
+                block_number: Some(16),
+                ..Default::default()
+            })
+        };
+        let retrying = Some(ExecutionOptions {
+            retries: 2,
+            ..Default::default()
+        });
+
+        // The second call reads the slot the first one's retry loaded. Independent calls run
+        // at once, so there's only one of those to say which failed.
+        for calls in [vec![call(false), call(false)], vec![call(true)]] {
+            let count = calls.len();
+            let results = execute_calldatas_fork(
+                &AppConfig::default(),
+                bytecode.clone(),
+                contract.into(),
+                calls,
+                fork(flaky_node()),
+                retrying.clone(),
+            )
+            .await
+            .unwrap();
+            let forty_two = Bytes::from(U256::from(42).to_be_bytes_vec());
+            assert!(results.iter().all(|result| result.result == forty_two));
+            let attempts: Vec<_> = results.iter().map(|result| result.attempts).collect();
+            assert_eq!(attempts, [Some(2), Some(1)][..count]);
+        }
+
+        // Without retries the failure fails the request, and is one a retry could get past
+        let err = execute_calldatas_fork(
+            &AppConfig::default(),
+            bytecode,
+            contract.into(),
+            vec![call(false)],
+            fork(flaky_node()),
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert!(engine::is_transient(&err), "{:?}", err);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_block_in_future() {
+        // The node would answer for any block, so this is refused on its head alone
+        let err = execute_calldatas_fork(
+            &AppConfig::default(),
+            Bytes::new(),
+            Address::ZERO.into(),
+            vec![],
+            resolved(ForkConfig {
+                rpc_url: Some(slow_rpc(Duration::ZERO)),
+                block_number: Some(17),
+                ..Default::default()
+            }),
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ForkError>(),
+            Some(ForkError::BlockInFuture {
+                requested: 17,
+                head: 16
+            })
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_deadline_skips_remaining_calls() {
+        // Each call loads a balance no call before it did, taking 100ms
+        let rpc = slow_rpc(Duration::from_millis(100));
+        let calls = (1..=3u8)
+            .map(|i| Call {
+                calldata: Bytes::copy_from_slice(Address::repeat_byte(i).into_word().as_slice()),
+                value: U256::ZERO,
+                caller: Some(demo::ACCOUNTS[0].into()),
+                independent: false,
+                signature: None,
+                args: None,
+                meta: Some(serde_json::json!(i)),
+            })
+            .collect();
+        let options = ExecutionOptions::with_deadline(
+            None,
+            Some(Instant::now() + Duration::from_millis(100)),
+        );
+        let results = execute_calldatas_fork(
+            &AppConfig::default(),
+            Bytes::from_str(BALANCE_OF).unwrap(),
+            Address::from_str("0x2000000000000000000000000000000000000000")
+                .unwrap()
+                .into(),
+            calls,
+            resolved(ForkConfig {
+                rpc_url: Some(rpc),
+                block_number: Some(16),
+                ..Default::default()
+            }),
+            options,
+        )
+        .await
+        .unwrap();
+
+        // The first ran whatever the time, and the deadline passed during it
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].skipped, None);
+        assert_eq!(U256::from_be_slice(&results[0].result), U256::from(1));
+        for (i, result) in results.iter().enumerate().skip(1) {
+            assert_eq!(result.skipped, Some(SkipReason::DeadlineExceeded));
+            assert_eq!(result.exit_reason, ExitReason::Skipped);
+            assert_eq!(result.call_index, Some(i));
+            assert_eq!(result.meta, Some(serde_json::json!(i + 1)));
+            assert_eq!(result.gas_limit, None);
+        }
+    }
 }
@@ -0,0 +1,153 @@
+use revm::interpreter::InstructionResult;
+use serde::{Deserialize, Serialize};
+
+/// Why execution stopped. revm's own names and numbering change between releases, so results
+/// carry this instead. Every revm result is mapped by name, so one added in an upgrade fails the
+/// build until it's given a reason here. `Other` only holds the interpreter's own states, which
+/// no call ends in, under revm's names.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ExitReason {
+    Success,
+    Revert,
+    OutOfGas,
+    InvalidOpcode,
+    InvalidJump,
+    StackUnderflow,
+    StackOverflow,
+    /// A state change, or a call with value, inside a static call
+    StaticCallViolation,
+    CallTooDeep,
+    OutOfFunds,
+    OutOfOffset,
+    CreateCollision,
+    ContractSizeLimit,
+    /// Deployed code starting with 0xEF
+    InvalidContractPrefix,
+    NonceOverflow,
+    OverflowPayment,
+    PrecompileError,
+    /// EOF code, or a rule only EOF code has, broken. No chain runs EOF yet.
+    InvalidEof,
+    FatalError,
+    /// The call never ran; the result's `skipped` says why
+    Skipped,
+    /// The call stopped at a breakpoint; the result's `pausedAt` says where
+    Paused,
+    #[serde(untagged)]
+    Other(String),
+}
+
+impl ExitReason {
+    pub fn is_success(&self) -> bool {
+        matches!(self, ExitReason::Success)
+    }
+}
+
+impl From<InstructionResult> for ExitReason {
+    fn from(reason: InstructionResult) -> Self {
+        use InstructionResult::*;
+
+        match reason {
+            Stop | Return | SelfDestruct | ReturnContract => ExitReason::Success,
+            Revert => ExitReason::Revert,
+            OutOfGas | MemoryOOG | MemoryLimitOOG | PrecompileOOG | InvalidOperandOOG => {
+                ExitReason::OutOfGas
+            }
+            OpcodeNotFound | InvalidFEOpcode | NotActivated => ExitReason::InvalidOpcode,
+            InvalidJump => ExitReason::InvalidJump,
+            StackUnderflow => ExitReason::StackUnderflow,
+            StackOverflow | EOFFunctionStackOverflow => ExitReason::StackOverflow,
+            CallNotAllowedInsideStatic | StateChangeDuringStaticCall => {
+                ExitReason::StaticCallViolation
+            }
+            CallTooDeep => ExitReason::CallTooDeep,
+            OutOfFunds => ExitReason::OutOfFunds,
+            OutOfOffset => ExitReason::OutOfOffset,
+            CreateCollision => ExitReason::CreateCollision,
+            CreateContractSizeLimit | CreateInitCodeSizeLimit => ExitReason::ContractSizeLimit,
+            CreateContractStartingWithEF => ExitReason::InvalidContractPrefix,
+            NonceOverflow => ExitReason::NonceOverflow,
+            OverflowPayment => ExitReason::OverflowPayment,
+            PrecompileError => ExitReason::PrecompileError,
+            CreateInitCodeStartingEF00
+            | InvalidEOFInitCode
+            | InvalidExtDelegateCallTarget
+            | ReturnContractInNotInitEOF
+            | EOFOpcodeDisabledInLegacy
+            | EofAuxDataOverflow
+            | EofAuxDataTooSmall
+            | InvalidEXTCALLTarget => ExitReason::InvalidEof,
+            FatalExternalError => ExitReason::FatalError,
+            // The interpreter's own states, between opcodes and around subcalls
+            Continue => ExitReason::Other("Continue".to_string()),
+            CallOrCreate => ExitReason::Other("CallOrCreate".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use InstructionResult::*;
+
+    // Pins the strings clients see, for every revm result. Changing one is a breaking API change.
+    #[test]
+    fn test_mapping_is_stable() {
+        let cases = [
+            (Stop, "success"),
+            (Return, "success"),
+            (SelfDestruct, "success"),
+            (ReturnContract, "success"),
+            (Revert, "revert"),
+            (OutOfGas, "outOfGas"),
+            (MemoryOOG, "outOfGas"),
+            (MemoryLimitOOG, "outOfGas"),
+            (PrecompileOOG, "outOfGas"),
+            (InvalidOperandOOG, "outOfGas"),
+            (OpcodeNotFound, "invalidOpcode"),
+            (InvalidFEOpcode, "invalidOpcode"),
+            (NotActivated, "invalidOpcode"),
+            (InvalidJump, "invalidJump"),
+            (StackUnderflow, "stackUnderflow"),
+            (StackOverflow, "stackOverflow"),
+            (EOFFunctionStackOverflow, "stackOverflow"),
+            (CallNotAllowedInsideStatic, "staticCallViolation"),
+            (StateChangeDuringStaticCall, "staticCallViolation"),
+            (CallTooDeep, "callTooDeep"),
+            (OutOfFunds, "outOfFunds"),
+            (OutOfOffset, "outOfOffset"),
+            (CreateCollision, "createCollision"),
+            (CreateContractSizeLimit, "contractSizeLimit"),
+            (CreateInitCodeSizeLimit, "contractSizeLimit"),
+            (CreateContractStartingWithEF, "invalidContractPrefix"),
+            (NonceOverflow, "nonceOverflow"),
+            (OverflowPayment, "overflowPayment"),
+            (PrecompileError, "precompileError"),
+            (CreateInitCodeStartingEF00, "invalidEof"),
+            (InvalidEOFInitCode, "invalidEof"),
+            (InvalidExtDelegateCallTarget, "invalidEof"),
+            (ReturnContractInNotInitEOF, "invalidEof"),
+            (EOFOpcodeDisabledInLegacy, "invalidEof"),
+            (EofAuxDataOverflow, "invalidEof"),
+            (EofAuxDataTooSmall, "invalidEof"),
+            (InvalidEXTCALLTarget, "invalidEof"),
+            (FatalExternalError, "fatalError"),
+            (Continue, "Continue"),
+            (CallOrCreate, "CallOrCreate"),
+        ];
+        for (reason, name) in cases {
+            let exit = ExitReason::from(reason);
+            let json = serde_json::to_value(&exit).unwrap();
+            assert_eq!(json, name, "{:?}", reason);
+            assert_eq!(serde_json::from_value::<ExitReason>(json).unwrap(), exit);
+        }
+    }
+
+    #[test]
+    fn test_success_agrees_with_revm() {
+        for reason in [Stop, Return, SelfDestruct, Revert, OutOfGas, InvalidJump] {
+            assert_eq!(ExitReason::from(reason).is_success(), reason.is_ok());
+        }
+    }
+}
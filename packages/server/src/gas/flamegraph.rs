@@ -0,0 +1,146 @@
+//! Call trees as folded stacks weighted by gas, the input `inferno` and speedscope draw
+//! flamegraphs from.
+
+use alloy_primitives::Address;
+use std::collections::BTreeMap;
+
+use super::trace::{TraceKind, TraceNode};
+
+/// One line per distinct stack, `Root;<frame>;<frame> <gas>`, weighted by the gas spent in the
+/// innermost frame itself rather than in the frames it called. Frames are named
+/// `<contract>.<selector>`, the contract by its label when it has one. The weights add up to the
+/// gas the top-level frames used, which leaves out the transaction's intrinsic cost.
+pub(super) fn folded(traces: &[TraceNode], labels: &BTreeMap<Address, String>) -> String {
+    let mut stacks = BTreeMap::new();
+    for node in traces {
+        fold(node, "Root", labels, &mut stacks);
+    }
+    stacks
+        .into_iter()
+        .filter(|(_, gas)| *gas > 0)
+        .map(|(stack, gas)| format!("{} {}\n", stack, gas))
+        .collect()
+}
+
+// Adds `node` under `parent` and then the frames it called. A frame that calls itself, directly
+// or not, is a longer stack, so it's counted apart from the outer call.
+fn fold(
+    node: &TraceNode,
+    parent: &str,
+    labels: &BTreeMap<Address, String>,
+    stacks: &mut BTreeMap<String, u64>,
+) {
+    let stack = format!("{};{}", parent, frame_name(node, labels));
+    let called: u64 = node.children.iter().map(|child| child.gas_used).sum();
+    *stacks.entry(stack.clone()).or_default() += node.gas_used.saturating_sub(called);
+    for child in &node.children {
+        fold(child, &stack, labels, stacks);
+    }
+}
+
+fn frame_name(node: &TraceNode, labels: &BTreeMap<Address, String>) -> String {
+    let contract = labels
+        .get(&node.to)
+        .cloned()
+        .unwrap_or_else(|| node.to.to_string());
+    let function = match node.kind {
+        TraceKind::Create | TraceKind::Create2 => "constructor".to_string(),
+        _ if node.input.len() >= 4 => node.input.slice(..4).to_string(),
+        _ => "fallback".to_string(),
+    };
+    format!("{}.{}", contract, function)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gas::TraceStatus;
+    use alloy_primitives::{address, Bytes, U256};
+
+    const TOKEN: Address = address!("1000000000000000000000000000000000000001");
+    const ROUTER: Address = address!("2000000000000000000000000000000000000002");
+
+    fn node(to: Address, input: &str, gas_used: u64, children: Vec<TraceNode>) -> TraceNode {
+        TraceNode {
+            kind: TraceKind::Call,
+            from: Address::ZERO,
+            to,
+            value: U256::ZERO,
+            gas_used,
+            input: input.parse::<Bytes>().unwrap(),
+            output: Bytes::new(),
+            status: TraceStatus::Success,
+            output_length: None,
+            revert_reason: None,
+            children,
+            logs: vec![],
+        }
+    }
+
+    fn weights(folded: &str) -> BTreeMap<String, u64> {
+        folded
+            .lines()
+            .map(|line| {
+                let (stack, gas) = line.rsplit_once(' ').unwrap();
+                (stack.to_string(), gas.parse().unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_folds_by_self_gas() {
+        // The router transfers twice and calls back into itself, which transfers once more
+        let tree = node(
+            ROUTER,
+            "0xaaaaaaaa",
+            50_000,
+            vec![
+                node(TOKEN, "0xa9059cbb", 12_000, vec![]),
+                node(TOKEN, "0xa9059cbb", 8_000, vec![]),
+                node(
+                    ROUTER,
+                    "0xaaaaaaaa",
+                    15_000,
+                    vec![node(TOKEN, "0xa9059cbb", 9_000, vec![])],
+                ),
+            ],
+        );
+        let labels = BTreeMap::from([(TOKEN, "TokenA".to_string())]);
+        let folded = folded(&[tree], &labels);
+
+        let router = format!("{}.0xaaaaaaaa", ROUTER);
+        assert_eq!(
+            weights(&folded),
+            BTreeMap::from([
+                (format!("Root;{}", router), 15_000),
+                (format!("Root;{};TokenA.0xa9059cbb", router), 20_000),
+                (format!("Root;{};{}", router, router), 6_000),
+                (
+                    format!("Root;{};{};TokenA.0xa9059cbb", router, router),
+                    9_000
+                ),
+            ])
+        );
+        assert_eq!(weights(&folded).values().sum::<u64>(), 50_000);
+    }
+
+    #[test]
+    fn test_unnamed_frames() {
+        let mut create = node(
+            TOKEN,
+            "0x6080",
+            30_000,
+            vec![node(ROUTER, "0x", 100, vec![])],
+        );
+        create.kind = TraceKind::Create;
+        let folded = folded(&[create], &BTreeMap::new());
+        assert_eq!(
+            folded,
+            format!(
+                "Root;{token}.constructor 29900\nRoot;{token}.constructor;{router}.fallback 100\n",
+                token = TOKEN,
+                router = ROUTER
+            )
+        );
+    }
+}
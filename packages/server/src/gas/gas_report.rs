@@ -0,0 +1,347 @@
+//! What `forge test --gas-report` prints: per contract, what each function cost across the calls
+//! of a request, and what deploying it cost.
+
+use alloy_json_abi::JsonAbi;
+use alloy_primitives::{Address, Selector};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use utoipa::ToSchema;
+
+use super::execute_calldatas_fork::ExecutionResult;
+use super::trace::{TraceKind, TraceNode, TraceStatus};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GasReport {
+    pub contracts: Vec<ContractGas>,
+    /// The same report as the markdown tables forge prints, one per contract
+    pub markdown: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ContractGas {
+    /// The contract's name, or its label or address when it wasn't compiled in the request
+    pub name: String,
+    #[schema(value_type = String)]
+    pub address: Address,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deployment: Option<DeploymentGas>,
+    pub functions: Vec<FunctionGas>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DeploymentGas {
+    pub gas: u64,
+    /// Bytes of runtime code
+    pub size: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FunctionGas {
+    /// The function's name when the contract's ABI has it, otherwise its selector
+    pub name: String,
+    pub calls: u64,
+    pub min: u64,
+    pub avg: u64,
+    pub median: u64,
+    pub max: u64,
+}
+
+/// Collects the gas of every frame in a request's results, by the contract and function it ran.
+/// A frame's gas is what the frame used, which for a top-level call leaves out the transaction's
+/// intrinsic cost, as forge's report does.
+#[derive(Default)]
+pub struct GasReporter {
+    contracts: BTreeMap<Address, Contract>,
+}
+
+#[derive(Default)]
+struct Contract {
+    name: Option<String>,
+    functions: HashMap<Selector, String>,
+    deployment: Option<DeploymentGas>,
+    calls: BTreeMap<String, Vec<u64>>,
+}
+
+impl GasReporter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Names the contract at `address`, and its functions from `abi`.
+    pub fn name(&mut self, address: Address, name: &str, abi: Option<&JsonAbi>) {
+        let contract = self.contracts.entry(address).or_default();
+        contract.name = Some(name.to_string());
+        if let Some(abi) = abi {
+            contract.functions.extend(
+                abi.functions()
+                    .map(|function| (function.selector(), function.name.clone())),
+            );
+        }
+    }
+
+    pub fn deployed(&mut self, address: Address, gas: u64, size: usize) {
+        self.contracts.entry(address).or_default().deployment = Some(DeploymentGas { gas, size });
+    }
+
+    /// Adds the frames of `result`'s trace, if it was traced. Contracts not named yet go by their
+    /// labels.
+    pub fn record(&mut self, result: &ExecutionResult) {
+        for (address, label) in &result.labels {
+            let contract = self.contracts.entry(*address).or_default();
+            contract.name.get_or_insert_with(|| label.clone());
+        }
+        for node in result.traces.iter().flatten() {
+            self.record_node(node);
+        }
+    }
+
+    fn record_node(&mut self, node: &TraceNode) {
+        let contract = self.contracts.entry(node.to).or_default();
+        match node.kind {
+            // Creations made by calls count as that contract's deployment
+            TraceKind::Create | TraceKind::Create2 => {
+                if node.status == TraceStatus::Success && contract.deployment.is_none() {
+                    contract.deployment = Some(DeploymentGas {
+                        gas: node.gas_used,
+                        size: node.output.len(),
+                    });
+                }
+            }
+            _ => {
+                let function = match node.input.get(..4) {
+                    Some(selector) => {
+                        let selector = Selector::from_slice(selector);
+                        contract
+                            .functions
+                            .get(&selector)
+                            .cloned()
+                            .unwrap_or_else(|| selector.to_string())
+                    }
+                    None => "fallback".to_string(),
+                };
+                contract
+                    .calls
+                    .entry(function)
+                    .or_default()
+                    .push(node.gas_used);
+            }
+        }
+        for child in &node.children {
+            self.record_node(child);
+        }
+    }
+
+    /// The report, leaving out contracts that were neither deployed nor called.
+    pub fn report(&self) -> GasReport {
+        let contracts: Vec<_> = self
+            .contracts
+            .iter()
+            .filter(|(_, contract)| contract.deployment.is_some() || !contract.calls.is_empty())
+            .map(|(address, contract)| ContractGas {
+                name: contract.name.clone().unwrap_or_else(|| address.to_string()),
+                address: *address,
+                deployment: contract.deployment.clone(),
+                functions: contract
+                    .calls
+                    .iter()
+                    .map(|(name, gas)| stats(name, gas))
+                    .collect(),
+            })
+            .collect();
+        GasReport {
+            markdown: markdown(&contracts),
+            contracts,
+        }
+    }
+}
+
+fn stats(name: &str, gas: &[u64]) -> FunctionGas {
+    let mut sorted = gas.to_vec();
+    sorted.sort_unstable();
+    let mid = sorted.len() / 2;
+    // Like forge, an even count's median is the mean of the middle two
+    let median = if sorted.len() % 2 == 0 {
+        (sorted[mid - 1] + sorted[mid]) / 2
+    } else {
+        sorted[mid]
+    };
+    FunctionGas {
+        name: name.to_string(),
+        calls: sorted.len() as u64,
+        min: sorted[0],
+        avg: sorted.iter().sum::<u64>() / sorted.len() as u64,
+        median,
+        max: sorted[sorted.len() - 1],
+    }
+}
+
+fn markdown(contracts: &[ContractGas]) -> String {
+    let mut out = String::new();
+    for (i, contract) in contracts.iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        let _ = writeln!(out, "| {} contract |  |  |  |  |  |", contract.name);
+        out.push_str("|---|---|---|---|---|---|\n");
+        out.push_str("| Deployment Cost | Deployment Size |  |  |  |  |\n");
+        match &contract.deployment {
+            Some(deployment) => {
+                let _ = writeln!(
+                    out,
+                    "| {} | {} |  |  |  |  |",
+                    deployment.gas, deployment.size
+                );
+            }
+            None => out.push_str("| - | - |  |  |  |  |\n"),
+        }
+        out.push_str("| Function Name | min | avg | median | max | # calls |\n");
+        for function in &contract.functions {
+            let _ = writeln!(
+                out,
+                "| {} | {} | {} | {} | {} | {} |",
+                function.name,
+                function.min,
+                function.avg,
+                function.median,
+                function.max,
+                function.calls
+            );
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gas::ExitReason;
+    use alloy_primitives::{address, Bytes, U256};
+
+    const COUNTER: Address = address!("1000000000000000000000000000000000000001");
+    const TOKEN: Address = address!("2000000000000000000000000000000000000002");
+
+    fn node(to: Address, input: &str, gas_used: u64, children: Vec<TraceNode>) -> TraceNode {
+        TraceNode {
+            kind: TraceKind::Call,
+            from: Address::ZERO,
+            to,
+            value: U256::ZERO,
+            gas_used,
+            input: input.parse::<Bytes>().unwrap(),
+            output: Bytes::new(),
+            status: TraceStatus::Success,
+            output_length: None,
+            revert_reason: None,
+            children,
+            logs: vec![],
+        }
+    }
+
+    fn result(trace: TraceNode) -> ExecutionResult {
+        ExecutionResult {
+            exit_reason: ExitReason::Success,
+            success: true,
+            reverted: false,
+            result: Bytes::new(),
+            revert_reason: None,
+            gas_used: 21_000 + trace.gas_used,
+            gas_limit: None,
+            logs: vec![],
+            traces: Some(vec![trace]),
+            raw_traces: None,
+            labels: BTreeMap::from([(TOKEN, "token.eth".to_string())]),
+            trace_export: None,
+            flamegraph: None,
+            state_diff: None,
+            access_list: None,
+            consistency_check: None,
+            env: None,
+            hint: None,
+            journal: None,
+            transient_storage_accesses: None,
+            created: None,
+            paused_at: None,
+            internal_frames: None,
+            target_balance_before: None,
+            target_balance_after: None,
+            tx_validity: None,
+            attempts: None,
+            skipped: None,
+            call_index: None,
+            meta: None,
+        }
+    }
+
+    #[test]
+    fn test_batch_report() {
+        let abi = JsonAbi::parse(["function increment()", "function add(uint256 amount)"]).unwrap();
+        let mut reporter = GasReporter::new();
+        reporter.name(COUNTER, "Counter", Some(&abi));
+        reporter.deployed(COUNTER, 120_000, 400);
+
+        // increment() three times, add(uint256) twice, once calling the token
+        for gas in [43_000, 5_000, 5_000] {
+            reporter.record(&result(node(COUNTER, "0xd09de08a", gas, vec![])));
+        }
+        reporter.record(&result(node(COUNTER, "0x1003e2d2", 30_000, vec![])));
+        reporter.record(&result(node(
+            COUNTER,
+            "0x1003e2d2",
+            9_000,
+            vec![node(TOKEN, "0x70a08231", 2_500, vec![])],
+        )));
+
+        let report = reporter.report();
+        assert_eq!(report.contracts.len(), 2);
+        let counter = &report.contracts[0];
+        assert_eq!(counter.name, "Counter");
+        assert_eq!(
+            counter.deployment,
+            Some(DeploymentGas {
+                gas: 120_000,
+                size: 400
+            })
+        );
+
+        let calls: Vec<_> = counter
+            .functions
+            .iter()
+            .map(|function| (function.name.as_str(), function.calls))
+            .collect();
+        assert_eq!(calls, [("add", 2), ("increment", 3)]);
+        for function in &counter.functions {
+            assert!(function.min <= function.avg && function.avg <= function.max);
+            assert!(function.min <= function.median && function.median <= function.max);
+        }
+        assert_eq!(
+            counter.functions[1],
+            FunctionGas {
+                name: "increment".to_string(),
+                calls: 3,
+                min: 5_000,
+                avg: 17_666,
+                median: 5_000,
+                max: 43_000,
+            }
+        );
+        assert_eq!(counter.functions[0].median, 19_500);
+
+        let token = &report.contracts[1];
+        assert_eq!(token.name, "token.eth");
+        assert_eq!(token.deployment, None);
+        assert_eq!(token.functions[0].name, "0x70a08231");
+
+        assert!(report
+            .markdown
+            .contains("| Counter contract |  |  |  |  |  |\n"));
+        assert!(report
+            .markdown
+            .contains("| increment | 5000 | 17666 | 5000 | 43000 | 3 |\n"));
+        assert!(report.markdown.contains("| 120000 | 400 |"));
+    }
+}
@@ -0,0 +1,191 @@
+//! Hardforks by name, as `hardfork` picks them, and the opcodes that arrived with later ones, to
+//! explain an invalid opcode that's only invalid because the chain runs an earlier one, like
+//! Cancun-compiled code on a Shanghai chain.
+
+use alloy_primitives::Bytes;
+use forge::executors::RawCallResult;
+use revm_primitives::{SpecId, TransactTo};
+
+// Every opcode added since Frontier, with the hardfork that added it
+const NEW_OPCODES: [(u8, &str, SpecId); 19] = [
+    (0xf4, "DELEGATECALL", SpecId::HOMESTEAD),
+    (0x3d, "RETURNDATASIZE", SpecId::BYZANTIUM),
+    (0x3e, "RETURNDATACOPY", SpecId::BYZANTIUM),
+    (0xfa, "STATICCALL", SpecId::BYZANTIUM),
+    (0xfd, "REVERT", SpecId::BYZANTIUM),
+    (0x1b, "SHL", SpecId::CONSTANTINOPLE),
+    (0x1c, "SHR", SpecId::CONSTANTINOPLE),
+    (0x1d, "SAR", SpecId::CONSTANTINOPLE),
+    (0x3f, "EXTCODEHASH", SpecId::CONSTANTINOPLE),
+    (0xf5, "CREATE2", SpecId::CONSTANTINOPLE),
+    (0x46, "CHAINID", SpecId::ISTANBUL),
+    (0x47, "SELFBALANCE", SpecId::ISTANBUL),
+    (0x48, "BASEFEE", SpecId::LONDON),
+    (0x5f, "PUSH0", SpecId::SHANGHAI),
+    (0x49, "BLOBHASH", SpecId::CANCUN),
+    (0x4a, "BLOBBASEFEE", SpecId::CANCUN),
+    (0x5c, "TLOAD", SpecId::CANCUN),
+    (0x5d, "TSTORE", SpecId::CANCUN),
+    (0x5e, "MCOPY", SpecId::CANCUN),
+];
+
+/// Every hardfork that changed the EVM, oldest first, named as solc's `evmVersion` names them
+pub const HARDFORKS: [(&str, SpecId); 14] = [
+    ("frontier", SpecId::FRONTIER),
+    ("homestead", SpecId::HOMESTEAD),
+    ("tangerineWhistle", SpecId::TANGERINE),
+    ("spuriousDragon", SpecId::SPURIOUS_DRAGON),
+    ("byzantium", SpecId::BYZANTIUM),
+    ("constantinople", SpecId::CONSTANTINOPLE),
+    ("petersburg", SpecId::PETERSBURG),
+    ("istanbul", SpecId::ISTANBUL),
+    ("berlin", SpecId::BERLIN),
+    ("london", SpecId::LONDON),
+    ("paris", SpecId::MERGE),
+    ("shanghai", SpecId::SHANGHAI),
+    ("cancun", SpecId::CANCUN),
+    ("prague", SpecId::PRAGUE),
+];
+
+/// What calls run under when nothing picks a hardfork
+pub const LATEST: &str = "latest";
+
+/// The hardfork called `name`, if there's one.
+pub fn spec(name: &str) -> Option<SpecId> {
+    HARDFORKS
+        .iter()
+        .find(|(hardfork, _)| *hardfork == name)
+        .map(|(_, spec)| *spec)
+}
+
+/// `spec`'s name, or `latest` for `SpecId::LATEST`.
+pub fn name(spec: SpecId) -> &'static str {
+    match spec {
+        SpecId::LATEST => LATEST,
+        spec => evm_version(spec),
+    }
+}
+
+/// For a call that hit an invalid opcode: which opcode in the code it ran is from a later
+/// hardfork than the chain's, if one is, and what to do about it.
+pub(super) fn hint(r: &RawCallResult) -> Option<String> {
+    let created = match r.env.tx.transact_to {
+        TransactTo::Create => Some(r.env.tx.data.clone()),
+        TransactTo::Call(_) => None,
+    };
+    let deployed = r
+        .state_changeset
+        .values()
+        .filter_map(|account| account.info.code.as_ref())
+        .map(|code| code.original_bytes());
+    unsupported_opcode(
+        r.env.handler_cfg.spec_id,
+        created.into_iter().chain(deployed),
+    )
+}
+
+fn unsupported_opcode(spec: SpecId, codes: impl IntoIterator<Item = Bytes>) -> Option<String> {
+    let (opcode, name, added) = codes
+        .into_iter()
+        .find_map(|code| first_unsupported(spec, without_metadata(&code)))?;
+    Some(format!(
+        "{} (0x{:02x}) is only available from {}, but the chain runs {}. Recompile with \
+         evmVersion {} or earlier, or run on a chain that has {}.",
+        name,
+        opcode,
+        evm_version(added),
+        evm_version(spec),
+        evm_version(spec),
+        evm_version(added)
+    ))
+}
+
+// The first instruction in `code` the chain doesn't have, push data skipped
+fn first_unsupported(spec: SpecId, code: &[u8]) -> Option<(u8, &'static str, SpecId)> {
+    let mut pc = 0;
+    while let Some(&opcode) = code.get(pc) {
+        if let Some(found) = NEW_OPCODES
+            .iter()
+            .find(|(op, _, added)| *op == opcode && !SpecId::enabled(spec, *added))
+        {
+            return Some(*found);
+        }
+        pc += match opcode {
+            0x60..=0x7f => 1 + (opcode - 0x5f) as usize,
+            _ => 1,
+        };
+    }
+    None
+}
+
+// solc appends CBOR metadata to the code, its length in the last two bytes. It isn't code, and
+// any byte may turn up in its hashes.
+pub(crate) fn without_metadata(code: &[u8]) -> &[u8] {
+    let Some(split) = code.len().checked_sub(2) else {
+        return code;
+    };
+    let length = u16::from_be_bytes([code[split], code[split + 1]]) as usize;
+    match split.checked_sub(length) {
+        // A CBOR map of one to three entries
+        Some(start) if (0xa1..=0xa3).contains(&code[start]) => &code[..start],
+        _ => code,
+    }
+}
+
+// The latest hardfork `spec` includes
+fn evm_version(spec: SpecId) -> &'static str {
+    HARDFORKS
+        .iter()
+        .rev()
+        .find(|(_, version)| SpecId::enabled(spec, *version))
+        .map_or("frontier", |(name, _)| name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::bytes;
+
+    #[test]
+    fn test_unsupported_opcode() {
+        // PUSH1 0x20 PUSH0 PUSH0 MCOPY STOP
+        let mcopy = bytes!("60205f5f5e00");
+        let hint = unsupported_opcode(SpecId::SHANGHAI, [mcopy.clone()]).unwrap();
+        assert!(hint.starts_with("MCOPY (0x5e) is only available from cancun"));
+        assert!(hint.contains("evmVersion shanghai or earlier"));
+        assert_eq!(unsupported_opcode(SpecId::CANCUN, [mcopy]), None);
+
+        // PUSH0 before Shanghai, reported over the MCOPY after it
+        let hint = unsupported_opcode(SpecId::MERGE, [bytes!("5f5e")]).unwrap();
+        assert!(hint.starts_with("PUSH0 (0x5f) is only available from shanghai"));
+        assert!(hint.contains("chain runs paris"));
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(spec("istanbul"), Some(SpecId::ISTANBUL));
+        assert_eq!(spec("paris"), Some(SpecId::MERGE));
+        assert_eq!(spec("merge"), None);
+        assert_eq!(name(SpecId::MERGE), "paris");
+        // Forks that didn't change the EVM go by the last one that did
+        assert_eq!(name(SpecId::MUIR_GLACIER), "istanbul");
+        assert_eq!(name(SpecId::LATEST), "latest");
+        for (hardfork, spec) in HARDFORKS {
+            assert_eq!(name(spec), hardfork);
+        }
+    }
+
+    #[test]
+    fn test_data_isnt_code() {
+        // PUSH2 0x5e5e STOP
+        assert_eq!(
+            unsupported_opcode(SpecId::SHANGHAI, [bytes!("615e5e00")]),
+            None
+        );
+        // STOP, then metadata: {"a": h'5e5c'}
+        assert_eq!(
+            unsupported_opcode(SpecId::SHANGHAI, [bytes!("00a16161425e5c0006")]),
+            None
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//! A call's internal function frames: the functions a contract jumped into and back out of
+//! without leaving itself, so gas can be put down to the function that spent it rather than
+//! the external call it ran in.
+//!
+//! The trace only has the jumps, from the `jump` or `debug` trace modes. Which of them go into
+//! or out of a function is in the source map, and which function starts where is in
+//! `functionDebugData`, so frames can only be read off code the request compiled.
+
+use alloy_primitives::Address;
+use forge::traces::{CallTraceArena, CallTraceNode};
+use revm::interpreter::opcode;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use super::journal::SourceLocation;
+use super::trace::TraceKind;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct InternalFrame {
+    /// As declared, or solc's name for a function it generated. Null when the code jumped to
+    /// isn't the start of any function solc knows of.
+    pub function: Option<String>,
+    /// The external call frame it ran in, the call itself being 0
+    pub depth: usize,
+    /// Of the steps the trace recorded for that frame, the jump in
+    pub enter_step: usize,
+    /// And the jump back out. Null when the function never returned, having reverted or run out
+    /// of gas.
+    pub exit_step: Option<usize>,
+    /// From the jump in to the jump out, what it called included
+    pub gas_used: u64,
+    /// Where the function is declared
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<SourceLocation>,
+    /// The functions it called, in order
+    pub children: Vec<InternalFrame>,
+}
+
+/// Which way a JUMP goes, as the source map marks it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FunctionJump {
+    In,
+    Out,
+}
+
+/// What a contract's compilation says about its deployed code, by pc.
+#[derive(Clone, Debug, Default)]
+pub struct CodeMap {
+    /// The JUMPs into and out of functions
+    pub jumps: BTreeMap<usize, FunctionJump>,
+    /// Function names by their entry points
+    pub functions: BTreeMap<usize, String>,
+    /// Where the function at each entry point is declared
+    pub sources: BTreeMap<usize, SourceLocation>,
+}
+
+/// The internal frames the code at `address`, described by `map`, ran in `arena`'s calls, in
+/// the order the calls were made.
+pub fn internal_frames(
+    arena: &CallTraceArena,
+    address: Address,
+    map: &CodeMap,
+) -> Vec<InternalFrame> {
+    arena
+        .nodes()
+        .iter()
+        // A constructor runs the creation code, which `map` isn't of
+        .filter(|node| {
+            node.trace.address == address
+                && !matches!(
+                    TraceKind::from(node.trace.kind),
+                    TraceKind::Create | TraceKind::Create2
+                )
+        })
+        .flat_map(|node| frames(node, map))
+        .collect()
+}
+
+// The frames of one external call
+fn frames(node: &CallTraceNode, map: &CodeMap) -> Vec<InternalFrame> {
+    let steps = &node.trace.steps;
+    let mut roots = Vec::new();
+    // Entered and not yet left, innermost last, each with the gas there was on entry
+    let mut open: Vec<(InternalFrame, u64)> = Vec::new();
+    for (index, step) in steps.iter().enumerate() {
+        match step.op.get() {
+            opcode::JUMP => match map.jumps.get(&step.pc) {
+                Some(FunctionJump::In) => {
+                    let frame = InternalFrame {
+                        function: None,
+                        depth: node.trace.depth,
+                        enter_step: index,
+                        exit_step: None,
+                        gas_used: 0,
+                        source: None,
+                        children: Vec::new(),
+                    };
+                    open.push((frame, step.gas_remaining));
+                }
+                Some(FunctionJump::Out) => {
+                    if let Some((mut frame, entered)) = open.pop() {
+                        frame.exit_step = Some(index);
+                        frame.gas_used = entered.saturating_sub(step.gas_remaining);
+                        close(&mut open, &mut roots, frame);
+                    }
+                }
+                None => {}
+            },
+            // Where the jump in landed, which is the function's entry point
+            opcode::JUMPDEST => {
+                if let Some((frame, _)) = open
+                    .last_mut()
+                    .filter(|(frame, _)| frame.enter_step + 1 == index)
+                {
+                    frame.function = map.functions.get(&step.pc).cloned();
+                    frame.source = map.sources.get(&step.pc).cloned();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let left = steps.last().map_or(0, |step| step.gas_remaining);
+    while let Some((mut frame, entered)) = open.pop() {
+        frame.gas_used = entered.saturating_sub(left);
+        close(&mut open, &mut roots, frame);
+    }
+    roots
+}
+
+// Hands a frame that's been left to the one that called it
+fn close(open: &mut [(InternalFrame, u64)], roots: &mut Vec<InternalFrame>, frame: InternalFrame) {
+    match open.last_mut() {
+        Some((caller, _)) => caller.children.push(frame),
+        None => roots.push(frame),
+    }
+}
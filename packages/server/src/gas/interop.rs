@@ -0,0 +1,317 @@
+//! Results in the shape cast prints them with `--json`, so scripts written against
+//! `cast access-list` and `cast call` can read ours. Field names and their order follow cast.
+
+use alloy_dyn_abi::{DynSolValue, FunctionExt};
+use alloy_eips::eip2930::{AccessList, AccessListItem};
+use alloy_json_abi::JsonAbi;
+use alloy_primitives::{hex, Address, B256, U256};
+use revm_primitives::State;
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use super::execute_calldatas_fork::{Call, ExecutionResult};
+
+/// One call, as cast reports it.
+#[derive(Serialize, Clone, Debug, PartialEq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CastResult {
+    /// What `cast call --json` prints: the return values as strings when `abi` has the function,
+    /// the raw return data as hex otherwise. Null when the call failed, where cast prints an
+    /// error instead.
+    #[schema(value_type = Object)]
+    pub call: Value,
+    pub access_list: CastAccessList,
+}
+
+/// What `cast access-list --json` prints.
+#[derive(Serialize, Clone, Debug, PartialEq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CastAccessList {
+    /// Every account and slot the call touched, less the caller, the coinbase and precompiles
+    /// when the call read none of their storage
+    #[schema(value_type = Vec<Object>)]
+    pub access_list: AccessList,
+    /// The call's, without the list applied
+    #[schema(value_type = String)]
+    pub gas_used: U256,
+    /// Why the call failed, as a node's `eth_createAccessList` puts it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// `result` of `call`, with its return values decoded from `abi` where it has the function.
+pub fn cast_result(call: &Call, result: &ExecutionResult, abi: Option<&JsonAbi>) -> CastResult {
+    let error = match (result.success, result.reverted) {
+        (true, _) => None,
+        (false, true) => Some("execution reverted".to_string()),
+        // Named as revm names the halt, e.g. `outOfGas`
+        (false, false) => serde_json::to_value(&result.exit_reason)
+            .ok()
+            .and_then(|name| name.as_str().map(str::to_string)),
+    };
+    let call = match &error {
+        Some(_) => Value::Null,
+        None => call_output(call, result, abi),
+    };
+    CastResult {
+        call,
+        access_list: CastAccessList {
+            access_list: result.access_list.clone().unwrap_or_default(),
+            gas_used: U256::from(result.gas_used),
+            error,
+        },
+    }
+}
+
+fn call_output(call: &Call, result: &ExecutionResult, abi: Option<&JsonAbi>) -> Value {
+    let function = call.calldata.get(..4).and_then(|selector| {
+        abi?.functions()
+            .find(|function| function.selector() == selector)
+    });
+    let decoded =
+        function.and_then(|function| function.abi_decode_output(&result.result, false).ok());
+    match decoded {
+        Some(values) => Value::Array(
+            values
+                .iter()
+                .map(|value| Value::String(format_raw(value)))
+                .collect(),
+        ),
+        None => Value::String(hex::encode_prefixed(&result.result)),
+    }
+}
+
+// As cast formats a top-level return value: strings bare, everything else as within arrays
+fn format_raw(value: &DynSolValue) -> String {
+    match value {
+        DynSolValue::String(s) => s.clone(),
+        other => format_nested(other),
+    }
+}
+
+fn format_nested(value: &DynSolValue) -> String {
+    let list = |items: &[DynSolValue]| {
+        items
+            .iter()
+            .map(format_nested)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    match value {
+        DynSolValue::Bool(b) => b.to_string(),
+        DynSolValue::Int(i, _) => i.to_string(),
+        DynSolValue::Uint(u, _) => u.to_string(),
+        DynSolValue::FixedBytes(word, size) => hex::encode_prefixed(&word[..*size]),
+        DynSolValue::Address(address) => address.to_checksum(None),
+        DynSolValue::Bytes(bytes) => hex::encode_prefixed(bytes),
+        DynSolValue::String(s) => format!("{:?}", s),
+        DynSolValue::Array(items) | DynSolValue::FixedArray(items) => {
+            format!("[{}]", list(items))
+        }
+        DynSolValue::Tuple(items) => format!("({})", list(items)),
+        other => hex::encode_prefixed(other.abi_encode()),
+    }
+}
+
+/// The accounts and slots in `changeset`, what a call loaded, as an access list. `skip` and the
+/// precompiles are left out unless the call read their storage, as nodes leave out the sender
+/// and precompiles.
+pub(super) fn access_list(changeset: &State, skip: &[Address]) -> AccessList {
+    let mut items: Vec<_> = changeset
+        .iter()
+        .map(|(address, account)| {
+            let mut storage_keys: Vec<_> =
+                account.storage.keys().map(|key| B256::from(*key)).collect();
+            storage_keys.sort();
+            AccessListItem {
+                address: *address,
+                storage_keys,
+            }
+        })
+        .filter(|item| {
+            !item.storage_keys.is_empty()
+                || !(skip.contains(&item.address) || is_precompile(item.address))
+        })
+        .collect();
+    items.sort_by_key(|item| item.address);
+    AccessList(items)
+}
+
+// 0x01 to 0x0a, up to Cancun's point evaluation
+fn is_precompile(address: Address) -> bool {
+    address[..19].iter().all(|byte| *byte == 0) && (1..=0x0a).contains(&address[19])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gas::{EventLog, ExitReason};
+    use alloy_primitives::{address, bytes, Bytes};
+    use revm_primitives::{Account, AccountInfo, AccountStatus, EvmStorageSlot};
+    use std::collections::BTreeMap;
+
+    const CALLER: Address = address!("1000000000000000000000000000000000000000");
+    const SIMPLE_STORAGE: Address = address!("b2f9974c62815d3177079e150377915d9bc49c82");
+    const COINBASE: Address = address!("0000000000000000000000000000000000000000");
+
+    fn abi() -> JsonAbi {
+        JsonAbi::parse([
+            "function get() view returns (uint256)",
+            "function owner() view returns (address)",
+            "function describe() view returns (string, uint256[], (bool, bytes4))",
+        ])
+        .unwrap()
+    }
+
+    fn call(calldata: Bytes) -> Call {
+        Call {
+            calldata,
+            value: U256::ZERO,
+            caller: Some(CALLER.into()),
+            independent: false,
+            signature: None,
+            args: None,
+            meta: None,
+        }
+    }
+
+    // get() on SimpleStorage holding 42, reading slot 0
+    fn get() -> (Call, ExecutionResult) {
+        let result = ExecutionResult {
+            exit_reason: ExitReason::Success,
+            success: true,
+            reverted: false,
+            result: bytes!("000000000000000000000000000000000000000000000000000000000000002a"),
+            revert_reason: None,
+            gas_used: 23_471,
+            gas_limit: Some(30_000_000),
+            logs: EventLog::from_logs(vec![]),
+            traces: None,
+            raw_traces: None,
+            labels: BTreeMap::new(),
+            trace_export: None,
+            flamegraph: None,
+            state_diff: None,
+            access_list: Some(AccessList(vec![AccessListItem {
+                address: SIMPLE_STORAGE,
+                storage_keys: vec![B256::ZERO],
+            }])),
+            consistency_check: None,
+            env: None,
+            hint: None,
+            journal: None,
+            transient_storage_accesses: None,
+            created: None,
+            paused_at: None,
+            internal_frames: None,
+            target_balance_before: None,
+            target_balance_after: None,
+            tx_validity: None,
+            attempts: None,
+            skipped: None,
+            call_index: None,
+            meta: None,
+        };
+        (call(bytes!("6d4ce63c")), result)
+    }
+
+    #[test]
+    fn test_access_list_matches_cast() {
+        let (call, result) = get();
+        let cast = cast_result(&call, &result, Some(&abi()));
+        // Compared as text, so the keys' order counts too
+        let fixture: String = include_str!("fixtures/cast_access_list.json")
+            .split_whitespace()
+            .collect();
+        assert_eq!(serde_json::to_string(&cast.access_list).unwrap(), fixture);
+    }
+
+    #[test]
+    fn test_call_matches_cast() {
+        let fixture: Value = serde_json::from_str(include_str!("fixtures/cast_call.json")).unwrap();
+        let (call, mut result) = get();
+        let cast = cast_result(&call, &result, Some(&abi()));
+        assert_eq!(cast.call, fixture["get"]);
+
+        // Without the function, the bytes as returned
+        let cast = cast_result(&call, &result, None);
+        assert_eq!(cast.call, fixture["raw"]);
+
+        let call = self::call(bytes!("8da5cb5b"));
+        result.result = bytes!("000000000000000000000000b2f9974c62815d3177079e150377915d9bc49c82");
+        let cast = cast_result(&call, &result, Some(&abi()));
+        assert_eq!(cast.call, fixture["owner"]);
+
+        let describe = &abi().function("describe").unwrap()[0];
+        let call = self::call(describe.selector().to_vec().into());
+        result.result = describe
+            .abi_encode_output(&[
+                DynSolValue::String("simple \"storage\"".to_string()),
+                DynSolValue::Array(vec![
+                    DynSolValue::Uint(U256::from(1), 256),
+                    DynSolValue::Uint(U256::from(2), 256),
+                ]),
+                DynSolValue::Tuple(vec![
+                    DynSolValue::Bool(true),
+                    DynSolValue::FixedBytes(B256::right_padding_from(&[0x6d, 0x4c, 0xe6, 0x3c]), 4),
+                ]),
+            ])
+            .unwrap()
+            .into();
+        let cast = cast_result(&call, &result, Some(&abi()));
+        assert_eq!(cast.call, fixture["describe"]);
+    }
+
+    #[test]
+    fn test_failed_call() {
+        let (call, mut result) = get();
+        result.success = false;
+        result.reverted = true;
+        result.exit_reason = ExitReason::Revert;
+        let body = serde_json::to_value(cast_result(&call, &result, Some(&abi()))).unwrap();
+        assert!(body["call"].is_null());
+        assert_eq!(body["accessList"]["error"], "execution reverted");
+        // What was touched before the revert is still listed
+        assert_eq!(
+            body["accessList"]["accessList"][0]["address"],
+            "0xb2f9974c62815d3177079e150377915d9bc49c82"
+        );
+    }
+
+    #[test]
+    fn test_access_list_from_changeset() {
+        let account = |slots: &[u64]| Account {
+            info: AccountInfo::default(),
+            storage: slots
+                .iter()
+                .map(|slot| (U256::from(*slot), EvmStorageSlot::new(U256::ZERO)))
+                .collect(),
+            status: AccountStatus::Loaded,
+        };
+        let identity = address!("0000000000000000000000000000000000000004");
+        let token = address!("4200000000000000000000000000000000000006");
+        let changeset = State::from_iter([
+            (CALLER, account(&[])),
+            (COINBASE, account(&[])),
+            (identity, account(&[])),
+            (SIMPLE_STORAGE, account(&[3, 0])),
+            (token, account(&[])),
+        ]);
+
+        let list = access_list(&changeset, &[CALLER, COINBASE]);
+        assert_eq!(
+            list.0,
+            [
+                AccessListItem {
+                    address: token,
+                    storage_keys: vec![],
+                },
+                AccessListItem {
+                    address: SIMPLE_STORAGE,
+                    storage_keys: vec![B256::ZERO, B256::from(U256::from(3))],
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,294 @@
+//! A call's journal: what it did that changes state or what a viewer shows, in the order it did
+//! it. Much smaller than the opcodes it ran, but enough to step a UI through the call.
+//!
+//! Forge's executor only takes its own inspectors, so the journal is read off the opcodes the
+//! `debug` trace mode records rather than from one of ours; asking for a journal records them.
+
+use alloy_primitives::{Address, Bytes, B256, U256};
+use forge::traces::{CallTraceArena, CallTraceNode, CallTraceStep};
+use revm::interpreter::opcode;
+use serde::{Deserialize, Serialize};
+
+use super::trace::{TraceKind, TraceStatus};
+
+/// The most events a call's journal keeps. The rest are dropped, with a `truncated` event at the
+/// end saying how many.
+pub const MAX_JOURNAL_EVENTS: usize = 10_000;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct JournalEvent {
+    /// How many opcodes the call had run, in every frame, before this happened
+    pub step: usize,
+    /// The frame it happened in, the call itself being 0
+    pub depth: usize,
+    /// The account whose code was running
+    pub address: Address,
+    /// The opcode's, for those that happened at one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pc: Option<usize>,
+    #[serde(flatten)]
+    pub entry: JournalEntry,
+    /// The source `pc` was compiled from, when the request compiled the contract
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<SourceLocation>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(
+    tag = "kind",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum JournalEntry {
+    StorageWrite {
+        slot: U256,
+        value: U256,
+    },
+    Log {
+        topics: Vec<B256>,
+        data: Bytes,
+    },
+    /// Into a frame, `address` being the caller
+    CallEnter {
+        call: TraceKind,
+        to: Address,
+        value: U256,
+        input: Bytes,
+    },
+    /// Out of the frame `address` ran
+    CallExit {
+        status: TraceStatus,
+        gas_used: u64,
+        output: Bytes,
+    },
+    Transfer {
+        from: Address,
+        to: Address,
+        value: U256,
+    },
+    /// A contract made at `to`, before its constructor runs
+    Create {
+        call: TraceKind,
+        to: Address,
+        value: U256,
+    },
+    Selfdestruct {
+        beneficiary: Address,
+    },
+    /// Where events were left out, past `MAX_JOURNAL_EVENTS`
+    Truncated {
+        dropped: usize,
+    },
+}
+
+/// A span of a source file.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceLocation {
+    /// As the file was sent
+    pub file: String,
+    /// Of the span's start, from 1
+    pub line: usize,
+    /// Bytes into the file
+    pub offset: usize,
+    pub length: usize,
+}
+
+/// The journal of the call in `arena`, at most `limit` events and the truncation marker.
+pub(super) fn journal(arena: &CallTraceArena, limit: usize) -> Vec<JournalEvent> {
+    let nodes = arena.nodes();
+    let mut builder = Builder {
+        nodes,
+        step: 0,
+        events: Vec::new(),
+    };
+    for root in nodes.iter().filter(|node| node.parent.is_none()) {
+        builder.enter(root, None);
+    }
+    cap(builder.events, limit)
+}
+
+/// `events` cut to `limit`, with a `truncated` event after them if any were dropped.
+pub fn cap(mut events: Vec<JournalEvent>, limit: usize) -> Vec<JournalEvent> {
+    if events.len() <= limit {
+        return events;
+    }
+    let dropped = events.len() - limit;
+    events.truncate(limit);
+    let last = events.last();
+    events.push(JournalEvent {
+        step: last.map_or(0, |event| event.step),
+        depth: last.map_or(0, |event| event.depth),
+        address: last.map_or(Address::ZERO, |event| event.address),
+        pc: None,
+        entry: JournalEntry::Truncated { dropped },
+        source: None,
+    });
+    events
+}
+
+struct Builder<'a> {
+    nodes: &'a [CallTraceNode],
+    // Opcodes run so far
+    step: usize,
+    events: Vec<JournalEvent>,
+}
+
+impl Builder<'_> {
+    // `node`'s frame, from its call to its exit. `at` is the caller's frame and the step that
+    // made the call, for all but the top-level call.
+    fn enter(&mut self, node: &CallTraceNode, at: Option<(&CallTraceNode, &CallTraceStep, usize)>) {
+        let trace = &node.trace;
+        let kind = TraceKind::from(trace.kind);
+        let (step, depth, pc) = match at {
+            Some((caller, call, index)) => (index, caller.trace.depth, Some(call.pc)),
+            None => (self.step, trace.depth, None),
+        };
+        let mut push = |entry| {
+            self.events.push(JournalEvent {
+                step,
+                depth,
+                address: trace.caller,
+                pc,
+                entry,
+                source: None,
+            })
+        };
+        let transfers = !trace.value.is_zero()
+            && !matches!(kind, TraceKind::DelegateCall | TraceKind::StaticCall);
+        if matches!(kind, TraceKind::Create | TraceKind::Create2) {
+            push(JournalEntry::Create {
+                call: kind,
+                to: trace.address,
+                value: trace.value,
+            });
+        } else {
+            push(JournalEntry::CallEnter {
+                call: kind,
+                to: trace.address,
+                value: trace.value,
+                input: trace.data.clone(),
+            });
+        }
+        if transfers {
+            push(JournalEntry::Transfer {
+                from: trace.caller,
+                to: trace.address,
+                value: trace.value,
+            });
+        }
+
+        let nodes = self.nodes;
+        let mut children = node.children.iter().map(|&child| &nodes[child]);
+        let mut logs = node.logs.iter();
+        for step in &trace.steps {
+            let index = self.step;
+            self.step += 1;
+            let entry = match step.op.get() {
+                opcode::SSTORE => storage_write(step),
+                opcode::LOG0..=opcode::LOG4 => logs.next().map(|log| JournalEntry::Log {
+                    topics: log.raw_log.topics().to_vec(),
+                    data: log.raw_log.data.clone(),
+                }),
+                opcode::SELFDESTRUCT => {
+                    stack(step, 0).map(|beneficiary| JournalEntry::Selfdestruct {
+                        beneficiary: Address::from_word(beneficiary.into()),
+                    })
+                }
+                opcode::CALL
+                | opcode::CALLCODE
+                | opcode::DELEGATECALL
+                | opcode::STATICCALL
+                | opcode::CREATE
+                | opcode::CREATE2 => {
+                    // None when the call failed before it started, e.g. too deep
+                    if let Some(child) = children.next() {
+                        self.enter(child, Some((node, step, index)));
+                    }
+                    None
+                }
+                _ => None,
+            };
+            if let Some(entry) = entry {
+                self.events.push(JournalEvent {
+                    step: index,
+                    depth: trace.depth,
+                    address: trace.address,
+                    pc: Some(step.pc),
+                    entry,
+                    source: None,
+                });
+            }
+        }
+
+        self.events.push(JournalEvent {
+            step: self.step,
+            depth: trace.depth,
+            address: trace.address,
+            pc: None,
+            entry: JournalEntry::CallExit {
+                status: trace.status.into(),
+                gas_used: trace.gas_used,
+                output: trace.output.clone(),
+            },
+            source: None,
+        });
+    }
+}
+
+// What the step's SSTORE wrote, recorded with the state diff or else read off its stack
+fn storage_write(step: &CallTraceStep) -> Option<JournalEntry> {
+    if let Some(change) = &step.storage_change {
+        return Some(JournalEntry::StorageWrite {
+            slot: change.key,
+            value: change.value,
+        });
+    }
+    Some(JournalEntry::StorageWrite {
+        slot: stack(step, 0)?,
+        value: stack(step, 1)?,
+    })
+}
+
+/// The `n`th item down the stack before the step ran.
+pub(super) fn stack(step: &CallTraceStep, n: usize) -> Option<U256> {
+    let stack = step.stack.as_ref()?;
+    stack.len().checked_sub(n + 1).map(|i| stack[i])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(step: usize) -> JournalEvent {
+        JournalEvent {
+            step,
+            depth: 0,
+            address: Address::repeat_byte(0x20),
+            pc: Some(step),
+            entry: JournalEntry::StorageWrite {
+                slot: U256::ZERO,
+                value: U256::from(step),
+            },
+            source: None,
+        }
+    }
+
+    #[test]
+    fn test_cap_marks_what_it_dropped() {
+        let events: Vec<_> = (0..5).map(write).collect();
+        assert_eq!(cap(events.clone(), 5), events);
+
+        let capped = cap(events.clone(), 3);
+        assert_eq!(capped.len(), 4);
+        assert_eq!(capped[..3], events[..3]);
+        assert_eq!(capped[3].entry, JournalEntry::Truncated { dropped: 2 });
+        assert_eq!(capped[3].step, 2);
+
+        let body = serde_json::to_value(&capped[3]).unwrap();
+        assert_eq!(body["kind"], "truncated");
+        assert_eq!(body["dropped"], 2);
+        assert!(body.get("pc").is_none());
+    }
+}
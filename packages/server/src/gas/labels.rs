@@ -0,0 +1,171 @@
+//! Names for addresses: well-known contracts by chain, merged with ENS names and the labels a
+//! request gives, so traces, exports and diffs say `WETH` rather than `0x4200…0006`.
+
+use alloy_primitives::{address, Address};
+use std::collections::BTreeMap;
+
+use super::execute_calldatas_fork::ExecutionResult;
+
+// At the same address wherever they're deployed
+const EVERY_CHAIN: [(Address, &str); 3] = [
+    (
+        address!("cA11bde05977b3631167028862bE2a173976CA11"),
+        "Multicall3",
+    ),
+    (
+        address!("000000000022D473030F116dDEE9F6B43aC78BA3"),
+        "Permit2",
+    ),
+    (
+        address!("4e59b44847b379578588920cA78FbF26c0B4956C"),
+        "Create2Deployer",
+    ),
+];
+
+const ETHEREUM: [(Address, &str); 7] = [
+    (address!("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2"), "WETH"),
+    (address!("A0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48"), "USDC"),
+    (address!("dAC17F958D2ee523a2206206994597C13D831ec7"), "USDT"),
+    (address!("6B175474E89094C44Da98b954EedeAC495271d0F"), "DAI"),
+    (
+        address!("7a250d5630B4cF539739dF2C5dAcb4c659F2488D"),
+        "UniswapV2Router02",
+    ),
+    (
+        address!("E592427A0AEce92De3Edee1F18E0157C05861564"),
+        "UniswapV3SwapRouter",
+    ),
+    (
+        address!("00000000000C2E074eC69A0dFb2997BA6C7d2e1e"),
+        "ENSRegistry",
+    ),
+];
+
+// Predeploys, at the same addresses on every OP Stack chain
+const OP_STACK: [(Address, &str); 7] = [
+    (address!("4200000000000000000000000000000000000006"), "WETH"),
+    (
+        address!("4200000000000000000000000000000000000007"),
+        "L2CrossDomainMessenger",
+    ),
+    (
+        address!("420000000000000000000000000000000000000F"),
+        "GasPriceOracle",
+    ),
+    (
+        address!("4200000000000000000000000000000000000010"),
+        "L2StandardBridge",
+    ),
+    (
+        address!("4200000000000000000000000000000000000011"),
+        "SequencerFeeVault",
+    ),
+    (
+        address!("4200000000000000000000000000000000000015"),
+        "L1Block",
+    ),
+    (
+        address!("4200000000000000000000000000000000000016"),
+        "L2ToL1MessagePasser",
+    ),
+];
+
+const BASE: [(Address, &str); 2] = [
+    (address!("833589fCD6eDb6E08f4c7C32D4f71b54bdA02913"), "USDC"),
+    (
+        address!("d9aAEc86B65D86f6A7B5B1b0c42FFA531710b6CA"),
+        "USDbC",
+    ),
+];
+
+const OPTIMISM: [(Address, &str); 2] = [
+    (address!("0b2C639c533813f4Aa9D7837CAf62653d097Ff85"), "USDC"),
+    (address!("4200000000000000000000000000000000000042"), "OP"),
+];
+
+/// The well-known contracts on `chain_id`.
+pub fn known(chain_id: u64) -> BTreeMap<Address, String> {
+    let chain: &[(Address, &str)] = match chain_id {
+        1 => &ETHEREUM,
+        8453 => &BASE,
+        10 => &OPTIMISM,
+        _ => &[],
+    };
+    // Base and OP Mainnet, and their Sepolia testnets
+    let op_stack: &[(Address, &str)] = match chain_id {
+        10 | 8453 | 84532 | 11155420 => &OP_STACK,
+        _ => &[],
+    };
+    EVERY_CHAIN
+        .iter()
+        .chain(op_stack)
+        .chain(chain)
+        .map(|(address, label)| (*address, label.to_string()))
+        .collect()
+}
+
+/// `known(chain_id)`, with `labels` in place of any the table has for the same address.
+pub fn merged(
+    chain_id: u64,
+    labels: Option<&BTreeMap<Address, String>>,
+) -> BTreeMap<Address, String> {
+    let mut merged = known(chain_id);
+    merged.extend(
+        labels
+            .into_iter()
+            .flatten()
+            .map(|(address, label)| (*address, label.clone())),
+    );
+    merged
+}
+
+/// The labels in `labels` for addresses `result` mentions: in its traces, their logs, its state
+/// diff or its access list.
+pub(super) fn touched(
+    result: &ExecutionResult,
+    labels: &BTreeMap<Address, String>,
+) -> BTreeMap<Address, String> {
+    let traced = result
+        .traces
+        .iter()
+        .flatten()
+        .flat_map(|root| root.iter())
+        .flat_map(|node| {
+            [node.from, node.to]
+                .into_iter()
+                .chain(node.logs.iter().map(|log| log.address))
+        });
+    let diffed = result
+        .state_diff
+        .iter()
+        .flat_map(|diff| diff.keys().copied());
+    let accessed = result
+        .access_list
+        .iter()
+        .flat_map(|list| list.0.iter().map(|item| item.address));
+    traced
+        .chain(diffed)
+        .chain(accessed)
+        .filter_map(|address| Some((address, labels.get(&address)?.clone())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WETH: Address = address!("4200000000000000000000000000000000000006");
+
+    #[test]
+    fn test_merged() {
+        assert_eq!(known(8453)[&WETH], "WETH");
+        assert_eq!(known(8453)[&EVERY_CHAIN[0].0], "Multicall3");
+        assert!(!known(1).contains_key(&WETH));
+        assert_eq!(known(1337).len(), EVERY_CHAIN.len());
+
+        let labels = BTreeMap::from([(WETH, "Wrapped Ether".to_string())]);
+        let merged = merged(8453, Some(&labels));
+        assert_eq!(merged[&WETH], "Wrapped Ether");
+        assert_eq!(merged.len(), known(8453).len());
+    }
+}
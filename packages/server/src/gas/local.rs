@@ -0,0 +1,245 @@
+use alloy_primitives::{Address, Bytes, U256};
+use forge::executors::Executor;
+use revm::db::AccountState;
+use revm_primitives::Env;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+use super::engine::{Engine, EngineConfig};
+use super::execute_calldatas_fork::{ExecutionOptions, ExecutionResult};
+
+/// The accounts of an in-memory chain, enough to carry on where an earlier deploy or transact
+/// left off.
+pub type StateDump = BTreeMap<Address, AccountDump>;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountDump {
+    #[serde(default)]
+    #[schema(value_type = String)]
+    pub balance: U256,
+    #[serde(default)]
+    pub nonce: u64,
+    #[serde(default, skip_serializing_if = "Bytes::is_empty")]
+    #[schema(value_type = String)]
+    pub code: Bytes,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[schema(value_type = BTreeMap<String, String>)]
+    pub storage: BTreeMap<U256, U256>,
+}
+
+fn dump(executor: &Executor) -> StateDump {
+    executor
+        .backend()
+        .mem_db()
+        .accounts
+        .iter()
+        .filter(|(_, account)| !matches!(account.account_state, AccountState::NotExisting))
+        .map(|(address, account)| {
+            let dump = AccountDump {
+                balance: account.info.balance,
+                nonce: account.info.nonce,
+                code: account
+                    .info
+                    .code
+                    .as_ref()
+                    .map(|code| code.original_bytes())
+                    .unwrap_or_default(),
+                storage: account
+                    .storage
+                    .iter()
+                    .filter(|(_, value)| !value.is_zero())
+                    .map(|(slot, value)| (*slot, *value))
+                    .collect(),
+            };
+            (*address, dump)
+        })
+        .collect()
+}
+
+/// An in-memory chain that keeps its state between transactions, for callers that hold on to
+/// one instead of passing state dumps around.
+pub struct LocalChain {
+    engine: Engine,
+    options: Option<ExecutionOptions>,
+}
+
+// Local chains run in the default environment rather than the demo chain's
+fn local_engine(
+    state: &StateDump,
+    options: Option<ExecutionOptions>,
+) -> Result<Engine, eyre::Error> {
+    EngineConfig::memory(state.clone(), options)
+        .with_env(Env::default())
+        .build()
+}
+
+impl LocalChain {
+    pub fn new(state: &StateDump, options: Option<ExecutionOptions>) -> Result<Self, eyre::Error> {
+        Ok(LocalChain {
+            engine: local_engine(state, options.clone())?,
+            options,
+        })
+    }
+
+    /// Runs creation code (constructor args appended) from `caller`. Returns the deployed
+    /// address, unless the constructor reverted, with the result.
+    pub fn deploy(
+        &mut self,
+        creation_code: Bytes,
+        value: U256,
+        caller: Address,
+    ) -> Result<(Option<Address>, ExecutionResult), eyre::Error> {
+        self.engine.create(caller, creation_code, value)
+    }
+
+    /// Sends one call, committing its state changes.
+    pub fn transact(
+        &mut self,
+        to: Address,
+        calldata: Bytes,
+        value: U256,
+        caller: Address,
+    ) -> Result<ExecutionResult, eyre::Error> {
+        self.engine.execute_call(caller, to, calldata, value)
+    }
+
+    pub fn dump(&self) -> StateDump {
+        dump(self.engine.executor())
+    }
+
+    /// Throws away the current state and carries on from `state`.
+    pub fn restore(&mut self, state: &StateDump) -> Result<(), eyre::Error> {
+        self.engine = local_engine(state, self.options.clone())?;
+        Ok(())
+    }
+}
+
+/// Runs creation code (constructor args appended) from `caller` on top of `state`. Returns the
+/// deployed address, unless the constructor reverted, with the result and the state after it.
+pub fn deploy_local(
+    state: StateDump,
+    creation_code: Bytes,
+    value: U256,
+    caller: Address,
+    options: Option<ExecutionOptions>,
+) -> Result<(Option<Address>, ExecutionResult, StateDump), eyre::Error> {
+    let mut chain = LocalChain::new(&state, options)?;
+    let (address, result) = chain.deploy(creation_code, value, caller)?;
+    Ok((address, result, chain.dump()))
+}
+
+/// Sends one call on top of `state`, returning the result and the state after it.
+pub fn transact_local(
+    state: StateDump,
+    to: Address,
+    calldata: Bytes,
+    value: U256,
+    caller: Address,
+    options: Option<ExecutionOptions>,
+) -> Result<(ExecutionResult, StateDump), eyre::Error> {
+    let mut chain = LocalChain::new(&state, options)?;
+    let result = chain.transact(to, calldata, value, caller)?;
+    Ok((result, chain.dump()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gas::execute_calldatas_fork::DEFAULT_DEPLOYER;
+    use alloy_primitives::hex;
+
+    // Creation code copying in a runtime that stores calldata[4..36] in slot 0 when given an
+    // argument and otherwise returns slot 0
+    const CREATION: &str = "0x601a80600b6000396000f3\
+        60243610600e57600435600055005b60005460005260206000f3";
+
+    #[test]
+    fn test_state_carries_over() {
+        let (address, deployed, state) = deploy_local(
+            StateDump::new(),
+            CREATION.parse().unwrap(),
+            U256::ZERO,
+            DEFAULT_DEPLOYER,
+            None,
+        )
+        .unwrap();
+        assert!(!deployed.reverted);
+        let address = address.unwrap();
+        assert_eq!(state[&DEFAULT_DEPLOYER].nonce, 1);
+
+        let mut set = hex::decode("60fe47b1").unwrap();
+        set.extend_from_slice(&U256::from(42).to_be_bytes::<32>());
+        let (_, state) = transact_local(
+            state,
+            address,
+            set.into(),
+            U256::ZERO,
+            DEFAULT_DEPLOYER,
+            None,
+        )
+        .unwrap();
+        assert_eq!(state[&address].storage[&U256::ZERO], U256::from(42));
+    }
+
+    #[test]
+    fn test_chain_restore() {
+        let mut chain = LocalChain::new(&StateDump::new(), None).unwrap();
+        let (address, _) = chain
+            .deploy(CREATION.parse().unwrap(), U256::ZERO, DEFAULT_DEPLOYER)
+            .unwrap();
+        let address = address.unwrap();
+        let deployed = chain.dump();
+
+        let mut set = hex::decode("60fe47b1").unwrap();
+        set.extend_from_slice(&U256::from(42).to_be_bytes::<32>());
+        chain
+            .transact(address, set.into(), U256::ZERO, DEFAULT_DEPLOYER)
+            .unwrap();
+        assert_eq!(chain.dump()[&address].storage[&U256::ZERO], U256::from(42));
+
+        chain.restore(&deployed).unwrap();
+        let get = chain
+            .transact(
+                address,
+                hex::decode("6d4ce63c").unwrap().into(),
+                U256::ZERO,
+                DEFAULT_DEPLOYER,
+            )
+            .unwrap();
+        assert_eq!(get.result, Bytes::from(U256::ZERO.to_be_bytes_vec()));
+    }
+
+    #[test]
+    fn test_sstore_across_hardforks() {
+        let (address, _, state) = deploy_local(
+            StateDump::new(),
+            CREATION.parse().unwrap(),
+            U256::ZERO,
+            DEFAULT_DEPLOYER,
+            None,
+        )
+        .unwrap();
+        // Stores 0 over 0, with calldata all zeroes so the intrinsic cost doesn't change either
+        let gas_under = |hardfork: &str| {
+            let options = ExecutionOptions {
+                hardfork: Some(hardfork.to_string()),
+                ..Default::default()
+            };
+            let (result, _) = transact_local(
+                state.clone(),
+                address,
+                vec![0; 36].into(),
+                U256::ZERO,
+                DEFAULT_DEPLOYER,
+                Some(options),
+            )
+            .unwrap();
+            assert!(result.success);
+            result.gas_used
+        };
+        // Before Istanbul's EIP-2200 a store that changes nothing cost a full 5,000, after it 800
+        assert_eq!(gas_under("petersburg") - gas_under("istanbul"), 4_200);
+    }
+}
@@ -0,0 +1,62 @@
+use alloy_primitives::{Address, Bytes, Log, B256};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// An event emitted during execution, with every field hex encoded.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EventLog {
+    #[schema(value_type = String)]
+    pub address: Address,
+    #[schema(value_type = Vec<String>)]
+    pub topics: Vec<B256>,
+    #[schema(value_type = String)]
+    pub data: Bytes,
+    /// Position among the call's logs
+    pub log_index: u64,
+}
+
+impl EventLog {
+    /// Converts logs in emission order.
+    pub fn from_logs(logs: Vec<Log>) -> Vec<EventLog> {
+        logs.into_iter()
+            .enumerate()
+            .map(|(i, log)| EventLog {
+                address: log.address,
+                topics: log.topics().to_vec(),
+                data: log.data.data,
+                log_index: i as u64,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::{address, b256, bytes, LogData};
+    use serde_json::json;
+
+    #[test]
+    fn test_json_shape() {
+        let log = Log {
+            address: address!("b2f9974c62815d3177079e150377915d9bc49c82"),
+            data: LogData::new_unchecked(
+                vec![b256!(
+                    "e0dca1a932506e28dc1cd7f50b0604489287b36ba09c37f13b25ee518d813528"
+                )],
+                bytes!("0000000000000000000000000000000000000000000000000000000000000001"),
+            ),
+        };
+        let logs = EventLog::from_logs(vec![log.clone(), log]);
+        assert_eq!(
+            serde_json::to_value(&logs[1]).unwrap(),
+            json!({
+                "address": "0xb2f9974c62815d3177079e150377915d9bc49c82",
+                "topics": ["0xe0dca1a932506e28dc1cd7f50b0604489287b36ba09c37f13b25ee518d813528"],
+                "data": "0x0000000000000000000000000000000000000000000000000000000000000001",
+                "logIndex": 1,
+            })
+        );
+    }
+}
@@ -0,0 +1,216 @@
+//! Call traces as Mermaid `sequenceDiagram` text, for docs and PR descriptions that render it.
+
+use alloy_primitives::{hex, Address};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use super::trace::{TraceKind, TraceNode, TraceStatus};
+use crate::decode::{decode_revert, RevertReason};
+
+/// A participant per contract (and the caller), a solid arrow per call and a dashed one back with
+/// what it returned, crossed when it reverted. Contracts are named by their labels where they
+/// have one and by a shortened address otherwise; functions by their selectors. Frames more than
+/// `depth` calls deep are left out.
+pub(super) fn render(
+    traces: &[TraceNode],
+    labels: &BTreeMap<Address, String>,
+    depth: Option<usize>,
+) -> String {
+    let mut participants = Vec::new();
+    let mut arrows = String::new();
+    for node in traces {
+        render_node(&mut arrows, &mut participants, node, depth, 1);
+    }
+
+    let mut out = "sequenceDiagram\n".to_string();
+    for (i, address) in participants.iter().enumerate() {
+        let _ = writeln!(out, "    participant P{} as {}", i, name(*address, labels));
+    }
+    out.push_str(&arrows);
+    out
+}
+
+// Participants are numbered in the order they first show up, so the caller comes first
+fn participant(participants: &mut Vec<Address>, address: Address) -> usize {
+    match participants.iter().position(|known| *known == address) {
+        Some(i) => i,
+        None => {
+            participants.push(address);
+            participants.len() - 1
+        }
+    }
+}
+
+fn render_node(
+    out: &mut String,
+    participants: &mut Vec<Address>,
+    node: &TraceNode,
+    depth: Option<usize>,
+    level: usize,
+) {
+    if depth.is_some_and(|depth| level > depth) {
+        return;
+    }
+    let from = participant(participants, node.from);
+    let to = participant(participants, node.to);
+    let _ = writeln!(out, "    P{}->>P{}: {}", from, to, escape(&call(node)));
+    for child in &node.children {
+        render_node(out, participants, child, depth, level + 1);
+    }
+    let arrow = match node.status {
+        TraceStatus::Success => "-->>",
+        TraceStatus::Revert | TraceStatus::Halt => "--x",
+    };
+    let _ = writeln!(
+        out,
+        "    P{}{}P{}: {}",
+        to,
+        arrow,
+        from,
+        escape(&returned(node))
+    );
+}
+
+fn name(address: Address, labels: &BTreeMap<Address, String>) -> String {
+    match labels.get(&address) {
+        Some(label) => escape(label),
+        None => {
+            let address = hex::encode_prefixed(address);
+            format!("{}…{}", &address[..6], &address[address.len() - 4..])
+        }
+    }
+}
+
+fn call(node: &TraceNode) -> String {
+    let mut line = match node.kind {
+        TraceKind::Create | TraceKind::Create2 => "new".to_string(),
+        _ => match node.input.get(..4) {
+            Some(selector) => format!("0x{}", hex::encode(selector)),
+            None => "fallback".to_string(),
+        },
+    };
+    if !node.value.is_zero() {
+        let _ = write!(line, "{{value: {}}}", node.value);
+    }
+    match node.kind {
+        TraceKind::StaticCall => line.push_str(" [staticcall]"),
+        TraceKind::DelegateCall => line.push_str(" [delegatecall]"),
+        TraceKind::CallCode => line.push_str(" [callcode]"),
+        TraceKind::AuthCall => line.push_str(" [authcall]"),
+        _ => {}
+    }
+    line
+}
+
+fn returned(node: &TraceNode) -> String {
+    match node.status {
+        TraceStatus::Success if matches!(node.kind, TraceKind::Create | TraceKind::Create2) => {
+            format!("{} bytes of code", node.output.len())
+        }
+        TraceStatus::Success if node.output.is_empty() => "stop".to_string(),
+        TraceStatus::Success => node.output.to_string(),
+        TraceStatus::Revert => match decode_revert(&node.output, &[]) {
+            RevertReason::Error { message } => format!("revert: {}", message),
+            RevertReason::Panic { code, description } => {
+                format!("panic: {} ({})", description, code)
+            }
+            RevertReason::Custom { signature, .. } => format!("revert: {}", signature),
+            RevertReason::Unknown { .. } if node.output.is_empty() => "revert".to_string(),
+            RevertReason::Unknown { .. } => format!("revert: {}", node.output),
+        },
+        TraceStatus::Halt => "halt".to_string(),
+    }
+}
+
+// Mermaid ends a message at a line break and reads `;` and `#` as markup
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '#' => escaped.push_str("#35;"),
+            ';' => escaped.push_str("#59;"),
+            '\n' | '\r' => escaped.push(' '),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::{address, bytes, Bytes, U256};
+
+    const USER: Address = address!("1804c8ab1f12e6bbf3894d4083f33e07309d1f38");
+    const VAULT: Address = address!("b2f9974c62815d3177079e150377915d9bc49c82");
+    const TOKEN: Address = address!("1000000000000000000000000000000000000001");
+
+    // A deposit that checks the token balance, then fails a require
+    fn fixture() -> TraceNode {
+        TraceNode {
+            kind: TraceKind::Call,
+            from: USER,
+            to: VAULT,
+            value: U256::from(1000),
+            gas_used: 48_211,
+            input: bytes!("b6b55f25000000000000000000000000000000000000000000000000000000000000002a"),
+            // Error("not enough; #1")
+            output: bytes!("08c379a00000000000000000000000000000000000000000000000000000000000000020000000000000000000000000000000000000000000000000000000000000000e6e6f7420656e6f7567683b202331000000000000000000000000000000000000"),
+            status: TraceStatus::Revert,
+            output_length: None,
+            revert_reason: None,
+            children: vec![TraceNode {
+                kind: TraceKind::StaticCall,
+                from: VAULT,
+                to: TOKEN,
+                value: U256::ZERO,
+                gas_used: 2_563,
+                input: bytes!("70a08231000000000000000000000000b2f9974c62815d3177079e150377915d9bc49c82"),
+                output: bytes!("00000000000000000000000000000000000000000000000000000000000003e8"),
+                status: TraceStatus::Success,
+                output_length: None,
+                revert_reason: None,
+                children: vec![],
+                logs: vec![],
+            }],
+            logs: vec![],
+        }
+    }
+
+    #[test]
+    fn test_render_matches_snapshot() {
+        let labels = BTreeMap::from([(TOKEN, "token.eth".to_string())]);
+        assert_eq!(
+            render(&[fixture()], &labels, None),
+            include_str!("fixtures/mermaid_trace.txt")
+        );
+    }
+
+    #[test]
+    fn test_depth_cutoff() {
+        let diagram = render(&[fixture()], &BTreeMap::new(), Some(1));
+        assert_eq!(
+            diagram,
+            "sequenceDiagram\n    participant P0 as 0x1804…1f38\n    participant P1 as \
+             0xb2f9…9c82\n    P0->>P1: 0xb6b55f25{value: 1000}\n    P1--xP0: revert: not \
+             enough#59; #35;1\n"
+        );
+    }
+
+    #[test]
+    fn test_create_and_empty_revert() {
+        let mut node = fixture();
+        node.kind = TraceKind::Create;
+        node.value = U256::ZERO;
+        node.output = Bytes::new();
+        node.children.clear();
+        assert!(render(&[node.clone()], &BTreeMap::new(), None)
+            .ends_with("    P0->>P1: new\n    P1--xP0: revert\n"));
+
+        node.status = TraceStatus::Success;
+        node.output = bytes!("6080");
+        assert!(
+            render(&[node], &BTreeMap::new(), None).ends_with("    P1-->>P0: 2 bytes of code\n")
+        );
+    }
+}
@@ -1,13 +1,75 @@
+pub mod anvil;
+mod breakpoint;
+mod chrome;
+pub mod code;
+mod consistency;
+mod created;
+pub mod demo;
 mod deploy;
+mod engine;
+pub mod ens;
+pub mod interop;
+pub mod rpc_diagnostics;
+pub mod rpc_pool;
 pub use deploy::deploy;
 mod transact;
 pub use transact::transact;
+mod execute_batch;
 mod execute_calldatas;
 mod execute_calldatas_fork;
-pub use execute_calldatas::{execute_calldatas, Call};
+mod exit;
+mod flamegraph;
+mod gas_report;
+pub mod hardfork;
+mod internal_frames;
+mod journal;
+pub mod labels;
+mod local;
+mod log;
+mod mermaid;
+pub mod op_stack;
+mod pretty;
+pub mod raw_transaction;
+#[cfg(test)]
+mod recorded_rpc;
+mod replay;
+mod resolve;
+mod state_codec;
+mod state_diff;
+pub mod summary;
+pub mod templates;
+pub mod tenderly;
+mod trace;
+mod transient;
+mod tx_validity;
+pub use breakpoint::{Breakpoint, PausedAt, StorageWrite};
+pub use consistency::{CheckStatus, ConsistencyCheck, Divergence};
+pub use created::CreatedContract;
+pub use engine::{CallEnv, Deployment, Engine, EngineConfig, EnvOverrides};
+pub use execute_batch::{execute_batch_fork, Scenario};
+pub use execute_calldatas::{execute_calldatas, Call, CallResult};
 pub use execute_calldatas_fork::{
-    execute_calldatas_fork, Call as ForkCall, ExecutionResult, ForkConfig,
+    deploy_and_execute_calldatas_fork, execute_calldatas_fork, execute_calldatas_fork_with,
+    execute_raw_transactions_fork, Call as ForkCall, ExecutionResult, ForkConfig, ForkContext,
+    ForkError, ForkProgress, NamedStateOverride, NotIndependent, SkipReason, Timings,
+    DEFAULT_DEPLOYER, MAX_DEBUG_TRACE_GAS,
 };
 
+pub(crate) use execute_calldatas_fork::run_blocking;
+pub use exit::ExitReason;
+pub use gas_report::{ContractGas, DeploymentGas, FunctionGas, GasReport, GasReporter};
+pub use internal_frames::{internal_frames, CodeMap, FunctionJump, InternalFrame};
+pub use journal::{JournalEntry, JournalEvent, SourceLocation};
+pub use local::{deploy_local, transact_local, AccountDump, LocalChain, StateDump};
+pub use log::EventLog;
+pub use replay::{replay_transaction, ReplayError, ReplayedTransaction};
+pub use resolve::{ForkSource, ResolvedFork};
+pub use rpc_diagnostics::RpcDiagnostics;
+pub use state_codec::{from_alloc, to_alloc, EncodedState, GenesisAccount, GenesisAlloc};
+pub use state_diff::{AccountDiff, Change};
+pub use trace::{TraceKind, TraceLog, TraceNode, TraceStatus};
+pub use transient::{TransientAccess, TransientAccessKind};
+pub use tx_validity::{TxCheck, TxCheckFailure, TxValidity};
+
 // Re-export the ExecutionOptions struct for other modules to use
 pub use execute_calldatas_fork::ExecutionOptions;
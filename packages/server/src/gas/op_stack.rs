@@ -0,0 +1,415 @@
+//! Stand-ins for the OP Stack's `L1Block` and `GasPriceOracle` predeploys, so contracts written
+//! for Base and other OP Stack chains can run on a local chain, where those addresses would
+//! otherwise be empty and every call to them revert.
+//!
+//! They answer the getters with the L1 values a request gives, and `GasPriceOracle` prices L1
+//! data as it has since Ecotone. Each value sits in a storage slot of its own in both contracts,
+//! rather than packed where the real ones keep it, so only code going through the getters sees
+//! them.
+
+use alloy_primitives::{address, keccak256, Address, Bytes, B256, U256};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use utoipa::ToSchema;
+
+use super::local::{AccountDump, StateDump};
+
+pub const L1_BLOCK: Address = address!("4200000000000000000000000000000000000015");
+pub const GAS_PRICE_ORACLE: Address = address!("420000000000000000000000000000000000000F");
+
+/// The L1 values the predeploys report. Any left out are Base's around mid-2024.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OpStackParams {
+    /// `L1Block.number()`
+    pub l1_block_number: Option<u64>,
+    /// `L1Block.timestamp()`
+    pub l1_timestamp: Option<u64>,
+    /// `L1Block.basefee()` and `GasPriceOracle.l1BaseFee()`, in wei
+    #[schema(value_type = Option<String>)]
+    pub l1_base_fee: Option<U256>,
+    /// `blobBaseFee()` on both, in wei
+    #[schema(value_type = Option<String>)]
+    pub blob_base_fee: Option<U256>,
+    /// `L1Block.hash()`
+    #[schema(value_type = Option<String>)]
+    pub l1_block_hash: Option<B256>,
+    /// `L1Block.sequenceNumber()`, the L2 block's position in its epoch
+    pub sequence_number: Option<u64>,
+    /// `baseFeeScalar()` on both
+    pub base_fee_scalar: Option<u32>,
+    /// `blobBaseFeeScalar()` on both
+    pub blob_base_fee_scalar: Option<u32>,
+}
+
+// Where each value is kept, in both contracts
+const NUMBER: u8 = 0;
+const TIMESTAMP: u8 = 1;
+const BASE_FEE: u8 = 2;
+const HASH: u8 = 3;
+const SEQUENCE_NUMBER: u8 = 4;
+const BLOB_BASE_FEE: u8 = 5;
+const BASE_FEE_SCALAR: u8 = 6;
+const BLOB_BASE_FEE_SCALAR: u8 = 7;
+
+/// `GasPriceOracle.decimals()`, the scalars' fixed point
+const DECIMALS: u64 = 6;
+
+/// Calldata gas added for a transaction's signature, 68 bytes at 16 each
+const SIGNATURE_GAS: u64 = 68 * 16;
+
+/// The two predeploys, with `params` in their storage.
+pub fn predeploys(params: &OpStackParams) -> StateDump {
+    let storage: BTreeMap<U256, U256> = [
+        (
+            NUMBER,
+            U256::from(params.l1_block_number.unwrap_or(20_000_000)),
+        ),
+        (
+            TIMESTAMP,
+            U256::from(params.l1_timestamp.unwrap_or(1_717_200_000)),
+        ),
+        (
+            BASE_FEE,
+            params.l1_base_fee.unwrap_or(U256::from(8_000_000_000u64)),
+        ),
+        (
+            HASH,
+            U256::from_be_bytes(params.l1_block_hash.unwrap_or_default().0),
+        ),
+        (
+            SEQUENCE_NUMBER,
+            U256::from(params.sequence_number.unwrap_or(0)),
+        ),
+        (BLOB_BASE_FEE, params.blob_base_fee.unwrap_or(U256::from(1))),
+        (
+            BASE_FEE_SCALAR,
+            U256::from(params.base_fee_scalar.unwrap_or(1_368)),
+        ),
+        (
+            BLOB_BASE_FEE_SCALAR,
+            U256::from(params.blob_base_fee_scalar.unwrap_or(810_949)),
+        ),
+    ]
+    .into_iter()
+    .map(|(slot, value)| (U256::from(slot), value))
+    .collect();
+    let account = |code: Bytes| AccountDump {
+        code,
+        storage: storage.clone(),
+        ..Default::default()
+    };
+    StateDump::from([
+        (L1_BLOCK, account(l1_block_code())),
+        (GAS_PRICE_ORACLE, account(gas_price_oracle_code())),
+    ])
+}
+
+fn l1_block_code() -> Bytes {
+    dispatch(&[
+        ("number()", Body::Slot(NUMBER)),
+        ("timestamp()", Body::Slot(TIMESTAMP)),
+        ("basefee()", Body::Slot(BASE_FEE)),
+        ("hash()", Body::Slot(HASH)),
+        ("sequenceNumber()", Body::Slot(SEQUENCE_NUMBER)),
+        ("blobBaseFee()", Body::Slot(BLOB_BASE_FEE)),
+        ("baseFeeScalar()", Body::Slot(BASE_FEE_SCALAR)),
+        ("blobBaseFeeScalar()", Body::Slot(BLOB_BASE_FEE_SCALAR)),
+    ])
+}
+
+fn gas_price_oracle_code() -> Bytes {
+    dispatch(&[
+        ("l1BaseFee()", Body::Slot(BASE_FEE)),
+        ("blobBaseFee()", Body::Slot(BLOB_BASE_FEE)),
+        ("baseFeeScalar()", Body::Slot(BASE_FEE_SCALAR)),
+        ("blobBaseFeeScalar()", Body::Slot(BLOB_BASE_FEE_SCALAR)),
+        ("decimals()", Body::Word(DECIMALS)),
+        ("DECIMALS()", Body::Word(DECIMALS)),
+        ("isEcotone()", Body::Word(1)),
+        ("isFjord()", Body::Word(0)),
+        ("gasPrice()", Body::BaseFee),
+        ("baseFee()", Body::BaseFee),
+        ("getL1GasUsed(bytes)", Body::L1GasUsed),
+        ("getL1Fee(bytes)", Body::L1Fee),
+    ])
+}
+
+// What a function does before returning its one word
+enum Body {
+    Slot(u8),
+    Word(u64),
+    /// The L2 block's basefee
+    BaseFee,
+    /// The calldata gas of its `bytes` argument, 4 per zero byte and 16 per other, plus
+    /// `SIGNATURE_GAS`
+    L1GasUsed,
+    /// That gas at Ecotone's price: `gas * (16 * baseFeeScalar * l1BaseFee + blobBaseFeeScalar
+    /// * blobBaseFee) / (16 * 10^DECIMALS)`
+    L1Fee,
+}
+
+// One instruction, or a jump destination or a push of one, labeled with a number of its own
+enum Asm {
+    Op(u8),
+    Push(Vec<u8>),
+    Dest(usize),
+    PushDest(usize),
+}
+
+mod op {
+    pub const STOP: u8 = 0x00;
+    pub const ADD: u8 = 0x01;
+    pub const MUL: u8 = 0x02;
+    pub const DIV: u8 = 0x04;
+    pub const LT: u8 = 0x10;
+    pub const EQ: u8 = 0x14;
+    pub const ISZERO: u8 = 0x15;
+    pub const BYTE: u8 = 0x1a;
+    pub const SHR: u8 = 0x1c;
+    pub const CALLDATALOAD: u8 = 0x35;
+    pub const BASEFEE: u8 = 0x48;
+    pub const POP: u8 = 0x50;
+    pub const MSTORE: u8 = 0x52;
+    pub const SLOAD: u8 = 0x54;
+    pub const JUMP: u8 = 0x56;
+    pub const JUMPI: u8 = 0x57;
+    pub const JUMPDEST: u8 = 0x5b;
+    pub const DUP1: u8 = 0x80;
+    pub const DUP2: u8 = 0x81;
+    pub const DUP3: u8 = 0x82;
+    pub const SWAP1: u8 = 0x90;
+    pub const SWAP2: u8 = 0x91;
+    pub const RETURN: u8 = 0xf3;
+    pub const REVERT: u8 = 0xfd;
+}
+
+fn push(value: u64) -> Asm {
+    let bytes = value.to_be_bytes();
+    let first = bytes.iter().position(|byte| *byte != 0).unwrap_or(7);
+    Asm::Push(bytes[first..].to_vec())
+}
+
+// Runtime code sending each function's selector to its body, and anything else to a revert
+fn dispatch(functions: &[(&str, Body)]) -> Bytes {
+    use op::*;
+    // The selector, left on the stack under everything the bodies do
+    let mut code = vec![push(0), Asm::Op(CALLDATALOAD), push(0xe0), Asm::Op(SHR)];
+    for (i, (signature, _)) in functions.iter().enumerate() {
+        code.extend([
+            Asm::Op(DUP1),
+            Asm::Push(keccak256(signature)[..4].to_vec()),
+            Asm::Op(EQ),
+            Asm::PushDest(i),
+            Asm::Op(JUMPI),
+        ]);
+    }
+    code.extend([push(0), Asm::Op(DUP1), Asm::Op(REVERT)]);
+
+    for (i, (_, body)) in functions.iter().enumerate() {
+        code.push(Asm::Dest(i));
+        // Labels for the calldata loop, past the bodies' own
+        let looping = functions.len() + 2 * i;
+        match body {
+            Body::Slot(slot) => code.extend([push(u64::from(*slot)), Asm::Op(SLOAD)]),
+            Body::Word(word) => code.push(push(*word)),
+            Body::BaseFee => code.push(Asm::Op(BASEFEE)),
+            Body::L1GasUsed | Body::L1Fee => {
+                code.extend(calldata_gas(looping, looping + 1));
+                if let Body::L1Fee = body {
+                    code.extend([
+                        push(u64::from(BASE_FEE_SCALAR)),
+                        Asm::Op(SLOAD),
+                        push(16),
+                        Asm::Op(MUL),
+                        push(u64::from(BASE_FEE)),
+                        Asm::Op(SLOAD),
+                        Asm::Op(MUL),
+                        push(u64::from(BLOB_BASE_FEE_SCALAR)),
+                        Asm::Op(SLOAD),
+                        push(u64::from(BLOB_BASE_FEE)),
+                        Asm::Op(SLOAD),
+                        Asm::Op(MUL),
+                        Asm::Op(ADD),
+                        Asm::Op(MUL),
+                        push(16 * 10u64.pow(DECIMALS as u32)),
+                        Asm::Op(SWAP1),
+                        Asm::Op(DIV),
+                    ]);
+                }
+            }
+        }
+        code.extend([push(0), Asm::Op(MSTORE), push(32), push(0), Asm::Op(RETURN)]);
+    }
+    code.push(Asm::Op(STOP));
+    assemble(&code)
+}
+
+// Leaves the calldata gas of the `bytes` argument on the stack. Keeps the end of the bytes and
+// the index of the next one under the running total while it counts.
+fn calldata_gas(looping: usize, done: usize) -> Vec<Asm> {
+    use op::*;
+    vec![
+        // Where the bytes' length is, then where they start and end
+        push(4),
+        Asm::Op(CALLDATALOAD),
+        push(4),
+        Asm::Op(ADD),
+        Asm::Op(DUP1),
+        Asm::Op(CALLDATALOAD),
+        Asm::Op(SWAP1),
+        push(32),
+        Asm::Op(ADD),
+        Asm::Op(DUP1),
+        Asm::Op(SWAP2),
+        Asm::Op(ADD),
+        Asm::Op(SWAP1),
+        push(SIGNATURE_GAS),
+        Asm::Dest(looping),
+        // Stop once the index reaches the end
+        Asm::Op(DUP3),
+        Asm::Op(DUP3),
+        Asm::Op(LT),
+        Asm::Op(ISZERO),
+        Asm::PushDest(done),
+        Asm::Op(JUMPI),
+        // 4, and 12 more when the byte isn't zero
+        Asm::Op(DUP2),
+        Asm::Op(CALLDATALOAD),
+        push(0),
+        Asm::Op(BYTE),
+        Asm::Op(ISZERO),
+        Asm::Op(ISZERO),
+        push(12),
+        Asm::Op(MUL),
+        push(4),
+        Asm::Op(ADD),
+        Asm::Op(ADD),
+        Asm::Op(SWAP1),
+        push(1),
+        Asm::Op(ADD),
+        Asm::Op(SWAP1),
+        Asm::PushDest(looping),
+        Asm::Op(JUMP),
+        Asm::Dest(done),
+        // Just the total
+        Asm::Op(SWAP2),
+        Asm::Op(POP),
+        Asm::Op(POP),
+    ]
+}
+
+fn assemble(code: &[Asm]) -> Bytes {
+    let size = |asm: &Asm| match asm {
+        Asm::Op(_) | Asm::Dest(_) => 1,
+        Asm::Push(bytes) => 1 + bytes.len(),
+        Asm::PushDest(_) => 3,
+    };
+    let mut dests = HashMap::new();
+    let mut offset = 0;
+    for asm in code {
+        if let Asm::Dest(id) = asm {
+            dests.insert(*id, offset as u16);
+        }
+        offset += size(asm);
+    }
+    let mut bytes = Vec::with_capacity(offset);
+    for asm in code {
+        match asm {
+            Asm::Op(op) => bytes.push(*op),
+            // PUSH1 to PUSH32
+            Asm::Push(value) => {
+                bytes.push(0x5f + value.len() as u8);
+                bytes.extend(value);
+            }
+            Asm::Dest(_) => bytes.push(op::JUMPDEST),
+            Asm::PushDest(id) => {
+                bytes.push(0x61);
+                bytes.extend(dests[id].to_be_bytes());
+            }
+        }
+    }
+    bytes.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gas::{transact_local, DEFAULT_DEPLOYER};
+    use alloy_primitives::{bytes, hex};
+
+    // Returns what `L1Block.basefee()` does
+    const READER: &str = "0x635cf2496960e01b6000526020600060046000\
+        734200000000000000000000000000000000000015\
+        5afa5060206000f3";
+
+    fn call(state: &StateDump, to: Address, calldata: Vec<u8>) -> U256 {
+        let (result, _) = transact_local(
+            state.clone(),
+            to,
+            calldata.into(),
+            U256::ZERO,
+            DEFAULT_DEPLOYER,
+            None,
+        )
+        .unwrap();
+        assert!(result.success, "{:?}", result.exit_reason);
+        U256::from_be_slice(&result.result)
+    }
+
+    #[test]
+    fn test_contract_reads_l1_basefee() {
+        let reader = Address::repeat_byte(0xaa);
+        let mut state = predeploys(&OpStackParams {
+            l1_base_fee: Some(U256::from(31_337)),
+            ..Default::default()
+        });
+        state.insert(
+            reader,
+            AccountDump {
+                code: READER.parse().unwrap(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(call(&state, reader, vec![]), U256::from(31_337));
+        // The rest keep their defaults
+        let sequence = keccak256("sequenceNumber()")[..4].to_vec();
+        assert_eq!(call(&state, L1_BLOCK, sequence), U256::ZERO);
+    }
+
+    #[test]
+    fn test_l1_fee() {
+        let state = predeploys(&OpStackParams::default());
+        // getL1Fee(bytes) of 3 zero and 43 other bytes
+        let mut data = vec![0, 0, 1, 2, 0, 3];
+        data.extend([5; 40]);
+        let mut calldata = keccak256("getL1Fee(bytes)")[..4].to_vec();
+        calldata.extend(U256::from(32).to_be_bytes::<32>());
+        calldata.extend(U256::from(data.len()).to_be_bytes::<32>());
+        calldata.extend(&data);
+
+        let gas = 1088 + 3 * 4 + 43 * 16;
+        assert_eq!(
+            call(&state, GAS_PRICE_ORACLE, calldata.clone()),
+            U256::from(gas * (16 * 1_368 * 8_000_000_000u64 + 810_949) / 16_000_000)
+        );
+        calldata[..4].copy_from_slice(&keccak256("getL1GasUsed(bytes)")[..4]);
+        assert_eq!(call(&state, GAS_PRICE_ORACLE, calldata), U256::from(gas));
+        assert_eq!(
+            call(&state, GAS_PRICE_ORACLE, hex!("519b4bd3").to_vec()),
+            U256::from(8_000_000_000u64)
+        );
+
+        // Anything else reverts
+        let (result, _) = transact_local(
+            state,
+            GAS_PRICE_ORACLE,
+            bytes!("12345678"),
+            U256::ZERO,
+            DEFAULT_DEPLOYER,
+            None,
+        )
+        .unwrap();
+        assert!(result.reverted);
+    }
+}
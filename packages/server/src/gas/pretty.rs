@@ -0,0 +1,202 @@
+//! Call traces as the tree of text `forge test -vvvv` prints, for pasting where JSON won't do.
+
+use alloy_primitives::{hex, Address};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use super::trace::{TraceKind, TraceLog, TraceNode, TraceStatus};
+use crate::decode::{decode_revert, RevertReason};
+
+const BRANCH: &str = "  ├─ ";
+const EDGE: &str = "  └─ ";
+const PIPE: &str = "  │  ";
+
+/// Each top-level call and what it called, with gas, events and return or revert data, the way
+/// forge renders traces. Contracts are named by their labels where they have one; selectors
+/// aren't decoded. A frame's events are listed after the calls it made, since the trace doesn't
+/// keep their order.
+pub(super) fn render(traces: &[TraceNode], labels: &BTreeMap<Address, String>) -> String {
+    let mut out = String::new();
+    for node in traces {
+        render_node(&mut out, node, labels, "", "");
+    }
+    out
+}
+
+// `left` goes before the frame's own line, `right` before every line under it
+fn render_node(
+    out: &mut String,
+    node: &TraceNode,
+    labels: &BTreeMap<Address, String>,
+    left: &str,
+    right: &str,
+) {
+    let _ = writeln!(out, "{}[{}] {}", left, node.gas_used, call(node, labels));
+
+    for child in &node.children {
+        render_node(
+            out,
+            child,
+            labels,
+            &format!("{}{}", right, BRANCH),
+            &format!("{}{}", right, PIPE),
+        );
+    }
+    for log in &node.logs {
+        render_log(out, log, right);
+    }
+    let _ = writeln!(out, "{}{}← {}", right, EDGE, returned(node));
+}
+
+fn name(address: Address, labels: &BTreeMap<Address, String>) -> String {
+    labels
+        .get(&address)
+        .cloned()
+        .unwrap_or_else(|| address.to_string())
+}
+
+fn call(node: &TraceNode, labels: &BTreeMap<Address, String>) -> String {
+    if matches!(node.kind, TraceKind::Create | TraceKind::Create2) {
+        let label = labels.get(&node.to).map_or("<unknown>", String::as_str);
+        return format!("→ new {}@{}", label, node.to);
+    }
+
+    let mut line = format!("{}::", name(node.to, labels));
+    match node.input.len() {
+        0 => line.push_str("fallback"),
+        1..=3 => line.push_str("<unknown>"),
+        _ => line.push_str(&hex::encode(&node.input[..4])),
+    }
+    if !node.value.is_zero() {
+        let _ = write!(line, "{{value: {}}}", node.value);
+    }
+    let args = node.input.get(4..).unwrap_or(&node.input[..]);
+    let _ = write!(line, "({})", hex::encode(args));
+    match node.kind {
+        TraceKind::StaticCall => line.push_str(" [staticcall]"),
+        TraceKind::DelegateCall => line.push_str(" [delegatecall]"),
+        TraceKind::CallCode => line.push_str(" [callcode]"),
+        TraceKind::AuthCall => line.push_str(" [authcall]"),
+        _ => {}
+    }
+    line
+}
+
+fn render_log(out: &mut String, log: &TraceLog, right: &str) {
+    let emit = format!("{}{}emit ", right, BRANCH);
+    // Continuation lines line up under the first topic
+    let under = format!("{}{}     ", right, PIPE);
+    for (i, topic) in log.topics.iter().enumerate() {
+        let prefix = if i == 0 { &emit } else { &under };
+        let _ = writeln!(out, "{}topic {}: {}", prefix, i, topic);
+    }
+    let prefix = if log.topics.is_empty() { &emit } else { &under };
+    let _ = writeln!(out, "{}   data: {}", prefix, log.data);
+}
+
+fn returned(node: &TraceNode) -> String {
+    match node.status {
+        TraceStatus::Success if matches!(node.kind, TraceKind::Create | TraceKind::Create2) => {
+            format!("[Return] {} bytes of code", node.output.len())
+        }
+        TraceStatus::Success if node.output.is_empty() => "[Stop]".to_string(),
+        TraceStatus::Success => format!("[Return] {}", node.output),
+        TraceStatus::Revert => match decode_revert(&node.output, &[]) {
+            RevertReason::Error { message } => format!("[Revert] revert: {}", message),
+            RevertReason::Panic { code, description } => {
+                format!("[Revert] panic: {} ({})", description, code)
+            }
+            RevertReason::Custom { signature, .. } => format!("[Revert] {}", signature),
+            RevertReason::Unknown { .. } => format!("[Revert] {}", node.output),
+        },
+        TraceStatus::Halt => "[Halt]".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::{address, b256, bytes, Bytes, U256};
+
+    const VAULT: Address = address!("b2f9974c62815d3177079e150377915d9bc49c82");
+    const TOKEN: Address = address!("1000000000000000000000000000000000000001");
+
+    // A deposit that pays the token, emits an event and then fails a require
+    fn fixture() -> TraceNode {
+        TraceNode {
+            kind: TraceKind::Call,
+            from: address!("1804c8ab1f12e6bbf3894d4083f33e07309d1f38"),
+            to: VAULT,
+            value: U256::from(1000),
+            gas_used: 48_211,
+            input: bytes!("b6b55f25000000000000000000000000000000000000000000000000000000000000002a"),
+            // Error("not enough")
+            output: bytes!("08c379a00000000000000000000000000000000000000000000000000000000000000020000000000000000000000000000000000000000000000000000000000000000a6e6f7420656e6f75676800000000000000000000000000000000000000000000"),
+            status: TraceStatus::Revert,
+            output_length: None,
+            revert_reason: None,
+            children: vec![TraceNode {
+                kind: TraceKind::StaticCall,
+                from: VAULT,
+                to: TOKEN,
+                value: U256::ZERO,
+                gas_used: 2_563,
+                input: bytes!("70a08231000000000000000000000000b2f9974c62815d3177079e150377915d9bc49c82"),
+                output: bytes!("00000000000000000000000000000000000000000000000000000000000003e8"),
+                status: TraceStatus::Success,
+                output_length: None,
+                revert_reason: None,
+                children: vec![],
+                logs: vec![],
+            }],
+            logs: vec![TraceLog {
+                address: VAULT,
+                topics: vec![
+                    b256!("e1fffcc4923d04b559f4d29a8bfc6cda04eb5b0d3c460751c2402c5c5cc9109c"),
+                    b256!("0000000000000000000000001804c8ab1f12e6bbf3894d4083f33e07309d1f38"),
+                ],
+                data: bytes!("00000000000000000000000000000000000000000000000000000000000003e8"),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_render_matches_snapshot() {
+        let labels = BTreeMap::from([
+            (VAULT, "vault.eth".to_string()),
+            (TOKEN, "token.eth".to_string()),
+        ]);
+        assert_eq!(
+            render(&[fixture()], &labels),
+            include_str!("fixtures/pretty_trace.txt")
+        );
+    }
+
+    #[test]
+    fn test_create_and_panic() {
+        let node = TraceNode {
+            kind: TraceKind::Create,
+            from: Address::ZERO,
+            to: VAULT,
+            value: U256::ZERO,
+            gas_used: 100,
+            input: Bytes::new(),
+            output: bytes!(
+                "4e487b710000000000000000000000000000000000000000000000000000000000000011"
+            ),
+            status: TraceStatus::Revert,
+            output_length: None,
+            revert_reason: None,
+            children: vec![],
+            logs: vec![],
+        };
+        assert_eq!(
+            render(&[node], &BTreeMap::new()),
+            format!(
+                "[100] → new <unknown>@{}\n  └─ ← [Revert] panic: arithmetic underflow or \
+                 overflow (0x11)\n",
+                VAULT
+            )
+        );
+    }
+}
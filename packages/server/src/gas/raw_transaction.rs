@@ -0,0 +1,419 @@
+//! Signed transactions, as `eth_sendRawTransaction` takes them: an EIP-2718 envelope (or a bare
+//! RLP list for legacy transactions) is decoded, its sender recovered from the signature, and run
+//! with the gas limit, fees and access list it was signed with. Mined transactions a node returns
+//! are run the same way, to replay a block.
+
+use alloy_consensus::{SignableTransaction, Signed, TxEnvelope};
+use alloy_eips::eip2718::Decodable2718;
+use alloy_eips::eip2930::AccessList;
+use alloy_primitives::{Address, Bytes, Signature, TxKind, B256, U256};
+use alloy_rpc_types_eth::Transaction;
+use revm_primitives::Env;
+use std::fmt;
+
+use super::engine::Engine;
+use super::execute_calldatas_fork::{ExecutionResult, SkipReason};
+
+/// A decoded transaction and the account that signed it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SignedTransaction {
+    pub hash: B256,
+    pub from: Address,
+    /// Missing from legacy transactions signed without EIP-155 replay protection
+    pub chain_id: Option<u64>,
+    pub nonce: u64,
+    pub gas_limit: u64,
+    /// The gas price, or for EIP-1559 and blob transactions the max fee per gas
+    pub gas_price: u128,
+    pub max_priority_fee_per_gas: Option<u128>,
+    pub to: TxKind,
+    pub value: U256,
+    pub input: Bytes,
+    pub access_list: AccessList,
+    pub blob_versioned_hashes: Vec<B256>,
+    pub max_fee_per_blob_gas: Option<u128>,
+}
+
+/// A raw transaction that can't be run: it doesn't decode, its signature doesn't recover, or it
+/// was signed for another chain.
+#[derive(Debug)]
+pub struct InvalidTransaction {
+    pub index: usize,
+    /// Once it decoded
+    pub hash: Option<B256>,
+    pub message: String,
+}
+
+impl InvalidTransaction {
+    pub fn field(&self) -> String {
+        format!("rawTransactions[{}]", self.index)
+    }
+}
+
+impl fmt::Display for InvalidTransaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.field(), self.message)
+    }
+}
+
+impl std::error::Error for InvalidTransaction {}
+
+/// What one raw transaction came to.
+#[derive(Debug)]
+pub struct RawTransactionOutcome {
+    /// Missing when it didn't decode
+    pub hash: Option<B256>,
+    /// Missing when its signature didn't recover
+    pub from: Option<Address>,
+    pub result: Result<ExecutionResult, eyre::Error>,
+}
+
+impl SignedTransaction {
+    /// Decodes `raw`, the `index`th of the request's raw transactions, and recovers its sender.
+    pub fn decode(index: usize, raw: &[u8]) -> Result<Self, InvalidTransaction> {
+        let invalid = |hash, message: String| InvalidTransaction {
+            index,
+            hash,
+            message,
+        };
+        let envelope = TxEnvelope::decode_2718(&mut &raw[..])
+            .map_err(|err| invalid(None, format!("isn't a signed transaction: {}", err)))?;
+        let (hash, from) = match &envelope {
+            TxEnvelope::Legacy(signed) => signer(signed),
+            TxEnvelope::Eip2930(signed) => signer(signed),
+            TxEnvelope::Eip1559(signed) => signer(signed),
+            TxEnvelope::Eip4844(signed) => signer(signed),
+            _ => {
+                return Err(invalid(
+                    None,
+                    "is of a transaction type not supported".into(),
+                ))
+            }
+        };
+        let from = from.map_err(|err| {
+            invalid(
+                Some(hash),
+                format!("has a signature no sender recovers from: {}", err),
+            )
+        })?;
+        let transaction = match envelope {
+            TxEnvelope::Legacy(signed) => {
+                let tx = signed.tx();
+                SignedTransaction {
+                    chain_id: tx.chain_id,
+                    nonce: tx.nonce,
+                    gas_limit: u64::try_from(tx.gas_limit).unwrap_or(u64::MAX),
+                    gas_price: tx.gas_price,
+                    max_priority_fee_per_gas: None,
+                    to: tx.to,
+                    value: tx.value,
+                    input: tx.input.clone(),
+                    access_list: AccessList::default(),
+                    blob_versioned_hashes: Vec::new(),
+                    max_fee_per_blob_gas: None,
+                    hash,
+                    from,
+                }
+            }
+            TxEnvelope::Eip2930(signed) => {
+                let tx = signed.tx();
+                SignedTransaction {
+                    chain_id: Some(tx.chain_id),
+                    nonce: tx.nonce,
+                    gas_limit: u64::try_from(tx.gas_limit).unwrap_or(u64::MAX),
+                    gas_price: tx.gas_price,
+                    max_priority_fee_per_gas: None,
+                    to: tx.to,
+                    value: tx.value,
+                    input: tx.input.clone(),
+                    access_list: tx.access_list.clone(),
+                    blob_versioned_hashes: Vec::new(),
+                    max_fee_per_blob_gas: None,
+                    hash,
+                    from,
+                }
+            }
+            TxEnvelope::Eip1559(signed) => {
+                let tx = signed.tx();
+                SignedTransaction {
+                    chain_id: Some(tx.chain_id),
+                    nonce: tx.nonce,
+                    gas_limit: u64::try_from(tx.gas_limit).unwrap_or(u64::MAX),
+                    gas_price: tx.max_fee_per_gas,
+                    max_priority_fee_per_gas: Some(tx.max_priority_fee_per_gas),
+                    to: tx.to,
+                    value: tx.value,
+                    input: tx.input.clone(),
+                    access_list: tx.access_list.clone(),
+                    blob_versioned_hashes: Vec::new(),
+                    max_fee_per_blob_gas: None,
+                    hash,
+                    from,
+                }
+            }
+            TxEnvelope::Eip4844(signed) => {
+                // With or without its sidecar, which only the mempool needs
+                let tx = signed.tx().tx();
+                SignedTransaction {
+                    chain_id: Some(tx.chain_id),
+                    nonce: tx.nonce,
+                    gas_limit: u64::try_from(tx.gas_limit).unwrap_or(u64::MAX),
+                    gas_price: tx.max_fee_per_gas,
+                    max_priority_fee_per_gas: Some(tx.max_priority_fee_per_gas),
+                    to: TxKind::Call(tx.to),
+                    value: tx.value,
+                    input: tx.input.clone(),
+                    access_list: tx.access_list.clone(),
+                    blob_versioned_hashes: tx.blob_versioned_hashes.clone(),
+                    max_fee_per_blob_gas: Some(tx.max_fee_per_blob_gas),
+                    hash,
+                    from,
+                }
+            }
+            _ => unreachable!("unsupported types are refused above"),
+        };
+        Ok(transaction)
+    }
+
+    /// A transaction as a node returns it, mined or not. The node vouches for the sender, so
+    /// there's no signature to check. Fields only some types have are left empty when missing.
+    pub fn from_rpc(tx: &Transaction) -> Self {
+        SignedTransaction {
+            hash: tx.hash,
+            from: tx.from,
+            chain_id: tx.chain_id,
+            nonce: tx.nonce,
+            gas_limit: u64::try_from(tx.gas).unwrap_or(u64::MAX),
+            gas_price: tx.max_fee_per_gas.or(tx.gas_price).unwrap_or_default(),
+            max_priority_fee_per_gas: tx.max_priority_fee_per_gas,
+            to: tx.to.map_or(TxKind::Create, TxKind::Call),
+            value: tx.value,
+            input: tx.input.clone(),
+            access_list: tx.access_list.clone().unwrap_or_default(),
+            blob_versioned_hashes: tx.blob_versioned_hashes.clone().unwrap_or_default(),
+            max_fee_per_blob_gas: tx.max_fee_per_blob_gas,
+        }
+    }
+
+    /// Puts what was signed into `env`'s transaction. The nonce is left unchecked, so a
+    /// transaction runs whatever its sender's account is up to.
+    pub(super) fn apply(&self, env: &mut Env) {
+        env.tx.gas_limit = self.gas_limit;
+        env.tx.gas_price = U256::from(self.gas_price);
+        env.tx.gas_priority_fee = self.max_priority_fee_per_gas.map(U256::from);
+        env.tx.chain_id = self.chain_id;
+        env.tx.access_list = self
+            .access_list
+            .0
+            .iter()
+            .map(|item| {
+                let slots = item
+                    .storage_keys
+                    .iter()
+                    .map(|key| U256::from_be_bytes(key.0))
+                    .collect();
+                (item.address, slots)
+            })
+            .collect();
+        env.tx.blob_hashes = self.blob_versioned_hashes.clone();
+        env.tx.max_fee_per_blob_gas = self.max_fee_per_blob_gas.map(U256::from);
+    }
+}
+
+// The transaction's hash, and who signed it
+fn signer<T: SignableTransaction<Signature>>(
+    signed: &Signed<T>,
+) -> (B256, Result<Address, alloy_primitives::SignatureError>) {
+    (*signed.hash(), signed.recover_signer())
+}
+
+/// Decodes the `index`th raw transaction and runs it on `engine`, committing what it changes. A
+/// transaction signed for another chain than the engine's isn't run.
+pub fn run(engine: &mut Engine, index: usize, raw: &[u8]) -> RawTransactionOutcome {
+    let tx = match SignedTransaction::decode(index, raw) {
+        Ok(tx) => tx,
+        Err(err) => {
+            return RawTransactionOutcome {
+                hash: err.hash,
+                from: None,
+                result: Err(err.into()),
+            }
+        }
+    };
+    let chain_id = engine.context().chain_id;
+    let result = match tx.chain_id.filter(|signed_for| *signed_for != chain_id) {
+        Some(signed_for) => Err(InvalidTransaction {
+            index,
+            hash: Some(tx.hash),
+            message: format!(
+                "is signed for chain {}, not the fork's chain {}",
+                signed_for, chain_id
+            ),
+        }
+        .into()),
+        None => engine.execute_transaction(&tx),
+    };
+    RawTransactionOutcome {
+        hash: Some(tx.hash),
+        from: Some(tx.from),
+        result,
+    }
+}
+
+/// The outcome of the `index`th raw transaction when it isn't run for `reason`, with its hash and
+/// sender when it decodes.
+pub fn skipped(index: usize, raw: &[u8], reason: SkipReason) -> RawTransactionOutcome {
+    let (hash, from) = match SignedTransaction::decode(index, raw) {
+        Ok(tx) => (Some(tx.hash), Some(tx.from)),
+        Err(err) => (err.hash, None),
+    };
+    RawTransactionOutcome {
+        hash,
+        from,
+        result: Ok(ExecutionResult::skipped(reason)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gas::demo::DEMO_CHAIN_ID;
+    use crate::gas::{EngineConfig, StateDump};
+    use alloy_consensus::{TxEip1559, TxLegacy};
+    use alloy_eips::eip2718::Encodable2718;
+    use alloy_primitives::address;
+    use alloy_signer::SignerSync;
+    use alloy_signer_wallet::LocalWallet;
+    use revm::DatabaseRef;
+
+    // set(uint256) when given a word of calldata, get() otherwise
+    const SIMPLE_STORAGE: &str = "0x60243610600e57600435600055005b60005460005260206000f3";
+    const STORAGE: Address = address!("2020202020202020202020202020202020202020");
+
+    fn set(value: u64) -> Bytes {
+        let mut calldata = vec![0x60, 0xfe, 0x47, 0xb1];
+        calldata.extend_from_slice(&U256::from(value).to_be_bytes::<32>());
+        calldata.into()
+    }
+
+    fn sign<T: SignableTransaction<Signature>>(wallet: &LocalWallet, tx: T) -> Vec<u8>
+    where
+        TxEnvelope: From<Signed<T>>,
+    {
+        let signature = wallet.sign_hash_sync(&tx.signature_hash()).unwrap();
+        TxEnvelope::from(tx.into_signed(signature)).encoded_2718()
+    }
+
+    fn eip1559(chain_id: u64, nonce: u64) -> TxEip1559 {
+        TxEip1559 {
+            chain_id,
+            nonce,
+            gas_limit: 100_000,
+            max_fee_per_gas: 100_000_000_000,
+            max_priority_fee_per_gas: 1_000_000_000,
+            to: TxKind::Call(STORAGE),
+            value: U256::ZERO,
+            access_list: AccessList::default(),
+            input: set(42),
+        }
+    }
+
+    #[test]
+    fn test_decode() {
+        let wallet = LocalWallet::random();
+        let legacy = TxLegacy {
+            chain_id: Some(DEMO_CHAIN_ID),
+            nonce: 3,
+            gas_price: 2_000_000_000,
+            gas_limit: 21_000,
+            to: TxKind::Call(STORAGE),
+            value: U256::from(5),
+            input: Bytes::new(),
+        };
+        let tx = SignedTransaction::decode(0, &sign(&wallet, legacy)).unwrap();
+        assert_eq!(tx.from, wallet.address());
+        assert_eq!(tx.chain_id, Some(DEMO_CHAIN_ID));
+        assert_eq!(
+            (tx.nonce, tx.gas_limit, tx.gas_price),
+            (3, 21_000, 2_000_000_000)
+        );
+        assert_eq!(tx.max_priority_fee_per_gas, None);
+
+        let raw = sign(&wallet, eip1559(DEMO_CHAIN_ID, 0));
+        let tx = SignedTransaction::decode(0, &raw).unwrap();
+        assert_eq!(tx.from, wallet.address());
+        assert_eq!(tx.hash, alloy_primitives::keccak256(&raw));
+        assert_eq!(tx.gas_price, 100_000_000_000);
+        assert_eq!(tx.max_priority_fee_per_gas, Some(1_000_000_000));
+        assert_eq!(tx.input, set(42));
+
+        let err = SignedTransaction::decode(2, &[0x02, 0xc0]).unwrap_err();
+        assert_eq!(err.field(), "rawTransactions[2]");
+        assert_eq!(err.hash, None);
+    }
+
+    #[test]
+    fn test_bad_signature() {
+        let wallet = LocalWallet::random();
+        let tx = eip1559(DEMO_CHAIN_ID, 0);
+        let signature = wallet.sign_hash_sync(&tx.signature_hash()).unwrap();
+        // r of 0 is on no curve point
+        let bad = Signature::new(U256::ZERO, signature.s(), signature.v());
+        let raw = TxEnvelope::from(tx.into_signed(bad)).encoded_2718();
+        let err = SignedTransaction::decode(1, &raw).unwrap_err();
+        assert!(err.hash.is_some());
+        assert!(err
+            .to_string()
+            .starts_with("rawTransactions[1] has a signature"));
+    }
+
+    #[test]
+    fn test_run() {
+        let wallet = LocalWallet::random();
+        let mut engine = EngineConfig::memory(StateDump::new(), None)
+            .build()
+            .unwrap();
+        engine.insert_contract(STORAGE, SIMPLE_STORAGE.parse().unwrap());
+        engine
+            .set_balance(wallet.address(), U256::from(10).pow(U256::from(18)))
+            .unwrap();
+
+        let raw = sign(&wallet, eip1559(DEMO_CHAIN_ID, 0));
+        let outcome = run(&mut engine, 0, &raw);
+        assert_eq!(outcome.from, Some(wallet.address()));
+        assert_eq!(outcome.hash, Some(alloy_primitives::keccak256(&raw)));
+        let result = outcome.result.unwrap();
+        assert!(result.success);
+        assert_eq!(result.env.as_ref().unwrap().caller, wallet.address());
+        assert_eq!(result.env.unwrap().gas_limit, 100_000);
+        let stored = engine
+            .executor()
+            .backend()
+            .storage_ref(STORAGE, U256::ZERO)
+            .unwrap();
+        assert_eq!(stored, U256::from(42));
+
+        // Signed for mainnet
+        let outcome = run(&mut engine, 1, &sign(&wallet, eip1559(1, 1)));
+        assert_eq!(outcome.from, Some(wallet.address()));
+        let err = outcome.result.unwrap_err();
+        let err = err.downcast_ref::<InvalidTransaction>().unwrap();
+        assert_eq!(err.index, 1);
+        assert!(err.message.contains("chain 1,"), "{}", err.message);
+    }
+
+    #[test]
+    fn test_skipped() {
+        let wallet = LocalWallet::random();
+        let raw = sign(&wallet, eip1559(DEMO_CHAIN_ID, 0));
+        let outcome = skipped(1, &raw, SkipReason::DeadlineExceeded);
+        assert_eq!(outcome.from, Some(wallet.address()));
+        assert_eq!(outcome.hash, Some(alloy_primitives::keccak256(&raw)));
+        let result = outcome.result.unwrap();
+        assert_eq!(result.skipped, Some(SkipReason::DeadlineExceeded));
+        assert!(!result.success);
+
+        let outcome = skipped(2, &[0x02, 0xc0], SkipReason::DeadlineExceeded);
+        assert_eq!((outcome.hash, outcome.from), (None, None));
+    }
+}
@@ -0,0 +1,187 @@
+//! An RPC for fork tests that answers from a recording instead of the network. Forks reach their
+//! node through a URL (forge's backend opens its own connection from it), so the recording is
+//! served on a local port and tests fork from that.
+//!
+//! A recording is made by forwarding a test's requests to a real node and writing its answers
+//! out when the test is done.
+//!
+//! `serve` and `serve_http` are also what every other test's mock node is built on.
+
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+// Answers keyed by request, `{"method", "params"}` as compact JSON
+type Answers = BTreeMap<String, Value>;
+
+pub struct RecordedRpc {
+    url: String,
+    // Where to write what was recorded, when recording
+    recording: Option<(PathBuf, Arc<Mutex<Answers>>)>,
+}
+
+impl RecordedRpc {
+    /// Serves what `path` holds, without a network. Requests it doesn't hold are answered with a
+    /// JSON-RPC error naming them.
+    pub fn replay(path: &Path) -> Self {
+        let recorded = std::fs::read_to_string(path).unwrap_or_else(|err| {
+            panic!(
+                "no recording at {} ({}); make one with RecordedRpc::record",
+                path.display(),
+                err
+            )
+        });
+        let entries: Vec<Value> = serde_json::from_str(&recorded).expect("recording is JSON");
+        let answers: Answers = entries
+            .into_iter()
+            .map(|mut entry| (key(&entry["request"]), entry["response"].take()))
+            .collect();
+        let url = serve(move |request| {
+            answers.get(&key(request)).cloned().unwrap_or_else(|| {
+                json!({
+                    "error": {
+                        "code": -32000,
+                        "message": format!("not recorded: {}", key(request)),
+                    }
+                })
+            })
+        });
+        RecordedRpc {
+            url,
+            recording: None,
+        }
+    }
+
+    /// Forwards requests to `upstream`, keeping its answers to write to `path` when dropped.
+    pub fn record(path: PathBuf, upstream: String) -> Self {
+        let answers = Arc::new(Mutex::new(Answers::new()));
+        let recorded = answers.clone();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let client = reqwest::Client::new();
+        let url = serve(move |request| {
+            let mut response: Value = runtime
+                .block_on(async {
+                    client
+                        .post(&upstream)
+                        .json(request)
+                        .send()
+                        .await?
+                        .json()
+                        .await
+                })
+                .expect("upstream RPC answers");
+            // Its result or error; the rest is the request's
+            if let Some(fields) = response.as_object_mut() {
+                fields.remove("jsonrpc");
+                fields.remove("id");
+            }
+            recorded
+                .lock()
+                .unwrap()
+                .insert(key(request), response.clone());
+            response
+        });
+        RecordedRpc {
+            url,
+            recording: Some((path, answers)),
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+}
+
+impl Drop for RecordedRpc {
+    fn drop(&mut self) {
+        let Some((path, answers)) = &self.recording else {
+            return;
+        };
+        let entries: Vec<_> = answers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(request, response)| {
+                json!({
+                    "request": serde_json::from_str::<Value>(request).unwrap(),
+                    "response": response,
+                })
+            })
+            .collect();
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, serde_json::to_string_pretty(&entries).unwrap() + "\n").unwrap();
+    }
+}
+
+// The request without its id and version, which don't change the answer
+fn key(request: &Value) -> String {
+    json!({ "method": request["method"], "params": request["params"] }).to_string()
+}
+
+/// Answers JSON-RPC over HTTP on a free local port, `answer` giving each request's result or
+/// error. Batches are answered one request at a time.
+pub(super) fn serve(answer: impl Fn(&Value) -> Value + Send + Sync + 'static) -> String {
+    serve_http(move |request| {
+        let respond = |request: &Value| {
+            let mut response = json!({ "jsonrpc": "2.0", "id": request["id"] });
+            for (field, value) in answer(request).as_object().into_iter().flatten() {
+                response[field] = value.clone();
+            }
+            response
+        };
+        let response = match request {
+            Value::Array(batch) => Value::Array(batch.iter().map(respond).collect()),
+            single => respond(single),
+        };
+        (200, response.to_string())
+    })
+}
+
+/// Like `serve`, for nodes that don't answer in JSON-RPC: `reply` gives the HTTP status and
+/// body each request gets.
+pub(super) fn serve_http(
+    reply: impl Fn(&Value) -> (u16, String) + Send + Sync + 'static,
+) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let reply = Arc::new(reply);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let reply = reply.clone();
+            std::thread::spawn(move || handle(stream.unwrap(), &*reply));
+        }
+    });
+    url
+}
+
+fn handle(mut stream: TcpStream, reply: &dyn Fn(&Value) -> (u16, String)) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        if line == "\r\n" || line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().unwrap();
+            }
+        }
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+    let request: Value = serde_json::from_slice(&body).unwrap();
+
+    let (status, response) = reply(&request);
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {} \r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        response.len(),
+        response
+    );
+}
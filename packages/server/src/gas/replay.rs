@@ -0,0 +1,375 @@
+//! Replaying a transaction that made it on chain. The fork is taken at the block before the
+//! transaction's, the transactions ahead of it in its block are run first, so it sees the state
+//! it saw on chain, and then it's run itself with tracing and whatever else the options ask for.
+//!
+//! Only a node with the chain's history can do this, so there's no replaying on the demo chain or
+//! the managed anvil.
+
+use alloy::providers::{Provider, ProviderBuilder};
+use alloy_eips::BlockId;
+use alloy_primitives::{Address, B256};
+use alloy_rpc_types_eth::{Block, BlockTransactionsKind, Transaction};
+use serde::Serialize;
+use std::fmt;
+use utoipa::ToSchema;
+
+use super::execute_calldatas_fork::{
+    fork_env, prepare_fork, run_blocking, ExecutionOptions, ExecutionResult, ForkContext, ForkError,
+};
+use super::raw_transaction::SignedTransaction;
+use super::resolve::{ForkSource, ResolvedFork};
+use super::rpc_pool;
+use crate::config::AppConfig;
+
+/// A mined transaction, run again on a fork of the block it was mined in.
+#[derive(Serialize, Clone, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayedTransaction {
+    #[schema(value_type = String)]
+    pub hash: B256,
+    #[schema(value_type = String)]
+    pub from: Address,
+    /// Missing for contract creations
+    #[schema(value_type = Option<String>)]
+    pub to: Option<Address>,
+    pub block_number: u64,
+    pub transaction_index: u64,
+    /// How many transactions ahead of it in its block were run first
+    pub replayed: usize,
+    /// What its receipt says it used, to compare with `result.gasUsed`. Missing when the node
+    /// has no receipt for it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_chain_gas_used: Option<u64>,
+    /// Whether it succeeded on chain, from the same receipt
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_chain_success: Option<bool>,
+    /// The block it ran in, which is its own rather than the one forked
+    pub fork_context: ForkContext,
+    pub result: ExecutionResult,
+}
+
+/// Why a transaction can't be replayed.
+#[derive(Debug)]
+pub enum ReplayError {
+    /// The node doesn't know of it
+    NotFound(B256),
+    /// It hasn't been mined, so there's no block to replay it in
+    Pending(B256),
+    /// Its block, as the node returned it, doesn't list it
+    NotInBlock { hash: B256, block_number: u64 },
+    /// The fork has no history to replay, being the demo chain or the managed anvil
+    NoHistory,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::NotFound(hash) => write!(f, "Transaction {} not found", hash),
+            ReplayError::Pending(hash) => {
+                write!(
+                    f,
+                    "Transaction {} is still pending, so can't be replayed",
+                    hash
+                )
+            }
+            ReplayError::NotInBlock { hash, block_number } => write!(
+                f,
+                "Block {} doesn't list transaction {}, though the node says it's in it",
+                block_number, hash
+            ),
+            ReplayError::NoHistory => write!(
+                f,
+                "Replaying needs a chain with history: give a chainId the server has RPC keys for, \
+                 or an rpcUrl"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+// What the node has on the transaction
+struct Fetched {
+    tx: Transaction,
+    block: Block,
+    gas_used: Option<u64>,
+    success: Option<bool>,
+}
+
+/// Replays `tx_hash` on the chain `fork` points at, of which only the source and chain ID are
+/// taken. `options.stateOverrides` are applied before anything in the block runs, so code put in
+/// place shows what the transaction would have done against it.
+pub async fn replay_transaction(
+    config: &AppConfig,
+    tx_hash: B256,
+    fork: ResolvedFork,
+    options: Option<ExecutionOptions>,
+) -> Result<ReplayedTransaction, eyre::Error> {
+    // The key that found the transaction is the one the fork is taken with
+    let (rpc, fetched) = match &fork.source {
+        ForkSource::Demo | ForkSource::Anvil { .. } => return Err(ReplayError::NoHistory.into()),
+        ForkSource::Url(url) => (url.clone(), fetch(url.clone(), tx_hash).await?),
+        ForkSource::Pool(chain_id) => {
+            let endpoints = config
+                .rpc_endpoints(*chain_id)
+                .ok_or(ForkError::UnsupportedChain(*chain_id))?;
+            rpc_pool::with_endpoint(endpoints, &config.rpc_pool, |rpc| fetch(rpc, tx_hash)).await?
+        }
+    };
+    let Fetched {
+        tx,
+        block,
+        gas_used,
+        success,
+    } = fetched;
+    let block_number = tx.block_number.ok_or(ReplayError::Pending(tx_hash))?;
+    let transactions = block.transactions.as_transactions().unwrap_or_default();
+    let index = transactions
+        .iter()
+        .position(|mined| mined.hash == tx_hash)
+        .ok_or(ReplayError::NotInBlock {
+            hash: tx_hash,
+            block_number,
+        })?;
+    let preceding: Vec<_> = transactions[..index]
+        .iter()
+        .map(SignedTransaction::from_rpc)
+        .collect();
+    let target = SignedTransaction::from_rpc(&tx);
+    let overrides = options
+        .as_ref()
+        .and_then(|opts| opts.state_overrides.clone());
+    let uses_names = options.as_ref().is_some_and(|opts| opts.uses_names());
+
+    let engine = prepare_fork(
+        config,
+        ResolvedFork {
+            source: ForkSource::Url(rpc),
+            chain_id: fork.chain_id,
+            block_number: Some(block_number.saturating_sub(1)),
+            confirmation_lag: None,
+        },
+        options,
+        uses_names,
+    )
+    .await?;
+    let (env, _) = fork_env(engine.context.chain_id, &block, config.limits.max_call_gas)?;
+    let engine = engine.with_env(env);
+    let fork_context = engine.context.clone();
+    let replayed = preceding.len();
+    let result = run_blocking(move || {
+        let mut engine = engine.build()?;
+        if let Some(overrides) = &overrides {
+            engine.override_state(overrides)?;
+        }
+        // What they did on chain is on record; here they only set the state up
+        for tx in &preceding {
+            engine.apply_transaction(tx)?;
+        }
+        engine.execute_transaction(&target)
+    })
+    .await?;
+
+    Ok(ReplayedTransaction {
+        hash: tx_hash,
+        from: tx.from,
+        to: tx.to,
+        block_number,
+        transaction_index: index as u64,
+        replayed,
+        on_chain_gas_used: gas_used,
+        on_chain_success: success,
+        fork_context,
+        result,
+    })
+}
+
+// The transaction, the block it was mined in with every transaction in full, and its receipt
+async fn fetch(rpc: String, hash: B256) -> Result<Fetched, eyre::Error> {
+    let provider = ProviderBuilder::new().on_http(rpc.parse()?);
+    let tx = provider
+        .get_transaction_by_hash(hash)
+        .await?
+        .ok_or(ReplayError::NotFound(hash))?;
+    let block_number = tx.block_number.ok_or(ReplayError::Pending(hash))?;
+    let (block, receipt) = tokio::try_join!(
+        provider.get_block(
+            BlockId::Number(block_number.into()),
+            BlockTransactionsKind::Full
+        ),
+        provider.get_transaction_receipt(hash),
+    )?;
+    let block = block.ok_or(ForkError::BlockNotFound)?;
+    Ok(Fetched {
+        tx,
+        block,
+        gas_used: receipt
+            .as_ref()
+            .map(|receipt| u64::try_from(receipt.gas_used).unwrap_or(u64::MAX)),
+        success: receipt.as_ref().map(|receipt| receipt.status()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gas::recorded_rpc::{serve, RecordedRpc};
+    use crate::gas::ForkConfig;
+    use alloy_primitives::{address, b256, Bytes, U256};
+    use alloy_rpc_types_eth::{BlockTransactions, Header};
+    use serde_json::{json, Value};
+    use std::str::FromStr;
+
+    // Stores calldata[4..36] in slot 0 when given an argument, otherwise returns slot 0
+    const SIMPLE_STORAGE: &str = "0x60243610600e57600435600055005b60005460005260206000f3";
+    const CONTRACT: Address = address!("2000000000000000000000000000000000000000");
+    const SENDER: Address = address!("1000000000000000000000000000000000000000");
+    const SET_7: B256 = b256!("00000000000000000000000000000000000000000000000000000000000000a1");
+    const GET: B256 = b256!("00000000000000000000000000000000000000000000000000000000000000a2");
+
+    fn transaction(hash: B256, index: u64, input: &str) -> Transaction {
+        Transaction {
+            hash,
+            nonce: index,
+            block_hash: Some(B256::repeat_byte(0x17)),
+            block_number: Some(17),
+            transaction_index: Some(index),
+            from: SENDER,
+            to: Some(CONTRACT),
+            value: U256::ZERO,
+            gas_price: Some(1),
+            gas: 100_000,
+            input: Bytes::from_str(input).unwrap(),
+            chain_id: Some(31337),
+            ..Default::default()
+        }
+    }
+
+    // A node whose block 17 holds a set(7) and then a get(), with the contract deployed before
+    fn node() -> String {
+        let header = |number: u64| Header {
+            hash: Some(B256::repeat_byte(number as u8)),
+            number: Some(number),
+            gas_limit: 30_000_000,
+            timestamp: 1_700_000_000 + number,
+            base_fee_per_gas: Some(0),
+            mix_hash: Some(B256::ZERO),
+            ..Default::default()
+        };
+        let set = transaction(
+            SET_7,
+            0,
+            "0x60fe47b10000000000000000000000000000000000000000000000000000000000000007",
+        );
+        let get = transaction(GET, 1, "0x6d4ce63c");
+        let mined = serde_json::to_value(Block {
+            header: header(17),
+            transactions: BlockTransactions::Full(vec![set, get.clone()]),
+            ..Default::default()
+        })
+        .unwrap();
+        let parent = serde_json::to_value(Block {
+            header: header(16),
+            transactions: BlockTransactions::Hashes(vec![]),
+            ..Default::default()
+        })
+        .unwrap();
+        let get = serde_json::to_value(get).unwrap();
+        serve(move |request: &Value| {
+            let params = &request["params"];
+            let result = match request["method"].as_str().unwrap_or_default() {
+                "eth_chainId" => json!("0x7a69"),
+                "eth_blockNumber" => json!("0x11"),
+                "eth_getTransactionByHash" if params[0] == json!(GET) => get.clone(),
+                "eth_getTransactionByHash" | "eth_getTransactionReceipt" => Value::Null,
+                "eth_getBlockByNumber" if params[0] == "0x11" => mined.clone(),
+                "eth_getBlockByNumber" | "eth_getBlockByHash" => parent.clone(),
+                "eth_getCode" if params[0] == json!(CONTRACT) => json!(SIMPLE_STORAGE),
+                "eth_getCode" => json!("0x"),
+                "eth_getStorageAt" => json!(B256::ZERO),
+                "eth_getBalance" => json!("0xde0b6b3a7640000"),
+                _ => json!("0x0"),
+            };
+            json!({ "result": result })
+        })
+    }
+
+    fn fork(rpc: &str) -> ResolvedFork {
+        ForkConfig {
+            rpc_url: Some(rpc.to_string()),
+            ..Default::default()
+        }
+        .resolve(&AppConfig::default())
+        .unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_replay_transaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("replay.json");
+        let config = AppConfig::default();
+        let live = {
+            let rpc = RecordedRpc::record(path.clone(), node());
+            replay_transaction(&config, GET, fork(rpc.url()), None)
+                .await
+                .unwrap()
+        };
+        assert_eq!(live.block_number, 17);
+        assert_eq!(live.transaction_index, 1);
+        assert_eq!(live.replayed, 1);
+        assert_eq!(live.fork_context.block_number, 17);
+        // Reads what the set(7) ahead of it stored
+        assert_eq!(
+            live.result.result,
+            Bytes::from(U256::from(7).to_be_bytes_vec())
+        );
+        assert_eq!(live.result.gas_limit, Some(100_000));
+        assert!(live.on_chain_gas_used.is_none());
+
+        // The recording is enough to replay it again with the node gone
+        let rpc = RecordedRpc::replay(&path);
+        let replayed = replay_transaction(&config, GET, fork(rpc.url()), None)
+            .await
+            .unwrap();
+        assert_eq!(replayed.result, live.result);
+
+        // With the contract's code swapped for code returning the caller
+        let overrides = serde_json::from_value(json!({
+            CONTRACT.to_string(): { "code": "0x3360005260206000f3" }
+        }))
+        .unwrap();
+        let options = ExecutionOptions {
+            state_overrides: Some(overrides),
+            state_diff: true,
+            ..Default::default()
+        };
+        let rpc = node();
+        let patched = replay_transaction(&config, GET, fork(&rpc), Some(options))
+            .await
+            .unwrap();
+        assert_eq!(
+            patched.result.result,
+            Bytes::from(SENDER.into_word().to_vec())
+        );
+        // A read, so nothing but the sender's nonce changes
+        assert!(!patched.result.state_diff.unwrap().contains_key(&CONTRACT));
+
+        let err = replay_transaction(&config, B256::repeat_byte(0xee), fork(&rpc), None)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ReplayError>(),
+            Some(ReplayError::NotFound(_))
+        ));
+
+        // With neither an RPC nor keys for the default chain, the fork would be the managed anvil
+        let anvil = ForkConfig::default().resolve(&config).unwrap();
+        let err = replay_transaction(&config, GET, anvil, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ReplayError>(),
+            Some(ReplayError::NoHistory)
+        ));
+    }
+}
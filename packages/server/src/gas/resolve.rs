@@ -0,0 +1,441 @@
+use alloy_primitives::Address;
+
+use super::demo;
+use super::execute_calldatas_fork::{ForkConfig, ForkError};
+use crate::config::AppConfig;
+
+/// Where a fork's state comes from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ForkSource {
+    /// The chain bundled with the server
+    Demo,
+    /// The managed anvil, after funding these accounts
+    Anvil { funded_accounts: Vec<Address> },
+    /// An RPC URL the request brought
+    Url(String),
+    /// The configured keys for a chain
+    Pool(u64),
+}
+
+/// A `ForkConfig` with its precedence applied and every default filled in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResolvedFork {
+    pub source: ForkSource,
+    /// The chain ID calls see. `None` takes it from the RPC.
+    pub chain_id: Option<u64>,
+    /// `None` forks the latest block, less the confirmation lag
+    pub block_number: Option<u64>,
+    /// Blocks behind the head to fork without `block_number`. `None` takes the chain's, once
+    /// it's known.
+    pub confirmation_lag: Option<u64>,
+}
+
+impl ResolvedFork {
+    /// The block a fork without `block_number` takes on `chain_id`, whose head is `head`, and
+    /// how far behind the head that is. Near genesis there may be fewer blocks than the lag.
+    pub fn lagged_block(&self, config: &AppConfig, chain_id: u64, head: u64) -> (u64, u64) {
+        let lag = self
+            .confirmation_lag
+            .unwrap_or_else(|| config.confirmation_lag(chain_id));
+        let number = head.saturating_sub(lag);
+        (number, head - number)
+    }
+}
+
+impl ForkConfig {
+    /// Settles which of the settings apply, refusing combinations that contradict each other:
+    ///
+    /// - `network` runs on the bundled chain, and can't be combined with another source
+    /// - `mode: "anvil"` starts a managed anvil, so there's no `rpcUrl` to use. `fundedAccounts`
+    ///   is only for it, and left alone otherwise, as it always was
+    /// - `rpcUrl` is used as is, but `chainId` can't name a different chain than the one it's
+    ///   configured for, when it's one of ours
+    /// - `chainId` alone picks that chain's configured keys
+    /// - With neither, the default chain's keys, or the managed anvil when it has none
+    /// - `confirmationLag` only applies without `blockNumber`, and the managed anvil, which
+    ///   doesn't reorg, defaults to none
+    pub fn resolve(&self, config: &AppConfig) -> Result<ResolvedFork, ForkError> {
+        if self.confirmation_lag.is_some() && self.block_number.is_some() {
+            return Err(conflict(
+                "forkConfig.confirmationLag",
+                "only applies when no blockNumber is given",
+            ));
+        }
+        if let Some(network) = self.network.as_deref() {
+            return self.resolve_network(network);
+        }
+        let anvil = match self.mode.as_deref() {
+            None | Some("rpc") => false,
+            Some("anvil") => true,
+            Some(mode) => return Err(ForkError::UnknownMode(mode.to_string())),
+        };
+        let funded_accounts = self.funded_accounts.clone().unwrap_or_default();
+
+        let source = match (&self.rpc_url, self.chain_id) {
+            (Some(_), _) if anvil => {
+                return Err(conflict(
+                    "forkConfig.rpcUrl",
+                    "mode \"anvil\" starts its own node, so it can't be combined with rpcUrl",
+                ))
+            }
+            _ if anvil => ForkSource::Anvil { funded_accounts },
+            (Some(url), chain_id) => {
+                if let (Some(chain_id), Some(configured)) =
+                    (chain_id, configured_chain(config, url))
+                {
+                    if chain_id != configured {
+                        return Err(conflict(
+                            "forkConfig.chainId",
+                            format!(
+                                "rpcUrl is configured for chain ID {}, not {}",
+                                configured, chain_id
+                            ),
+                        ));
+                    }
+                }
+                ForkSource::Url(url.clone())
+            }
+            (None, Some(chain_id)) => {
+                config
+                    .rpc_endpoints(chain_id)
+                    .ok_or(ForkError::UnsupportedChain(chain_id))?;
+                ForkSource::Pool(chain_id)
+            }
+            (None, None) => match config.rpc_endpoints(config.default_chain_id) {
+                Some(_) => ForkSource::Pool(config.default_chain_id),
+                None => ForkSource::Anvil {
+                    funded_accounts: vec![],
+                },
+            },
+        };
+        let confirmation_lag = match &source {
+            ForkSource::Anvil { .. } => Some(self.confirmation_lag.unwrap_or(0)),
+            _ => self.confirmation_lag,
+        };
+        Ok(ResolvedFork {
+            source,
+            chain_id: self.chain_id,
+            block_number: self.block_number,
+            confirmation_lag,
+        })
+    }
+
+    fn resolve_network(&self, network: &str) -> Result<ResolvedFork, ForkError> {
+        if network != demo::NETWORK {
+            return Err(ForkError::UnknownNetwork(network.to_string()));
+        }
+        if self.rpc_url.is_some() {
+            return Err(conflict(
+                "forkConfig.rpcUrl",
+                "network can't be combined with rpcUrl",
+            ));
+        }
+        if self.mode.is_some() {
+            return Err(conflict(
+                "forkConfig.mode",
+                "network can't be combined with mode",
+            ));
+        }
+        if self
+            .chain_id
+            .is_some_and(|chain_id| chain_id != demo::DEMO_CHAIN_ID)
+        {
+            return Err(conflict(
+                "forkConfig.chainId",
+                format!("the demo network is chain ID {}", demo::DEMO_CHAIN_ID),
+            ));
+        }
+        if let Some(requested) = self.block_number {
+            if requested > demo::DEMO_BLOCK_NUMBER {
+                return Err(ForkError::BlockInFuture {
+                    requested,
+                    head: demo::DEMO_BLOCK_NUMBER,
+                });
+            }
+            if requested < demo::DEMO_BLOCK_NUMBER {
+                return Err(conflict(
+                    "forkConfig.blockNumber",
+                    format!(
+                        "the demo network only has block {}",
+                        demo::DEMO_BLOCK_NUMBER
+                    ),
+                ));
+            }
+        }
+        Ok(ResolvedFork {
+            source: ForkSource::Demo,
+            chain_id: Some(demo::DEMO_CHAIN_ID),
+            block_number: Some(demo::DEMO_BLOCK_NUMBER),
+            confirmation_lag: None,
+        })
+    }
+}
+
+// The chain an RPC URL is one of the keys for
+fn configured_chain(config: &AppConfig, url: &str) -> Option<u64> {
+    config
+        .chain_rpc_urls
+        .iter()
+        .find(|(_, endpoints)| endpoints.iter().any(|endpoint| endpoint.url == url))
+        .map(|(chain_id, _)| *chain_id)
+}
+
+fn conflict(field: &'static str, message: impl Into<String>) -> ForkError {
+    ForkError::Conflict {
+        field,
+        message: message.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RpcEndpoint;
+    use alloy_primitives::address;
+    use std::collections::HashMap;
+
+    const BASE_KEY: &str = "http://base.example/v2/key";
+
+    fn config(default_chain_id: u64) -> AppConfig {
+        AppConfig {
+            chain_rpc_urls: HashMap::from([(
+                8453,
+                vec![RpcEndpoint {
+                    url: BASE_KEY.to_string(),
+                    weight: 1,
+                }],
+            )]),
+            default_chain_id,
+            ..Default::default()
+        }
+    }
+
+    fn resolve(fork: ForkConfig) -> Result<ResolvedFork, ForkError> {
+        fork.resolve(&config(8453))
+    }
+
+    fn field(err: ForkError) -> &'static str {
+        err.field().unwrap()
+    }
+
+    #[test]
+    fn test_defaults() {
+        let fork = resolve(ForkConfig::default()).unwrap();
+        assert_eq!(
+            fork,
+            ResolvedFork {
+                source: ForkSource::Pool(8453),
+                chain_id: None,
+                block_number: None,
+                confirmation_lag: None,
+            }
+        );
+
+        // Without keys for the default chain, the managed anvil
+        let fork = ForkConfig::default().resolve(&config(1)).unwrap();
+        assert_eq!(
+            fork.source,
+            ForkSource::Anvil {
+                funded_accounts: vec![]
+            }
+        );
+    }
+
+    #[test]
+    fn test_chain_id() {
+        let fork = resolve(ForkConfig {
+            chain_id: Some(8453),
+            block_number: Some(100),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(fork.source, ForkSource::Pool(8453));
+        assert_eq!((fork.chain_id, fork.block_number), (Some(8453), Some(100)));
+
+        let err = resolve(ForkConfig {
+            chain_id: Some(999),
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert!(matches!(err, ForkError::UnsupportedChain(999)));
+    }
+
+    #[test]
+    fn test_rpc_url() {
+        // Unknown URLs are taken at their word
+        let fork = resolve(ForkConfig {
+            rpc_url: Some("http://localhost:8545".to_string()),
+            chain_id: Some(31337),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            fork.source,
+            ForkSource::Url("http://localhost:8545".to_string())
+        );
+        assert_eq!(fork.chain_id, Some(31337));
+
+        let matching = ForkConfig {
+            rpc_url: Some(BASE_KEY.to_string()),
+            chain_id: Some(8453),
+            ..Default::default()
+        };
+        assert_eq!(
+            resolve(matching.clone()).unwrap().source,
+            ForkSource::Url(BASE_KEY.to_string())
+        );
+
+        let err = resolve(ForkConfig {
+            chain_id: Some(1),
+            ..matching
+        })
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "rpcUrl is configured for chain ID 8453, not 1"
+        );
+        assert_eq!(field(err), "forkConfig.chainId");
+    }
+
+    #[test]
+    fn test_anvil() {
+        let accounts = vec![address!("1000000000000000000000000000000000000001")];
+        let fork = resolve(ForkConfig {
+            mode: Some("anvil".to_string()),
+            funded_accounts: Some(accounts.clone()),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            fork.source,
+            ForkSource::Anvil {
+                funded_accounts: accounts.clone()
+            }
+        );
+
+        let err = resolve(ForkConfig {
+            mode: Some("anvil".to_string()),
+            rpc_url: Some("http://localhost:8545".to_string()),
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert_eq!(field(err), "forkConfig.rpcUrl");
+
+        // Ignored without anvil
+        let fork = resolve(ForkConfig {
+            funded_accounts: Some(accounts),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(fork.source, ForkSource::Pool(8453));
+
+        let err = resolve(ForkConfig {
+            mode: Some("ipc".to_string()),
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert_eq!(field(err), "forkConfig.mode");
+    }
+
+    #[test]
+    fn test_network() {
+        let network = ForkConfig {
+            network: Some("demo".to_string()),
+            ..Default::default()
+        };
+        let expected = ResolvedFork {
+            source: ForkSource::Demo,
+            chain_id: Some(demo::DEMO_CHAIN_ID),
+            block_number: Some(demo::DEMO_BLOCK_NUMBER),
+            confirmation_lag: None,
+        };
+        assert_eq!(resolve(network.clone()).unwrap(), expected);
+        let explicit = ForkConfig {
+            chain_id: Some(demo::DEMO_CHAIN_ID),
+            block_number: Some(demo::DEMO_BLOCK_NUMBER),
+            ..network.clone()
+        };
+        assert_eq!(resolve(explicit).unwrap(), expected);
+
+        let err = resolve(ForkConfig {
+            block_number: Some(2),
+            ..network.clone()
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Block 2 is past the chain's head, block 1");
+        assert_eq!(field(err), "forkConfig.blockNumber");
+
+        for (fork, expected) in [
+            (
+                ForkConfig {
+                    rpc_url: Some(BASE_KEY.to_string()),
+                    ..network.clone()
+                },
+                "forkConfig.rpcUrl",
+            ),
+            (
+                ForkConfig {
+                    mode: Some("anvil".to_string()),
+                    ..network.clone()
+                },
+                "forkConfig.mode",
+            ),
+            (
+                ForkConfig {
+                    chain_id: Some(8453),
+                    ..network.clone()
+                },
+                "forkConfig.chainId",
+            ),
+            (
+                ForkConfig {
+                    block_number: Some(0),
+                    ..network.clone()
+                },
+                "forkConfig.blockNumber",
+            ),
+            (
+                ForkConfig {
+                    network: Some("mainnet".to_string()),
+                    ..Default::default()
+                },
+                "forkConfig.network",
+            ),
+        ] {
+            assert_eq!(field(resolve(fork).unwrap_err()), expected);
+        }
+    }
+
+    #[test]
+    fn test_confirmation_lag() {
+        let config = config(8453);
+        let latest = resolve(ForkConfig::default()).unwrap();
+        // The chain's lag, Polygon's deeper than the rest
+        assert_eq!(latest.lagged_block(&config, 8453, 1_000), (999, 1));
+        assert_eq!(latest.lagged_block(&config, 137, 1_000), (995, 5));
+        // Not past genesis
+        assert_eq!(latest.lagged_block(&config, 137, 3), (0, 3));
+
+        let head = resolve(ForkConfig {
+            confirmation_lag: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(head.lagged_block(&config, 137, 1_000), (1_000, 0));
+
+        let anvil = resolve(ForkConfig {
+            mode: Some("anvil".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(anvil.lagged_block(&config, 31337, 7), (7, 0));
+
+        let err = resolve(ForkConfig {
+            block_number: Some(100),
+            confirmation_lag: Some(2),
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert_eq!(field(err), "forkConfig.confirmationLag");
+    }
+}
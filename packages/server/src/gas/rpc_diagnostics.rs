@@ -0,0 +1,337 @@
+//! What a fork's node says about itself, for telling a stale fork from a lagging or pruned node.
+//! Every probe is optional: providers that block a method, or take too long, leave its answer
+//! out rather than fail the fork.
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tracing::debug;
+use utoipa::ToSchema;
+
+use super::consistency::Node;
+use crate::caches::{Cache, CacheStats};
+use crate::telemetry::redact_url;
+
+/// How long each probe gets
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long a node's answers are reused for
+const CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// Answers kept at most, a few for each node. Every request's own `rpcUrl` is one more node.
+const MAX_ENTRIES: usize = 1024;
+
+/// Blocks behind the head whose state a full node still keeps. Forks of older blocks need an
+/// archive node.
+const RECENT_STATE_BLOCKS: u64 = 128;
+
+/// The block whose state is asked for to tell an archive node from a pruned one
+const OLD_BLOCK: &str = "0x1";
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcDiagnostics {
+    /// `web3_clientVersion`, e.g. `Geth/v1.14.5-stable/linux-amd64/go1.22.4`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_version: Option<String>,
+    /// Whether `eth_syncing` says the node is still catching up with the chain
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub syncing: Option<bool>,
+    /// Only when the fork names a block more than 128 behind the head: whether the node still
+    /// has the state of the chain's first blocks, as only archive nodes do
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_archive_likely: Option<bool>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Probe {
+    ClientVersion,
+    Syncing,
+    Head,
+    Archive,
+}
+
+static CACHE: Lazy<DiagnosticsCache> = Lazy::new(|| DiagnosticsCache::new(MAX_ENTRIES));
+
+struct Entry {
+    answer: Option<Value>,
+    stored_at: Instant,
+}
+
+/// Nodes' answers to the probes, by URL and probe, each normalized. No answer is cached too, so a
+/// provider that blocks a method isn't asked again on every fork. Answers go once they're
+/// `CACHE_TTL` old, and the oldest first when there's no room. Clones share the same entries.
+#[derive(Clone)]
+pub struct DiagnosticsCache {
+    entries: Arc<Mutex<HashMap<(String, Probe), Entry>>>,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+    max_entries: usize,
+}
+
+/// The cache `diagnose` keeps answers in, for registering with `Caches`.
+pub fn cache() -> DiagnosticsCache {
+    CACHE.clone()
+}
+
+impl DiagnosticsCache {
+    fn new(max_entries: usize) -> Self {
+        DiagnosticsCache {
+            entries: Arc::default(),
+            hits: Arc::default(),
+            misses: Arc::default(),
+            max_entries,
+        }
+    }
+
+    // The answer for `key`, if there's one young enough
+    fn get(&self, key: &(String, Probe)) -> Option<Option<Value>> {
+        match self.lock().get(key) {
+            Some(entry) if entry.stored_at.elapsed() < CACHE_TTL => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(entry.answer.clone())
+            }
+            _ => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    fn insert(&self, key: (String, Probe), answer: Option<Value>) {
+        let mut entries = self.lock();
+        entries.retain(|_, entry| entry.stored_at.elapsed() < CACHE_TTL);
+        while entries.len() >= self.max_entries {
+            let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.stored_at)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+        entries.insert(
+            key,
+            Entry {
+                answer,
+                stored_at: Instant::now(),
+            },
+        );
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<(String, Probe), Entry>> {
+        self.entries.lock().unwrap()
+    }
+}
+
+impl Cache for DiagnosticsCache {
+    fn name(&self) -> &'static str {
+        "rpcDiagnostics"
+    }
+
+    fn stats(&self) -> CacheStats {
+        let entries = self.lock();
+        CacheStats {
+            entries: entries.len(),
+            bytes: entries
+                .iter()
+                .map(|((url, _), entry)| {
+                    url.len()
+                        + entry
+                            .answer
+                            .as_ref()
+                            .map_or(0, |answer| answer.to_string().len())
+                })
+                .sum(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            oldest_entry_age_secs: entries
+                .values()
+                .map(|entry| entry.stored_at.elapsed().as_secs())
+                .max(),
+        }
+    }
+
+    fn clear(&self) -> usize {
+        let mut entries = self.lock();
+        let cleared = entries.len();
+        entries.clear();
+        cleared
+    }
+}
+
+/// Asks `rpc` about itself. `requested` is the block the fork names, if it names one.
+pub(super) async fn diagnose(rpc: &str, requested: Option<u64>) -> RpcDiagnostics {
+    let node = Node::new(rpc, 0);
+    let archive = async {
+        let requested = requested?;
+        let head = probe(rpc, Probe::Head, &node).await?.as_u64()?;
+        if head.saturating_sub(requested) <= RECENT_STATE_BLOCKS {
+            return None;
+        }
+        probe(rpc, Probe::Archive, &node).await?.as_bool()
+    };
+    let (client_version, syncing, is_archive_likely) = tokio::join!(
+        probe(rpc, Probe::ClientVersion, &node),
+        probe(rpc, Probe::Syncing, &node),
+        archive
+    );
+    RpcDiagnostics {
+        client_version: client_version.and_then(|version| version.as_str().map(str::to_string)),
+        syncing: syncing.and_then(|syncing| syncing.as_bool()),
+        is_archive_likely,
+    }
+}
+
+// `which`'s answer from the cache, or from `node` when it isn't there or is stale
+async fn probe(rpc: &str, which: Probe, node: &Node) -> Option<Value> {
+    let key = (rpc.to_string(), which);
+    if let Some(answer) = CACHE.get(&key) {
+        return answer;
+    }
+    let probing = async {
+        match which {
+            Probe::ClientVersion => {
+                let version = ask(node, "web3_clientVersion", json!([])).await?;
+                let version = version.as_str()?.trim();
+                (!version.is_empty()).then(|| json!(version))
+            }
+            // `false`, or an object with how far along it is
+            Probe::Syncing => match ask(node, "eth_syncing", json!([])).await? {
+                Value::Bool(syncing) => Some(json!(syncing)),
+                Value::Object(_) => Some(json!(true)),
+                _ => None,
+            },
+            Probe::Head => {
+                let head = ask(node, "eth_blockNumber", json!([])).await?;
+                let head = head.as_str()?.trim_start_matches("0x");
+                u64::from_str_radix(head, 16).ok().map(|head| json!(head))
+            }
+            Probe::Archive => archive(node).await.map(|archive| json!(archive)),
+        }
+    };
+    let answer = tokio::time::timeout(PROBE_TIMEOUT, probing)
+        .await
+        .unwrap_or_else(|_| {
+            debug!(rpc = %redact_url(rpc), "RPC diagnostics probe timed out");
+            None
+        });
+    CACHE.insert(key, answer.clone());
+    answer
+}
+
+// `method`'s result. An error, or no answer, is none.
+async fn ask(node: &Node, method: &str, params: Value) -> Option<Value> {
+    node.request(method, params).await.ok()?.ok()
+}
+
+// Whether the node can answer for state at `OLD_BLOCK`. A pruned node answers with an error
+// (`missing trie node`, `header not found`, ...), which says no, where not answering at all
+// says nothing.
+async fn archive(node: &Node) -> Option<bool> {
+    let params = json!(["0x0000000000000000000000000000000000000000", OLD_BLOCK]);
+    match node.request("eth_getBalance", params).await {
+        Ok(Ok(_)) => Some(true),
+        Ok(Err(_)) => Some(false),
+        Err(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gas::recorded_rpc::serve;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    // A node at block 0x100000 that answers `eth_getBalance` with `balance`, counting how often
+    // it's asked for one
+    fn node(balance: Value, syncing: Value) -> (String, Arc<AtomicUsize>) {
+        let asked = Arc::new(AtomicUsize::new(0));
+        let counter = asked.clone();
+        let url = serve(move |request| match request["method"].as_str().unwrap() {
+            "web3_clientVersion" => json!({ "result": "Geth/v1.14.5-stable/linux-amd64 " }),
+            "eth_syncing" => syncing.clone(),
+            "eth_blockNumber" => json!({ "result": "0x100000" }),
+            "eth_getBalance" => {
+                counter.fetch_add(1, Ordering::SeqCst);
+                balance.clone()
+            }
+            method => {
+                json!({ "error": { "code": -32601, "message": format!("{} not found", method) } })
+            }
+        });
+        (url, asked)
+    }
+
+    #[tokio::test]
+    async fn test_archive_node() {
+        let (url, asked) = node(json!({ "result": "0x0" }), json!({ "result": false }));
+        let diagnostics = diagnose(&url, Some(1_000)).await;
+        assert_eq!(
+            diagnostics,
+            RpcDiagnostics {
+                client_version: Some("Geth/v1.14.5-stable/linux-amd64".to_string()),
+                syncing: Some(false),
+                is_archive_likely: Some(true),
+            }
+        );
+
+        // Cached
+        assert_eq!(diagnose(&url, Some(1_000)).await, diagnostics);
+        assert_eq!(asked.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_pruned_node() {
+        let missing = json!({ "error": { "code": -32000, "message": "missing trie node" } });
+        let syncing =
+            json!({ "result": { "currentBlock": "0xff000", "highestBlock": "0x100000" } });
+        let (url, _) = node(missing, syncing);
+        let diagnostics = diagnose(&url, Some(1_000)).await;
+        assert_eq!(diagnostics.syncing, Some(true));
+        assert_eq!(diagnostics.is_archive_likely, Some(false));
+    }
+
+    #[tokio::test]
+    async fn test_archive_only_probed_for_old_blocks() {
+        let (url, asked) = node(json!({ "result": "0x0" }), json!({ "result": false }));
+        // Recent enough for any full node, or the latest block
+        for requested in [Some(0x100000 - 10), None] {
+            let diagnostics = diagnose(&url, requested).await;
+            assert_eq!(diagnostics.is_archive_likely, None);
+            assert!(diagnostics.client_version.is_some());
+        }
+        assert_eq!(asked.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_cache_is_bounded() {
+        let cache = DiagnosticsCache::new(2);
+        let key = |url: &str| (url.to_string(), Probe::Head);
+        cache.insert(key("http://a"), Some(json!(1)));
+        cache.insert(key("http://b"), None);
+        cache.insert(key("http://c"), Some(json!(3)));
+        // The oldest made room
+        assert_eq!(cache.get(&key("http://a")), None);
+        assert_eq!(cache.get(&key("http://b")), Some(None));
+        assert_eq!(cache.get(&key("http://c")), Some(Some(json!(3))));
+
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses), (2, 2, 1));
+        assert_eq!(cache.clear(), 2);
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[tokio::test]
+    async fn test_blocked_methods_are_left_out() {
+        let blocked = json!({ "error": { "code": -32601, "message": "method not allowed" } });
+        let url = serve(move |_| blocked.clone());
+        assert_eq!(diagnose(&url, Some(1)).await, RpcDiagnostics::default());
+    }
+}
@@ -0,0 +1,322 @@
+use crate::config::{PoolStrategy, RpcEndpoint, RpcPoolConfig};
+use alloy_transport::{RpcError, TransportError, TransportErrorKind};
+use once_cell::sync::Lazy;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::telemetry::redact_url;
+
+#[derive(Default)]
+struct KeyState {
+    // Smooth weighted round robin: the key with the highest is picked, then pays the total back
+    current_weight: i64,
+    limited_at: Option<Instant>,
+    cooling_until: Option<Instant>,
+    requests: u64,
+    rate_limited: u64,
+}
+
+impl KeyState {
+    fn cooling(&self, now: Instant) -> bool {
+        self.cooling_until.is_some_and(|until| until > now)
+    }
+}
+
+// By URL, which stands for its key, so chains that share an endpoint share its cooldown. Like the
+// managed anvil, this outlives any one request and any one config.
+static KEYS: Lazy<Mutex<HashMap<String, KeyState>>> = Lazy::new(Mutex::default);
+
+/// Picks the endpoint for the next request. Cooling-down keys are skipped unless all of them are,
+/// in which case the one that recovers first is used.
+pub fn pick<'a>(endpoints: &'a [RpcEndpoint], strategy: PoolStrategy) -> &'a RpcEndpoint {
+    assert!(!endpoints.is_empty(), "a pool has at least one endpoint");
+    let now = Instant::now();
+    let mut keys = KEYS.lock().unwrap();
+    for endpoint in endpoints {
+        keys.entry(endpoint.url.clone()).or_default();
+    }
+
+    let mut candidates: Vec<&RpcEndpoint> = endpoints
+        .iter()
+        .filter(|endpoint| !keys[&endpoint.url].cooling(now))
+        .collect();
+    if candidates.is_empty() {
+        let soonest = endpoints
+            .iter()
+            .min_by_key(|endpoint| keys[&endpoint.url].cooling_until)
+            .expect("endpoints isn't empty");
+        candidates.push(soonest);
+    } else if strategy == PoolStrategy::LeastRecentlyLimited {
+        // Never limited sorts first
+        let least = candidates
+            .iter()
+            .map(|endpoint| keys[&endpoint.url].limited_at)
+            .min()
+            .expect("candidates isn't empty");
+        candidates.retain(|endpoint| keys[&endpoint.url].limited_at == least);
+    }
+
+    let total: i64 = candidates.iter().map(|endpoint| weight(endpoint)).sum();
+    for endpoint in &candidates {
+        keys.get_mut(&endpoint.url)
+            .expect("inserted above")
+            .current_weight += weight(endpoint);
+    }
+    // The first of any tied, so equal weights go in order
+    let picked = candidates
+        .into_iter()
+        .reduce(|picked, endpoint| {
+            if keys[&endpoint.url].current_weight > keys[&picked.url].current_weight {
+                endpoint
+            } else {
+                picked
+            }
+        })
+        .expect("candidates isn't empty");
+    let key = keys.get_mut(&picked.url).expect("inserted above");
+    key.current_weight -= total;
+    key.requests += 1;
+    picked
+}
+
+fn weight(endpoint: &RpcEndpoint) -> i64 {
+    i64::from(endpoint.weight.max(1))
+}
+
+/// Takes `url` out of rotation for `cooldown` after a 429.
+pub fn mark_limited(url: &str, cooldown: Duration) {
+    let now = Instant::now();
+    let mut keys = KEYS.lock().unwrap();
+    let key = keys.entry(url.to_string()).or_default();
+    key.limited_at = Some(now);
+    key.cooling_until = Some(now + cooldown);
+    key.rate_limited += 1;
+}
+
+/// Whether the provider answered with a 429, or a JSON-RPC error saying the same.
+pub fn is_rate_limited(err: &eyre::Error) -> bool {
+    err.chain()
+        .any(|cause| match cause.downcast_ref::<TransportError>() {
+            Some(RpcError::Transport(TransportErrorKind::HttpError(err))) => err.status == 429,
+            Some(RpcError::ErrorResp(payload)) => payload.code == 429,
+            _ => false,
+        })
+}
+
+/// Runs `f` against a key from the pool, moving on to the next key when one is rate limited. Gives
+/// up once every key has been tried. Returns the URL that worked, for the rest of the fork to use.
+pub async fn with_endpoint<T, F, Fut>(
+    endpoints: &[RpcEndpoint],
+    pool: &RpcPoolConfig,
+    mut f: F,
+) -> Result<(String, T), eyre::Error>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = Result<T, eyre::Error>>,
+{
+    let mut attempts = 0;
+    loop {
+        let url = pick(endpoints, pool.strategy).url.clone();
+        attempts += 1;
+        match f(url.clone()).await {
+            Ok(value) => return Ok((url, value)),
+            Err(err) if is_rate_limited(&err) => {
+                mark_limited(&url, pool.cooldown);
+                warn!(rpc = %redact_url(&url), cooldown_secs = pool.cooldown.as_secs(), "RPC key rate limited");
+                if attempts >= endpoints.len() {
+                    return Err(err);
+                }
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Pool health per chain in the Prometheus text format. Keys are labelled by their position in
+/// the chain's list, since their URLs carry secrets.
+pub fn render(chains: &HashMap<u64, Vec<RpcEndpoint>>, out: &mut String) {
+    let chains: BTreeMap<_, _> = chains.iter().collect();
+    let now = Instant::now();
+    let keys = KEYS.lock().unwrap();
+    // Keys no fork has used yet
+    let unused = KeyState::default();
+    let families: [(&str, &str, &str, fn(&KeyState, Instant) -> u64); 3] = [
+        (
+            "evm_repl_rpc_key_available",
+            "Whether an RPC key is in rotation, 0 while it cools down after a 429",
+            "gauge",
+            |key, now| u64::from(!key.cooling(now)),
+        ),
+        (
+            "evm_repl_rpc_key_requests_total",
+            "Forks set up through an RPC key",
+            "counter",
+            |key, _| key.requests,
+        ),
+        (
+            "evm_repl_rpc_key_rate_limited_total",
+            "429s from an RPC key",
+            "counter",
+            |key, _| key.rate_limited,
+        ),
+    ];
+    for (name, help, kind, value) in families {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        for (chain_id, endpoints) in &chains {
+            for (i, endpoint) in endpoints.iter().enumerate() {
+                let key = keys.get(&endpoint.url).unwrap_or(&unused);
+                let _ = writeln!(
+                    out,
+                    "{}{{chain=\"{}\",key=\"{}\"}} {}",
+                    name,
+                    chain_id,
+                    i,
+                    value(key, now)
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gas::recorded_rpc::serve_http;
+    use alloy::providers::{Provider, ProviderBuilder};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn endpoint(url: &str, weight: u32) -> RpcEndpoint {
+        RpcEndpoint {
+            url: url.to_string(),
+            weight,
+        }
+    }
+
+    fn picks(endpoints: &[RpcEndpoint], strategy: PoolStrategy, n: usize) -> Vec<String> {
+        (0..n)
+            .map(|_| pick(endpoints, strategy).url.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_weighted_round_robin() {
+        let endpoints = [endpoint("http://wrr-a", 2), endpoint("http://wrr-b", 1)];
+        let picks = picks(&endpoints, PoolStrategy::RoundRobin, 6);
+        let a = picks.iter().filter(|url| *url == "http://wrr-a").count();
+        assert_eq!(a, 4);
+        // Spread out rather than in runs
+        assert!(picks
+            .windows(3)
+            .all(|w| w.iter().any(|url| url == "http://wrr-b")));
+    }
+
+    #[test]
+    fn test_cooling_down_keys_are_skipped() {
+        let endpoints = [endpoint("http://cool-a", 1), endpoint("http://cool-b", 1)];
+        mark_limited("http://cool-a", Duration::from_secs(60));
+        assert!(picks(&endpoints, PoolStrategy::RoundRobin, 4)
+            .iter()
+            .all(|url| url == "http://cool-b"));
+
+        // With every key cooling, the one back soonest
+        mark_limited("http://cool-b", Duration::from_secs(120));
+        assert_eq!(
+            pick(&endpoints, PoolStrategy::RoundRobin).url,
+            "http://cool-a"
+        );
+    }
+
+    #[test]
+    fn test_least_recently_limited() {
+        let endpoints = [
+            endpoint("http://lrl-a", 1),
+            endpoint("http://lrl-b", 1),
+            endpoint("http://lrl-c", 1),
+        ];
+        mark_limited("http://lrl-a", Duration::ZERO);
+        mark_limited("http://lrl-b", Duration::ZERO);
+        // a and b are back in rotation, but c has never been limited
+        assert!(picks(&endpoints, PoolStrategy::LeastRecentlyLimited, 3)
+            .iter()
+            .all(|url| url == "http://lrl-c"));
+
+        mark_limited("http://lrl-c", Duration::ZERO);
+        assert_eq!(
+            pick(&endpoints, PoolStrategy::LeastRecentlyLimited).url,
+            "http://lrl-a"
+        );
+    }
+
+    // Answers every request with `status`, and eth_chainId with `chain_id` when it's a 200
+    fn mock_rpc(status: u16, chain_id: u64) -> (String, Arc<AtomicUsize>) {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let url = serve_http(move |request| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            match status {
+                200 => {
+                    let answer = serde_json::json!({
+                        "jsonrpc": "2.0",
+                        "id": request["id"],
+                        "result": format!("{:#x}", chain_id),
+                    });
+                    (status, answer.to_string())
+                }
+                _ => (status, "rate limited".to_string()),
+            }
+        });
+        (url, hits)
+    }
+
+    async fn chain_id(url: String) -> Result<u64, eyre::Error> {
+        let provider = ProviderBuilder::new().on_http(url.parse()?);
+        Ok(provider.get_chain_id().await?)
+    }
+
+    #[tokio::test]
+    async fn test_traffic_shifts_off_a_limited_key() {
+        let (limited, limited_hits) = mock_rpc(429, 0);
+        let (healthy, healthy_hits) = mock_rpc(200, 8453);
+        // The limited key comes first, and would get every other request
+        let endpoints = [endpoint(&limited, 1), endpoint(&healthy, 1)];
+        let pool = RpcPoolConfig {
+            strategy: PoolStrategy::RoundRobin,
+            cooldown: Duration::from_secs(60),
+        };
+
+        for _ in 0..4 {
+            let (url, chain) = with_endpoint(&endpoints, &pool, chain_id).await.unwrap();
+            assert_eq!((url.as_str(), chain), (healthy.as_str(), 8453));
+        }
+        assert_eq!(limited_hits.load(Ordering::SeqCst), 1);
+        assert_eq!(healthy_hits.load(Ordering::SeqCst), 4);
+
+        let chains = HashMap::from([(8453, endpoints.to_vec())]);
+        let mut out = String::new();
+        render(&chains, &mut out);
+        assert!(out.contains("evm_repl_rpc_key_available{chain=\"8453\",key=\"0\"} 0\n"));
+        assert!(out.contains("evm_repl_rpc_key_available{chain=\"8453\",key=\"1\"} 1\n"));
+        assert!(out.contains("evm_repl_rpc_key_rate_limited_total{chain=\"8453\",key=\"0\"} 1\n"));
+        assert!(out.contains("evm_repl_rpc_key_requests_total{chain=\"8453\",key=\"1\"} 4\n"));
+        // Key URLs are secret
+        assert!(!out.contains("127.0.0.1"));
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_when_every_key_is_limited() {
+        let (a, _) = mock_rpc(429, 0);
+        let (b, _) = mock_rpc(429, 0);
+        let endpoints = [endpoint(&a, 1), endpoint(&b, 1)];
+        let pool = RpcPoolConfig::default();
+        let err = with_endpoint(&endpoints, &pool, chain_id)
+            .await
+            .unwrap_err();
+        assert!(is_rate_limited(&err));
+    }
+}
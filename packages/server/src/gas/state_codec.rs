@@ -0,0 +1,234 @@
+//! Converting states between our dump and the `alloc` block of a genesis file, as geth's dev
+//! genesis and reth's test vectors hold them.
+
+use alloy_primitives::{Address, Bytes, B256, U256, U64};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+use super::local::{AccountDump, StateDump};
+
+/// A genesis file's `alloc`: accounts by address.
+pub type GenesisAlloc = BTreeMap<Address, GenesisAccount>;
+
+/// An account as a genesis `alloc` lists it. Numbers are hex strings and storage words are
+/// padded to 32 bytes; what's zero or empty is left out. Reading, words can be shorter and the
+/// balance decimal.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, ToSchema)]
+pub struct GenesisAccount {
+    #[serde(default)]
+    #[schema(value_type = String)]
+    pub balance: U256,
+    #[serde(default, skip_serializing_if = "U64::is_zero")]
+    #[schema(value_type = Option<String>)]
+    pub nonce: U64,
+    #[serde(default, skip_serializing_if = "Bytes::is_empty")]
+    #[schema(value_type = Option<String>)]
+    pub code: Bytes,
+    #[serde(
+        default,
+        skip_serializing_if = "BTreeMap::is_empty",
+        serialize_with = "serialize_words"
+    )]
+    #[schema(value_type = Option<BTreeMap<String, String>>)]
+    pub storage: BTreeMap<U256, U256>,
+}
+
+fn serialize_words<S: Serializer>(
+    storage: &BTreeMap<U256, U256>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(
+        storage
+            .iter()
+            .map(|(slot, value)| (B256::from(*slot), B256::from(*value))),
+    )
+}
+
+/// A state as requests send it: our dump, or a genesis `alloc`. Dumps give nonces as numbers
+/// and allocs as hex strings, which is how the two are told apart; where there are no nonces
+/// the two read the same anyway.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum EncodedState {
+    Dump(StateDump),
+    Alloc(GenesisAlloc),
+}
+
+impl EncodedState {
+    pub fn into_dump(self) -> StateDump {
+        match self {
+            EncodedState::Dump(state) => state,
+            EncodedState::Alloc(alloc) => from_alloc(alloc),
+        }
+    }
+}
+
+/// `state` as a genesis `alloc`.
+pub fn to_alloc(state: &StateDump) -> GenesisAlloc {
+    state
+        .iter()
+        .map(|(address, account)| {
+            let account = GenesisAccount {
+                balance: account.balance,
+                nonce: U64::from(account.nonce),
+                code: account.code.clone(),
+                storage: account.storage.clone(),
+            };
+            (*address, account)
+        })
+        .collect()
+}
+
+/// A genesis `alloc` as a state to run on.
+pub fn from_alloc(alloc: GenesisAlloc) -> StateDump {
+    alloc
+        .into_iter()
+        .map(|(address, account)| {
+            let account = AccountDump {
+                balance: account.balance,
+                nonce: account.nonce.to(),
+                code: account.code,
+                storage: account.storage,
+            };
+            (address, account)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::{address, bytes};
+    use serde_json::{json, Value};
+
+    const EOA: Address = address!("1804c8ab1f12e6bbf3894d4083f33e07309d1f38");
+    const CONTRACT: Address = address!("b2f9974c62815d3177079e150377915d9bc49c82");
+    const EMPTY: Address = address!("0000000000000000000000000000000000000001");
+
+    // An EOA with no code, a contract with hundreds of slots and an account with nothing at all
+    fn state() -> StateDump {
+        StateDump::from([
+            (
+                EOA,
+                AccountDump {
+                    balance: U256::from(10).pow(U256::from(18)),
+                    nonce: 7,
+                    ..Default::default()
+                },
+            ),
+            (
+                CONTRACT,
+                AccountDump {
+                    balance: U256::ZERO,
+                    nonce: 1,
+                    code: bytes!("60243610600e57600435600055005b60005460005260206000f3"),
+                    storage: (0..300u64)
+                        .map(|slot| {
+                            (
+                                U256::from(slot),
+                                U256::MAX / U256::from(300) * U256::from(slot),
+                            )
+                        })
+                        .collect(),
+                },
+            ),
+            (EMPTY, AccountDump::default()),
+        ])
+    }
+
+    #[test]
+    fn test_dump_round_trips() {
+        let state = state();
+        assert_eq!(from_alloc(to_alloc(&state)), state);
+
+        // Through JSON, as it would go to another tool and come back
+        let json = serde_json::to_string(&to_alloc(&state)).unwrap();
+        let alloc: GenesisAlloc = serde_json::from_str(&json).unwrap();
+        assert_eq!(from_alloc(alloc), state);
+    }
+
+    #[test]
+    fn test_alloc_round_trips() {
+        let alloc = json!({
+            "0x1804c8ab1f12e6bbf3894d4083f33e07309d1f38": {
+                "balance": "0xde0b6b3a7640000",
+                "nonce": "0x7",
+            },
+            "0xb2f9974c62815d3177079e150377915d9bc49c82": {
+                "balance": "0x0",
+                "nonce": "0x1",
+                "code": "0x60243610600e57600435600055005b60005460005260206000f3",
+                "storage": {
+                    "0x0000000000000000000000000000000000000000000000000000000000000000":
+                        "0x000000000000000000000000000000000000000000000000000000000000002a",
+                },
+            },
+            "0x0000000000000000000000000000000000000001": { "balance": "0x0" },
+        });
+        let state = from_alloc(serde_json::from_value(alloc.clone()).unwrap());
+        assert_eq!(state[&EOA].nonce, 7);
+        assert!(state[&EOA].code.is_empty());
+        assert_eq!(state[&CONTRACT].storage[&U256::ZERO], U256::from(42));
+        assert_eq!(serde_json::to_value(to_alloc(&state)).unwrap(), alloc);
+    }
+
+    #[test]
+    fn test_alloc_shape() {
+        let alloc = serde_json::to_value(to_alloc(&state())).unwrap();
+        let contract = &alloc["0xb2f9974c62815d3177079e150377915d9bc49c82"];
+        assert_eq!(contract["nonce"], "0x1");
+        let storage = contract["storage"].as_object().unwrap();
+        assert_eq!(storage.len(), 300);
+        assert!(storage
+            .iter()
+            .all(|(slot, value)| slot.len() == 66 && value.as_str().unwrap().len() == 66));
+
+        // Nothing but the balance for an account without nonce, code or storage
+        assert_eq!(
+            alloc["0x0000000000000000000000000000000000000001"],
+            json!({ "balance": "0x0" })
+        );
+        let eoa = alloc["0x1804c8ab1f12e6bbf3894d4083f33e07309d1f38"]
+            .as_object()
+            .unwrap();
+        assert_eq!(eoa.keys().collect::<Vec<_>>(), ["balance", "nonce"]);
+    }
+
+    #[test]
+    fn test_reads_loose_alloc() {
+        // As ethereum/tests write them: short words, a decimal balance, no 0x on the address
+        let alloc: GenesisAlloc = serde_json::from_value(json!({
+            "b2f9974c62815d3177079e150377915d9bc49c82": {
+                "balance": "1000",
+                "nonce": "0x00",
+                "storage": { "0x01": "0x02" },
+            },
+        }))
+        .unwrap();
+        let state = from_alloc(alloc);
+        assert_eq!(state[&CONTRACT].balance, U256::from(1000));
+        assert_eq!(state[&CONTRACT].nonce, 0);
+        assert_eq!(
+            state[&CONTRACT].storage,
+            BTreeMap::from([(U256::from(1), U256::from(2))])
+        );
+    }
+
+    #[test]
+    fn test_encoded_state_takes_either() {
+        let dump = serde_json::to_value(state()).unwrap();
+        let alloc = serde_json::to_value(to_alloc(&state())).unwrap();
+        for body in [dump, alloc] {
+            let state_in: EncodedState = serde_json::from_value(body).unwrap();
+            assert_eq!(state_in.into_dump(), state());
+        }
+
+        let eoa = "0x1804c8ab1f12e6bbf3894d4083f33e07309d1f38";
+        let parsed: EncodedState =
+            serde_json::from_str(&format!(r#"{{"{}": {{"nonce": "0x2"}}}}"#, eoa)).unwrap();
+        assert!(matches!(parsed, EncodedState::Alloc(_)));
+        let parsed: Value = serde_json::to_value(&parsed).unwrap();
+        assert_eq!(parsed[eoa], json!({ "balance": "0x0", "nonce": "0x2" }));
+    }
+}
@@ -0,0 +1,71 @@
+//! What a call changed, account by account.
+
+use alloy_primitives::{Address, B256, U256};
+use forge::backend::Backend;
+use revm::DatabaseRef;
+use revm_primitives::State;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+/// An account's balance and storage before and after a call. Only what changed is listed.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, ToSchema)]
+pub struct AccountDiff {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balance: Option<Change>,
+    /// By slot
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[schema(value_type = BTreeMap<String, Change>)]
+    pub storage: BTreeMap<B256, Change>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, ToSchema)]
+pub struct Change {
+    #[schema(value_type = String)]
+    pub from: U256,
+    #[schema(value_type = String)]
+    pub to: U256,
+}
+
+impl AccountDiff {
+    pub fn is_empty(&self) -> bool {
+        self.balance.is_none() && self.storage.is_empty()
+    }
+}
+
+/// The accounts in `changeset` whose balance or storage differ from `before`, the state the call
+/// ran on.
+pub(super) fn diff(
+    before: &Backend,
+    changeset: &State,
+) -> Result<BTreeMap<Address, AccountDiff>, eyre::Error> {
+    let mut accounts = BTreeMap::new();
+    for (address, account) in changeset {
+        if !account.is_touched() {
+            continue;
+        }
+        let original = before.basic_ref(*address)?.unwrap_or_default();
+        let diff = AccountDiff {
+            balance: (original.balance != account.info.balance).then_some(Change {
+                from: original.balance,
+                to: account.info.balance,
+            }),
+            storage: account
+                .storage
+                .iter()
+                .filter(|(_, slot)| slot.is_changed())
+                .map(|(key, slot)| {
+                    let change = Change {
+                        from: slot.original_value(),
+                        to: slot.present_value(),
+                    };
+                    (B256::from(*key), change)
+                })
+                .collect(),
+        };
+        if !diff.is_empty() {
+            accounts.insert(*address, diff);
+        }
+    }
+    Ok(accounts)
+}
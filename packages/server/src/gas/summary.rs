@@ -0,0 +1,182 @@
+//! A one-line verdict per call, for clients that only want to know how each went: CLIs, chat
+//! bots, CI checks. Made from the full results, so it agrees with them whatever `fields` leaves
+//! out of the results themselves.
+
+use alloy_json_abi::JsonAbi;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::execute_calldatas_fork::ExecutionResult;
+use crate::decode::decode_revert;
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CallSummary {
+    pub call_index: usize,
+    pub status: CallStatus,
+    pub gas_used: u64,
+    /// Why it reverted, in a line
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revert_reason: Option<String>,
+    /// The name of the first event it emitted that's in the request's `abi`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub primary_event: Option<String>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum CallStatus {
+    Success,
+    Revert,
+    /// Out of gas, an invalid opcode and other exceptional halts, or a raw transaction that
+    /// couldn't run at all
+    Error,
+    /// Never ran, as the request's deadline passed first
+    Skipped,
+}
+
+impl CallSummary {
+    /// `result`, the `index`th call's. Custom errors and events are named from `abi`.
+    pub fn of(index: usize, result: &ExecutionResult, abi: Option<&JsonAbi>) -> Self {
+        let status = match (result.success, result.reverted) {
+            _ if result.skipped.is_some() => CallStatus::Skipped,
+            (true, _) => CallStatus::Success,
+            (false, true) => CallStatus::Revert,
+            (false, false) => CallStatus::Error,
+        };
+        let revert_reason = result.reverted.then(|| {
+            let errors: Vec<_> = abi
+                .into_iter()
+                .flat_map(|abi| abi.errors().cloned())
+                .collect();
+            decode_revert(&result.result, &errors).message()
+        });
+        let primary_event = abi.and_then(|abi| {
+            result.logs.iter().find_map(|log| {
+                let topic = log.topics.first()?;
+                abi.events()
+                    .find(|event| !event.anonymous && event.selector() == *topic)
+                    .map(|event| event.name.clone())
+            })
+        });
+        CallSummary {
+            call_index: result.call_index.unwrap_or(index),
+            status,
+            gas_used: result.gas_used,
+            revert_reason,
+            primary_event,
+        }
+    }
+
+    /// The `index`th call, which didn't run
+    pub fn failed(index: usize) -> Self {
+        CallSummary {
+            call_index: index,
+            status: CallStatus::Error,
+            gas_used: 0,
+            revert_reason: None,
+            primary_event: None,
+        }
+    }
+}
+
+/// One summary per result, in call order.
+pub fn summarize(results: &[ExecutionResult], abi: Option<&JsonAbi>) -> Vec<CallSummary> {
+    results
+        .iter()
+        .enumerate()
+        .map(|(index, result)| CallSummary::of(index, result, abi))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gas::{EventLog, ExitReason};
+    use alloy_primitives::{b256, bytes, Bytes, Log, LogData, B256};
+    use serde_json::Value;
+    use std::collections::BTreeMap;
+
+    // keccak256("ValueChanged(uint256)")
+    const VALUE_CHANGED: B256 =
+        b256!("93fe6d397c74fdf1402a8b72e47b68512f0510d7b98a4bc4cbdf6ac7108b3c59");
+
+    fn result(exit_reason: ExitReason, output: Bytes, logs: Vec<Log>) -> ExecutionResult {
+        ExecutionResult {
+            success: exit_reason.is_success(),
+            reverted: exit_reason == ExitReason::Revert,
+            exit_reason,
+            result: output,
+            revert_reason: None,
+            gas_used: 21_000,
+            gas_limit: None,
+            logs: EventLog::from_logs(logs),
+            traces: None,
+            raw_traces: None,
+            labels: BTreeMap::new(),
+            trace_export: None,
+            flamegraph: None,
+            state_diff: None,
+            access_list: None,
+            consistency_check: None,
+            env: None,
+            hint: None,
+            journal: None,
+            transient_storage_accesses: None,
+            created: None,
+            paused_at: None,
+            internal_frames: None,
+            target_balance_before: None,
+            target_balance_after: None,
+            tx_validity: None,
+            attempts: None,
+            skipped: None,
+            call_index: None,
+            meta: None,
+        }
+    }
+
+    #[test]
+    fn test_mixed_batch_matches_golden() {
+        let abi = JsonAbi::parse([
+            "event ValueChanged(uint256 newValue)",
+            "error NotOwner(address caller)",
+        ])
+        .unwrap();
+        let changed = Log {
+            address: Default::default(),
+            data: LogData::new_unchecked(vec![VALUE_CHANGED], Bytes::new()),
+        };
+        let unnamed = Log {
+            address: Default::default(),
+            data: LogData::new_unchecked(vec![B256::repeat_byte(1)], Bytes::new()),
+        };
+        let results = [
+            result(ExitReason::Success, Bytes::new(), vec![unnamed, changed]),
+            // Error(string) with "not owner"
+            result(
+                ExitReason::Revert,
+                bytes!("08c379a0000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000000000096e6f74206f776e65720000000000000000000000000000000000000000000000"),
+                vec![],
+            ),
+            // NotOwner(0x0000...0001)
+            result(
+                ExitReason::Revert,
+                bytes!("245aecd30000000000000000000000000000000000000000000000000000000000000001"),
+                vec![],
+            ),
+            result(ExitReason::OutOfGas, Bytes::new(), vec![]),
+        ];
+        let summary = summarize(&results, Some(&abi));
+        let golden: Value = serde_json::from_str(include_str!("fixtures/summary.json")).unwrap();
+        assert_eq!(serde_json::to_value(&summary).unwrap(), golden);
+
+        // Without the ABI, nothing is named that needs it
+        let summary = summarize(&results, None);
+        assert_eq!(summary[0].primary_event, None);
+        assert_eq!(
+            summary[2].revert_reason.as_deref(),
+            Some("execution reverted")
+        );
+    }
+}
@@ -0,0 +1,305 @@
+//! Calls given as a function signature and JSON arguments instead of calldata. They're encoded
+//! once the fork is up, since an argument can be a `$sign712` template, standing for an EIP-712
+//! signature that has to be made for the chain the calls run on:
+//!
+//! ```json
+//! {"$sign712": {"domain": {...}, "types": {...}, "message": {...}, "signer": "0x..."}}
+//! ```
+//!
+//! `domain`, `types` and `message` are as `eth_signTypedData_v4` takes them, with the domain's
+//! `chainId` set to the fork's. `primaryType` can be left out when `types` has one type besides
+//! `EIP712Domain` that no other type refers to. The signer's key has to be among the request's
+//! `signers`, which are meant for test keys only: they're sent to the server in the clear.
+
+use alloy_dyn_abi::{JsonAbiExt, Specifier, TypedData};
+use alloy_json_abi::Function;
+use alloy_primitives::{Address, B256};
+use alloy_signer::SignerSync;
+use alloy_signer_wallet::LocalWallet;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::execute_calldatas_fork::Call;
+use crate::decode::from_json;
+
+/// The key in an argument that makes it a signature template.
+pub const SIGN_712: &str = "$sign712";
+
+/// A call that couldn't be encoded, at `path` within `calls[index]`.
+#[derive(Debug)]
+pub struct TemplateError {
+    pub index: usize,
+    /// e.g. `args[4]`, or `signature`
+    pub path: String,
+    pub message: String,
+}
+
+impl TemplateError {
+    pub fn field(&self) -> String {
+        format!("calls[{}].{}", self.index, self.path)
+    }
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field(), self.message)
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Sign712 {
+    domain: serde_json::Map<String, Value>,
+    types: BTreeMap<String, Vec<TypeField>>,
+    primary_type: Option<String>,
+    message: Value,
+    signer: Address,
+}
+
+#[derive(Deserialize, Serialize)]
+struct TypeField {
+    name: String,
+    #[serde(rename = "type")]
+    ty: String,
+}
+
+/// Checks each of `signers` is the key of the address it's given for. The first that isn't is
+/// returned, with why.
+pub fn check_signers(signers: &BTreeMap<Address, B256>) -> Result<(), (Address, String)> {
+    for (address, key) in signers {
+        let wallet = LocalWallet::from_bytes(key)
+            .map_err(|err| (*address, format!("isn't a private key: {}", err)))?;
+        if wallet.address() != *address {
+            return Err((
+                *address,
+                format!("is the key of {}", wallet.address().to_checksum(None)),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// `calls` with those given by `signature` and `args` encoded into `calldata`, signing for
+/// `chain_id` with `signers`. The rest are left as they are.
+pub(super) fn encode_calls(
+    calls: Vec<Call>,
+    signers: &BTreeMap<Address, B256>,
+    chain_id: u64,
+) -> Result<Vec<Call>, TemplateError> {
+    calls
+        .into_iter()
+        .enumerate()
+        .map(|(index, mut call)| {
+            if let Some(signature) = call.signature.take() {
+                let args = call.args.take().unwrap_or_default();
+                call.calldata = encode(&signature, args, signers, chain_id)
+                    .map_err(|(path, message)| TemplateError {
+                        index,
+                        path,
+                        message,
+                    })?
+                    .into();
+            }
+            Ok(call)
+        })
+        .collect()
+}
+
+// The calldata for one call, or where in it what went wrong
+fn encode(
+    signature: &str,
+    args: Vec<Value>,
+    signers: &BTreeMap<Address, B256>,
+    chain_id: u64,
+) -> Result<Vec<u8>, (String, String)> {
+    let function = Function::parse(signature).map_err(|err| {
+        (
+            "signature".to_string(),
+            format!("invalid signature: {}", err),
+        )
+    })?;
+    // Each with where it came from, as a template is three of them
+    let mut expanded = Vec::with_capacity(args.len());
+    for (i, arg) in args.into_iter().enumerate() {
+        let path = format!("args[{}]", i);
+        match arg.get(SIGN_712) {
+            Some(template) => {
+                let (v, r, s) =
+                    sign(template, signers, chain_id).map_err(|message| (path.clone(), message))?;
+                expanded.extend([(path.clone(), json!(v)), (path.clone(), json!(r))]);
+                expanded.push((path, json!(s)));
+            }
+            None => expanded.push((path, arg)),
+        }
+    }
+    if function.inputs.len() != expanded.len() {
+        return Err((
+            "args".to_string(),
+            format!(
+                "{} takes {} arguments, got {} with each signature as 3",
+                function.name,
+                function.inputs.len(),
+                expanded.len()
+            ),
+        ));
+    }
+    let values = function
+        .inputs
+        .iter()
+        .zip(&expanded)
+        .map(|(param, (path, arg))| {
+            let ty = param
+                .resolve()
+                .map_err(|err| (path.clone(), err.to_string()))?;
+            from_json(&ty, arg, path)
+                .map_err(|err| (err.path, format!("expected {}: {}", err.ty, err.message)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    function
+        .abi_encode_input(&values)
+        .map_err(|err| ("args".to_string(), err.to_string()))
+}
+
+// The v, r and s of the signature `template` asks for
+fn sign(
+    template: &Value,
+    signers: &BTreeMap<Address, B256>,
+    chain_id: u64,
+) -> Result<(u8, B256, B256), String> {
+    let template: Sign712 = serde_json::from_value(template.clone())
+        .map_err(|err| format!("{} isn't a signature template: {}", SIGN_712, err))?;
+    let key = signers.get(&template.signer).ok_or_else(|| {
+        format!(
+            "no key for signer {} among signers",
+            template.signer.to_checksum(None)
+        )
+    })?;
+    let primary_type = match template.primary_type {
+        Some(primary_type) => primary_type,
+        None => primary_type(&template.types)?,
+    };
+    let mut domain = template.domain;
+    domain.insert("chainId".to_string(), json!(format!("{:#x}", chain_id)));
+    let typed: TypedData = serde_json::from_value(json!({
+        "domain": domain,
+        "types": template.types,
+        "primaryType": primary_type,
+        "message": template.message,
+    }))
+    .map_err(|err| format!("isn't EIP-712 typed data: {}", err))?;
+    let hash = typed
+        .eip712_signing_hash()
+        .map_err(|err| format!("can't be hashed: {}", err))?;
+    let wallet = LocalWallet::from_bytes(key).map_err(|err| err.to_string())?;
+    let signature = wallet
+        .sign_hash_sync(&hash)
+        .map_err(|err| err.to_string())?;
+    Ok((
+        27 + signature.v().y_parity_byte(),
+        B256::from(signature.r()),
+        B256::from(signature.s()),
+    ))
+}
+
+// The one type that isn't the domain and isn't part of another
+fn primary_type(types: &BTreeMap<String, Vec<TypeField>>) -> Result<String, String> {
+    let referenced: Vec<&str> = types
+        .values()
+        .flatten()
+        .map(|field| {
+            field
+                .ty
+                .trim_end_matches(|c: char| c == '[' || c == ']' || c.is_ascii_digit())
+        })
+        .collect();
+    let mut candidates = types
+        .keys()
+        .filter(|name| *name != "EIP712Domain" && !referenced.contains(&name.as_str()));
+    match (candidates.next(), candidates.next()) {
+        (Some(primary_type), None) => Ok(primary_type.clone()),
+        _ => Err("needs a primaryType, as types doesn't make it clear".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::{address, b256, U256};
+
+    // anvil's first test account
+    const KEY: B256 = b256!("ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80");
+    const OWNER: Address = address!("f39Fd6e51aad88F6F4ce6aB8827279cffFb92266");
+
+    #[test]
+    fn test_primary_type() {
+        let types: BTreeMap<String, Vec<TypeField>> = serde_json::from_value(json!({
+            "EIP712Domain": [{ "name": "name", "type": "string" }],
+            "Mail": [{ "name": "from", "type": "Person" }, { "name": "cc", "type": "Person[]" }],
+            "Person": [{ "name": "wallet", "type": "address" }],
+        }))
+        .unwrap();
+        assert_eq!(primary_type(&types).unwrap(), "Mail");
+        let types = serde_json::from_value(json!({
+            "A": [{ "name": "x", "type": "uint256" }],
+            "B": [{ "name": "y", "type": "uint256" }],
+        }))
+        .unwrap();
+        assert!(primary_type(&types).is_err());
+    }
+
+    #[test]
+    fn test_check_signers() {
+        assert!(check_signers(&BTreeMap::from([(OWNER, KEY)])).is_ok());
+        let (address, message) =
+            check_signers(&BTreeMap::from([(Address::ZERO, KEY)])).unwrap_err();
+        assert_eq!(address, Address::ZERO);
+        assert!(message.contains("0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266"));
+    }
+
+    #[test]
+    fn test_encode_errors() {
+        let call = |args: Value| Call {
+            calldata: Default::default(),
+            value: U256::ZERO,
+            caller: Some(OWNER.into()),
+            independent: false,
+            signature: Some("permit(address,uint8,bytes32,bytes32)".to_string()),
+            args: Some(serde_json::from_value(args).unwrap()),
+            meta: None,
+        };
+        let template = |signer: Address| {
+            json!({ SIGN_712: {
+                "domain": { "name": "Token" },
+                "types": { "Permit": [{ "name": "owner", "type": "address" }] },
+                "message": { "owner": OWNER },
+                "signer": signer,
+            }})
+        };
+        let signers = BTreeMap::from([(OWNER, KEY)]);
+
+        let encoded =
+            encode_calls(vec![call(json!([OWNER, template(OWNER)]))], &signers, 1337).unwrap();
+        assert_eq!(encoded[0].calldata.len(), 4 + 32 * 4);
+        assert!(encoded[0].signature.is_none());
+
+        let err = encode_calls(
+            vec![call(json!([OWNER, template(Address::ZERO)]))],
+            &signers,
+            1,
+        )
+        .unwrap_err();
+        assert_eq!(err.field(), "calls[0].args[1]");
+        assert!(err.message.contains("no key"), "{}", err);
+
+        let err =
+            encode_calls(vec![call(json!(["0x12", template(OWNER)]))], &signers, 1).unwrap_err();
+        assert_eq!(err.field(), "calls[0].args[0]");
+
+        let err = encode_calls(vec![call(json!([template(OWNER)]))], &signers, 1).unwrap_err();
+        assert_eq!(err.field(), "calls[0].args");
+    }
+}
@@ -0,0 +1,486 @@
+//! Results in the shape of Tenderly's simulation responses, so scripts and dashboards built on
+//! those can read ours. What we have no value for is `null` rather than left out.
+
+use alloy_dyn_abi::EventExt;
+use alloy_json_abi::{Event, JsonAbi};
+use alloy_primitives::{Address, Bytes, LogData, B256, U256};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+use super::ens::NameOrAddress;
+use super::execute_calldatas_fork::{Call, ExecutionResult, ForkContext};
+use super::trace::{TraceKind, TraceNode, TraceStatus};
+use crate::decode::{decode_revert, to_json};
+
+/// One call, as Tenderly reports a simulated transaction.
+#[derive(Serialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct TenderlySimulation {
+    pub status: bool,
+    pub transaction: TenderlyTransaction,
+    /// Every frame, depth first. Empty with `traceMode: "none"`.
+    pub call_trace: Vec<TenderlyCall>,
+    pub logs: Vec<TenderlyLog>,
+    /// Requested with `outputFormat: "tenderly"`, so always there
+    #[schema(value_type = BTreeMap<String, TenderlyAccountDiff>)]
+    pub state_diff: BTreeMap<Address, TenderlyAccountDiff>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct TenderlyTransaction {
+    /// Always null, nothing is mined
+    #[schema(value_type = Option<String>)]
+    pub hash: Option<B256>,
+    pub block_number: u64,
+    /// The chain ID, in decimal
+    pub network_id: String,
+    /// Null when the caller was an ENS name and traces were off
+    #[schema(value_type = Option<String>)]
+    pub from: Option<Address>,
+    /// Null when the contract was given as an ENS name and traces were off
+    #[schema(value_type = Option<String>)]
+    pub to: Option<Address>,
+    #[schema(value_type = String)]
+    pub input: Bytes,
+    #[schema(value_type = String)]
+    pub value: U256,
+    /// Always null
+    pub nonce: Option<u64>,
+    /// The gas the call was given
+    pub gas: u64,
+    pub gas_used: u64,
+    /// Calls aren't charged for gas, so there's no price or fee to report: always null
+    #[schema(value_type = Option<String>)]
+    pub gas_price: Option<U256>,
+    #[schema(value_type = Option<String>)]
+    pub effective_gas_price: Option<U256>,
+    #[schema(value_type = Option<String>)]
+    pub fee: Option<U256>,
+    pub status: bool,
+    /// Why it failed: the revert message or the exit reason
+    pub error_message: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct TenderlyCall {
+    /// `CALL`, `STATICCALL`, `DELEGATECALL`, `CREATE`, ...
+    pub call_type: String,
+    #[schema(value_type = String)]
+    pub from: Address,
+    #[schema(value_type = String)]
+    pub to: Address,
+    #[schema(value_type = String)]
+    pub input: Bytes,
+    #[schema(value_type = String)]
+    pub output: Bytes,
+    #[schema(value_type = String)]
+    pub value: U256,
+    /// Only known for the top-level call
+    pub gas: Option<u64>,
+    pub gas_used: u64,
+    /// From `abi`, when the selector is in it
+    pub function_name: Option<String>,
+    pub error: Option<String>,
+    /// The frame's position in the tree: the index of each call on the way down to it, empty for
+    /// the top-level call
+    pub trace_address: Vec<usize>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct TenderlyLog {
+    /// The event's name, when it's in `abi`
+    pub name: Option<String>,
+    /// Its decoded arguments, when it's in `abi`
+    pub inputs: Option<Vec<TenderlyLogInput>>,
+    pub raw: TenderlyRawLog,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct TenderlyLogInput {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: String,
+    pub indexed: bool,
+    /// Numbers as decimal strings
+    #[schema(value_type = Object)]
+    pub value: Value,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct TenderlyRawLog {
+    #[schema(value_type = String)]
+    pub address: Address,
+    #[schema(value_type = Vec<String>)]
+    pub topics: Vec<B256>,
+    #[schema(value_type = String)]
+    pub data: Bytes,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct TenderlyAccountDiff {
+    /// Null when unchanged
+    pub balance: Option<TenderlyBalanceChange>,
+    pub storage: Vec<TenderlyStorageChange>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct TenderlyBalanceChange {
+    #[schema(value_type = String)]
+    pub original: U256,
+    #[schema(value_type = String)]
+    pub dirty: U256,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct TenderlyStorageChange {
+    #[schema(value_type = String)]
+    pub key: B256,
+    #[schema(value_type = String)]
+    pub original: B256,
+    #[schema(value_type = String)]
+    pub dirty: B256,
+}
+
+/// `result` of `call` to `to`, with functions and events named from `abi` where it has them.
+pub fn simulation(
+    context: &ForkContext,
+    to: &NameOrAddress,
+    call: &Call,
+    result: &ExecutionResult,
+    abi: Option<&JsonAbi>,
+) -> TenderlySimulation {
+    let traces = result.traces.as_deref().unwrap_or_default();
+    let from = traces.first().map(|node| node.from).or(match call.caller {
+        Some(NameOrAddress::Address(address)) => Some(address),
+        _ => None,
+    });
+    let to = traces.first().map(|node| node.to).or(to.as_address());
+    let error_message = match &result.revert_reason {
+        Some(reason) => Some(reason.message()),
+        None if !result.success => serde_json::to_value(&result.exit_reason)
+            .ok()
+            .and_then(|name| name.as_str().map(str::to_string)),
+        None => None,
+    };
+
+    let mut call_trace = Vec::new();
+    for node in traces {
+        push_frames(&mut call_trace, node, Vec::new(), abi);
+    }
+    if let (Some(top), Some(gas)) = (call_trace.first_mut(), result.gas_limit) {
+        top.gas = Some(gas);
+    }
+
+    TenderlySimulation {
+        status: result.success,
+        transaction: TenderlyTransaction {
+            hash: None,
+            block_number: context.block_number,
+            network_id: context.chain_id.to_string(),
+            from,
+            to,
+            input: call.calldata.clone(),
+            value: call.value,
+            nonce: None,
+            gas: result.gas_limit.unwrap_or(context.gas_limit),
+            gas_used: result.gas_used,
+            gas_price: None,
+            effective_gas_price: None,
+            fee: None,
+            status: result.success,
+            error_message,
+        },
+        call_trace,
+        logs: result
+            .logs
+            .iter()
+            .map(|log| named_log(log.address, &log.topics, &log.data, abi))
+            .collect(),
+        state_diff: result
+            .state_diff
+            .iter()
+            .flatten()
+            .map(|(address, diff)| {
+                let diff = TenderlyAccountDiff {
+                    balance: diff.balance.map(|change| TenderlyBalanceChange {
+                        original: change.from,
+                        dirty: change.to,
+                    }),
+                    storage: diff
+                        .storage
+                        .iter()
+                        .map(|(key, change)| TenderlyStorageChange {
+                            key: *key,
+                            original: change.from.into(),
+                            dirty: change.to.into(),
+                        })
+                        .collect(),
+                };
+                (*address, diff)
+            })
+            .collect(),
+    }
+}
+
+fn push_frames(
+    frames: &mut Vec<TenderlyCall>,
+    node: &TraceNode,
+    trace_address: Vec<usize>,
+    abi: Option<&JsonAbi>,
+) {
+    let function_name = match node.kind {
+        TraceKind::Create | TraceKind::Create2 => None,
+        _ => node.input.get(..4).and_then(|selector| {
+            abi?.functions()
+                .find(|function| function.selector() == selector)
+                .map(|function| function.name.clone())
+        }),
+    };
+    let error = match node.status {
+        TraceStatus::Success => None,
+        TraceStatus::Revert => Some(decode_revert(&node.output, &[]).message()),
+        TraceStatus::Halt => Some("execution halted".to_string()),
+    };
+    frames.push(TenderlyCall {
+        call_type: call_type(node.kind).to_string(),
+        from: node.from,
+        to: node.to,
+        input: node.input.clone(),
+        output: node.output.clone(),
+        value: node.value,
+        gas: None,
+        gas_used: node.gas_used,
+        function_name,
+        error,
+        trace_address: trace_address.clone(),
+    });
+    for (i, child) in node.children.iter().enumerate() {
+        let mut address = trace_address.clone();
+        address.push(i);
+        push_frames(frames, child, address, abi);
+    }
+}
+
+fn call_type(kind: TraceKind) -> &'static str {
+    match kind {
+        TraceKind::Call => "CALL",
+        TraceKind::StaticCall => "STATICCALL",
+        TraceKind::CallCode => "CALLCODE",
+        TraceKind::DelegateCall => "DELEGATECALL",
+        TraceKind::AuthCall => "AUTHCALL",
+        TraceKind::Create => "CREATE",
+        TraceKind::Create2 => "CREATE2",
+    }
+}
+
+fn named_log(
+    address: Address,
+    topics: &[B256],
+    data: &Bytes,
+    abi: Option<&JsonAbi>,
+) -> TenderlyLog {
+    let decoded = topics.first().and_then(|topic| {
+        let event = abi?
+            .events()
+            .find(|event| !event.anonymous && event.selector() == *topic)?;
+        Some((event, decode_inputs(event, topics, data)?))
+    });
+    TenderlyLog {
+        name: decoded.as_ref().map(|(event, _)| event.name.clone()),
+        inputs: decoded.map(|(_, inputs)| inputs),
+        raw: TenderlyRawLog {
+            address,
+            topics: topics.to_vec(),
+            data: data.clone(),
+        },
+    }
+}
+
+// None when the log doesn't decode as `event`, despite the matching selector
+fn decode_inputs(event: &Event, topics: &[B256], data: &Bytes) -> Option<Vec<TenderlyLogInput>> {
+    let decoded = event
+        .decode_log(&LogData::new_unchecked(topics.to_vec(), data.clone()), true)
+        .ok()?;
+    let mut indexed = decoded.indexed.iter();
+    let mut body = decoded.body.iter();
+    event
+        .inputs
+        .iter()
+        .map(|param| {
+            let value = if param.indexed {
+                indexed.next()
+            } else {
+                body.next()
+            }?;
+            Some(TenderlyLogInput {
+                name: param.name.clone(),
+                ty: param.ty.clone(),
+                indexed: param.indexed,
+                value: to_json(value),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::RevertReason;
+    use crate::gas::{AccountDiff, Change, EventLog, ExitReason, TraceLog};
+    use alloy_primitives::{address, b256, bytes, Log};
+
+    const CALLER: Address = address!("1000000000000000000000000000000000000000");
+    const SIMPLE_STORAGE: Address = address!("b2f9974c62815d3177079e150377915d9bc49c82");
+    // keccak256("ValueChanged(uint256)")
+    const VALUE_CHANGED: B256 =
+        b256!("93fe6d397c74fdf1402a8b72e47b68512f0510d7b98a4bc4cbdf6ac7108b3c59");
+
+    fn context() -> ForkContext {
+        ForkContext {
+            chain_id: 8453,
+            block_number: 17_000_000,
+            gas_limit: 30_000_000,
+            hardfork: "latest".to_string(),
+            confirmation_lag: 0,
+            rpc_diagnostics: None,
+        }
+    }
+
+    fn abi() -> JsonAbi {
+        JsonAbi::parse([
+            "function set(uint256 x)",
+            "function get() view returns (uint256)",
+            "event ValueChanged(uint256 newValue)",
+        ])
+        .unwrap()
+    }
+
+    // set(1) on SimpleStorage, which stores it in slot 0 and emits ValueChanged(1)
+    fn set_one() -> (Call, ExecutionResult) {
+        let input =
+            bytes!("60fe47b10000000000000000000000000000000000000000000000000000000000000001");
+        let data = bytes!("0000000000000000000000000000000000000000000000000000000000000001");
+        let call = Call {
+            calldata: input.clone(),
+            value: U256::ZERO,
+            caller: Some(CALLER.into()),
+            independent: false,
+            signature: None,
+            args: None,
+            meta: None,
+        };
+        let result = ExecutionResult {
+            exit_reason: ExitReason::Success,
+            success: true,
+            reverted: false,
+            result: Bytes::new(),
+            revert_reason: None,
+            gas_used: 43_724,
+            gas_limit: Some(30_000_000),
+            logs: EventLog::from_logs(vec![Log {
+                address: SIMPLE_STORAGE,
+                data: LogData::new_unchecked(vec![VALUE_CHANGED], data.clone()),
+            }]),
+            traces: Some(vec![TraceNode {
+                kind: TraceKind::Call,
+                from: CALLER,
+                to: SIMPLE_STORAGE,
+                value: U256::ZERO,
+                gas_used: 22_524,
+                input,
+                output: Bytes::new(),
+                status: TraceStatus::Success,
+                output_length: None,
+                revert_reason: None,
+                children: vec![],
+                logs: vec![TraceLog {
+                    address: SIMPLE_STORAGE,
+                    topics: vec![VALUE_CHANGED],
+                    data,
+                }],
+            }]),
+            raw_traces: None,
+            labels: BTreeMap::new(),
+            trace_export: None,
+            flamegraph: None,
+            state_diff: Some(BTreeMap::from([(
+                SIMPLE_STORAGE,
+                AccountDiff {
+                    balance: None,
+                    storage: BTreeMap::from([(
+                        B256::ZERO,
+                        Change {
+                            from: U256::ZERO,
+                            to: U256::from(1),
+                        },
+                    )]),
+                },
+            )])),
+            access_list: None,
+            consistency_check: None,
+            env: None,
+            hint: None,
+            journal: None,
+            transient_storage_accesses: None,
+            created: None,
+            paused_at: None,
+            internal_frames: None,
+            target_balance_before: None,
+            target_balance_after: None,
+            tx_validity: None,
+            attempts: None,
+            skipped: None,
+            call_index: None,
+            meta: None,
+        };
+        (call, result)
+    }
+
+    #[test]
+    fn test_simple_storage_matches_golden() {
+        let (call, result) = set_one();
+        let simulation = simulation(
+            &context(),
+            &SIMPLE_STORAGE.into(),
+            &call,
+            &result,
+            Some(&abi()),
+        );
+        let golden: Value =
+            serde_json::from_str(include_str!("fixtures/tenderly_simple_storage.json")).unwrap();
+        assert_eq!(serde_json::to_value(&simulation).unwrap(), golden);
+    }
+
+    #[test]
+    fn test_unknown_fields_are_null() {
+        let (call, mut result) = set_one();
+        result.success = false;
+        result.reverted = true;
+        result.exit_reason = ExitReason::Revert;
+        result.revert_reason = Some(RevertReason::Error {
+            message: "not owner".to_string(),
+        });
+        result.traces = None;
+        result.state_diff = None;
+
+        let body = serde_json::to_value(simulation(
+            &context(),
+            &SIMPLE_STORAGE.into(),
+            &call,
+            &result,
+            None,
+        ))
+        .unwrap();
+        assert_eq!(body["transaction"]["error_message"], "not owner");
+        assert_eq!(
+            body["transaction"]["from"],
+            "0x1000000000000000000000000000000000000000"
+        );
+        assert!(body["transaction"]["gas_price"].is_null());
+        assert_eq!(body["call_trace"], serde_json::json!([]));
+        assert!(body["logs"][0]["name"].is_null());
+        assert!(body["logs"][0]["inputs"].is_null());
+        assert_eq!(body["state_diff"], serde_json::json!({}));
+    }
+}
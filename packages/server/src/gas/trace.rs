@@ -0,0 +1,309 @@
+use alloy_json_abi::Error;
+use alloy_primitives::{Address, Bytes, B256, U256};
+use forge::traces::{CallKind, CallTraceArena, CallTraceNode};
+use revm::interpreter::InstructionResult;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::decode::{decode_revert, RevertReason};
+
+/// The most of a frame's output `captureSubcallOutputs` keeps, as much as a contract's code can
+/// be
+pub const MAX_FRAME_OUTPUT: usize = 24_576;
+
+/// One call frame and the calls it made. Unlike forge's arena, this shape is ours and only
+/// changes deliberately.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceNode {
+    pub kind: TraceKind,
+    #[schema(value_type = String)]
+    pub from: Address,
+    /// The callee, or the created contract
+    #[schema(value_type = String)]
+    pub to: Address,
+    #[schema(value_type = String)]
+    pub value: U256,
+    pub gas_used: u64,
+    /// Calldata, or init code for creations
+    #[schema(value_type = String)]
+    pub input: Bytes,
+    /// Return data, or the deployed code for creations
+    #[schema(value_type = String)]
+    pub output: Bytes,
+    pub status: TraceStatus,
+    /// Only with `captureSubcallOutputs`, when `output` was cut short: how long it was
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_length: Option<usize>,
+    /// Only with `captureSubcallOutputs`, for a frame that reverted: its revert data read, a
+    /// caught revert included
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revert_reason: Option<RevertReason>,
+    pub children: Vec<TraceNode>,
+    pub logs: Vec<TraceLog>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum TraceKind {
+    Call,
+    StaticCall,
+    CallCode,
+    DelegateCall,
+    AuthCall,
+    Create,
+    Create2,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum TraceStatus {
+    Success,
+    Revert,
+    /// Out of gas, an invalid opcode and other exceptional halts
+    Halt,
+}
+
+/// An event emitted by the frame it's listed under.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct TraceLog {
+    #[schema(value_type = String)]
+    pub address: Address,
+    #[schema(value_type = Vec<String>)]
+    pub topics: Vec<B256>,
+    #[schema(value_type = String)]
+    pub data: Bytes,
+}
+
+impl From<CallKind> for TraceKind {
+    fn from(kind: CallKind) -> Self {
+        match kind {
+            CallKind::Call => TraceKind::Call,
+            CallKind::StaticCall => TraceKind::StaticCall,
+            CallKind::CallCode => TraceKind::CallCode,
+            CallKind::DelegateCall => TraceKind::DelegateCall,
+            CallKind::AuthCall => TraceKind::AuthCall,
+            CallKind::Create => TraceKind::Create,
+            CallKind::Create2 => TraceKind::Create2,
+        }
+    }
+}
+
+impl From<InstructionResult> for TraceStatus {
+    fn from(status: InstructionResult) -> Self {
+        if status.is_ok() {
+            TraceStatus::Success
+        } else if status.is_revert() {
+            TraceStatus::Revert
+        } else {
+            TraceStatus::Halt
+        }
+    }
+}
+
+impl TraceNode {
+    /// The call trees in `arena`, one per top-level call.
+    pub fn from_arena(arena: &CallTraceArena) -> Vec<TraceNode> {
+        let nodes = arena.nodes();
+        nodes
+            .iter()
+            .filter(|node| node.parent.is_none())
+            .map(|node| TraceNode::build(nodes, node))
+            .collect()
+    }
+
+    fn build(nodes: &[CallTraceNode], node: &CallTraceNode) -> TraceNode {
+        let trace = &node.trace;
+        TraceNode {
+            kind: trace.kind.into(),
+            from: trace.caller,
+            to: trace.address,
+            value: trace.value,
+            gas_used: trace.gas_used,
+            input: trace.data.clone(),
+            output: trace.output.clone(),
+            status: trace.status.into(),
+            output_length: None,
+            revert_reason: None,
+            children: node
+                .children
+                .iter()
+                .map(|&child| TraceNode::build(nodes, &nodes[child]))
+                .collect(),
+            logs: node
+                .logs
+                .iter()
+                .map(|log| TraceLog {
+                    address: node.execution_address(),
+                    topics: log.raw_log.topics().to_vec(),
+                    data: log.raw_log.data.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Reads the revert data of each frame from this one down that reverted, with custom errors
+    /// from `errors`, then cuts its output to `limit` bytes.
+    pub(super) fn capture_outputs(&mut self, limit: usize, errors: &[Error]) {
+        if self.status == TraceStatus::Revert {
+            self.revert_reason = Some(decode_revert(&self.output, errors));
+        }
+        if self.output.len() > limit {
+            self.output_length = Some(self.output.len());
+            self.output = self.output.slice(..limit);
+        }
+        for child in &mut self.children {
+            child.capture_outputs(limit, errors);
+        }
+    }
+
+    /// This node and everything under it, depth first.
+    pub fn iter(&self) -> impl Iterator<Item = &TraceNode> {
+        let mut stack = vec![self];
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            stack.extend(node.children.iter().rev());
+            Some(node)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::{address, b256, bytes};
+    use serde_json::json;
+
+    fn node() -> TraceNode {
+        TraceNode {
+            kind: TraceKind::Call,
+            from: address!("1000000000000000000000000000000000000000"),
+            to: address!("2000000000000000000000000000000000000000"),
+            value: U256::from(1),
+            gas_used: 21_000,
+            input: bytes!("6d4ce63c"),
+            output: bytes!("2a"),
+            status: TraceStatus::Success,
+            output_length: None,
+            revert_reason: None,
+            children: vec![TraceNode {
+                kind: TraceKind::DelegateCall,
+                from: address!("2000000000000000000000000000000000000000"),
+                to: address!("3000000000000000000000000000000000000000"),
+                value: U256::ZERO,
+                gas_used: 100,
+                input: Bytes::new(),
+                output: Bytes::new(),
+                status: TraceStatus::Revert,
+                output_length: None,
+                revert_reason: None,
+                children: vec![],
+                logs: vec![],
+            }],
+            logs: vec![TraceLog {
+                address: address!("2000000000000000000000000000000000000000"),
+                topics: vec![b256!(
+                    "e0dca1a932506e28dc1cd7f50b0604489287b36ba09c37f13b25ee518d813528"
+                )],
+                data: bytes!("01"),
+            }],
+        }
+    }
+
+    // Pins the JSON clients see. Changing it is a breaking API change.
+    #[test]
+    fn test_json_shape() {
+        assert_eq!(
+            serde_json::to_value(node()).unwrap(),
+            json!({
+                "kind": "call",
+                "from": "0x1000000000000000000000000000000000000000",
+                "to": "0x2000000000000000000000000000000000000000",
+                "value": "0x1",
+                "gasUsed": 21000,
+                "input": "0x6d4ce63c",
+                "output": "0x2a",
+                "status": "success",
+                "children": [{
+                    "kind": "delegateCall",
+                    "from": "0x2000000000000000000000000000000000000000",
+                    "to": "0x3000000000000000000000000000000000000000",
+                    "value": "0x0",
+                    "gasUsed": 100,
+                    "input": "0x",
+                    "output": "0x",
+                    "status": "revert",
+                    "children": [],
+                    "logs": [],
+                }],
+                "logs": [{
+                    "address": "0x2000000000000000000000000000000000000000",
+                    "topics": ["0xe0dca1a932506e28dc1cd7f50b0604489287b36ba09c37f13b25ee518d813528"],
+                    "data": "0x01",
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn test_capture_outputs() {
+        let mut node = node();
+        node.output = bytes!("010203");
+        node.children[0].output =
+            bytes!("08c379a0000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000000000026869000000000000000000000000000000000000000000000000000000000000");
+        node.capture_outputs(2, &[]);
+
+        assert_eq!(node.output, bytes!("0102"));
+        assert_eq!(node.output_length, Some(3));
+        assert_eq!(node.revert_reason, None);
+        // Read before it was cut
+        let child = &node.children[0];
+        assert_eq!(
+            child.revert_reason,
+            Some(RevertReason::Error {
+                message: "hi".to_string()
+            })
+        );
+        assert_eq!(child.output_length, Some(100));
+        let body = serde_json::to_value(child).unwrap();
+        assert_eq!(body["outputLength"], 100);
+        assert_eq!(body["revertReason"]["kind"], "error");
+    }
+
+    #[test]
+    fn test_kind_and_status_names() {
+        let kinds = [
+            (CallKind::Call, "call"),
+            (CallKind::StaticCall, "staticCall"),
+            (CallKind::CallCode, "callCode"),
+            (CallKind::DelegateCall, "delegateCall"),
+            (CallKind::AuthCall, "authCall"),
+            (CallKind::Create, "create"),
+            (CallKind::Create2, "create2"),
+        ];
+        for (kind, name) in kinds {
+            assert_eq!(serde_json::to_value(TraceKind::from(kind)).unwrap(), name);
+        }
+
+        let statuses = [
+            (InstructionResult::Stop, "success"),
+            (InstructionResult::Return, "success"),
+            (InstructionResult::Revert, "revert"),
+            (InstructionResult::OutOfGas, "halt"),
+            (InstructionResult::InvalidFEOpcode, "halt"),
+        ];
+        for (status, name) in statuses {
+            assert_eq!(
+                serde_json::to_value(TraceStatus::from(status)).unwrap(),
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_iter_is_depth_first() {
+        let node = node();
+        let kinds: Vec<_> = node.iter().map(|node| node.kind).collect();
+        assert_eq!(kinds, [TraceKind::Call, TraceKind::DelegateCall]);
+    }
+}
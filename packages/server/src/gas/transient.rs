@@ -0,0 +1,220 @@
+//! What a call did with EIP-1153 transient storage, which is gone once the transaction ends and
+//! so never shows up in a state diff. Read off the opcodes the `debug` trace mode records, as the
+//! journal is.
+
+use alloy_primitives::{Address, U256};
+use forge::traces::{CallTraceArena, CallTraceNode};
+use revm::interpreter::opcode;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+use super::journal::stack;
+
+/// The most accesses a call's list keeps. The rest are dropped.
+pub const MAX_TRANSIENT_ACCESSES: usize = 10_000;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TransientAccess {
+    /// How many opcodes the call had run, in every frame, before this one, counted as the
+    /// journal counts them
+    pub step: usize,
+    /// The frame it happened in, the call itself being 0
+    pub depth: usize,
+    /// Whose transient storage it was
+    #[schema(value_type = String)]
+    pub address: Address,
+    pub pc: usize,
+    pub kind: TransientAccessKind,
+    #[schema(value_type = String)]
+    pub slot: U256,
+    /// What was written, or read. Missing for a read the call didn't get past.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub value: Option<U256>,
+    /// A read, in a frame nested in the one that wrote the slot, of the nonzero value it wrote:
+    /// a reentrancy lock finding itself locked
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub reentrancy_lock: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum TransientAccessKind {
+    /// TLOAD
+    Read,
+    /// TSTORE
+    Write,
+}
+
+/// Every TLOAD and TSTORE of the call in `arena`, in the order they ran, at most `limit`.
+pub(super) fn transient_accesses(arena: &CallTraceArena, limit: usize) -> Vec<TransientAccess> {
+    let nodes = arena.nodes();
+    let mut walker = Walker {
+        nodes,
+        step: 0,
+        frames: Vec::new(),
+        written: HashMap::new(),
+        accesses: Vec::new(),
+    };
+    for root in nodes.iter().filter(|node| node.parent.is_none()) {
+        walker.enter(root);
+    }
+    walker.accesses.truncate(limit);
+    walker.accesses
+}
+
+struct Walker<'a> {
+    nodes: &'a [CallTraceNode],
+    // Opcodes run so far
+    step: usize,
+    // The frames entered and not yet left, outermost first
+    frames: Vec<usize>,
+    // The last value written to each account's slot, and the frame that wrote it
+    written: HashMap<(Address, U256), (U256, usize)>,
+    accesses: Vec<TransientAccess>,
+}
+
+impl Walker<'_> {
+    fn enter(&mut self, node: &CallTraceNode) {
+        self.frames.push(node.idx);
+        let trace = &node.trace;
+        let nodes = self.nodes;
+        let mut children = node.children.iter().map(|&child| &nodes[child]);
+        for (i, step) in trace.steps.iter().enumerate() {
+            let index = self.step;
+            self.step += 1;
+            let access = match step.op.get() {
+                opcode::TSTORE => stack(step, 0).map(|slot| {
+                    let value = stack(step, 1);
+                    let written = value.unwrap_or_default();
+                    self.written
+                        .insert((trace.address, slot), (written, node.idx));
+                    (TransientAccessKind::Write, slot, value, false)
+                }),
+                opcode::TLOAD => stack(step, 0).map(|slot| {
+                    // What it pushed is on top of the stack at the next step
+                    let value = trace.steps.get(i + 1).and_then(|next| stack(next, 0));
+                    let locked =
+                        self.written
+                            .get(&(trace.address, slot))
+                            .is_some_and(|(written, frame)| {
+                                !written.is_zero()
+                                    && *frame != node.idx
+                                    && self.frames.contains(frame)
+                            });
+                    let lock = locked && value.is_some_and(|value| !value.is_zero());
+                    (TransientAccessKind::Read, slot, value, lock)
+                }),
+                opcode::CALL
+                | opcode::CALLCODE
+                | opcode::DELEGATECALL
+                | opcode::STATICCALL
+                | opcode::CREATE
+                | opcode::CREATE2 => {
+                    // None when the call failed before it started, e.g. too deep
+                    if let Some(child) = children.next() {
+                        self.enter(child);
+                    }
+                    None
+                }
+                _ => None,
+            };
+            if let Some((kind, slot, value, reentrancy_lock)) = access {
+                self.accesses.push(TransientAccess {
+                    step: index,
+                    depth: trace.depth,
+                    address: trace.address,
+                    pc: step.pc,
+                    kind,
+                    slot,
+                    value,
+                    reentrancy_lock,
+                });
+            }
+        }
+        self.frames.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gas::execute_calldatas_fork::DEFAULT_DEPLOYER;
+    use crate::gas::{
+        transact_local, AccountDump, ExecutionOptions, ExecutionResult, ExitReason, StateDump,
+    };
+    use alloy_primitives::{address, Bytes};
+
+    const GUARDED: Address = address!("00000000000000000000000000000000000c0de5");
+
+    // Locks transient slot 0, reverting if it's already locked, and with no calldata calls
+    // itself once before unlocking, which the lock turns away
+    const REENTRANT: &str = "0x5f5c156009575f5ffd5b60015f5d36601c575f5f60015f5f305af1505b5f5f5d00";
+
+    fn call(hardfork: Option<&str>) -> ExecutionResult {
+        let state = StateDump::from([(
+            GUARDED,
+            AccountDump {
+                code: REENTRANT.parse().unwrap(),
+                ..Default::default()
+            },
+        )]);
+        let options = ExecutionOptions {
+            transient_storage: true,
+            hardfork: hardfork.map(str::to_string),
+            ..Default::default()
+        };
+        let (result, _) = transact_local(
+            state,
+            GUARDED,
+            Bytes::new(),
+            U256::ZERO,
+            DEFAULT_DEPLOYER,
+            Some(options),
+        )
+        .unwrap();
+        result
+    }
+
+    #[test]
+    fn test_reentrancy_lock() {
+        let result = call(None);
+        assert!(result.success);
+        let accesses = result.transient_storage_accesses.unwrap();
+        let seen: Vec<_> = accesses
+            .iter()
+            .map(|access| {
+                (
+                    access.depth,
+                    access.kind,
+                    access.value.map(|v| v.to::<u64>()),
+                )
+            })
+            .collect();
+        assert_eq!(
+            seen,
+            [
+                (0, TransientAccessKind::Read, Some(0)),
+                (0, TransientAccessKind::Write, Some(1)),
+                (1, TransientAccessKind::Read, Some(1)),
+                (0, TransientAccessKind::Write, Some(0)),
+            ]
+        );
+        let locks: Vec<_> = accesses
+            .iter()
+            .map(|access| access.reentrancy_lock)
+            .collect();
+        assert_eq!(locks, [false, false, true, false]);
+        assert!(accesses.iter().all(|access| access.address == GUARDED));
+    }
+
+    #[test]
+    fn test_before_cancun_hints_instead() {
+        let result = call(Some("shanghai"));
+        assert_eq!(result.exit_reason, ExitReason::InvalidOpcode);
+        assert_eq!(result.transient_storage_accesses, None);
+        assert!(result.hint.unwrap().starts_with("TLOAD (0x5c)"));
+    }
+}
@@ -0,0 +1,105 @@
+//! What a call run here gets away with that a transaction sent to the chain wouldn't. Calls run
+//! without paying for gas, from any address, at whatever nonce the sender has, so one that
+//! succeeds here can still be rejected by a node. These checks say which of the node's would
+//! fail, from the sender as it was before the call and what the call used.
+
+use alloy_primitives::U256;
+use forge::executors::RawCallResult;
+use revm_primitives::{AccountInfo, TransactTo, KECCAK_EMPTY, MAX_INITCODE_SIZE};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::raw_transaction::SignedTransaction;
+
+/// The checks a node makes before including a transaction that the call would fail.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TxValidity {
+    /// None of them failed
+    pub valid: bool,
+    pub failures: Vec<TxCheckFailure>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TxCheckFailure {
+    pub check: TxCheck,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum TxCheck {
+    /// The sender can't pay for the gas and value. For calls, the gas is what the call used at
+    /// the block's basefee; for signed transactions, their gas limit at their gas price, which
+    /// is what a node asks for up front.
+    InsufficientFunds,
+    /// The sender has code, which EIP-3607 doesn't let send transactions
+    SenderIsContract,
+    /// A signed transaction's nonce isn't the sender's
+    NonceMismatch,
+    /// Deployment code over EIP-3860's limit
+    InitCodeTooLarge,
+}
+
+/// Checks `r`, sent by `sender` as it was before it ran. `signed` is the transaction it came
+/// from, when it was one.
+pub(super) fn check(
+    sender: &AccountInfo,
+    r: &RawCallResult,
+    signed: Option<&SignedTransaction>,
+) -> TxValidity {
+    let tx = &r.env.tx;
+    let from = tx.caller.to_checksum(None);
+    let mut failures = Vec::new();
+
+    let (gas, price) = match signed {
+        Some(signed) => (signed.gas_limit, U256::from(signed.gas_price)),
+        None => (r.gas_used, r.env.block.basefee),
+    };
+    let cost = U256::from(gas)
+        .saturating_mul(price)
+        .saturating_add(tx.value);
+    if sender.balance < cost {
+        failures.push(TxCheckFailure {
+            check: TxCheck::InsufficientFunds,
+            message: format!(
+                "{} has {} wei but needs {} for {} gas at {} wei and {} wei of value",
+                from, sender.balance, cost, gas, price, tx.value
+            ),
+        });
+    }
+    if sender.code_hash != KECCAK_EMPTY && !sender.code_hash.is_zero() {
+        failures.push(TxCheckFailure {
+            check: TxCheck::SenderIsContract,
+            message: format!(
+                "{} has code, so it can't send transactions (EIP-3607)",
+                from
+            ),
+        });
+    }
+    if let Some(signed) = signed.filter(|signed| signed.nonce != sender.nonce) {
+        failures.push(TxCheckFailure {
+            check: TxCheck::NonceMismatch,
+            message: format!(
+                "signed with nonce {}, but {} is at {}",
+                signed.nonce, from, sender.nonce
+            ),
+        });
+    }
+    if tx.transact_to == TransactTo::Create && tx.data.len() > MAX_INITCODE_SIZE {
+        failures.push(TxCheckFailure {
+            check: TxCheck::InitCodeTooLarge,
+            message: format!(
+                "{} bytes of init code, over the limit of {}",
+                tx.data.len(),
+                MAX_INITCODE_SIZE
+            ),
+        });
+    }
+
+    TxValidity {
+        valid: failures.is_empty(),
+        failures,
+    }
+}
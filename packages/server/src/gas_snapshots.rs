@@ -0,0 +1,301 @@
+//! Gas snapshots: the gas each scenario of an execution used, kept under a name so a later run can
+//! be compared with it, as `forge snapshot --diff` compares with `.gas-snapshot`. Snapshots go in
+//! the result store, so they last as long as persisted results do.
+
+use rocket::http::Status;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use utoipa::ToSchema;
+
+use crate::error::ApiError;
+use crate::results::{Results, StoredResult};
+
+/// What a snapshot's name can be: it's part of a key in the result store.
+pub const MAX_NAME_LENGTH: usize = 64;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GasSnapshot {
+    pub name: String,
+    /// The persisted execution it was taken from
+    pub result_id: String,
+    /// Gas used by scenario: the call's label in the request's `scenarioLabels`, or `calls[i]`
+    pub scenarios: BTreeMap<String, u64>,
+}
+
+/// How one scenario's gas moved between two snapshots.
+#[derive(Serialize, Clone, Debug, PartialEq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ScenarioDiff {
+    pub scenario: String,
+    /// In the snapshot diffed. Null when only the other one has the scenario.
+    pub gas_used: Option<u64>,
+    /// In the snapshot it's diffed against. Null when it doesn't have the scenario.
+    pub against: Option<u64>,
+    /// `gasUsed` less `against`, when both have the scenario
+    pub delta: Option<i64>,
+    /// `delta` as a percentage of `against`, when both have the scenario and `against` isn't 0
+    pub percent: Option<f64>,
+    /// Whether the gas went up by more than the threshold
+    pub regression: bool,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GasSnapshotDiff {
+    pub name: String,
+    pub against: String,
+    /// Percent the gas can go up by before a scenario counts as a regression
+    pub threshold: f64,
+    /// Every scenario either snapshot has, by label
+    pub scenarios: Vec<ScenarioDiff>,
+    /// How many scenarios regressed
+    pub regressions: usize,
+}
+
+impl GasSnapshot {
+    /// The snapshot of a persisted `/execute_calldatas_fork` response, its results labelled by
+    /// the request's `scenarioLabels` when the request was kept with it.
+    pub fn from_stored(name: String, stored: &StoredResult) -> Result<Self, ApiError> {
+        let not_results = || {
+            ApiError::new(
+                Status::UnprocessableEntity,
+                "NOT_GAS_RESULTS",
+                format!(
+                    "result {} isn't a list of execution results with gasUsed",
+                    stored.id
+                ),
+            )
+        };
+        let labels = stored
+            .request
+            .as_ref()
+            .and_then(|request| request["scenarioLabels"].as_array());
+        // With `summary`, the results are under `results`
+        let response = stored.response.get("results").unwrap_or(&stored.response);
+        let results = response.as_array().ok_or_else(not_results)?;
+        let scenarios = results
+            .iter()
+            .enumerate()
+            .map(|(i, result)| {
+                let label = labels
+                    .and_then(|labels| labels.get(i))
+                    .and_then(Value::as_str)
+                    .map_or_else(|| format!("calls[{}]", i), str::to_string);
+                let gas_used = result["gasUsed"].as_u64().ok_or_else(not_results)?;
+                Ok((label, gas_used))
+            })
+            .collect::<Result<_, ApiError>>()?;
+        Ok(GasSnapshot {
+            name,
+            result_id: stored.id.clone(),
+            scenarios,
+        })
+    }
+
+    pub fn save(&self, results: &Results) -> Result<(), ApiError> {
+        results.save_as(key(&self.name), self, None)?;
+        Ok(())
+    }
+
+    pub fn load(results: &Results, name: &str) -> Result<Self, ApiError> {
+        let not_found = || {
+            ApiError::new(
+                Status::NotFound,
+                "GAS_SNAPSHOT_NOT_FOUND",
+                format!("no gas snapshot named {}, it may have expired", name),
+            )
+        };
+        if check_name(name).is_err() {
+            return Err(not_found());
+        }
+        let stored = results.load_key(&key(name))?.ok_or_else(not_found)?;
+        serde_json::from_value(stored.response).map_err(|err| {
+            ApiError::new(
+                Status::InternalServerError,
+                "RESULT_STORE_FAILED",
+                err.to_string(),
+            )
+        })
+    }
+}
+
+/// `snapshot` compared with `against`, gas going up by more than `threshold` percent counting as
+/// a regression.
+pub fn diff(snapshot: &GasSnapshot, against: &GasSnapshot, threshold: f64) -> GasSnapshotDiff {
+    let labels: BTreeSet<_> = snapshot
+        .scenarios
+        .keys()
+        .chain(against.scenarios.keys())
+        .collect();
+    let scenarios: Vec<_> = labels
+        .into_iter()
+        .map(|label| {
+            let gas_used = snapshot.scenarios.get(label).copied();
+            let before = against.scenarios.get(label).copied();
+            let delta = gas_used
+                .zip(before)
+                .map(|(gas_used, before)| gas_used as i64 - before as i64);
+            let percent = delta
+                .zip(before.filter(|before| *before > 0))
+                .map(|(delta, before)| delta as f64 * 100.0 / before as f64);
+            let regression = match (delta, percent) {
+                (_, Some(percent)) => percent > threshold,
+                // Up from nothing
+                (Some(delta), None) => delta > 0,
+                (None, _) => false,
+            };
+            ScenarioDiff {
+                scenario: label.clone(),
+                gas_used,
+                against: before,
+                delta,
+                percent,
+                regression,
+            }
+        })
+        .collect();
+    GasSnapshotDiff {
+        name: snapshot.name.clone(),
+        against: against.name.clone(),
+        threshold,
+        regressions: scenarios
+            .iter()
+            .filter(|scenario| scenario.regression)
+            .count(),
+        scenarios,
+    }
+}
+
+/// Names are letters, digits, `-` and `_`, up to `MAX_NAME_LENGTH`.
+pub fn check_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    if !valid {
+        return Err(format!(
+            "must be 1 to {} letters, digits, - or _",
+            MAX_NAME_LENGTH
+        ));
+    }
+    Ok(())
+}
+
+// Results are stored under hex ids, which never have a `-`
+fn key(name: &str) -> String {
+    format!("gas-snapshot-{}", name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Limits;
+    use crate::results::MemoryStore;
+    use serde_json::json;
+
+    fn snapshot(name: &str, scenarios: &[(&str, u64)]) -> GasSnapshot {
+        GasSnapshot {
+            name: name.to_string(),
+            result_id: "00".to_string(),
+            scenarios: scenarios
+                .iter()
+                .map(|(label, gas)| (label.to_string(), *gas))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_store() {
+        let results = Results::new(MemoryStore::default(), &Limits::default());
+        let id = results
+            .save(
+                &json!([{ "gasUsed": 43_000 }, { "gasUsed": 23_000 }]),
+                Some(json!({ "scenarioLabels": ["set", "get"] })),
+            )
+            .unwrap();
+        let stored = results.load(&id).unwrap();
+
+        let taken = GasSnapshot::from_stored("main".to_string(), &stored).unwrap();
+        assert_eq!(taken, {
+            let mut expected = snapshot("main", &[("set", 43_000), ("get", 23_000)]);
+            expected.result_id = id;
+            expected
+        });
+        taken.save(&results).unwrap();
+        assert_eq!(GasSnapshot::load(&results, "main").unwrap(), taken);
+
+        let err = GasSnapshot::load(&results, "other").err().unwrap();
+        assert_eq!(err.code, "GAS_SNAPSHOT_NOT_FOUND");
+        let err = GasSnapshot::load(&results, "../main").err().unwrap();
+        assert_eq!(err.code, "GAS_SNAPSHOT_NOT_FOUND");
+
+        // Without the request, calls are labelled by position
+        let id = results.save(&json!([{ "gasUsed": 1 }]), None).unwrap();
+        let stored = results.load(&id).unwrap();
+        let taken = GasSnapshot::from_stored("unlabelled".to_string(), &stored).unwrap();
+        assert_eq!(taken.scenarios["calls[0]"], 1);
+
+        // A Tenderly or cast response has no gasUsed at the top
+        let id = results.save(&json!([{ "call": "0x" }]), None).unwrap();
+        let stored = results.load(&id).unwrap();
+        let err = GasSnapshot::from_stored("cast".to_string(), &stored)
+            .err()
+            .unwrap();
+        assert_eq!(err.code, "NOT_GAS_RESULTS");
+    }
+
+    #[test]
+    fn test_diff_with_regression() {
+        let before = snapshot("before", &[("get", 23_000), ("set", 43_000)]);
+        let after = snapshot("after", &[("get", 22_000), ("set", 44_000)]);
+
+        let diff = diff(&after, &before, 2.0);
+        assert_eq!(diff.regressions, 0);
+        let set = &diff.scenarios[1];
+        assert_eq!(set.scenario, "set");
+        assert_eq!(set.delta, Some(1_000));
+        assert!((set.percent.unwrap() - 2.3256).abs() < 0.001);
+
+        let diff = self::diff(&after, &before, 1.0);
+        assert_eq!(diff.regressions, 1);
+        assert!(diff.scenarios[1].regression);
+        // Cheaper never regresses
+        assert_eq!(diff.scenarios[0].delta, Some(-1_000));
+        assert!(!diff.scenarios[0].regression);
+    }
+
+    #[test]
+    fn test_diff_with_missing_scenario() {
+        let before = snapshot("before", &[("get", 23_000), ("transfer", 51_000)]);
+        let after = snapshot("after", &[("get", 23_000), ("set", 43_000)]);
+
+        let diff = diff(&after, &before, 1.0);
+        let scenarios: Vec<_> = diff
+            .scenarios
+            .iter()
+            .map(|scenario| {
+                (
+                    scenario.scenario.as_str(),
+                    scenario.gas_used,
+                    scenario.against,
+                )
+            })
+            .collect();
+        assert_eq!(
+            scenarios,
+            [
+                ("get", Some(23_000), Some(23_000)),
+                ("set", Some(43_000), None),
+                ("transfer", None, Some(51_000)),
+            ]
+        );
+        // Only in one of them, so nothing to compare
+        assert!(diff.scenarios[1..]
+            .iter()
+            .all(|scenario| scenario.delta.is_none() && !scenario.regression));
+        assert_eq!(diff.regressions, 0);
+    }
+}
@@ -0,0 +1,35 @@
+//! Ids for what a request leaves stored, like a state or a persisted result.
+
+use alloy_primitives::hex;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// 128 random bits, as hex.
+pub fn random() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+/// The first 128 bits of the SHA-256 of `request` as JSON, as hex, so sending the same
+/// `deterministic` request again gets the same ids back.
+pub fn derived(request: &impl Serialize) -> String {
+    // Serializing a request that was just deserialized can't fail
+    let json = serde_json::to_vec(request).unwrap_or_default();
+    hex::encode(&Sha256::digest(json)[..16])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_ids() {
+        assert_eq!(random().len(), 32);
+        assert_ne!(random(), random());
+
+        let request = json!({ "to": "0x01", "deterministic": true });
+        assert_eq!(derived(&request), derived(&request));
+        assert_eq!(derived(&request).len(), 32);
+        assert_ne!(derived(&request), derived(&json!({ "to": "0x02" })));
+    }
+}
@@ -0,0 +1,475 @@
+use alloy_primitives::Bytes;
+use rocket::http::Status;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tracing::Instrument;
+use url::Url;
+use utoipa::ToSchema;
+
+use crate::admission::{Gate, Gates};
+use crate::config::{AppConfig, Limits};
+use crate::error::ApiError;
+use crate::gas::ens::NameOrAddress;
+use crate::gas::{
+    execute_calldatas_fork_with, ExecutionOptions, ExecutionResult, ForkCall, ForkProgress,
+    ResolvedFork,
+};
+use crate::shutdown::InFlight;
+use crate::webhooks::Webhooks;
+
+/// A fork execution to run in the background.
+pub struct ExecuteJob {
+    pub bytecode: Bytes,
+    pub address: NameOrAddress,
+    pub calls: Vec<ForkCall>,
+    pub fork: ResolvedFork,
+    pub options: Option<ExecutionOptions>,
+    /// How long after the job starts running calls that haven't started are skipped. Without
+    /// it every call runs.
+    pub deadline: Option<Duration>,
+    /// Where to POST the job once it's done or has failed
+    pub callback: Option<Url>,
+    /// Who submitted it, as `ExecuteKey` has it: the API key, or for a demo request the client's
+    /// IP. Only requests from the same owner can see or cancel it.
+    pub owner: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+#[serde(tag = "status", rename_all = "camelCase")]
+pub enum JobState {
+    Queued,
+    Running { completed: usize, total: usize },
+    Done { results: Vec<ExecutionResult> },
+    Failed { error: ApiError },
+    Cancelled,
+}
+
+impl JobState {
+    fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            JobState::Done { .. } | JobState::Failed { .. } | JobState::Cancelled
+        )
+    }
+}
+
+struct Job {
+    state: JobState,
+    finished_at: Option<Instant>,
+    owner: Option<String>,
+}
+
+type Jobs = Arc<Mutex<HashMap<String, Job>>>;
+
+/// Executions that outlive the request that started them. At most `max_job_concurrency` run at
+/// once and at most `max_queued_jobs` wait; finished jobs are dropped `job_ttl` after they end.
+/// A running job also holds a slot in the fork gate, so jobs and requests share its bound.
+pub struct JobQueue {
+    jobs: Jobs,
+    permits: Arc<Semaphore>,
+    fork_gate: Gate,
+    max_queued: usize,
+    ttl: Duration,
+    in_flight: InFlight,
+    webhooks: Option<Webhooks>,
+}
+
+impl JobQueue {
+    pub fn new(limits: &Limits, in_flight: InFlight, gates: &Gates) -> Self {
+        JobQueue {
+            jobs: Arc::default(),
+            permits: Arc::new(Semaphore::new(limits.max_job_concurrency.max(1))),
+            fork_gate: gates.fork.clone(),
+            max_queued: limits.max_queued_jobs,
+            ttl: limits.job_ttl,
+            in_flight,
+            webhooks: None,
+        }
+    }
+
+    /// Lets jobs ask for a callback when they finish.
+    pub fn with_webhooks(mut self, webhooks: Webhooks) -> Self {
+        self.webhooks = Some(webhooks);
+        self
+    }
+
+    /// Checks a job's `callbackUrl` before it's submitted.
+    pub async fn check_callback(&self, url: &str) -> Result<Url, ApiError> {
+        match &self.webhooks {
+            Some(webhooks) => webhooks.check_url(url).await,
+            None => Err(ApiError::new(
+                Status::UnprocessableEntity,
+                "CALLBACKS_DISABLED",
+                "this server isn't configured to send job callbacks",
+            )
+            .with_details(serde_json::json!({ "field": "callbackUrl" }))),
+        }
+    }
+
+    /// Queues the job and returns its id. Must be called from within a tokio runtime.
+    pub fn submit(&self, config: AppConfig, job: ExecuteJob) -> Result<String, ApiError> {
+        let id = format!("{:032x}", rand::random::<u128>());
+        {
+            let mut jobs = self.lock();
+            let queued = jobs
+                .values()
+                .filter(|job| matches!(job.state, JobState::Queued))
+                .count();
+            if queued >= self.max_queued {
+                return Err(ApiError::new(
+                    Status::ServiceUnavailable,
+                    "QUEUE_FULL",
+                    format!("{} jobs are already queued", queued),
+                ));
+            }
+            jobs.insert(
+                id.clone(),
+                Job {
+                    state: JobState::Queued,
+                    finished_at: None,
+                    owner: job.owner.clone(),
+                },
+            );
+        }
+
+        let jobs = self.jobs.clone();
+        let permits = self.permits.clone();
+        let fork_gate = self.fork_gate.clone();
+        let job_id = id.clone();
+        let in_flight = self.in_flight.clone();
+        let callback = job
+            .callback
+            .clone()
+            .and_then(|url| Some((url, self.webhooks.clone()?)));
+        let task = tokio::spawn(
+            async move {
+                let Ok(_permit) = permits.acquire_owned().await else {
+                    return;
+                };
+                // Waits its turn behind requests rather than being turned away like them
+                let Some(_slot) = fork_gate.wait().await else {
+                    return;
+                };
+                // Only counts as in-flight once it starts, so shutdown doesn't wait on the queue
+                let work = match in_flight.begin() {
+                    Ok(work) => work,
+                    Err(error) => {
+                        update(&jobs, &job_id, |_| Some(JobState::Failed { error }));
+                        return;
+                    }
+                };
+                let total = job.calls.len();
+                // A job cancelled while it waited is left as is
+                if !update(&jobs, &job_id, |state| {
+                    matches!(state, JobState::Queued).then_some(JobState::Running {
+                        completed: 0,
+                        total,
+                    })
+                }) {
+                    return;
+                }
+
+                let outcome = execute_calldatas_fork_with(
+                    &config,
+                    job.bytecode,
+                    job.address,
+                    job.calls,
+                    job.fork,
+                    ExecutionOptions::with_deadline(
+                        job.options,
+                        job.deadline.map(|after| Instant::now() + after),
+                    ),
+                    |progress| {
+                        if let ForkProgress::Result(index, _) = progress {
+                            update(&jobs, &job_id, |_| {
+                                Some(JobState::Running {
+                                    completed: index + 1,
+                                    total,
+                                })
+                            });
+                        }
+                        Ok(())
+                    },
+                )
+                .await;
+
+                let finished = match outcome {
+                    Ok((_, _, results)) => JobState::Done { results },
+                    Err(err) => JobState::Failed {
+                        error: ApiError::from_execution(err),
+                    },
+                };
+                update(&jobs, &job_id, |_| Some(finished.clone()));
+
+                // On its own task, so retrying doesn't hold up the next job. It takes the job's
+                // place in flight, so shutdown waits for the callback too.
+                if let Some((url, webhooks)) = callback {
+                    let delivery = tokio::spawn(
+                        async move {
+                            let _work = work;
+                            webhooks.deliver(&url, &job_id, &finished).await
+                        }
+                        .in_current_span(),
+                    );
+                    in_flight.abort_on_shutdown(delivery.abort_handle());
+                }
+            }
+            .in_current_span(),
+        );
+        self.in_flight.abort_on_shutdown(task.abort_handle());
+
+        Ok(id)
+    }
+
+    /// The state of the job `owner` submitted as `id`. Another key's job isn't there at all, so
+    /// its ids can't be told apart from ones that don't exist.
+    pub fn get(&self, id: &str, owner: Option<&str>) -> Option<JobState> {
+        self.lock()
+            .get(id)
+            .filter(|job| job.owner.as_deref() == owner)
+            .map(|job| job.state.clone())
+    }
+
+    /// Cancels a job `owner` submitted that hasn't started yet.
+    pub fn cancel(&self, id: &str, owner: Option<&str>) -> Result<JobState, ApiError> {
+        let mut jobs = self.lock();
+        let job = jobs
+            .get_mut(id)
+            .filter(|job| job.owner.as_deref() == owner)
+            .ok_or_else(|| not_found(id))?;
+        if !matches!(job.state, JobState::Queued) {
+            return Err(ApiError::new(
+                Status::Conflict,
+                "JOB_NOT_CANCELLABLE",
+                format!("job {} has already started", id),
+            ));
+        }
+        job.state = JobState::Cancelled;
+        job.finished_at = Some(Instant::now());
+        Ok(job.state.clone())
+    }
+
+    // Expired jobs are dropped lazily, whenever the table is touched
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Job>> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let ttl = self.ttl;
+        jobs.retain(|_, job| job.finished_at.map_or(true, |at| at.elapsed() < ttl));
+        jobs
+    }
+}
+
+pub fn not_found(id: &str) -> ApiError {
+    ApiError::new(
+        Status::NotFound,
+        "JOB_NOT_FOUND",
+        format!("no job with id {}", id),
+    )
+}
+
+// Replaces a job's state with whatever `next` returns, if anything. Returns whether it did.
+fn update(jobs: &Jobs, id: &str, next: impl FnOnce(&JobState) -> Option<JobState>) -> bool {
+    let mut jobs = jobs.lock().unwrap();
+    let Some(job) = jobs.get_mut(id) else {
+        return false;
+    };
+    match next(&job.state) {
+        Some(state) => {
+            if state.is_finished() {
+                job.finished_at = Some(Instant::now());
+            }
+            job.state = state;
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gas::ForkConfig;
+    use alloy_primitives::Address;
+    use std::str::FromStr;
+
+    fn job() -> ExecuteJob {
+        let caller = Address::from_str("0x1000000000000000000000000000000000000000").unwrap();
+        ExecuteJob {
+            // get() on a contract that returns storage slot 0
+            bytecode: Bytes::from_str("0x60005460005260206000f3").unwrap(),
+            address: "0xb2f9974c62815d3177079e150377915d9bc49c82"
+                .parse()
+                .unwrap(),
+            calls: vec![ForkCall {
+                calldata: Bytes::from_str("0x6d4ce63c").unwrap(),
+                value: Default::default(),
+                caller: Some(caller.into()),
+                independent: false,
+                signature: None,
+                args: None,
+                meta: None,
+            }],
+            fork: ForkConfig::default()
+                .resolve(&AppConfig::from_env().unwrap())
+                .unwrap(),
+            options: None,
+            deadline: None,
+            callback: None,
+            owner: None,
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_job_runs_to_completion() {
+        let config = AppConfig::from_env().unwrap();
+        let queue = JobQueue::new(
+            &config.limits,
+            InFlight::default(),
+            &Gates::new(&config.limits),
+        );
+        let id = queue.submit(config, job()).unwrap();
+
+        let state = loop {
+            match queue.get(&id, None).unwrap() {
+                state if state.is_finished() => break state,
+                _ => tokio::time::sleep(Duration::from_millis(50)).await,
+            }
+        };
+        match state {
+            JobState::Done { results } => assert_eq!(results.len(), 1),
+            other => panic!("expected the job to succeed, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancel_queued_job() {
+        let queue = JobQueue::new(
+            &Limits::default(),
+            InFlight::default(),
+            &Gates::new(&Limits::default()),
+        );
+        // Hold every permit so the job stays queued
+        let _permits = queue
+            .permits
+            .clone()
+            .acquire_many_owned(Limits::default().max_job_concurrency as u32)
+            .await
+            .unwrap();
+
+        let id = queue.submit(AppConfig::default(), job()).unwrap();
+        assert!(matches!(queue.get(&id, None), Some(JobState::Queued)));
+        assert!(matches!(queue.cancel(&id, None), Ok(JobState::Cancelled)));
+        assert_eq!(
+            queue.cancel(&id, None).unwrap_err().code,
+            "JOB_NOT_CANCELLABLE"
+        );
+        assert_eq!(
+            queue.cancel("nope", None).unwrap_err().code,
+            "JOB_NOT_FOUND"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_jobs_belong_to_their_key() {
+        let queue = JobQueue::new(
+            &Limits::default(),
+            InFlight::default(),
+            &Gates::new(&Limits::default()),
+        );
+        let _permits = queue
+            .permits
+            .clone()
+            .acquire_many_owned(Limits::default().max_job_concurrency as u32)
+            .await
+            .unwrap();
+
+        let owned = ExecuteJob {
+            owner: Some("key-1".to_string()),
+            ..job()
+        };
+        let id = queue.submit(AppConfig::default(), owned).unwrap();
+        assert!(queue.get(&id, Some("key-2")).is_none());
+        assert!(queue.get(&id, None).is_none());
+        assert_eq!(
+            queue.cancel(&id, Some("key-2")).unwrap_err().code,
+            "JOB_NOT_FOUND"
+        );
+        assert!(matches!(
+            queue.get(&id, Some("key-1")),
+            Some(JobState::Queued)
+        ));
+        assert!(matches!(
+            queue.cancel(&id, Some("key-1")),
+            Ok(JobState::Cancelled)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_jobs_wait_for_the_fork_gate() {
+        let limits = Limits::default();
+        let gates = Gates::new(&limits);
+        let queue = JobQueue::new(&limits, InFlight::default(), &gates);
+        let mut held = Vec::new();
+        for _ in 0..limits.max_concurrent_forks {
+            held.push(gates.fork.enter().await.unwrap());
+        }
+
+        let id = queue.submit(AppConfig::default(), job()).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(matches!(queue.get(&id, None), Some(JobState::Queued)));
+        assert_eq!(
+            queue.permits.available_permits(),
+            limits.max_job_concurrency - 1
+        );
+        queue.cancel(&id, None).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_callbacks_need_webhooks() {
+        let queue = JobQueue::new(
+            &Limits::default(),
+            InFlight::default(),
+            &Gates::new(&Limits::default()),
+        );
+        let err = queue
+            .check_callback("https://example.com/hook")
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "CALLBACKS_DISABLED");
+
+        let config = AppConfig {
+            webhook_secret: Some("shh".to_string()),
+            ..AppConfig::default()
+        };
+        let queue = queue.with_webhooks(Webhooks::new(&config).unwrap());
+        let err = queue
+            .check_callback("http://127.0.0.1/hook")
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "INVALID_CALLBACK_URL");
+    }
+
+    #[tokio::test]
+    async fn test_finished_jobs_expire() {
+        let limits = Limits {
+            job_ttl: Duration::from_millis(20),
+            ..Limits::default()
+        };
+        let queue = JobQueue::new(&limits, InFlight::default(), &Gates::new(&limits));
+        let _permits = queue
+            .permits
+            .clone()
+            .acquire_many_owned(limits.max_job_concurrency as u32)
+            .await
+            .unwrap();
+
+        let id = queue.submit(AppConfig::default(), job()).unwrap();
+        queue.cancel(&id, None).unwrap();
+        assert!(queue.get(&id, None).is_some());
+
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        assert!(queue.get(&id, None).is_none());
+    }
+}
@@ -0,0 +1,336 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::ContentType;
+use rocket::{Data, Request, Response};
+use serde_json::{json, Value};
+use std::io::Cursor;
+use tracing::warn;
+
+/// Rewrites a `/v1` response body into the shape the unprefixed route used to return.
+pub type Adapter = fn(&mut Value);
+
+/// Serves the unprefixed routes as deprecated aliases of `/v1`. Every response on them says so
+/// and points at its successor, and routes whose `/v1` shape has changed since get their JSON
+/// converted back by the adapter registered under the handler's name.
+///
+/// Forge's trace arena, which results had as `traces` before `/v1`, only comes back when it's
+/// asked for, so requests to the unprefixed routes are marked for `Checked` to ask for it.
+#[derive(Default)]
+pub struct Legacy {
+    adapters: Vec<(&'static str, Adapter)>,
+}
+
+impl Legacy {
+    /// With the adapters for every route whose `/v1` shape has changed.
+    pub fn with_adapters() -> Self {
+        Legacy::default()
+            .adapt("deploy_route", to_legacy)
+            .adapt("transact_route", to_legacy)
+            .adapt("execute_calldatas_fork_route", |body| {
+                to_legacy(results_of(body))
+            })
+            .adapt("run_route", |body| to_legacy(results_of(body)))
+            .adapt("execute_batch_route", |body| {
+                for scenario in body.as_array_mut().into_iter().flatten() {
+                    to_legacy(results_of(scenario));
+                }
+            })
+    }
+
+    pub fn adapt(mut self, route: &'static str, adapter: Adapter) -> Self {
+        self.adapters.push((route, adapter));
+        self
+    }
+}
+
+// Set on requests to the unprefixed routes
+struct Unprefixed(bool);
+
+/// Whether `req` came in on an unprefixed route, with `Legacy` attached to convert its response
+/// back.
+pub(crate) fn requested(req: &Request<'_>) -> bool {
+    req.local_cache(|| Unprefixed(false)).0
+}
+
+/// Sets `includeRawTraces` in `options` when it's an object, so results have the arena the legacy
+/// `traces` is.
+pub fn ask_for_raw_traces(options: &mut Value) {
+    if let Some(options) = options.as_object_mut() {
+        options.insert("includeRawTraces".to_string(), json!(true));
+    }
+}
+
+// Fields results have only on /v1: `success`, which came with their own exit reasons, which call
+// they're of, the environment they ran in, the balance of the account called before and after,
+// and the checks a node would make of the call as a transaction. `meta` stays, since only a
+// client that sent it gets it back.
+const V1_FIELDS: [&str; 6] = [
+    "success",
+    "callIndex",
+    "env",
+    "targetBalanceBefore",
+    "targetBalanceAfter",
+    "txValidity",
+];
+
+// A body's results: what's under its `results`, or the body itself
+fn results_of(body: &mut Value) -> &mut Value {
+    if body.get("results").is_some() {
+        return &mut body["results"];
+    }
+    body
+}
+
+// A result, or a list of them, as results were before /v1: without `V1_FIELDS`, with revm's exit
+// reasons, logs without their index and forge's trace arena as `traces`
+fn to_legacy(body: &mut Value) {
+    match body {
+        Value::Array(results) => results.iter_mut().for_each(to_legacy),
+        Value::Object(result) => {
+            for field in V1_FIELDS {
+                result.remove(field);
+            }
+            if let Some(reason) = result.get("exitReason").and_then(Value::as_str) {
+                let output = result.get("result").and_then(Value::as_str);
+                let reason = revm_exit_reason(reason, output.unwrap_or("0x")).to_string();
+                result.insert("exitReason".to_string(), json!(reason));
+            }
+            let logs = result.get_mut("logs").and_then(Value::as_array_mut);
+            for log in logs.into_iter().flatten() {
+                if let Some(log) = log.as_object_mut() {
+                    log.remove("logIndex");
+                }
+            }
+            // Empty, as it was, when nothing was traced
+            let arena = result
+                .remove("rawTraces")
+                .unwrap_or_else(|| json!({ "arena": [] }));
+            result.insert("traces".to_string(), arena);
+        }
+        _ => {}
+    }
+}
+
+// revm's name for one of our exit reasons. A success is a STOP or a RETURN, told apart by whether
+// it returned anything. Reasons revm has no name for, and those under revm's names already, are
+// left as they are.
+fn revm_exit_reason<'a>(reason: &'a str, output: &str) -> &'a str {
+    match reason {
+        "success" if output == "0x" => "Stop",
+        "success" => "Return",
+        "revert" => "Revert",
+        "outOfGas" => "OutOfGas",
+        "invalidOpcode" => "OpcodeNotFound",
+        "invalidJump" => "InvalidJump",
+        "stackUnderflow" => "StackUnderflow",
+        "stackOverflow" => "StackOverflow",
+        "staticCallViolation" => "StateChangeDuringStaticCall",
+        "callTooDeep" => "CallTooDeep",
+        "outOfFunds" => "OutOfFunds",
+        "outOfOffset" => "OutOfOffset",
+        "createCollision" => "CreateCollision",
+        "contractSizeLimit" => "CreateContractSizeLimit",
+        "invalidContractPrefix" => "CreateContractStartingWithEF",
+        "nonceOverflow" => "NonceOverflow",
+        "overflowPayment" => "OverflowPayment",
+        "precompileError" => "PrecompileError",
+        "invalidEof" => "InvalidEOFInitCode",
+        "fatalError" => "FatalExternalError",
+        other => other,
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Legacy {
+    fn info(&self) -> Info {
+        Info {
+            name: "Legacy route adapter",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+        // Without adapters, nothing would turn the arena back into `traces`
+        if !self.adapters.is_empty() && !req.uri().path().starts_with("/v1/") {
+            req.local_cache(|| Unprefixed(true));
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let path = req.uri().path().as_str();
+        let Some(route) = req.route() else {
+            return;
+        };
+        if path.starts_with("/v1/") {
+            return;
+        }
+        res.set_raw_header("Deprecation", "true");
+        res.set_raw_header("Link", format!("</v1{}>; rel=\"successor-version\"", path));
+
+        let Some(adapter) = self
+            .adapters
+            .iter()
+            .find(|(name, _)| route.name.as_deref() == Some(*name))
+            .map(|(_, adapter)| adapter)
+        else {
+            return;
+        };
+        if res.content_type() != Some(ContentType::JSON) {
+            return;
+        }
+        let body = match res.body_mut().to_bytes().await {
+            Ok(body) => body,
+            Err(err) => {
+                warn!(%err, "couldn't read response body for the legacy adapter");
+                return;
+            }
+        };
+        let body = match serde_json::from_slice::<Value>(&body) {
+            Ok(mut value) => {
+                adapter(&mut value);
+                serde_json::to_vec(&value).unwrap_or(body)
+            }
+            Err(_) => body,
+        };
+        res.set_sized_body(body.len(), Cursor::new(body));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Auth;
+    use crate::config::AppConfig;
+    use crate::gas::DEFAULT_DEPLOYER;
+    use crate::results::{MemoryStore, Results};
+    use crate::routes::{deploy_route, transact_route};
+    use crate::shutdown::InFlight;
+    use crate::snapshots::Snapshots;
+    use rocket::http::Status;
+    use rocket::local::blocking::Client;
+    use rocket::routes;
+
+    // Stores calldata[4..36] in slot 0 when given an argument, otherwise returns slot 0
+    const CREATION: &str = "0x601a80600b6000396000f3\
+        60243610600e57600435600055005b60005460005260206000f3";
+
+    fn client(legacy: Legacy) -> Client {
+        let config = AppConfig::default();
+        let mut rocket = rocket::build()
+            .manage(Auth::new(&config))
+            .manage(InFlight::default())
+            .manage(Snapshots::new(&config.limits))
+            .manage(Results::new(MemoryStore::default(), &config.limits))
+            .manage(config)
+            .attach(legacy)
+            .register("/", crate::error::catchers());
+        for (base, _) in crate::routes::versions() {
+            rocket = rocket.mount(base, routes![deploy_route, transact_route]);
+        }
+        Client::tracked(rocket).unwrap()
+    }
+
+    fn deploy(client: &Client, uri: &'static str) -> (Option<String>, Value) {
+        let response = client
+            .post(uri)
+            .header(ContentType::JSON)
+            .body(json!({ "bytecode": CREATION }).to_string())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let deprecation = response.headers().get_one("Deprecation").map(String::from);
+        let mut body: Value = response.into_json().unwrap();
+        // Random per response
+        body["stateId"] = Value::Null;
+        (deprecation, body)
+    }
+
+    #[test]
+    fn test_both_paths_serve() {
+        let client = client(Legacy::default());
+        let (deprecation, current) = deploy(&client, "/v1/deploy");
+        assert_eq!(deprecation, None);
+        let (deprecation, legacy) = deploy(&client, "/deploy");
+        assert_eq!(deprecation.as_deref(), Some("true"));
+        assert_eq!(legacy, current);
+
+        let response = client
+            .post("/deploy")
+            .header(ContentType::JSON)
+            .body(json!({ "bytecode": CREATION }).to_string())
+            .dispatch();
+        assert_eq!(
+            response.headers().get_one("Link"),
+            Some("</v1/deploy>; rel=\"successor-version\"")
+        );
+    }
+
+    // The shape /deploy returned before /v1 existed, before results had their own exit reasons,
+    // logs and trace tree. Gas and state are as /v1 has them, which the adapter doesn't touch.
+    #[test]
+    fn test_legacy_matches_snapshot() {
+        let client = client(Legacy::with_adapters());
+        let (_, current) = deploy(&client, "/v1/deploy");
+        let (_, mut body) = deploy(&client, "/deploy");
+
+        // Forge's, whose shape is whatever forge's is
+        let arena = body["traces"]["arena"].as_array();
+        assert!(arena.is_some_and(|arena| !arena.is_empty()), "{}", body);
+        body["traces"] = Value::Null;
+        assert_eq!(
+            body,
+            json!({
+                "address": DEFAULT_DEPLOYER.create(0),
+                "exitReason": "Return",
+                "reverted": false,
+                "result": "0x60243610600e57600435600055005b60005460005260206000f3",
+                "gasUsed": current["gasUsed"],
+                "logs": [],
+                "traces": null,
+                "state": current["state"],
+                "stateId": null,
+            })
+        );
+    }
+
+    #[test]
+    fn test_results_to_legacy() {
+        let mut body = json!([{
+            "callIndex": 0,
+            "meta": { "row": 3 },
+            "exitReason": "revert",
+            "success": false,
+            "result": "0x",
+            "targetBalanceBefore": "0x0",
+            "targetBalanceAfter": "0xde0b6b3a7640000",
+            "txValidity": { "valid": true },
+            "logs": [{ "address": "0x01", "topics": [], "data": "0x", "logIndex": 0 }],
+            "traces": [],
+            "rawTraces": { "arena": [{ "idx": 0 }] },
+        }]);
+        to_legacy(&mut body);
+        assert_eq!(
+            body,
+            json!([{
+                "meta": { "row": 3 },
+                "exitReason": "Revert",
+                "result": "0x",
+                "logs": [{ "address": "0x01", "topics": [], "data": "0x" }],
+                "traces": { "arena": [{ "idx": 0 }] },
+            }])
+        );
+
+        let mut body = json!({ "results": [{ "exitReason": "success", "result": "0x" }] });
+        to_legacy(results_of(&mut body));
+        assert_eq!(body["results"][0]["exitReason"], "Stop");
+        assert_eq!(body["results"][0]["traces"], json!({ "arena": [] }));
+    }
+
+    #[test]
+    fn test_adapter_rewrites_legacy_only() {
+        let legacy = Legacy::default().adapt("deploy_route", |body| {
+            body["exitReason"] = json!("Stop");
+        });
+        let client = client(legacy);
+        assert_eq!(deploy(&client, "/v1/deploy").1["exitReason"], "success");
+        assert_eq!(deploy(&client, "/deploy").1["exitReason"], "Stop");
+    }
+}
@@ -1,3 +1,26 @@
+pub mod admission;
+pub mod auth;
+pub mod caches;
 pub mod compile;
+pub mod compression;
+pub mod config;
+pub mod cors;
+pub mod decode;
+pub mod demo;
+pub mod error;
+pub mod fields;
+pub mod format;
 pub mod gas;
+pub mod gas_snapshots;
+pub mod ids;
+pub mod jobs;
+pub mod legacy;
+pub mod results;
 pub mod routes;
+pub mod schema;
+pub mod sessions;
+pub mod shutdown;
+pub mod snapshots;
+pub mod storage;
+pub mod telemetry;
+pub mod webhooks;
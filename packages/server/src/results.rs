@@ -0,0 +1,408 @@
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Responder};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::config::Limits;
+use crate::error::ApiError;
+use crate::ids;
+use crate::telemetry;
+
+/// How often expired results are swept.
+pub const GC_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Somewhere to keep persisted results. Records are opaque bytes keyed by id.
+pub trait ResultStore: Send + Sync {
+    fn put(&self, id: &str, record: &[u8]) -> Result<(), eyre::Error>;
+    fn get(&self, id: &str) -> Result<Option<Vec<u8>>, eyre::Error>;
+    /// Deletes records stored before `cutoff`, returning how many went.
+    fn delete_before(&self, cutoff: SystemTime) -> Result<usize, eyre::Error>;
+}
+
+/// One JSON file per result in `dir`.
+pub struct FsStore {
+    dir: PathBuf,
+}
+
+impl FsStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self, eyre::Error> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)
+            .map_err(|err| eyre::eyre!("can't create {}: {}", dir.display(), err))?;
+        Ok(FsStore { dir })
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+}
+
+impl ResultStore for FsStore {
+    fn put(&self, id: &str, record: &[u8]) -> Result<(), eyre::Error> {
+        Ok(fs::write(self.path(id), record)?)
+    }
+
+    fn get(&self, id: &str) -> Result<Option<Vec<u8>>, eyre::Error> {
+        match fs::read(self.path(id)) {
+            Ok(record) => Ok(Some(record)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn delete_before(&self, cutoff: SystemTime) -> Result<usize, eyre::Error> {
+        let mut deleted = 0;
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if entry.metadata()?.modified()? <= cutoff {
+                fs::remove_file(entry.path())?;
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+}
+
+/// Keeps records in memory, for tests and deployments that don't want them on disk.
+#[derive(Default)]
+pub struct MemoryStore {
+    records: Mutex<HashMap<String, (SystemTime, Vec<u8>)>>,
+}
+
+impl ResultStore for MemoryStore {
+    fn put(&self, id: &str, record: &[u8]) -> Result<(), eyre::Error> {
+        let mut records = self.records.lock().unwrap();
+        records.insert(id.to_string(), (SystemTime::now(), record.to_vec()));
+        Ok(())
+    }
+
+    fn get(&self, id: &str) -> Result<Option<Vec<u8>>, eyre::Error> {
+        let records = self.records.lock().unwrap();
+        Ok(records.get(id).map(|(_, record)| record.clone()))
+    }
+
+    fn delete_before(&self, cutoff: SystemTime) -> Result<usize, eyre::Error> {
+        let mut records = self.records.lock().unwrap();
+        let before = records.len();
+        records.retain(|_, (stored_at, _)| *stored_at > cutoff);
+        Ok(before - records.len())
+    }
+}
+
+/// A persisted execution, as returned by `/results/<id>`.
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredResult {
+    pub id: String,
+    /// Unix seconds
+    pub created_at: u64,
+    /// Only when the request asked for it to be kept too
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub request: Option<Value>,
+    #[schema(value_type = Object)]
+    pub response: Value,
+}
+
+/// Whether a request's response is kept, and the request with it. Flattened into the execution
+/// requests.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Persistence {
+    /// Keep the response so it can be fetched from `/results/<id>`. The id comes back in the
+    /// `X-Result-Id` header.
+    #[serde(default)]
+    pub persist: bool,
+    /// Keep the request alongside it, with `forkConfig.rpcUrl` cut down to its host and without
+    /// `signers`
+    #[serde(default)]
+    pub persist_request: bool,
+}
+
+impl Persistence {
+    /// What of `request` to keep with the response, when the request asked for it to be kept.
+    pub fn request(&self, request: &impl Serialize) -> Option<Value> {
+        (self.persist && self.persist_request).then(|| stored_request(request))
+    }
+}
+
+/// `request` as it's kept, and as ids derived from it are hashed: without what could carry a
+/// credential. `forkConfig.rpcUrl` keeps only its scheme and host, and `signers` is left out.
+pub fn stored_request(request: &impl Serialize) -> Value {
+    // Serializing a request that was just deserialized can't fail
+    let mut stored = serde_json::to_value(request).unwrap_or_default();
+    if let Some(fields) = stored.as_object_mut() {
+        fields.remove("signers");
+    }
+    let url = stored
+        .get_mut("forkConfig")
+        .and_then(|fork| fork.get_mut("rpcUrl"));
+    if let Some(url) = url {
+        if let Some(redacted) = url.as_str().map(telemetry::redact_url) {
+            *url = Value::String(redacted);
+        }
+    }
+    stored
+}
+
+/// Execution results kept so they can be shared by id. Records over `max_bytes` are refused and
+/// records older than `ttl` are gone, whether or not they've been swept yet.
+#[derive(Clone)]
+pub struct Results {
+    store: Arc<dyn ResultStore>,
+    max_bytes: usize,
+    ttl: Duration,
+}
+
+impl Results {
+    pub fn new(store: impl ResultStore + 'static, limits: &Limits) -> Self {
+        Results {
+            store: Arc::new(store),
+            max_bytes: limits.max_result_bytes,
+            ttl: limits.result_ttl,
+        }
+    }
+
+    /// Stores the response, and the request if given, returning the new id.
+    pub fn save(
+        &self,
+        response: &impl Serialize,
+        request: Option<Value>,
+    ) -> Result<String, ApiError> {
+        self.save_as(ids::random(), response, request)
+    }
+
+    /// Saves `response` when `persistence` asks for it, under `id` when there's one, returning
+    /// the id it was saved under. `request` is what `Persistence::request` kept of the request.
+    pub fn keep(
+        &self,
+        persistence: Persistence,
+        id: Option<String>,
+        response: &impl Serialize,
+        request: Option<Value>,
+    ) -> Result<Option<String>, ApiError> {
+        if !persistence.persist {
+            return Ok(None);
+        }
+        match id {
+            Some(id) => self.save_as(id, response, request),
+            None => self.save(response, request),
+        }
+        .map(Some)
+    }
+
+    /// Same as `save`, under `id`, replacing any record already there.
+    pub fn save_as(
+        &self,
+        id: String,
+        response: &impl Serialize,
+        request: Option<Value>,
+    ) -> Result<String, ApiError> {
+        let record = StoredResult {
+            id: id.clone(),
+            created_at: now_secs(),
+            request,
+            response: serde_json::to_value(response).map_err(storage_error)?,
+        };
+        let record = serde_json::to_vec(&record).map_err(storage_error)?;
+        if record.len() > self.max_bytes {
+            return Err(ApiError::new(
+                Status::PayloadTooLarge,
+                "RESULT_TOO_LARGE",
+                format!(
+                    "the result is {} bytes, over the {} that can be persisted",
+                    record.len(),
+                    self.max_bytes
+                ),
+            ));
+        }
+        self.store.put(&id, &record).map_err(storage_error)?;
+        Ok(id)
+    }
+
+    pub fn load(&self, id: &str) -> Result<StoredResult, ApiError> {
+        let not_found = || {
+            ApiError::new(
+                Status::NotFound,
+                "RESULT_NOT_FOUND",
+                format!("no result with id {}, it may have expired", id),
+            )
+        };
+        // Ids are generated hex, anything else would be a path
+        if id.is_empty() || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(not_found());
+        }
+        self.load_key(id)?.ok_or_else(not_found)
+    }
+
+    /// What `save_as` stored under `key`, a key of the caller's rather than a generated id. The
+    /// caller makes sure it's safe as a file name. None when there's nothing there or it expired.
+    pub fn load_key(&self, key: &str) -> Result<Option<StoredResult>, ApiError> {
+        let Some(record) = self.store.get(key).map_err(storage_error)? else {
+            return Ok(None);
+        };
+        let record: StoredResult = serde_json::from_slice(&record).map_err(storage_error)?;
+        if now_secs().saturating_sub(record.created_at) >= self.ttl.as_secs() {
+            return Ok(None);
+        }
+        Ok(Some(record))
+    }
+
+    /// Deletes expired records.
+    pub fn collect_garbage(&self) -> Result<usize, eyre::Error> {
+        let cutoff = SystemTime::now()
+            .checked_sub(self.ttl)
+            .unwrap_or(UNIX_EPOCH);
+        self.store.delete_before(cutoff)
+    }
+
+    /// Sweeps every `GC_INTERVAL` until the task is dropped.
+    pub async fn collect_garbage_forever(self) {
+        let mut interval = tokio::time::interval(GC_INTERVAL);
+        loop {
+            interval.tick().await;
+            match self.collect_garbage() {
+                Ok(0) => {}
+                Ok(deleted) => info!(deleted, "expired stored results"),
+                Err(err) => warn!(error = %err, "failed to expire stored results"),
+            }
+        }
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn storage_error(err: impl std::fmt::Display) -> ApiError {
+    ApiError::new(
+        Status::InternalServerError,
+        "RESULT_STORE_FAILED",
+        err.to_string(),
+    )
+}
+
+/// A response sent with the id it was persisted under, if it was, in `X-Result-Id`.
+pub struct Persisted<R> {
+    pub response: R,
+    pub result_id: Option<String>,
+}
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for Persisted<R> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'o> {
+        let mut response = self.response.respond_to(req)?;
+        if let Some(id) = self.result_id {
+            response.set_raw_header("X-Result-Id", id);
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn results(dir: &tempfile::TempDir, limits: Limits) -> Results {
+        Results::new(FsStore::new(dir.path()).unwrap(), &limits)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let results = results(&dir, Limits::default());
+        let id = results
+            .save(&json!([{ "gasUsed": 21000 }]), Some(json!({ "calls": [] })))
+            .unwrap();
+
+        let stored = results.load(&id).unwrap();
+        assert_eq!(stored.id, id);
+        assert_eq!(stored.response, json!([{ "gasUsed": 21000 }]));
+        assert_eq!(stored.request, Some(json!({ "calls": [] })));
+        assert_eq!(results.load("0123").err().unwrap().code, "RESULT_NOT_FOUND");
+        assert_eq!(results.load("../x").err().unwrap().code, "RESULT_NOT_FOUND");
+    }
+
+    #[test]
+    fn test_stored_request_has_no_credentials() {
+        let persistence = Persistence {
+            persist: true,
+            persist_request: true,
+        };
+        let stored = persistence
+            .request(&json!({
+                "calls": [],
+                "forkConfig": { "rpcUrl": "https://rpc.example/v2/KEY", "chainId": 8453 },
+                "signers": { "0x01": "0x02" },
+            }))
+            .unwrap();
+        assert_eq!(
+            stored,
+            json!({
+                "calls": [],
+                "forkConfig": { "rpcUrl": "https://rpc.example/***", "chainId": 8453 },
+            })
+        );
+
+        // Nothing to redact, and nothing kept unless asked for
+        assert_eq!(
+            stored_request(&json!({ "calls": [] })),
+            json!({ "calls": [] })
+        );
+        assert_eq!(
+            stored_request(&json!({ "forkConfig": null })),
+            json!({ "forkConfig": null })
+        );
+        let persistence = Persistence {
+            persist: true,
+            persist_request: false,
+        };
+        assert_eq!(persistence.request(&json!({})), None);
+    }
+
+    #[test]
+    fn test_expiry() {
+        let dir = tempfile::tempdir().unwrap();
+        let results = results(
+            &dir,
+            Limits {
+                result_ttl: Duration::ZERO,
+                ..Default::default()
+            },
+        );
+        let id = results.save(&json!({}), None).unwrap();
+        assert_eq!(results.load(&id).err().unwrap().code, "RESULT_NOT_FOUND");
+
+        assert_eq!(results.collect_garbage().unwrap(), 1);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let results = results(
+            &dir,
+            Limits {
+                max_result_bytes: 16,
+                ..Default::default()
+            },
+        );
+        let err = results
+            .save(&json!({ "result": "0x".repeat(16) }), None)
+            .err()
+            .unwrap();
+        assert_eq!(err.code, "RESULT_TOO_LARGE");
+    }
+}
@@ -0,0 +1,583 @@
+use crate::auth::ApiKey;
+use crate::decode::{decode_revert, from_json, to_json, RevertReason};
+use crate::error::ApiError;
+use crate::gas::hardfork::without_metadata;
+use alloy_dyn_abi::{DynSolValue, JsonAbiExt, Specifier};
+use alloy_json_abi::{Error, Function, JsonAbi};
+use alloy_primitives::{hex, Bytes};
+use rocket::{http::Status, post, serde::json::Json};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EncodeRequest {
+    /// e.g. `transfer(address,uint256)`
+    pub signature: Option<String>,
+    /// A JSON ABI function fragment, as an alternative to `signature`
+    #[schema(value_type = Option<Object>)]
+    pub function: Option<Function>,
+    pub args: Vec<Value>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EncodeResponse {
+    #[schema(value_type = String)]
+    pub calldata: Bytes,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DecodeRequest {
+    #[schema(value_type = String)]
+    pub calldata: Bytes,
+    /// The function is found by selector among these and `signatures`
+    #[schema(value_type = Option<Vec<Object>>)]
+    pub abi: Option<JsonAbi>,
+    pub signatures: Option<Vec<String>>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DecodeResponse {
+    pub name: String,
+    pub signature: String,
+    pub args: Vec<Value>,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DecodeRevertRequest {
+    /// Revert data as hex, on its own or as another tool printed it, e.g.
+    /// `execution reverted: 0x4e487b71...`
+    pub data: String,
+    /// Custom errors are looked for among these and `signatures`
+    #[schema(value_type = Option<Vec<Object>>)]
+    pub abi: Option<JsonAbi>,
+    /// e.g. `InsufficientBalance(address,uint256)`
+    pub signatures: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DecodeConstructorRequest {
+    /// The code a contract was created with, arguments and all, as explorers show it
+    #[schema(value_type = String)]
+    pub creation_code: Bytes,
+    /// The constructor is taken from this
+    #[schema(value_type = Vec<Object>)]
+    pub abi: JsonAbi,
+    /// The compiled creation code, without arguments. Without it, the arguments are taken to
+    /// start after the last metadata solc appended.
+    #[schema(value_type = Option<String>)]
+    pub bytecode: Option<Bytes>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DecodeConstructorResponse {
+    /// e.g. `constructor(uint256,address)`
+    pub signature: String,
+    pub args: Vec<Value>,
+    /// The encoded arguments, as they followed the code
+    #[schema(value_type = String)]
+    pub raw_args: Bytes,
+}
+
+#[utoipa::path(
+    post,
+    path = "/abi/encode",
+    tag = "tools",
+    request_body = EncodeRequest,
+    responses(
+        (status = 200, description = "ABI-encoded calldata", body = EncodeResponse),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("apiKey" = []))
+)]
+#[post("/abi/encode", format = "json", data = "<req>")]
+pub fn abi_encode_route(
+    _key: ApiKey,
+    req: Json<EncodeRequest>,
+) -> Result<Json<EncodeResponse>, ApiError> {
+    encode(req.into_inner()).map(Json)
+}
+
+#[utoipa::path(
+    post,
+    path = "/abi/decode",
+    tag = "tools",
+    request_body = DecodeRequest,
+    responses(
+        (status = 200, description = "The matching function and its arguments", body = DecodeResponse),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("apiKey" = []))
+)]
+#[post("/abi/decode", format = "json", data = "<req>")]
+pub fn abi_decode_route(
+    _key: ApiKey,
+    req: Json<DecodeRequest>,
+) -> Result<Json<DecodeResponse>, ApiError> {
+    decode(req.into_inner()).map(Json)
+}
+
+#[utoipa::path(
+    post,
+    path = "/decode/revert",
+    tag = "tools",
+    request_body = DecodeRevertRequest,
+    responses(
+        (status = 200, description = "The best reading of the revert data", body = RevertReason),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("apiKey" = []))
+)]
+#[post("/decode/revert", format = "json", data = "<req>")]
+pub fn decode_revert_route(
+    _key: ApiKey,
+    req: Json<DecodeRevertRequest>,
+) -> Result<Json<RevertReason>, ApiError> {
+    let req = req.into_inner();
+    let data = parse_revert_data(&req.data)?;
+    let mut errors: Vec<Error> = req
+        .abi
+        .map(|abi| abi.errors().cloned().collect())
+        .unwrap_or_default();
+    for signature in req.signatures.unwrap_or_default() {
+        errors.push(Error::parse(&signature).map_err(|err| {
+            ApiError::invalid_request(format!("invalid signature {}: {}", signature, err))
+        })?);
+    }
+    Ok(Json(decode_revert(&data, &errors)))
+}
+
+/// Decodes the constructor arguments a contract was created with.
+#[utoipa::path(
+    post,
+    path = "/decode/constructor",
+    tag = "tools",
+    request_body = DecodeConstructorRequest,
+    responses(
+        (status = 200, description = "The constructor's arguments", body = DecodeConstructorResponse),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("apiKey" = []))
+)]
+#[post("/decode/constructor", format = "json", data = "<req>")]
+pub fn decode_constructor_route(
+    _key: ApiKey,
+    req: Json<DecodeConstructorRequest>,
+) -> Result<Json<DecodeConstructorResponse>, ApiError> {
+    decode_constructor(req.into_inner()).map(Json)
+}
+
+// The longest run of hex after a 0x, or the whole input if it's bare hex
+fn parse_revert_data(input: &str) -> Result<Vec<u8>, ApiError> {
+    let input = input.trim();
+    let digits = input
+        .match_indices("0x")
+        .map(|(i, _)| {
+            let rest = &input[i + 2..];
+            &rest[..rest
+                .find(|c: char| !c.is_ascii_hexdigit())
+                .unwrap_or(rest.len())]
+        })
+        .max_by_key(|digits| digits.len())
+        .unwrap_or(input);
+    hex::decode(digits)
+        .map_err(|err| ApiError::invalid_request(format!("data isn't revert data: {}", err)))
+}
+
+fn parse_signature(signature: &str) -> Result<Function, ApiError> {
+    Function::parse(signature).map_err(|err| {
+        ApiError::invalid_request(format!("invalid signature {}: {}", signature, err))
+    })
+}
+
+pub fn encode(req: EncodeRequest) -> Result<EncodeResponse, ApiError> {
+    let function = match (req.function, req.signature) {
+        (Some(function), _) => function,
+        (None, Some(signature)) => parse_signature(&signature)?,
+        (None, None) => {
+            return Err(ApiError::invalid_request(
+                "either signature or function is required",
+            ))
+        }
+    };
+    if function.inputs.len() != req.args.len() {
+        return Err(ApiError::invalid_request(format!(
+            "{} takes {} arguments, got {}",
+            function.name,
+            function.inputs.len(),
+            req.args.len()
+        )));
+    }
+
+    let values = function
+        .inputs
+        .iter()
+        .zip(&req.args)
+        .enumerate()
+        .map(|(i, (param, arg))| {
+            let ty = param
+                .resolve()
+                .map_err(|err| ApiError::invalid_request(format!("args[{}]: {}", i, err)))?;
+            from_json(&ty, arg, &format!("args[{}]", i)).map_err(|err| {
+                ApiError::new(
+                    Status::UnprocessableEntity,
+                    "INVALID_ABI_ARGUMENT",
+                    format!("{}: expected {}: {}", err.path, err.ty, err.message),
+                )
+                .with_details(json!({
+                    "index": i,
+                    "path": err.path,
+                    "expectedType": err.ty,
+                }))
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let calldata = function.abi_encode_input(&values).map_err(|err| {
+        ApiError::new(
+            Status::UnprocessableEntity,
+            "INVALID_ABI_ARGUMENT",
+            err.to_string(),
+        )
+    })?;
+    Ok(EncodeResponse {
+        calldata: calldata.into(),
+    })
+}
+
+pub fn decode(req: DecodeRequest) -> Result<DecodeResponse, ApiError> {
+    if req.calldata.len() < 4 {
+        return Err(ApiError::invalid_request(
+            "calldata is shorter than a selector",
+        ));
+    }
+    let mut candidates: Vec<Function> = req
+        .abi
+        .map(|abi| abi.functions().cloned().collect())
+        .unwrap_or_default();
+    for signature in req.signatures.unwrap_or_default() {
+        candidates.push(parse_signature(&signature)?);
+    }
+
+    let selector = &req.calldata[..4];
+    let function = candidates
+        .into_iter()
+        .find(|function| function.selector().as_slice() == selector)
+        .ok_or_else(|| {
+            ApiError::new(
+                Status::UnprocessableEntity,
+                "UNKNOWN_SELECTOR",
+                format!("no function matches selector 0x{}", hex::encode(selector)),
+            )
+        })?;
+
+    let values = function
+        .abi_decode_input(&req.calldata[4..], true)
+        .map_err(|err| {
+            ApiError::new(
+                Status::UnprocessableEntity,
+                "INVALID_CALLDATA",
+                format!("{}: {}", function.signature(), err),
+            )
+        })?;
+    Ok(DecodeResponse {
+        name: function.name.clone(),
+        signature: function.signature(),
+        args: values.iter().map(to_json).collect(),
+    })
+}
+
+pub fn decode_constructor(
+    req: DecodeConstructorRequest,
+) -> Result<DecodeConstructorResponse, ApiError> {
+    let code = &req.creation_code[..];
+    let args_start = match &req.bytecode {
+        Some(bytecode) if same_code(code, bytecode) => bytecode.len(),
+        Some(_) => {
+            return Err(ApiError::new(
+                Status::UnprocessableEntity,
+                "BYTECODE_MISMATCH",
+                "creationCode doesn't start with bytecode",
+            ))
+        }
+        None => metadata_end(code).ok_or_else(|| {
+            ApiError::new(
+                Status::UnprocessableEntity,
+                "METADATA_NOT_FOUND",
+                "creationCode has no solc metadata to find the arguments after; send bytecode",
+            )
+        })?,
+    };
+    let raw_args = &code[args_start..];
+
+    let Some(constructor) = req.abi.constructor() else {
+        if raw_args.is_empty() {
+            return Ok(DecodeConstructorResponse {
+                signature: "constructor()".to_string(),
+                args: Vec::new(),
+                raw_args: Bytes::new(),
+            });
+        }
+        return Err(ApiError::new(
+            Status::UnprocessableEntity,
+            "NO_CONSTRUCTOR",
+            format!(
+                "the ABI has no constructor, but {} bytes follow the code",
+                raw_args.len()
+            ),
+        ));
+    };
+    let types: Vec<String> = constructor
+        .inputs
+        .iter()
+        .map(|input| input.selector_type().into_owned())
+        .collect();
+    let signature = format!("constructor({})", types.join(","));
+    let values = constructor
+        // Not validated, which would fail trailing bytes without saying so
+        .abi_decode_input(raw_args, false)
+        .map_err(|err| {
+            ApiError::new(
+                Status::UnprocessableEntity,
+                "INVALID_CONSTRUCTOR_ARGS",
+                format!("{}: {}", signature, err),
+            )
+        })?;
+    // Decoding reads what it needs and ignores the rest, so anything past that is left over
+    let used = DynSolValue::Tuple(values.clone()).abi_encode_params().len();
+    if used < raw_args.len() {
+        return Err(ApiError::new(
+            Status::UnprocessableEntity,
+            "TRAILING_BYTES",
+            format!(
+                "{} bytes follow the arguments of {}",
+                raw_args.len() - used,
+                signature
+            ),
+        )
+        .with_details(json!({ "argsLength": used, "trailing": raw_args.len() - used })));
+    }
+    Ok(DecodeConstructorResponse {
+        signature,
+        args: values.iter().map(to_json).collect(),
+        raw_args: Bytes::copy_from_slice(raw_args),
+    })
+}
+
+// Whether `code` starts with `bytecode`, but for the hashes in its metadata, which differ between
+// builds of the same sources
+fn same_code(code: &[u8], bytecode: &[u8]) -> bool {
+    let Some(prefix) = code.get(..bytecode.len()) else {
+        return false;
+    };
+    let compared = without_metadata(bytecode).len();
+    prefix[..compared] == bytecode[..compared]
+}
+
+// The CBOR keys solc starts its metadata with
+const METADATA_KEYS: [&[u8]; 4] = [b"\x64ipfs", b"\x65bzzr0", b"\x65bzzr1", b"\x64solc"];
+
+// Where the last metadata solc appended in `code` ends: after the deployed code, where a creation
+// transaction's arguments start. Contracts the constructor creates come earlier, with their own.
+fn metadata_end(code: &[u8]) -> Option<usize> {
+    (2..=code.len()).rev().find(|&end| {
+        let start = without_metadata(&code[..end]).len();
+        start < end
+            && METADATA_KEYS
+                .iter()
+                .any(|key| code[start + 1..end].starts_with(key))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(signature: &str, args: Value) -> Value {
+        let calldata = encode(EncodeRequest {
+            signature: Some(signature.to_string()),
+            function: None,
+            args: serde_json::from_value(args).unwrap(),
+        })
+        .unwrap()
+        .calldata;
+        let decoded = decode(DecodeRequest {
+            calldata,
+            abi: None,
+            signatures: Some(vec![signature.to_string()]),
+        })
+        .unwrap();
+        Value::Array(decoded.args)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let args = json!([
+            "-57896044618658097711785492504343953926634992332820282019728792003956564819968",
+            [
+                "0x1000000000000000000000000000000000000000",
+                ["0x0102", "3"]
+            ],
+            ["1", "2", "3"],
+            "0xdeadbeef"
+        ]);
+        let decoded = round_trip(
+            "f(int256,(address,(bytes,uint8)),uint16[3],bytes4)",
+            args.clone(),
+        );
+        assert_eq!(decoded, args);
+    }
+
+    #[test]
+    fn test_negative_int_and_numbers() {
+        assert_eq!(
+            round_trip("f(int8,uint256)", json!([-1, 5])),
+            json!(["-1", "5"])
+        );
+    }
+
+    #[test]
+    fn test_error_points_at_argument() {
+        let err = encode(EncodeRequest {
+            signature: Some("f(uint256,(bool,address))".to_string()),
+            function: None,
+            args: vec![json!("1"), json!([true, "nope"])],
+        })
+        .err()
+        .unwrap();
+        assert_eq!(err.code, "INVALID_ABI_ARGUMENT");
+        assert_eq!(
+            err.details,
+            Some(json!({ "index": 1, "path": "args[1][1]", "expectedType": "address" }))
+        );
+    }
+
+    #[test]
+    fn test_fixed_array_length_checked() {
+        let err = encode(EncodeRequest {
+            signature: Some("f(uint8[2])".to_string()),
+            function: None,
+            args: vec![json!(["1", "2", "3"])],
+        })
+        .err()
+        .unwrap();
+        assert_eq!(err.details.unwrap()["expectedType"], "uint8[2]");
+    }
+
+    #[test]
+    fn test_unknown_selector() {
+        let err = decode(DecodeRequest {
+            calldata: Bytes::from_static(&[0xde, 0xad, 0xbe, 0xef]),
+            abi: None,
+            signatures: Some(vec!["transfer(address,uint256)".to_string()]),
+        })
+        .err()
+        .unwrap();
+        assert_eq!(err.code, "UNKNOWN_SELECTOR");
+    }
+
+    // Code ending in bzzr0 metadata, as solc 0.4 and 0.5 left it
+    const CREATION_CODE: &str = "6080604052a165627a7a7230582011111111111111111111111111111111111111111111111111111111111111110029";
+
+    fn creation(abi: &JsonAbi, args: Value) -> (Bytes, Bytes) {
+        let constructor = abi.constructor().unwrap();
+        let values: Vec<_> = constructor
+            .inputs
+            .iter()
+            .zip(args.as_array().unwrap())
+            .map(|(param, arg)| from_json(&param.resolve().unwrap(), arg, "args").unwrap())
+            .collect();
+        let encoded = constructor.abi_encode_input(&values).unwrap();
+        let code = hex::decode(CREATION_CODE).unwrap();
+        (code.clone().into(), [code, encoded].concat().into())
+    }
+
+    #[test]
+    fn test_constructor_args_round_trip() {
+        let abi =
+            JsonAbi::parse(["constructor(uint256 supply, address owner, string name)"]).unwrap();
+        let args = json!([
+            "1000000",
+            "0x1000000000000000000000000000000000000000",
+            "Token"
+        ]);
+        let (bytecode, creation_code) = creation(&abi, args.clone());
+
+        // Split after the metadata
+        let decoded = decode_constructor(DecodeConstructorRequest {
+            creation_code: creation_code.clone(),
+            abi: abi.clone(),
+            bytecode: None,
+        })
+        .unwrap();
+        assert_eq!(decoded.signature, "constructor(uint256,address,string)");
+        assert_eq!(Value::Array(decoded.args), args);
+        assert_eq!(decoded.raw_args.len(), creation_code.len() - bytecode.len());
+
+        // Split after the compiled code, with another build's metadata hash
+        let mut rebuilt = bytecode.to_vec();
+        rebuilt[20] ^= 0xff;
+        let decoded = decode_constructor(DecodeConstructorRequest {
+            creation_code: creation_code.clone(),
+            abi: abi.clone(),
+            bytecode: Some(rebuilt.into()),
+        })
+        .unwrap();
+        assert_eq!(Value::Array(decoded.args), args);
+
+        let trailing = decode_constructor(DecodeConstructorRequest {
+            creation_code: [&creation_code[..], &[0; 32]].concat().into(),
+            abi,
+            bytecode: None,
+        })
+        .err()
+        .unwrap();
+        assert_eq!(trailing.code, "TRAILING_BYTES");
+
+        let no_constructor = decode_constructor(DecodeConstructorRequest {
+            creation_code,
+            abi: JsonAbi::new(),
+            bytecode: None,
+        })
+        .err()
+        .unwrap();
+        assert_eq!(no_constructor.code, "NO_CONSTRUCTOR");
+
+        let no_metadata = decode_constructor(DecodeConstructorRequest {
+            creation_code: Bytes::from_static(&[0x60, 0x80, 0x60, 0x40, 0x52]),
+            abi: JsonAbi::new(),
+            bytecode: None,
+        })
+        .err()
+        .unwrap();
+        assert_eq!(no_metadata.code, "METADATA_NOT_FOUND");
+    }
+
+    #[test]
+    fn test_parse_revert_data() {
+        let panic = "4e487b710000000000000000000000000000000000000000000000000000000000000011";
+        for input in [
+            format!("0x{}", panic),
+            format!("execution reverted: 0x{}", panic),
+            format!("  {}\n", panic),
+        ] {
+            assert_eq!(hex::encode(parse_revert_data(&input).unwrap()), panic);
+        }
+        assert!(parse_revert_data("0x").unwrap().is_empty());
+        assert_eq!(
+            parse_revert_data("0x123").unwrap_err().code,
+            "INVALID_REQUEST"
+        );
+        assert_eq!(
+            parse_revert_data("oops").unwrap_err().code,
+            "INVALID_REQUEST"
+        );
+    }
+}
@@ -0,0 +1,122 @@
+use crate::auth::{AdminKey, Auth, Usage};
+use crate::caches::{CacheStats, Caches};
+use crate::error::ApiError;
+use rocket::{get, post, serde::json::Json, State};
+use std::collections::BTreeMap;
+
+/// Requests served and rejected per API key since startup.
+#[utoipa::path(
+    get,
+    path = "/admin/usage",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Usage keyed by API key", body = BTreeMap<String, Usage>),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("adminKey" = []))
+)]
+#[get("/admin/usage")]
+pub fn usage_route(_key: AdminKey, auth: &State<Auth>) -> Json<BTreeMap<String, Usage>> {
+    Json(auth.usage())
+}
+
+/// Size and hit rate of each server cache.
+#[utoipa::path(
+    get,
+    path = "/admin/caches",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Stats keyed by cache name", body = BTreeMap<String, CacheStats>),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("adminKey" = []))
+)]
+#[get("/admin/caches")]
+pub fn caches_route(
+    _key: AdminKey,
+    caches: &State<Caches>,
+) -> Json<BTreeMap<&'static str, CacheStats>> {
+    Json(caches.stats())
+}
+
+/// Empties one cache, or every cache when none is named.
+#[utoipa::path(
+    post,
+    path = "/admin/caches/flush",
+    tag = "admin",
+    params(("cache" = Option<String>, Query, description = "Only flush this cache")),
+    responses(
+        (status = 200, description = "Entries dropped, keyed by cache name", body = BTreeMap<String, usize>),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("adminKey" = []))
+)]
+#[post("/admin/caches/flush?<cache>")]
+pub fn flush_caches_route(
+    _key: AdminKey,
+    caches: &State<Caches>,
+    cache: Option<&str>,
+) -> Result<Json<BTreeMap<&'static str, usize>>, ApiError> {
+    caches.flush(cache).map(Json)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::Auth;
+    use crate::caches::Caches;
+    use crate::config::AppConfig;
+    use crate::results::{MemoryStore, Results};
+    use crate::routes::{caches_route, deploy_route, flush_caches_route};
+    use crate::shutdown::InFlight;
+    use crate::snapshots::Snapshots;
+    use rocket::http::{ContentType, Status};
+    use rocket::local::blocking::Client;
+    use rocket::routes;
+    use serde_json::Value;
+
+    fn client() -> Client {
+        let config = AppConfig::default();
+        let snapshots = Snapshots::new(&config.limits);
+        let mut caches = Caches::default();
+        caches.register(snapshots.clone());
+        let rocket = rocket::build()
+            .manage(Auth::new(&config))
+            .manage(InFlight::default())
+            .manage(snapshots)
+            .manage(caches)
+            .manage(Results::new(MemoryStore::default(), &config.limits))
+            .manage(config)
+            .mount("/", routes![deploy_route, caches_route, flush_caches_route])
+            .register("/", crate::error::catchers());
+        Client::tracked(rocket).unwrap()
+    }
+
+    fn entries(client: &Client) -> u64 {
+        let stats: Value = client.get("/admin/caches").dispatch().into_json().unwrap();
+        stats["snapshots"]["entries"].as_u64().unwrap()
+    }
+
+    #[test]
+    fn test_flush_empties_snapshots() {
+        let client = client();
+        let response = client
+            .post("/deploy")
+            .header(ContentType::JSON)
+            .body(r#"{"bytecode":"0x6000600055"}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(entries(&client), 1);
+
+        let response = client.post("/admin/caches/flush?cache=nope").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        assert_eq!(entries(&client), 1);
+
+        let flushed: Value = client
+            .post("/admin/caches/flush?cache=snapshots")
+            .dispatch()
+            .into_json()
+            .unwrap();
+        assert_eq!(flushed["snapshots"], 1);
+        assert_eq!(entries(&client), 0);
+    }
+}
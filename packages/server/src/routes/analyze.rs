@@ -0,0 +1,76 @@
+use crate::auth::ApiKey;
+use crate::compile::imports::{import_graph, ImportGraph};
+use crate::compile::solidity::SolidityFile;
+use crate::config::AppConfig;
+use crate::error::ApiError;
+use rocket::{post, serde::json::Json, State};
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AnalyzeImportsRequest {
+    pub files: Vec<SolidityFile>,
+}
+
+/// Which of the files import which, what packages they import from, which imports don't resolve
+/// and which files import one another in a circle, read off their import directives without
+/// compiling them. `/compile_solidity` adds the same with `importGraph: true`.
+#[utoipa::path(
+    post,
+    path = "/analyze/imports",
+    tag = "compile",
+    request_body = AnalyzeImportsRequest,
+    responses(
+        (status = 200, description = "The files' import graph", body = ImportGraph),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("apiKey" = []))
+)]
+#[post("/analyze/imports", format = "json", data = "<req>")]
+pub fn analyze_imports_route(
+    _key: ApiKey,
+    config: &State<AppConfig>,
+    req: Json<AnalyzeImportsRequest>,
+) -> Result<Json<ImportGraph>, ApiError> {
+    super::validate::check_sources(&config.limits, &req.files)?;
+    Ok(Json(import_graph(&req.files)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Auth;
+    use rocket::http::{ContentType, Status};
+    use rocket::local::blocking::Client;
+    use rocket::routes;
+    use serde_json::json;
+
+    #[test]
+    fn test_analyze_imports() {
+        let config = AppConfig::default();
+        let rocket = rocket::build()
+            .manage(Auth::new(&config))
+            .manage(config)
+            .mount("/", routes![analyze_imports_route]);
+        let client = Client::tracked(rocket).unwrap();
+        let body = json!({
+            "files": [
+                { "name": "A.sol", "content": "import \"./B.sol\";\ncontract A {}" },
+                { "name": "B.sol", "content": "import \"./A.sol\";\ncontract B {}" },
+                { "name": "C.sol", "content": "import \"forge-std/Test.sol\";\ncontract C {}" },
+            ],
+        });
+        let response = client
+            .post("/analyze/imports")
+            .header(ContentType::JSON)
+            .body(body.to_string())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let graph: serde_json::Value = response.into_json().unwrap();
+        assert_eq!(graph["cycles"], json!([["A.sol", "B.sol"]]));
+        assert_eq!(graph["externalPackages"], json!(["forge-std"]));
+        assert_eq!(graph["orphans"], json!(["C.sol"]));
+        assert_eq!(graph["edges"][2]["kind"], "external");
+    }
+}
@@ -0,0 +1,230 @@
+use crate::auth::ApiKey;
+use crate::compile::bytecode_diff::{diff, disassemble, Hunk};
+use crate::compile::cache::{CompileCache, Sources};
+use crate::error::ApiError;
+use crate::gas::hardfork::without_metadata;
+use alloy_primitives::Bytes;
+use foundry_compilers::artifacts::sourcemap::{self, SourceMap};
+use foundry_compilers::contracts::VersionedContracts;
+use foundry_compilers::Artifact;
+use rocket::{http::Status, post, serde::json::Json, State};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::validate::invalid_field;
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CompareBytecodeRequest {
+    pub a: BytecodeSide,
+    pub b: BytecodeSide,
+    /// Compare the CBOR metadata solc appends too, which differs whenever any source does
+    #[serde(default)]
+    pub include_metadata: bool,
+}
+
+/// Deployed code, given either as `bytecode` or as a contract of a cached compile.
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BytecodeSide {
+    #[schema(value_type = Option<String>)]
+    pub bytecode: Option<Bytes>,
+    /// solc's compressed source map for `bytecode`
+    pub source_map: Option<String>,
+    /// The sources `sourceMap` refers to, by name, for naming files and lines
+    #[schema(value_type = Option<Object>)]
+    pub sources: Option<Sources>,
+    /// The `ETag` of the `/compile_solidity` response, quotes or not. The compile has to still
+    /// be cached.
+    pub compile_id: Option<String>,
+    /// With `compileId`
+    pub contract_name: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CompareBytecodeResponse {
+    pub hunks: Vec<Hunk>,
+    /// How many instructions each side has
+    pub a_instructions: usize,
+    pub b_instructions: usize,
+}
+
+// Just what's needed of a cached compile response
+#[derive(Deserialize)]
+struct Compiled {
+    contracts: VersionedContracts,
+}
+
+/// Diffs two deployed bytecodes instruction by instruction, each hunk with the source ranges it
+/// was compiled from on either side. Moved jump targets aren't differences.
+#[utoipa::path(
+    post,
+    path = "/compare/bytecode",
+    tag = "tools",
+    request_body = CompareBytecodeRequest,
+    responses(
+        (status = 200, description = "Where the codes differ", body = CompareBytecodeResponse),
+        (status = 404, description = "A compile isn't cached (anymore)", body = ApiError),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("apiKey" = []))
+)]
+#[post("/compare/bytecode", format = "json", data = "<req>")]
+pub fn compare_bytecode_route(
+    _key: ApiKey,
+    cache: &State<CompileCache>,
+    req: Json<CompareBytecodeRequest>,
+) -> Result<Json<CompareBytecodeResponse>, ApiError> {
+    compare_bytecode(cache, req.into_inner()).map(Json)
+}
+
+pub fn compare_bytecode(
+    cache: &CompileCache,
+    req: CompareBytecodeRequest,
+) -> Result<CompareBytecodeResponse, ApiError> {
+    let [a, b] = [("a", &req.a), ("b", &req.b)].map(|(field, side)| {
+        let (code, source_map, sources) = resolve(cache, field, side)?;
+        let code = match req.include_metadata {
+            true => &code[..],
+            false => without_metadata(&code),
+        };
+        Ok::<_, ApiError>(disassemble(code, source_map.as_deref(), &sources))
+    });
+    let (a, b) = (a?, b?);
+    Ok(CompareBytecodeResponse {
+        hunks: diff(&a, &b),
+        a_instructions: a.len(),
+        b_instructions: b.len(),
+    })
+}
+
+// The code, source map and sources `side` stands for
+fn resolve(
+    cache: &CompileCache,
+    field: &str,
+    side: &BytecodeSide,
+) -> Result<(Bytes, Option<SourceMap>, Sources), ApiError> {
+    match (&side.bytecode, &side.compile_id) {
+        (Some(code), None) => {
+            let source_map = side
+                .source_map
+                .as_deref()
+                .map(sourcemap::parse)
+                .transpose()
+                .map_err(|err| invalid_field(&format!("{}.sourceMap", field), err))?;
+            Ok((
+                code.clone(),
+                source_map,
+                side.sources.clone().unwrap_or_default(),
+            ))
+        }
+        (None, Some(compile_id)) => {
+            let contract_name = side.contract_name.as_deref().ok_or_else(|| {
+                invalid_field(
+                    &format!("{}.contractName", field),
+                    "is needed with compileId",
+                )
+            })?;
+            let (body, sources) =
+                cache
+                    .compiled(compile_id.trim_matches('"'))
+                    .ok_or_else(|| {
+                        ApiError::new(
+                            Status::NotFound,
+                            "COMPILE_NOT_FOUND",
+                            format!("No cached compile {}; compile again", compile_id),
+                        )
+                    })?;
+            let compiled: Compiled = serde_json::from_slice(&body).map_err(|err| {
+                ApiError::new(
+                    Status::InternalServerError,
+                    "COMPILE_UNREADABLE",
+                    err.to_string(),
+                )
+            })?;
+            let contract = compiled
+                .contracts
+                .contracts_with_files_and_version()
+                .find(|(_, name, _, _)| name.as_str() == contract_name)
+                .map(|(_, _, contract, _)| contract);
+            let code = contract
+                .and_then(|contract| contract.get_deployed_bytecode_bytes())
+                .ok_or_else(|| {
+                    ApiError::new(
+                        Status::UnprocessableEntity,
+                        "CONTRACT_NOT_FOUND",
+                        format!(
+                            "Contract {} has no deployed code in that compile",
+                            contract_name
+                        ),
+                    )
+                })?
+                .into_owned();
+            let source_map = contract
+                .and_then(|contract| contract.get_source_map_deployed())
+                .and_then(Result::ok);
+            Ok((code, source_map, (*sources).clone()))
+        }
+        _ => Err(invalid_field(field, "needs one of bytecode or compileId")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::bytecode_diff::HunkKind;
+    use crate::config::Limits;
+    use alloy_primitives::bytes;
+
+    fn raw(bytecode: Bytes) -> BytecodeSide {
+        BytecodeSide {
+            bytecode: Some(bytecode),
+            source_map: None,
+            sources: None,
+            compile_id: None,
+            contract_name: None,
+        }
+    }
+
+    #[test]
+    fn test_compare_bytecode() {
+        let cache = CompileCache::new(&Limits::default());
+        // The same code, but for a PUSH1 and solc's metadata
+        let req = CompareBytecodeRequest {
+            a: raw(bytes!("6001600055a165627a7a7230582000000000000000000000000000000000000000000000000000000000000000000029")),
+            b: raw(bytes!("6002600055a165627a7a7230582011111111111111111111111111111111111111111111111111111111111111110029")),
+            include_metadata: false,
+        };
+        let response = compare_bytecode(&cache, req).unwrap();
+        assert_eq!(response.a_instructions, 3);
+        assert_eq!(response.hunks.len(), 1);
+        assert_eq!(response.hunks[0].kind, HunkKind::Changed);
+
+        let req = CompareBytecodeRequest {
+            a: BytecodeSide {
+                compile_id: Some("\"abc\"".to_string()),
+                contract_name: Some("SimpleStorage".to_string()),
+                ..raw(Bytes::new())
+            },
+            b: raw(Bytes::new()),
+            include_metadata: false,
+        };
+        // Both bytecode and compileId
+        let err = compare_bytecode(&cache, req).err().unwrap();
+        assert_eq!(err.code, "INVALID_FIELD");
+
+        let req = CompareBytecodeRequest {
+            a: BytecodeSide {
+                bytecode: None,
+                compile_id: Some("\"abc\"".to_string()),
+                contract_name: Some("SimpleStorage".to_string()),
+                ..raw(Bytes::new())
+            },
+            b: raw(Bytes::new()),
+            include_metadata: false,
+        };
+        let err = compare_bytecode(&cache, req).err().unwrap();
+        assert_eq!(err.code, "COMPILE_NOT_FOUND");
+    }
+}
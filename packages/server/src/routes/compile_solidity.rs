@@ -1,16 +1,510 @@
-use crate::compile::solidity::{compile, CompileResult, SolidityFile};
-use rocket::{post, response::status, serde::json::Json};
+use crate::admission::CompileSlot;
+use crate::auth::CompileKey;
+use crate::compile::cache::{request_hash, CompileCache};
+use crate::compile::imports::import_graph;
+use crate::compile::solidity::{
+    compile, compile_partial, compile_relative, CompileResult, SolidityFile,
+};
+use crate::config::AppConfig;
+use crate::error::ApiError;
+use crate::gas::run_blocking;
+use crate::shutdown::Work;
+use crate::telemetry::RequestId;
+use rocket::http::{ContentType, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::{post, State};
 use serde::Deserialize;
+use serde_json::Value;
+use std::convert::Infallible;
+use std::io::Cursor;
+use std::sync::Arc;
+use tracing::Instrument;
+use utoipa::ToSchema;
 
-#[derive(Deserialize)]
+use super::validate::{check_known, Checked, Validate};
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct CompileRequest {
     pub files: Vec<SolidityFile>,
+    /// `hardhat` adds `artifacts`, every contract as the JSON file Hardhat writes for it
+    pub artifact_format: Option<String>,
+    /// Name files in the output as they were sent, rather than by the directory they were
+    /// compiled in, so the same sources always get the same bytes back
+    #[serde(default)]
+    pub deterministic: bool,
+    /// Compile each group of files that import one another on its own, and answer with the
+    /// groups that compiled even when others didn't, flagged `partialFailure`, the others in
+    /// `failedClusters`. A 422 only when none did.
+    #[serde(default)]
+    pub partial: bool,
+    /// Add `importGraph`, what `/analyze/imports` answers for the files, to the output, and
+    /// to the 422's too
+    #[serde(default)]
+    pub import_graph: bool,
+}
+
+impl Validate for CompileRequest {
+    fn check_json(_body: &Value) -> Result<(), ApiError> {
+        Ok(())
+    }
+
+    fn check_known_fields(body: &Value) -> Result<(), ApiError> {
+        check_known::<Self>(body, "")?;
+        for (i, file) in body["files"].as_array().into_iter().flatten().enumerate() {
+            check_known::<SolidityFile>(file, &format!("files[{}]", i))?;
+        }
+        Ok(())
+    }
+}
+
+/// The `If-None-Match` header, if any.
+pub struct IfNoneMatch(Option<String>);
+
+impl IfNoneMatch {
+    fn matches(&self, etag: &str) -> bool {
+        self.0.as_deref().is_some_and(|header| {
+            header
+                .split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfNoneMatch {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(IfNoneMatch(
+            req.headers().get_one("If-None-Match").map(String::from),
+        ))
+    }
 }
+
+/// A compile response tagged with its request hash, or a bodiless 304 when the client already
+/// has it.
+pub enum Compiled {
+    Fresh { etag: String, body: Arc<Vec<u8>> },
+    NotModified { etag: String },
+}
+
+impl<'r> Responder<'r, 'static> for Compiled {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        match self {
+            Compiled::Fresh { etag, body } => Response::build()
+                .header(ContentType::JSON)
+                .raw_header("ETag", etag)
+                .sized_body(body.len(), Cursor::new(body.to_vec()))
+                .ok(),
+            Compiled::NotModified { etag } => Response::build()
+                .status(Status::NotModified)
+                .raw_header("ETag", etag)
+                .ok(),
+        }
+    }
+}
+
+/// Sources with errors get a 422 rather than a 200 with no contracts, unless `partial` got some
+/// of them compiled. Identical requests are
+/// answered from a cache keyed by the hash of their sources, which is also sent as the `ETag`.
+/// Send it back in `If-None-Match` to get a 304 while it's still cached.
+#[utoipa::path(
+    post,
+    path = "/compile_solidity",
+    tag = "compile",
+    request_body = CompileRequest,
+    params((
+        "lenient" = Option<bool>, Query,
+        description = "`true` ignores JSON keys that aren't fields instead of refusing them"
+    )),
+    responses(
+        (status = 200, description = "Compiler output, including any warnings", body = CompileResult,
+            headers(("ETag" = String, description = "Hash of the request's sources"))),
+        (status = 304, description = "Unchanged since the `If-None-Match` ETag"),
+        (status = 422, description = "The sources didn't compile. `details` holds the compiler output: its errors, and whatever contracts it still produced.", body = ApiError),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("apiKey" = []))
+)]
 #[post("/compile_solidity", format = "json", data = "<req>")]
-pub fn compile_solidity_route(
-    req: Json<CompileRequest>,
-) -> Result<Json<CompileResult>, status::BadRequest<String>> {
-    let result = compile(&req.files).map_err(|err| status::BadRequest(err.to_string()))?;
+#[allow(clippy::too_many_arguments)]
+pub async fn compile_solidity_route(
+    _key: CompileKey,
+    _work: Work,
+    _slot: CompileSlot,
+    id: RequestId,
+    config: &State<AppConfig>,
+    cache: &State<CompileCache>,
+    if_none_match: IfNoneMatch,
+    req: Checked<CompileRequest>,
+) -> Result<Compiled, ApiError> {
+    super::validate::check_sources(&config.limits, &req.files)?;
+    let hardhat = match req.artifact_format.as_deref() {
+        None => false,
+        Some("hardhat") => true,
+        Some(_) => {
+            return Err(super::validate::invalid_field(
+                "artifactFormat",
+                "expected hardhat",
+            ))
+        }
+    };
+
+    // Responses with artifacts are a representation of their own
+    let mut hash = request_hash(&req.files);
+    if hardhat {
+        hash.push_str("-hardhat");
+    }
+    if req.deterministic {
+        hash.push_str("-deterministic");
+    }
+    if req.partial {
+        hash.push_str("-partial");
+    }
+    if req.import_graph {
+        hash.push_str("-imports");
+    }
+    let etag = format!("\"{}\"", hash);
+    if let Some(body) = cache.get(&hash) {
+        if if_none_match.matches(&etag) {
+            return Ok(Compiled::NotModified { etag });
+        }
+        return Ok(Compiled::Fresh { etag, body });
+    }
+
+    // solc blocks until it's done, so it runs on the blocking pool
+    let files = req.files.clone();
+    let limits = config.limits.clone();
+    let (partial, deterministic) = (req.partial, req.deterministic);
+    let result = async {
+        run_blocking(move || match (partial, deterministic) {
+            (true, relative) => compile_partial(&files, &limits, relative),
+            (false, true) => compile_relative(&files, &limits),
+            (false, false) => compile(&files, &limits),
+        })
+        .await
+    }
+    .instrument(id.span())
+    .await;
+    let mut result = result.map_err(ApiError::compile_failed)?;
+    if req.import_graph {
+        result.import_graph = Some(import_graph(&req.files));
+    }
+    // Not cached, since a 304 stands in for a 200
+    if result.has_errors() {
+        return Err(ApiError::compile_errors(&result));
+    }
+    if hardhat {
+        result.artifacts = Some(result.hardhat_artifacts());
+    }
+    let body = serde_json::to_vec(&result).map_err(|err| ApiError::compile_failed(err.into()))?;
+    let sources = req
+        .files
+        .iter()
+        .map(|file| (file.name.clone(), file.content.clone()))
+        .collect();
+    let body = cache.insert(hash, body, sources);
+    Ok(Compiled::Fresh { etag, body })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admission::Gates;
+    use crate::auth::Auth;
+    use crate::caches::Cache;
+    use crate::shutdown::InFlight;
+    use rocket::http::Header;
+    use rocket::local::blocking::Client;
+    use rocket::routes;
+    use serde_json::json;
+
+    #[test]
+    fn test_if_none_match() {
+        let etag = "\"abc\"";
+        assert!(!IfNoneMatch(None).matches(etag));
+        assert!(IfNoneMatch(Some("\"abc\"".to_string())).matches(etag));
+        assert!(IfNoneMatch(Some("\"x\", W/\"abc\"".to_string())).matches(etag));
+        assert!(IfNoneMatch(Some("*".to_string())).matches(etag));
+        assert!(!IfNoneMatch(Some("\"abcd\"".to_string())).matches(etag));
+    }
+
+    fn client(cache: &CompileCache) -> Client {
+        let config = AppConfig::default();
+        let rocket = rocket::build()
+            .manage(Auth::new(&config))
+            .manage(InFlight::default())
+            .manage(Gates::new(&config.limits))
+            .manage(cache.clone())
+            .manage(config)
+            .mount("/", routes![compile_solidity_route]);
+        Client::tracked(rocket).unwrap()
+    }
+
+    fn post(client: &Client, content: &str) -> (Status, serde_json::Value) {
+        let body = json!({ "files": [{ "name": "A.sol", "content": content }] });
+        let response = client
+            .post("/compile_solidity")
+            .header(ContentType::JSON)
+            .body(body.to_string())
+            .dispatch();
+        (response.status(), response.into_json().unwrap())
+    }
+
+    #[test]
+    fn test_status_follows_severity() {
+        let cache = CompileCache::new(&AppConfig::default().limits);
+        let client = client(&cache);
+
+        let (status, body) = post(
+            &client,
+            "// SPDX-License-Identifier: MIT\npragma solidity ^0.8.0;\ncontract A {}",
+        );
+        assert_eq!(status, Status::Ok);
+        assert_eq!(body["errors"], json!([]));
+        assert!(body["contracts"].to_string().contains("\"A\""));
+
+        // No license, and an unused variable
+        let (status, body) = post(
+            &client,
+            "pragma solidity ^0.8.0;\ncontract A { function f() public pure { uint x; } }",
+        );
+        assert_eq!(status, Status::Ok);
+        let errors = body["errors"].as_array().unwrap();
+        assert!(!errors.is_empty());
+        assert!(errors.iter().all(|err| err["severity"] == "warning"));
+        assert!(body["contracts"].to_string().contains("\"A\""));
+
+        let (status, body) = post(
+            &client,
+            "// SPDX-License-Identifier: MIT\npragma solidity ^0.8.0;\ncontract A { uint x }",
+        );
+        assert_eq!(status, Status::UnprocessableEntity);
+        assert_eq!(body["code"], "COMPILE_FAILED");
+        assert_eq!(body["message"], "Compilation failed with 1 error(s)");
+        let errors = body["details"]["errors"].as_array().unwrap();
+        assert_eq!(errors[0]["type"], "ParserError");
+        assert_eq!(errors[0]["severity"], "error");
+        assert!(body["details"].get("contracts").is_some());
+        // Only the two that compiled are cached
+        assert_eq!(cache.stats().entries, 2);
+    }
+
+    #[test]
+    fn test_hardhat_artifacts() {
+        let cache = CompileCache::new(&AppConfig::default().limits);
+        let client = client(&cache);
+        let files = json!([{
+            "name": "SimpleStorage.sol",
+            "content": "// SPDX-License-Identifier: MIT\npragma solidity ^0.8.0;\n\
+                contract SimpleStorage { uint256 public storedData; \
+                function set(uint256 x) public { storedData = x; } }",
+        }]);
+        let compile = |body: serde_json::Value| {
+            let response = client
+                .post("/compile_solidity")
+                .header(ContentType::JSON)
+                .body(body.to_string())
+                .dispatch();
+            let etag = response.headers().get_one("ETag").map(String::from);
+            (
+                response.status(),
+                etag,
+                response.into_json::<serde_json::Value>().unwrap(),
+            )
+        };
+
+        let (status, plain_etag, body) = compile(json!({ "files": files }));
+        assert_eq!(status, Status::Ok);
+        assert!(body.get("artifacts").is_none());
+
+        let (status, etag, body) = compile(json!({ "files": files, "artifactFormat": "hardhat" }));
+        assert_eq!(status, Status::Ok);
+        assert_ne!(etag, plain_etag);
+        let artifact = &body["artifacts"]["SimpleStorage.sol:SimpleStorage"];
+        assert_eq!(artifact["_format"], "hh-sol-artifact-1");
+        assert_eq!(artifact["contractName"], "SimpleStorage");
+        assert_eq!(artifact["sourceName"], "SimpleStorage.sol");
+        assert_eq!(artifact["abi"].as_array().unwrap().len(), 2);
+        for field in ["bytecode", "deployedBytecode"] {
+            let code = artifact[field].as_str().unwrap();
+            assert!(code.starts_with("0x6080"), "{}: {}", field, code);
+        }
+        assert_eq!(artifact["linkReferences"], json!({}));
+        assert_eq!(artifact["deployedLinkReferences"], json!({}));
+
+        let (status, _, body) = compile(json!({ "files": files, "artifactFormat": "truffle" }));
+        assert_eq!(status, Status::UnprocessableEntity);
+        assert_eq!(body["code"], "INVALID_FIELD");
+    }
+
+    #[test]
+    fn test_etag_not_modified() {
+        let cache = CompileCache::new(&AppConfig::default().limits);
+        let client = client(&cache);
+        let body = json!({
+            "files": [{
+                "name": "A.sol",
+                "content": "// SPDX-License-Identifier: MIT\npragma solidity ^0.8.0;\ncontract A {}",
+            }],
+        })
+        .to_string();
+
+        let response = client
+            .post("/compile_solidity")
+            .header(ContentType::JSON)
+            .body(&body)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let etag = response.headers().get_one("ETag").unwrap().to_string();
+        let compiled: serde_json::Value = response.into_json().unwrap();
+        assert!(compiled["contracts"].to_string().contains("\"A\""));
+        let hits = cache.stats().hits;
+
+        let response = client
+            .post("/compile_solidity")
+            .header(ContentType::JSON)
+            .header(Header::new("If-None-Match", etag.clone()))
+            .body(&body)
+            .dispatch();
+        assert_eq!(response.status(), Status::NotModified);
+        assert_eq!(response.headers().get_one("ETag"), Some(etag.as_str()));
+        assert!(response.into_bytes().unwrap_or_default().is_empty());
+        assert_eq!(cache.stats().hits, hits + 1);
+
+        // Once the entry is gone the sources are compiled again
+        cache.clear();
+        let response = client
+            .post("/compile_solidity")
+            .header(ContentType::JSON)
+            .header(Header::new("If-None-Match", etag.clone()))
+            .body(&body)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("ETag"), Some(etag.as_str()));
+    }
+
+    #[test]
+    fn test_deterministic_output() {
+        let body = json!({
+            "files": [{
+                "name": "A.sol",
+                "content": "// SPDX-License-Identifier: MIT\npragma solidity ^0.8.0;\ncontract A {}",
+            }],
+            "deterministic": true,
+        })
+        .to_string();
+        // Each compiled in a directory of its own, with nothing cached between them
+        let compiled: Vec<_> = (0..2)
+            .map(|_| {
+                let cache = CompileCache::new(&AppConfig::default().limits);
+                let response = client(&cache)
+                    .post("/compile_solidity")
+                    .header(ContentType::JSON)
+                    .body(&body)
+                    .dispatch();
+                assert_eq!(response.status(), Status::Ok);
+                response.into_bytes().unwrap()
+            })
+            .collect();
+        assert_eq!(compiled[0], compiled[1]);
+
+        let output: serde_json::Value = serde_json::from_slice(&compiled[0]).unwrap();
+        assert!(output["contracts"].get("A.sol").is_some());
+        assert!(output["source_maps"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .all(|key| key.starts_with("A.sol:")));
+    }
+
+    #[test]
+    fn test_partial_compile() {
+        let cache = CompileCache::new(&AppConfig::default().limits);
+        let client = client(&cache);
+        let header = "// SPDX-License-Identifier: MIT\npragma solidity ^0.8.0;\n";
+        let files = json!([
+            { "name": "A.sol", "content": format!("{}import \"./B.sol\";\ncontract A is B {{}}", header) },
+            { "name": "B.sol", "content": format!("{}contract B {{}}", header) },
+            { "name": "C.sol", "content": format!("{}import {{ D }} from \"./Missing.sol\";\ncontract C {{}}", header) },
+        ]);
+        let compile = |partial: bool| {
+            let response = client
+                .post("/compile_solidity")
+                .header(ContentType::JSON)
+                .body(json!({ "files": files, "partial": partial }).to_string())
+                .dispatch();
+            (
+                response.status(),
+                response.into_json::<serde_json::Value>().unwrap(),
+            )
+        };
+
+        // Whole, the missing import fails all three
+        let (status, _) = compile(false);
+        assert_eq!(status, Status::UnprocessableEntity);
+
+        let (status, body) = compile(true);
+        assert_eq!(status, Status::Ok);
+        assert_eq!(body["partialFailure"], true);
+        let contracts = body["contracts"].as_object().unwrap();
+        for (file, name) in [("A.sol", "A"), ("B.sol", "B")] {
+            let (_, compiled) = contracts
+                .iter()
+                .find(|(path, _)| path.ends_with(file))
+                .unwrap();
+            let code = compiled[name][0]["contract"]["evm"]["bytecode"]["object"]
+                .as_str()
+                .unwrap();
+            assert!(
+                code.trim_start_matches("0x").starts_with("6080"),
+                "{}: {}",
+                name,
+                code
+            );
+        }
+        assert!(!contracts.keys().any(|path| path.ends_with("C.sol")));
+        let failed = body["failedClusters"].as_array().unwrap();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0]["files"], json!(["C.sol"]));
+        let errors = failed[0]["errors"].as_array().unwrap();
+        assert!(errors.iter().any(|err| err["severity"] == "error"));
+        assert!(body["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|err| err["severity"] != "error"));
+    }
+
+    #[test]
+    fn test_import_graph_flag() {
+        let cache = CompileCache::new(&AppConfig::default().limits);
+        let client = client(&cache);
+        let content = "// SPDX-License-Identifier: MIT\npragma solidity ^0.8.0;\n\
+            import \"./Missing.sol\";\ncontract A {}";
+        let body = json!({
+            "files": [{ "name": "A.sol", "content": content }],
+            "importGraph": true,
+        });
+        let response = client
+            .post("/compile_solidity")
+            .header(ContentType::JSON)
+            .body(body.to_string())
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let body: serde_json::Value = response.into_json().unwrap();
+        let edge = &body["details"]["importGraph"]["edges"][0];
+        assert_eq!(edge["kind"], "unresolved");
+        assert_eq!(edge["line"], 3);
 
-    Ok(Json(result))
+        let (status, body) = post(
+            &client,
+            "// SPDX-License-Identifier: MIT\npragma solidity ^0.8.0;\ncontract A {}",
+        );
+        assert_eq!(status, Status::Ok);
+        assert!(body.get("importGraph").is_none());
+    }
 }
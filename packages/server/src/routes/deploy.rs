@@ -0,0 +1,168 @@
+use crate::auth::ExecuteKey;
+use crate::config::AppConfig;
+use crate::error::ApiError;
+use crate::format::Negotiated;
+use crate::gas::op_stack::OpStackParams;
+use crate::gas::{deploy_local, EncodedState, ExecutionOptions, ExecutionResult, DEFAULT_DEPLOYER};
+use crate::ids;
+use crate::legacy;
+use crate::results::{Persisted, Persistence, Results};
+use crate::shutdown::Work;
+use crate::snapshots::Snapshots;
+use crate::telemetry::RequestId;
+use alloy_primitives::{Address, Bytes, U256};
+use rocket::{post, State};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use super::transact::{
+    check_state_format, encode_state, starting_state, with_chain_profile, with_fresh_nonce,
+};
+use super::validate::{self, Checked, Validate};
+
+#[derive(Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DeployRequest {
+    /// Creation code
+    #[schema(value_type = String)]
+    pub bytecode: Bytes,
+    /// ABI-encoded, appended to the creation code
+    #[schema(value_type = Option<String>)]
+    pub constructor_args: Option<Bytes>,
+    #[schema(value_type = Option<String>)]
+    pub value: Option<U256>,
+    /// `0x1804c8AB1F12E6bbf3894d4083f33e07309d1f38` without one
+    #[schema(value_type = Option<String>)]
+    pub caller: Option<Address>,
+    /// Deploy on top of a state returned by an earlier `/deploy` or `/transact`...
+    pub state_id: Option<String>,
+    /// ...or on top of a state passed in full, as returned or as a genesis file's `alloc`. An
+    /// empty chain without either.
+    #[schema(value_type = Option<BTreeMap<String, AccountDump>>)]
+    pub state: Option<EncodedState>,
+    /// `genesis` returns `state` as a genesis file's `alloc`
+    pub state_format: Option<String>,
+    pub trace_mode: Option<String>,
+    /// Also return forge's trace arena as `rawTraces`. Deprecated.
+    #[serde(default)]
+    pub include_raw_traces: bool,
+    /// Record the events calls emit as `logs`. Defaults to true.
+    pub collect_logs: Option<bool>,
+    /// Run under this hardfork's rules, e.g. `istanbul`, instead of the latest
+    pub hardfork: Option<String>,
+    /// `op-stack` adds stand-ins for the `L1Block` and `GasPriceOracle` predeploys to the
+    /// state, replacing whatever is at their addresses
+    pub chain_profile: Option<String>,
+    /// The L1 values they report, with the `op-stack` profile
+    pub op_stack: Option<OpStackParams>,
+    #[serde(flatten)]
+    #[schema(inline)]
+    pub persistence: Persistence,
+    /// Derive `stateId`, and `X-Result-Id` when persisted, from the request instead of making
+    /// them up, so the same request gets a byte-identical response. Only the `X-Request-Id`
+    /// header and a persisted result's `createdAt` still differ.
+    #[serde(default)]
+    pub deterministic: bool,
+    /// Reset the sender's nonce to 0 before running, so what it deploys lands at the same
+    /// address whatever state the request starts from, and return `created`: what the address
+    /// of every contract created was derived from
+    #[serde(default)]
+    pub deterministic_addresses: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DeployResponse {
+    /// Missing when the constructor reverted
+    #[schema(value_type = Option<String>)]
+    pub address: Option<Address>,
+    #[serde(flatten)]
+    pub result: ExecutionResult,
+    pub state_id: String,
+    /// Every account the chain has touched, in `stateFormat`
+    #[schema(value_type = BTreeMap<String, AccountDump>)]
+    pub state: EncodedState,
+}
+
+impl Validate for DeployRequest {
+    fn for_legacy(body: &mut Value) {
+        legacy::ask_for_raw_traces(body);
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/deploy",
+    tag = "execute",
+    request_body = DeployRequest,
+    responses(
+        (status = 200, description = "The deployment and the state after it", body = DeployResponse,
+            content_type = ["application/json", "application/msgpack", "application/cbor"],
+            headers(("X-Result-Id" = String, description = "Set when the result was persisted"))),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("apiKey" = []))
+)]
+#[post("/deploy", format = "json", data = "<req>")]
+pub fn deploy_route(
+    _key: ExecuteKey,
+    _work: Work,
+    id: RequestId,
+    config: &State<AppConfig>,
+    snapshots: &State<Snapshots>,
+    results: &State<Results>,
+    req: Checked<DeployRequest>,
+) -> Result<Persisted<Negotiated<DeployResponse>>, ApiError> {
+    let _span = id.span().entered();
+    id.record_execution(None, 1);
+    let req = req.into_inner();
+    let persistence = req.persistence;
+    let request = persistence.request(&req);
+    let derived_id = req.deterministic.then(|| ids::derived(&req));
+    let mut creation_code = req.bytecode.to_vec();
+    creation_code.extend_from_slice(&req.constructor_args.unwrap_or_default());
+    validate::check_bytecode(&config.limits, "bytecode", creation_code.len())?;
+    check_state_format(req.state_format.as_deref())?;
+    validate::check_hardfork(req.hardfork.as_deref())?;
+
+    let state = starting_state(snapshots, req.state_id, req.state)?;
+    let state = with_chain_profile(state, req.chain_profile.as_deref(), req.op_stack.as_ref())?;
+    let caller = req.caller.unwrap_or(DEFAULT_DEPLOYER);
+    let state = with_fresh_nonce(state, caller, req.deterministic_addresses);
+    let (address, result, state) = deploy_local(
+        state,
+        creation_code.into(),
+        req.value.unwrap_or_default(),
+        caller,
+        ExecutionOptions::with_deterministic_addresses(
+            ExecutionOptions::with_hardfork(
+                ExecutionOptions::new(
+                    req.trace_mode,
+                    req.include_raw_traces,
+                    req.collect_logs,
+                    None,
+                    false,
+                ),
+                req.hardfork,
+            ),
+            req.deterministic_addresses,
+        ),
+    )
+    .map_err(ApiError::from_execution)?;
+
+    let response = DeployResponse {
+        address,
+        result,
+        state_id: match derived_id.clone() {
+            Some(id) => snapshots.insert_as(id, state.clone()),
+            None => snapshots.insert(state.clone()),
+        },
+        state: encode_state(state, req.state_format.as_deref()),
+    };
+    let result_id = results.keep(persistence, derived_id, &response, request)?;
+    Ok(Persisted {
+        response: Negotiated(response),
+        result_id,
+    })
+}
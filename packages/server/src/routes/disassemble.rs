@@ -0,0 +1,88 @@
+use crate::auth::ApiKey;
+use crate::compile::cache::Sources;
+use crate::compile::disassembly::{listing, ListingEntry};
+use crate::error::ApiError;
+use alloy_primitives::Bytes;
+use foundry_compilers::artifacts::sourcemap;
+use rocket::{post, serde::json::Json};
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+use super::validate::invalid_field;
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DisassembleRequest {
+    /// Deployed code
+    #[schema(value_type = String)]
+    pub bytecode: Bytes,
+    /// solc's compressed source map for `bytecode`
+    pub source_map: Option<String>,
+    /// The sources `sourceMap` refers to, by name, for naming files and lines
+    #[schema(value_type = Option<Object>)]
+    pub sources: Option<Sources>,
+}
+
+/// Lists deployed code an instruction at a time, with basic blocks, JUMPDEST labels and, given a
+/// source map, the source each instruction was compiled from.
+#[utoipa::path(
+    post,
+    path = "/disassemble",
+    tag = "tools",
+    request_body = DisassembleRequest,
+    responses(
+        (status = 200, description = "The code's instructions, in order", body = Vec<ListingEntry>),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("apiKey" = []))
+)]
+#[post("/disassemble", format = "json", data = "<req>")]
+pub fn disassemble_route(
+    _key: ApiKey,
+    req: Json<DisassembleRequest>,
+) -> Result<Json<Vec<ListingEntry>>, ApiError> {
+    disassemble(req.into_inner()).map(Json)
+}
+
+pub fn disassemble(req: DisassembleRequest) -> Result<Vec<ListingEntry>, ApiError> {
+    let source_map = req
+        .source_map
+        .as_deref()
+        .map(sourcemap::parse)
+        .transpose()
+        .map_err(|err| invalid_field("sourceMap", err))?;
+    Ok(listing(
+        &req.bytecode,
+        source_map.as_deref(),
+        &req.sources.unwrap_or_default(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::bytes;
+
+    #[test]
+    fn test_disassemble_with_source_map() {
+        let source = "contract C { function f() public {} }";
+        let req = DisassembleRequest {
+            // PUSH1 0x80, PUSH1 0x40, MSTORE
+            bytecode: bytes!("6080604052"),
+            source_map: Some("0:37:0:-:0;;".to_string()),
+            sources: Some(Sources::from([("C.sol".to_string(), source.to_string())])),
+        };
+        let listing = disassemble(req).unwrap();
+        assert_eq!(listing.len(), 3);
+        assert!(listing.iter().all(|entry| entry.line == Some(1)
+            && entry.source_file.as_deref() == Some("C.sol")
+            && entry.snippet.as_deref() == Some(source)));
+
+        let req = DisassembleRequest {
+            bytecode: bytes!("6080604052"),
+            source_map: Some("x:y".to_string()),
+            sources: None,
+        };
+        assert_eq!(disassemble(req).err().unwrap().code, "INVALID_FIELD");
+    }
+}
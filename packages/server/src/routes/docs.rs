@@ -0,0 +1,328 @@
+use crate::auth::Usage;
+use crate::caches::CacheStats;
+use crate::compile::bytecode_diff::{Hunk, HunkKind, HunkSide, Instruction, SourceRange};
+use crate::compile::disassembly::ListingEntry;
+use crate::compile::hardhat::HardhatArtifact;
+use crate::compile::imports::{ImportEdge, ImportGraph, ImportKind};
+use crate::compile::solidity::{CompileResult, FailedCluster, SolidityFile};
+use crate::compile::sourcify::{SourcifyMatch, SourcifyPayload};
+use crate::decode::RevertReason;
+use crate::error::ApiError;
+use crate::gas::interop::{CastAccessList, CastResult};
+use crate::gas::op_stack::OpStackParams;
+use crate::gas::summary::{CallStatus, CallSummary};
+use crate::gas::tenderly::{
+    TenderlyAccountDiff, TenderlyBalanceChange, TenderlyCall, TenderlyLog, TenderlyLogInput,
+    TenderlyRawLog, TenderlySimulation, TenderlyStorageChange, TenderlyTransaction,
+};
+use crate::gas::{
+    AccountDiff, AccountDump, Breakpoint, Call, CallEnv, Change, CheckStatus, ConsistencyCheck,
+    ContractGas, CreatedContract, DeploymentGas, Divergence, EventLog, ExecutionResult, ForkCall,
+    ForkConfig, ForkContext, FunctionGas, GasReport, GenesisAccount, PausedAt, ReplayedTransaction,
+    RpcDiagnostics, SkipReason, StorageWrite, Timings, TraceKind, TraceLog, TraceNode, TraceStatus,
+    TransientAccess, TransientAccessKind, TxCheck, TxCheckFailure, TxValidity,
+};
+use crate::gas_snapshots::{GasSnapshot, GasSnapshotDiff, ScenarioDiff};
+use crate::jobs::JobState;
+use crate::results::StoredResult;
+use crate::schema::{
+    CompiledContracts, CompilerError, RevmExecutionResult, SourceLocation, TraceArena,
+};
+use crate::storage::{LayoutEntry, LayoutType, SlotLocation, SlotStep, StorageLayout};
+use rocket::{get, serde::json::Json, Route};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use super::abi::{
+    DecodeConstructorRequest, DecodeConstructorResponse, DecodeRequest, DecodeResponse,
+    DecodeRevertRequest, EncodeRequest, EncodeResponse,
+};
+use super::analyze::AnalyzeImportsRequest;
+use super::compare::{BytecodeSide, CompareBytecodeRequest, CompareBytecodeResponse};
+use super::compile_solidity::CompileRequest;
+use super::deploy::{DeployRequest, DeployResponse};
+use super::disassemble::DisassembleRequest;
+use super::execute_batch::{ExecuteBatchRequest, ScenarioResult};
+use super::execute_calldatas_fork::{ExecuteCalldatasRequest, RawTransactionResult};
+use super::gas_snapshots::GasSnapshotRequest;
+use super::jobs::{JobCreated, SubmitJobRequest};
+use super::replay::ReplayTxRequest;
+use super::run::{RunRequest, RunResponse};
+use super::storage::StorageSlotRequest;
+use super::transact::{TransactRequest, TransactResponse};
+use super::verify::{VerifyRequest, VerifyResponse};
+
+#[derive(OpenApi)]
+#[openapi(
+    servers(
+        (url = "/v1"),
+        (url = "/", description = "Deprecated unprefixed routes, same shapes for now"),
+    ),
+    paths(
+        super::compile_solidity::compile_solidity_route,
+        super::analyze::analyze_imports_route,
+        super::execute_calldatas::execute_calldatas_route,
+        super::execute_calldatas_fork::execute_calldatas_fork_route,
+        super::execute_calldatas_fork::execute_calldatas_fork_stream_route,
+        super::execute_batch::execute_batch_route,
+        super::run::run_route,
+        super::deploy::deploy_route,
+        super::transact::transact_route,
+        super::replay::replay_tx_route,
+        super::results::get_result_route,
+        super::gas_snapshots::save_gas_snapshot_route,
+        super::gas_snapshots::diff_gas_snapshots_route,
+        super::ws::ws_route,
+        super::jobs::submit_job_route,
+        super::jobs::get_job_route,
+        super::jobs::cancel_job_route,
+        super::abi::abi_encode_route,
+        super::abi::abi_decode_route,
+        super::abi::decode_revert_route,
+        super::abi::decode_constructor_route,
+        super::storage::storage_slot_route,
+        super::verify::verify_sourcify_route,
+        super::compare::compare_bytecode_route,
+        super::disassemble::disassemble_route,
+        super::admin::usage_route,
+        super::admin::caches_route,
+        super::admin::flush_caches_route,
+        super::metrics::metrics_route,
+        super::metrics::health_route,
+        openapi_route,
+    ),
+    components(schemas(
+        ApiError,
+        Usage,
+        CacheStats,
+        SolidityFile,
+        CompileRequest,
+        CompileResult,
+        FailedCluster,
+        AnalyzeImportsRequest,
+        ImportGraph,
+        ImportEdge,
+        ImportKind,
+        HardhatArtifact,
+        CompilerError,
+        SourceLocation,
+        CompiledContracts,
+        Call,
+        RevmExecutionResult,
+        ForkCall,
+        ForkConfig,
+        ForkContext,
+        RpcDiagnostics,
+        Timings,
+        ExecuteCalldatasRequest,
+        ExecutionResult,
+        SkipReason,
+        TransientAccess,
+        TransientAccessKind,
+        CreatedContract,
+        Breakpoint,
+        PausedAt,
+        StorageWrite,
+        EventLog,
+        TraceNode,
+        TraceKind,
+        TraceStatus,
+        TraceLog,
+        TraceArena,
+        AccountDiff,
+        Change,
+        TenderlySimulation,
+        TenderlyTransaction,
+        TenderlyCall,
+        TenderlyLog,
+        TenderlyLogInput,
+        TenderlyRawLog,
+        TenderlyAccountDiff,
+        TenderlyBalanceChange,
+        TenderlyStorageChange,
+        CastResult,
+        CastAccessList,
+        RawTransactionResult,
+        CallSummary,
+        CallStatus,
+        CallEnv,
+        TxValidity,
+        TxCheckFailure,
+        TxCheck,
+        ConsistencyCheck,
+        CheckStatus,
+        Divergence,
+        ExecuteBatchRequest,
+        ScenarioResult,
+        RunRequest,
+        RunResponse,
+        GasReport,
+        ContractGas,
+        DeploymentGas,
+        FunctionGas,
+        AccountDump,
+        GenesisAccount,
+        DeployRequest,
+        OpStackParams,
+        DeployResponse,
+        TransactRequest,
+        TransactResponse,
+        ReplayTxRequest,
+        ReplayedTransaction,
+        StoredResult,
+        GasSnapshotRequest,
+        GasSnapshot,
+        GasSnapshotDiff,
+        ScenarioDiff,
+        SubmitJobRequest,
+        JobCreated,
+        JobState,
+        EncodeRequest,
+        EncodeResponse,
+        DecodeRequest,
+        DecodeResponse,
+        DecodeRevertRequest,
+        RevertReason,
+        DecodeConstructorRequest,
+        DecodeConstructorResponse,
+        StorageSlotRequest,
+        StorageLayout,
+        LayoutEntry,
+        LayoutType,
+        SlotLocation,
+        SlotStep,
+        VerifyRequest,
+        VerifyResponse,
+        SourcifyPayload,
+        SourcifyMatch,
+        CompareBytecodeRequest,
+        BytecodeSide,
+        CompareBytecodeResponse,
+        Hunk,
+        HunkKind,
+        HunkSide,
+        Instruction,
+        SourceRange,
+        DisassembleRequest,
+        ListingEntry,
+    )),
+    modifiers(&SecuritySchemes),
+    tags(
+        (name = "compile"),
+        (name = "execute"),
+        (name = "jobs", description = "Fork executions run in the background"),
+        (name = "tools", description = "ABI and storage helpers that don't execute anything"),
+        (name = "admin"),
+        (name = "meta"),
+    )
+)]
+pub struct ApiDoc;
+
+// Keys are only checked when the server is configured with some, so every route also lists an
+// empty requirement
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "apiKey",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-API-Key"))),
+        );
+        components.add_security_scheme(
+            "adminKey",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-Admin-Key"))),
+        );
+    }
+}
+
+/// This document.
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    tag = "meta",
+    responses((status = 200, description = "The OpenAPI 3 document", body = Object))
+)]
+#[get("/openapi.json")]
+pub fn openapi_route() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// Swagger UI at `/docs`, reading `/openapi.json`. Empty unless built with `swagger-ui`.
+pub fn swagger_ui() -> Vec<Route> {
+    #[cfg(feature = "swagger-ui")]
+    {
+        use utoipa_swagger_ui::{Config, SwaggerUi};
+
+        SwaggerUi::new("/docs/<_..>")
+            .config(Config::new(["/openapi.json"]))
+            .into()
+    }
+    #[cfg(not(feature = "swagger-ui"))]
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::Method;
+    use serde_json::Value;
+
+    fn refs(value: &Value, found: &mut Vec<String>) {
+        match value {
+            Value::Object(map) => {
+                if let Some(Value::String(reference)) = map.get("$ref") {
+                    found.push(reference.clone());
+                }
+                map.values().for_each(|value| refs(value, found));
+            }
+            Value::Array(items) => items.iter().for_each(|value| refs(value, found)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn test_document_is_valid() {
+        let document = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let spec: openapiv3::OpenAPI = serde_json::from_value(document.clone()).unwrap();
+        assert!(spec.openapi.starts_with("3."));
+
+        let schemas = &spec.components.unwrap().schemas;
+        let mut found = Vec::new();
+        refs(&document, &mut found);
+        for reference in found {
+            let name = reference
+                .strip_prefix("#/components/schemas/")
+                .unwrap_or_else(|| panic!("unexpected reference {}", reference));
+            assert!(schemas.contains_key(name), "{} isn't defined", name);
+        }
+    }
+
+    #[test]
+    fn test_every_route_documented() {
+        let spec: openapiv3::OpenAPI =
+            serde_json::from_value(serde_json::to_value(ApiDoc::openapi()).unwrap()).unwrap();
+        for route in crate::routes::routes() {
+            // Rocket's `<id>` is OpenAPI's `{id}`
+            let path = route.uri.path().replace('<', "{").replace('>', "}");
+            let item = spec
+                .paths
+                .paths
+                .get(&path)
+                .and_then(|item| item.as_item())
+                .unwrap_or_else(|| panic!("{} isn't documented", path));
+            let operation = match route.method {
+                Method::Get => &item.get,
+                Method::Post => &item.post,
+                Method::Delete => &item.delete,
+                other => panic!("unexpected method {}", other),
+            };
+            assert!(
+                operation.is_some(),
+                "{} {} isn't documented",
+                route.method,
+                path
+            );
+        }
+    }
+}
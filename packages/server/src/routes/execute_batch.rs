@@ -0,0 +1,385 @@
+use super::execute_calldatas_fork::{requested_chain_id, resolve_fork, ExecuteCalldatasRequest};
+use super::validate::{check_deadline, invalid_field, Checked, Validate};
+use crate::admission::ForkSlot;
+use crate::auth::ExecuteKey;
+use crate::config::AppConfig;
+use crate::error::ApiError;
+use crate::format::Negotiated;
+use crate::gas::ens::EnsError;
+use crate::gas::{
+    execute_batch_fork, ExecutionOptions, ExecutionResult, ForkConfig, ResolvedFork, Scenario,
+};
+use crate::legacy;
+use crate::shutdown::Work;
+use crate::telemetry::RequestId;
+use rocket::{http::Status, post, State};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+use tracing::Instrument;
+use utoipa::ToSchema;
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecuteBatchRequest {
+    // Parsed one by one so a malformed scenario doesn't fail the whole batch
+    #[schema(value_type = Vec<ExecuteCalldatasRequest>)]
+    pub scenarios: Vec<Value>,
+    pub fork_config: Option<ForkConfig>,
+    pub trace_mode: Option<String>,
+    /// Also return forge's trace arena as `rawTraces`. Deprecated.
+    #[serde(default)]
+    pub include_raw_traces: bool,
+    /// Record the events calls emit as `logs`. Defaults to true.
+    pub collect_logs: Option<bool>,
+    pub concurrency: Option<usize>,
+    /// Milliseconds from when the batch comes in after which calls that haven't started are
+    /// skipped, as `deadlineMs` skips a request's. Each scenario's first call always runs. At
+    /// most, and by default, `requestDeadlineMs`; without it the first valid scenario's.
+    pub deadline_ms: Option<u64>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ScenarioResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub results: Option<Vec<ExecutionResult>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ApiError>,
+}
+
+impl ScenarioResult {
+    fn failed(error: ApiError) -> Self {
+        ScenarioResult {
+            results: None,
+            error: Some(error),
+        }
+    }
+}
+
+impl Validate for ExecuteBatchRequest {
+    fn for_legacy(body: &mut Value) {
+        legacy::ask_for_raw_traces(body);
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/execute_batch",
+    tag = "execute",
+    request_body = ExecuteBatchRequest,
+    responses(
+        (status = 200, description = "One entry per scenario, in order", body = Vec<ScenarioResult>,
+            content_type = ["application/json", "application/msgpack", "application/cbor"]),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("apiKey" = []))
+)]
+#[post("/execute_batch", format = "json", data = "<req>")]
+pub async fn execute_batch_route(
+    _key: ExecuteKey,
+    _work: Work,
+    _slot: ForkSlot,
+    id: RequestId,
+    config: &State<AppConfig>,
+    req: Checked<ExecuteBatchRequest>,
+) -> Result<Negotiated<Vec<ScenarioResult>>, ApiError> {
+    let calls = req
+        .scenarios
+        .iter()
+        .map(|scenario| scenario["calls"].as_array().map_or(0, Vec::len))
+        .sum();
+    let results = execute_batch(config, req.into_inner(), |fork| {
+        id.record_execution(requested_chain_id(fork), calls)
+    })
+    .instrument(id.span())
+    .await?;
+    Ok(Negotiated(results))
+}
+
+/// Runs the batch's scenarios on one fork, calling `on_fork` with the fork they share once its
+/// settings are settled.
+pub async fn execute_batch(
+    config: &AppConfig,
+    req: ExecuteBatchRequest,
+    on_fork: impl FnOnce(&ResolvedFork),
+) -> Result<Vec<ScenarioResult>, ApiError> {
+    let started = Instant::now();
+    let limits = &config.limits;
+    check_deadline(limits, req.deadline_ms)?;
+    if req.scenarios.len() > limits.max_batch_scenarios {
+        return Err(ApiError::new(
+            Status::UnprocessableEntity,
+            "TOO_MANY_SCENARIOS",
+            format!(
+                "batch has {} scenarios, the limit is {}",
+                req.scenarios.len(),
+                limits.max_batch_scenarios
+            ),
+        )
+        .with_details(json!({
+            "limit": limits.max_batch_scenarios,
+            "actual": req.scenarios.len(),
+        })));
+    }
+
+    let parsed: Vec<Result<ExecuteCalldatasRequest, ApiError>> = req
+        .scenarios
+        .into_iter()
+        .enumerate()
+        .map(|(i, scenario)| {
+            serde_json::from_value(scenario)
+                .map_err(|err| ApiError::invalid_request(format!("scenarios[{}]: {}", i, err)))
+        })
+        .collect();
+
+    // The batch-level settings override the scenarios'; without them the first valid scenario
+    // decides, and scenarios that disagree can't share the fork
+    let first = parsed.iter().find_map(|scenario| scenario.as_ref().ok());
+    let fork_config = req
+        .fork_config
+        .clone()
+        .or_else(|| first.and_then(|s| s.fork_config.clone()));
+    let trace_mode = req
+        .trace_mode
+        .clone()
+        .or_else(|| first.and_then(|s| s.trace_mode.clone()));
+    let include_raw_traces = req.include_raw_traces || first.is_some_and(|s| s.include_raw_traces);
+    let collect_logs = req
+        .collect_logs
+        .or_else(|| first.and_then(|s| s.collect_logs));
+    let deadline = started
+        + req
+            .deadline_ms
+            .or_else(|| first.and_then(|s| s.deadline_ms))
+            .map_or(limits.request_deadline, Duration::from_millis);
+    let fork = resolve_fork(config, fork_config.as_ref())?;
+    on_fork(&fork);
+
+    let mut slots = Vec::with_capacity(parsed.len());
+    let mut scenarios = Vec::new();
+    for (i, scenario) in parsed.into_iter().enumerate() {
+        let scenario = scenario.and_then(|scenario| {
+            scenario
+                .validate(limits)
+                .and_then(|_| scenario.check_no_raw_transactions("batches"))
+                .map_err(|mut err| {
+                    err.message = format!("scenarios[{}]: {}", i, err.message);
+                    err
+                })?;
+            if let Some(option) = unsupported_option(&scenario) {
+                return Err(invalid_field(
+                    &format!("scenarios[{}].{}", i, option),
+                    "isn't available for batches",
+                ));
+            }
+            if req.fork_config.is_none()
+                && scenario.fork_config.is_some()
+                && scenario.fork_config != fork_config
+            {
+                return Err(ApiError::invalid_request(format!(
+                    "scenarios[{}].forkConfig differs from the other scenarios; set forkConfig on the batch instead",
+                    i
+                )));
+            }
+            if req.trace_mode.is_none()
+                && scenario.trace_mode.is_some()
+                && scenario.trace_mode != trace_mode
+            {
+                return Err(ApiError::invalid_request(format!(
+                    "scenarios[{}].traceMode differs from the other scenarios; set traceMode on the batch instead",
+                    i
+                )));
+            }
+            Ok(scenario)
+        });
+        match scenario {
+            Ok(scenario) => {
+                slots.push(Ok(scenarios.len()));
+                let calls = scenario.effective_calls();
+                scenarios.push(Scenario {
+                    bytecode: scenario.bytecode,
+                    address: scenario.address,
+                    calls,
+                    signers: scenario.signers.unwrap_or_default(),
+                    state_overrides: scenario.state_overrides,
+                });
+            }
+            Err(err) => slots.push(Err(err)),
+        }
+    }
+
+    let options = ExecutionOptions::with_deadline(
+        ExecutionOptions::new(trace_mode, include_raw_traces, collect_logs, None, false),
+        Some(deadline),
+    );
+    let concurrency = req
+        .concurrency
+        .unwrap_or(limits.max_batch_concurrency)
+        .min(limits.max_batch_concurrency);
+
+    let mut outcomes: Vec<Option<Result<Vec<ExecutionResult>, eyre::Error>>> =
+        if scenarios.is_empty() {
+            Vec::new()
+        } else {
+            execute_batch_fork(config, scenarios, fork, options, concurrency)
+                .await
+                .map_err(ApiError::from_execution)?
+                .into_iter()
+                .map(Some)
+                .collect()
+        };
+
+    Ok(slots
+        .into_iter()
+        .enumerate()
+        .map(|(i, slot)| match slot {
+            Ok(index) => match outcomes[index].take() {
+                Some(Ok(results)) => ScenarioResult {
+                    results: Some(results),
+                    error: None,
+                },
+                Some(Err(mut err)) => {
+                    // A name that didn't resolve is reported against the scenario it came from
+                    if let Some(ens) = err.downcast_mut::<EnsError>() {
+                        ens.field = format!("scenarios[{}].{}", i, ens.field);
+                    }
+                    ScenarioResult::failed(ApiError::from_execution(err))
+                }
+                None => unreachable!("each scenario result is taken once"),
+            },
+            Err(err) => ScenarioResult::failed(err),
+        })
+        .collect())
+}
+
+// The first per-request option set that the scenarios' shared engine can't apply to one
+// scenario alone
+fn unsupported_option(scenario: &ExecuteCalldatasRequest) -> Option<&'static str> {
+    [
+        ("hardfork", scenario.hardfork.is_some()),
+        ("traceExport", scenario.trace_export.is_some()),
+        ("flamegraph", scenario.flamegraph),
+        ("stateDiff", scenario.state_diff),
+        ("accessList", scenario.access_list),
+        ("consistencyCheck", scenario.consistency_check),
+        ("journal", scenario.journal),
+        ("transientStorage", scenario.transient_storage),
+        ("deterministicAddresses", scenario.deterministic_addresses),
+        ("captureSubcallOutputs", scenario.capture_subcall_outputs),
+        ("labels", scenario.labels.is_some()),
+        ("retries", scenario.retries.is_some()),
+    ]
+    .into_iter()
+    .find_map(|(option, set)| set.then_some(option))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::{Bytes, B256, U256};
+
+    fn scenario(bytecode: &str) -> Value {
+        json!({
+            "bytecode": bytecode,
+            "address": "0xb2f9974c62815d3177079e150377915d9bc49c82",
+            "calls": [{
+                "calldata": "0x6d4ce63c",
+                "value": "0x0",
+                "caller": "0x1000000000000000000000000000000000000000"
+            }]
+        })
+    }
+
+    fn demo() -> Option<ForkConfig> {
+        Some(ForkConfig {
+            network: Some("demo".to_string()),
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_batch_isolates_invalid_scenario() {
+        // get() on a contract that returns storage slot 0
+        let bytecode = "0x60005460005260206000f3";
+        let req = ExecuteBatchRequest {
+            scenarios: vec![scenario(bytecode), scenario("0xzz"), scenario(bytecode)],
+            fork_config: demo(),
+            trace_mode: None,
+            include_raw_traces: false,
+            collect_logs: None,
+            concurrency: Some(2),
+            deadline_ms: None,
+        };
+
+        let results = execute_batch(&AppConfig::default(), req, |_| {})
+            .await
+            .unwrap();
+
+        assert_eq!(results.len(), 3);
+        assert!(results[0].results.is_some());
+        assert_eq!(
+            results[1].error.as_ref().map(|err| err.code),
+            Some("INVALID_REQUEST")
+        );
+        assert!(results[2].results.is_some());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_batch_scenario_options() {
+        let bytecode = "0x60005460005260206000f3";
+        let mut overridden = scenario(bytecode);
+        overridden["stateOverrides"] = json!({
+            "0xb2f9974c62815d3177079e150377915d9bc49c82": {
+                "stateDiff": { B256::ZERO.to_string(): B256::with_last_byte(42).to_string() }
+            }
+        });
+        let mut hardfork = scenario(bytecode);
+        hardfork["hardfork"] = json!("cancun");
+        let req = ExecuteBatchRequest {
+            scenarios: vec![overridden, hardfork, scenario(bytecode)],
+            fork_config: demo(),
+            trace_mode: None,
+            include_raw_traces: false,
+            collect_logs: None,
+            concurrency: None,
+            deadline_ms: None,
+        };
+
+        let results = execute_batch(&AppConfig::default(), req, |_| {})
+            .await
+            .unwrap();
+
+        // The override reaches its own scenario and no other
+        let slot = |result: &ScenarioResult| result.results.as_ref().unwrap()[0].result.clone();
+        assert_eq!(
+            slot(&results[0]),
+            Bytes::from(U256::from(42).to_be_bytes_vec())
+        );
+        assert_eq!(slot(&results[2]), Bytes::from(U256::ZERO.to_be_bytes_vec()));
+        let err = results[1].error.as_ref().unwrap();
+        assert_eq!(err.code, "INVALID_FIELD");
+        assert_eq!(
+            err.details.as_ref().unwrap()["field"],
+            "scenarios[1].hardfork"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_batch_scenario_limit() {
+        let config = AppConfig::default();
+        let req = ExecuteBatchRequest {
+            scenarios: vec![scenario("0x00"); config.limits.max_batch_scenarios + 1],
+            fork_config: None,
+            trace_mode: None,
+            include_raw_traces: false,
+            collect_logs: None,
+            concurrency: None,
+            deadline_ms: None,
+        };
+
+        let err = execute_batch(&config, req, |_| {}).await.err().unwrap();
+        assert_eq!(err.status, Status::UnprocessableEntity);
+        assert_eq!(err.code, "TOO_MANY_SCENARIOS");
+    }
+}
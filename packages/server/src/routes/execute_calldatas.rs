@@ -1,26 +1,68 @@
-use crate::gas::{execute_calldatas, Call};
+use crate::auth::ExecuteKey;
+use crate::config::{AppConfig, Limits};
+use crate::error::ApiError;
+use crate::format::Negotiated;
+use crate::gas::{execute_calldatas, Call, CallResult};
+use crate::shutdown::Work;
+use crate::telemetry::RequestId;
 use alloy_primitives::hex;
-use revm::primitives::{Bytecode, ExecutionResult};
-use rocket::{post, response::status, serde::json::Json};
+use revm::primitives::Bytecode;
+use rocket::{post, State};
 use serde::Deserialize;
+use utoipa::ToSchema;
 
-#[derive(Deserialize)]
+use super::validate::{self, Checked, Validate};
+
+#[derive(Deserialize, ToSchema)]
 pub struct ExecuteCalldatasRequest {
+    /// Creation code, hex
     pub bytecode: String,
     pub calls: Vec<Call>,
 }
 
+impl Validate for ExecuteCalldatasRequest {}
+
+#[utoipa::path(
+    post,
+    path = "/execute_calldatas",
+    tag = "execute",
+    request_body = inline(ExecuteCalldatasRequest),
+    responses(
+        (status = 200, description = "One result per call", body = Vec<RevmExecutionResult>,
+            content_type = ["application/json", "application/msgpack", "application/cbor"]),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("apiKey" = []))
+)]
 #[post("/execute_calldatas", format = "json", data = "<req>")]
 pub fn execute_calldatas_route(
-    req: Json<ExecuteCalldatasRequest>,
-) -> Result<Json<Vec<ExecutionResult>>, status::BadRequest<Option<String>>> {
-    let result = handle(req).map_err(|err| status::BadRequest(Some(err.to_string())))?;
-    Ok(Json(result))
+    _key: ExecuteKey,
+    _work: Work,
+    id: RequestId,
+    config: &State<AppConfig>,
+    req: Checked<ExecuteCalldatasRequest>,
+) -> Result<Negotiated<Vec<CallResult>>, ApiError> {
+    let _span = id.span().entered();
+    id.record_execution(None, req.calls.len());
+    let result = handle(&config.limits, req)?;
+    Ok(Negotiated(result))
 }
 
-fn handle(req: Json<ExecuteCalldatasRequest>) -> Result<Vec<ExecutionResult>, eyre::Error> {
-    let bytecode = hex::decode(&req.bytecode).map_err(|err| eyre::eyre!(err.to_string()))?;
+fn handle(
+    limits: &Limits,
+    req: Checked<ExecuteCalldatasRequest>,
+) -> Result<Vec<CallResult>, ApiError> {
+    let bytecode = hex::decode(&req.bytecode)
+        .map_err(|err| ApiError::invalid_request(format!("bytecode: {}", err)))?;
+    validate::check_bytecode(limits, "bytecode", bytecode.len())?;
+    validate::check_calls(
+        limits,
+        req.calls
+            .iter()
+            .map(|call| call.calldata.as_ref().map_or(0, |calldata| calldata.len())),
+    )?;
+    validate::check_call_meta(limits, req.calls.iter().map(|call| call.meta.as_ref()))?;
     let result = execute_calldatas(Bytecode::new_raw(bytecode.into()), req.calls.clone())
-        .map_err(|err| eyre::eyre!(err.to_string()))?;
+        .map_err(ApiError::from_execution)?;
     Ok(result)
 }
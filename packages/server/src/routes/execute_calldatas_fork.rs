@@ -1,43 +1,2334 @@
-use crate::gas::{execute_calldatas_fork, ExecutionResult, ForkCall, ForkConfig};
+use super::validate::{
+    check_address, check_calldata, check_fields, check_hex, check_known, check_u256, Checked,
+    Validate,
+};
+use crate::admission::ForkSlot;
+use crate::auth::ExecuteKey;
+use crate::config::{AppConfig, Limits};
+use crate::error::ApiError;
+use crate::fields::{Fields, Pruned};
+use crate::format::Negotiated;
+use crate::gas::ens::NameOrAddress;
+use crate::gas::interop::{self, CastResult};
+use crate::gas::summary::{summarize, CallSummary};
+use crate::gas::tenderly::{self, TenderlySimulation};
+use crate::gas::{
+    execute_calldatas_fork_with, execute_raw_transactions_fork, ExecutionOptions, ExecutionResult,
+    ForkCall, ForkConfig, ForkContext, ForkProgress, ForkSource, NamedStateOverride, ResolvedFork,
+    Timings, DEFAULT_DEPLOYER,
+};
+use crate::ids;
+use crate::legacy;
+use crate::results::{stored_request, Persisted, Persistence, Results};
+use crate::shutdown::{InFlight, Work};
+use crate::telemetry::RequestId;
+use alloy_json_abi::JsonAbi;
 use alloy_primitives::Address;
 use alloy_primitives::Bytes;
-use rocket::{post, response::status, serde::json::Json};
-use serde::Deserialize;
+use alloy_primitives::B256;
+use rocket::http::ContentType;
+use rocket::request::Request;
+use rocket::response::stream::{ByteStream, Event, EventStream};
+use rocket::response::{self, Responder};
+use rocket::{post, Either, State};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::Instrument;
+use utoipa::ToSchema;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ExecuteCalldatasRequest {
+    /// Runtime code, placed at `address`. Code that looks like creation code is refused unless
+    /// `allowCreationBytecode` is set.
+    #[schema(value_type = String)]
     pub bytecode: Bytes,
-    pub address: Address,
+    /// Place `bytecode` even though it looks like creation code
+    #[serde(default)]
+    pub allow_creation_bytecode: bool,
+    /// Where `bytecode` is placed and the calls go. An ENS name on chains with ENS, resolved at
+    /// the fork's block.
+    #[schema(value_type = String)]
+    pub address: NameOrAddress,
+    /// Left out, or empty, with `rawTransactions`
+    #[serde(default)]
     pub calls: Vec<ForkCall>,
+    /// Signed transactions to run instead of `calls`, hex-encoded as `eth_sendRawTransaction`
+    /// takes them: legacy, EIP-2930, EIP-1559 or blob. Each runs in order from the account that
+    /// signed it, with the gas limit, fees and access list it was signed with, and comes back
+    /// with its hash and sender, or an error of its own when it doesn't decode, its signature
+    /// doesn't recover or it was signed for another chain. Nonces aren't checked. Only for the
+    /// plain response, without `outputFormat`, `fields` or `consistencyCheck`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Vec<String>>)]
+    pub raw_transactions: Option<Vec<Bytes>>,
+    /// The caller of every call that doesn't give its own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub default_caller: Option<Address>,
+    /// Private keys by their address, for `$sign712` templates in calls' `args` to sign with,
+    /// e.g. a `permit` for the fork's chain. Test keys only: they're sent in the clear, so never a
+    /// key that holds anything. Left out of persisted requests and derived ids.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<BTreeMap<String, String>>)]
+    pub signers: Option<BTreeMap<Address, B256>>,
+    /// A name for each call, in the same order, that gas snapshots taken of the persisted
+    /// response key its gas by. Each must be different.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scenario_labels: Option<Vec<String>>,
     pub fork_config: Option<ForkConfig>,
-    pub trace_mode: Option<String>, // Options: "call", "jump", "jumpSimple", "debug", "none"
+    /// `call`, `jump`, `jumpSimple`, `debug` or `none`. `debug` records every opcode, which is
+    /// slower, and so do `journal` and `transientStorage` whatever this says. Calls recording
+    /// opcodes are given at most 5,000,000 gas.
+    pub trace_mode: Option<String>,
+    /// Also return forge's trace arena as `rawTraces`. Deprecated.
+    #[serde(default)]
+    pub include_raw_traces: bool,
+    /// Record the events calls emit as `logs`. Defaults to true.
+    pub collect_logs: Option<bool>,
+    /// Adds `traceExport` to each result: its trace in Chrome's Trace Event Format for `chrome`,
+    /// with opcodes under `traceMode: "debug"`, as forge's text tree for `pretty`, or as a
+    /// Mermaid sequence diagram for `mermaid`.
+    pub trace_export: Option<String>,
+    /// How many calls deep a `mermaid` diagram goes, counting the top-level call as 1. All of
+    /// them when missing.
+    pub trace_export_depth: Option<usize>,
+    /// Add `flamegraph` to each result, its trace as gas-weighted folded stacks
+    #[serde(default)]
+    pub flamegraph: bool,
+    /// Add `stateDiff` to each result, the balances and storage the call changed
+    #[serde(default)]
+    pub state_diff: bool,
+    /// Add `accessList` to each result, the accounts and slots the call touched
+    #[serde(default)]
+    pub access_list: bool,
+    /// Add `consistencyCheck` to each result: the same call run by the fork's node, through
+    /// `debug_traceCall` or else `eth_call`, and where its success, return data or gas (within
+    /// 1%) differ. Only calls to the contract on chain, with `bytecode` its deployed code and no
+    /// committing call before them, are compared. Not for streamed responses.
+    #[serde(default)]
+    pub consistency_check: bool,
+    /// Run every call under this hardfork's rules, whatever the chain and block: `frontier`,
+    /// `homestead`, `tangerineWhistle`, `spuriousDragon`, `byzantium`, `constantinople`,
+    /// `petersburg`, `istanbul`, `berlin`, `london`, `paris`, `shanghai`, `cancun` or `prague`.
+    /// The latest without one. `forkContext.hardfork` says which ran.
+    pub hardfork: Option<String>,
+    /// Accounts to change before the calls run, in `eth_call`'s state override set format: per
+    /// address, any of `balance`, `nonce`, `code`, and either `state`, which replaces the whole
+    /// storage, or `stateDiff`, which only sets the slots given. Accounts may be ENS names on
+    /// chains with ENS. Applied after `bytecode` is placed, so an override for `address` changes
+    /// it too.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub state_overrides: Option<NamedStateOverride>,
+    /// Add `journal` to each result: what the call did, event by event (storage writes, logs,
+    /// calls in and out, transfers, creates and selfdestructs), each with the opcode it happened
+    /// at.
+    #[serde(default)]
+    pub journal: bool,
+    /// Add `transientStorageAccesses` to each result: every TLOAD and TSTORE, with the slot,
+    /// value and frame, and reads that find a reentrancy lock taken flagged.
+    #[serde(default)]
+    pub transient_storage: bool,
+    /// Default `defaultCaller` to `0x1804c8AB1F12E6bbf3894d4083f33e07309d1f38`, and add
+    /// `created` to each result: the deployer and nonce the address of every contract a call
+    /// created was derived from
+    #[serde(default)]
+    pub deterministic_addresses: bool,
+    /// Add `revertReason` to each frame in `traces` that reverted, so a revert a contract caught
+    /// can still be read. Custom errors are named from `abi`. Each frame's `output` is cut to
+    /// 24 KiB, with `outputLength` saying how long it was.
+    #[serde(default)]
+    pub capture_subcall_outputs: bool,
+    /// Names for addresses, used in `labels` and trace exports over the well-known contracts
+    /// the server knows on the chain, e.g. `WETH` for `0x4200…0006` on Base
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<BTreeMap<String, String>>)]
+    pub labels: Option<BTreeMap<Address, String>>,
+    /// Run a call again, up to this many more times, when the fork's node fails to answer for
+    /// state it loads, e.g. with a timeout or a 5xx. A committing call starts over from the state
+    /// before it, so nothing the failed run wrote is kept. Each result then says how many runs
+    /// it took in `attempts`. Errors from the EVM itself are never retried. At most
+    /// `maxCallRetries`.
+    pub retries: Option<u32>,
+    /// Milliseconds from when the request comes in after which calls that haven't started are
+    /// skipped, each coming back with `skipped: "deadlineExceeded"`, and the response has an
+    /// `X-Deadline-Hit: true` header, and with `summary` `deadlineHit: true` too. The first call
+    /// always runs, and a call that has started runs to the end. At most, and by default,
+    /// `requestDeadlineMs`. Raw transactions are skipped the same way. A job counts it from when
+    /// it starts running, and without it runs every call.
+    pub deadline_ms: Option<u64>,
+    /// `tenderly` returns each result in the shape of a Tenderly simulation instead, state diff
+    /// included; `cast` as `cast call --json` and `cast access-list --json` print it. Not for
+    /// streamed responses; batches and jobs ignore it.
+    pub output_format: Option<String>,
+    /// Names functions and events in `tenderly` output and decodes return values in `cast`
+    /// output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Vec<Object>>)]
+    pub abi: Option<JsonAbi>,
+    /// Keep the response so it can be fetched from `/results/<id>`. The id comes back in the
+    /// `X-Result-Id` header.
+    #[serde(default)]
+    pub persist: bool,
+    /// Keep the request alongside it, with `forkConfig.rpcUrl` cut down to its host and without
+    /// `signers`
+    #[serde(default)]
+    pub persist_request: bool,
+    /// Accept mixed-case addresses whose EIP-55 checksum doesn't match
+    #[serde(default)]
+    pub skip_checksum: bool,
+    /// Only these top-level fields of each result, e.g. `["gasUsed", "reverted"]`. Jobs and
+    /// batches keep every field.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Vec<String>>)]
+    pub fields: Option<Fields>,
+    /// Answer with `{results, summary}` rather than the results alone. `summary` has a line per
+    /// call, `{callIndex, status, gasUsed, revertReason?, primaryEvent?}`: `status` is
+    /// `success`, `revert`, `error` for other halts and raw transactions that didn't run, or
+    /// `skipped` for calls past the deadline, and
+    /// `primaryEvent` names the first event found in `abi`. It's made from the full results, so
+    /// `fields` doesn't leave anything out of it. Streamed responses always have one, in the
+    /// `done` event or as `calls` on the NDJSON `summary` line. Jobs and batches ignore it.
+    #[serde(default)]
+    pub summary: bool,
+    /// Answer the same request with the same bytes: forks need `forkConfig.blockNumber`,
+    /// streamed responses leave out `timings` and `rpcDiagnostics`, and `X-Result-Id` is derived
+    /// from the request.
+    /// Only the `X-Request-Id` header and a persisted result's `createdAt` still differ, and a
+    /// fork's results only stay the same while its node serves the same block.
+    #[serde(default)]
+    pub deterministic: bool,
 }
 
-#[post("/execute_calldatas_fork", format = "json", data = "<req>")]
+impl ExecuteCalldatasRequest {
+    // The same fields as the other routes' flattened `Persistence`. They're spelt out here since
+    // flattening would buffer the request through serde's untyped content, which reads
+    // MessagePack's binary addresses and numbers as if they were JSON and lists no fields for
+    // `check_known`.
+    fn persistence(&self) -> Persistence {
+        Persistence {
+            persist: self.persist,
+            persist_request: self.persist_request,
+        }
+    }
+
+    pub(super) fn validate(&self, limits: &Limits) -> Result<(), ApiError> {
+        super::validate::check_bytecode(limits, "bytecode", self.bytecode.len())?;
+        if !self.allow_creation_bytecode {
+            super::validate::check_runtime_code("bytecode", &self.bytecode)?;
+        }
+        super::validate::check_calls(limits, self.calls.iter().map(|call| call.calldata.len()))?;
+        super::validate::check_call_meta(limits, self.calls.iter().map(|call| call.meta.as_ref()))?;
+        for (i, call) in self.calls.iter().enumerate() {
+            match (&call.signature, &call.args) {
+                (Some(_), _) if !call.calldata.is_empty() => {
+                    return Err(super::validate::invalid_field(
+                        &format!("calls[{}].signature", i),
+                        "can't be combined with calldata",
+                    ))
+                }
+                (None, Some(_)) => {
+                    return Err(super::validate::invalid_field(
+                        &format!("calls[{}].args", i),
+                        "is only used with signature",
+                    ))
+                }
+                _ => {}
+            }
+        }
+        if let Some(signers) = &self.signers {
+            crate::gas::templates::check_signers(signers).map_err(|(address, message)| {
+                super::validate::invalid_field(&format!("signers.{}", address), message)
+            })?;
+        }
+        if self.default_caller().is_none() {
+            if let Some(i) = self.calls.iter().position(|call| call.caller.is_none()) {
+                return Err(super::validate::invalid_field(
+                    &format!("calls[{}].caller", i),
+                    "is required without defaultCaller",
+                ));
+            }
+        }
+        if self
+            .trace_export
+            .as_deref()
+            .is_some_and(|export| !["chrome", "pretty", "mermaid"].contains(&export))
+        {
+            return Err(super::validate::invalid_field(
+                "traceExport",
+                "expected chrome, pretty or mermaid",
+            ));
+        }
+        if self
+            .output_format
+            .as_deref()
+            .is_some_and(|format| !["tenderly", "cast"].contains(&format))
+        {
+            return Err(super::validate::invalid_field(
+                "outputFormat",
+                "expected tenderly or cast",
+            ));
+        }
+        super::validate::check_hardfork(self.hardfork.as_deref())?;
+        super::validate::check_retries(limits, self.retries)?;
+        super::validate::check_deadline(limits, self.deadline_ms)?;
+        if let Some(transactions) = &self.raw_transactions {
+            self.check_raw_transactions(limits, transactions)?;
+        }
+        self.check_scenario_labels()?;
+        super::validate::check_state_overrides(self.state_overrides.as_ref())?;
+        if self.output_format.is_some() && self.fields.is_some() {
+            return Err(super::validate::invalid_field(
+                "fields",
+                "can't be combined with outputFormat",
+            ));
+        }
+        // The latest block moves on between requests; the demo chain doesn't
+        if self.deterministic
+            && self.fork_config.as_ref().map_or(true, |fork| {
+                fork.network.is_none() && fork.block_number.is_none()
+            })
+        {
+            return Err(super::validate::invalid_field(
+                "forkConfig.blockNumber",
+                "is required with deterministic",
+            ));
+        }
+        if self.trace_export_depth == Some(0) {
+            return Err(super::validate::invalid_field(
+                "traceExportDepth",
+                "must be at least 1",
+            ));
+        }
+        // Sending value changes state, which an independent call would lose
+        match self
+            .calls
+            .iter()
+            .position(|call| call.independent && !call.value.is_zero())
+        {
+            Some(i) => Err(super::validate::invalid_field(
+                &format!("calls[{}].independent", i),
+                "can't be set on a call that sends value",
+            )),
+            None => Ok(()),
+        }
+    }
+
+    // `defaultCaller`, or the fixed one `deterministicAddresses` falls back on
+    fn default_caller(&self) -> Option<Address> {
+        self.default_caller
+            .or(self.deterministic_addresses.then_some(DEFAULT_DEPLOYER))
+    }
+
+    /// `calls`, with `defaultCaller` for those that leave out `caller`.
+    pub(super) fn effective_calls(&self) -> Vec<ForkCall> {
+        self.calls
+            .iter()
+            .map(|call| ForkCall {
+                caller: call
+                    .caller
+                    .clone()
+                    .or(self.default_caller().map(Into::into)),
+                ..call.clone()
+            })
+            .collect()
+    }
+
+    /// When calls stop being started, for a request that came in at `started`.
+    pub(super) fn deadline(&self, limits: &Limits, started: Instant) -> Instant {
+        started
+            + self
+                .deadline_ms
+                .map_or(limits.request_deadline, Duration::from_millis)
+    }
+
+    pub(super) fn options(&self) -> Option<crate::gas::ExecutionOptions> {
+        let state_diff = self.state_diff || self.output_format.as_deref() == Some("tenderly");
+        let access_list = self.access_list || self.output_format.as_deref() == Some("cast");
+        crate::gas::ExecutionOptions::new(
+            self.trace_mode.clone(),
+            self.include_raw_traces,
+            self.collect_logs,
+            self.trace_export.clone(),
+            self.flamegraph,
+        )
+        .or_else(|| {
+            (state_diff
+                || access_list
+                || self.consistency_check
+                || self.hardfork.is_some()
+                || self.state_overrides.is_some()
+                || self.journal
+                || self.transient_storage
+                || self.deterministic_addresses
+                || self.capture_subcall_outputs
+                || self.labels.is_some()
+                || self.signers.is_some()
+                || self.retries.is_some())
+            .then(Default::default)
+        })
+        .map(|options| crate::gas::ExecutionOptions {
+            trace_export_depth: self.trace_export_depth,
+            state_diff,
+            access_list,
+            consistency_check: self.consistency_check,
+            hardfork: self.hardfork.clone(),
+            state_overrides: self.state_overrides.clone(),
+            journal: self.journal,
+            transient_storage: self.transient_storage,
+            deterministic_addresses: self.deterministic_addresses,
+            capture_subcall_outputs: self.capture_subcall_outputs,
+            errors: self.abi.as_ref().map(|abi| abi.errors().cloned().collect()),
+            labels: self.labels.clone(),
+            signers: self.signers.clone(),
+            retries: self.retries.unwrap_or_default(),
+            ..options
+        })
+    }
+
+    // Raw transactions stand in for calls, in the plain response only
+    fn check_raw_transactions(
+        &self,
+        limits: &Limits,
+        transactions: &[Bytes],
+    ) -> Result<(), ApiError> {
+        super::validate::check_calls(limits, transactions.iter().map(|tx| tx.len()))?;
+        let conflicting = [
+            ("calls", !self.calls.is_empty()),
+            ("outputFormat", self.output_format.is_some()),
+            ("fields", self.fields.is_some()),
+            ("consistencyCheck", self.consistency_check),
+        ];
+        match conflicting.iter().find(|(_, set)| *set) {
+            Some((field, _)) => Err(super::validate::invalid_field(
+                field,
+                "can't be combined with rawTransactions",
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Refuses `rawTransactions` where only `calls` can run, e.g. in jobs.
+    pub(super) fn check_no_raw_transactions(&self, place: &str) -> Result<(), ApiError> {
+        match self.raw_transactions {
+            Some(_) => Err(super::validate::invalid_field(
+                "rawTransactions",
+                format!("isn't available for {}", place),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    // One label per call, none repeated
+    fn check_scenario_labels(&self) -> Result<(), ApiError> {
+        let Some(labels) = &self.scenario_labels else {
+            return Ok(());
+        };
+        let calls = self
+            .raw_transactions
+            .as_ref()
+            .map_or(self.calls.len(), Vec::len);
+        if labels.len() != calls {
+            return Err(super::validate::invalid_field(
+                "scenarioLabels",
+                format!("has {} labels for {} calls", labels.len(), calls),
+            ));
+        }
+        let mut seen = HashSet::new();
+        match labels.iter().position(|label| !seen.insert(label)) {
+            Some(i) => Err(super::validate::invalid_field(
+                &format!("scenarioLabels[{}]", i),
+                format!("repeats {}", labels[i]),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    // Rejects what only the plain JSON response can do
+    fn check_streamable(&self) -> Result<(), ApiError> {
+        self.check_no_raw_transactions("streamed responses")?;
+        if self.output_format.is_some() {
+            return Err(super::validate::invalid_field(
+                "outputFormat",
+                "isn't available for streamed responses",
+            ));
+        }
+        // Results go out as they come, before the node could be asked about them
+        if self.consistency_check {
+            return Err(super::validate::invalid_field(
+                "consistencyCheck",
+                "isn't available for streamed responses",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Validate for ExecuteCalldatasRequest {
+    fn check_json(body: &Value) -> Result<(), ApiError> {
+        let skip_checksum = body["skipChecksum"].as_bool().unwrap_or(false);
+        check_hex(&body["bytecode"], "bytecode", false)?;
+        // Anything else is an ENS name, resolved later
+        if body["address"]
+            .as_str()
+            .map_or(true, |address| address.starts_with("0x"))
+        {
+            check_address(&body["address"], "address", skip_checksum)?;
+        }
+        check_fields(&body["fields"], "fields")?;
+        if let Some(transactions) = body["rawTransactions"].as_array() {
+            for (i, tx) in transactions.iter().enumerate() {
+                check_hex(tx, &format!("rawTransactions[{}]", i), false)?;
+            }
+        }
+        if !body["defaultCaller"].is_null() {
+            check_address(&body["defaultCaller"], "defaultCaller", skip_checksum)?;
+        }
+        let Some(calls) = body["calls"].as_array() else {
+            return Ok(());
+        };
+        for (i, call) in calls.iter().enumerate() {
+            // Encoded from signature and args, once the fork is up
+            if call["signature"].is_null() || !call["calldata"].is_null() {
+                check_calldata(&call["calldata"], &format!("calls[{}].calldata", i))?;
+            }
+            check_u256(&call["value"], &format!("calls[{}].value", i))?;
+            // Anything else is an ENS name, resolved later. Left out, it's `defaultCaller`.
+            if !call["caller"].is_null()
+                && call["caller"]
+                    .as_str()
+                    .map_or(true, |caller| caller.starts_with("0x"))
+            {
+                check_address(
+                    &call["caller"],
+                    &format!("calls[{}].caller", i),
+                    skip_checksum,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_known_fields(body: &Value) -> Result<(), ApiError> {
+        check_known::<Self>(body, "")?;
+        check_known::<ForkConfig>(&body["forkConfig"], "forkConfig")?;
+        for (i, call) in body["calls"].as_array().into_iter().flatten().enumerate() {
+            check_known::<ForkCall>(call, &format!("calls[{}]", i))?;
+        }
+        Ok(())
+    }
+
+    fn for_legacy(body: &mut Value) {
+        if let Some(fields) = body.as_object_mut() {
+            let options = fields.entry("options").or_insert(Value::Null);
+            if options.is_null() {
+                *options = Value::Object(Default::default());
+            }
+            legacy::ask_for_raw_traces(options);
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/execute_calldatas_fork",
+    tag = "execute",
+    request_body(content = ExecuteCalldatasRequest,
+        description = "JSON, or MessagePack sent as `application/msgpack` with byte fields and \
+            addresses as binary"),
+    params(
+        (
+            "format" = Option<String>, Query,
+            description = "`ndjson` streams a `forkContext` line, a `result` line per call as \
+                it completes, then a `summary` line (or an `error` line) instead"
+        ),
+        (
+            "lenient" = Option<bool>, Query,
+            description = "`true` ignores JSON keys that aren't fields, as older servers did, \
+                instead of refusing them with `UNKNOWN_FIELD`"
+        ),
+    ),
+    responses(
+        (status = 200, description = "One result per call, or with `outputFormat: \"tenderly\"` \
+            one `TenderlySimulation` per call, or with `\"cast\"` one `CastResult`, or with \
+            `rawTransactions` one `RawTransactionResult` per transaction. With `summary`, those as \
+            `results` alongside a `summary`, and `deadlineHit` once `deadlineMs` skipped calls.",
+            body = Vec<ExecutionResult>,
+            content_type = [
+                "application/json", "application/msgpack", "application/cbor",
+                "application/x-ndjson",
+            ],
+            headers(
+                ("X-Result-Id" = String, description = "Set when the result was persisted"),
+                ("X-Deadline-Hit" = String, description = "`true` when `deadlineMs` skipped calls"),
+            )),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("apiKey" = []))
+)]
+#[post("/execute_calldatas_fork?<format>", format = "json", data = "<req>")]
+#[allow(clippy::too_many_arguments)]
 pub async fn execute_calldatas_fork_route(
-    req: Json<ExecuteCalldatasRequest>,
-) -> Result<Json<Vec<ExecutionResult>>, status::BadRequest<Option<String>>> {
-    println!("Received request with fork_config: {:?}", req.fork_config);
-    println!("Trace mode: {:?}", req.trace_mode);
+    _key: ExecuteKey,
+    _work: Work,
+    slot: ForkSlot,
+    id: RequestId,
+    in_flight: &State<InFlight>,
+    config: &State<AppConfig>,
+    results: &State<Results>,
+    format: Option<&str>,
+    req: Checked<ExecuteCalldatasRequest>,
+) -> Result<
+    Either<DeadlineHit<Persisted<Negotiated<Output>>>, (ContentType, ByteStream![Vec<u8>])>,
+    ApiError,
+> {
+    let started = Instant::now();
+    req.validate(&config.limits)?;
+    let fork = resolve_fork(config, req.fork_config.as_ref())?;
+    id.record_execution(requested_chain_id(&fork), req.calls.len());
+    match format {
+        None => {}
+        Some("ndjson") => {
+            req.check_streamable()?;
+            let mut lines = id.span().in_scope(|| {
+                ndjson_calldatas_fork(in_flight, config.inner().clone(), req.into_inner(), fork)
+            })?;
+            return Ok(Either::Right((
+                ContentType::new("application", "x-ndjson"),
+                ByteStream! {
+                    // Held until the stream ends
+                    let _slot = slot;
+                    while let Some(line) = lines.recv().await {
+                        yield line;
+                    }
+                },
+            )));
+        }
+        Some(other) => {
+            return Err(ApiError::invalid_request(format!(
+                "unknown format {}, expected ndjson",
+                other
+            )))
+        }
+    }
+    let request = req.persistence().request(&*req);
+    let derived_id = req
+        .deterministic
+        .then(|| ids::derived(&stored_request(&*req)));
+
+    let deadline = req.deadline(&config.limits, started);
+    let (output, hit) = match req.raw_transactions.clone() {
+        Some(transactions) => {
+            raw_transactions_output(config, &req, fork, transactions, deadline)
+                .instrument(id.span())
+                .await?
+        }
+        None => {
+            calls_output(config, &req, fork, deadline)
+                .instrument(id.span())
+                .await?
+        }
+    };
+    let result_id = results.keep(req.persistence(), derived_id, &output, request)?;
+    Ok(Either::Left(DeadlineHit {
+        response: Persisted {
+            response: Negotiated(output),
+            result_id,
+        },
+        hit,
+    }))
+}
 
+/// A response sent with `X-Deadline-Hit: true` when the deadline skipped calls.
+pub struct DeadlineHit<R> {
+    pub response: R,
+    pub hit: bool,
+}
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for DeadlineHit<R> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'o> {
+        let mut response = self.response.respond_to(req)?;
+        if self.hit {
+            response.set_raw_header("X-Deadline-Hit", "true");
+        }
+        Ok(response)
+    }
+}
+
+// The request's calls, their results in the format it asked for, skipping those not started
+// by `deadline`, and whether any were
+async fn calls_output(
+    config: &AppConfig,
+    req: &ExecuteCalldatasRequest,
+    fork: ResolvedFork,
+    deadline: Instant,
+) -> Result<(Output, bool), ApiError> {
     // Create execution options with the specified trace mode
-    let options = req
-        .trace_mode
-        .as_ref()
-        .map(|trace_mode| crate::gas::ExecutionOptions {
-            trace_mode: Some(trace_mode.clone()),
-        });
+    let options = ExecutionOptions::with_deadline(req.options(), Some(deadline));
+    let calls = req.effective_calls();
 
-    let result = execute_calldatas_fork(
+    let (context, _, result) = execute_calldatas_fork_with(
+        config,
         req.bytecode.clone(),
-        req.address,
-        req.calls.clone(),
-        req.fork_config.clone(),
+        req.address.clone(),
+        calls.clone(),
+        fork,
         options,
+        |_| Ok(()),
     )
     .await
-    .map_err(|err| status::BadRequest(Some(err.to_string())))?;
+    .map_err(ApiError::from_execution)?;
+
+    let summary = req.summary.then(|| summarize(&result, req.abi.as_ref()));
+    let deadline_hit = result.iter().any(|result| result.skipped.is_some());
+    let output = match req.output_format.as_deref() {
+        Some("cast") => Output::Cast(
+            calls
+                .iter()
+                .zip(&result)
+                .map(|(call, result)| interop::cast_result(call, result, req.abi.as_ref()))
+                .collect(),
+        ),
+        Some(_) => Output::Tenderly(
+            calls
+                .iter()
+                .zip(&result)
+                .map(|(call, result)| {
+                    tenderly::simulation(&context, &req.address, call, result, req.abi.as_ref())
+                })
+                .collect(),
+        ),
+        None => Output::Results(Pruned {
+            value: result,
+            fields: req.fields,
+        }),
+    };
+    Ok((enveloped(output, summary, deadline_hit), deadline_hit))
+}
+
+// The request's raw transactions, each with its result or why it couldn't run, skipping those
+// not started by `deadline`, and whether any were
+async fn raw_transactions_output(
+    config: &AppConfig,
+    req: &ExecuteCalldatasRequest,
+    fork: ResolvedFork,
+    transactions: Vec<Bytes>,
+    deadline: Instant,
+) -> Result<(Output, bool), ApiError> {
+    let (_, _, outcomes) = execute_raw_transactions_fork(
+        config,
+        req.bytecode.clone(),
+        req.address.clone(),
+        transactions,
+        fork,
+        ExecutionOptions::with_deadline(req.options(), Some(deadline)),
+    )
+    .await
+    .map_err(ApiError::from_execution)?;
+    let deadline_hit = outcomes
+        .iter()
+        .any(|outcome| matches!(&outcome.result, Ok(result) if result.skipped.is_some()));
+    let summary = req.summary.then(|| {
+        outcomes
+            .iter()
+            .enumerate()
+            .map(|(i, outcome)| match &outcome.result {
+                Ok(result) => CallSummary::of(i, result, req.abi.as_ref()),
+                Err(_) => CallSummary::failed(i),
+            })
+            .collect()
+    });
+    let output = Output::RawTransactions(
+        outcomes
+            .into_iter()
+            .map(|outcome| {
+                let (result, error) = match outcome.result {
+                    Ok(result) => (Some(result), None),
+                    Err(err) => (None, Some(ApiError::from_execution(err))),
+                };
+                RawTransactionResult {
+                    hash: outcome.hash,
+                    from: outcome.from,
+                    result,
+                    error,
+                }
+            })
+            .collect(),
+    );
+    Ok((enveloped(output, summary, deadline_hit), deadline_hit))
+}
+
+// `output` with `summary` alongside it when the request asked for one, along with `deadlineHit`
+// when calls were skipped
+fn enveloped(output: Output, summary: Option<Vec<CallSummary>>, deadline_hit: bool) -> Output {
+    match summary {
+        None => output,
+        summary => Output::Enveloped {
+            results: Box::new(output),
+            summary,
+            deadline_hit,
+        },
+    }
+}
+
+/// The body of an `/execute_calldatas_fork` response, in the format the request asked for.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum Output {
+    Results(Pruned<Vec<ExecutionResult>>),
+    Tenderly(Vec<TenderlySimulation>),
+    Cast(Vec<CastResult>),
+    RawTransactions(Vec<RawTransactionResult>),
+    /// With `summary`
+    #[serde(rename_all = "camelCase")]
+    Enveloped {
+        results: Box<Output>,
+        summary: Option<Vec<CallSummary>>,
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        deadline_hit: bool,
+    },
+}
+
+/// What one of `rawTransactions` did: its result, or the error it failed with.
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RawTransactionResult {
+    /// Missing when the transaction didn't decode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub hash: Option<B256>,
+    /// Who signed it. Missing when the signature didn't recover.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub from: Option<Address>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<ExecutionResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ApiError>,
+}
+
+/// `/execute_calldatas_fork` for a MessagePack body. Bytecode, calldata and addresses go as
+/// binary rather than hex strings, about half the size of the JSON request.
+#[post("/execute_calldatas_fork?<format>", format = "msgpack", data = "<req>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn execute_calldatas_fork_msgpack_route(
+    key: ExecuteKey,
+    work: Work,
+    slot: ForkSlot,
+    id: RequestId,
+    in_flight: &State<InFlight>,
+    config: &State<AppConfig>,
+    results: &State<Results>,
+    format: Option<&str>,
+    req: Checked<ExecuteCalldatasRequest>,
+) -> Result<
+    Either<DeadlineHit<Persisted<Negotiated<Output>>>, (ContentType, ByteStream![Vec<u8>])>,
+    ApiError,
+> {
+    execute_calldatas_fork_route(key, work, slot, id, in_flight, config, results, format, req).await
+}
+
+/// Settles `forkConfig` before any work starts, so settings that contradict each other get a 422
+/// naming the field.
+pub(super) fn resolve_fork(
+    config: &AppConfig,
+    fork_config: Option<&ForkConfig>,
+) -> Result<ResolvedFork, ApiError> {
+    fork_config
+        .cloned()
+        .unwrap_or_default()
+        .resolve(config)
+        .map_err(|err| ApiError::from_execution(err.into()))
+}
+
+/// The chain a request forks, for the access log. Unknown when it brings its own RPC URL, or uses
+/// the managed anvil, without saying which chain that is.
+pub(super) fn requested_chain_id(fork: &ResolvedFork) -> Option<u64> {
+    match fork.source {
+        ForkSource::Pool(chain_id) => Some(fork.chain_id.unwrap_or(chain_id)),
+        _ => fork.chain_id,
+    }
+}
+
+/// One Server-Sent Event on the streaming route. A stream is zero or more `Result`s followed by
+/// exactly one `Done` or `Error`.
+#[derive(Debug)]
+pub enum StreamEvent {
+    Result {
+        index: usize,
+        result: Box<ExecutionResult>,
+    },
+    Done {
+        fork_context: ForkContext,
+        /// Missing for deterministic requests
+        timings: Option<Timings>,
+        summary: Vec<CallSummary>,
+        /// Whether the deadline skipped calls
+        deadline_hit: bool,
+    },
+    Error(ApiError),
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ResultEvent<'a> {
+    index: usize,
+    result: Pruned<&'a ExecutionResult>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DoneEvent<'a> {
+    fork_context: &'a ForkContext,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timings: Option<&'a Timings>,
+    summary: &'a [CallSummary],
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    deadline_hit: bool,
+}
+
+impl StreamEvent {
+    fn into_event(self, fields: Option<Fields>) -> Event {
+        match self {
+            StreamEvent::Result { index, result } => Event::json(&ResultEvent {
+                index,
+                result: Pruned {
+                    value: &result,
+                    fields,
+                },
+            })
+            .event("result"),
+            StreamEvent::Done {
+                fork_context,
+                timings,
+                summary,
+                deadline_hit,
+            } => Event::json(&DoneEvent {
+                fork_context: &fork_context,
+                timings: timings.as_ref(),
+                summary: &summary,
+                deadline_hit,
+            })
+            .event("done"),
+            StreamEvent::Error(err) => Event::json(&err).event("error"),
+        }
+    }
+}
+
+/// Same as `/execute_calldatas_fork`, but sends each call's result as soon as it completes
+/// instead of waiting for the whole batch.
+#[utoipa::path(
+    post,
+    path = "/execute_calldatas_fork/stream",
+    tag = "execute",
+    request_body = ExecuteCalldatasRequest,
+    params((
+        "lenient" = Option<bool>, Query,
+        description = "`true` ignores JSON keys that aren't fields instead of refusing them"
+    )),
+    responses(
+        (
+            status = 200,
+            description = "Server-Sent Events: `result` ({index, result}) per call, then one \
+                `done` ({forkContext, timings, summary, deadlineHit?}) or `error` (an ApiError)",
+            content_type = "text/event-stream",
+            body = String,
+        ),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("apiKey" = []))
+)]
+#[post("/execute_calldatas_fork/stream", format = "json", data = "<req>")]
+pub fn execute_calldatas_fork_stream_route(
+    _key: ExecuteKey,
+    slot: ForkSlot,
+    id: RequestId,
+    in_flight: &State<InFlight>,
+    config: &State<AppConfig>,
+    req: Checked<ExecuteCalldatasRequest>,
+) -> Result<EventStream![], ApiError> {
+    req.validate(&config.limits)?;
+    req.check_streamable()?;
+    let fork = resolve_fork(config, req.fork_config.as_ref())?;
+    id.record_execution(requested_chain_id(&fork), req.calls.len());
+    let fields = req.fields;
+    let mut events = id.span().in_scope(|| {
+        stream_calldatas_fork(in_flight, config.inner().clone(), req.into_inner(), fork)
+    })?;
+    Ok(EventStream! {
+        // Held until the stream ends
+        let _slot = slot;
+        while let Some(event) = events.recv().await {
+            yield event.into_event(fields);
+        }
+    })
+}
+
+/// Runs the request on a background task, forking as `fork` says, sending events as they happen.
+/// The channel closes after the final `Done` or `Error`; if the client goes away, execution stops
+/// at the next call.
+pub fn stream_calldatas_fork(
+    in_flight: &InFlight,
+    config: AppConfig,
+    req: ExecuteCalldatasRequest,
+    fork: ResolvedFork,
+) -> Result<mpsc::UnboundedReceiver<StreamEvent>, ApiError> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    in_flight.spawn(
+        async move {
+            let deadline = req.deadline(&config.limits, Instant::now());
+            let options = ExecutionOptions::with_deadline(req.options(), Some(deadline));
+            let deterministic = req.deterministic;
+            let abi = req.abi.clone();
+            let outcome = execute_calldatas_fork_with(
+                &config,
+                req.bytecode,
+                req.address,
+                req.effective_calls(),
+                fork,
+                options,
+                |progress| match progress {
+                    ForkProgress::Forked(_) => Ok(()),
+                    ForkProgress::Result(index, result) => tx
+                        .send(StreamEvent::Result {
+                            index,
+                            result: Box::new(result.clone()),
+                        })
+                        .map_err(|_| eyre::eyre!("stream closed by client")),
+                },
+            )
+            .await;
+
+            let last = match outcome {
+                Ok((fork_context, timings, results)) => StreamEvent::Done {
+                    fork_context: ForkContext {
+                        // What the node says about itself changes from one request to the next
+                        rpc_diagnostics: fork_context.rpc_diagnostics.filter(|_| !deterministic),
+                        ..fork_context
+                    },
+                    timings: (!deterministic).then_some(timings),
+                    summary: summarize(&results, abi.as_ref()),
+                    deadline_hit: results.iter().any(|result| result.skipped.is_some()),
+                },
+                Err(err) => StreamEvent::Error(ApiError::from_execution(err)),
+            };
+            let _ = tx.send(last);
+        }
+        .in_current_span(),
+    )?;
+    Ok(rx)
+}
+
+/// Lines of `?format=ndjson` output buffered ahead of the client.
+const NDJSON_BUFFER: usize = 16;
+
+/// One line of `?format=ndjson` output.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum NdjsonLine<'a> {
+    ForkContext(&'a ForkContext),
+    Result {
+        index: usize,
+        result: Pruned<&'a ExecutionResult>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Summary {
+        total_gas_used: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        timings: Option<Timings>,
+        /// A line per call, as the plain response's `summary`
+        calls: Vec<CallSummary>,
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        deadline_hit: bool,
+    },
+    Error(ApiError),
+}
+
+impl NdjsonLine<'_> {
+    fn to_line(&self) -> Vec<u8> {
+        // Nothing in a line can fail to serialize
+        let mut line = serde_json::to_vec(self).expect("ndjson line serializes");
+        line.push(b'\n');
+        line
+    }
+}
+
+/// Runs the request on a background task, forking as `fork` says, writing one JSON line per event
+/// into a channel. The channel holds `NDJSON_BUFFER` lines, so a slow client holds execution back
+/// instead of results piling up in memory, and a client that goes away stops it at the next call.
+pub fn ndjson_calldatas_fork(
+    in_flight: &InFlight,
+    config: AppConfig,
+    req: ExecuteCalldatasRequest,
+    fork: ResolvedFork,
+) -> Result<mpsc::Receiver<Vec<u8>>, ApiError> {
+    let (tx, rx) = mpsc::channel(NDJSON_BUFFER);
+    in_flight.spawn(
+        async move {
+            // Progress is reported from synchronous execution, so wait for room off the runtime
+            let send = |line: Vec<u8>| {
+                tokio::task::block_in_place(|| tx.blocking_send(line))
+                    .map_err(|_| eyre::eyre!("stream closed by client"))
+            };
+            let deadline = req.deadline(&config.limits, Instant::now());
+            let options = ExecutionOptions::with_deadline(req.options(), Some(deadline));
+            let fields = req.fields;
+            let deterministic = req.deterministic;
+            let abi = req.abi.clone();
+            let outcome = execute_calldatas_fork_with(
+                &config,
+                req.bytecode,
+                req.address,
+                req.effective_calls(),
+                fork,
+                options,
+                |progress| match progress {
+                    ForkProgress::Forked(context) if deterministic => {
+                        let context = ForkContext {
+                            rpc_diagnostics: None,
+                            ..context.clone()
+                        };
+                        send(NdjsonLine::ForkContext(&context).to_line())
+                    }
+                    ForkProgress::Forked(context) => {
+                        send(NdjsonLine::ForkContext(context).to_line())
+                    }
+                    ForkProgress::Result(index, result) => send(
+                        NdjsonLine::Result {
+                            index,
+                            result: Pruned {
+                                value: result,
+                                fields,
+                            },
+                        }
+                        .to_line(),
+                    ),
+                },
+            )
+            .await;
+
+            let last = match outcome {
+                Ok((_, timings, results)) => NdjsonLine::Summary {
+                    total_gas_used: results.iter().map(|result| result.gas_used).sum(),
+                    timings: (!deterministic).then_some(timings),
+                    calls: summarize(&results, abi.as_ref()),
+                    deadline_hit: results.iter().any(|result| result.skipped.is_some()),
+                },
+                Err(err) => NdjsonLine::Error(ApiError::from_execution(err)),
+            };
+            let _ = tx.send(last.to_line()).await;
+        }
+        .in_current_span(),
+    )?;
+    Ok(rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::U256;
+    use std::str::FromStr;
+
+    fn request(fork_config: Option<ForkConfig>) -> ExecuteCalldatasRequest {
+        let caller = Address::from_str("0x1000000000000000000000000000000000000000").unwrap();
+        ExecuteCalldatasRequest {
+            // get() on a contract that returns storage slot 0
+            bytecode: Bytes::from_str("0x60005460005260206000f3").unwrap(),
+            allow_creation_bytecode: false,
+            address: "0xb2f9974c62815d3177079e150377915d9bc49c82"
+                .parse()
+                .unwrap(),
+            calls: (0..3)
+                .map(|_| ForkCall {
+                    calldata: Bytes::from_str("0x6d4ce63c").unwrap(),
+                    value: Default::default(),
+                    caller: Some(caller.into()),
+                    independent: false,
+                    signature: None,
+                    args: None,
+                    meta: None,
+                })
+                .collect(),
+            default_caller: None,
+            signers: None,
+            scenario_labels: None,
+            fork_config,
+            trace_mode: None,
+            include_raw_traces: false,
+            collect_logs: None,
+            trace_export: None,
+            trace_export_depth: None,
+            flamegraph: false,
+            state_diff: false,
+            access_list: false,
+            consistency_check: false,
+            hardfork: None,
+            state_overrides: None,
+            journal: false,
+            transient_storage: false,
+            deterministic_addresses: false,
+            capture_subcall_outputs: false,
+            labels: None,
+            retries: None,
+            deadline_ms: None,
+            output_format: None,
+            abi: None,
+            persist: false,
+            persist_request: false,
+            skip_checksum: false,
+            fields: None,
+            summary: false,
+            deterministic: false,
+        }
+    }
+
+    async fn collect(mut rx: mpsc::UnboundedReceiver<StreamEvent>) -> Vec<StreamEvent> {
+        let mut events = Vec::new();
+        while let Some(event) = rx.recv().await {
+            events.push(event);
+        }
+        events
+    }
+
+    fn stream(
+        config: AppConfig,
+        req: ExecuteCalldatasRequest,
+    ) -> mpsc::UnboundedReceiver<StreamEvent> {
+        let fork = resolve_fork(&config, req.fork_config.as_ref()).unwrap();
+        stream_calldatas_fork(&InFlight::default(), config, req, fork).unwrap()
+    }
+
+    #[test]
+    fn test_independent_calls_send_no_value() {
+        let mut req = request(None);
+        req.calls[1].independent = true;
+        assert!(req.validate(&Limits::default()).is_ok());
+
+        req.calls[1].value = U256::from(1);
+        let err = req.validate(&Limits::default()).unwrap_err();
+        assert_eq!(err.code, "INVALID_FIELD");
+        assert_eq!(
+            err.details.unwrap()["field"],
+            serde_json::json!("calls[1].independent")
+        );
+    }
+
+    #[test]
+    fn test_scenario_labels() {
+        let mut req = request(None);
+        let labels = |labels: &[&str]| Some(labels.iter().map(|l| l.to_string()).collect());
+        req.scenario_labels = labels(&["first", "second", "third"]);
+        assert!(req.validate(&Limits::default()).is_ok());
+
+        req.scenario_labels = labels(&["first", "second"]);
+        let err = req.validate(&Limits::default()).unwrap_err();
+        assert_eq!(err.message, "scenarioLabels has 2 labels for 3 calls");
+
+        req.scenario_labels = labels(&["first", "second", "first"]);
+        let err = req.validate(&Limits::default()).unwrap_err();
+        assert_eq!(
+            err.details.unwrap()["field"],
+            serde_json::json!("scenarioLabels[2]")
+        );
+    }
+
+    #[test]
+    fn test_creation_bytecode_refused() {
+        let mut req = request(None);
+        // The runtime code above, with the constructor solc would put in front of it
+        req.bytecode = Bytes::from_str(
+            "0x6080604052348015600e575f80fd5b50600b80601a5f395ff3fe60005460005260206000f3",
+        )
+        .unwrap();
+        let err = req.validate(&Limits::default()).unwrap_err();
+        assert_eq!(err.status, rocket::http::Status::UnprocessableEntity);
+        assert_eq!(err.code, "CREATION_BYTECODE");
+        assert_eq!(err.details.unwrap()["field"], serde_json::json!("bytecode"));
+
+        req.allow_creation_bytecode = true;
+        assert!(req.validate(&Limits::default()).is_ok());
+    }
+
+    #[test]
+    fn test_unknown_trace_export() {
+        let mut req = request(None);
+        req.trace_export = Some("chrome".to_string());
+        assert!(req.validate(&Limits::default()).is_ok());
+        req.trace_export = Some("pretty".to_string());
+        assert!(req.validate(&Limits::default()).is_ok());
+        req.trace_export = Some("mermaid".to_string());
+        req.trace_export_depth = Some(2);
+        assert!(req.validate(&Limits::default()).is_ok());
+
+        req.trace_export_depth = Some(0);
+        let err = req.validate(&Limits::default()).unwrap_err();
+        assert_eq!(
+            err.details.unwrap()["field"],
+            serde_json::json!("traceExportDepth")
+        );
+        req.trace_export_depth = None;
+
+        req.trace_export = Some("perfetto".to_string());
+        let err = req.validate(&Limits::default()).unwrap_err();
+        assert_eq!(err.code, "INVALID_FIELD");
+        assert_eq!(
+            err.details.unwrap()["field"],
+            serde_json::json!("traceExport")
+        );
+    }
+
+    #[test]
+    fn test_output_format() {
+        let mut req = request(None);
+        req.output_format = Some("tenderly".to_string());
+        assert!(req.validate(&Limits::default()).is_ok());
+        assert!(req.options().unwrap().state_diff);
+        req.output_format = Some("cast".to_string());
+        assert!(req.validate(&Limits::default()).is_ok());
+        let options = req.options().unwrap();
+        assert!(options.access_list && !options.state_diff);
+
+        req.fields = Some(Fields::parse(["gasUsed"]).unwrap());
+        let err = req.validate(&Limits::default()).unwrap_err();
+        assert_eq!(err.details.unwrap()["field"], serde_json::json!("fields"));
+        req.fields = None;
+
+        req.output_format = Some("foundry".to_string());
+        let err = req.validate(&Limits::default()).unwrap_err();
+        assert_eq!(
+            err.details.unwrap()["field"],
+            serde_json::json!("outputFormat")
+        );
+    }
+
+    fn client() -> rocket::local::blocking::Client {
+        use crate::admission::Gates;
+        use crate::auth::Auth;
+        use crate::results::MemoryStore;
+        use rocket::routes;
+
+        let config = AppConfig::default();
+        let rocket = rocket::build()
+            .manage(Auth::new(&config))
+            .manage(InFlight::default())
+            .manage(Gates::new(&config.limits))
+            .manage(Results::new(MemoryStore::default(), &config.limits))
+            .manage(config)
+            .mount("/", routes![execute_calldatas_fork_route]);
+        rocket::local::blocking::Client::tracked(rocket).unwrap()
+    }
+
+    #[test]
+    fn test_raw_transactions() {
+        use alloy_consensus::{SignableTransaction, TxEip1559, TxEnvelope};
+        use alloy_eips::eip2718::Encodable2718;
+        use alloy_primitives::{hex, TxKind};
+        use alloy_signer::SignerSync;
+        use alloy_signer_wallet::LocalWallet;
+        use rocket::http::Status;
+        use serde_json::json;
+
+        let client = client();
+        let wallet = LocalWallet::random();
+        let address = Address::from_str("0xb2f9974c62815d3177079e150377915d9bc49c82").unwrap();
+        let tx = TxEip1559 {
+            chain_id: crate::gas::demo::DEMO_CHAIN_ID,
+            nonce: 0,
+            gas_limit: 100_000,
+            max_fee_per_gas: 100_000_000_000,
+            max_priority_fee_per_gas: 1_000_000_000,
+            to: TxKind::Call(address),
+            value: U256::ZERO,
+            access_list: Default::default(),
+            // set(42)
+            input: Bytes::from_str(
+                "0x60fe47b1000000000000000000000000000000000000000000000000000000000000002a",
+            )
+            .unwrap(),
+        };
+        let signature = wallet.sign_hash_sync(&tx.signature_hash()).unwrap();
+        let raw = TxEnvelope::from(tx.into_signed(signature)).encoded_2718();
+
+        // Stores calldata[4..36] in slot 0
+        let mut body = json!({
+            "bytecode": "0x60243610600e57600435600055005b60005460005260206000f3",
+            "address": address,
+            "rawTransactions": [format!("0x{}", hex::encode(&raw)), "0x02c0"],
+            "forkConfig": { "network": "demo" },
+            "stateOverrides": {
+                wallet.address().to_string(): { "balance": "0xde0b6b3a7640000" }
+            },
+        });
+        let response = client
+            .post("/execute_calldatas_fork")
+            .header(ContentType::JSON)
+            .body(body.to_string())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let results = response.into_json::<Value>().unwrap();
+
+        let from: Address = serde_json::from_value(results[0]["from"].clone()).unwrap();
+        assert_eq!(from, wallet.address());
+        assert_eq!(results[0]["hash"], json!(alloy_primitives::keccak256(&raw)));
+        assert_eq!(results[0]["result"]["success"], true);
+        // The second doesn't decode, which fails it alone
+        assert!(results[1]["result"].is_null());
+        assert_eq!(results[1]["error"]["code"], "INVALID_TRANSACTION");
+        assert_eq!(
+            results[1]["error"]["details"]["field"],
+            "rawTransactions[1]"
+        );
+
+        body["calls"] = json!([{
+            "calldata": "0x6d4ce63c",
+            "value": "0",
+            "caller": "0x1000000000000000000000000000000000000000",
+        }]);
+        let response = client
+            .post("/execute_calldatas_fork")
+            .header(ContentType::JSON)
+            .body(body.to_string())
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let err = response.into_json::<Value>().unwrap();
+        assert_eq!(err["details"]["field"], "calls");
+    }
+
+    // Just enough of an ERC20Permit for permit then transferFrom
+    const PERMIT_TOKEN: &str = r#"
+        // SPDX-License-Identifier: MIT
+        pragma solidity ^0.8.0;
+
+        contract PermitToken {
+            mapping(address => uint256) public balanceOf;
+            mapping(address => mapping(address => uint256)) public allowance;
+            mapping(address => uint256) public nonces;
+
+            function DOMAIN_SEPARATOR() public view returns (bytes32) {
+                return keccak256(abi.encode(
+                    keccak256("EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)"),
+                    keccak256("Permit Token"),
+                    keccak256("1"),
+                    block.chainid,
+                    address(this)
+                ));
+            }
+
+            function permit(
+                address owner,
+                address spender,
+                uint256 value,
+                uint256 deadline,
+                uint8 v,
+                bytes32 r,
+                bytes32 s
+            ) external {
+                require(block.timestamp <= deadline, "expired");
+                bytes32 structHash = keccak256(abi.encode(
+                    keccak256("Permit(address owner,address spender,uint256 value,uint256 nonce,uint256 deadline)"),
+                    owner,
+                    spender,
+                    value,
+                    nonces[owner]++,
+                    deadline
+                ));
+                bytes32 digest = keccak256(abi.encodePacked("\x19\x01", DOMAIN_SEPARATOR(), structHash));
+                address signer = ecrecover(digest, v, r, s);
+                require(signer != address(0) && signer == owner, "invalid signature");
+                allowance[owner][spender] = value;
+            }
+
+            function transferFrom(address from, address to, uint256 value) external returns (bool) {
+                allowance[from][msg.sender] -= value;
+                balanceOf[from] -= value;
+                balanceOf[to] += value;
+                return true;
+            }
+        }
+    "#;
+
+    #[test]
+    fn test_permit_then_transfer_from() {
+        use crate::compile::solidity::{compile, SolidityFile};
+        use crate::config::Limits;
+        use alloy_primitives::{keccak256, B256};
+        use rocket::http::Status;
+        use serde_json::json;
+
+        let compiled = compile(
+            &[SolidityFile {
+                name: "PermitToken.sol".to_string(),
+                content: PERMIT_TOKEN.to_string(),
+            }],
+            &Limits::default(),
+        )
+        .unwrap();
+        let runtime = compiled
+            .contracts
+            .find_first("PermitToken")
+            .and_then(|c| c.bin_runtime.and_then(|b| b.as_bytes().cloned()))
+            .unwrap();
+
+        let client = client();
+        let token = "0xb2f9974c62815d3177079e150377915d9bc49c82";
+        // anvil's first test account
+        let owner = "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266";
+        let key = "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+        let spender = "0x1000000000000000000000000000000000000000";
+        let recipient = "0x2000000000000000000000000000000000000000";
+        // balanceOf[owner], in slot 0
+        let owner_balance = keccak256(
+            [
+                B256::left_padding_from(Address::from_str(owner).unwrap().as_slice()).as_slice(),
+                B256::ZERO.as_slice(),
+            ]
+            .concat(),
+        );
+
+        let body = json!({
+            "bytecode": runtime,
+            "address": token,
+            "calls": [
+                {
+                    "signature": "permit(address,address,uint256,uint256,uint8,bytes32,bytes32)",
+                    "args": [owner, spender, "100", "2000000000", { "$sign712": {
+                        "domain": { "name": "Permit Token", "version": "1", "verifyingContract": token },
+                        "types": {
+                            "EIP712Domain": [
+                                { "name": "name", "type": "string" },
+                                { "name": "version", "type": "string" },
+                                { "name": "chainId", "type": "uint256" },
+                                { "name": "verifyingContract", "type": "address" },
+                            ],
+                            "Permit": [
+                                { "name": "owner", "type": "address" },
+                                { "name": "spender", "type": "address" },
+                                { "name": "value", "type": "uint256" },
+                                { "name": "nonce", "type": "uint256" },
+                                { "name": "deadline", "type": "uint256" },
+                            ],
+                        },
+                        "message": {
+                            "owner": owner,
+                            "spender": spender,
+                            "value": 100,
+                            "nonce": 0,
+                            "deadline": 2000000000,
+                        },
+                        "signer": owner,
+                    }}],
+                    "value": "0",
+                    "caller": spender,
+                },
+                {
+                    "signature": "transferFrom(address,address,uint256)",
+                    "args": [owner, recipient, "100"],
+                    "value": "0",
+                    "caller": spender,
+                },
+                {
+                    "signature": "balanceOf(address)",
+                    "args": [recipient],
+                    "value": "0",
+                    "caller": spender,
+                },
+            ],
+            "forkConfig": { "network": "demo" },
+            "signers": { owner: key },
+            "stateOverrides": {
+                token: { "stateDiff": { owner_balance.to_string(): format!("{:#066x}", 1000) } }
+            },
+            "persist": true,
+            "persistRequest": true,
+        });
+        let response = client
+            .post("/execute_calldatas_fork")
+            .header(ContentType::JSON)
+            .body(body.to_string())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let result_id = response
+            .headers()
+            .get_one("X-Result-Id")
+            .unwrap()
+            .to_string();
+        let results = response.into_json::<Value>().unwrap();
+
+        // The keys are never kept
+        let stored = client
+            .rocket()
+            .state::<Results>()
+            .unwrap()
+            .load(&result_id)
+            .unwrap();
+        let stored = stored.request.unwrap();
+        assert_eq!(stored["calls"].as_array().map(Vec::len), Some(3));
+        assert!(stored.get("signers").is_none());
+
+        assert_eq!(results[0]["success"], true, "{}", results[0]);
+        assert_eq!(results[1]["success"], true, "{}", results[1]);
+        assert_eq!(results[1]["result"], format!("{:#066x}", 1));
+        assert_eq!(results[2]["result"], format!("{:#066x}", 100));
+    }
+
+    #[test]
+    fn test_deadline_returns_partial_results() {
+        use rocket::http::Status;
+        use serde_json::json;
+
+        let call = json!({
+            "calldata": "0x",
+            "value": "0",
+            "caller": "0x1000000000000000000000000000000000000000",
+        });
+        let body = |deadline_ms: u64, summary: bool| {
+            json!({
+                "bytecode": "0x60005460005260206000f3",
+                "address": "0xb2f9974c62815d3177079e150377915d9bc49c82",
+                "calls": [call, call, call],
+                "forkConfig": { "network": "demo" },
+                "deadlineMs": deadline_ms,
+                "summary": summary,
+            })
+        };
+        let client = client();
+        // Already passed by the time the first call is done. The results are still the array.
+        let response = client
+            .post("/execute_calldatas_fork")
+            .header(ContentType::JSON)
+            .body(body(0, false).to_string())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("X-Deadline-Hit"), Some("true"));
+        let results = response.into_json::<Vec<Value>>().unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[1]["skipped"], "deadlineExceeded");
+
+        let response = client
+            .post("/execute_calldatas_fork")
+            .header(ContentType::JSON)
+            .body(body(0, true).to_string())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("X-Deadline-Hit"), Some("true"));
+        let body_json = response.into_json::<Value>().unwrap();
+        assert_eq!(body_json["deadlineHit"], true);
+        let results = body_json["results"].as_array().unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0]["success"], true);
+        assert!(results[0].get("skipped").is_none());
+        assert_eq!(results[1]["skipped"], "deadlineExceeded");
+        assert_eq!(results[2]["exitReason"], "skipped");
+        let statuses: Vec<_> = body_json["summary"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|line| line["status"].clone())
+            .collect();
+        assert_eq!(statuses, ["success", "skipped", "skipped"]);
+
+        // Only ever lowered
+        let over = Limits::default().request_deadline.as_millis() as u64 + 1;
+        let response = client
+            .post("/execute_calldatas_fork")
+            .header(ContentType::JSON)
+            .body(body(over, true).to_string())
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let err = response.into_json::<Value>().unwrap();
+        assert_eq!(err["details"]["limit"], "requestDeadlineMs");
+    }
+
+    #[test]
+    fn test_summary_alongside_pruned_results() {
+        use rocket::http::Status;
+        use serde_json::json;
+
+        let call = |calldata: &str| {
+            json!({
+                "calldata": calldata,
+                "value": "0",
+                "caller": "0x1000000000000000000000000000000000000000",
+            })
+        };
+        // Reverts with no data when given any calldata
+        let body = json!({
+            "bytecode": "0x3615600957600080fd5b00",
+            "address": "0xb2f9974c62815d3177079e150377915d9bc49c82",
+            "calls": [call("0x"), call("0x01"), call("0x")],
+            "forkConfig": { "network": "demo" },
+            "fields": ["gasUsed"],
+            "summary": true,
+        });
+        let response = client()
+            .post("/execute_calldatas_fork")
+            .header(ContentType::JSON)
+            .body(body.to_string())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body = response.into_json::<Value>().unwrap();
+
+        let results = body["results"].as_array().unwrap();
+        assert_eq!(results.len(), 3);
+        assert!(results[1].get("reverted").is_none());
+        let summary = body["summary"].as_array().unwrap();
+        let statuses: Vec<_> = summary.iter().map(|line| line["status"].clone()).collect();
+        assert_eq!(statuses, ["success", "revert", "success"]);
+        assert_eq!(summary[1]["revertReason"], "execution reverted");
+        for (i, (line, result)) in summary.iter().zip(results).enumerate() {
+            assert_eq!(line["callIndex"], i);
+            assert_eq!(line["gasUsed"], result["gasUsed"]);
+        }
+    }
+
+    #[test]
+    fn test_call_meta_is_echoed() {
+        use rocket::http::Status;
+        use serde_json::json;
+
+        let client = client();
+        let meta = json!({
+            "uiAction": "swap",
+            "row": { "id": 7, "tags": ["a", "b", null], "ratio": 0.25, "label": "dépôt 🚀" },
+            "nested": [[1, [2, [3]]], {}],
+        });
+        // Stores calldata[4..36] in slot 0
+        let mut body = json!({
+            "bytecode": "0x60243610600e57600435600055005b60005460005260206000f3",
+            "address": "0xb2f9974c62815d3177079e150377915d9bc49c82",
+            "calls": [
+                {
+                    "calldata": "0x60fe47b1000000000000000000000000000000000000000000000000000000000000002a",
+                    "value": "0",
+                    "caller": "0x1000000000000000000000000000000000000000",
+                    "meta": meta,
+                },
+                {
+                    "calldata": "0x6d4ce63c",
+                    "value": "0",
+                    "caller": "0x1000000000000000000000000000000000000000",
+                },
+                {
+                    "calldata": "0x6d4ce63c",
+                    "value": "0",
+                    "caller": "0x1000000000000000000000000000000000000000",
+                    "meta": "second read",
+                },
+            ],
+            "forkConfig": { "network": "demo" },
+        });
+        let response = client
+            .post("/execute_calldatas_fork")
+            .header(ContentType::JSON)
+            .body(body.to_string())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let results = response.into_json::<Value>().unwrap();
+
+        assert_eq!(results[0]["callIndex"], 0);
+        assert_eq!(results[0]["meta"], meta);
+        assert_eq!(results[1]["callIndex"], 1);
+        assert!(results[1].get("meta").is_none());
+        assert_eq!(results[2]["callIndex"], 2);
+        assert_eq!(results[2]["meta"], "second read");
+
+        body["calls"][1]["meta"] =
+            json!({ "blob": "x".repeat(Limits::default().max_call_meta_bytes) });
+        let response = client
+            .post("/execute_calldatas_fork")
+            .header(ContentType::JSON)
+            .body(body.to_string())
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let err = response.into_json::<Value>().unwrap();
+        assert_eq!(err["code"], "LIMIT_EXCEEDED");
+        assert_eq!(err["details"]["limit"], "maxCallMetaBytes");
+        assert!(err["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("calls[1].meta"));
+    }
+
+    #[test]
+    fn test_demo_gates_features() {
+        use crate::admission::Gates;
+        use crate::auth::Auth;
+        use crate::config::DemoConfig;
+        use crate::error::catchers;
+        use crate::results::MemoryStore;
+        use rocket::http::{Header, Status};
+        use rocket::routes;
+        use serde_json::json;
+
+        let config = AppConfig {
+            api_keys: ["secret".to_string()].into(),
+            demo: Some(DemoConfig::default()),
+            ..AppConfig::default()
+        };
+        let rocket = rocket::build()
+            .manage(Auth::new(&config))
+            .manage(InFlight::default())
+            .manage(Gates::new(&config.limits))
+            .manage(Results::new(MemoryStore::default(), &config.limits))
+            .manage(config)
+            .mount("/", routes![execute_calldatas_fork_route])
+            .register("/", catchers());
+        let client = rocket::local::blocking::Client::tracked(rocket).unwrap();
+        let post = |body: &Value, key: Option<&'static str>| {
+            let mut request = client
+                .post("/execute_calldatas_fork")
+                .header(ContentType::JSON)
+                .body(body.to_string());
+            if let Some(key) = key {
+                request = request.header(Header::new("X-API-Key", key));
+            }
+            let response = request.dispatch();
+            (response.status(), response.into_json::<Value>().unwrap())
+        };
+
+        let body = json!({
+            "bytecode": "0x00",
+            "address": "0xb2f9974c62815d3177079e150377915d9bc49c82",
+            "calls": [],
+            "forkConfig": { "rpcUrl": "http://localhost:8545" },
+        });
+        let (status, err) = post(&body, None);
+        assert_eq!(status, Status::Forbidden);
+        assert_eq!(err["code"], "UPGRADE_REQUIRED");
+        assert_eq!(err["details"]["feature"], "rpcUrl");
+        assert_eq!(err["details"]["field"], "forkConfig.rpcUrl");
+
+        let body = json!({
+            "bytecode": "0x00",
+            "address": "0xb2f9974c62815d3177079e150377915d9bc49c82",
+            "calls": [],
+            "traceMode": "debug",
+            "forkConfig": { "network": "demo" },
+        });
+        let (status, err) = post(&body, None);
+        assert_eq!(status, Status::Forbidden);
+        assert_eq!(err["details"]["feature"], "debugTraces");
+
+        // A key gets it, and so does a demo request without it
+        assert_eq!(post(&body, Some("secret")).0, Status::Ok);
+        let mut body = body;
+        body.as_object_mut().unwrap().remove("traceMode");
+        assert_eq!(post(&body, None).0, Status::Ok);
+    }
+
+    #[test]
+    fn test_tenderly_output() {
+        use rocket::http::Status;
+        use serde_json::json;
+
+        let client = client();
+
+        // Stores calldata[4..36] in slot 0
+        let body = json!({
+            "bytecode": "0x60243610600e57600435600055005b60005460005260206000f3",
+            "address": "0xb2f9974c62815d3177079e150377915d9bc49c82",
+            "calls": [{
+                "calldata": "0x60fe47b1000000000000000000000000000000000000000000000000000000000000002a",
+                "value": "0",
+                "caller": "0x1000000000000000000000000000000000000000",
+            }],
+            "forkConfig": { "network": "demo" },
+            "outputFormat": "tenderly",
+            "abi": [{
+                "type": "function",
+                "name": "set",
+                "inputs": [{ "name": "x", "type": "uint256" }],
+                "outputs": [],
+                "stateMutability": "nonpayable",
+            }],
+        });
+        let response = client
+            .post("/execute_calldatas_fork")
+            .header(ContentType::JSON)
+            .body(body.to_string())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let simulations = response.into_json::<Value>().unwrap();
+
+        let simulation = &simulations[0];
+        assert_eq!(simulation["status"], true);
+        assert_eq!(simulation["transaction"]["network_id"], "1337");
+        assert!(simulation["transaction"]["hash"].is_null());
+        assert_eq!(simulation["call_trace"][0]["function_name"], "set");
+        assert_eq!(
+            simulation["state_diff"]["0xb2f9974c62815d3177079e150377915d9bc49c82"]["storage"],
+            json!([{
+                "key": "0x0000000000000000000000000000000000000000000000000000000000000000",
+                "original": "0x0000000000000000000000000000000000000000000000000000000000000000",
+                "dirty": "0x000000000000000000000000000000000000000000000000000000000000002a",
+            }])
+        );
+    }
+
+    #[test]
+    fn test_deterministic() {
+        use rocket::http::Status;
+        use serde_json::json;
+
+        let mut req = request(None);
+        req.deterministic = true;
+        let err = req.validate(&Limits::default()).unwrap_err();
+        assert_eq!(
+            err.details.unwrap()["field"],
+            serde_json::json!("forkConfig.blockNumber")
+        );
+        req.fork_config = Some(ForkConfig {
+            block_number: Some(19_000_000),
+            ..Default::default()
+        });
+        assert!(req.validate(&Limits::default()).is_ok());
+
+        let client = client();
+        let body = json!({
+            "bytecode": "0x60243610600e57600435600055005b60005460005260206000f3",
+            "address": "0xb2f9974c62815d3177079e150377915d9bc49c82",
+            "calls": [{
+                "calldata": "0x60fe47b1000000000000000000000000000000000000000000000000000000000000002a",
+                "value": "0",
+                "caller": "0x1000000000000000000000000000000000000000",
+            }],
+            "forkConfig": { "network": "demo" },
+            "traceMode": "call",
+            "stateDiff": true,
+            "persist": true,
+            "deterministic": true,
+        })
+        .to_string();
+        let send = || {
+            let response = client
+                .post("/execute_calldatas_fork")
+                .header(ContentType::JSON)
+                .body(&body)
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            let result_id = response.headers().get_one("X-Result-Id").map(String::from);
+            (result_id, response.into_bytes().unwrap())
+        };
+        let first = send();
+        assert!(first.0.is_some());
+        assert_eq!(send(), first);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_deterministic_stream_has_no_timings() {
+        let mut req = request(Some(ForkConfig {
+            network: Some("demo".to_string()),
+            ..Default::default()
+        }));
+        req.deterministic = true;
+        let lines = collect_lines(ndjson(AppConfig::from_env().unwrap(), req)).await;
+        assert_eq!(lines[4]["type"], "summary");
+        assert!(lines[4].get("timings").is_none());
+    }
+
+    #[test]
+    fn test_consistency_check() {
+        use rocket::http::Status;
+        use serde_json::json;
+
+        let mut req = request(None);
+        req.consistency_check = true;
+        assert!(req.options().unwrap().consistency_check);
+        let err = req.check_streamable().unwrap_err();
+        assert_eq!(
+            err.details.unwrap()["field"],
+            serde_json::json!("consistencyCheck")
+        );
+
+        // The demo chain has no node behind it, so there's nothing to compare with
+        let body = json!({
+            "bytecode": "0x60005460005260206000f3",
+            "address": "0xb2f9974c62815d3177079e150377915d9bc49c82",
+            "calls": [{
+                "calldata": "0x6d4ce63c",
+                "value": "0",
+                "caller": "0x1000000000000000000000000000000000000000",
+            }],
+            "forkConfig": { "network": "demo" },
+            "consistencyCheck": true,
+        });
+        let response = client()
+            .post("/execute_calldatas_fork")
+            .header(ContentType::JSON)
+            .body(body.to_string())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let results = response.into_json::<Value>().unwrap();
+        assert_eq!(results[0]["consistencyCheck"]["status"], "skipped");
+    }
+
+    #[test]
+    fn test_cast_output() {
+        use rocket::http::Status;
+        use serde_json::json;
+
+        // Stores 42 in slot 0, then reads it back with get()
+        let body = json!({
+            "bytecode": "0x60243610600e57600435600055005b60005460005260206000f3",
+            "address": "0xb2f9974c62815d3177079e150377915d9bc49c82",
+            "calls": [
+                {
+                    "calldata": "0x60fe47b1000000000000000000000000000000000000000000000000000000000000002a",
+                    "value": "0",
+                    "caller": "0x1000000000000000000000000000000000000000",
+                },
+                {
+                    "calldata": "0x6d4ce63c",
+                    "value": "0",
+                    "caller": "0x1000000000000000000000000000000000000000",
+                },
+            ],
+            "forkConfig": { "network": "demo" },
+            "outputFormat": "cast",
+            "abi": [{
+                "type": "function",
+                "name": "get",
+                "inputs": [],
+                "outputs": [{ "name": "", "type": "uint256" }],
+                "stateMutability": "view",
+            }],
+        });
+        let response = client()
+            .post("/execute_calldatas_fork")
+            .header(ContentType::JSON)
+            .body(body.to_string())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let results = response.into_json::<Value>().unwrap();
+
+        // set isn't in the abi, so its empty return data comes back as is
+        assert_eq!(results[0]["call"], "0x");
+        assert_eq!(results[1]["call"], json!(["42"]));
+        for result in results.as_array().unwrap() {
+            assert_eq!(
+                result["accessList"]["accessList"],
+                json!([{
+                    "address": "0xb2f9974c62815d3177079e150377915d9bc49c82",
+                    "storageKeys": [
+                        "0x0000000000000000000000000000000000000000000000000000000000000000",
+                    ],
+                }])
+            );
+            assert!(result["accessList"]["gasUsed"]
+                .as_str()
+                .unwrap()
+                .starts_with("0x"));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_stream_emits_results_then_done() {
+        let events = collect(stream(AppConfig::from_env().unwrap(), request(None))).await;
+
+        assert_eq!(events.len(), 4);
+        for (i, event) in events[..3].iter().enumerate() {
+            assert!(matches!(event, StreamEvent::Result { index, .. } if *index == i));
+        }
+        assert!(matches!(events[3], StreamEvent::Done { .. }));
+    }
+
+    async fn collect_lines(mut rx: mpsc::Receiver<Vec<u8>>) -> Vec<serde_json::Value> {
+        let mut lines = Vec::new();
+        while let Some(line) = rx.recv().await {
+            assert_eq!(line.last(), Some(&b'\n'));
+            lines.push(serde_json::from_slice(&line).unwrap());
+        }
+        lines
+    }
+
+    fn ndjson(config: AppConfig, req: ExecuteCalldatasRequest) -> mpsc::Receiver<Vec<u8>> {
+        let fork = resolve_fork(&config, req.fork_config.as_ref()).unwrap();
+        ndjson_calldatas_fork(&InFlight::default(), config, req, fork).unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_ndjson_lines_in_order() {
+        let lines = collect_lines(ndjson(AppConfig::from_env().unwrap(), request(None))).await;
+
+        let types: Vec<_> = lines.iter().map(|line| line["type"].clone()).collect();
+        assert_eq!(
+            types,
+            ["forkContext", "result", "result", "result", "summary"]
+        );
+        assert!(lines[0]["chainId"].is_u64());
+        for (i, line) in lines[1..4].iter().enumerate() {
+            assert_eq!(line["index"], i);
+        }
+        let total: u64 = lines[1..4]
+            .iter()
+            .map(|line| line["result"]["gasUsed"].as_u64().unwrap())
+            .sum();
+        assert_eq!(lines[4]["totalGasUsed"], total);
+        assert!(lines[4]["timings"]["executionMs"].is_u64());
+        assert_eq!(lines[4]["timings"]["blockRetries"], 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_default_caller() {
+        let default = Address::repeat_byte(0xde);
+        let own = Address::repeat_byte(0x0c);
+        let mut req = request(Some(ForkConfig {
+            network: Some("demo".to_string()),
+            ..Default::default()
+        }));
+        // CALLER, returned
+        req.bytecode = Bytes::from_str("0x3360005260206000f3").unwrap();
+        req.default_caller = Some(default);
+        req.calls[0].caller = None;
+        req.calls[1].caller = Some(own.into());
+        req.calls[2].caller = None;
+        req.validate(&Limits::default()).unwrap();
+        let lines = collect_lines(ndjson(AppConfig::default(), req)).await;
+        let callers: Vec<Address> = lines[1..4]
+            .iter()
+            .map(|line| serde_json::from_value(line["result"]["env"]["caller"].clone()).unwrap())
+            .collect();
+        assert_eq!(callers, [default, own, default]);
+        assert_eq!(
+            lines[1]["result"]["result"],
+            serde_json::to_value(default.into_word()).unwrap()
+        );
+
+        let mut req = request(None);
+        req.calls[1].caller = None;
+        let err = req.validate(&Limits::default()).unwrap_err();
+        assert_eq!(err.status, rocket::http::Status::UnprocessableEntity);
+        assert_eq!(err.details.unwrap()["field"], "calls[1].caller");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_hardfork() {
+        let run = |hardfork: &str| {
+            let mut req = request(Some(ForkConfig {
+                network: Some("demo".to_string()),
+                ..Default::default()
+            }));
+            req.hardfork = Some(hardfork.to_string());
+            req.validate(&Limits::default()).unwrap();
+            collect_lines(ndjson(AppConfig::default(), req))
+        };
+        // Istanbul raised SLOAD from 200 gas to 800, more than it saved on get()'s calldata
+        let petersburg = run("petersburg").await;
+        let istanbul = run("istanbul").await;
+        assert_eq!(petersburg[0]["hardfork"], "petersburg");
+        assert_eq!(istanbul[0]["hardfork"], "istanbul");
+        let gas = |lines: &[Value]| lines[1]["result"]["gasUsed"].as_u64().unwrap();
+        assert!(gas(&istanbul) > gas(&petersburg));
+
+        let mut req = request(None);
+        req.hardfork = Some("merge".to_string());
+        let err = req.validate(&Limits::default()).unwrap_err();
+        assert_eq!(err.status, rocket::http::Status::UnprocessableEntity);
+        assert!(err.message.contains("frontier"), "{}", err.message);
+        assert_eq!(err.details.unwrap()["valid"][10], "paris");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_state_overrides() {
+        let demo = request(Some(ForkConfig {
+            network: Some("demo".to_string()),
+            ..Default::default()
+        }));
+        // As geth takes them, copied from an eth_call
+        let mut body = serde_json::to_value(&demo).unwrap();
+        body["stateOverrides"] = serde_json::json!({
+            "0xb2f9974c62815d3177079e150377915d9bc49c82": {
+                "balance": "0xde0b6b3a7640000",
+                "stateDiff": {
+                    "0x0000000000000000000000000000000000000000000000000000000000000000":
+                        "0x000000000000000000000000000000000000000000000000000000000000002a"
+                }
+            }
+        });
+        let req: ExecuteCalldatasRequest = serde_json::from_value(body.clone()).unwrap();
+        req.validate(&Limits::default()).unwrap();
+        let lines = collect_lines(ndjson(AppConfig::default(), req)).await;
+        assert_eq!(
+            lines[1]["result"]["result"],
+            "0x000000000000000000000000000000000000000000000000000000000000002a"
+        );
+
+        let account = &mut body["stateOverrides"]["0xb2f9974c62815d3177079e150377915d9bc49c82"];
+        account["state"] = account["stateDiff"].clone();
+        let req: ExecuteCalldatasRequest = serde_json::from_value(body).unwrap();
+        let err = req.validate(&Limits::default()).unwrap_err();
+        assert_eq!(
+            err.details.unwrap()["field"],
+            format!("stateOverrides.{}", demo.address)
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_ndjson_fields() {
+        let whole = collect_lines(ndjson(AppConfig::from_env().unwrap(), request(None))).await;
+        let mut req = request(None);
+        req.fields = Some(Fields::parse(["gasUsed", "reverted"]).unwrap());
+        let pruned = collect_lines(ndjson(AppConfig::from_env().unwrap(), req)).await;
+
+        for (whole, pruned) in whole[1..4].iter().zip(&pruned[1..4]) {
+            let mut keys: Vec<_> = pruned["result"].as_object().unwrap().keys().collect();
+            keys.sort();
+            assert_eq!(keys, ["gasUsed", "reverted"]);
+            assert_eq!(pruned["result"]["gasUsed"], whole["result"]["gasUsed"]);
+            assert!(pruned.to_string().len() < whole.to_string().len());
+        }
+        // The summary still has what it needs
+        assert_eq!(pruned[4]["totalGasUsed"], whole[4]["totalGasUsed"]);
+        assert_eq!(pruned[4]["calls"], whole[4]["calls"]);
+        assert_eq!(pruned[4]["calls"].as_array().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_ndjson_error_line() {
+        // Nothing listens on port 1
+        let fork_config = ForkConfig {
+            rpc_url: Some("http://127.0.0.1:1".to_string()),
+            ..Default::default()
+        };
+        let lines = collect_lines(ndjson(AppConfig::default(), request(Some(fork_config)))).await;
+
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0]["type"], "error");
+        assert_eq!(lines[0]["code"], "FORK_RPC_UNREACHABLE");
+    }
+
+    #[tokio::test]
+    async fn test_stream_error_terminates() {
+        // Nothing listens on port 1
+        let fork_config = ForkConfig {
+            rpc_url: Some("http://127.0.0.1:1".to_string()),
+            ..Default::default()
+        };
+        let events = collect(stream(AppConfig::default(), request(Some(fork_config)))).await;
+
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            StreamEvent::Error(err) => assert_eq!(err.code, "FORK_RPC_UNREACHABLE"),
+            other => panic!("expected an error event, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_msgpack_request_matches_json() {
+        use crate::admission::Gates;
+        use crate::auth::Auth;
+        use crate::results::MemoryStore;
+        use rocket::http::Status;
+        use rocket::local::blocking::Client;
+        use rocket::routes;
+
+        let config = AppConfig::default();
+        let rocket = rocket::build()
+            .manage(Auth::new(&config))
+            .manage(InFlight::default())
+            .manage(Gates::new(&config.limits))
+            .manage(Results::new(MemoryStore::default(), &config.limits))
+            .manage(config)
+            .mount(
+                "/",
+                routes![
+                    execute_calldatas_fork_route,
+                    execute_calldatas_fork_msgpack_route
+                ],
+            );
+        let client = Client::tracked(rocket).unwrap();
+        let req = request(Some(ForkConfig {
+            network: Some("demo".to_string()),
+            ..Default::default()
+        }));
+        let execute = |content_type: ContentType, body: Vec<u8>| {
+            let response = client
+                .post("/execute_calldatas_fork")
+                .header(content_type)
+                .body(body)
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            response.into_json::<Value>().unwrap()
+        };
+
+        let json = serde_json::to_vec(&req).unwrap();
+        let msgpack = rmp_serde::to_vec_named(&req).unwrap();
+        assert!(msgpack.len() < json.len());
+        // The bytecode goes as its raw bytes
+        assert!(msgpack
+            .windows(req.bytecode.len())
+            .any(|w| w == &req.bytecode[..]));
+
+        let from_json = execute(ContentType::JSON, json);
+        let from_msgpack = execute(ContentType::MsgPack, msgpack);
+        assert_eq!(from_json.as_array().map(Vec::len), Some(3));
+        assert_eq!(from_msgpack, from_json);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_execution_leaves_other_requests_alone() {
+        use crate::admission::Gates;
+        use crate::auth::Auth;
+        use crate::results::MemoryStore;
+        use crate::routes::health_route;
+        use rocket::http::Status;
+        use rocket::local::asynchronous::Client;
+        use rocket::routes;
+        use serde_json::json;
+        use std::time::{Duration, Instant};
+
+        let config = AppConfig::default();
+        let rocket = rocket::build()
+            .manage(Auth::new(&config))
+            .manage(InFlight::default())
+            .manage(Gates::new(&config.limits))
+            .manage(Results::new(MemoryStore::default(), &config.limits))
+            .manage(config)
+            .mount("/", routes![execute_calldatas_fork_route, health_route]);
+        let client = Client::tracked(rocket).await.unwrap();
+
+        // Each call loops until it has used up the demo chain's 30M gas
+        let call = json!({
+            "calldata": "0x",
+            "value": "0",
+            "caller": "0x1000000000000000000000000000000000000001",
+        });
+        let body = json!({
+            "bytecode": "0x5b600056",
+            "address": "0x2000000000000000000000000000000000000000",
+            "calls": vec![call; 10],
+            "forkConfig": { "network": "demo" },
+            "traceMode": "none",
+        });
+        let execute = async {
+            let response = client
+                .post("/execute_calldatas_fork")
+                .header(ContentType::JSON)
+                .body(body.to_string())
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Ok);
+            Instant::now()
+        };
+        let health = async {
+            // Give the execution time to get going
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let asked = Instant::now();
+            let response = client.get("/health").dispatch().await;
+            assert_eq!(response.status(), Status::Ok);
+            (asked.elapsed(), Instant::now())
+        };
+        let (executed, (waited, answered)) = tokio::join!(execute, health);
 
-    Ok(Json(result))
+        assert!(answered < executed, "the execution finished first");
+        assert!(
+            waited < Duration::from_millis(20),
+            "/health took {:?}",
+            waited
+        );
+    }
 }
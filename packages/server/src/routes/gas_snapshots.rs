@@ -0,0 +1,161 @@
+use crate::auth::ApiKey;
+use crate::config::AppConfig;
+use crate::error::ApiError;
+use crate::gas_snapshots::{check_name, diff, GasSnapshot, GasSnapshotDiff};
+use crate::results::Results;
+use rocket::{get, post, serde::json::Json, State};
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GasSnapshotRequest {
+    /// Letters, digits, `-` and `_`. Taking a snapshot under a name already taken replaces it.
+    pub name: String,
+    /// The `X-Result-Id` of an `/execute_calldatas_fork` response sent with `persist`. Its calls
+    /// are labelled by `scenarioLabels` when it was also sent with `persistRequest`, and
+    /// `calls[i]` otherwise.
+    pub result_id: String,
+}
+
+/// Keeps the gas each call of a persisted execution used, under a name to diff against later.
+#[utoipa::path(
+    post,
+    path = "/gas_snapshots",
+    tag = "execute",
+    request_body = GasSnapshotRequest,
+    responses(
+        (status = 200, description = "The snapshot taken", body = GasSnapshot),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("apiKey" = []))
+)]
+#[post("/gas_snapshots", format = "json", data = "<req>")]
+pub fn save_gas_snapshot_route(
+    _key: ApiKey,
+    results: &State<Results>,
+    req: Json<GasSnapshotRequest>,
+) -> Result<Json<GasSnapshot>, ApiError> {
+    let req = req.into_inner();
+    check_name(&req.name).map_err(|message| super::validate::invalid_field("name", message))?;
+    let stored = results.load(&req.result_id)?;
+    let snapshot = GasSnapshot::from_stored(req.name, &stored)?;
+    snapshot.save(results)?;
+    Ok(Json(snapshot))
+}
+
+/// How each scenario's gas in snapshot `name` moved from snapshot `against`.
+#[utoipa::path(
+    get,
+    path = "/gas_snapshots/{name}/diff",
+    tag = "execute",
+    params(
+        ("name" = String, Path, description = "The snapshot to compare"),
+        ("against" = String, Query, description = "The snapshot to compare it with, e.g. the last one taken on main"),
+        ("threshold" = Option<f64>, Query, description = "Percent gas can go up by before it counts as a regression. The server's `GAS_REGRESSION_THRESHOLD` without one."),
+    ),
+    responses(
+        (status = 200, description = "Every scenario either snapshot has, with its change", body = GasSnapshotDiff),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("apiKey" = []))
+)]
+#[get("/gas_snapshots/<name>/diff?<against>&<threshold>")]
+pub fn diff_gas_snapshots_route(
+    _key: ApiKey,
+    config: &State<AppConfig>,
+    results: &State<Results>,
+    name: &str,
+    against: &str,
+    threshold: Option<f64>,
+) -> Result<Json<GasSnapshotDiff>, ApiError> {
+    let threshold = threshold.unwrap_or(config.gas_regression_threshold);
+    if !threshold.is_finite() || threshold < 0.0 {
+        return Err(super::validate::invalid_field(
+            "threshold",
+            "must be a percentage, 0 or more",
+        ));
+    }
+    let snapshot = GasSnapshot::load(results, name)?;
+    let against = GasSnapshot::load(results, against)?;
+    Ok(Json(diff(&snapshot, &against, threshold)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Auth;
+    use crate::results::MemoryStore;
+    use rocket::http::{ContentType, Status};
+    use rocket::local::blocking::Client;
+    use rocket::routes;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_snapshot_and_diff() {
+        let config = AppConfig::default();
+        let results = Results::new(MemoryStore::default(), &config.limits);
+        let request = json!({ "scenarioLabels": ["set", "get"] });
+        let before = results
+            .save(
+                &json!([{ "gasUsed": 43_000 }, { "gasUsed": 23_000 }]),
+                Some(request.clone()),
+            )
+            .unwrap();
+        let after = results
+            .save(
+                &json!([{ "gasUsed": 45_000 }, { "gasUsed": 23_000 }]),
+                Some(request),
+            )
+            .unwrap();
+        let rocket = rocket::build()
+            .manage(Auth::new(&config))
+            .manage(results)
+            .manage(config)
+            .mount(
+                "/",
+                routes![save_gas_snapshot_route, diff_gas_snapshots_route],
+            )
+            .register("/", crate::error::catchers());
+        let client = Client::tracked(rocket).unwrap();
+        let snapshot = |name: &str, result_id: &str| {
+            client
+                .post("/gas_snapshots")
+                .header(ContentType::JSON)
+                .body(json!({ "name": name, "resultId": result_id }).to_string())
+                .dispatch()
+        };
+
+        let response = snapshot("main", &before);
+        assert_eq!(response.status(), Status::Ok);
+        let body: Value = response.into_json().unwrap();
+        assert_eq!(body["scenarios"], json!({ "get": 23_000, "set": 43_000 }));
+        assert_eq!(snapshot("feature", &after).status(), Status::Ok);
+
+        let response = client
+            .get("/gas_snapshots/feature/diff?against=main")
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body: Value = response.into_json().unwrap();
+        assert_eq!(body["regressions"], 1);
+        assert_eq!(body["scenarios"][1]["scenario"], "set");
+        assert_eq!(body["scenarios"][1]["delta"], 2_000);
+        assert_eq!(body["scenarios"][1]["regression"], true);
+
+        // Under a looser threshold, 4.65% is fine
+        let response = client
+            .get("/gas_snapshots/feature/diff?against=main&threshold=5")
+            .dispatch();
+        let body: Value = response.into_json().unwrap();
+        assert_eq!(body["regressions"], 0);
+
+        let response = snapshot("not/a/name", &before);
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let response = client
+            .get("/gas_snapshots/feature/diff?against=missing")
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        let body: Value = response.into_json().unwrap();
+        assert_eq!(body["code"], "GAS_SNAPSHOT_NOT_FOUND");
+    }
+}
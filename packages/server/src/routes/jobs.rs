@@ -0,0 +1,129 @@
+use super::execute_calldatas_fork::ExecuteCalldatasRequest;
+use super::validate::{Checked, Validate};
+use crate::auth::{ApiKey, ExecuteKey};
+use crate::config::AppConfig;
+use crate::error::ApiError;
+use crate::jobs::{not_found, ExecuteJob, JobQueue, JobState};
+use crate::telemetry::RequestId;
+use rocket::response::status::Accepted;
+use rocket::{delete, get, post, serde::json::Json, State};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+/// An `/execute_calldatas_fork` request, plus where to send the result.
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SubmitJobRequest {
+    #[serde(flatten)]
+    pub request: ExecuteCalldatasRequest,
+    /// POSTed the job's final state when it's done or has failed, signed in `X-Signature-256`
+    /// as `sha256=<hex HMAC-SHA256 of the body>` under the server's webhook secret. Retried on
+    /// 5xx. Must resolve to a public address.
+    pub callback_url: Option<String>,
+}
+
+impl Validate for SubmitJobRequest {
+    fn check_json(body: &Value) -> Result<(), ApiError> {
+        ExecuteCalldatasRequest::check_json(body)
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct JobCreated {
+    pub id: String,
+}
+
+/// Queues an `/execute_calldatas_fork` request and returns immediately. Poll `GET /jobs/<id>`
+/// for the result, or pass a `callbackUrl` to have it sent. Only requests sending the same
+/// `X-API-Key` can see or cancel the job, or for a job submitted without one in demo mode,
+/// requests from the same IP.
+#[utoipa::path(
+    post,
+    path = "/jobs/execute",
+    tag = "jobs",
+    request_body = SubmitJobRequest,
+    responses(
+        (status = 202, description = "Queued", body = JobCreated),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("apiKey" = []))
+)]
+#[post("/jobs/execute", format = "json", data = "<req>")]
+pub async fn submit_job_route(
+    key: ExecuteKey,
+    request_id: RequestId,
+    config: &State<AppConfig>,
+    jobs: &State<JobQueue>,
+    req: Checked<SubmitJobRequest>,
+) -> Result<Accepted<Json<JobCreated>>, ApiError> {
+    req.request.validate(&config.limits)?;
+    req.request.check_no_raw_transactions("jobs")?;
+    let fork =
+        super::execute_calldatas_fork::resolve_fork(config, req.request.fork_config.as_ref())?;
+    let callback = match &req.callback_url {
+        Some(url) => Some(jobs.check_callback(url).await?),
+        None => None,
+    };
+    let options = req.request.options();
+    let calls = req.request.effective_calls();
+    let req = req.into_inner().request;
+    let _span = request_id.span().entered();
+    let id = jobs.submit(
+        config.inner().clone(),
+        ExecuteJob {
+            bytecode: req.bytecode,
+            address: req.address,
+            calls,
+            fork,
+            options,
+            deadline: req.deadline_ms.map(Duration::from_millis),
+            callback,
+            owner: key.0,
+        },
+    )?;
+    Ok(Accepted(Json(JobCreated { id })))
+}
+
+#[utoipa::path(
+    get,
+    path = "/jobs/{id}",
+    tag = "jobs",
+    params(("id" = String, Path, description = "Job id")),
+    responses(
+        (status = 200, description = "The job's current state", body = JobState),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("apiKey" = []))
+)]
+#[get("/jobs/<id>")]
+pub fn get_job_route(
+    key: ApiKey,
+    jobs: &State<JobQueue>,
+    id: &str,
+) -> Result<Json<JobState>, ApiError> {
+    jobs.get(id, key.0.as_deref())
+        .map(Json)
+        .ok_or_else(|| not_found(id))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/jobs/{id}",
+    tag = "jobs",
+    params(("id" = String, Path, description = "Job id")),
+    responses(
+        (status = 200, description = "The job's state after cancelling", body = JobState),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("apiKey" = []))
+)]
+#[delete("/jobs/<id>")]
+pub fn cancel_job_route(
+    key: ApiKey,
+    jobs: &State<JobQueue>,
+    id: &str,
+) -> Result<Json<JobState>, ApiError> {
+    jobs.cancel(id, key.0.as_deref()).map(Json)
+}
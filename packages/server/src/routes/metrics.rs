@@ -0,0 +1,129 @@
+use crate::admission::{Gate, Gates};
+use crate::auth::AdminKey;
+use crate::config::AppConfig;
+use crate::gas::rpc_pool;
+use crate::shutdown::InFlight;
+use crate::telemetry::AccessMetrics;
+use rocket::response::content::RawText;
+use rocket::{get, State};
+use std::fmt::Write;
+
+/// Answers as soon as it's asked, for load balancer and container health checks. It does no work,
+/// so a slow answer means the async workers are tied up.
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "admin",
+    responses(
+        (status = 200, description = "The server is up", body = String, content_type = "text/plain"),
+    )
+)]
+#[get("/health")]
+pub fn health_route() -> RawText<&'static str> {
+    RawText("ok")
+}
+
+/// Load gauges, per-route request metrics and RPC key health in the Prometheus text format.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Prometheus text exposition", body = String, content_type = "text/plain"),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("adminKey" = []))
+)]
+#[get("/metrics")]
+pub fn metrics_route(
+    _key: AdminKey,
+    in_flight: &State<InFlight>,
+    gates: &State<Gates>,
+    access: &State<AccessMetrics>,
+    config: &State<AppConfig>,
+) -> RawText<String> {
+    let mut out = String::new();
+    out.push_str("# HELP evm_repl_in_flight Compile and execute work running\n");
+    out.push_str("# TYPE evm_repl_in_flight gauge\n");
+    let _ = writeln!(out, "evm_repl_in_flight {}", in_flight.active());
+
+    let gates: [(&str, &Gate); 2] = [("fork", &gates.fork), ("compile", &gates.compile)];
+    out.push_str("# HELP evm_repl_gate_in_flight Requests holding a slot\n");
+    out.push_str("# TYPE evm_repl_gate_in_flight gauge\n");
+    for (name, gate) in gates {
+        let _ = writeln!(
+            out,
+            "evm_repl_gate_in_flight{{gate=\"{}\"}} {}",
+            name,
+            gate.in_flight()
+        );
+    }
+    out.push_str("# HELP evm_repl_gate_queued Requests waiting for a slot\n");
+    out.push_str("# TYPE evm_repl_gate_queued gauge\n");
+    for (name, gate) in gates {
+        let _ = writeln!(
+            out,
+            "evm_repl_gate_queued{{gate=\"{}\"}} {}",
+            name,
+            gate.queued()
+        );
+    }
+    access.render(&mut out);
+    rpc_pool::render(&config.chain_rpc_urls, &mut out);
+    RawText(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::admission::Gates;
+    use crate::auth::Auth;
+    use crate::config::AppConfig;
+    use crate::routes::metrics_route;
+    use crate::shutdown::InFlight;
+    use crate::telemetry::RequestLogger;
+    use rocket::local::asynchronous::Client;
+    use rocket::routes;
+
+    #[tokio::test]
+    async fn test_reports_gates() {
+        let config = AppConfig::default();
+        let gates = Gates::new(&config.limits);
+        let logger = RequestLogger::default();
+        let rocket = rocket::build()
+            .manage(Auth::new(&config))
+            .manage(InFlight::default())
+            .manage(gates.clone())
+            .manage(logger.metrics())
+            .manage(config)
+            .attach(logger)
+            .mount("/", routes![metrics_route]);
+        let client = Client::tracked(rocket).await.unwrap();
+
+        let _permit = gates.fork.enter().await.unwrap();
+        let body = client
+            .get("/metrics")
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert!(body.contains("evm_repl_in_flight 0\n"));
+        assert!(body.contains("evm_repl_gate_in_flight{gate=\"fork\"} 1\n"));
+        assert!(body.contains("evm_repl_gate_queued{gate=\"compile\"} 0\n"));
+
+        // The first scrape has been logged by the time of the second
+        let body = client
+            .get("/metrics")
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert!(
+            body.contains("evm_repl_requests_total{route=\"metrics_route\",status=\"200\"} 1\n")
+        );
+        assert!(
+            body.contains("evm_repl_request_duration_seconds_count{route=\"metrics_route\"} 1\n")
+        );
+    }
+}
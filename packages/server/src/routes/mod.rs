@@ -1,6 +1,92 @@
+use rocket::{routes, Route};
+
+mod abi;
+mod admin;
+mod analyze;
+mod compare;
 mod compile_solidity;
+mod deploy;
+mod disassemble;
+mod docs;
+mod execute_batch;
 mod execute_calldatas;
 mod execute_calldatas_fork;
+mod gas_snapshots;
+mod jobs;
+mod metrics;
+mod replay;
+mod results;
+mod run;
+mod storage;
+mod transact;
+mod validate;
+mod verify;
+mod ws;
+pub use abi::{abi_decode_route, abi_encode_route, decode_constructor_route, decode_revert_route};
+pub use admin::{caches_route, flush_caches_route, usage_route};
+pub use analyze::analyze_imports_route;
+pub use compare::compare_bytecode_route;
 pub use compile_solidity::compile_solidity_route;
+pub use deploy::deploy_route;
+pub use disassemble::disassemble_route;
+pub use docs::{openapi_route, swagger_ui, ApiDoc};
+pub use execute_batch::execute_batch_route;
 pub use execute_calldatas::execute_calldatas_route;
-pub use execute_calldatas_fork::execute_calldatas_fork_route;
+pub use execute_calldatas_fork::{
+    execute_calldatas_fork_msgpack_route, execute_calldatas_fork_route,
+    execute_calldatas_fork_stream_route,
+};
+pub use gas_snapshots::{diff_gas_snapshots_route, save_gas_snapshot_route};
+pub use jobs::{cancel_job_route, get_job_route, submit_job_route};
+pub use metrics::{health_route, metrics_route};
+pub use replay::replay_tx_route;
+pub use results::get_result_route;
+pub use run::run_route;
+pub use storage::storage_slot_route;
+pub use transact::transact_route;
+pub use verify::verify_sourcify_route;
+pub use ws::ws_route;
+
+/// The API by version, as `(base, routes)` to mount. `/v1` is current; the unprefixed routes are
+/// the same handlers kept for older clients, marked deprecated by `Legacy`.
+pub fn versions() -> Vec<(&'static str, Vec<Route>)> {
+    vec![("/v1", routes()), ("/", routes())]
+}
+
+/// Every API route, relative to its version's base.
+pub fn routes() -> Vec<Route> {
+    routes![
+        execute_calldatas_route,
+        compile_solidity_route,
+        analyze_imports_route,
+        execute_calldatas_fork_route,
+        execute_calldatas_fork_msgpack_route,
+        run_route,
+        execute_batch_route,
+        execute_calldatas_fork_stream_route,
+        submit_job_route,
+        get_job_route,
+        cancel_job_route,
+        usage_route,
+        caches_route,
+        flush_caches_route,
+        metrics_route,
+        health_route,
+        abi_encode_route,
+        abi_decode_route,
+        decode_revert_route,
+        decode_constructor_route,
+        storage_slot_route,
+        verify_sourcify_route,
+        compare_bytecode_route,
+        disassemble_route,
+        deploy_route,
+        transact_route,
+        replay_tx_route,
+        get_result_route,
+        save_gas_snapshot_route,
+        diff_gas_snapshots_route,
+        ws_route,
+        openapi_route,
+    ]
+}
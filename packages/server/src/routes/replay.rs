@@ -0,0 +1,132 @@
+use crate::admission::ForkSlot;
+use crate::auth::ExecuteKey;
+use crate::config::AppConfig;
+use crate::error::ApiError;
+use crate::gas::{
+    replay_transaction, ExecutionOptions, ForkConfig, NamedStateOverride, ReplayedTransaction,
+};
+use crate::shutdown::Work;
+use crate::telemetry::RequestId;
+use alloy_primitives::B256;
+use rocket::{post, serde::json::Json, State};
+use serde::Deserialize;
+use tracing::Instrument;
+use utoipa::ToSchema;
+
+use super::execute_calldatas_fork::{requested_chain_id, resolve_fork};
+use super::validate::{Checked, Validate};
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayTxRequest {
+    /// A mined transaction
+    #[schema(value_type = String)]
+    pub tx_hash: B256,
+    /// The chain it's on, replayed with the server's RPC keys for it. The default chain without
+    /// one.
+    pub chain_id: Option<u64>,
+    /// A node to replay it from instead, which has to keep the state of the block before it
+    pub rpc_url: Option<String>,
+    pub trace_mode: Option<String>, // "call", "jump", "jumpSimple", "debug", "none"
+    /// Also return the balances and storage it changed in `stateDiff`. Defaults to true.
+    pub state_diff: Option<bool>,
+    /// Also return its trace as gas-weighted folded stacks in `flamegraph`
+    #[serde(default)]
+    pub flamegraph: bool,
+    /// Accounts to change before anything in the block runs, as `eth_call` takes them. Code put
+    /// in place of a contract's shows what the transaction would have done against it. Accounts
+    /// may be ENS names on chains with ENS.
+    #[schema(value_type = Option<Object>)]
+    pub state_overrides: Option<NamedStateOverride>,
+}
+
+impl Validate for ReplayTxRequest {}
+
+/// Runs a mined transaction again on a fork of its block, after the transactions ahead of it in
+/// the block, and returns the result with its trace.
+#[utoipa::path(
+    post,
+    path = "/replay_tx",
+    tag = "execute",
+    request_body = ReplayTxRequest,
+    responses(
+        (status = 200, description = "The transaction and how it ran", body = ReplayedTransaction),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("apiKey" = []))
+)]
+#[post("/replay_tx", format = "json", data = "<req>")]
+pub async fn replay_tx_route(
+    _key: ExecuteKey,
+    _work: Work,
+    _slot: ForkSlot,
+    id: RequestId,
+    config: &State<AppConfig>,
+    req: Checked<ReplayTxRequest>,
+) -> Result<Json<ReplayedTransaction>, ApiError> {
+    let req = req.into_inner();
+    super::validate::check_state_overrides(req.state_overrides.as_ref())?;
+    let fork = resolve_fork(
+        config,
+        Some(&ForkConfig {
+            rpc_url: req.rpc_url,
+            chain_id: req.chain_id,
+            ..Default::default()
+        }),
+    )?;
+    id.record_execution(requested_chain_id(&fork), 1);
+    let options = ExecutionOptions {
+        trace_mode: req.trace_mode,
+        flamegraph: req.flamegraph,
+        state_diff: req.state_diff.unwrap_or(true),
+        state_overrides: req.state_overrides,
+        ..Default::default()
+    };
+    let replayed = replay_transaction(config, req.tx_hash, fork, Some(options))
+        .instrument(id.span())
+        .await
+        .map_err(ApiError::from_execution)?;
+    Ok(Json(replayed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admission::Gates;
+    use crate::auth::Auth;
+    use crate::shutdown::InFlight;
+    use rocket::http::{ContentType, Status};
+    use rocket::local::blocking::Client;
+    use rocket::routes;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_replay_without_history() {
+        let config = AppConfig::default();
+        let rocket = rocket::build()
+            .manage(Auth::new(&config))
+            .manage(InFlight::default())
+            .manage(Gates::new(&config.limits))
+            .manage(config)
+            .mount("/", routes![replay_tx_route]);
+        let client = Client::tracked(rocket).unwrap();
+        let replay = |body: Value| {
+            client
+                .post("/replay_tx")
+                .header(ContentType::JSON)
+                .body(body.to_string())
+                .dispatch()
+        };
+
+        // The default chain has no keys here, which would leave the managed anvil
+        let response = replay(json!({ "txHash": B256::repeat_byte(1) }));
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let body: Value = response.into_json().unwrap();
+        assert_eq!(body["code"], "INVALID_FORK_CONFIG");
+
+        let response = replay(json!({ "txHash": B256::repeat_byte(1), "chainId": 1 }));
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let body: Value = response.into_json().unwrap();
+        assert_eq!(body["code"], "UNSUPPORTED_CHAIN");
+    }
+}
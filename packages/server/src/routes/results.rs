@@ -0,0 +1,93 @@
+use crate::auth::ApiKey;
+use crate::error::ApiError;
+use crate::results::{Results, StoredResult};
+use rocket::{get, serde::json::Json, State};
+
+/// A response kept by a request sent with `persist`, under the id from its `X-Result-Id` header.
+#[utoipa::path(
+    get,
+    path = "/results/{id}",
+    tag = "execute",
+    params(("id" = String, Path, description = "Result id")),
+    responses(
+        (status = 200, description = "The stored response, and request if it was kept", body = StoredResult),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("apiKey" = []))
+)]
+#[get("/results/<id>")]
+pub fn get_result_route(
+    _key: ApiKey,
+    results: &State<Results>,
+    id: &str,
+) -> Result<Json<StoredResult>, ApiError> {
+    results.load(id).map(Json)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::Auth;
+    use crate::config::AppConfig;
+    use crate::results::{MemoryStore, Results};
+    use crate::routes::{deploy_route, get_result_route};
+    use crate::shutdown::InFlight;
+    use crate::snapshots::Snapshots;
+    use rocket::http::{ContentType, Status};
+    use rocket::local::blocking::Client;
+    use rocket::routes;
+    use serde_json::{json, Value};
+
+    fn client() -> Client {
+        let config = AppConfig::default();
+        let rocket = rocket::build()
+            .manage(Auth::new(&config))
+            .manage(InFlight::default())
+            .manage(Snapshots::new(&config.limits))
+            .manage(Results::new(MemoryStore::default(), &config.limits))
+            .manage(config)
+            .mount("/", routes![deploy_route, get_result_route])
+            .register("/", crate::error::catchers());
+        Client::tracked(rocket).unwrap()
+    }
+
+    fn deploy(client: &Client, body: Value) -> (Option<String>, Value) {
+        let response = client
+            .post("/deploy")
+            .header(ContentType::JSON)
+            .body(body.to_string())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let id = response.headers().get_one("X-Result-Id").map(String::from);
+        (id, response.into_json().unwrap())
+    }
+
+    #[test]
+    fn test_persist_and_fetch() {
+        let client = client();
+        // Stores 0 in slot 0 and deploys no code
+        let (id, body) = deploy(
+            &client,
+            json!({ "bytecode": "0x6000600055", "persist": true, "persistRequest": true }),
+        );
+        let id = id.expect("a persisted result has an id");
+
+        let response = client.get(format!("/results/{}", id)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let stored: Value = response.into_json().unwrap();
+        assert_eq!(stored["id"], id);
+        assert_eq!(stored["response"], body);
+        assert_eq!(stored["request"]["bytecode"], "0x6000600055");
+    }
+
+    #[test]
+    fn test_not_persisted_by_default() {
+        let client = client();
+        let (id, _) = deploy(&client, json!({ "bytecode": "0x6000600055" }));
+        assert!(id.is_none());
+
+        let response = client.get("/results/00").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        let body: Value = response.into_json().unwrap();
+        assert_eq!(body["code"], "RESULT_NOT_FOUND");
+    }
+}
@@ -0,0 +1,962 @@
+use super::execute_calldatas_fork::{requested_chain_id, resolve_fork};
+use super::validate::{Checked, Validate};
+use crate::admission::ForkSlot;
+use crate::auth::ExecuteKey;
+use crate::compile::solidity::{compile, CompileResult, ContractKind, SolidityFile};
+use crate::config::AppConfig;
+use crate::error::ApiError;
+use crate::format::Negotiated;
+use crate::gas::{
+    deploy_and_execute_calldatas_fork, internal_frames, run_blocking, Breakpoint, ExecutionOptions,
+    ExecutionResult, ForkCall, ForkConfig, GasReport, GasReporter, ResolvedFork, SourceLocation,
+};
+use crate::legacy;
+use crate::results::{Persisted, Persistence, Results};
+use crate::shutdown::Work;
+use crate::telemetry::RequestId;
+use alloy_dyn_abi::{DynSolValue, Specifier};
+use alloy_json_abi::JsonAbi;
+use alloy_primitives::Address;
+use foundry_compilers::artifacts::CompactContractRef;
+use foundry_compilers::compilers::CompilationError;
+use rocket::{http::Status, post, State};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use tracing::Instrument;
+use utoipa::ToSchema;
+
+#[derive(Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RunRequest {
+    pub files: Vec<SolidityFile>,
+    /// The contract to deploy: `File.sol:Name`, or just `Name` when only one file declares it.
+    /// Can be left out when there's only one contract to deploy, not counting abstract
+    /// contracts, interfaces and libraries. `contractName` is accepted too.
+    #[serde(default, alias = "contractName")]
+    pub target_contract: Option<String>,
+    pub constructor_args: Option<Vec<String>>,
+    pub calls: Vec<ForkCall>,
+    pub fork_config: Option<ForkConfig>,
+    pub trace_mode: Option<String>,
+    /// Also return forge's trace arena as `rawTraces`. Deprecated.
+    #[serde(default)]
+    pub include_raw_traces: bool,
+    /// Record the events calls emit as `logs`. Defaults to true.
+    pub collect_logs: Option<bool>,
+    #[serde(flatten)]
+    #[schema(inline)]
+    pub persistence: Persistence,
+    /// Also return `gasReport`, what `forge test --gas-report` would print for the calls. Needs
+    /// traces, so not with `traceMode: "none"`.
+    #[serde(default)]
+    pub gas_report: bool,
+    /// Add `journal` to each result: what the call did, event by event, with where in the
+    /// sources each of the target contract's events came from
+    #[serde(default)]
+    pub journal: bool,
+    /// Add `internalFrames` to each result: the functions the target jumped into within each of
+    /// its calls, with the gas each used and where it's declared. They're read off the jumps, so
+    /// `traceMode` is `jump` unless it's `debug` or `jumpSimple`.
+    #[serde(default)]
+    pub internal_frames: bool,
+    /// Lines of the target's sources to stop its calls at. A call that reaches the first opcode
+    /// compiled from one comes back `paused`, with where it stopped in `pausedAt`, and none of
+    /// what it changed is kept. Each has to be a line some of the target's code was compiled
+    /// from.
+    pub breakpoints: Option<Vec<Breakpoint>>,
+    /// Add `revertReason` to each frame in `traces` that reverted, so a revert a contract caught
+    /// can still be read. Custom errors are named from every contract compiled. Each frame's
+    /// `output` is cut to 24 KiB, with `outputLength` saying how long it was.
+    #[serde(default)]
+    pub capture_subcall_outputs: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RunResponse {
+    pub compilation: CompileResult,
+    /// The contract deployed, as `File.sol:Name`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_contract: Option<String>,
+    #[schema(value_type = Option<String>)]
+    pub address: Option<Address>,
+    pub results: Option<Vec<ExecutionResult>>,
+    /// Only with `gasReport`: each function's min, average, median and max gas over the calls
+    /// that reached it, directly or not, and the deployment's cost, for every contract the calls
+    /// ran. `markdown` has the same as forge's tables.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gas_report: Option<GasReport>,
+}
+
+impl Validate for RunRequest {
+    fn for_legacy(body: &mut Value) {
+        legacy::ask_for_raw_traces(body);
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/run",
+    tag = "execute",
+    request_body = RunRequest,
+    responses(
+        (status = 200, description = "Compiler output, and results when compilation succeeded", body = RunResponse,
+            content_type = ["application/json", "application/msgpack", "application/cbor"],
+            headers(("X-Result-Id" = String, description = "Set when the result was persisted"))),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("apiKey" = []))
+)]
+#[post("/run", format = "json", data = "<req>")]
+pub async fn run_route(
+    _key: ExecuteKey,
+    _work: Work,
+    _slot: ForkSlot,
+    id: RequestId,
+    config: &State<AppConfig>,
+    results: &State<Results>,
+    req: Checked<RunRequest>,
+) -> Result<Persisted<Negotiated<RunResponse>>, ApiError> {
+    let req = req.into_inner();
+    let fork = resolve_fork(config, req.fork_config.as_ref())?;
+    id.record_execution(requested_chain_id(&fork), req.calls.len());
+    let persistence = req.persistence;
+    let request = persistence.request(&req);
+    let response = run(config, req, fork).instrument(id.span()).await?;
+
+    let result_id = results.keep(persistence, None, &response, request)?;
+    Ok(Persisted {
+        response: Negotiated(response),
+        result_id,
+    })
+}
+
+pub async fn run(
+    config: &AppConfig,
+    req: RunRequest,
+    fork: ResolvedFork,
+) -> Result<RunResponse, ApiError> {
+    super::validate::check_sources(&config.limits, &req.files)?;
+    super::validate::check_calls(
+        &config.limits,
+        req.calls.iter().map(|call| call.calldata.len()),
+    )?;
+    super::validate::check_call_meta(
+        &config.limits,
+        req.calls.iter().map(|call| call.meta.as_ref()),
+    )?;
+    if req.gas_report && req.trace_mode.as_deref() == Some("none") {
+        return Err(super::validate::invalid_field(
+            "gasReport",
+            "needs traces, which traceMode none skips",
+        ));
+    }
+
+    // solc blocks until it's done, so it runs on the blocking pool
+    let (files, limits) = (req.files.clone(), config.limits.clone());
+    let compilation = run_blocking(move || compile(&files, &limits))
+        .await
+        .map_err(ApiError::compile_failed)?;
+
+    // Don't execute anything if the sources didn't compile
+    if compilation.has_errors() {
+        return Ok(RunResponse {
+            compilation,
+            target_contract: None,
+            address: None,
+            results: None,
+            gas_report: None,
+        });
+    }
+
+    let target = target_contract(&compilation, req.target_contract.as_deref())?;
+    let creation_code =
+        creation_code(&target, req.constructor_args.as_deref().unwrap_or_default())?;
+    let (target_name, contract_name) = (target.fully_qualified, target.name);
+    let abi = target.contract.abi.cloned();
+
+    // Internal frames are read off the jumps, which only these modes record
+    let records_jumps = matches!(
+        req.trace_mode.as_deref(),
+        Some("debug" | "jump" | "jumpSimple")
+    );
+    let trace_mode = match req.internal_frames && !records_jumps {
+        true => Some("jump".to_string()),
+        false => req.trace_mode,
+    };
+    // Internal frames are read off the raw traces, which are dropped after unless asked for
+    let options = ExecutionOptions::new(
+        trace_mode,
+        req.include_raw_traces || req.internal_frames,
+        req.collect_logs,
+        None,
+        false,
+    );
+    let options = match req.journal {
+        false => options,
+        true => Some(ExecutionOptions {
+            journal: true,
+            ..options.unwrap_or_default()
+        }),
+    };
+    let options = match req.capture_subcall_outputs {
+        false => options,
+        true => Some(ExecutionOptions {
+            capture_subcall_outputs: true,
+            errors: Some(
+                compilation
+                    .contracts
+                    .contracts_with_files_and_version()
+                    .flat_map(|(_, _, contract, _)| contract.abi.iter())
+                    .flat_map(|abi| abi.errors().cloned())
+                    .collect(),
+            ),
+            ..options.unwrap_or_default()
+        }),
+    };
+    let options = match req.breakpoints {
+        None => options,
+        Some(breakpoints) => {
+            let sources = compilation
+                .source_locations(&target_name)
+                .unwrap_or_default();
+            check_breakpoints(&breakpoints, &sources, &target_name)?;
+            Some(ExecutionOptions {
+                breakpoints: Some(breakpoints),
+                source_locations: Some(sources),
+                ..options.unwrap_or_default()
+            })
+        }
+    };
+
+    let (deployment, mut results) =
+        deploy_and_execute_calldatas_fork(config, creation_code.into(), req.calls, fork, options)
+            .await
+            .map_err(ApiError::from_execution)?;
+    locate_journals(&compilation, &target_name, deployment.address, &mut results);
+    if req.internal_frames {
+        let map = compilation.code_map(&target_name).unwrap_or_default();
+        for result in &mut results {
+            result.internal_frames = result
+                .raw_traces
+                .as_ref()
+                .map(|arena| internal_frames(arena, deployment.address, &map));
+            if !req.include_raw_traces {
+                result.raw_traces = None;
+            }
+        }
+    }
+
+    let gas_report = req.gas_report.then(|| {
+        let mut reporter = GasReporter::new();
+        reporter.name(deployment.address, &contract_name, abi.as_ref());
+        reporter.deployed(
+            deployment.address,
+            deployment.gas_used,
+            deployment.code_size,
+        );
+        for result in &results {
+            reporter.record(result);
+        }
+        reporter.report()
+    });
+
+    Ok(RunResponse {
+        compilation,
+        target_contract: Some(target_name),
+        address: Some(deployment.address),
+        results: Some(results),
+        gas_report,
+    })
+}
+
+// Fails on the first breakpoint none of the target's code, located by `sources`, is on
+fn check_breakpoints(
+    breakpoints: &[Breakpoint],
+    sources: &BTreeMap<usize, SourceLocation>,
+    target: &str,
+) -> Result<(), ApiError> {
+    match breakpoints
+        .iter()
+        .position(|breakpoint| !sources.values().any(|source| breakpoint.matches(source)))
+    {
+        None => Ok(()),
+        Some(i) => Err(super::validate::invalid_field(
+            &format!("breakpoints[{}]", i),
+            format!(
+                "is at {}:{}, which none of {}'s code was compiled from",
+                breakpoints[i].file, breakpoints[i].line, target
+            ),
+        )),
+    }
+}
+
+// Points the journal events the target ran at the source they came from
+fn locate_journals(
+    compilation: &CompileResult,
+    target: &str,
+    address: Address,
+    results: &mut [ExecutionResult],
+) {
+    let events = results
+        .iter_mut()
+        .flat_map(|result| result.journal.iter_mut().flatten())
+        .filter(|event| event.address == address);
+    for event in events {
+        event.source = event
+            .pc
+            .and_then(|pc| compilation.source_location(target, pc));
+    }
+}
+
+/// A contract picked out of a compilation to deploy.
+pub(super) struct Target<'a> {
+    /// `File.sol:Name`, with the file as it was sent
+    pub fully_qualified: String,
+    pub name: String,
+    pub contract: CompactContractRef<'a>,
+}
+
+/// The contract `target` names in `compilation`, either `File.sol:Name` or a name only one file
+/// declares, or with no `target` the only one there is to deploy. Abstract contracts and
+/// interfaces are refused, and so is a choice that isn't clear, with the candidates in `details`.
+pub(super) fn target_contract<'a>(
+    compilation: &'a CompileResult,
+    target: Option<&str>,
+) -> Result<Target<'a>, ApiError> {
+    let contracts: Vec<_> = compilation
+        .contracts
+        .contracts_with_files_and_version()
+        .map(|(file, name, contract, _)| Target {
+            fully_qualified: format!("{}:{}", compilation.source_name(file), name),
+            name: name.clone(),
+            contract: CompactContractRef::from(contract),
+        })
+        .collect();
+    let mut matching: Vec<_> = match target {
+        Some(target) => contracts
+            .into_iter()
+            .filter(|contract| {
+                if target.contains(':') {
+                    contract.fully_qualified == target
+                } else {
+                    contract.name == target
+                }
+            })
+            .collect(),
+        // Helpers left out: the contracts there's no point deploying
+        None => contracts
+            .into_iter()
+            .filter(|contract| {
+                has_code(contract)
+                    && compilation.kind(source_of(contract), &contract.name)
+                        != ContractKind::Library
+            })
+            .collect(),
+    };
+    if matching.len() != 1 {
+        return Err(unclear_target(target, &matching));
+    }
+    let found = matching.remove(0);
+    if !has_code(&found) {
+        let kind = match compilation.kind(source_of(&found), &found.name) {
+            ContractKind::Interface => "an interface",
+            ContractKind::Abstract => "abstract",
+            _ => "missing its bytecode",
+        };
+        return Err(ApiError::new(
+            Status::UnprocessableEntity,
+            "CONTRACT_NOT_DEPLOYABLE",
+            format!(
+                "Contract {} is {}, so it can't be deployed",
+                found.fully_qualified, kind
+            ),
+        ));
+    }
+    Ok(found)
+}
+
+fn has_code(target: &Target) -> bool {
+    target
+        .contract
+        .bin
+        .and_then(|bin| bin.as_bytes())
+        .is_some_and(|bytes| !bytes.is_empty())
+}
+
+fn source_of<'t>(target: &'t Target) -> &'t str {
+    target
+        .fully_qualified
+        .rsplit_once(':')
+        .map_or("", |(source, _)| source)
+}
+
+// No contract for `target`, or more than one
+fn unclear_target(target: Option<&str>, candidates: &[Target]) -> ApiError {
+    if candidates.is_empty() {
+        let message = match target {
+            Some(target) => format!("Contract {} not found in compile output", target),
+            None => "Nothing in the sources can be deployed".to_string(),
+        };
+        return ApiError::new(Status::UnprocessableEntity, "CONTRACT_NOT_FOUND", message);
+    }
+    let message = match target {
+        Some(target) => format!(
+            "More than one file declares {}, name one as File.sol:{} in targetContract",
+            target, target
+        ),
+        None => "More than one contract can be deployed, name one in targetContract".to_string(),
+    };
+    let candidates: Vec<_> = candidates
+        .iter()
+        .map(|candidate| candidate.fully_qualified.as_str())
+        .collect();
+    ApiError::new(Status::UnprocessableEntity, "AMBIGUOUS_CONTRACT", message)
+        .with_details(json!({ "candidates": candidates }))
+}
+
+/// `target`'s creation code, with `args` ABI-encoded onto the end.
+pub(super) fn creation_code(target: &Target, args: &[String]) -> Result<Vec<u8>, ApiError> {
+    let contract = &target.contract;
+    let mut creation_code = contract
+        .bin
+        .and_then(|bin| bin.as_bytes())
+        .ok_or_else(|| {
+            ApiError::new(
+                Status::UnprocessableEntity,
+                "CONTRACT_NOT_DEPLOYABLE",
+                format!("Contract {} has no bytecode", target.fully_qualified),
+            )
+        })?
+        .to_vec();
+    creation_code.extend(encode_constructor_args(contract.abi, args).map_err(|err| {
+        ApiError::new(
+            Status::UnprocessableEntity,
+            "INVALID_CONSTRUCTOR_ARGS",
+            err.to_string(),
+        )
+    })?);
+    Ok(creation_code)
+}
+
+fn encode_constructor_args(abi: Option<&JsonAbi>, args: &[String]) -> Result<Vec<u8>, eyre::Error> {
+    let inputs = match abi.and_then(|abi| abi.constructor()) {
+        Some(constructor) => &constructor.inputs[..],
+        None => &[][..],
+    };
+    if inputs.len() != args.len() {
+        return Err(eyre::eyre!(
+            "Constructor takes {} arguments, got {}",
+            inputs.len(),
+            args.len()
+        ));
+    }
+
+    let values = inputs
+        .iter()
+        .zip(args)
+        .enumerate()
+        .map(|(i, (param, arg))| {
+            param
+                .resolve()
+                .and_then(|ty| ty.coerce_str(arg))
+                .map_err(|err| eyre::eyre!("constructorArgs[{}]: {}", i, err))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(DynSolValue::Tuple(values).abi_encode_params())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Limits;
+    use crate::decode::RevertReason;
+    use crate::gas::{ExitReason, InternalFrame, JournalEntry, TraceStatus};
+    use alloy_primitives::{hex, keccak256, Bytes, U256};
+    use std::str::FromStr;
+
+    const SIMPLE_STORAGE: &str = r#"
+        pragma solidity ^0.8.0;
+
+        contract SimpleStorage {
+            uint256 public storedData;
+
+            constructor(uint256 initial) {
+                storedData = initial;
+            }
+
+            function set(uint256 x) public {
+                storedData = x;
+            }
+
+            function get() public view returns (uint256) {
+                return storedData;
+            }
+        }
+    "#;
+
+    fn request(content: &str) -> RunRequest {
+        let caller = Address::from_str("0x1000000000000000000000000000000000000000").unwrap();
+        RunRequest {
+            files: vec![SolidityFile {
+                name: "SimpleStorage.sol".to_string(),
+                content: content.to_string(),
+            }],
+            target_contract: Some("SimpleStorage".to_string()),
+            constructor_args: Some(vec!["7".to_string()]),
+            calls: vec![
+                ForkCall {
+                    // set(1)
+                    calldata: Bytes::from_str(
+                        "0x60fe47b10000000000000000000000000000000000000000000000000000000000000001",
+                    )
+                    .unwrap(),
+                    value: U256::ZERO,
+                    caller: Some(caller.into()),
+                    independent: false,
+                    signature: None,
+                    args: None,
+                    meta: None,
+                },
+                ForkCall {
+                    calldata: Bytes::from_str("0x6d4ce63c").unwrap(), // get()
+                    value: U256::ZERO,
+                    caller: Some(caller.into()),
+                    independent: false,
+                    signature: None,
+                    args: None,
+                    meta: None,
+                },
+            ],
+            fork_config: None,
+            trace_mode: None,
+            include_raw_traces: false,
+            collect_logs: None,
+            persistence: Persistence::default(),
+            gas_report: false,
+            journal: false,
+            internal_frames: false,
+            breakpoints: None,
+            capture_subcall_outputs: false,
+        }
+    }
+
+    async fn resolve_and_run(config: &AppConfig, req: RunRequest) -> Result<RunResponse, ApiError> {
+        let fork = resolve_fork(config, req.fork_config.as_ref())?;
+        run(config, req, fork).await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_run_simple_storage() {
+        let config = AppConfig::default();
+        let mut req = request(SIMPLE_STORAGE);
+        req.fork_config = Some(ForkConfig {
+            network: Some("demo".to_string()),
+            ..Default::default()
+        });
+        let response = resolve_and_run(&config, req).await.unwrap();
+
+        assert!(response.address.is_some());
+        assert_eq!(
+            response.target_contract.as_deref(),
+            Some("SimpleStorage.sol:SimpleStorage")
+        );
+        let results = response.results.unwrap();
+        assert_eq!(
+            hex::encode(&results[1].result),
+            "0000000000000000000000000000000000000000000000000000000000000001"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_run_gas_report() {
+        let config = AppConfig::default();
+        let mut req = request(SIMPLE_STORAGE);
+        req.fork_config = Some(ForkConfig {
+            network: Some("demo".to_string()),
+            ..Default::default()
+        });
+        req.gas_report = true;
+        // set(1), get(), set(2), get(), get()
+        let (set, get) = (req.calls[0].clone(), req.calls[1].clone());
+        let mut set_two = set.clone();
+        set_two.calldata = Bytes::from_str(
+            "0x60fe47b10000000000000000000000000000000000000000000000000000000000000002",
+        )
+        .unwrap();
+        req.calls = vec![set, get.clone(), set_two, get.clone(), get];
+        let response = resolve_and_run(&config, req).await.unwrap();
+
+        let report = response.gas_report.unwrap();
+        assert_eq!(report.contracts.len(), 1);
+        let contract = &report.contracts[0];
+        assert_eq!(contract.name, "SimpleStorage");
+        assert_eq!(Some(contract.address), response.address);
+        let deployment = contract.deployment.as_ref().unwrap();
+        assert!(deployment.gas > 53_000 && deployment.size > 0);
+
+        let calls: Vec<_> = contract
+            .functions
+            .iter()
+            .map(|function| (function.name.as_str(), function.calls))
+            .collect();
+        assert_eq!(calls, [("get", 3), ("set", 2)]);
+        for function in &contract.functions {
+            assert!(function.min <= function.avg && function.avg <= function.max);
+        }
+        assert!(report.markdown.starts_with("| SimpleStorage contract |"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_journal() {
+        let config = AppConfig::default();
+        let mut req = request(SIMPLE_STORAGE);
+        req.fork_config = Some(ForkConfig {
+            network: Some("demo".to_string()),
+            ..Default::default()
+        });
+        req.journal = true;
+        let response = resolve_and_run(&config, req).await.unwrap();
+        let results = response.results.unwrap();
+
+        // set(1)
+        let journal = results[0].journal.as_ref().unwrap();
+        let writes: Vec<_> = journal
+            .iter()
+            .filter(|event| matches!(event.entry, JournalEntry::StorageWrite { .. }))
+            .collect();
+        assert_eq!(writes.len(), 1);
+        assert_eq!(
+            writes[0].entry,
+            JournalEntry::StorageWrite {
+                slot: U256::ZERO,
+                value: U256::from(1),
+            }
+        );
+        assert_eq!(Some(writes[0].address), response.address);
+        let line = SIMPLE_STORAGE
+            .lines()
+            .position(|line| line.contains("storedData = x;"))
+            .unwrap()
+            + 1;
+        let source = writes[0].source.as_ref().unwrap();
+        assert_eq!(
+            (source.file.as_str(), source.line),
+            ("SimpleStorage.sol", line)
+        );
+
+        // The call in and out around it
+        assert!(matches!(journal[0].entry, JournalEntry::CallEnter { .. }));
+        assert!(matches!(
+            journal.last().unwrap().entry,
+            JournalEntry::CallExit { .. }
+        ));
+        // get() writes nothing
+        let journal = results[1].journal.as_ref().unwrap();
+        assert!(journal
+            .iter()
+            .all(|event| !matches!(event.entry, JournalEntry::StorageWrite { .. })));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_breakpoint() {
+        let config = AppConfig::default();
+        let mut req = request(SIMPLE_STORAGE);
+        req.fork_config = Some(ForkConfig {
+            network: Some("demo".to_string()),
+            ..Default::default()
+        });
+        let line = SIMPLE_STORAGE
+            .lines()
+            .position(|line| line.contains("storedData = x;"))
+            .unwrap()
+            + 1;
+        req.breakpoints = Some(vec![Breakpoint {
+            file: "SimpleStorage.sol".to_string(),
+            line,
+        }]);
+        let response = resolve_and_run(&config, req).await.unwrap();
+        let results = response.results.unwrap();
+
+        // set(1) stops before its SSTORE
+        let set = &results[0];
+        assert_eq!(set.exit_reason, ExitReason::Paused);
+        assert!(!set.success && !set.reverted);
+        let paused = set.paused_at.as_ref().unwrap();
+        assert_eq!(
+            (paused.file.as_str(), paused.line),
+            ("SimpleStorage.sol", line)
+        );
+        assert_eq!(Some(paused.address), response.address);
+        assert_eq!(paused.depth, 0);
+        assert!(!paused.stack.is_empty());
+        assert!(paused.storage_writes_so_far.is_empty());
+
+        // So get() still sees what the constructor stored
+        let get = &results[1];
+        assert!(get.success);
+        assert_eq!(get.paused_at, None);
+        assert_eq!(hex::encode(&get.result), format!("{:064x}", 7));
+    }
+
+    #[tokio::test]
+    async fn test_breakpoint_without_code() {
+        let config = AppConfig::default();
+        let mut req = request(SIMPLE_STORAGE);
+        // The pragma
+        req.breakpoints = Some(vec![Breakpoint {
+            file: "SimpleStorage.sol".to_string(),
+            line: 2,
+        }]);
+        let err = resolve_and_run(&config, req).await.err().unwrap();
+        assert_eq!(err.code, "INVALID_FIELD");
+        assert_eq!(err.details.unwrap()["field"], "breakpoints[0]");
+    }
+
+    const TALLY: &str = r#"
+        pragma solidity ^0.8.0;
+
+        contract Tally {
+            uint256 public total;
+
+            function add(uint256 x) public returns (uint256) {
+                uint256 squared = square(x);
+                record(squared);
+                return squared;
+            }
+
+            function square(uint256 x) internal pure returns (uint256) {
+                return x * x;
+            }
+
+            function record(uint256 x) internal {
+                total += x;
+            }
+        }
+    "#;
+
+    // Every frame in `frames`, depth first
+    fn flatten(frames: &[InternalFrame]) -> Vec<&InternalFrame> {
+        frames
+            .iter()
+            .flat_map(|frame| std::iter::once(frame).chain(flatten(&frame.children)))
+            .collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_internal_frames() {
+        let config = AppConfig::default();
+        let mut req = request(TALLY);
+        req.files[0].name = "Tally.sol".to_string();
+        req.target_contract = None;
+        req.constructor_args = None;
+        let mut calldata = keccak256("add(uint256)")[..4].to_vec();
+        calldata.extend(U256::from(3).to_be_bytes::<32>());
+        req.calls.truncate(1);
+        req.calls[0].calldata = calldata.into();
+        req.fork_config = Some(ForkConfig {
+            network: Some("demo".to_string()),
+            ..Default::default()
+        });
+        req.internal_frames = true;
+        let response = resolve_and_run(&config, req).await.unwrap();
+        let result = &response.results.unwrap()[0];
+        assert!(result.success);
+        // Only kept to read the frames off
+        assert!(result.raw_traces.is_none());
+
+        let frames = result.internal_frames.as_ref().unwrap();
+        let all = flatten(frames);
+        let named = |name: &str| {
+            *all.iter()
+                .find(|frame| frame.function.as_deref() == Some(name))
+                .unwrap_or_else(|| panic!("no frame for {}", name))
+        };
+        let (add, square, record) = (named("add"), named("square"), named("record"));
+        assert!(add.children.contains(square) && add.children.contains(record));
+        assert!(square.enter_step < record.enter_step);
+        assert!(square.exit_step.unwrap() < record.enter_step);
+        assert_eq!(square.depth, 0);
+
+        // A multiplication, against a fresh storage slot's 22,100 for the write
+        assert!(square.gas_used > 0 && square.gas_used < 1_000);
+        assert!(record.gas_used > 22_100 && record.gas_used < 30_000);
+        assert!(add.gas_used >= square.gas_used + record.gas_used);
+
+        let line = TALLY
+            .lines()
+            .position(|line| line.contains("function record"))
+            .unwrap()
+            + 1;
+        let source = record.source.as_ref().unwrap();
+        assert_eq!(source.file, "Tally.sol");
+        assert_eq!(source.line, line);
+    }
+
+    const CAUGHT: &str = r#"
+        pragma solidity ^0.8.4;
+
+        contract Inner {
+            error Unauthorized(address caller, uint256 code);
+
+            function poke() external view {
+                revert Unauthorized(msg.sender, 42);
+            }
+        }
+
+        contract Outer {
+            Inner public inner = new Inner();
+
+            function tryPoke() external view returns (bool) {
+                try inner.poke() {
+                    return true;
+                } catch {
+                    return false;
+                }
+            }
+        }
+    "#;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_capture_subcall_outputs() {
+        let config = AppConfig::default();
+        let mut req = request(CAUGHT);
+        req.files[0].name = "Caught.sol".to_string();
+        req.target_contract = Some("Outer".to_string());
+        req.constructor_args = None;
+        req.calls.truncate(1);
+        req.calls[0].calldata = keccak256("tryPoke()")[..4].to_vec().into();
+        req.fork_config = Some(ForkConfig {
+            network: Some("demo".to_string()),
+            ..Default::default()
+        });
+        req.capture_subcall_outputs = true;
+        let response = resolve_and_run(&config, req).await.unwrap();
+        let result = &response.results.unwrap()[0];
+        // The revert was caught, so the call itself went through
+        assert!(result.success);
+        assert_eq!(hex::encode(&result.result), format!("{:064x}", 0));
+
+        let outer = &result.traces.as_ref().unwrap()[0];
+        assert_eq!(outer.revert_reason, None);
+        let inner = &outer.children[0];
+        assert_eq!(inner.status, TraceStatus::Revert);
+        let Some(RevertReason::Custom { name, args, .. }) = &inner.revert_reason else {
+            panic!("not decoded: {:?}", inner.revert_reason);
+        };
+        assert_eq!(name, "Unauthorized");
+        assert_eq!(args[1], "42");
+        assert_eq!(inner.output_length, None);
+    }
+
+    const HELPERS: &str = r#"
+        pragma solidity ^0.8.0;
+
+        interface IStore {
+            function get() external view returns (uint256);
+        }
+
+        library Math {
+            function double(uint256 x) external pure returns (uint256) {
+                return x * 2;
+            }
+        }
+
+        abstract contract Base is IStore {}
+
+        contract Store is Base {
+            function get() external pure returns (uint256) {
+                return 1;
+            }
+        }
+    "#;
+
+    fn compiled(files: &[(&str, &str)]) -> CompileResult {
+        let files: Vec<_> = files
+            .iter()
+            .map(|(name, content)| SolidityFile {
+                name: name.to_string(),
+                content: content.to_string(),
+            })
+            .collect();
+        let compilation = compile(&files, &Limits::default()).unwrap();
+        assert!(!compilation.has_errors());
+        compilation
+    }
+
+    #[test]
+    fn test_target_auto_selected() {
+        let compilation = compiled(&[("Store.sol", HELPERS)]);
+        // The interface, the library and the abstract contract aren't candidates
+        let target = target_contract(&compilation, None).unwrap();
+        assert_eq!(target.fully_qualified, "Store.sol:Store");
+        assert_eq!(target.name, "Store");
+
+        let target = target_contract(&compilation, Some("Store.sol:Store")).unwrap();
+        assert_eq!(target.fully_qualified, "Store.sol:Store");
+        // Named, a library can still be deployed
+        let target = target_contract(&compilation, Some("Math")).unwrap();
+        assert_eq!(target.fully_qualified, "Store.sol:Math");
+
+        let err = target_contract(&compilation, Some("Missing"))
+            .err()
+            .unwrap();
+        assert_eq!(err.code, "CONTRACT_NOT_FOUND");
+    }
+
+    #[test]
+    fn test_ambiguous_target() {
+        let token = "pragma solidity ^0.8.0;\ncontract Token {}";
+        let compilation = compiled(&[("A.sol", token), ("B.sol", token)]);
+        for target in [Some("Token"), None] {
+            let err = target_contract(&compilation, target).err().unwrap();
+            assert_eq!(err.status, Status::UnprocessableEntity);
+            assert_eq!(err.code, "AMBIGUOUS_CONTRACT");
+            assert_eq!(
+                err.details.unwrap()["candidates"],
+                json!(["A.sol:Token", "B.sol:Token"])
+            );
+        }
+        let target = target_contract(&compilation, Some("B.sol:Token")).unwrap();
+        assert_eq!(target.fully_qualified, "B.sol:Token");
+    }
+
+    #[test]
+    fn test_abstract_target_rejected() {
+        let compilation = compiled(&[("Store.sol", HELPERS)]);
+        let err = target_contract(&compilation, Some("Base")).err().unwrap();
+        assert_eq!(err.code, "CONTRACT_NOT_DEPLOYABLE");
+        assert_eq!(
+            err.message,
+            "Contract Store.sol:Base is abstract, so it can't be deployed"
+        );
+        let err = target_contract(&compilation, Some("IStore")).err().unwrap();
+        assert_eq!(
+            err.message,
+            "Contract Store.sol:IStore is an interface, so it can't be deployed"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_gas_report_needs_traces() {
+        let config = AppConfig::default();
+        let mut req = request(SIMPLE_STORAGE);
+        req.gas_report = true;
+        req.trace_mode = Some("none".to_string());
+        let err = resolve_and_run(&config, req).await.err().unwrap();
+        assert_eq!(err.code, "INVALID_FIELD");
+    }
+
+    #[tokio::test]
+    async fn test_run_skips_execution_on_compile_error() {
+        // Needs no RPC: a syntax error stops the run before forking
+        let config = AppConfig::default();
+        let response = resolve_and_run(&config, request("contract SimpleStorage {"))
+            .await
+            .unwrap();
+
+        assert!(!response.compilation.errors.is_empty());
+        assert!(response.results.is_none());
+    }
+}
@@ -0,0 +1,106 @@
+use crate::auth::ApiKey;
+use crate::error::ApiError;
+use crate::storage::{locate, parse_slot, SlotLocation, SlotType, StorageLayout};
+use rocket::{http::Status, post, serde::json::Json};
+use serde::Deserialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageSlotRequest {
+    /// A contract's `storageLayout` from the compile output, used with `variable`
+    pub layout: Option<StorageLayout>,
+    pub variable: Option<String>,
+    /// Decimal or 0x-prefixed hex, used with `type` when there's no layout
+    pub base_slot: Option<String>,
+    /// e.g. `mapping(address => uint256[])`
+    #[serde(rename = "type")]
+    pub ty: Option<String>,
+    /// Mapping keys, array indices and struct member names, outermost first
+    #[serde(default)]
+    pub path: Vec<Value>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/storage/slot",
+    tag = "tools",
+    request_body = StorageSlotRequest,
+    responses(
+        (status = 200, description = "The slot, where the value sits in it, and how it was computed", body = SlotLocation),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("apiKey" = []))
+)]
+#[post("/storage/slot", format = "json", data = "<req>")]
+pub fn storage_slot_route(
+    _key: ApiKey,
+    req: Json<StorageSlotRequest>,
+) -> Result<Json<SlotLocation>, ApiError> {
+    storage_slot(req.into_inner()).map(Json)
+}
+
+pub fn storage_slot(req: StorageSlotRequest) -> Result<SlotLocation, ApiError> {
+    let invalid = |err: eyre::Error| ApiError::invalid_request(err.to_string());
+    let (slot, offset, ty) = match (req.layout, req.variable, req.base_slot, req.ty) {
+        (Some(layout), Some(variable), None, None) => {
+            layout.variable(&variable).map_err(invalid)?
+        }
+        (None, None, Some(base_slot), Some(ty)) => (
+            parse_slot(&base_slot).map_err(invalid)?,
+            0,
+            SlotType::parse(&ty).map_err(invalid)?,
+        ),
+        _ => {
+            return Err(ApiError::invalid_request(
+                "provide either layout and variable, or baseSlot and type",
+            ))
+        }
+    };
+    locate(slot, offset, &ty, &req.path).map_err(|err| {
+        ApiError::new(
+            Status::UnprocessableEntity,
+            "INVALID_STORAGE_PATH",
+            err.to_string(),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_requires_one_input_form() {
+        let err = storage_slot(StorageSlotRequest {
+            layout: None,
+            variable: Some("balances".to_string()),
+            base_slot: Some("0".to_string()),
+            ty: Some("uint256".to_string()),
+            path: vec![],
+        })
+        .err()
+        .unwrap();
+        assert_eq!(err.code, "INVALID_REQUEST");
+    }
+
+    #[test]
+    fn test_bad_path() {
+        let err = storage_slot(StorageSlotRequest {
+            layout: None,
+            variable: None,
+            base_slot: Some("0x3".to_string()),
+            ty: Some("mapping(address => uint256)".to_string()),
+            path: vec![
+                json!("0x1000000000000000000000000000000000000000"),
+                json!(1),
+            ],
+        })
+        .err()
+        .unwrap();
+        assert_eq!(err.code, "INVALID_STORAGE_PATH");
+        assert!(err.message.starts_with("path[1]"));
+    }
+}
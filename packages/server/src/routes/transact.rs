@@ -0,0 +1,521 @@
+use crate::auth::ExecuteKey;
+use crate::config::AppConfig;
+use crate::error::ApiError;
+use crate::format::Negotiated;
+use crate::gas::op_stack::{self, OpStackParams};
+use crate::gas::{
+    to_alloc, transact_local, EncodedState, ExecutionOptions, ExecutionResult, StateDump,
+    DEFAULT_DEPLOYER,
+};
+use crate::ids;
+use crate::legacy;
+use crate::results::{Persisted, Persistence, Results};
+use crate::shutdown::Work;
+use crate::snapshots::Snapshots;
+use crate::telemetry::RequestId;
+use alloy_primitives::{Address, Bytes, U256};
+use rocket::{post, State};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::iter;
+use utoipa::ToSchema;
+
+use super::validate::{self, Checked, Validate};
+
+#[derive(Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactRequest {
+    #[schema(value_type = String)]
+    pub to: Address,
+    #[serde(default)]
+    #[schema(value_type = String)]
+    pub calldata: Bytes,
+    #[schema(value_type = Option<String>)]
+    pub value: Option<U256>,
+    /// `0x1804c8AB1F12E6bbf3894d4083f33e07309d1f38` without one
+    #[schema(value_type = Option<String>)]
+    pub caller: Option<Address>,
+    /// A state returned by an earlier `/deploy` or `/transact`...
+    pub state_id: Option<String>,
+    /// ...or one passed in full, as returned or as a genesis file's `alloc`
+    #[schema(value_type = Option<BTreeMap<String, AccountDump>>)]
+    pub state: Option<EncodedState>,
+    /// `genesis` returns `state` as a genesis file's `alloc`
+    pub state_format: Option<String>,
+    pub trace_mode: Option<String>,
+    /// Also return forge's trace arena as `rawTraces`. Deprecated.
+    #[serde(default)]
+    pub include_raw_traces: bool,
+    /// Record the events calls emit as `logs`. Defaults to true.
+    pub collect_logs: Option<bool>,
+    /// Run under this hardfork's rules, e.g. `istanbul`, instead of the latest
+    pub hardfork: Option<String>,
+    /// `op-stack` adds stand-ins for the `L1Block` and `GasPriceOracle` predeploys to the
+    /// state, replacing whatever is at their addresses
+    pub chain_profile: Option<String>,
+    /// The L1 values they report, with the `op-stack` profile
+    pub op_stack: Option<OpStackParams>,
+    #[serde(flatten)]
+    #[schema(inline)]
+    pub persistence: Persistence,
+    /// Derive `stateId`, and `X-Result-Id` when persisted, from the request instead of making
+    /// them up, so the same request gets a byte-identical response. Only the `X-Request-Id`
+    /// header and a persisted result's `createdAt` still differ.
+    #[serde(default)]
+    pub deterministic: bool,
+    /// Reset the sender's nonce to 0 before running, so what it deploys lands at the same
+    /// address whatever state the request starts from, and return `created`: what the address
+    /// of every contract created was derived from
+    #[serde(default)]
+    pub deterministic_addresses: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactResponse {
+    #[serde(flatten)]
+    pub result: ExecutionResult,
+    pub state_id: String,
+    /// Every account the chain has touched, in `stateFormat`
+    #[schema(value_type = BTreeMap<String, AccountDump>)]
+    pub state: EncodedState,
+}
+
+impl Validate for TransactRequest {
+    fn for_legacy(body: &mut Value) {
+        legacy::ask_for_raw_traces(body);
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/transact",
+    tag = "execute",
+    request_body = TransactRequest,
+    responses(
+        (status = 200, description = "The call's result and the state after it", body = TransactResponse,
+            content_type = ["application/json", "application/msgpack", "application/cbor"],
+            headers(("X-Result-Id" = String, description = "Set when the result was persisted"))),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("apiKey" = []))
+)]
+#[post("/transact", format = "json", data = "<req>")]
+pub fn transact_route(
+    _key: ExecuteKey,
+    _work: Work,
+    id: RequestId,
+    config: &State<AppConfig>,
+    snapshots: &State<Snapshots>,
+    results: &State<Results>,
+    req: Checked<TransactRequest>,
+) -> Result<Persisted<Negotiated<TransactResponse>>, ApiError> {
+    let _span = id.span().entered();
+    id.record_execution(None, 1);
+    let req = req.into_inner();
+    let persistence = req.persistence;
+    let request = persistence.request(&req);
+    let derived_id = req.deterministic.then(|| ids::derived(&req));
+    validate::check_calls(&config.limits, iter::once(req.calldata.len()))?;
+    check_state_format(req.state_format.as_deref())?;
+    validate::check_hardfork(req.hardfork.as_deref())?;
+
+    let state = starting_state(snapshots, req.state_id, req.state)?;
+    let state = with_chain_profile(state, req.chain_profile.as_deref(), req.op_stack.as_ref())?;
+    let caller = req.caller.unwrap_or(DEFAULT_DEPLOYER);
+    let state = with_fresh_nonce(state, caller, req.deterministic_addresses);
+    let (result, state) = transact_local(
+        state,
+        req.to,
+        req.calldata,
+        req.value.unwrap_or_default(),
+        caller,
+        ExecutionOptions::with_deterministic_addresses(
+            ExecutionOptions::with_hardfork(
+                ExecutionOptions::new(
+                    req.trace_mode,
+                    req.include_raw_traces,
+                    req.collect_logs,
+                    None,
+                    false,
+                ),
+                req.hardfork,
+            ),
+            req.deterministic_addresses,
+        ),
+    )
+    .map_err(ApiError::from_execution)?;
+
+    let response = TransactResponse {
+        result,
+        state_id: match derived_id.clone() {
+            Some(id) => snapshots.insert_as(id, state.clone()),
+            None => snapshots.insert(state.clone()),
+        },
+        state: encode_state(state, req.state_format.as_deref()),
+    };
+    let result_id = results.keep(persistence, derived_id, &response, request)?;
+    Ok(Persisted {
+        response: Negotiated(response),
+        result_id,
+    })
+}
+
+/// The state a request runs on: a stored snapshot, one passed in full, or an empty chain.
+pub(super) fn starting_state(
+    snapshots: &Snapshots,
+    state_id: Option<String>,
+    state: Option<EncodedState>,
+) -> Result<StateDump, ApiError> {
+    match (state_id, state) {
+        (Some(_), Some(_)) => Err(ApiError::invalid_request(
+            "pass either stateId or state, not both",
+        )),
+        (Some(id), None) => Ok(snapshots.get(&id)?.as_ref().clone()),
+        (None, state) => Ok(state.map(EncodedState::into_dump).unwrap_or_default()),
+    }
+}
+
+/// `state` with what `profile` adds to it.
+pub(super) fn with_chain_profile(
+    mut state: StateDump,
+    profile: Option<&str>,
+    op_stack: Option<&OpStackParams>,
+) -> Result<StateDump, ApiError> {
+    match profile {
+        None if op_stack.is_some() => Err(validate::invalid_field(
+            "opStack",
+            "only applies with chainProfile op-stack",
+        )),
+        None => Ok(state),
+        Some("op-stack") => {
+            state.extend(op_stack::predeploys(&op_stack.cloned().unwrap_or_default()));
+            Ok(state)
+        }
+        Some(_) => Err(validate::invalid_field("chainProfile", "expected op-stack")),
+    }
+}
+
+/// `state` with `sender`'s nonce back at 0 for `deterministicAddresses`, so its deployments land
+/// where they would on a fresh chain.
+pub(super) fn with_fresh_nonce(
+    mut state: StateDump,
+    sender: Address,
+    deterministic_addresses: bool,
+) -> StateDump {
+    if let Some(account) = state.get_mut(&sender).filter(|_| deterministic_addresses) {
+        account.nonce = 0;
+    }
+    state
+}
+
+pub(super) fn check_state_format(format: Option<&str>) -> Result<(), ApiError> {
+    match format {
+        None | Some("dump") | Some("genesis") => Ok(()),
+        Some(_) => Err(validate::invalid_field(
+            "stateFormat",
+            "expected dump or genesis",
+        )),
+    }
+}
+
+/// `state` as the response is to return it.
+pub(super) fn encode_state(state: StateDump, format: Option<&str>) -> EncodedState {
+    match format {
+        Some("genesis") => EncodedState::Alloc(to_alloc(&state)),
+        _ => EncodedState::Dump(state),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::Auth;
+    use crate::config::AppConfig;
+    use crate::results::{MemoryStore, Results};
+    use crate::routes::{deploy_route, transact_route};
+    use crate::shutdown::InFlight;
+    use crate::snapshots::Snapshots;
+    use rocket::http::{ContentType, Status};
+    use rocket::local::blocking::Client;
+    use rocket::routes;
+    use serde_json::{json, Value};
+
+    // Stores calldata[4..36] in slot 0 when given an argument, otherwise returns slot 0
+    const CREATION: &str = "0x601a80600b6000396000f3\
+        60243610600e57600435600055005b60005460005260206000f3";
+    const SET_42: &str = "0x60fe47b1\
+        000000000000000000000000000000000000000000000000000000000000002a";
+    const GET: &str = "0x6d4ce63c";
+
+    fn client() -> Client {
+        let config = AppConfig::default();
+        let rocket = rocket::build()
+            .manage(Auth::new(&config))
+            .manage(InFlight::default())
+            .manage(Snapshots::new(&config.limits))
+            .manage(Results::new(MemoryStore::default(), &config.limits))
+            .manage(config)
+            .mount("/", routes![deploy_route, transact_route])
+            .register("/", crate::error::catchers());
+        Client::tracked(rocket).unwrap()
+    }
+
+    fn post(client: &Client, uri: &'static str, body: Value) -> (Status, Value) {
+        let response = client
+            .post(uri)
+            .header(ContentType::JSON)
+            .body(body.to_string())
+            .dispatch();
+        (response.status(), response.into_json().unwrap())
+    }
+
+    #[test]
+    fn test_deploy_then_transact_by_id() {
+        let client = client();
+        let (status, deployed) = post(&client, "/deploy", json!({ "bytecode": CREATION }));
+        assert_eq!(status, Status::Ok);
+        assert_eq!(deployed["reverted"], false);
+        assert_eq!(deployed["success"], true);
+        assert_eq!(deployed["exitReason"], "success");
+        assert_eq!(deployed["traces"][0]["kind"], "create");
+        assert!(deployed.get("rawTraces").is_none());
+        let address = deployed["address"].clone();
+
+        let (status, set) = post(
+            &client,
+            "/transact",
+            json!({ "to": address, "calldata": SET_42, "stateId": deployed["stateId"] }),
+        );
+        assert_eq!(status, Status::Ok);
+        assert_ne!(set["stateId"], deployed["stateId"]);
+
+        let (_, get) = post(
+            &client,
+            "/transact",
+            json!({ "to": address, "calldata": GET, "stateId": set["stateId"] }),
+        );
+        assert_eq!(get["result"], format!("0x{:064x}", 42));
+
+        // The deploy's own snapshot is unchanged
+        let (_, get) = post(
+            &client,
+            "/transact",
+            json!({ "to": address, "calldata": GET, "stateId": deployed["stateId"] }),
+        );
+        assert_eq!(get["result"], format!("0x{:064x}", 0));
+    }
+
+    #[test]
+    fn test_transact_on_supplied_state() {
+        let client = client();
+        let (_, deployed) = post(&client, "/deploy", json!({ "bytecode": CREATION }));
+        let (_, set) = post(
+            &client,
+            "/transact",
+            json!({ "to": deployed["address"], "calldata": SET_42, "state": deployed["state"] }),
+        );
+        assert_eq!(set["reverted"], false);
+
+        let address = deployed["address"].as_str().unwrap().to_lowercase();
+        let storage = set["state"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .find(|(account, _)| account.to_lowercase() == address)
+            .map(|(_, account)| account["storage"].clone())
+            .unwrap();
+        assert_eq!(storage.as_object().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_genesis_state() {
+        let client = client();
+        let (_, deployed) = post(
+            &client,
+            "/deploy",
+            json!({ "bytecode": CREATION, "stateFormat": "genesis" }),
+        );
+        let address = deployed["address"].as_str().unwrap().to_lowercase();
+        let alloc = &deployed["state"];
+        assert_eq!(alloc[&address]["nonce"], "0x1");
+        assert!(alloc[&address]["code"].is_string());
+
+        // An alloc goes back in as it came out
+        let (status, set) = post(
+            &client,
+            "/transact",
+            json!({
+                "to": address,
+                "calldata": SET_42,
+                "state": alloc,
+                "stateFormat": "genesis",
+            }),
+        );
+        assert_eq!(status, Status::Ok);
+        assert_eq!(
+            set["state"][&address]["storage"],
+            json!({
+                "0x0000000000000000000000000000000000000000000000000000000000000000":
+                    format!("0x{:064x}", 42),
+            })
+        );
+
+        let (status, body) = post(
+            &client,
+            "/transact",
+            json!({ "to": address, "stateFormat": "anvil" }),
+        );
+        assert_eq!(status, Status::UnprocessableEntity);
+        assert_eq!(body["details"]["field"], "stateFormat");
+    }
+
+    #[test]
+    fn test_raw_traces_opt_in() {
+        let client = client();
+        let (_, deployed) = post(&client, "/deploy", json!({ "bytecode": CREATION }));
+        let (_, set) = post(
+            &client,
+            "/transact",
+            json!({
+                "to": deployed["address"],
+                "calldata": SET_42,
+                "stateId": deployed["stateId"],
+                "includeRawTraces": true,
+            }),
+        );
+        assert_eq!(set["traces"][0]["kind"], "call");
+        assert_eq!(set["traces"][0]["input"], SET_42);
+        assert!(set["rawTraces"]["arena"].is_array());
+    }
+
+    #[test]
+    fn test_deterministic_responses() {
+        let client = client();
+        let send = |uri: &'static str, body: &Value| {
+            let response = client
+                .post(uri)
+                .header(ContentType::JSON)
+                .body(body.to_string())
+                .dispatch();
+            let result_id = response.headers().get_one("X-Result-Id").map(String::from);
+            (result_id, response.into_bytes().unwrap())
+        };
+
+        let deploy = json!({ "bytecode": CREATION, "persist": true, "deterministic": true });
+        let (result_id, first) = send("/deploy", &deploy);
+        assert_eq!(send("/deploy", &deploy), (result_id.clone(), first.clone()));
+        assert!(result_id.is_some());
+        let deployed: Value = serde_json::from_slice(&first).unwrap();
+
+        let transact = json!({
+            "to": deployed["address"],
+            "calldata": SET_42,
+            "stateId": deployed["stateId"],
+            "deterministic": true,
+        });
+        let (_, first) = send("/transact", &transact);
+        assert_eq!(send("/transact", &transact).1, first);
+        let set: Value = serde_json::from_slice(&first).unwrap();
+        assert_ne!(set["stateId"], deployed["stateId"]);
+
+        // Without it, every state gets an id of its own
+        let (_, deployed) = post(&client, "/deploy", json!({ "bytecode": CREATION }));
+        let (_, again) = post(&client, "/deploy", json!({ "bytecode": CREATION }));
+        assert_ne!(deployed["stateId"], again["stateId"]);
+    }
+
+    #[test]
+    fn test_op_stack_profile() {
+        // Returns what L1Block.basefee() does
+        let reader = "0x00000000000000000000000000000000000000aa";
+        let state = json!({ reader: { "code": "0x635cf2496960e01b6000526020600060046000\
+            734200000000000000000000000000000000000015\
+            5afa5060206000f3" } });
+        let client = client();
+        let (status, read) = post(
+            &client,
+            "/transact",
+            json!({
+                "to": reader,
+                "state": state,
+                "chainProfile": "op-stack",
+                "opStack": { "l1BaseFee": "0x7a69" },
+            }),
+        );
+        assert_eq!(status, Status::Ok);
+        assert_eq!(read["result"], format!("0x{:064x}", 0x7a69));
+
+        // Without the profile nothing at L1Block answers
+        let (_, read) = post(
+            &client,
+            "/transact",
+            json!({ "to": reader, "state": state }),
+        );
+        assert_ne!(read["result"], format!("0x{:064x}", 0x7a69));
+
+        let (status, body) = post(
+            &client,
+            "/transact",
+            json!({ "to": reader, "chainProfile": "arbitrum" }),
+        );
+        assert_eq!(status, Status::UnprocessableEntity);
+        assert_eq!(body["details"]["field"], "chainProfile");
+        let (_, body) = post(
+            &client,
+            "/transact",
+            json!({ "to": reader, "opStack": { "l1BaseFee": "0x1" } }),
+        );
+        assert_eq!(body["details"]["field"], "opStack");
+    }
+
+    #[test]
+    fn test_deterministic_addresses() {
+        // A factory whose constructor CREATEs an empty child
+        let factory = "0x5f5f5ff05000";
+        let client = client();
+        let deploy = |nonce: u64| {
+            let state = json!({ "0x1804c8AB1F12E6bbf3894d4083f33e07309d1f38": { "nonce": nonce } });
+            let (status, deployed) = post(
+                &client,
+                "/deploy",
+                json!({ "bytecode": factory, "state": state, "deterministicAddresses": true }),
+            );
+            assert_eq!(status, Status::Ok);
+            deployed
+        };
+
+        let first = deploy(3);
+        assert_eq!(first["created"], deploy(17)["created"]);
+        let created = first["created"].as_array().unwrap();
+        assert_eq!(created.len(), 2);
+        assert_eq!(created[0]["kind"], "create");
+        assert_eq!(created[0]["nonce"], 0);
+        assert_eq!(created[0]["address"], first["address"]);
+        // A contract's nonce starts at 1
+        assert_eq!(created[1]["deployer"], first["address"]);
+        assert_eq!(created[1]["nonce"], 1);
+
+        // Without it the sender's nonce is left alone, and nothing's reported
+        let (_, deployed) = post(
+            &client,
+            "/deploy",
+            json!({
+                "bytecode": factory,
+                "state": { "0x1804c8AB1F12E6bbf3894d4083f33e07309d1f38": { "nonce": 3 } },
+            }),
+        );
+        assert_ne!(deployed["address"], first["address"]);
+        assert!(deployed.get("created").is_none());
+    }
+
+    #[test]
+    fn test_unknown_state_id() {
+        let (status, body) = post(
+            &client(),
+            "/transact",
+            json!({ "to": "0x0000000000000000000000000000000000000001", "stateId": "nope" }),
+        );
+        assert_eq!(status, Status::NotFound);
+        assert_eq!(body["code"], "SNAPSHOT_NOT_FOUND");
+    }
+}
@@ -0,0 +1,794 @@
+use crate::compile::solidity::SolidityFile;
+use crate::config::Limits;
+use crate::demo;
+use crate::error::{reject, ApiError};
+use crate::fields::RESULT_FIELDS;
+use crate::gas::code::looks_like_creation_code;
+use crate::gas::hardfork::{self, HARDFORKS};
+use crate::gas::NamedStateOverride;
+use crate::legacy;
+use alloy_primitives::{Address, U256};
+use rocket::data::{self, Data, FromData};
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::serde::json::Json;
+use serde::de::{self, DeserializeOwned, Deserializer, Visitor};
+use serde::forward_to_deserialize_any;
+use serde_json::{json, Value};
+use std::ops::{Deref, DerefMut};
+use std::str::FromStr;
+
+// Sizes in bytes are reported as 413, counts as 422
+fn check(
+    status: Status,
+    field: &str,
+    limit: &'static str,
+    max: usize,
+    actual: usize,
+) -> Result<(), ApiError> {
+    if actual <= max {
+        return Ok(());
+    }
+    Err(ApiError::new(
+        status,
+        "LIMIT_EXCEEDED",
+        format!("{} is {}, over the {} of {}", field, actual, limit, max),
+    )
+    .with_details(json!({
+        "limit": limit,
+        "max": max,
+        "actual": actual,
+    })))
+}
+
+pub(super) fn check_bytecode(limits: &Limits, field: &str, len: usize) -> Result<(), ApiError> {
+    check(
+        Status::PayloadTooLarge,
+        field,
+        "maxBytecodeBytes",
+        limits.max_bytecode_bytes,
+        len,
+    )
+}
+
+/// Code to place at an address as it is. Creation code sent by mistake would run as if it were
+/// deployed, every call going through the constructor instead of the contract.
+pub(super) fn check_runtime_code(field: &str, code: &[u8]) -> Result<(), ApiError> {
+    if !looks_like_creation_code(code) {
+        return Ok(());
+    }
+    Err(ApiError::new(
+        Status::UnprocessableEntity,
+        "CREATION_BYTECODE",
+        format!(
+            "{} looks like creation bytecode: send the contract's deployedBytecode, deploy it \
+             with /deploy or /run, or set allowCreationBytecode if it really is runtime code",
+            field
+        ),
+    )
+    .with_details(json!({ "field": field })))
+}
+
+/// Takes the calldata length of each call.
+pub(super) fn check_calls(
+    limits: &Limits,
+    calldata_lens: impl ExactSizeIterator<Item = usize>,
+) -> Result<(), ApiError> {
+    check(
+        Status::UnprocessableEntity,
+        "calls",
+        "maxCalls",
+        limits.max_calls,
+        calldata_lens.len(),
+    )?;
+    check(
+        Status::PayloadTooLarge,
+        "calldata",
+        "maxCalldataBytes",
+        limits.max_calldata_bytes,
+        calldata_lens.sum(),
+    )
+}
+
+/// Each call's `meta`, in call order. Unlike the other sizes this is one field of one call, so
+/// it's a 422 naming the call.
+pub(super) fn check_call_meta<'a>(
+    limits: &Limits,
+    metas: impl Iterator<Item = Option<&'a Value>>,
+) -> Result<(), ApiError> {
+    for (i, meta) in metas.enumerate() {
+        let Some(meta) = meta else { continue };
+        // Only fails for maps with keys that aren't strings, which JSON can't have
+        let len = serde_json::to_vec(meta).map_or(0, |json| json.len());
+        check(
+            Status::UnprocessableEntity,
+            &format!("calls[{}].meta", i),
+            "maxCallMetaBytes",
+            limits.max_call_meta_bytes,
+            len,
+        )?;
+    }
+    Ok(())
+}
+
+/// Takes how many snapshots a session would hold with one more.
+pub(super) fn check_session_snapshots(limits: &Limits, count: usize) -> Result<(), ApiError> {
+    check(
+        Status::UnprocessableEntity,
+        "snapshots",
+        "maxSessionSnapshots",
+        limits.max_session_snapshots,
+        count,
+    )
+}
+
+pub(super) fn check_retries(limits: &Limits, retries: Option<u32>) -> Result<(), ApiError> {
+    check(
+        Status::UnprocessableEntity,
+        "retries",
+        "maxCallRetries",
+        limits.max_call_retries as usize,
+        retries.unwrap_or_default() as usize,
+    )
+}
+
+pub(super) fn check_deadline(limits: &Limits, deadline_ms: Option<u64>) -> Result<(), ApiError> {
+    check(
+        Status::UnprocessableEntity,
+        "deadlineMs",
+        "requestDeadlineMs",
+        limits.request_deadline.as_millis() as usize,
+        deadline_ms.unwrap_or_default() as usize,
+    )
+}
+
+pub(super) fn check_sources(limits: &Limits, files: &[SolidityFile]) -> Result<(), ApiError> {
+    check(
+        Status::UnprocessableEntity,
+        "files",
+        "maxFiles",
+        limits.max_files,
+        files.len(),
+    )?;
+    check(
+        Status::PayloadTooLarge,
+        "sources",
+        "maxSourceBytes",
+        limits.max_source_bytes,
+        files.iter().map(|file| file.content.len()).sum(),
+    )
+}
+
+/// Rules a request body has beyond what its types enforce. They run on the raw JSON, before
+/// deserializing, so a violation can name the exact field and see what the client actually sent
+/// (the case of an address, say).
+pub trait Validate {
+    /// Checks nothing unless a type says what to check.
+    fn check_json(_body: &Value) -> Result<(), ApiError> {
+        Ok(())
+    }
+
+    /// Refuses keys that aren't fields of the body's types, which serde would otherwise drop
+    /// without a word, leaving a misspelt option unapplied. Skipped with `?lenient=true`. Checks
+    /// nothing unless a type says what to check.
+    fn check_known_fields(_body: &Value) -> Result<(), ApiError> {
+        Ok(())
+    }
+
+    /// On a request to an unprefixed route, asks for what `Legacy` needs to turn the response
+    /// back into the shape it had before `/v1`. Asks for nothing unless a type says what.
+    fn for_legacy(_body: &mut Value) {}
+}
+
+/// A JSON body that passed `T::check_json`, and `T::check_known_fields` unless the query has
+/// `lenient=true`. Malformed JSON is refused as `Json` would refuse it.
+///
+/// A MessagePack body (`Content-Type: application/msgpack`) is decoded straight into `T` instead.
+/// Its bytes and addresses are binary, so there's no hex or checksum to check.
+///
+/// For a request let in as a demo request, this is also where the features demo mode doesn't
+/// give away are refused, and a MessagePack body with them.
+pub struct Checked<T>(pub T);
+
+impl<T> Checked<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Checked<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Checked<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, T: DeserializeOwned + Validate> FromData<'r> for Checked<T> {
+    type Error = ApiError;
+
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let demo = demo::gated(req);
+        if req.content_type().is_some_and(|ct| ct.is_msgpack()) {
+            // Gated from the JSON, which a MessagePack body never becomes
+            let decoded = match demo {
+                Some(_) => Err(demo::upgrade(
+                    "msgpack",
+                    "Content-Type",
+                    "A MessagePack body",
+                )),
+                None => from_msgpack(req, data).await,
+            };
+            return match decoded {
+                Ok(value) => data::Outcome::Success(Checked(value)),
+                Err(err) => {
+                    reject(req, err.clone());
+                    data::Outcome::Error((err.status, err))
+                }
+            };
+        }
+        let mut body = match Json::<Value>::from_data(req, data).await {
+            data::Outcome::Success(Json(body)) => body,
+            // Left to the catcher, as for any other JSON body
+            data::Outcome::Error((status, err)) => {
+                return data::Outcome::Error((
+                    status,
+                    ApiError::new(status, "INVALID_REQUEST", err.to_string()),
+                ))
+            }
+            data::Outcome::Forward(forward) => return data::Outcome::Forward(forward),
+        };
+        // A query parameter, so it can't be misspelt away along with what it lets through
+        let lenient = req
+            .query_value::<bool>("lenient")
+            .and_then(Result::ok)
+            .unwrap_or(false);
+        let gate = demo.map_or(Ok(()), |demo| demo::gate(&body, demo));
+        let known = match lenient {
+            true => Ok(()),
+            false => T::check_known_fields(&body),
+        };
+        if legacy::requested(req) {
+            T::for_legacy(&mut body);
+        }
+        let checked = gate
+            .and_then(|()| known)
+            .and_then(|()| T::check_json(&body))
+            .and_then(|()| {
+                serde_json::from_value(body).map_err(|err| {
+                    ApiError::new(
+                        Status::UnprocessableEntity,
+                        "INVALID_REQUEST",
+                        err.to_string(),
+                    )
+                })
+            });
+        match checked {
+            Ok(value) => data::Outcome::Success(Checked(value)),
+            Err(err) => {
+                reject(req, err.clone());
+                data::Outcome::Error((err.status, err))
+            }
+        }
+    }
+}
+
+async fn from_msgpack<T: DeserializeOwned>(
+    req: &Request<'_>,
+    data: Data<'_>,
+) -> Result<T, ApiError> {
+    let limit = req
+        .limits()
+        .get("msgpack")
+        .unwrap_or(data::Limits::MESSAGE_PACK);
+    let body = data
+        .open(limit)
+        .into_bytes()
+        .await
+        .map_err(|err| ApiError::invalid_request(err.to_string()))?;
+    if !body.is_complete() {
+        return Err(ApiError::new(
+            Status::PayloadTooLarge,
+            "PAYLOAD_TOO_LARGE",
+            format!("request body is over the {} limit", limit),
+        ));
+    }
+    rmp_serde::from_slice(&body).map_err(|err| {
+        ApiError::new(
+            Status::UnprocessableEntity,
+            "INVALID_REQUEST",
+            err.to_string(),
+        )
+    })
+}
+
+/// A field that's present but wrong, named by its path in the request.
+pub(super) fn invalid_field(field: &str, message: impl std::fmt::Display) -> ApiError {
+    ApiError::new(
+        Status::UnprocessableEntity,
+        "INVALID_FIELD",
+        format!("{} {}", field, message),
+    )
+    .with_details(json!({ "field": field }))
+}
+
+/// Every key of `body`, when it's an object, a field `T` deserializes, `path` being where in the
+/// request it is. An unknown key is refused with the closest field name as a suggestion.
+pub(super) fn check_known<T: DeserializeOwned>(body: &Value, path: &str) -> Result<(), ApiError> {
+    let Some(object) = body.as_object() else {
+        return Ok(());
+    };
+    let fields = field_names::<T>();
+    // Types without a fixed set of fields, e.g. flattened ones, have nothing to check against
+    if fields.is_empty() {
+        return Ok(());
+    }
+    let Some(key) = object.keys().find(|key| !fields.contains(&key.as_str())) else {
+        return Ok(());
+    };
+    let field = match path {
+        "" => key.clone(),
+        path => format!("{}.{}", path, key),
+    };
+    let suggestion = closest(key, fields);
+    let message = match suggestion {
+        Some(suggestion) => format!("{} isn't a field; did you mean {}?", field, suggestion),
+        None => format!("{} isn't a field", field),
+    };
+    Err(
+        ApiError::new(Status::UnprocessableEntity, "UNKNOWN_FIELD", message)
+            .with_details(json!({ "field": field, "suggestion": suggestion, "valid": fields })),
+    )
+}
+
+// The JSON names `T`'s derived `Deserialize` reads its fields from, aliases included, as it
+// hands them to the deserializer. Empty for anything that isn't deserialized as a struct.
+fn field_names<T: DeserializeOwned>() -> &'static [&'static str] {
+    struct Names<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de> Deserializer<'de> for Names<'_> {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+            Err(de::Error::custom("not a struct"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _: &'static str,
+            fields: &'static [&'static str],
+            _: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(de::Error::custom("only after the field names"))
+        }
+
+        forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+            option unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier
+            ignored_any
+        }
+    }
+
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(Names(&mut fields));
+    fields
+}
+
+// The field `key` most likely meant, ignoring case, underscores and dashes, if any is close
+fn closest(key: &str, fields: &[&'static str]) -> Option<&'static str> {
+    let normalize = |name: &str| -> Vec<char> {
+        name.chars()
+            .filter(|c| !matches!(c, '_' | '-'))
+            .flat_map(char::to_lowercase)
+            .collect()
+    };
+    let key = normalize(key);
+    fields
+        .iter()
+        .map(|field| (edit_distance(&key, &normalize(field)), *field))
+        .filter(|(distance, _)| *distance <= (key.len() / 3).max(2))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, field)| field)
+}
+
+// Levenshtein: the fewest insertions, deletions and substitutions that turn `a` into `b`
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, x) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, y) in b.iter().enumerate() {
+            let substituted = diagonal + usize::from(x != y);
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// 0x-prefixed, even-length hex. Returns the decoded length.
+pub(super) fn check_hex(value: &Value, field: &str, allow_empty: bool) -> Result<usize, ApiError> {
+    let s = value
+        .as_str()
+        .ok_or_else(|| invalid_field(field, "must be a hex string"))?;
+    let digits = s
+        .strip_prefix("0x")
+        .ok_or_else(|| invalid_field(field, "must start with 0x"))?;
+    if digits.len() % 2 != 0 {
+        return Err(invalid_field(field, "has an odd number of hex digits"));
+    }
+    if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(invalid_field(field, "isn't valid hex"));
+    }
+    if digits.is_empty() && !allow_empty {
+        return Err(invalid_field(field, "is empty"));
+    }
+    Ok(digits.len() / 2)
+}
+
+/// A 0x-prefixed address. Mixed-case addresses must carry a valid EIP-55 checksum unless
+/// `skip_checksum` is set; all-lowercase and all-uppercase ones carry none.
+pub(super) fn check_address(
+    value: &Value,
+    field: &str,
+    skip_checksum: bool,
+) -> Result<(), ApiError> {
+    let s = value
+        .as_str()
+        .ok_or_else(|| invalid_field(field, "must be an address string"))?;
+    let digits = s
+        .strip_prefix("0x")
+        .ok_or_else(|| invalid_field(field, "must start with 0x"))?;
+    Address::from_str(s).map_err(|_| invalid_field(field, "isn't a 20-byte hex address"))?;
+
+    let has_lower = digits.bytes().any(|b| b.is_ascii_lowercase());
+    let has_upper = digits.bytes().any(|b| b.is_ascii_uppercase());
+    if has_lower && has_upper && !skip_checksum && Address::parse_checksummed(s, None).is_err() {
+        return Err(invalid_field(
+            field,
+            "fails its EIP-55 checksum; send it lowercase or set skipChecksum",
+        ));
+    }
+    Ok(())
+}
+
+/// A decimal or 0x-hex string, or a non-negative integer, that fits in 256 bits.
+pub(super) fn check_u256(value: &Value, field: &str) -> Result<(), ApiError> {
+    let fits = match value {
+        Value::String(s) => U256::from_str(s).is_ok(),
+        Value::Number(n) => n.is_u64(),
+        _ => false,
+    };
+    if fits {
+        Ok(())
+    } else {
+        Err(invalid_field(field, "isn't a uint256"))
+    }
+}
+
+/// Calldata is either empty or starts with a 4-byte selector.
+pub(super) fn check_calldata(value: &Value, field: &str) -> Result<(), ApiError> {
+    match check_hex(value, field, true)? {
+        1..=3 => Err(invalid_field(
+            field,
+            "is too short to hold a function selector",
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// State overrides that say what to do with each account's storage at most one way, if given.
+pub(super) fn check_state_overrides(
+    overrides: Option<&NamedStateOverride>,
+) -> Result<(), ApiError> {
+    // geth refuses these too: there's no saying whether the diff applies before or after
+    match overrides
+        .into_iter()
+        .flatten()
+        .find(|(_, account)| account.state.is_some() && account.state_diff.is_some())
+    {
+        Some((address, _)) => Err(invalid_field(
+            &format!("stateOverrides.{}", address),
+            "can't set both state and stateDiff",
+        )),
+        None => Ok(()),
+    }
+}
+
+/// A hardfork `hardfork` knows, if given.
+pub(super) fn check_hardfork(hardfork: Option<&str>) -> Result<(), ApiError> {
+    match hardfork {
+        Some(name) if hardfork::spec(name).is_none() => {
+            let valid: Vec<_> = HARDFORKS.iter().map(|(name, _)| *name).collect();
+            Err(invalid_field(
+                "hardfork",
+                format!(
+                    "{} isn't a known hardfork; expected one of {}",
+                    name,
+                    valid.join(", ")
+                ),
+            )
+            .with_details(json!({ "field": "hardfork", "valid": valid })))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Names of `ExecutionResult` fields to return, if given.
+pub(super) fn check_fields(value: &Value, field: &str) -> Result<(), ApiError> {
+    if value.is_null() {
+        return Ok(());
+    }
+    let names = value
+        .as_array()
+        .ok_or_else(|| invalid_field(field, "must be an array of field names"))?;
+    for (i, name) in names.iter().enumerate() {
+        let path = format!("{}[{}]", field, i);
+        let name = name
+            .as_str()
+            .ok_or_else(|| invalid_field(&path, "must be a field name"))?;
+        if !RESULT_FIELDS.contains(&name) {
+            return Err(invalid_field(
+                &path,
+                format!(
+                    "{} isn't an ExecutionResult field; expected one of {}",
+                    name,
+                    RESULT_FIELDS.join(", ")
+                ),
+            )
+            .with_details(json!({ "field": path, "valid": RESULT_FIELDS })));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> Limits {
+        Limits {
+            max_bytecode_bytes: 4,
+            max_calls: 2,
+            max_calldata_bytes: 8,
+            max_call_meta_bytes: 16,
+            max_files: 2,
+            max_source_bytes: 10,
+            ..Limits::default()
+        }
+    }
+
+    fn file(content: &str) -> SolidityFile {
+        SolidityFile {
+            name: "A.sol".to_string(),
+            content: content.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_bytecode_limit() {
+        assert!(check_bytecode(&limits(), "bytecode", 4).is_ok());
+        let err = check_bytecode(&limits(), "bytecode", 5).unwrap_err();
+        assert_eq!(err.status, Status::PayloadTooLarge);
+        assert_eq!(err.code, "LIMIT_EXCEEDED");
+        assert_eq!(
+            err.details,
+            Some(json!({ "limit": "maxBytecodeBytes", "max": 4, "actual": 5 }))
+        );
+    }
+
+    #[test]
+    fn test_call_limits() {
+        assert!(check_calls(&limits(), [4, 4].into_iter()).is_ok());
+
+        let err = check_calls(&limits(), [0, 0, 0].into_iter()).unwrap_err();
+        assert_eq!(err.status, Status::UnprocessableEntity);
+        assert_eq!(err.details.unwrap()["limit"], "maxCalls");
+
+        let err = check_calls(&limits(), [4, 5].into_iter()).unwrap_err();
+        assert_eq!(err.status, Status::PayloadTooLarge);
+        assert_eq!(err.details.unwrap()["limit"], "maxCalldataBytes");
+    }
+
+    #[test]
+    fn test_call_meta_limit() {
+        let small = json!({ "id": 1 });
+        let large = json!({ "id": "0123456789abcdef" });
+        assert!(check_call_meta(&limits(), [None, Some(&small)].into_iter()).is_ok());
+
+        let err = check_call_meta(&limits(), [Some(&small), Some(&large)].into_iter()).unwrap_err();
+        assert_eq!(err.status, Status::UnprocessableEntity);
+        let details = err.details.unwrap();
+        assert_eq!(details["limit"], "maxCallMetaBytes");
+        assert_eq!(details["actual"], large.to_string().len());
+        assert!(err.message.starts_with("calls[1].meta"), "{}", err.message);
+    }
+
+    #[test]
+    fn test_source_limits() {
+        assert!(check_sources(&limits(), &[file("12345"), file("12345")]).is_ok());
+
+        let err = check_sources(&limits(), &[file(""), file(""), file("")]).unwrap_err();
+        assert_eq!(err.details.unwrap()["limit"], "maxFiles");
+
+        let err = check_sources(&limits(), &[file("12345"), file("123456")]).unwrap_err();
+        assert_eq!(err.status, Status::PayloadTooLarge);
+        assert_eq!(err.details.unwrap()["limit"], "maxSourceBytes");
+    }
+
+    fn field(err: ApiError) -> Value {
+        assert_eq!(err.status, Status::UnprocessableEntity);
+        assert_eq!(err.code, "INVALID_FIELD");
+        err.details.unwrap()["field"].clone()
+    }
+
+    // The EIP-55 example address
+    const CHECKSUMMED: &str = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(
+            check_hex(&json!("0x6d4ce63c"), "bytecode", false).unwrap(),
+            4
+        );
+        assert_eq!(check_hex(&json!("0xABcd"), "bytecode", false).unwrap(), 2);
+        assert_eq!(check_hex(&json!("0x"), "calldata", true).unwrap(), 0);
+
+        for bad in [
+            json!("6d4ce63c"),
+            json!("0x6d4"),
+            json!("0xzz"),
+            json!("0x"),
+            json!(1),
+        ] {
+            let err = check_hex(&bad, "bytecode", false).unwrap_err();
+            assert_eq!(field(err), "bytecode", "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_address() {
+        let lower = CHECKSUMMED.to_lowercase();
+        let upper = format!("0x{}", CHECKSUMMED[2..].to_uppercase());
+        for ok in [CHECKSUMMED, lower.as_str(), upper.as_str()] {
+            assert!(
+                check_address(&json!(ok), "address", false).is_ok(),
+                "{}",
+                ok
+            );
+        }
+
+        let wrong_case = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD";
+        let err = check_address(&json!(wrong_case), "calls[2].caller", false).unwrap_err();
+        assert!(err.message.contains("EIP-55"));
+        assert_eq!(field(err), "calls[2].caller");
+        assert!(check_address(&json!(wrong_case), "address", true).is_ok());
+
+        for bad in [
+            json!("5aaeb6053f3e94c9b9a09f33669435e7ef1beaed"),
+            json!("0x5aaeb6053f3e94c9b9a09f33669435e7ef1bea"),
+            json!("0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaezz"),
+            json!(null),
+        ] {
+            // No escape hatch for these
+            let err = check_address(&bad, "address", true).unwrap_err();
+            assert_eq!(field(err), "address", "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_u256() {
+        for ok in [
+            json!("0"),
+            json!("0xff"),
+            json!(12),
+            json!(U256::MAX.to_string()),
+        ] {
+            assert!(check_u256(&ok, "value").is_ok(), "{}", ok);
+        }
+        let too_big = format!("{}0", U256::MAX);
+        for bad in [
+            json!(too_big),
+            json!(-1),
+            json!(1.5),
+            json!("ten"),
+            json!(null),
+        ] {
+            assert_eq!(
+                field(check_u256(&bad, "value").unwrap_err()),
+                "value",
+                "{}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn test_calldata() {
+        assert!(check_calldata(&json!("0x"), "calls[0].calldata").is_ok());
+        assert!(check_calldata(&json!("0x6d4ce63c"), "calls[0].calldata").is_ok());
+        let err = check_calldata(&json!("0x6d4ce6"), "calls[0].calldata").unwrap_err();
+        assert!(err.message.contains("selector"));
+        assert_eq!(field(err), "calls[0].calldata");
+    }
+
+    #[test]
+    fn test_hardfork() {
+        assert!(check_hardfork(None).is_ok());
+        assert!(check_hardfork(Some("istanbul")).is_ok());
+        let err = check_hardfork(Some("Istanbul")).unwrap_err();
+        assert!(
+            err.message.contains("frontier, homestead"),
+            "{}",
+            err.message
+        );
+        assert_eq!(err.details.as_ref().unwrap()["valid"][13], "prague");
+        assert_eq!(field(err), "hardfork");
+    }
+
+    #[test]
+    fn test_fields() {
+        assert!(check_fields(&json!(null), "fields").is_ok());
+        assert!(check_fields(&json!(["gasUsed", "revertReason"]), "fields").is_ok());
+
+        let err = check_fields(&json!(["gasUsed", "gas"]), "fields").unwrap_err();
+        assert!(err.message.contains("revertReason, gasUsed"));
+        assert_eq!(err.details.as_ref().unwrap()["valid"][5], "gasUsed");
+        assert_eq!(field(err), "fields[1]");
+        assert_eq!(
+            field(check_fields(&json!("gasUsed"), "fields").unwrap_err()),
+            "fields"
+        );
+    }
+
+    #[test]
+    fn test_unknown_field_suggestions() {
+        use crate::gas::ForkConfig;
+        use crate::routes::execute_calldatas_fork::ExecuteCalldatasRequest;
+
+        let suggestion = |err: ApiError| err.details.unwrap()["suggestion"].clone();
+        let err = check_known::<ForkConfig>(&json!({ "blockNumer": 1 }), "forkConfig").unwrap_err();
+        assert_eq!(err.status, Status::UnprocessableEntity);
+        assert_eq!(err.code, "UNKNOWN_FIELD");
+        assert_eq!(
+            err.message,
+            "forkConfig.blockNumer isn't a field; did you mean blockNumber?"
+        );
+        assert_eq!(
+            err.details.as_ref().unwrap()["field"],
+            "forkConfig.blockNumer"
+        );
+        assert_eq!(suggestion(err), "blockNumber");
+
+        // Case, underscores and dashes don't count against a name
+        for (typo, meant) in [
+            ("forkconfig", "forkConfig"),
+            ("trace_Mode", "traceMode"),
+            ("include-raw-traces", "includeRawTraces"),
+            ("stateDif", "stateDiff"),
+            ("defaultCalller", "defaultCaller"),
+        ] {
+            let err =
+                check_known::<ExecuteCalldatasRequest>(&json!({ typo: true }), "").unwrap_err();
+            assert_eq!(err.details.as_ref().unwrap()["field"], typo);
+            assert_eq!(suggestion(err), meant, "{}", typo);
+        }
+
+        // Nothing close enough to suggest
+        let err = check_known::<ForkConfig>(&json!({ "gasPrice": 1 }), "forkConfig").unwrap_err();
+        assert_eq!(err.message, "forkConfig.gasPrice isn't a field");
+        assert_eq!(suggestion(err), Value::Null);
+
+        // Only keys that aren't fields are refused, and only objects have keys
+        assert!(check_known::<ForkConfig>(&json!({ "chainId": 1, "rpcUrl": "x" }), "").is_ok());
+        assert!(check_known::<ForkConfig>(&json!(null), "forkConfig").is_ok());
+    }
+}
@@ -0,0 +1,169 @@
+use crate::auth::ApiKey;
+use crate::compile::cache::CompileCache;
+use crate::compile::sourcify::{find_metadata, payload, submit, SourcifyMatch, SourcifyPayload};
+use crate::config::AppConfig;
+use crate::error::ApiError;
+use alloy_primitives::Address;
+use foundry_compilers::contracts::VersionedContracts;
+use rocket::{http::Status, post, serde::json::Json, State};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyRequest {
+    /// The `ETag` of the `/compile_solidity` response the contract came from, quotes or not.
+    /// The compile has to still be cached.
+    pub compile_id: String,
+    pub contract_name: String,
+    pub chain_id: u64,
+    #[schema(value_type = String)]
+    pub address: Address,
+    /// Return what would be submitted without submitting it
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyResponse {
+    /// Sourcify's match. Missing for a dry run.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<SourcifyMatch>,
+    /// Only for a dry run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload: Option<SourcifyPayload>,
+}
+
+// Just what's needed of a cached compile response
+#[derive(Deserialize)]
+struct Compiled {
+    contracts: VersionedContracts,
+}
+
+/// Submits a compiled contract's metadata and sources to Sourcify to verify it at `address`.
+#[utoipa::path(
+    post,
+    path = "/verify/sourcify",
+    tag = "tools",
+    request_body = VerifyRequest,
+    responses(
+        (status = 200, description = "How Sourcify matched the contract, or the payload for a dry run", body = VerifyResponse),
+        (status = 404, description = "The compile isn't cached (anymore)", body = ApiError),
+        (status = 503, description = "No Sourcify server is configured", body = ApiError),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("apiKey" = []))
+)]
+#[post("/verify/sourcify", format = "json", data = "<req>")]
+pub async fn verify_sourcify_route(
+    _key: ApiKey,
+    config: &State<AppConfig>,
+    cache: &State<CompileCache>,
+    req: Json<VerifyRequest>,
+) -> Result<Json<VerifyResponse>, ApiError> {
+    verify_sourcify(config, cache, req.into_inner())
+        .await
+        .map(Json)
+}
+
+pub async fn verify_sourcify(
+    config: &AppConfig,
+    cache: &CompileCache,
+    req: VerifyRequest,
+) -> Result<VerifyResponse, ApiError> {
+    let url = match (&config.sourcify_url, req.dry_run) {
+        (Some(url), false) => Some(url),
+        (None, false) => {
+            return Err(ApiError::new(
+                Status::ServiceUnavailable,
+                "SOURCIFY_DISABLED",
+                "No Sourcify server is configured; only dryRun is available",
+            ))
+        }
+        (_, true) => None,
+    };
+
+    let (body, sources) = cache
+        .compiled(req.compile_id.trim_matches('"'))
+        .ok_or_else(|| {
+            ApiError::new(
+                Status::NotFound,
+                "COMPILE_NOT_FOUND",
+                format!("No cached compile {}; compile again", req.compile_id),
+            )
+        })?;
+    let compiled: Compiled = serde_json::from_slice(&body).map_err(|err| {
+        ApiError::new(
+            Status::InternalServerError,
+            "COMPILE_UNREADABLE",
+            err.to_string(),
+        )
+    })?;
+    let metadata = find_metadata(&compiled.contracts, &req.contract_name).ok_or_else(|| {
+        ApiError::new(
+            Status::UnprocessableEntity,
+            "CONTRACT_NOT_FOUND",
+            format!(
+                "Contract {} has no metadata in that compile",
+                req.contract_name
+            ),
+        )
+    })?;
+    let payload = payload(metadata, &sources, req.chain_id, req.address).map_err(|err| {
+        ApiError::new(
+            Status::UnprocessableEntity,
+            "INVALID_METADATA",
+            err.to_string(),
+        )
+    })?;
+
+    let Some(url) = url else {
+        return Ok(VerifyResponse {
+            result: None,
+            payload: Some(payload),
+        });
+    };
+    let result = submit(url, &payload)
+        .await
+        .map_err(|err| ApiError::new(Status::BadGateway, "SOURCIFY_FAILED", err.to_string()))?;
+    Ok(VerifyResponse {
+        result: Some(result),
+        payload: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::address;
+
+    fn request(dry_run: bool) -> VerifyRequest {
+        VerifyRequest {
+            compile_id: "\"abc\"".to_string(),
+            contract_name: "SimpleStorage".to_string(),
+            chain_id: 8453,
+            address: address!("b2f9974c62815d3177079e150377915d9bc49c82"),
+            dry_run,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_submitting_needs_a_server() {
+        let config = AppConfig::default();
+        let cache = CompileCache::new(&config.limits);
+
+        let err = verify_sourcify(&config, &cache, request(false))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.code, "SOURCIFY_DISABLED");
+
+        // A dry run gets as far as looking up the compile
+        let err = verify_sourcify(&config, &cache, request(true))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.code, "COMPILE_NOT_FOUND");
+    }
+}
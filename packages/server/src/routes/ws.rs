@@ -0,0 +1,536 @@
+use crate::admission::Gates;
+use crate::auth::{ApiKey, Auth, RouteClass};
+use crate::compile::solidity::{compile, SolidityFile};
+use crate::config::{AppConfig, Limits};
+use crate::demo;
+use crate::error::ApiError;
+use crate::gas::{ExecutionResult, DEFAULT_DEPLOYER};
+use crate::sessions::{Session, Sessions};
+use crate::shutdown::InFlight;
+use crate::telemetry::RequestId;
+use alloy_primitives::{Address, Bytes, U256};
+use foundry_compilers::compilers::CompilationError;
+use rocket::futures::{SinkExt, StreamExt};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::{get, State};
+use rocket_ws::{Channel, Message, WebSocket};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::convert::Infallible;
+use std::iter;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, PoisonError};
+use tracing::{debug, Instrument};
+
+use super::run::{creation_code, target_contract};
+use super::validate;
+
+/// One message from the client. Every message also carries an `id`, echoed on its reply.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum Command {
+    /// Replaces the session's compilation when the sources compile
+    Compile {
+        files: Vec<SolidityFile>,
+    },
+    /// A contract from the last compilation by name, or as `File.sol:Name`, or raw creation code
+    #[serde(rename_all = "camelCase")]
+    Deploy {
+        contract_name: Option<String>,
+        constructor_args: Option<Vec<String>>,
+        bytecode: Option<Bytes>,
+        value: Option<U256>,
+        caller: Option<Address>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Call {
+        to: Address,
+        #[serde(default)]
+        calldata: Bytes,
+        value: Option<U256>,
+        caller: Option<Address>,
+    },
+    Snapshot,
+    /// Puts the chain back as it was at a snapshot. The snapshot stays usable.
+    #[serde(rename_all = "camelCase")]
+    Revert {
+        snapshot_id: usize,
+    },
+}
+
+impl Command {
+    // Cheap commands aren't charged
+    fn class(&self) -> Option<RouteClass> {
+        match self {
+            Command::Compile { .. } => Some(RouteClass::Compile),
+            Command::Deploy { .. } | Command::Call { .. } => Some(RouteClass::Execute),
+            Command::Snapshot | Command::Revert { .. } => None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Deployed {
+    address: Option<Address>,
+    #[serde(flatten)]
+    result: ExecutionResult,
+}
+
+/// Who every message is charged against: the raw `X-API-Key`, or for a demo request the
+/// client's IP, so a socket draws on the same per-IP buckets as the demo's HTTP requests.
+pub struct Caller<'r> {
+    key: Option<&'r str>,
+    demo_ip: Option<IpAddr>,
+}
+
+impl Caller<'_> {
+    fn authorize(&self, auth: &Auth, class: RouteClass) -> Result<(), ApiError> {
+        match self.demo_ip {
+            Some(ip) => auth.authorize_demo(ip, class),
+            None => auth.authorize(self.key, class),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Caller<'r> {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let key = req.headers().get_one("X-API-Key");
+        let demo_ip = match req.guard::<&State<Auth>>().await {
+            Outcome::Success(auth) => auth.demo(key).map(|demo| demo::client_ip(req, demo)),
+            _ => None,
+        };
+        Outcome::Success(Caller { key, demo_ip })
+    }
+}
+
+/// An interactive session over a WebSocket. The server first sends
+/// `{"type": "session", "sessionId": ...}`; the client then sends commands such as
+/// `{"id": 1, "type": "call", "to": ..., "calldata": ...}` and gets `{"id": 1, "result": ...}`
+/// or `{"id": 1, "error": ...}` back for each, in order.
+#[utoipa::path(
+    get,
+    path = "/ws",
+    tag = "execute",
+    params(
+        ("session" = Option<String>, Query, description = "Resume a session whose connection dropped within the grace period"),
+    ),
+    responses(
+        (status = 101, description = "Switched to the WebSocket protocol. Commands are `compile`, `deploy`, `call`, `snapshot` and `revert`."),
+        (status = "default", description = "Failure", body = ApiError),
+    ),
+    security((), ("apiKey" = []))
+)]
+#[allow(clippy::too_many_arguments)]
+#[get("/ws?<session>")]
+pub fn ws_route<'r>(
+    _key: ApiKey,
+    caller: Caller<'r>,
+    id: RequestId,
+    config: &'r State<AppConfig>,
+    auth: &'r State<Auth>,
+    gates: &'r State<Gates>,
+    sessions: &'r State<Sessions>,
+    in_flight: &'r State<InFlight>,
+    session: Option<&str>,
+    ws: WebSocket,
+) -> Result<Channel<'r>, ApiError> {
+    let (session_id, session) = sessions.attach(session)?;
+    let span = id.span();
+
+    Ok(ws.channel(move |mut stream| {
+        Box::pin(
+            async move {
+                debug!(session = %session_id, "session attached");
+                let hello = json!({ "type": "session", "sessionId": session_id });
+                let mut outcome = stream.send(Message::Text(hello.to_string())).await;
+
+                while outcome.is_ok() {
+                    let text = match stream.next().await {
+                        Some(Ok(Message::Text(text))) => text,
+                        Some(Ok(Message::Close(_))) | None => break,
+                        Some(Ok(_)) => continue,
+                        Some(Err(err)) => {
+                            outcome = Err(err);
+                            break;
+                        }
+                    };
+                    let reply = reply(
+                        &text,
+                        &caller,
+                        auth,
+                        gates,
+                        &config.limits,
+                        in_flight,
+                        &session,
+                    )
+                    .await;
+                    outcome = stream.send(Message::Text(reply.to_string())).await;
+                }
+
+                sessions.detach(&session_id);
+                debug!(session = %session_id, "session detached");
+                outcome
+            }
+            .instrument(span),
+        )
+    }))
+}
+
+async fn reply(
+    text: &str,
+    caller: &Caller<'_>,
+    auth: &Auth,
+    gates: &Gates,
+    limits: &Limits,
+    in_flight: &InFlight,
+    session: &Arc<Mutex<Session>>,
+) -> Value {
+    let message: Value = match serde_json::from_str(text) {
+        Ok(message) => message,
+        Err(err) => {
+            return json!({ "id": null, "error": ApiError::invalid_request(err.to_string()) })
+        }
+    };
+    let id = message.get("id").cloned().unwrap_or(Value::Null);
+
+    let result = async {
+        let command: Command = serde_json::from_value(message)
+            .map_err(|err| ApiError::invalid_request(err.to_string()))?;
+        if let Some(class) = command.class() {
+            caller.authorize(auth, class)?;
+        }
+        // A compile holds a slot in the same gate as /compile_solidity
+        let _slot = match command {
+            Command::Compile { .. } => Some(gates.compile.enter().await?),
+            _ => None,
+        };
+        let _work = in_flight.begin()?;
+
+        // Compiling and executing block, and the session stays locked while they run
+        let limits = limits.clone();
+        let session = session.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut session = session.lock().unwrap_or_else(PoisonError::into_inner);
+            run(&mut session, command, &limits)
+        })
+        .await
+        .map_err(|err| {
+            ApiError::new(
+                Status::InternalServerError,
+                "EXECUTION_PANIC",
+                err.to_string(),
+            )
+        })?
+    }
+    .await;
+
+    match result {
+        Ok(result) => json!({ "id": id, "result": result }),
+        Err(err) => json!({ "id": id, "error": err }),
+    }
+}
+
+fn run(session: &mut Session, command: Command, limits: &Limits) -> Result<Value, ApiError> {
+    match command {
+        Command::Compile { files } => {
+            validate::check_sources(limits, &files)?;
+            let compilation = compile(&files, limits).map_err(ApiError::compile_failed)?;
+            let output = json!(compilation);
+            if !compilation.has_errors() {
+                session.compilation = Some(compilation);
+            }
+            Ok(output)
+        }
+        Command::Deploy {
+            contract_name,
+            constructor_args,
+            bytecode,
+            value,
+            caller,
+        } => {
+            let creation_code = match (contract_name, bytecode) {
+                (Some(name), None) => {
+                    let compilation = session.compilation.as_ref().ok_or_else(|| {
+                        ApiError::invalid_request("nothing compiled yet, send compile first")
+                    })?;
+                    creation_code(
+                        &target_contract(compilation, Some(&name))?,
+                        constructor_args.as_deref().unwrap_or_default(),
+                    )?
+                }
+                (None, Some(bytecode)) if constructor_args.is_none() => bytecode.to_vec(),
+                (None, Some(_)) => return Err(ApiError::invalid_request(
+                    "constructorArgs need contractName; append encoded args to bytecode instead",
+                )),
+                _ => {
+                    return Err(ApiError::invalid_request(
+                        "pass either contractName or bytecode",
+                    ))
+                }
+            };
+            validate::check_bytecode(limits, "bytecode", creation_code.len())?;
+
+            let (address, result) = session
+                .chain
+                .deploy(
+                    creation_code.into(),
+                    value.unwrap_or_default(),
+                    caller.unwrap_or(DEFAULT_DEPLOYER),
+                )
+                .map_err(ApiError::from_execution)?;
+            Ok(json!(Deployed { address, result }))
+        }
+        Command::Call {
+            to,
+            calldata,
+            value,
+            caller,
+        } => {
+            validate::check_calls(limits, iter::once(calldata.len()))?;
+            let result = session
+                .chain
+                .transact(
+                    to,
+                    calldata,
+                    value.unwrap_or_default(),
+                    caller.unwrap_or(DEFAULT_DEPLOYER),
+                )
+                .map_err(ApiError::from_execution)?;
+            Ok(json!(result))
+        }
+        Command::Snapshot => {
+            validate::check_session_snapshots(limits, session.snapshots.len() + 1)?;
+            session.snapshots.push(session.chain.dump());
+            Ok(json!({ "snapshotId": session.snapshots.len() - 1 }))
+        }
+        Command::Revert { snapshot_id } => {
+            let state = session.snapshots.get(snapshot_id).ok_or_else(|| {
+                ApiError::new(
+                    Status::NotFound,
+                    "SNAPSHOT_NOT_FOUND",
+                    format!("no snapshot {} in this session", snapshot_id),
+                )
+            })?;
+            session
+                .chain
+                .restore(state)
+                .map_err(ApiError::from_execution)?;
+            Ok(json!({ "snapshotId": snapshot_id }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::Auth;
+    use crate::config::{AppConfig, DemoConfig, RateLimit};
+    use crate::routes::ws_route;
+    use crate::sessions::Sessions;
+    use crate::shutdown::InFlight;
+    use alloy_primitives::Address;
+    use rocket::config::LogLevel;
+    use rocket::fairing::AdHoc;
+    use rocket::futures::{SinkExt, StreamExt};
+    use rocket::routes;
+    use serde_json::{json, Value};
+    use tokio::net::TcpStream;
+    use tokio::sync::oneshot;
+    use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+    type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    const COUNTER: &str = r#"
+        pragma solidity ^0.8.0;
+
+        contract Counter {
+            uint256 public count;
+
+            constructor(uint256 initial) {
+                count = initial;
+            }
+
+            function set(uint256 x) public {
+                count = x;
+            }
+
+            function get() public view returns (uint256) {
+                return count;
+            }
+        }
+    "#;
+    const SET_42: &str = "0x60fe47b1\
+        000000000000000000000000000000000000000000000000000000000000002a";
+    const GET: &str = "0x6d4ce63c";
+
+    // Launches the route on a free port and returns the port
+    async fn serve(config: AppConfig) -> u16 {
+        let (port_tx, port_rx) = oneshot::channel();
+        let rocket = rocket::custom(rocket::Config {
+            port: 0,
+            log_level: LogLevel::Off,
+            ..rocket::Config::debug_default()
+        })
+        .manage(Auth::new(&config))
+        .manage(Gates::new(&config.limits))
+        .manage(InFlight::default())
+        .manage(Sessions::new(&config.limits))
+        .manage(config)
+        .mount("/", routes![ws_route])
+        .register("/", crate::error::catchers())
+        .attach(AdHoc::on_liftoff("Report port", move |rocket| {
+            Box::pin(async move {
+                let _ = port_tx.send(rocket.config().port);
+            })
+        }));
+        tokio::spawn(rocket.launch());
+        port_rx.await.unwrap()
+    }
+
+    async fn receive(socket: &mut Socket) -> Value {
+        let message = socket.next().await.unwrap().unwrap();
+        serde_json::from_str(&message.into_text().unwrap()).unwrap()
+    }
+
+    async fn request(socket: &mut Socket, message: Value) -> Value {
+        let id = message["id"].clone();
+        socket
+            .send(Message::Text(message.to_string()))
+            .await
+            .unwrap();
+        let reply = receive(socket).await;
+        assert_eq!(reply["id"], id);
+        reply
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_compile_deploy_call_revert() {
+        let port = serve(AppConfig::default()).await;
+        let (mut socket, _) = connect_async(format!("ws://127.0.0.1:{}/ws", port))
+            .await
+            .unwrap();
+        let hello = receive(&mut socket).await;
+        assert_eq!(hello["type"], "session");
+
+        let compiled = request(
+            &mut socket,
+            json!({
+                "id": 1,
+                "type": "compile",
+                "files": [{ "name": "Counter.sol", "content": COUNTER }],
+            }),
+        )
+        .await;
+        assert!(compiled["result"]["contracts"].is_object());
+
+        let deployed = request(
+            &mut socket,
+            json!({ "id": 2, "type": "deploy", "contractName": "Counter", "constructorArgs": ["7"] }),
+        )
+        .await;
+        assert_eq!(deployed["result"]["success"], true);
+        let address = deployed["result"]["address"].clone();
+
+        let snapshot = request(&mut socket, json!({ "id": 3, "type": "snapshot" })).await;
+        let snapshot_id = snapshot["result"]["snapshotId"].clone();
+
+        request(
+            &mut socket,
+            json!({ "id": 4, "type": "call", "to": address, "calldata": SET_42 }),
+        )
+        .await;
+        let get = request(
+            &mut socket,
+            json!({ "id": 5, "type": "call", "to": address, "calldata": GET }),
+        )
+        .await;
+        assert_eq!(get["result"]["result"], format!("0x{:064x}", 42));
+
+        request(
+            &mut socket,
+            json!({ "id": 6, "type": "revert", "snapshotId": snapshot_id }),
+        )
+        .await;
+        let get = request(
+            &mut socket,
+            json!({ "id": 7, "type": "call", "to": address, "calldata": GET }),
+        )
+        .await;
+        assert_eq!(get["result"]["result"], format!("0x{:064x}", 7));
+
+        let missing = request(
+            &mut socket,
+            json!({ "id": "eight", "type": "revert", "snapshotId": 99 }),
+        )
+        .await;
+        assert_eq!(missing["error"]["code"], "SNAPSHOT_NOT_FOUND");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_resume_unknown_session() {
+        let port = serve(AppConfig::default()).await;
+        let err = connect_async(format!("ws://127.0.0.1:{}/ws?session=nope", port))
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("404"), "{}", err);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_demo_messages_use_ip_buckets() {
+        let port = serve(AppConfig {
+            api_keys: ["secret".to_string()].into(),
+            demo: Some(DemoConfig {
+                execute_rate: RateLimit {
+                    burst: 1,
+                    per_minute: 1,
+                },
+                ..DemoConfig::default()
+            }),
+            ..AppConfig::default()
+        })
+        .await;
+        let (mut socket, _) = connect_async(format!("ws://127.0.0.1:{}/ws", port))
+            .await
+            .unwrap();
+        receive(&mut socket).await;
+
+        let call = |id: u32| json!({ "id": id, "type": "call", "to": Address::ZERO });
+        assert_eq!(
+            request(&mut socket, call(1)).await["result"]["success"],
+            true
+        );
+        let limited = request(&mut socket, call(2)).await;
+        assert_eq!(limited["error"]["code"], "RATE_LIMITED");
+        // Snapshots aren't charged
+        let snapshot = request(&mut socket, json!({ "id": 3, "type": "snapshot" })).await;
+        assert_eq!(snapshot["result"]["snapshotId"], 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_snapshot_limit() {
+        let mut config = AppConfig::default();
+        config.limits.max_session_snapshots = 1;
+        let port = serve(config).await;
+        let (mut socket, _) = connect_async(format!("ws://127.0.0.1:{}/ws", port))
+            .await
+            .unwrap();
+        receive(&mut socket).await;
+
+        let snapshot = request(&mut socket, json!({ "id": 1, "type": "snapshot" })).await;
+        assert_eq!(snapshot["result"]["snapshotId"], 0);
+        let refused = request(&mut socket, json!({ "id": 2, "type": "snapshot" })).await;
+        assert_eq!(refused["error"]["code"], "LIMIT_EXCEEDED");
+        assert_eq!(refused["error"]["details"]["limit"], "maxSessionSnapshots");
+        // The one taken still works
+        let reverted = request(
+            &mut socket,
+            json!({ "id": 3, "type": "revert", "snapshotId": 0 }),
+        )
+        .await;
+        assert_eq!(reverted["result"]["snapshotId"], 0);
+    }
+}
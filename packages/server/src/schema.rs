@@ -0,0 +1,57 @@
+//! OpenAPI stand-ins for types we serialize but don't own. They describe the JSON clients see
+//! without tying the spec to foundry's or revm's internal representations.
+
+use serde_json::Value;
+use utoipa::ToSchema;
+
+/// forge's call trace arena: a flat list of call nodes, each pointing at its parent and children
+/// by index. Deprecated in favour of `TraceNode`; the shape follows forge and isn't covered by the
+/// API's stability guarantees.
+#[derive(ToSchema)]
+pub struct TraceArena {
+    pub arena: Vec<Value>,
+}
+
+/// A solc diagnostic.
+#[derive(ToSchema)]
+#[schema(rename_all = "camelCase")]
+pub struct CompilerError {
+    pub source_location: Option<SourceLocation>,
+    #[schema(rename = "type")]
+    pub kind: String,
+    pub component: String,
+    /// `error`, `warning` or `info`
+    pub severity: String,
+    pub error_code: Option<String>,
+    pub message: String,
+    pub formatted_message: Option<String>,
+}
+
+#[derive(ToSchema)]
+pub struct SourceLocation {
+    pub file: String,
+    pub start: i32,
+    pub end: i32,
+}
+
+/// Compiled contracts keyed by source file, then contract name, with one entry per compiler
+/// version. Each entry holds the solc output (`abi`, `evm`, ...) under `contract`.
+#[derive(ToSchema)]
+#[schema(value_type = Object)]
+pub struct CompiledContracts(pub Value);
+
+/// revm's result for `/execute_calldatas`: an object with one of `Success`, `Revert` or `Halt`,
+/// next to the call's index and `meta`.
+#[derive(ToSchema)]
+pub struct RevmExecutionResult {
+    #[schema(rename = "Success")]
+    pub success: Option<Value>,
+    #[schema(rename = "Revert")]
+    pub revert: Option<Value>,
+    #[schema(rename = "Halt")]
+    pub halt: Option<Value>,
+    #[schema(rename = "callIndex")]
+    pub call_index: usize,
+    /// The call's `meta`, as it was sent
+    pub meta: Option<Value>,
+}
@@ -0,0 +1,166 @@
+use rocket::http::Status;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::compile::solidity::CompileResult;
+use crate::config::Limits;
+use crate::error::ApiError;
+use crate::gas::{LocalChain, StateDump};
+
+/// What a `/ws` connection works on: the last successful compilation, a live in-memory chain
+/// and the snapshots taken of it.
+pub struct Session {
+    pub compilation: Option<CompileResult>,
+    pub chain: LocalChain,
+    /// Indexed by snapshot id
+    pub snapshots: Vec<StateDump>,
+}
+
+impl Session {
+    fn new() -> Result<Self, ApiError> {
+        Ok(Session {
+            compilation: None,
+            chain: LocalChain::new(&StateDump::new(), None).map_err(ApiError::from_execution)?,
+            snapshots: Vec::new(),
+        })
+    }
+}
+
+struct Entry {
+    session: Arc<Mutex<Session>>,
+    connections: usize,
+    /// When the last connection using it went away
+    detached_at: Option<Instant>,
+}
+
+/// Sessions by id. A session outlives its connection by `grace` so a client that drops can
+/// reconnect and pick up where it left off.
+#[derive(Clone)]
+pub struct Sessions {
+    sessions: Arc<Mutex<HashMap<String, Entry>>>,
+    max: usize,
+    grace: Duration,
+}
+
+impl Sessions {
+    pub fn new(limits: &Limits) -> Self {
+        Sessions {
+            sessions: Arc::default(),
+            max: limits.max_sessions.max(1),
+            grace: limits.session_grace,
+        }
+    }
+
+    /// Attaches to the session `id`, or starts a new one without it.
+    pub fn attach(&self, id: Option<&str>) -> Result<(String, Arc<Mutex<Session>>), ApiError> {
+        let mut sessions = self.lock();
+        if let Some(id) = id {
+            let entry = sessions.get_mut(id).ok_or_else(|| {
+                ApiError::new(
+                    Status::NotFound,
+                    "SESSION_NOT_FOUND",
+                    format!("no session with id {}, it may have expired", id),
+                )
+            })?;
+            entry.connections += 1;
+            entry.detached_at = None;
+            return Ok((id.to_string(), entry.session.clone()));
+        }
+
+        if sessions.len() >= self.max {
+            return Err(ApiError::new(
+                Status::ServiceUnavailable,
+                "TOO_MANY_SESSIONS",
+                format!("the server is at its limit of {} sessions", self.max),
+            ));
+        }
+        let id = format!("{:032x}", rand::random::<u128>());
+        let session = Arc::new(Mutex::new(Session::new()?));
+        sessions.insert(
+            id.clone(),
+            Entry {
+                session: session.clone(),
+                connections: 1,
+                detached_at: None,
+            },
+        );
+        Ok((id, session))
+    }
+
+    /// Called when a connection closes. Once the last one has, the session is dropped unless
+    /// reattached within the grace period.
+    pub fn detach(&self, id: &str) {
+        if let Some(entry) = self.lock().get_mut(id) {
+            entry.connections = entry.connections.saturating_sub(1);
+            if entry.connections == 0 {
+                entry.detached_at = Some(Instant::now());
+            }
+        }
+        let sessions = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(sessions.grace).await;
+            drop(sessions.lock());
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Expired sessions are dropped whenever the map is touched
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Entry>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, entry| {
+            entry
+                .detached_at
+                .map_or(true, |detached_at| detached_at.elapsed() < self.grace)
+        });
+        sessions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reattach_within_grace() {
+        let sessions = Sessions::new(&Limits::default());
+        let (id, _) = sessions.attach(None).unwrap();
+        sessions.detach(&id);
+        assert!(sessions.attach(Some(&id)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_dropped_after_grace() {
+        let sessions = Sessions::new(&Limits {
+            session_grace: Duration::ZERO,
+            ..Default::default()
+        });
+        let (id, _) = sessions.attach(None).unwrap();
+        sessions.detach(&id);
+        assert!(sessions.is_empty());
+        assert_eq!(
+            sessions.attach(Some(&id)).err().unwrap().code,
+            "SESSION_NOT_FOUND"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_session_limit() {
+        let sessions = Sessions::new(&Limits {
+            max_sessions: 1,
+            ..Default::default()
+        });
+        sessions.attach(None).unwrap();
+        assert_eq!(
+            sessions.attach(None).err().unwrap().code,
+            "TOO_MANY_SESSIONS"
+        );
+    }
+}
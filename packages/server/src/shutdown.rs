@@ -0,0 +1,186 @@
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::{Orbit, Rocket, State};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::task::{AbortHandle, JoinHandle};
+use tracing::{info, warn};
+
+use crate::config::AppConfig;
+use crate::error::{reject, ApiError};
+use crate::gas::anvil;
+
+/// Compile and execute work still running, so shutdown can wait for it instead of dropping it.
+#[derive(Clone, Default)]
+pub struct InFlight {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    active: AtomicUsize,
+    draining: AtomicBool,
+    idle: Notify,
+    tasks: Mutex<Vec<AbortHandle>>,
+}
+
+/// Counts as in-flight work until dropped.
+pub struct WorkGuard(Arc<Inner>);
+
+impl Drop for WorkGuard {
+    fn drop(&mut self) {
+        if self.0.active.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+impl InFlight {
+    /// Starts a unit of work, unless the server is shutting down.
+    pub fn begin(&self) -> Result<WorkGuard, ApiError> {
+        if self.inner.draining.load(Ordering::SeqCst) {
+            return Err(ApiError::new(
+                Status::ServiceUnavailable,
+                "SHUTTING_DOWN",
+                "the server is shutting down",
+            ));
+        }
+        self.inner.active.fetch_add(1, Ordering::SeqCst);
+        Ok(WorkGuard(self.inner.clone()))
+    }
+
+    /// Spawns work that outlives the request. It counts as in-flight until it finishes and is
+    /// aborted if it's still running when the drain deadline passes.
+    pub fn spawn<F>(&self, future: F) -> Result<JoinHandle<F::Output>, ApiError>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let guard = self.begin()?;
+        let handle = tokio::spawn(async move {
+            let _guard = guard;
+            future.await
+        });
+        self.abort_on_shutdown(handle.abort_handle());
+        Ok(handle)
+    }
+
+    /// Aborts the task if it's still running when the drain deadline passes, without counting it
+    /// as in-flight. For tasks that wait before doing any work.
+    pub fn abort_on_shutdown(&self, task: AbortHandle) {
+        let mut tasks = self.inner.tasks.lock().unwrap();
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(task);
+    }
+
+    pub fn active(&self) -> usize {
+        self.inner.active.load(Ordering::SeqCst)
+    }
+
+    /// Stops new work from starting and waits up to `timeout` for in-flight work to finish, then
+    /// aborts spawned stragglers. Returns whether everything finished in time.
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        self.inner.draining.store(true, Ordering::SeqCst);
+
+        let idle = async {
+            loop {
+                let notified = self.inner.idle.notified();
+                tokio::pin!(notified);
+                notified.as_mut().enable();
+                if self.active() == 0 {
+                    return;
+                }
+                notified.await;
+            }
+        };
+        let drained = tokio::time::timeout(timeout, idle).await.is_ok();
+
+        for task in self.inner.tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+        drained
+    }
+}
+
+/// A slot of in-flight work held for the duration of a handler. Fails with 503 once shutdown
+/// has started.
+pub struct Work {
+    _guard: WorkGuard,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Work {
+    type Error = ApiError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let in_flight = match req.guard::<&State<InFlight>>().await {
+            Outcome::Success(in_flight) => in_flight,
+            _ => return Outcome::Forward(Status::InternalServerError),
+        };
+        match in_flight.begin() {
+            Ok(guard) => Outcome::Success(Work { _guard: guard }),
+            Err(err) => {
+                reject(req, err.clone());
+                Outcome::Error((err.status, err))
+            }
+        }
+    }
+}
+
+/// Shutdown fairing body: drains in-flight work, then stops the managed anvil. Temp dirs are
+/// removed as the work owning them finishes or is aborted.
+pub async fn shutdown(rocket: &Rocket<Orbit>) {
+    let timeout = rocket
+        .state::<AppConfig>()
+        .map(|config| config.drain_timeout)
+        .unwrap_or_default();
+    if let Some(in_flight) = rocket.state::<InFlight>() {
+        info!(active = in_flight.active(), "draining in-flight work");
+        if !in_flight.drain(timeout).await {
+            warn!(
+                active = in_flight.active(),
+                "drain timed out, aborted remaining work"
+            );
+        }
+    }
+    anvil::shutdown().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_drain_waits_for_work() {
+        let in_flight = InFlight::default();
+        let finished = Arc::new(AtomicBool::new(false));
+        let done = finished.clone();
+        in_flight
+            .spawn(async move {
+                // A slow fake execution
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                done.store(true, Ordering::SeqCst);
+            })
+            .unwrap();
+
+        assert!(in_flight.drain(Duration::from_secs(5)).await);
+        assert!(finished.load(Ordering::SeqCst));
+        assert_eq!(in_flight.active(), 0);
+        assert_eq!(in_flight.begin().err().unwrap().code, "SHUTTING_DOWN");
+    }
+
+    #[tokio::test]
+    async fn test_drain_aborts_stragglers() {
+        let in_flight = InFlight::default();
+        let handle = in_flight
+            .spawn(tokio::time::sleep(Duration::from_secs(60)))
+            .unwrap();
+
+        assert!(!in_flight.drain(Duration::from_millis(50)).await);
+        assert!(handle.await.unwrap_err().is_cancelled());
+        assert_eq!(in_flight.active(), 0);
+    }
+}
@@ -0,0 +1,178 @@
+use rocket::http::Status;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::caches::{Cache, CacheStats};
+use crate::config::Limits;
+use crate::error::ApiError;
+use crate::gas::StateDump;
+use crate::ids;
+
+struct Snapshot {
+    state: Arc<StateDump>,
+    stored_at: Instant,
+    used_at: Instant,
+}
+
+/// States left behind by `/deploy` and `/transact`, so a later `/transact` can continue from one
+/// by id. Snapshots are immutable: every transaction stores a new one, so clients can branch.
+/// Clones share the same snapshots.
+#[derive(Clone)]
+pub struct Snapshots {
+    snapshots: Arc<Mutex<HashMap<String, Snapshot>>>,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+    max: usize,
+    ttl: Duration,
+}
+
+impl Snapshots {
+    pub fn new(limits: &Limits) -> Self {
+        Snapshots {
+            snapshots: Arc::default(),
+            hits: Arc::default(),
+            misses: Arc::default(),
+            max: limits.max_snapshots.max(1),
+            ttl: limits.snapshot_ttl,
+        }
+    }
+
+    /// Stores the state and returns its id, evicting the least recently used past the limit.
+    pub fn insert(&self, state: StateDump) -> String {
+        self.insert_as(ids::random(), state)
+    }
+
+    /// Stores the state under `id`, replacing any already there.
+    pub fn insert_as(&self, id: String, state: StateDump) -> String {
+        let mut snapshots = self.lock();
+        while snapshots.len() >= self.max {
+            let Some(oldest) = snapshots
+                .iter()
+                .min_by_key(|(_, snapshot)| snapshot.used_at)
+                .map(|(id, _)| id.clone())
+            else {
+                break;
+            };
+            snapshots.remove(&oldest);
+        }
+        snapshots.insert(
+            id.clone(),
+            Snapshot {
+                state: Arc::new(state),
+                stored_at: Instant::now(),
+                used_at: Instant::now(),
+            },
+        );
+        id
+    }
+
+    pub fn get(&self, id: &str) -> Result<Arc<StateDump>, ApiError> {
+        let mut snapshots = self.lock();
+        let snapshot = snapshots.get_mut(id).ok_or_else(|| {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            ApiError::new(
+                Status::NotFound,
+                "SNAPSHOT_NOT_FOUND",
+                format!("no state with id {}, it may have expired", id),
+            )
+        })?;
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        snapshot.used_at = Instant::now();
+        Ok(snapshot.state.clone())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Snapshot>> {
+        let mut snapshots = self.snapshots.lock().unwrap();
+        snapshots.retain(|_, snapshot| snapshot.used_at.elapsed() < self.ttl);
+        snapshots
+    }
+}
+
+impl Cache for Snapshots {
+    fn name(&self) -> &'static str {
+        "snapshots"
+    }
+
+    fn stats(&self) -> CacheStats {
+        let snapshots = self.lock();
+        CacheStats {
+            entries: snapshots.len(),
+            bytes: snapshots
+                .values()
+                .map(|snapshot| estimated_size(&snapshot.state))
+                .sum(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            oldest_entry_age_secs: snapshots
+                .values()
+                .map(|snapshot| snapshot.stored_at.elapsed().as_secs())
+                .max(),
+        }
+    }
+
+    fn clear(&self) -> usize {
+        let mut snapshots = self.lock();
+        let cleared = snapshots.len();
+        snapshots.clear();
+        cleared
+    }
+}
+
+// Code and storage dominate, the rest is a per-account guess
+fn estimated_size(state: &StateDump) -> usize {
+    state
+        .values()
+        .map(|account| 128 + account.code.len() + account.storage.len() * 64)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let snapshots = Snapshots::new(&Limits {
+            max_snapshots: 2,
+            ..Default::default()
+        });
+        let first = snapshots.insert(StateDump::new());
+        let second = snapshots.insert(StateDump::new());
+        snapshots.get(&first).unwrap();
+        snapshots.insert(StateDump::new());
+
+        assert!(snapshots.get(&first).is_ok());
+        assert_eq!(
+            snapshots.get(&second).err().unwrap().code,
+            "SNAPSHOT_NOT_FOUND"
+        );
+    }
+
+    #[test]
+    fn test_expiry() {
+        let snapshots = Snapshots::new(&Limits {
+            snapshot_ttl: Duration::ZERO,
+            ..Default::default()
+        });
+        let id = snapshots.insert(StateDump::new());
+        assert!(snapshots.get(&id).is_err());
+    }
+
+    #[test]
+    fn test_stats_and_clear() {
+        let snapshots = Snapshots::new(&Limits::default());
+        let id = snapshots.insert(StateDump::new());
+        snapshots.get(&id).unwrap();
+        assert!(snapshots.get("missing").is_err());
+
+        let stats = snapshots.stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses), (1, 1, 1));
+        assert_eq!(stats.oldest_entry_age_secs, Some(0));
+
+        assert_eq!(snapshots.clear(), 1);
+        assert_eq!(snapshots.stats().entries, 0);
+        assert_eq!(snapshots.stats().oldest_entry_age_secs, None);
+    }
+}
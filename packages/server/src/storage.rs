@@ -0,0 +1,514 @@
+use alloy_dyn_abi::DynSolType;
+use alloy_primitives::{hex, keccak256, B256, U256};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+/// The shape of a storage variable, as far as slot computation cares.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SlotType {
+    /// A value type packed into at most one slot
+    Value {
+        label: String,
+        bytes: usize,
+    },
+    /// `bytes` or `string`
+    Bytes {
+        label: String,
+    },
+    Mapping {
+        key: String,
+        value: Box<SlotType>,
+    },
+    DynamicArray(Box<SlotType>),
+    StaticArray(Box<SlotType>, usize),
+    Struct {
+        label: String,
+        members: Vec<Member>,
+        bytes: usize,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Member {
+    pub name: String,
+    /// Relative to the struct's first slot
+    pub slot: U256,
+    pub offset: usize,
+    pub ty: SlotType,
+}
+
+impl SlotType {
+    pub fn label(&self) -> String {
+        match self {
+            SlotType::Value { label, .. }
+            | SlotType::Bytes { label }
+            | SlotType::Struct { label, .. } => label.clone(),
+            SlotType::Mapping { key, value } => format!("mapping({} => {})", key, value.label()),
+            SlotType::DynamicArray(element) => format!("{}[]", element.label()),
+            SlotType::StaticArray(element, len) => format!("{}[{}]", element.label(), len),
+        }
+    }
+
+    /// Bytes taken in storage. Anything over 32 spans whole slots.
+    pub fn bytes(&self) -> usize {
+        match self {
+            SlotType::Value { bytes, .. } | SlotType::Struct { bytes, .. } => *bytes,
+            SlotType::Bytes { .. } | SlotType::Mapping { .. } | SlotType::DynamicArray(_) => 32,
+            SlotType::StaticArray(element, len) => {
+                let size = element.bytes();
+                if size <= 16 {
+                    let per_slot = 32 / size;
+                    len.div_ceil(per_slot) * 32
+                } else {
+                    len * size.div_ceil(32) * 32
+                }
+            }
+        }
+    }
+
+    /// Parses a type written the way Solidity declares it, e.g.
+    /// `mapping(address => mapping(uint256 => uint256[]))`. Structs need a storage layout.
+    pub fn parse(s: &str) -> Result<SlotType, eyre::Error> {
+        let s = s.trim();
+        if let Some(inner) = s
+            .strip_prefix("mapping(")
+            .and_then(|rest| rest.strip_suffix(')'))
+        {
+            let arrow =
+                top_level_arrow(inner).ok_or_else(|| eyre::eyre!("expected `=>` in {}", s))?;
+            let key = inner[..arrow].trim();
+            mapping_key(key)?;
+            return Ok(SlotType::Mapping {
+                key: key.to_string(),
+                value: Box::new(SlotType::parse(&inner[arrow + 2..])?),
+            });
+        }
+        if let Some(rest) = s.strip_suffix(']') {
+            let open = rest
+                .rfind('[')
+                .ok_or_else(|| eyre::eyre!("unbalanced brackets in {}", s))?;
+            let element = Box::new(SlotType::parse(&rest[..open])?);
+            let len = rest[open + 1..].trim();
+            if len.is_empty() {
+                return Ok(SlotType::DynamicArray(element));
+            }
+            let len = len
+                .parse()
+                .map_err(|_| eyre::eyre!("invalid array length in {}", s))?;
+            return Ok(SlotType::StaticArray(element, len));
+        }
+        if s == "string" || s == "bytes" {
+            return Ok(SlotType::Bytes {
+                label: s.to_string(),
+            });
+        }
+        let bytes = match DynSolType::parse(s)? {
+            DynSolType::Bool => 1,
+            DynSolType::Address => 20,
+            DynSolType::Uint(bits) | DynSolType::Int(bits) => bits / 8,
+            DynSolType::FixedBytes(size) => size,
+            _ => return Err(eyre::eyre!("{} is not a storage value type", s)),
+        };
+        Ok(SlotType::Value {
+            label: s.to_string(),
+            bytes,
+        })
+    }
+}
+
+fn top_level_arrow(s: &str) -> Option<usize> {
+    let mut depth = 0i32;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            '=' if depth == 0 && s[i..].starts_with("=>") => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+// Mapping keys are value types or bytes/string. Contracts and enums come through layouts
+// labelled as such and are stored as their underlying type.
+fn mapping_key(label: &str) -> Result<Option<DynSolType>, eyre::Error> {
+    if label == "string" || label == "bytes" {
+        return Ok(None);
+    }
+    if label.starts_with("contract ") || label.starts_with("interface ") {
+        return Ok(Some(DynSolType::Address));
+    }
+    if label.starts_with("enum ") {
+        return Ok(Some(DynSolType::Uint(8)));
+    }
+    Ok(Some(DynSolType::parse(label)?))
+}
+
+/// The `storageLayout` solc produces for a contract.
+#[derive(Deserialize, Clone, Debug, ToSchema)]
+pub struct StorageLayout {
+    pub storage: Vec<LayoutEntry>,
+    pub types: BTreeMap<String, LayoutType>,
+}
+
+#[derive(Deserialize, Clone, Debug, ToSchema)]
+pub struct LayoutEntry {
+    pub label: String,
+    pub slot: String,
+    pub offset: usize,
+    #[serde(rename = "type")]
+    pub ty: String,
+}
+
+#[derive(Deserialize, Clone, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LayoutType {
+    pub encoding: String,
+    pub label: String,
+    pub number_of_bytes: String,
+    pub key: Option<String>,
+    pub value: Option<String>,
+    pub base: Option<String>,
+    pub members: Option<Vec<LayoutEntry>>,
+}
+
+impl StorageLayout {
+    /// Finds a variable, returning its slot, byte offset and type.
+    pub fn variable(&self, label: &str) -> Result<(U256, usize, SlotType), eyre::Error> {
+        let entry = self
+            .storage
+            .iter()
+            .find(|entry| entry.label == label)
+            .ok_or_else(|| eyre::eyre!("no storage variable named {}", label))?;
+        Ok((
+            parse_slot(&entry.slot)?,
+            entry.offset,
+            self.resolve(&entry.ty)?,
+        ))
+    }
+
+    fn resolve(&self, id: &str) -> Result<SlotType, eyre::Error> {
+        let ty = self
+            .types
+            .get(id)
+            .ok_or_else(|| eyre::eyre!("type {} missing from the layout", id))?;
+        let field = |value: &Option<String>, name: &str| {
+            value
+                .clone()
+                .ok_or_else(|| eyre::eyre!("type {} has no {}", id, name))
+        };
+        let bytes: usize = ty
+            .number_of_bytes
+            .parse()
+            .map_err(|_| eyre::eyre!("type {} has an invalid size", id))?;
+
+        Ok(match ty.encoding.as_str() {
+            "mapping" => {
+                let key = field(&ty.key, "key")?;
+                SlotType::Mapping {
+                    key: self.types.get(&key).map_or(key, |key| key.label.clone()),
+                    value: Box::new(self.resolve(&field(&ty.value, "value")?)?),
+                }
+            }
+            "dynamic_array" => {
+                SlotType::DynamicArray(Box::new(self.resolve(&field(&ty.base, "base")?)?))
+            }
+            "bytes" => SlotType::Bytes {
+                label: ty.label.clone(),
+            },
+            _ => match (&ty.members, &ty.base) {
+                (Some(members), _) => SlotType::Struct {
+                    label: ty.label.clone(),
+                    members: members
+                        .iter()
+                        .map(|member| {
+                            Ok(Member {
+                                name: member.label.clone(),
+                                slot: parse_slot(&member.slot)?,
+                                offset: member.offset,
+                                ty: self.resolve(&member.ty)?,
+                            })
+                        })
+                        .collect::<Result<_, eyre::Error>>()?,
+                    bytes,
+                },
+                (None, Some(base)) => {
+                    // e.g. `uint8[3]`
+                    let len = ty
+                        .label
+                        .rsplit_once('[')
+                        .and_then(|(_, len)| len.strip_suffix(']'))
+                        .and_then(|len| len.parse().ok())
+                        .ok_or_else(|| eyre::eyre!("can't read array length from {}", ty.label))?;
+                    SlotType::StaticArray(Box::new(self.resolve(base)?), len)
+                }
+                (None, None) => SlotType::Value {
+                    label: ty.label.clone(),
+                    bytes,
+                },
+            },
+        })
+    }
+}
+
+pub fn parse_slot(slot: &str) -> Result<U256, eyre::Error> {
+    U256::from_str(slot.trim()).map_err(|err| eyre::eyre!("invalid slot {}: {}", slot, err))
+}
+
+/// One step of the computation, for display.
+#[derive(Serialize, Clone, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SlotStep {
+    pub description: String,
+    /// Hashed to get `slot`, when the step hashes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preimage: Option<String>,
+    #[schema(value_type = String)]
+    pub slot: B256,
+}
+
+#[derive(Serialize, Clone, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SlotLocation {
+    #[schema(value_type = String)]
+    pub slot: B256,
+    /// Byte offset from the right of the slot, as in solc's layout
+    pub offset: usize,
+    /// Bytes taken, or 32 for anything that fills whole slots
+    pub length: usize,
+    #[serde(rename = "type")]
+    pub ty: String,
+    pub steps: Vec<SlotStep>,
+}
+
+/// Walks `path` from a variable at `slot`/`offset`. Each path element is a mapping key, an array
+/// index or a struct member name, depending on the type it's applied to.
+pub fn locate(
+    slot: U256,
+    offset: usize,
+    ty: &SlotType,
+    path: &[Value],
+) -> Result<SlotLocation, eyre::Error> {
+    let mut slot = slot;
+    let mut offset = offset;
+    let mut ty = ty.clone();
+    let mut steps = vec![SlotStep {
+        description: format!("{} starts at slot {}", ty.label(), slot),
+        preimage: None,
+        slot: slot.into(),
+    }];
+
+    for (i, element) in path.iter().enumerate() {
+        let at = |message: String| eyre::eyre!("path[{}]: {}", i, message);
+        match ty {
+            SlotType::Mapping { key, value } => {
+                let key_text = match element {
+                    Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                };
+                let mut preimage = match mapping_key(&key).map_err(|err| at(err.to_string()))? {
+                    Some(key_ty) => key_ty
+                        .coerce_str(&key_text)
+                        .map_err(|err| at(format!("key for {}: {}", key, err)))?
+                        .abi_encode(),
+                    None if key == "string" => key_text.into_bytes(),
+                    None => hex::decode(&key_text).map_err(|err| at(err.to_string()))?,
+                };
+                preimage.extend_from_slice(&B256::from(slot)[..]);
+                slot = keccak256(&preimage).into();
+                offset = 0;
+                steps.push(SlotStep {
+                    description: format!("mapping key {}", key_text),
+                    preimage: Some(hex::encode_prefixed(&preimage)),
+                    slot: slot.into(),
+                });
+                ty = *value;
+            }
+            SlotType::DynamicArray(element_ty) => {
+                let index = array_index(element).map_err(at)?;
+                let data: U256 = keccak256(B256::from(slot)).into();
+                steps.push(SlotStep {
+                    description: format!("array data for slot {}", slot),
+                    preimage: Some(B256::from(slot).to_string()),
+                    slot: data.into(),
+                });
+                (slot, offset) = element_position(data, index, &element_ty);
+                steps.push(SlotStep {
+                    description: format!("element {}", index),
+                    preimage: None,
+                    slot: slot.into(),
+                });
+                ty = *element_ty;
+            }
+            SlotType::StaticArray(element_ty, len) => {
+                let index = array_index(element).map_err(at)?;
+                if index >= len {
+                    return Err(at(format!(
+                        "index {} out of bounds for length {}",
+                        index, len
+                    )));
+                }
+                (slot, offset) = element_position(slot, index, &element_ty);
+                steps.push(SlotStep {
+                    description: format!("element {}", index),
+                    preimage: None,
+                    slot: slot.into(),
+                });
+                ty = *element_ty;
+            }
+            SlotType::Struct { members, label, .. } => {
+                let name = element
+                    .as_str()
+                    .ok_or_else(|| at(format!("expected a member name of {}", label)))?;
+                let member = members
+                    .into_iter()
+                    .find(|member| member.name == name)
+                    .ok_or_else(|| at(format!("{} has no member {}", label, name)))?;
+                slot += member.slot;
+                offset = member.offset;
+                steps.push(SlotStep {
+                    description: format!("member {}", name),
+                    preimage: None,
+                    slot: slot.into(),
+                });
+                ty = member.ty;
+            }
+            SlotType::Value { .. } | SlotType::Bytes { .. } => {
+                return Err(at(format!("can't index into {}", ty.label())));
+            }
+        }
+    }
+
+    Ok(SlotLocation {
+        slot: slot.into(),
+        offset,
+        length: ty.bytes().min(32),
+        ty: ty.label(),
+        steps,
+    })
+}
+
+fn array_index(element: &Value) -> Result<usize, String> {
+    match element {
+        Value::Number(n) => n.as_u64().map(|n| n as usize),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| format!("expected an array index, got {}", element))
+}
+
+// Elements of 16 bytes or less are packed several to a slot
+fn element_position(base: U256, index: usize, element: &SlotType) -> (U256, usize) {
+    let size = element.bytes();
+    if size <= 16 {
+        let per_slot = 32 / size;
+        (
+            base + U256::from(index / per_slot),
+            (index % per_slot) * size,
+        )
+    } else {
+        (base + U256::from(index * size.div_ceil(32)), 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn hash(parts: &[B256]) -> U256 {
+        keccak256(parts.concat()).into()
+    }
+
+    fn word(n: u64) -> B256 {
+        U256::from(n).into()
+    }
+
+    // From the Solidity docs: `data[4][9].c` where
+    //   struct S { uint16 a; uint16 b; uint256 c; }
+    //   uint x;
+    //   mapping(uint => mapping(uint => S)) data;
+    fn docs_layout() -> StorageLayout {
+        serde_json::from_value(json!({
+            "storage": [
+                { "label": "x", "slot": "0", "offset": 0, "type": "t_uint256" },
+                { "label": "data", "slot": "1", "offset": 0,
+                  "type": "t_mapping(t_uint256,t_mapping(t_uint256,t_struct(S)storage))" }
+            ],
+            "types": {
+                "t_uint16": { "encoding": "inplace", "label": "uint16", "numberOfBytes": "2" },
+                "t_uint256": { "encoding": "inplace", "label": "uint256", "numberOfBytes": "32" },
+                "t_struct(S)storage": {
+                    "encoding": "inplace", "label": "struct C.S", "numberOfBytes": "64",
+                    "members": [
+                        { "label": "a", "slot": "0", "offset": 0, "type": "t_uint16" },
+                        { "label": "b", "slot": "0", "offset": 2, "type": "t_uint16" },
+                        { "label": "c", "slot": "1", "offset": 0, "type": "t_uint256" }
+                    ]
+                },
+                "t_mapping(t_uint256,t_struct(S)storage)": {
+                    "encoding": "mapping", "label": "mapping(uint256 => struct C.S)",
+                    "numberOfBytes": "32", "key": "t_uint256", "value": "t_struct(S)storage"
+                },
+                "t_mapping(t_uint256,t_mapping(t_uint256,t_struct(S)storage))": {
+                    "encoding": "mapping",
+                    "label": "mapping(uint256 => mapping(uint256 => struct C.S))",
+                    "numberOfBytes": "32", "key": "t_uint256",
+                    "value": "t_mapping(t_uint256,t_struct(S)storage)"
+                }
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_docs_example() {
+        let layout = docs_layout();
+        let (slot, offset, ty) = layout.variable("data").unwrap();
+        let inner = hash(&[word(4), word(1)]);
+        let outer = hash(&[word(9), inner.into()]);
+
+        let c = locate(slot, offset, &ty, &[json!(4), json!(9), json!("c")]).unwrap();
+        assert_eq!(c.slot, B256::from(outer + U256::from(1)));
+        assert_eq!((c.offset, c.length), (0, 32));
+        assert_eq!(c.steps.len(), 4);
+
+        // a and b are packed into the struct's first slot
+        let b = locate(slot, offset, &ty, &[json!(4), json!(9), json!("b")]).unwrap();
+        assert_eq!(b.slot, B256::from(outer));
+        assert_eq!((b.offset, b.length), (2, 2));
+    }
+
+    #[test]
+    fn test_arrays() {
+        // uint8[] at slot 2: 32 elements per slot
+        let ty = SlotType::parse("uint8[]").unwrap();
+        let loc = locate(U256::from(2), 0, &ty, &[json!(33)]).unwrap();
+        assert_eq!(loc.slot, B256::from(hash(&[word(2)]) + U256::from(1)));
+        assert_eq!((loc.offset, loc.length), (1, 1));
+
+        // uint128[3] at slot 5: two elements per slot
+        let ty = SlotType::parse("uint128[3]").unwrap();
+        let loc = locate(U256::from(5), 0, &ty, &[json!(1)]).unwrap();
+        assert_eq!((loc.slot, loc.offset), (word(5), 16));
+        let loc = locate(U256::from(5), 0, &ty, &[json!(2)]).unwrap();
+        assert_eq!((loc.slot, loc.offset), (word(6), 0));
+        assert!(locate(U256::from(5), 0, &ty, &[json!(3)]).is_err());
+    }
+
+    #[test]
+    fn test_mapping_keys() {
+        let ty = SlotType::parse("mapping(address => mapping(string => uint256))").unwrap();
+        let owner = "0x1000000000000000000000000000000000000000";
+        let loc = locate(U256::ZERO, 0, &ty, &[json!(owner), json!("abc")]).unwrap();
+
+        let mut address_key = [0u8; 32];
+        address_key[12] = 0x10;
+        let first = hash(&[B256::from(address_key), word(0)]);
+        let expected = keccak256([b"abc".as_slice(), &B256::from(first)[..]].concat());
+        assert_eq!(loc.slot, expected);
+    }
+}
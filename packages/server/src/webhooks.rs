@@ -0,0 +1,425 @@
+use alloy_primitives::hex;
+use hmac::{Hmac, Mac};
+use rocket::http::Status;
+use serde_json::json;
+use sha2::Sha256;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tracing::{info, warn};
+use url::{Host, Url};
+
+use crate::config::AppConfig;
+use crate::error::ApiError;
+use crate::jobs::JobState;
+
+/// Sent with every callback, so receivers can check it came from us.
+pub const SIGNATURE_HEADER: &str = "X-Signature-256";
+
+const DELIVERY_ATTEMPTS: u32 = 4;
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// POSTs finished jobs to the `callbackUrl` they were submitted with. Bodies are signed with
+/// HMAC-SHA256 under the server's webhook secret, and only public addresses are called unless
+/// the host is allowed in config.
+#[derive(Clone)]
+pub struct Webhooks {
+    secret: String,
+    allowed_hosts: Vec<String>,
+    max_inline_bytes: usize,
+    retry_delay: Duration,
+}
+
+impl Webhooks {
+    /// Callbacks are off without a secret to sign them with.
+    pub fn new(config: &AppConfig) -> Option<Self> {
+        Some(Webhooks {
+            secret: config.webhook_secret.clone()?,
+            allowed_hosts: config.webhook_allowed_hosts.clone(),
+            max_inline_bytes: config.limits.max_callback_bytes,
+            retry_delay: Duration::from_secs(1),
+        })
+    }
+
+    /// Checks a callback URL when the job is submitted, so a bad one is refused up front.
+    /// Delivery checks it again, since what the host resolves to can change in between.
+    pub async fn check_url(&self, url: &str) -> Result<Url, ApiError> {
+        let url = Url::parse(url).map_err(|err| invalid_url(err.to_string()))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(invalid_url("must be http or https"));
+        }
+        self.resolve(&url).await?;
+        Ok(url)
+    }
+
+    // The address to connect to, pinned so the request can't be rebound to another one. None when
+    // the host is allowed, or is already an address.
+    async fn resolve(&self, url: &Url) -> Result<Option<(String, SocketAddr)>, ApiError> {
+        let host = url.host().ok_or_else(|| invalid_url("has no host"))?;
+        let host_str = url.host_str().unwrap_or_default();
+        if self
+            .allowed_hosts
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(host_str))
+        {
+            return Ok(None);
+        }
+        let port = url.port_or_known_default().unwrap_or(80);
+        let addrs: Vec<SocketAddr> = match host {
+            Host::Ipv4(ip) => vec![SocketAddr::new(ip.into(), port)],
+            Host::Ipv6(ip) => vec![SocketAddr::new(ip.into(), port)],
+            Host::Domain(domain) => tokio::net::lookup_host((domain, port))
+                .await
+                .map_err(|err| invalid_url(format!("can't resolve {}: {}", domain, err)))?
+                .collect(),
+        };
+        if addrs.is_empty() {
+            return Err(invalid_url(format!("{} resolves to nothing", host_str)));
+        }
+        if let Some(addr) = addrs.iter().find(|addr| !is_public(addr.ip())) {
+            return Err(invalid_url(format!(
+                "{} resolves to {}, which isn't a public address",
+                host_str,
+                addr.ip()
+            )));
+        }
+        Ok(match host {
+            Host::Domain(domain) => Some((domain.to_string(), addrs[0])),
+            _ => None,
+        })
+    }
+
+    /// The body sent for a finished job: its full state, or a summary and where to fetch the rest
+    /// if that's over `max_callback_bytes`.
+    pub fn envelope(&self, job_id: &str, state: &JobState) -> Vec<u8> {
+        let full = serde_json::to_vec(&json!({ "jobId": job_id, "job": state }))
+            .expect("job states serialize");
+        if full.len() <= self.max_inline_bytes {
+            return full;
+        }
+        let mut summary = json!({
+            "jobId": job_id,
+            "status": serde_json::to_value(state).expect("job states serialize")["status"],
+            "resultUrl": format!("/v1/jobs/{}", job_id),
+        });
+        if let JobState::Done { results } = state {
+            summary["results"] = json!(results.len());
+        }
+        serde_json::to_vec(&summary).expect("summaries serialize")
+    }
+
+    pub fn sign(&self, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes())
+            .expect("HMAC takes keys of any length");
+        mac.update(body);
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    /// Posts the job's envelope, retrying with backoff on 5xx and connection failures.
+    pub async fn deliver(&self, url: &Url, job_id: &str, state: &JobState) {
+        let body = self.envelope(job_id, state);
+        let signature = self.sign(&body);
+        let mut delay = self.retry_delay;
+        for attempt in 1..=DELIVERY_ATTEMPTS {
+            match self.post(url, job_id, &signature, body.clone()).await {
+                Ok(status) if status.is_success() => {
+                    info!(job_id, attempt, %status, "delivered job callback");
+                    return;
+                }
+                Ok(status) if !status.is_server_error() => {
+                    warn!(job_id, %status, "job callback refused");
+                    return;
+                }
+                Ok(status) => warn!(job_id, attempt, %status, "job callback failed"),
+                Err(err) => warn!(job_id, attempt, %err, "job callback failed"),
+            }
+            if attempt < DELIVERY_ATTEMPTS {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+        }
+        warn!(job_id, "giving up on job callback");
+    }
+
+    async fn post(
+        &self,
+        url: &Url,
+        job_id: &str,
+        signature: &str,
+        body: Vec<u8>,
+    ) -> Result<reqwest::StatusCode, eyre::Error> {
+        let mut client = reqwest::Client::builder()
+            .timeout(DELIVERY_TIMEOUT)
+            // A redirect could point anywhere
+            .redirect(reqwest::redirect::Policy::none());
+        if let Some((domain, addr)) = self.resolve(url).await? {
+            client = client.resolve(&domain, addr);
+        }
+        let response = client
+            .build()?
+            .post(url.clone())
+            .header("Content-Type", "application/json")
+            .header("X-Job-Id", job_id)
+            .header(SIGNATURE_HEADER, signature)
+            .body(body)
+            .send()
+            .await?;
+        Ok(response.status())
+    }
+}
+
+fn invalid_url(message: impl std::fmt::Display) -> ApiError {
+    ApiError::new(
+        Status::UnprocessableEntity,
+        "INVALID_CALLBACK_URL",
+        format!("callbackUrl {}", message),
+    )
+    .with_details(json!({ "field": "callbackUrl" }))
+}
+
+// Loopback, private, link-local and the other ranges a callback has no business reaching
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        || a == 0
+        // Carrier-grade NAT, 100.64.0.0/10
+        || (a == 100 && (b & 0xc0) == 64)
+        // Benchmarking, 198.18.0.0/15
+        || (a == 198 && (b & 0xfe) == 18)
+        // Reserved, 240.0.0.0/4
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Unique local, fc00::/7
+        || (first & 0xfe00) == 0xfc00
+        // Link-local, fe80::/10
+        || (first & 0xffc0) == 0xfe80
+        // Site-local, fec0::/10
+        || (first & 0xffc0) == 0xfec0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gas::{ExecutionResult, ExitReason};
+    use alloy_primitives::Bytes;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    struct Received {
+        headers: String,
+        body: Vec<u8>,
+    }
+
+    // Answers each request with the next status in `statuses`, passing along what it got
+    async fn receiver(statuses: Vec<u16>) -> (Url, mpsc::UnboundedReceiver<Received>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/hook", listener.local_addr().unwrap())).unwrap();
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            for status in statuses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                let (headers, body) = loop {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    let Some(end) = text.find("\r\n\r\n") else {
+                        continue;
+                    };
+                    let length: usize = text[..end]
+                        .lines()
+                        .find_map(|line| {
+                            let (name, value) = line.split_once(':')?;
+                            name.eq_ignore_ascii_case("content-length")
+                                .then(|| value.trim().parse().unwrap())
+                        })
+                        .unwrap_or(0);
+                    if request.len() >= end + 4 + length {
+                        break (text[..end].to_string(), request[end + 4..].to_vec());
+                    }
+                };
+                let response = format!(
+                    "HTTP/1.1 {} X\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+                tx.send(Received { headers, body }).unwrap();
+            }
+        });
+        (url, rx)
+    }
+
+    fn webhooks() -> Webhooks {
+        Webhooks {
+            secret: "shh".to_string(),
+            allowed_hosts: vec!["127.0.0.1".to_string()],
+            max_inline_bytes: 1 << 20,
+            retry_delay: Duration::from_millis(10),
+        }
+    }
+
+    fn done(results: usize) -> JobState {
+        let result = ExecutionResult {
+            exit_reason: ExitReason::Success,
+            success: true,
+            reverted: false,
+            revert_reason: None,
+            result: Bytes::new(),
+            gas_used: 21_000,
+            gas_limit: None,
+            logs: vec![],
+            traces: None,
+            raw_traces: None,
+            labels: Default::default(),
+            trace_export: None,
+            flamegraph: None,
+            state_diff: None,
+            access_list: None,
+            consistency_check: None,
+            env: None,
+            hint: None,
+            journal: None,
+            transient_storage_accesses: None,
+            created: None,
+            paused_at: None,
+            internal_frames: None,
+            target_balance_before: None,
+            target_balance_after: None,
+            tx_validity: None,
+            attempts: None,
+            skipped: None,
+            call_index: None,
+            meta: None,
+        };
+        JobState::Done {
+            results: vec![result; results],
+        }
+    }
+
+    fn header<'a>(received: &'a Received, name: &str) -> Option<&'a str> {
+        received.headers.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.eq_ignore_ascii_case(name).then(|| value.trim())
+        })
+    }
+
+    #[tokio::test]
+    async fn test_delivers_signed_envelope() {
+        let webhooks = webhooks();
+        let (url, mut received) = receiver(vec![200]).await;
+        webhooks.deliver(&url, "abc", &done(1)).await;
+
+        let request = received.recv().await.unwrap();
+        assert_eq!(header(&request, "X-Job-Id"), Some("abc"));
+
+        // What a receiver would do with the shared secret
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"shh").unwrap();
+        mac.update(&request.body);
+        let expected = format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+        assert_eq!(header(&request, SIGNATURE_HEADER), Some(expected.as_str()));
+
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body["jobId"], "abc");
+        assert_eq!(body["job"]["status"], "done");
+        assert_eq!(body["job"]["results"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_retries_server_errors_only() {
+        let webhooks = webhooks();
+        let (url, mut received) = receiver(vec![503, 500, 200]).await;
+        webhooks.deliver(&url, "abc", &done(0)).await;
+        for _ in 0..3 {
+            received.recv().await.unwrap();
+        }
+
+        let (url, mut received) = receiver(vec![400, 200]).await;
+        webhooks.deliver(&url, "abc", &done(0)).await;
+        received.recv().await.unwrap();
+        assert!(received.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_large_results_are_summarized() {
+        let webhooks = Webhooks {
+            max_inline_bytes: 64,
+            ..webhooks()
+        };
+        let body: serde_json::Value =
+            serde_json::from_slice(&webhooks.envelope("abc", &done(3))).unwrap();
+        assert_eq!(
+            body,
+            json!({ "jobId": "abc", "status": "done", "resultUrl": "/v1/jobs/abc", "results": 3 })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rejects_private_hosts() {
+        let strict = Webhooks {
+            allowed_hosts: vec![],
+            ..webhooks()
+        };
+        for url in [
+            "http://127.0.0.1/hook",
+            "http://localhost:8000/hook",
+            "http://10.1.2.3/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]/hook",
+            "http://[::ffff:192.168.0.1]/hook",
+            "ftp://example.com/hook",
+            "not a url",
+        ] {
+            let err = strict.check_url(url).await.unwrap_err();
+            assert_eq!(err.code, "INVALID_CALLBACK_URL", "{}", url);
+            assert_eq!(err.details.unwrap()["field"], "callbackUrl");
+        }
+        assert!(strict.check_url("http://8.8.8.8/hook").await.is_ok());
+        assert!(webhooks()
+            .check_url("http://127.0.0.1:9/hook")
+            .await
+            .is_ok());
+    }
+
+    #[test]
+    fn test_public_ranges() {
+        for ip in ["1.1.1.1", "100.128.0.1", "198.20.0.1", "2606:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "0.1.2.3",
+            "100.64.0.1",
+            "172.16.0.1",
+            "198.19.255.1",
+            "240.0.0.1",
+            "fc00::1",
+            "fd00::1",
+            "fe80::1",
+            "fec0::1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+}
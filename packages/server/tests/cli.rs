@@ -0,0 +1,98 @@
+use assert_cmd::Command;
+use serde_json::Value;
+
+fn evm_repl() -> Command {
+    let mut cmd = Command::cargo_bin("evm-repl").unwrap();
+    cmd.current_dir(env!("CARGO_MANIFEST_DIR"));
+    cmd
+}
+
+fn stdout_json(output: &std::process::Output) -> Value {
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+#[test]
+fn test_compile_json() {
+    let output = evm_repl()
+        .args(["compile", "tests/fixtures/counter", "--json"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let result = stdout_json(&output);
+    assert!(result["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|err| err["severity"] != "error"));
+    assert!(result["contracts"].to_string().contains("Counter"));
+    assert!(result["source_maps"].as_object().is_some());
+}
+
+#[test]
+fn test_compile_errors_exit_nonzero() {
+    evm_repl()
+        .args(["compile", "tests/fixtures/broken", "--json"])
+        .assert()
+        .code(1);
+    evm_repl()
+        .args(["compile", "tests/fixtures/missing"])
+        .assert()
+        .code(3);
+}
+
+#[test]
+fn test_exec() {
+    let output = evm_repl()
+        .args([
+            "exec",
+            "--bytecode-file",
+            "tests/fixtures/get.hex",
+            "--calls",
+            "tests/fixtures/calls.json",
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    let results = stdout_json(&output);
+    assert_eq!(results.as_array().unwrap().len(), 2);
+    assert_eq!(results[0]["success"], true);
+    assert_eq!(
+        results[0]["result"],
+        "0x0000000000000000000000000000000000000000000000000000000000000000"
+    );
+}
+
+#[test]
+fn test_exec_strict_fails_on_revert() {
+    let args = [
+        "exec",
+        "--bytecode-file",
+        "tests/fixtures/revert.hex",
+        "--calls",
+        "tests/fixtures/calls.json",
+    ];
+    let output = evm_repl().args(args).output().unwrap();
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    assert_eq!(stdout_json(&output)[0]["reverted"], true);
+
+    evm_repl().args(args).arg("--strict").assert().code(1);
+}
+
+#[test]
+fn test_exec_rejects_bad_calls_file() {
+    let output = evm_repl()
+        .args([
+            "exec",
+            "--bytecode-file",
+            "tests/fixtures/get.hex",
+            "--calls",
+            "tests/fixtures/get.hex",
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    // After any log lines
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let err: Value = serde_json::from_str(stderr.lines().last().unwrap()).unwrap();
+    assert_eq!(err["code"], "INVALID_REQUEST");
+}
@@ -0,0 +1,8 @@
+// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.0;
+
+contract Broken {
+    function f() public pure returns (uint256) {
+        return missing;
+    }
+}
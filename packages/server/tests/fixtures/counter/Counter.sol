@@ -0,0 +1,12 @@
+// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.0;
+
+import "./Math.sol";
+
+contract Counter {
+    uint256 public number;
+
+    function increment() public {
+        number = Math.inc(number);
+    }
+}
@@ -0,0 +1,8 @@
+// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.0;
+
+library Math {
+    function inc(uint256 x) internal pure returns (uint256) {
+        return x + 1;
+    }
+}
@@ -0,0 +1,121 @@
+use alloy_primitives::{address, Bytes, U256};
+use gas_exp::format::Negotiated;
+use gas_exp::gas::{ExecutionResult, ExitReason, TraceKind, TraceNode, TraceStatus};
+use rocket::local::asynchronous::Client;
+use rocket::tokio::io::AsyncReadExt;
+use rocket::{get, routes, State};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+// Tracks the bytes allocated now and the most there have been, for the whole test binary
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let now = ALLOCATED.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+            PEAK.fetch_max(now, Ordering::SeqCst);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+// Built before the request, so only what sending them takes is measured
+struct Prepared(Mutex<Option<Vec<ExecutionResult>>>);
+
+#[get("/results")]
+fn results_route(prepared: &State<Prepared>) -> Negotiated<Vec<ExecutionResult>> {
+    Negotiated(prepared.0.lock().unwrap().take().unwrap())
+}
+
+// A call whose trace returned 256KB, 512KB of hex in JSON
+fn fat_result(i: u8) -> ExecutionResult {
+    let contract = address!("b2f9974c62815d3177079e150377915d9bc49c82");
+    ExecutionResult {
+        exit_reason: ExitReason::Success,
+        success: true,
+        reverted: false,
+        revert_reason: None,
+        result: Bytes::new(),
+        gas_used: 43_512,
+        gas_limit: None,
+        logs: vec![],
+        traces: Some(vec![TraceNode {
+            kind: TraceKind::Call,
+            from: address!("1000000000000000000000000000000000000000"),
+            to: contract,
+            value: U256::ZERO,
+            gas_used: 22_512,
+            input: Bytes::from(vec![i; 4]),
+            output: Bytes::from(vec![i; 256 << 10]),
+            status: TraceStatus::Success,
+            output_length: None,
+            revert_reason: None,
+            children: vec![],
+            logs: vec![],
+        }]),
+        raw_traces: None,
+        labels: BTreeMap::new(),
+        trace_export: None,
+        flamegraph: None,
+        state_diff: None,
+        access_list: None,
+        consistency_check: None,
+        env: None,
+        hint: None,
+        journal: None,
+        transient_storage_accesses: None,
+        created: None,
+        paused_at: None,
+        internal_frames: None,
+        target_balance_before: None,
+        target_balance_after: None,
+        tx_validity: None,
+        attempts: None,
+        skipped: None,
+        call_index: None,
+        meta: None,
+    }
+}
+
+#[rocket::async_test]
+async fn test_large_batch_streams_in_bounded_memory() {
+    let results: Vec<ExecutionResult> = (0..50).map(fat_result).collect();
+    let rocket = rocket::build()
+        .manage(Prepared(Mutex::new(Some(results))))
+        .mount("/", routes![results_route]);
+    let client = Client::untracked(rocket).await.unwrap();
+
+    let baseline = ALLOCATED.load(Ordering::SeqCst);
+    PEAK.store(baseline, Ordering::SeqCst);
+    let mut response = client.get("/results").dispatch().await;
+    let mut chunk = vec![0; 16 << 10];
+    let mut sent = 0;
+    loop {
+        let read = response.read(&mut chunk).await.unwrap();
+        if read == 0 {
+            break;
+        }
+        sent += read;
+    }
+    let peak = PEAK.load(Ordering::SeqCst) - baseline;
+
+    // Over 25MB went out, the whole batch in JSON
+    assert!(sent > 25_000_000, "sent {} bytes", sent);
+    // A body built in memory would have taken all of that at once
+    assert!(peak < 4 << 20, "peaked at {} bytes", peak);
+}